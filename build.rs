@@ -0,0 +1,69 @@
+//! Build script: records the git commit, build time, and rustc version for
+//! `agentkernel version --verbose`, and the guest agent version the host expects.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let sha = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=AGENTKERNEL_GIT_SHA={}", sha);
+
+    // Honor SOURCE_DATE_EPOCH for reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=AGENTKERNEL_BUILD_TIMESTAMP={}", timestamp);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    println!(
+        "cargo:rustc-env=AGENTKERNEL_RUSTC_VERSION={}",
+        rustc_version
+    );
+
+    // Compared against the version a guest agent reports on Ping. Packaged
+    // builds don't include guest-agent/, and skip the comparison.
+    let agent_version = std::fs::read_to_string("guest-agent/Cargo.toml")
+        .ok()
+        .and_then(|manifest| package_version(&manifest))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!(
+        "cargo:rustc-env=AGENTKERNEL_GUEST_AGENT_VERSION={}",
+        agent_version
+    );
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=guest-agent/Cargo.toml");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+/// `version` from a manifest's `[package]` table
+fn package_version(manifest: &str) -> Option<String> {
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[package]")
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "version").then(|| value.trim().trim_matches('"').to_string())
+        })
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
@@ -0,0 +1,194 @@
+//! Message framing for the host <-> guest vsock protocol.
+//!
+//! Mirrors the framing in the host's `vsock.rs`: a 14-byte header
+//! (magic `AKVS`, protocol version u16, payload length u32, CRC32 u32,
+//! all little-endian) followed by the JSON payload.
+
+use std::fmt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Magic bytes at the start of every frame
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"AKVS";
+
+/// Protocol version spoken by this agent (must match the host)
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Largest payload accepted in a single frame
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+const FRAME_HEADER_LEN: usize = 14;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32 (IEEE) checksum
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Why a frame could not be read
+#[derive(Debug)]
+pub enum FrameError {
+    /// I/O error on the stream
+    Io(std::io::Error),
+    /// Header didn't start with the magic bytes (legacy or foreign peer)
+    BadMagic,
+    /// Peer speaks a different protocol version
+    Version(u16),
+    /// Payload exceeds `MAX_MESSAGE_SIZE`
+    TooLarge(usize),
+    /// Payload checksum didn't match the header
+    Checksum,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "I/O error: {}", e),
+            FrameError::BadMagic => write!(
+                f,
+                "Invalid frame (bad magic); host agentkernel is likely too old for this agent"
+            ),
+            FrameError::Version(v) => write!(
+                f,
+                "Protocol version mismatch: host speaks v{}, guest agent speaks v{}",
+                v, PROTOCOL_VERSION
+            ),
+            FrameError::TooLarge(len) => write!(f, "Request too large: {} bytes", len),
+            FrameError::Checksum => write!(f, "Frame checksum mismatch: request was corrupted"),
+        }
+    }
+}
+
+/// Encode a payload as a frame (header + payload)
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&PROTOCOL_MAGIC);
+    frame.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Write one frame
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> std::io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    writer.write_all(&encode_frame(payload)).await?;
+    writer.flush().await
+}
+
+/// Read one frame. Returns `Ok(None)` if the peer closed the connection.
+///
+/// On `FrameError::Checksum` the payload has been consumed, so the stream
+/// is still aligned on the next frame.
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>, FrameError>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(FrameError::Io(e)),
+    }
+
+    if header[0..4] != PROTOCOL_MAGIC {
+        return Err(FrameError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != PROTOCOL_VERSION {
+        return Err(FrameError::Version(version));
+    }
+
+    let len = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(FrameError::TooLarge(len));
+    }
+    let expected_crc = u32::from_le_bytes([header[10], header[11], header[12], header[13]]);
+
+    let mut payload = vec![0u8; len];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(FrameError::Io)?;
+
+    if crc32(&payload) != expected_crc {
+        return Err(FrameError::Checksum);
+    }
+
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, br#"{"id":"1"}"#).await.unwrap();
+        write_frame(&mut buf, b"").await.unwrap();
+
+        let mut reader = buf.as_slice();
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap().unwrap(),
+            br#"{"id":"1"}"#
+        );
+        assert!(read_frame(&mut reader).await.unwrap().unwrap().is_empty());
+        // Clean EOF
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frame_rejects_version_mismatch() {
+        let mut frame = encode_frame(b"{}");
+        frame[4..6].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert!(matches!(err, FrameError::Version(v) if v == PROTOCOL_VERSION + 1));
+    }
+
+    #[tokio::test]
+    async fn test_frame_rejects_legacy_and_corrupt_messages() {
+        let mut legacy = 2u32.to_le_bytes().to_vec();
+        legacy.extend_from_slice(b"{}");
+        legacy.extend_from_slice(&[0u8; 12]);
+        let err = read_frame(&mut legacy.as_slice()).await.unwrap_err();
+        assert!(matches!(err, FrameError::BadMagic));
+
+        let mut frame = encode_frame(b"{}");
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert!(matches!(err, FrameError::Checksum));
+    }
+}
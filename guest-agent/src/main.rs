@@ -4,29 +4,53 @@
 //! Communicates over virtio-vsock using a JSON-RPC protocol.
 //!
 //! Supports:
-//! - Command execution (Run)
+//! - Command execution (Run) and cancellation (Cancel)
 //! - Interactive shell sessions (Shell, ShellInput, ShellResize, ShellClose)
-//! - File operations (WriteFile, ReadFile, RemoveFile, Mkdir)
+//! - File operations (WriteFile, ReadFile, RemoveFile, Mkdir, Symlink, Hardlink)
 //! - Health check (Ping) and shutdown (Shutdown)
 
+mod frame;
 mod pty;
 
 use anyhow::{Context, Result};
+use frame::{read_frame, write_frame, FrameError};
 use pty::SessionManager;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::process::Stdio;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::process::Command;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::Mutex;
 use tokio_vsock::{VsockAddr, VsockListener};
 
 /// Default port to listen on
 const AGENT_PORT: u32 = 52000;
 
+/// Port to listen on: `AGENTKERNEL_AGENT_PORT`, then `agentkernel.agent_port=`
+/// on the kernel command line, then the default
+fn agent_port() -> u32 {
+    let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    parse_agent_port(std::env::var("AGENTKERNEL_AGENT_PORT").ok().as_deref(), &cmdline)
+}
+
+fn parse_agent_port(env: Option<&str>, cmdline: &str) -> u32 {
+    let from_cmdline = cmdline
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("agentkernel.agent_port="));
+    env.or(from_cmdline)
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .filter(|&port| port > 0)
+        .unwrap_or(AGENT_PORT)
+}
+
 /// Listen on any CID
 const VMADDR_CID_ANY: u32 = u32::MAX;
 
+/// Version string kept in the binary so the host can find it in a rootfs
+/// image (`agentkernel version --verbose`) without booting it
+#[used]
+static VERSION_MARKER: &str = concat!("agentkernel-guest-agent/", env!("CARGO_PKG_VERSION"), "\0");
+
 /// Request types supported by the agent
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -53,6 +77,12 @@ pub enum RequestType {
     RemoveFile,
     /// Create a directory in the guest filesystem
     Mkdir,
+    /// Create a symbolic link in the guest filesystem
+    Symlink,
+    /// Create a hard link in the guest filesystem
+    Hardlink,
+    /// Kill a running command (the Run request with the same id)
+    Cancel,
 }
 
 /// Shell event types for async shell communication
@@ -67,6 +97,17 @@ pub enum ShellEvent {
     Exited,
 }
 
+/// Resource usage of a completed command (from `wait4`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// User CPU time in milliseconds
+    pub user_time_ms: u64,
+    /// System CPU time in milliseconds
+    pub system_time_ms: u64,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
 /// Request from host
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentRequest {
@@ -101,6 +142,28 @@ pub struct AgentRequest {
     /// Input data as base64 (for ShellInput)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub input_base64: Option<String>,
+    /// Link target (for Symlink, Hardlink; path is the link itself)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// User to act as, `uid[:gid]` or `name[:group]` (for Run, Shell, and
+    /// file operations)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Append instead of truncating (for WriteFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append: Option<bool>,
+    /// Permission bits for the file (for WriteFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Capture stdout and stderr as one stream, in the order written (for Run)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined: Option<bool>,
+    /// Byte to start reading at (for ReadFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    /// Most bytes to read (for ReadFile; None = to the end)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
 }
 
 /// Response to host
@@ -113,6 +176,10 @@ pub struct AgentResponse {
     pub stdout: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stderr: Option<String>,
+    /// stdout and stderr interleaved in the order written (for Run with
+    /// `combined`, in place of `stdout` and `stderr`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
     /// File content as base64 (for ReadFile)
@@ -128,6 +195,15 @@ pub struct AgentResponse {
     /// Response type for shell events (started, output, exited)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shell_event: Option<ShellEvent>,
+    /// Resource usage of the command (for Run type)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rusage: Option<ResourceUsage>,
+    /// Guest agent version (for Ping)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Framing protocol version (for Ping)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u16>,
 }
 
 impl AgentResponse {
@@ -137,11 +213,25 @@ impl AgentResponse {
             exit_code: Some(0),
             stdout: None,
             stderr: None,
+            output: None,
             error: None,
             content_base64: None,
             session_id: None,
             output_base64: None,
             shell_event: None,
+            rusage: None,
+            version: None,
+            protocol_version: None,
+        }
+    }
+
+    /// Answer to a Ping, with the agent and protocol versions so the host
+    /// can spot a stale rootfs
+    fn pong(id: &str) -> Self {
+        Self {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(frame::PROTOCOL_VERSION),
+            ..Self::success(id)
         }
     }
 
@@ -151,25 +241,33 @@ impl AgentResponse {
             exit_code: None,
             stdout: None,
             stderr: None,
+            output: None,
             error: Some(msg.to_string()),
             content_base64: None,
             session_id: None,
             output_base64: None,
             shell_event: None,
+            rusage: None,
+            version: None,
+            protocol_version: None,
         }
     }
 
-    fn from_output(id: &str, exit_code: i32, stdout: String, stderr: String) -> Self {
+    fn from_output(id: &str, output: CommandOutput) -> Self {
         Self {
             id: id.to_string(),
-            exit_code: Some(exit_code),
-            stdout: Some(stdout),
-            stderr: Some(stderr),
+            exit_code: Some(output.exit_code),
+            stdout: output.combined.is_none().then_some(output.stdout),
+            stderr: output.combined.is_none().then_some(output.stderr),
+            output: output.combined,
             error: None,
             content_base64: None,
             session_id: None,
             output_base64: None,
             shell_event: None,
+            rusage: Some(output.rusage),
+            version: None,
+            protocol_version: None,
         }
     }
 
@@ -179,11 +277,15 @@ impl AgentResponse {
             exit_code: Some(0),
             stdout: None,
             stderr: None,
+            output: None,
             error: None,
             content_base64: Some(content_base64),
             session_id: None,
             output_base64: None,
             shell_event: None,
+            rusage: None,
+            version: None,
+            protocol_version: None,
         }
     }
 
@@ -193,11 +295,15 @@ impl AgentResponse {
             exit_code: None,
             stdout: None,
             stderr: None,
+            output: None,
             error: None,
             content_base64: None,
             session_id: Some(session_id),
             output_base64: None,
             shell_event: Some(ShellEvent::Started),
+            rusage: None,
+            version: None,
+            protocol_version: None,
         }
     }
 
@@ -207,11 +313,15 @@ impl AgentResponse {
             exit_code: None,
             stdout: None,
             stderr: None,
+            output: None,
             error: None,
             content_base64: None,
             session_id: Some(session_id.to_string()),
             output_base64: Some(output_base64),
             shell_event: Some(ShellEvent::Output),
+            rusage: None,
+            version: None,
+            protocol_version: None,
         }
     }
 
@@ -221,13 +331,182 @@ impl AgentResponse {
             exit_code: Some(exit_code),
             stdout: None,
             stderr: None,
+            output: None,
             error: None,
             content_base64: None,
             session_id: Some(session_id.to_string()),
             output_base64: None,
             shell_event: Some(ShellEvent::Exited),
+            rusage: None,
+            version: None,
+            protocol_version: None,
+        }
+    }
+}
+
+/// Process groups of running commands, by Run request id
+static RUNNING: std::sync::Mutex<Vec<(String, libc::pid_t)>> = std::sync::Mutex::new(Vec::new());
+
+/// Kill the command started by the Run request `id`, and everything it spawned.
+///
+/// Returns false if no such command is running.
+fn cancel_command(id: &str) -> bool {
+    // Hold the lock while signalling: the runner unregisters the pid under it
+    // before reaping, so a registered pid still names our unreaped child
+    let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(&(_, pid)) = running.iter().find(|(run_id, _)| run_id == id) else {
+        return false;
+    };
+    // SAFETY: kill has no memory-safety requirements; the pid can't have been
+    // reused, since the child is unreaped while it is registered
+    unsafe { libc::kill(-pid, libc::SIGKILL) };
+    true
+}
+
+/// Output of a command run to completion
+struct CommandOutput {
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+    /// Both streams in the order written, when captured `combined`
+    combined: Option<String>,
+    rusage: ResourceUsage,
+}
+
+/// A pipe whose ends are closed on exec, so only the child it is handed to
+/// keeps the write end open
+fn cloexec_pipe() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::fd::FromRawFd;
+
+    let mut fds = [0 as libc::c_int; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both descriptors are open and owned only here
+    let (reader, writer) = unsafe {
+        (
+            std::fs::File::from_raw_fd(fds[0]),
+            std::fs::File::from_raw_fd(fds[1]),
+        )
+    };
+    Ok((reader, writer))
+}
+
+/// Run a command to completion and collect its resource usage.
+///
+/// The child is reaped with `wait4` so the rusage covers only this command
+/// (and any descendants it waited for), not other concurrent commands.
+///
+/// The child leads its own process group, registered under `id` so a Cancel
+/// request can kill it along with anything it started.
+///
+/// With `combined`, stdout and stderr share one pipe (like `2>&1`), so the
+/// output keeps the order the command wrote it in.
+fn run_with_rusage(
+    mut cmd: std::process::Command,
+    id: &str,
+    combined: bool,
+) -> std::io::Result<CommandOutput> {
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+
+    let combined_pipe = if combined {
+        let (reader, writer) = cloexec_pipe()?;
+        cmd.stdout(writer.try_clone()?).stderr(writer);
+        Some(reader)
+    } else {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        None
+    };
+    let mut child = cmd.stdin(Stdio::null()).process_group(0).spawn()?;
+    // The command holds our copies of the write end; the read only ends
+    // once every copy is closed
+    drop(cmd);
+    let pid = child.id() as libc::pid_t;
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((id.to_string(), pid));
+
+    let (stdout, stderr, combined) = match combined_pipe {
+        Some(mut reader) => {
+            let mut buf = Vec::new();
+            let _ = reader.read_to_end(&mut buf);
+            (Vec::new(), Vec::new(), Some(buf))
+        }
+        None => {
+            // Drain both pipes concurrently so a full stderr can't block stdout
+            let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+            let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+            let stderr_reader = std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = stderr_pipe.read_to_end(&mut buf);
+                buf
+            });
+            let mut stdout = Vec::new();
+            let _ = stdout_pipe.read_to_end(&mut stdout);
+            (stdout, stderr_reader.join().unwrap_or_default(), None)
         }
+    };
+
+    // Wait for the exit without reaping, then unregister before reaping: once
+    // reaped, the pid (and its group id) can be reused, and a Cancel must not
+    // signal whatever gets it next
+    loop {
+        // SAFETY: siginfo_t is plain old data, written by waitid on success
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // SAFETY: pid is our own unreaped child and info is a valid pointer
+        let ret = unsafe {
+            libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT)
+        };
+        if ret == -1 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        break;
     }
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(_, running)| *running != pid);
+
+    let mut status: libc::c_int = 0;
+    // SAFETY: rusage is plain old data and fully written by wait4 on success
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let ret = loop {
+        // SAFETY: pid is our own unreaped child; status and usage are valid pointers
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if ret == -1 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        break ret;
+    };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let exit_code = if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        -1
+    };
+
+    Ok(CommandOutput {
+        exit_code,
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+        combined: combined.map(|buf| String::from_utf8_lossy(&buf).to_string()),
+        rusage: ResourceUsage {
+            user_time_ms: timeval_ms(usage.ru_utime),
+            system_time_ms: timeval_ms(usage.ru_stime),
+            // Linux reports ru_maxrss in kilobytes
+            max_rss_kb: usage.ru_maxrss.max(0) as u64,
+        },
+    })
+}
+
+fn timeval_ms(tv: libc::timeval) -> u64 {
+    (tv.tv_sec.max(0) as u64) * 1000 + (tv.tv_usec.max(0) as u64) / 1000
 }
 
 /// Validate a path is safe (no traversal, absolute path)
@@ -248,6 +527,135 @@ fn validate_path(path: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Resolve symlinks in a path, tolerating components that don't exist yet
+fn resolve_path(path: &std::path::Path, depth: u32) -> std::path::PathBuf {
+    if let Ok(real) = std::fs::canonicalize(path) {
+        return real;
+    }
+    // Dangling symlink: follow it by hand so writes through it are checked
+    if depth < 40 {
+        if let Ok(target) = std::fs::read_link(path) {
+            let parent = path.parent().unwrap_or(std::path::Path::new("/"));
+            return resolve_path(&parent.join(target), depth + 1);
+        }
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => resolve_path(parent, depth).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Validate a path and the location it resolves to after following symlinks
+fn validate_resolved(path: &str) -> Result<(), String> {
+    validate_path(path)?;
+    let resolved = resolve_path(std::path::Path::new(path), 0);
+    validate_path(&resolved.to_string_lossy())
+}
+
+/// Validate a link request: where the link goes and what it points at
+fn validate_link(link: &str, target: &str) -> Result<(), String> {
+    validate_resolved(link)?;
+    if target.is_empty() {
+        return Err("No target specified".to_string());
+    }
+    // Relative targets are resolved against the link's directory
+    let parent = std::path::Path::new(link)
+        .parent()
+        .unwrap_or(std::path::Path::new("/"));
+    validate_resolved(&parent.join(target).to_string_lossy())
+}
+
+/// Resolve a `uid[:gid]` or `name[:group]` spec to numeric ids.
+///
+/// Numeric ids don't need a passwd entry, so they work with any image; a uid
+/// without a group uses its passwd group if there is one, else the same id.
+fn resolve_user(spec: &str) -> Result<(u32, u32), String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let (uid, primary_gid) = match user.parse::<u32>() {
+        Ok(uid) => {
+            let passwd = unsafe { libc::getpwuid(uid) };
+            let gid = if passwd.is_null() {
+                uid
+            } else {
+                unsafe { (*passwd).pw_gid }
+            };
+            (uid, gid)
+        }
+        Err(_) => {
+            let name =
+                std::ffi::CString::new(user).map_err(|_| format!("Invalid user '{}'", spec))?;
+            let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+            if passwd.is_null() {
+                return Err(format!("User '{}' does not exist in the guest image", user));
+            }
+            unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) }
+        }
+    };
+    let gid = match group {
+        None => primary_gid,
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => {
+                let name = std::ffi::CString::new(group)
+                    .map_err(|_| format!("Invalid user '{}'", spec))?;
+                let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+                if entry.is_null() {
+                    return Err(format!(
+                        "Group '{}' does not exist in the guest image",
+                        group
+                    ));
+                }
+                unsafe { (*entry).gr_gid }
+            }
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Switch the calling thread, and only it, to `uid`/`gid` with no
+/// supplementary groups.
+///
+/// Uses raw syscalls: the libc wrappers change every thread in the process.
+fn drop_thread_privileges(uid: u32, gid: u32) -> std::io::Result<()> {
+    // SAFETY: setgroups is given an empty list; the others take plain ids
+    let failed = unsafe {
+        libc::syscall(libc::SYS_setgroups, 0, std::ptr::null::<libc::gid_t>()) != 0
+            || libc::syscall(libc::SYS_setresgid, gid, gid, gid) != 0
+            || libc::syscall(libc::SYS_setresuid, uid, uid, uid) != 0
+    };
+    if failed {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Run a file operation as the user a request names (or as the agent's
+/// user), so it is allowed exactly what that user's commands are.
+///
+/// The operation gets a thread of its own, which drops privileges and then
+/// exits, so nothing else ever runs with the user's ids.
+async fn as_user<T, F>(user: Option<String>, op: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let result = match user.as_deref().map(resolve_user).transpose() {
+            Ok(Some((uid, gid))) => drop_thread_privileges(uid, gid)
+                .map_err(|e| format!("Failed to switch to user: {}", e))
+                .and_then(|_| op()),
+            Ok(None) => op(),
+            Err(e) => Err(e),
+        };
+        let _ = tx.send(result);
+    });
+    rx.await.unwrap_or_else(|_| Err("File operation failed".to_string()))
+}
+
 /// Handle a single request
 async fn handle_request(
     request: AgentRequest,
@@ -256,7 +664,7 @@ async fn handle_request(
     use base64::{engine::general_purpose::STANDARD, Engine};
 
     match request.request_type {
-        RequestType::Ping => AgentResponse::success(&request.id),
+        RequestType::Ping => AgentResponse::pong(&request.id),
 
         RequestType::Shutdown => {
             eprintln!("Shutdown requested, exiting...");
@@ -283,9 +691,14 @@ async fn handle_request(
                 .unwrap_or_default();
             let rows = request.rows.unwrap_or(24);
             let cols = request.cols.unwrap_or(80);
+            // Resolved before forking: the lookups aren't safe in the child
+            let ids = match request.user.as_deref().map(resolve_user).transpose() {
+                Ok(ids) => ids,
+                Err(e) => return AgentResponse::error(&request.id, &e),
+            };
 
             match session_manager
-                .create_session(command, &args, rows, cols, request.env.as_ref())
+                .create_session(command, &args, rows, cols, request.env.as_ref(), ids)
                 .await
             {
                 Ok(session_id) => {
@@ -359,11 +772,8 @@ async fn handle_request(
             let program = &command[0];
             let args = &command[1..];
 
-            let mut cmd = Command::new(program);
+            let mut cmd = std::process::Command::new(program);
             cmd.args(args);
-            cmd.stdin(Stdio::null());
-            cmd.stdout(Stdio::piped());
-            cmd.stderr(Stdio::piped());
 
             // Set working directory if specified
             if let Some(ref cwd) = request.cwd {
@@ -377,12 +787,24 @@ async fn handle_request(
                 }
             }
 
-            match cmd.output().await {
-                Ok(output) => {
-                    let exit_code = output.status.code().unwrap_or(-1);
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    AgentResponse::from_output(&request.id, exit_code, stdout, stderr)
+            // Drop privileges before exec if a user was requested
+            if let Some(ref user) = request.user {
+                use std::os::unix::process::CommandExt;
+                match resolve_user(user) {
+                    Ok((uid, gid)) => {
+                        cmd.uid(uid).gid(gid);
+                    }
+                    Err(e) => return AgentResponse::error(&request.id, &e),
+                }
+            }
+
+            // wait4 blocks, so run the child on the blocking pool
+            let id = request.id.clone();
+            let combined = request.combined.unwrap_or(false);
+            match tokio::task::spawn_blocking(move || run_with_rusage(cmd, &id, combined)).await {
+                Ok(Ok(output)) => AgentResponse::from_output(&request.id, output),
+                Ok(Err(e)) => {
+                    AgentResponse::error(&request.id, &format!("Failed to run command: {}", e))
                 }
                 Err(e) => {
                     AgentResponse::error(&request.id, &format!("Failed to run command: {}", e))
@@ -390,12 +812,21 @@ async fn handle_request(
             }
         }
 
+        RequestType::Cancel => {
+            if cancel_command(&request.id) {
+                eprintln!("Cancelled command: {}", request.id);
+                AgentResponse::success(&request.id)
+            } else {
+                AgentResponse::error(&request.id, "No running command with this id")
+            }
+        }
+
         RequestType::WriteFile => {
             let Some(path) = request.path else {
                 return AgentResponse::error(&request.id, "No path specified");
             };
 
-            if let Err(e) = validate_path(&path) {
+            if let Err(e) = validate_resolved(&path) {
                 return AgentResponse::error(&request.id, &e);
             }
 
@@ -410,21 +841,22 @@ async fn handle_request(
                 }
             };
 
-            // Ensure parent directory exists
-            if let Some(parent) = std::path::Path::new(&path).parent() {
-                if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                    return AgentResponse::error(
-                        &request.id,
-                        &format!("Failed to create parent directory: {}", e),
-                    );
+            let append = request.append.unwrap_or(false);
+            let mode = request.mode;
+            let result = as_user(request.user, move || {
+                // Ensure parent directory exists
+                if let Some(parent) = std::path::Path::new(&path).parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| format!("Failed to create parent directory: {}", e))?;
                 }
-            }
+                write_file(&path, &content, append, mode)
+                    .map_err(|e| format!("Failed to write file: {}", e))
+            })
+            .await;
 
-            match tokio::fs::write(&path, &content).await {
-                Ok(_) => AgentResponse::success(&request.id),
-                Err(e) => {
-                    AgentResponse::error(&request.id, &format!("Failed to write file: {}", e))
-                }
+            match result {
+                Ok(()) => AgentResponse::success(&request.id),
+                Err(e) => AgentResponse::error(&request.id, &e),
             }
         }
 
@@ -433,16 +865,22 @@ async fn handle_request(
                 return AgentResponse::error(&request.id, "No path specified");
             };
 
-            if let Err(e) = validate_path(&path) {
+            if let Err(e) = validate_resolved(&path) {
                 return AgentResponse::error(&request.id, &e);
             }
 
-            match tokio::fs::read(&path).await {
+            let (offset, length) = (request.offset, request.length);
+            let result = as_user(request.user, move || {
+                read_file(&path, offset, length).map_err(|e| format!("Failed to read file: {}", e))
+            })
+            .await;
+
+            match result {
                 Ok(content) => {
                     let content_base64 = STANDARD.encode(&content);
                     AgentResponse::with_content(&request.id, content_base64)
                 }
-                Err(e) => AgentResponse::error(&request.id, &format!("Failed to read file: {}", e)),
+                Err(e) => AgentResponse::error(&request.id, &e),
             }
         }
 
@@ -451,15 +889,23 @@ async fn handle_request(
                 return AgentResponse::error(&request.id, "No path specified");
             };
 
-            if let Err(e) = validate_path(&path) {
+            // Only the parent is resolved so a symlink itself can be removed
+            let parent = std::path::Path::new(&path)
+                .parent()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "/".to_string());
+            if let Err(e) = validate_path(&path).and_then(|_| validate_resolved(&parent)) {
                 return AgentResponse::error(&request.id, &e);
             }
 
-            match tokio::fs::remove_file(&path).await {
-                Ok(_) => AgentResponse::success(&request.id),
-                Err(e) => {
-                    AgentResponse::error(&request.id, &format!("Failed to remove file: {}", e))
-                }
+            let result = as_user(request.user, move || {
+                std::fs::remove_file(&path).map_err(|e| format!("Failed to remove file: {}", e))
+            })
+            .await;
+
+            match result {
+                Ok(()) => AgentResponse::success(&request.id),
+                Err(e) => AgentResponse::error(&request.id, &e),
             }
         }
 
@@ -468,22 +914,56 @@ async fn handle_request(
                 return AgentResponse::error(&request.id, "No path specified");
             };
 
-            if let Err(e) = validate_path(&path) {
+            if let Err(e) = validate_resolved(&path) {
                 return AgentResponse::error(&request.id, &e);
             }
 
             let recursive = request.recursive.unwrap_or(false);
-            let result = if recursive {
-                tokio::fs::create_dir_all(&path).await
-            } else {
-                tokio::fs::create_dir(&path).await
-            };
+            let result = as_user(request.user, move || {
+                if recursive {
+                    std::fs::create_dir_all(&path)
+                } else {
+                    std::fs::create_dir(&path)
+                }
+                .map_err(|e| format!("Failed to create directory: {}", e))
+            })
+            .await;
 
             match result {
-                Ok(_) => AgentResponse::success(&request.id),
-                Err(e) => {
-                    AgentResponse::error(&request.id, &format!("Failed to create directory: {}", e))
+                Ok(()) => AgentResponse::success(&request.id),
+                Err(e) => AgentResponse::error(&request.id, &e),
+            }
+        }
+
+        RequestType::Symlink | RequestType::Hardlink => {
+            let hard = matches!(request.request_type, RequestType::Hardlink);
+            let Some(path) = request.path else {
+                return AgentResponse::error(&request.id, "No path specified");
+            };
+            let Some(target) = request.target else {
+                return AgentResponse::error(&request.id, "No target specified");
+            };
+
+            if let Err(e) = validate_link(&path, &target) {
+                return AgentResponse::error(&request.id, &e);
+            }
+            if hard && !target.starts_with('/') {
+                return AgentResponse::error(&request.id, "Hardlink target must be absolute");
+            }
+
+            let result = as_user(request.user, move || {
+                if hard {
+                    std::fs::hard_link(&target, &path)
+                } else {
+                    std::os::unix::fs::symlink(&target, &path)
                 }
+                .map_err(|e| format!("Failed to create link: {}", e))
+            })
+            .await;
+
+            match result {
+                Ok(()) => AgentResponse::success(&request.id),
+                Err(e) => AgentResponse::error(&request.id, &e),
             }
         }
     }
@@ -491,33 +971,66 @@ async fn handle_request(
 
 /// Handle a single connection
 async fn handle_connection(
-    mut stream: tokio_vsock::VsockStream,
+    stream: tokio_vsock::VsockStream,
     session_manager: Arc<SessionManager>,
 ) -> Result<()> {
+    let (mut reader, writer) = stream.into_split();
+    // Shared so shell output can be streamed while requests are still handled
+    let writer = Arc::new(Mutex::new(writer));
+
+    // Announce our protocol version so the host can reject a mismatch up front
+    write_frame(&mut *writer.lock().await, b"")
+        .await
+        .context("Failed to send greeting")?;
+
+    // Shell sessions live as long as the connection that started them
+    let mut shells = Vec::new();
+    let result = serve_requests(&mut reader, &writer, &session_manager, &mut shells).await;
+    for session_id in shells {
+        let _ = session_manager.close_session(&session_id).await;
+    }
+    result
+}
+
+/// Read and answer requests until the peer closes the connection.
+///
+/// Commands run concurrently, so one connection can have several in flight;
+/// each response carries its request's id and is written as a single frame.
+/// Everything else is answered in the order it arrives.
+async fn serve_requests<R, W>(
+    reader: &mut R,
+    writer: &Arc<Mutex<W>>,
+    session_manager: &Arc<SessionManager>,
+    shells: &mut Vec<String>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
     loop {
-        // Read length prefix
-        let mut len_bytes = [0u8; 4];
-        match stream.read_exact(&mut len_bytes).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                // Connection closed
+        let request_bytes = match read_frame(reader).await {
+            Ok(Some(bytes)) => bytes,
+            // Connection closed
+            Ok(None) => return Ok(()),
+            Err(FrameError::Io(e)) => return Err(e.into()),
+            Err(FrameError::BadMagic) => {
+                // Can't reply in a format the peer understands
+                eprintln!("{}", FrameError::BadMagic);
                 return Ok(());
             }
-            Err(e) => return Err(e.into()),
-        }
-
-        let len = u32::from_le_bytes(len_bytes) as usize;
-        if len > 10 * 1024 * 1024 {
-            eprintln!("Request too large: {} bytes", len);
-            continue;
-        }
-
-        // Read request body
-        let mut request_bytes = vec![0u8; len];
-        stream
-            .read_exact(&mut request_bytes)
-            .await
-            .context("Failed to read request")?;
+            Err(e @ FrameError::Checksum) => {
+                // The payload was consumed, so the stream is still aligned
+                eprintln!("{}", e);
+                send_response(writer, &AgentResponse::error("", &e.to_string())).await?;
+                continue;
+            }
+            Err(e) => {
+                // Version mismatch or oversized frame: report and drop the connection
+                eprintln!("{}", e);
+                send_response(writer, &AgentResponse::error("", &e.to_string())).await?;
+                return Ok(());
+            }
+        };
 
         // Parse request
         let request: AgentRequest = match serde_json::from_slice(&request_bytes) {
@@ -528,29 +1041,104 @@ async fn handle_connection(
             }
         };
 
+        if matches!(request.request_type, RequestType::Run) {
+            let session_manager = session_manager.clone();
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                let response = handle_request(request, session_manager).await;
+                if let Err(e) = send_response(&writer, &response).await {
+                    eprintln!("Failed to send response: {}", e);
+                }
+            });
+            continue;
+        }
+
         // Handle request
+        let request_id = request.id.clone();
         let response = handle_request(request, session_manager.clone()).await;
+        send_response(writer, &response).await?;
+
+        // Stream a new shell's output back over this connection
+        if matches!(response.shell_event, Some(ShellEvent::Started)) {
+            if let Some(session_id) = response.session_id {
+                shells.push(session_id.clone());
+                tokio::spawn(stream_shell_output(
+                    request_id,
+                    session_id,
+                    session_manager.clone(),
+                    writer.clone(),
+                ));
+            }
+        }
+    }
+}
+
+/// Forward a shell session's output as ShellOutput frames, then send
+/// ShellExited with the process exit code once the PTY closes.
+async fn stream_shell_output<W>(
+    request_id: String,
+    session_id: String,
+    session_manager: Arc<SessionManager>,
+    writer: Arc<Mutex<W>>,
+) where
+    W: AsyncWrite + Unpin,
+{
+    let mut output = match session_manager.output_reader(&session_id).await {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Failed to read shell output: {}", e);
+            return;
+        }
+    };
 
-        // Serialize response
-        let response_bytes = serde_json::to_vec(&response)?;
+    use base64::{engine::general_purpose::STANDARD, Engine};
 
-        // Send length-prefixed response
-        let len = response_bytes.len() as u32;
-        stream.write_all(&len.to_le_bytes()).await?;
-        stream.write_all(&response_bytes).await?;
-        stream.flush().await?;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        // Reading the master fails with EIO once the shell exits
+        let n = match output.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let response =
+            AgentResponse::shell_output(&request_id, &session_id, STANDARD.encode(&buf[..n]));
+        if send_response(&writer, &response).await.is_err() {
+            return;
+        }
     }
+
+    let exit_code = session_manager
+        .wait_exit_code(&session_id)
+        .await
+        .unwrap_or(-1);
+    eprintln!("Shell session exited: {} (exit: {})", session_id, exit_code);
+    let _ = send_response(
+        &writer,
+        &AgentResponse::shell_exited(&request_id, &session_id, exit_code),
+    )
+    .await;
+}
+
+/// Serialize and send a response frame
+async fn send_response<W>(writer: &Mutex<W>, response: &AgentResponse) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let response_bytes = serde_json::to_vec(response)?;
+    write_frame(&mut *writer.lock().await, &response_bytes).await?;
+    Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     eprintln!("Agentkernel guest agent starting...");
-    eprintln!("Listening on vsock port {}", AGENT_PORT);
+    let port = agent_port();
+    eprintln!("Listening on vsock port {}", port);
 
     // Create the session manager for PTY sessions
     let session_manager = Arc::new(SessionManager::new());
 
-    let addr = VsockAddr::new(VMADDR_CID_ANY, AGENT_PORT);
+    let addr = VsockAddr::new(VMADDR_CID_ANY, port);
     let mut listener = VsockListener::bind(addr).context("Failed to bind vsock listener")?;
 
     eprintln!("Agent ready (with PTY support)");
@@ -572,3 +1160,354 @@ async fn main() -> Result<()> {
         }
     }
 }
+
+/// Write `content` to `path`, truncating or appending.
+///
+/// `mode` is applied when the file is created, and when an existing file is
+/// overwritten; appending leaves an existing file's permissions alone.
+fn write_file(
+    path: &str,
+    content: &[u8],
+    append: bool,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut options = std::fs::OpenOptions::new();
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    // Create first so an append knows whether the file is new
+    let (mut file, created) = match options.clone().create_new(true).open(path) {
+        Ok(file) => (file, true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            (options.create(true).open(path)?, false)
+        }
+        Err(e) => return Err(e),
+    };
+    // Set explicitly: the mode given at creation is reduced by the umask
+    if let (Some(mode), true) = (mode, created || !append) {
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+    file.write_all(content)?;
+    file.flush()
+}
+
+/// Read a file, or at most `length` bytes of it starting at `offset`
+fn read_file(
+    path: &str,
+    offset: Option<u64>,
+    length: Option<u64>,
+) -> std::io::Result<Vec<u8>> {
+    use std::io::{Read, Seek};
+
+    let mut file = std::fs::File::open(path)?;
+    if let Some(offset) = offset {
+        file.seek(std::io::SeekFrom::Start(offset))?;
+    }
+    let mut content = Vec::new();
+    match length {
+        Some(length) => file.take(length).read_to_end(&mut content)?,
+        None => file.read_to_end(&mut content)?,
+    };
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_agent_port() {
+        assert_eq!(parse_agent_port(None, "console=ttyS0 quiet"), AGENT_PORT);
+        assert_eq!(
+            parse_agent_port(None, "console=ttyS0 agentkernel.agent_port=6000"),
+            6000
+        );
+        assert_eq!(
+            parse_agent_port(Some("7000"), "agentkernel.agent_port=6000"),
+            7000
+        );
+        assert_eq!(parse_agent_port(Some("bad"), ""), AGENT_PORT);
+    }
+
+    #[tokio::test]
+    async fn test_ping_reports_version() {
+        let request: AgentRequest = serde_json::from_str(r#"{"id":"ping","type":"ping"}"#).unwrap();
+        let response = handle_request(request, Arc::new(SessionManager::new())).await;
+        assert_eq!(response.exit_code, Some(0));
+        assert!(!response.version.unwrap().is_empty());
+        assert_eq!(response.protocol_version, Some(frame::PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_write_file_append_accumulates() {
+        let path = std::env::temp_dir().join(format!("agent-append-{}", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+
+        write_file(&path, b"one\n", true, None).unwrap();
+        write_file(&path, b"two\n", true, None).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+
+        // Without append the file is replaced
+        write_file(&path, b"three\n", false, None).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "three\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_range() {
+        let path = std::env::temp_dir().join(format!("agent-range-{}", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let content: Vec<u8> = (0..=255).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        assert_eq!(read_file(&path, None, None).unwrap(), content);
+        assert_eq!(read_file(&path, Some(0), Some(100)).unwrap(), &content[..100]);
+        // A range past the end stops at the end
+        assert_eq!(read_file(&path, Some(200), Some(100)).unwrap(), &content[200..]);
+        assert!(read_file(&path, Some(300), Some(10)).unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_sets_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("agent-mode-{}", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        let mode = |p: &str| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+
+        write_file(&path, b"#!/bin/sh\n", false, Some(0o750)).unwrap();
+        assert_eq!(mode(&path), 0o750);
+
+        // Overwriting applies the new mode; appending keeps the existing one
+        write_file(&path, b"#!/bin/sh\n", false, Some(0o700)).unwrap();
+        assert_eq!(mode(&path), 0o700);
+        write_file(&path, b"echo hi\n", true, Some(0o644)).unwrap();
+        assert_eq!(mode(&path), 0o700);
+
+        // Appending to a new file creates it with the mode, whatever the umask
+        std::fs::remove_file(&path).unwrap();
+        write_file(&path, b"echo hi\n", true, Some(0o777)).unwrap();
+        assert_eq!(mode(&path), 0o777);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_as_user_runs_op_on_its_own_thread() {
+        let caller = std::thread::current().id();
+        let other = as_user(None, move || Ok(std::thread::current().id() != caller)).await;
+        assert_eq!(other, Ok(true));
+
+        // An unknown user fails before the operation runs
+        let err = as_user(Some("no-such-user".to_string()), || -> Result<(), String> {
+            panic!("ran as an unknown user")
+        })
+        .await
+        .unwrap_err();
+        assert!(err.contains("does not exist"), "{}", err);
+
+        let err = as_user(None, || -> Result<(), String> { Err("boom".to_string()) }).await;
+        assert_eq!(err, Err("boom".to_string()));
+    }
+
+    #[test]
+    fn test_run_with_rusage_reports_memory() {
+        // Hold ~16MB in a shell variable so peak RSS is clearly above baseline
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args([
+            "-c",
+            "x=$(head -c 16000000 /dev/zero | tr '\\0' a); echo ${#x}",
+        ]);
+        let output = run_with_rusage(cmd, "test", false).unwrap();
+
+        assert_eq!(output.exit_code, 0);
+        assert_eq!(output.stdout.trim(), "16000000");
+        assert!(
+            output.rusage.max_rss_kb > 8_000,
+            "max_rss_kb too small: {}",
+            output.rusage.max_rss_kb
+        );
+        assert!(
+            output.rusage.max_rss_kb < 4_000_000,
+            "max_rss_kb implausibly large: {}",
+            output.rusage.max_rss_kb
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_runs_on_one_connection() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (mut reader, writer) = tokio::io::split(server);
+        let writer = Arc::new(Mutex::new(writer));
+        tokio::spawn(async move {
+            let _ = serve_requests(
+                &mut reader,
+                &writer,
+                &Arc::new(SessionManager::new()),
+                &mut Vec::new(),
+            )
+            .await;
+        });
+
+        // Each command writes its own marker many times; crossed or
+        // interleaved output would mix them
+        for (id, delay) in [("slow", "0.5"), ("fast", "0")] {
+            let script = format!("sleep {}; for i in $(seq 200); do echo {}; done", delay, id);
+            let request = serde_json::json!({
+                "id": id,
+                "type": "run",
+                "command": ["sh", "-c", script],
+            });
+            write_frame(&mut client, &serde_json::to_vec(&request).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let frame = read_frame(&mut client).await.unwrap().unwrap();
+            let response: AgentResponse = serde_json::from_slice(&frame).unwrap();
+            responses.push(response);
+        }
+
+        // The quick command isn't held up behind the slow one
+        assert_eq!(responses[0].id, "fast");
+        assert_eq!(responses[1].id, "slow");
+        for response in &responses {
+            let expected = format!("{}\n", response.id).repeat(200);
+            assert_eq!(response.stdout.as_deref(), Some(expected.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_run_with_rusage_exit_code_and_stderr() {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", "echo oops >&2; exit 3"]);
+        let output = run_with_rusage(cmd, "test", false).unwrap();
+
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stderr.trim(), "oops");
+        assert!(output.combined.is_none());
+    }
+
+    #[test]
+    fn test_run_with_rusage_combined_keeps_order() {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args([
+            "-c",
+            "for i in 1 2 3; do echo out$i; echo err$i >&2; done; exit 2",
+        ]);
+        let output = run_with_rusage(cmd, "test", true).unwrap();
+
+        assert_eq!(output.exit_code, 2);
+        assert_eq!(
+            output.combined.as_deref(),
+            Some("out1\nerr1\nout2\nerr2\nout3\nerr3\n")
+        );
+        assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+        let response = AgentResponse::from_output("test", output);
+        assert!(response.stdout.is_none() && response.stderr.is_none());
+        assert!(response.output.is_some());
+    }
+
+    #[test]
+    fn test_cancel_kills_running_command() {
+        // The sleep is a grandchild, so this also checks the whole group is killed
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", "sleep 30; echo done"]);
+        let started = std::time::Instant::now();
+        let run = std::thread::spawn(move || run_with_rusage(cmd, "cancel-me", false).unwrap());
+
+        while !cancel_command("cancel-me") {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let output = run.join().unwrap();
+
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(output.exit_code, -1);
+        assert!(output.stdout.is_empty());
+        // Reaped and unregistered: nothing left to cancel
+        assert!(!cancel_command("cancel-me"));
+    }
+
+    #[test]
+    fn test_response_rusage_serialization() {
+        let response = AgentResponse::from_output(
+            "req-1",
+            CommandOutput {
+                exit_code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+                combined: None,
+                rusage: ResourceUsage {
+                    user_time_ms: 12,
+                    system_time_ms: 3,
+                    max_rss_kb: 2048,
+                },
+            },
+        );
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json
+            .contains("\"rusage\":{\"user_time_ms\":12,\"system_time_ms\":3,\"max_rss_kb\":2048}"));
+
+        // Non-run responses omit the field
+        let json = serde_json::to_string(&AgentResponse::success("req-2")).unwrap();
+        assert!(!json.contains("rusage"));
+    }
+
+    #[test]
+    fn test_resolve_user() {
+        assert_eq!(resolve_user("0"), Ok((0, 0)));
+        assert_eq!(resolve_user("root"), Ok((0, 0)));
+        assert_eq!(resolve_user("1000:1000"), Ok((1000, 1000)));
+        // A uid missing from the image still works
+        assert_eq!(resolve_user("54321"), Ok((54321, 54321)));
+        assert!(resolve_user("no-such-user").is_err());
+        assert!(resolve_user("0:no-such-group").is_err());
+    }
+
+    #[test]
+    fn test_validate_link_targets() {
+        assert!(validate_link("/app/link", "data.txt").is_ok());
+        assert!(validate_link("/app/link", "/app/data.txt").is_ok());
+        assert!(validate_link("/app/link", "").is_err());
+        assert!(validate_link("/app/link", "/etc/shadow").is_err());
+        assert!(validate_link("/app/link", "../etc/shadow").is_err());
+        assert!(validate_link("/proc/link", "/app/data.txt").is_err());
+    }
+
+    #[test]
+    fn test_validate_resolved_follows_symlinks() {
+        let dir = std::env::temp_dir().join(format!("agent-links-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let escape = dir.join("escape");
+        let dangling = dir.join("dangling");
+        let _ = std::fs::remove_file(&escape);
+        let _ = std::fs::remove_file(&dangling);
+        std::os::unix::fs::symlink("/proc/self", &escape).unwrap();
+        std::os::unix::fs::symlink("/sys/nonexistent", &dangling).unwrap();
+
+        let path = |p: &std::path::Path| p.to_string_lossy().into_owned();
+        assert!(validate_resolved(&path(&dir.join("new/file.txt"))).is_ok());
+        assert!(validate_resolved(&path(&escape.join("status"))).is_err());
+        assert!(validate_resolved(&path(&escape)).is_err());
+        assert!(validate_resolved(&path(&dangling)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -38,6 +38,7 @@ impl PtySession {
     /// * `rows` - Initial terminal rows
     /// * `cols` - Initial terminal columns
     /// * `env` - Environment variables
+    /// * `ids` - uid and gid to switch to before running the command
     pub fn spawn(
         id: String,
         command: &str,
@@ -45,6 +46,7 @@ impl PtySession {
         rows: u16,
         cols: u16,
         env: Option<&HashMap<String, String>>,
+        ids: Option<(u32, u32)>,
     ) -> Result<Self> {
         // Open a new PTY pair
         let OpenptyResult { master, slave } = openpty(None, None)
@@ -91,6 +93,24 @@ impl PtySession {
                     let _ = close(slave_fd);
                 }
 
+                // Drop privileges if a user was requested
+                if let Some((uid, gid)) = ids {
+                    let failed = unsafe {
+                        libc::setgroups(0, std::ptr::null()) != 0
+                            || libc::setgid(gid) != 0
+                            || libc::setuid(uid) != 0
+                    };
+                    if failed {
+                        eprintln!(
+                            "Failed to switch to user {}:{}: {}",
+                            uid,
+                            gid,
+                            std::io::Error::last_os_error()
+                        );
+                        unsafe { libc::_exit(126) };
+                    }
+                }
+
                 // Prepare command and arguments
                 let cmd = CString::new(command).expect("Invalid command");
                 let mut c_args: Vec<CString> = vec![cmd.clone()];
@@ -187,6 +207,16 @@ impl PtySession {
         }
     }
 
+    /// Open an independent reader for the PTY output.
+    ///
+    /// Duplicates the master fd so output can be streamed without holding
+    /// the session lock (which would block input and resize requests).
+    pub fn output_reader(&self) -> Result<tokio::fs::File> {
+        let fd = nix::unistd::dup(self.master_fd).context("Failed to duplicate PTY fd")?;
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+        Ok(tokio::fs::File::from_std(file))
+    }
+
     /// Check if the child process is still running
     pub fn is_running(&self) -> bool {
         if let Some(pid) = self.child_pid {
@@ -261,9 +291,10 @@ impl SessionManager {
         rows: u16,
         cols: u16,
         env: Option<&HashMap<String, String>>,
+        ids: Option<(u32, u32)>,
     ) -> Result<String> {
         let id = uuid::Uuid::new_v4().to_string();
-        let session = PtySession::spawn(id.clone(), command, args, rows, cols, env)?;
+        let session = PtySession::spawn(id.clone(), command, args, rows, cols, env, ids)?;
 
         let mut sessions = self.sessions.lock().await;
         sessions.insert(id.clone(), session);
@@ -301,6 +332,33 @@ impl SessionManager {
         }
     }
 
+    /// Open an output reader for a session (see [`PtySession::output_reader`])
+    pub async fn output_reader(&self, id: &str) -> Result<tokio::fs::File> {
+        let sessions = self.sessions.lock().await;
+        if let Some(session) = sessions.get(id) {
+            session.output_reader()
+        } else {
+            bail!("Session not found: {}", id)
+        }
+    }
+
+    /// Wait briefly for a session's process to exit and return its exit code.
+    ///
+    /// Returns `None` if the session is gone or still running after ~1s.
+    pub async fn wait_exit_code(&self, id: &str) -> Option<i32> {
+        for _ in 0..50 {
+            {
+                let sessions = self.sessions.lock().await;
+                let session = sessions.get(id)?;
+                if let Some(code) = session.exit_code() {
+                    return Some(code);
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        None
+    }
+
     /// Resize a session's terminal
     pub async fn resize_session(&self, id: &str, rows: u16, cols: u16) -> Result<()> {
         let sessions = self.sessions.lock().await;
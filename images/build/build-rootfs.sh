@@ -89,9 +89,17 @@ case "$RUNTIME" in
         SIZE_MB=512
         PACKAGES="rust cargo"
         ;;
+    ruby)
+        SIZE_MB=256
+        PACKAGES="ruby ruby-bundler"
+        ;;
+    java)
+        SIZE_MB=512
+        PACKAGES="openjdk17"
+        ;;
     *)
         echo "Unknown runtime: $RUNTIME"
-        echo "Available: base, python, node, go, rust"
+        echo "Available: base, python, node, go, rust, ruby, java"
         exit 1
         ;;
 esac
@@ -103,6 +111,7 @@ echo "    Packages: ${PACKAGES:-none}"
 docker run --rm --privileged \
     -v "$ROOTFS_DIR:/output" \
     -v "$AGENT_BIN:/agent-bin:ro" \
+    -v "$SCRIPT_DIR/guest-init.sh:/guest-init.sh:ro" \
     -e RUNTIME="$RUNTIME" \
     -e SIZE_MB="$SIZE_MB" \
     -e PACKAGES="$PACKAGES" \
@@ -128,7 +137,7 @@ apk -X https://dl-cdn.alpinelinux.org/alpine/v3.20/main \
     add alpine-base busybox-static $PACKAGES
 
 # Create essential directories
-mkdir -p "$MOUNT_DIR"/{dev,proc,sys,tmp,run,root,app}
+mkdir -p "$MOUNT_DIR"/{dev,proc,sys,tmp,run,root,app,workspace}
 chmod 1777 "$MOUNT_DIR/tmp"
 
 # Create device nodes
@@ -147,6 +156,7 @@ chmod +x "$MOUNT_DIR/usr/bin/agent"
 # Create init script that starts the agent
 cat > "$MOUNT_DIR/init" << '\''INIT'\''
 #!/bin/busybox sh
+BB=/bin/busybox
 
 # Mount essential filesystems
 /bin/busybox mount -t proc proc /proc
@@ -156,6 +166,10 @@ cat > "$MOUNT_DIR/init" << '\''INIT'\''
 # Set hostname
 /bin/busybox hostname agentkernel
 
+INIT
+cat /guest-init.sh >> "$MOUNT_DIR/init"
+cat >> "$MOUNT_DIR/init" << '\''INIT'\''
+
 # Start guest agent in background
 echo "Starting agentkernel guest agent..."
 /usr/bin/agent &
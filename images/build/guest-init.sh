@@ -0,0 +1,69 @@
+# Guest init: mounts and limits requested on the kernel command line.
+#
+# Shared by every rootfs init (images/build/build-rootfs.sh, `agentkernel
+# setup` and Docker image conversion), which include this file verbatim.
+# External commands go through $BB, which images without busybox applet
+# links set to /bin/busybox; it is empty otherwise.
+
+# Read-only root: mount a tmpfs for the writable workspace (/tmp comes from
+# agentkernel.tmp_size, which a read-only root always gets)
+if ! $BB touch /.rw-check 2>/dev/null; then
+    for arg in $($BB cat /proc/cmdline); do
+        case "$arg" in
+            agentkernel.writable=*)
+                dir="${arg#agentkernel.writable=}"
+                $BB mkdir -p "$dir" 2>/dev/null || true
+                $BB mount -t tmpfs -o mode=0755,nosuid tmpfs "$dir"
+                ;;
+        esac
+    done
+else
+    $BB rm -f /.rw-check
+fi
+
+# Mount the scratch disk, if one is attached
+for arg in $($BB cat /proc/cmdline); do
+    case "$arg" in
+        agentkernel.scratch=*)
+            dir="${arg#agentkernel.scratch=}"
+            $BB mkdir -p "$dir" 2>/dev/null || true
+            $BB mount -t ext4 /dev/vdb "$dir"
+            ;;
+    esac
+done
+
+# Size-limited /tmp and /run, if requested; /tmp is noexec as on Docker
+for arg in $($BB cat /proc/cmdline); do
+    case "$arg" in
+        agentkernel.tmp_size=*)
+            $BB mount -t tmpfs -o "mode=1777,nosuid,noexec,size=${arg#agentkernel.tmp_size=}" tmpfs /tmp
+            ;;
+        agentkernel.run_size=*)
+            $BB mkdir -p /run 2>/dev/null || true
+            $BB mount -t tmpfs -o "mode=0755,nosuid,size=${arg#agentkernel.run_size=}" tmpfs /run
+            ;;
+    esac
+done
+
+# Resource limits, if requested; the guest agent and its commands inherit them
+for arg in $($BB cat /proc/cmdline); do
+    case "$arg" in
+        agentkernel.ulimit.*=*)
+            spec=${arg#agentkernel.ulimit.}
+            soft=${spec#*=}
+            hard=${soft#*:}
+            soft=${soft%%:*}
+            # fsize and core are given in bytes; ulimit counts 512-byte blocks
+            case "${spec%%=*}" in
+                nofile) flag=-n ;;
+                nproc) flag=-u ;;
+                fsize) flag=-f; soft=$((soft / 512)); hard=$((hard / 512)) ;;
+                core) flag=-c; soft=$((soft / 512)); hard=$((hard / 512)) ;;
+                *) continue ;;
+            esac
+            # Lower the soft limit first so a lower hard limit is accepted
+            ulimit -S $flag "$soft" 2>/dev/null
+            ulimit -H $flag "$hard" && ulimit -S $flag "$soft"
+            ;;
+    esac
+done
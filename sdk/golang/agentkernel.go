@@ -173,9 +173,7 @@ func (c *Client) RunStream(ctx context.Context, command []string, opts *RunOptio
 
 // ListSandboxes returns all sandboxes.
 func (c *Client) ListSandboxes(ctx context.Context) ([]SandboxInfo, error) {
-	var result []SandboxInfo
-	err := c.request(ctx, http.MethodGet, "/sandboxes", nil, &result)
-	return result, err
+	return requestAll[SandboxInfo](ctx, c, "/sandboxes")
 }
 
 // CreateSandbox creates a new sandbox.
@@ -263,9 +261,7 @@ func (c *Client) DeleteFile(ctx context.Context, name, path string) error {
 
 // GetSandboxLogs returns audit log entries for a sandbox.
 func (c *Client) GetSandboxLogs(ctx context.Context, name string) ([]map[string]interface{}, error) {
-	var result []map[string]interface{}
-	err := c.request(ctx, http.MethodGet, "/sandboxes/"+name+"/logs", nil, &result)
-	return result, err
+	return requestAll[map[string]interface{}](ctx, c, "/sandboxes/"+name+"/logs")
 }
 
 // BatchRun executes multiple commands in parallel.
@@ -290,39 +286,60 @@ func (c *Client) applyHeaders(req *http.Request) {
 }
 
 func (c *Client) request(ctx context.Context, method, path string, body interface{}, result interface{}) error {
+	_, err := c.requestPage(ctx, method, path, body, result)
+	return err
+}
+
+// requestAll GETs every page of a paginated list, following the next links.
+func requestAll[T any](ctx context.Context, c *Client, path string) ([]T, error) {
+	var items []T
+	for path != "" {
+		var page []T
+		next, err := c.requestPage(ctx, http.MethodGet, path, nil, &page)
+		if err != nil {
+			return nil, err
+		}
+		items = append(items, page...)
+		path = next
+	}
+	return items, nil
+}
+
+// requestPage sends a request and returns the path of the next page, if any.
+func (c *Client) requestPage(ctx context.Context, method, path string, body interface{}, result interface{}) (string, error) {
 	var bodyReader io.Reader
 	if body != nil {
 		jsonBody, err := json.Marshal(body)
 		if err != nil {
-			return fmt.Errorf("agentkernel: marshal request: %w", err)
+			return "", fmt.Errorf("agentkernel: marshal request: %w", err)
 		}
 		bodyReader = bytes.NewReader(jsonBody)
 	}
 
 	req, err := http.NewRequestWithContext(ctx, method, c.baseURL+path, bodyReader)
 	if err != nil {
-		return fmt.Errorf("agentkernel: create request: %w", err)
+		return "", fmt.Errorf("agentkernel: create request: %w", err)
 	}
 	c.applyHeaders(req)
 
 	resp, err := c.httpClient.Do(req)
 	if err != nil {
-		return fmt.Errorf("agentkernel: %w", err)
+		return "", fmt.Errorf("agentkernel: %w", err)
 	}
 	defer resp.Body.Close()
 
 	if resp.StatusCode >= 400 {
-		return c.handleErrorResponse(resp)
+		return "", c.handleErrorResponse(resp)
 	}
 
 	respBody, err := io.ReadAll(resp.Body)
 	if err != nil {
-		return fmt.Errorf("agentkernel: read response: %w", err)
+		return "", fmt.Errorf("agentkernel: read response: %w", err)
 	}
 
 	var apiResp apiResponse[json.RawMessage]
 	if err := json.Unmarshal(respBody, &apiResp); err != nil {
-		return fmt.Errorf("agentkernel: decode response: %w", err)
+		return "", fmt.Errorf("agentkernel: decode response: %w", err)
 	}
 
 	if !apiResp.Success {
@@ -330,15 +347,15 @@ func (c *Client) request(ctx context.Context, method, path string, body interfac
 		if msg == "" {
 			msg = "unknown error"
 		}
-		return &Error{StatusCode: resp.StatusCode, Message: msg}
+		return "", &Error{StatusCode: resp.StatusCode, Message: msg}
 	}
 
 	if result != nil && apiResp.Data != nil {
 		if err := json.Unmarshal(apiResp.Data, result); err != nil {
-			return fmt.Errorf("agentkernel: decode data: %w", err)
+			return "", fmt.Errorf("agentkernel: decode data: %w", err)
 		}
 	}
-	return nil
+	return apiResp.Next, nil
 }
 
 func (c *Client) handleErrorResponse(resp *http.Response) error {
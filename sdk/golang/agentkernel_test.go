@@ -137,6 +137,33 @@ func TestListSandboxes(t *testing.T) {
 	}
 }
 
+func TestListSandboxesFollowsNext(t *testing.T) {
+	client, srv := testClient(func(w http.ResponseWriter, r *http.Request) {
+		w.Header().Set("Content-Type", "application/json")
+		resp := map[string]interface{}{
+			"success": true,
+			"data":    []map[string]string{{"name": "sb1", "status": "running", "backend": "docker"}},
+			"next":    "/sandboxes?limit=1&offset=1",
+		}
+		if r.URL.Query().Get("offset") == "1" {
+			resp = map[string]interface{}{
+				"success": true,
+				"data":    []map[string]string{{"name": "sb2", "status": "stopped", "backend": "docker"}},
+			}
+		}
+		json.NewEncoder(w).Encode(resp)
+	})
+	defer srv.Close()
+
+	sandboxes, err := client.ListSandboxes(context.Background())
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(sandboxes) != 2 || sandboxes[0].Name != "sb1" || sandboxes[1].Name != "sb2" {
+		t.Fatalf("expected sb1 and sb2, got %+v", sandboxes)
+	}
+}
+
 func TestCreateSandbox(t *testing.T) {
 	client, srv := testClient(func(w http.ResponseWriter, r *http.Request) {
 		if r.Method != "POST" {
@@ -51,6 +51,7 @@ type apiResponse[T any] struct {
 	Success bool   `json:"success"`
 	Data    T      `json:"data,omitempty"`
 	Error   string `json:"error,omitempty"`
+	Next    string `json:"next,omitempty"`
 }
 
 // runRequest is the POST /run body.
@@ -103,7 +103,7 @@ export class AgentKernel {
 
   /** List all sandboxes. */
   async listSandboxes(): Promise<SandboxInfo[]> {
-    return this.request<SandboxInfo[]>("GET", "/sandboxes");
+    return this.requestAll<SandboxInfo>("/sandboxes");
   }
 
   /** Create a new sandbox. */
@@ -171,8 +171,7 @@ export class AgentKernel {
 
   /** Get audit log entries for a sandbox. */
   async getSandboxLogs(name: string): Promise<Record<string, unknown>[]> {
-    return this.request<Record<string, unknown>[]>(
-      "GET",
+    return this.requestAll<Record<string, unknown>>(
       `/sandboxes/${encodeURIComponent(name)}/logs`,
     );
   }
@@ -244,6 +243,26 @@ export class AgentKernel {
     path: string,
     body?: unknown,
   ): Promise<T> {
+    return (await this.requestPage<T>(method, path, body)).data as T;
+  }
+
+  /** GET every page of a paginated list, following `next` links. */
+  private async requestAll<T>(path: string): Promise<T[]> {
+    const items: T[] = [];
+    let next: string | undefined = path;
+    while (next) {
+      const page: ApiResponse<T[]> = await this.requestPage<T[]>("GET", next);
+      items.push(...(page.data ?? []));
+      next = page.next;
+    }
+    return items;
+  }
+
+  private async requestPage<T>(
+    method: string,
+    path: string,
+    body?: unknown,
+  ): Promise<ApiResponse<T>> {
     const init: RequestInit = {
       method,
       headers: this.headers(body ? "application/json" : undefined),
@@ -261,6 +280,6 @@ export class AgentKernel {
     if (!parsed.success) {
       throw new AgentKernelError(parsed.error ?? "Unknown error");
     }
-    return parsed.data as T;
+    return parsed;
   }
 }
@@ -92,4 +92,6 @@ export interface ApiResponse<T> {
   success: boolean;
   data?: T;
   error?: string;
+  /** Path of the next page, for paginated lists. */
+  next?: string;
 }
@@ -123,6 +123,27 @@ describe("AgentKernel", () => {
       expect(list[0].name).toBe("test-1");
       expect(list[0].status).toBe("running");
     });
+
+    it("follows next links to later pages", async () => {
+      server.use(
+        http.get(`${BASE_URL}/sandboxes`, ({ request }) => {
+          const offset = new URL(request.url).searchParams.get("offset");
+          if (offset === "1") {
+            return HttpResponse.json({
+              success: true,
+              data: [{ name: "test-2", status: "stopped", backend: "docker" }],
+            });
+          }
+          return HttpResponse.json({
+            success: true,
+            data: [{ name: "test-1", status: "running", backend: "docker" }],
+            next: "/sandboxes?limit=1&offset=1",
+          });
+        }),
+      );
+      const list = await client().listSandboxes();
+      expect(list.map((sb) => sb.name)).toEqual(["test-1", "test-2"]);
+    });
   });
 
   describe("createSandbox", () => {
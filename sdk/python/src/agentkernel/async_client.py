@@ -148,7 +148,7 @@ class AsyncAgentKernel:
 
     async def list_sandboxes(self) -> list[SandboxInfo]:
         """List all sandboxes."""
-        data = await self._request("GET", "/sandboxes")
+        data = await self._request_all("/sandboxes")
         return [SandboxInfo(**s) for s in data]
 
     async def create_sandbox(
@@ -208,7 +208,7 @@ class AsyncAgentKernel:
 
     async def get_sandbox_logs(self, name: str) -> list[dict]:
         """Get audit log entries for a sandbox."""
-        return await self._request("GET", f"/sandboxes/{name}/logs")
+        return await self._request_all(f"/sandboxes/{name}/logs")
 
     async def batch_run(self, commands: list[list[str]]) -> BatchRunResponse:
         """Run multiple commands in parallel."""
@@ -239,6 +239,19 @@ class AsyncAgentKernel:
     # -- Internal --
 
     async def _request(self, method: str, path: str, **kwargs: Any) -> Any:
+        return (await self._request_page(method, path, **kwargs)).get("data")
+
+    async def _request_all(self, path: str) -> list[Any]:
+        """GET every page of a paginated list, following ``next`` links."""
+        items: list[Any] = []
+        next_path: str | None = path
+        while next_path:
+            page = await self._request_page("GET", next_path)
+            items.extend(page.get("data") or [])
+            next_path = page.get("next")
+        return items
+
+    async def _request_page(self, method: str, path: str, **kwargs: Any) -> dict[str, Any]:
         try:
             response = await self._http.request(method, path, **kwargs)
         except httpx.ConnectError as e:
@@ -252,4 +265,4 @@ class AsyncAgentKernel:
         data = response.json()
         if not data.get("success"):
             raise AgentKernelError(data.get("error", "Unknown error"))
-        return data.get("data")
+        return data
@@ -134,7 +134,7 @@ class AgentKernel:
 
     def list_sandboxes(self) -> list[SandboxInfo]:
         """List all sandboxes."""
-        data = self._request("GET", "/sandboxes")
+        data = self._request_all("/sandboxes")
         return [SandboxInfo(**s) for s in data]
 
     def create_sandbox(
@@ -194,7 +194,7 @@ class AgentKernel:
 
     def get_sandbox_logs(self, name: str) -> list[dict]:
         """Get audit log entries for a sandbox."""
-        return self._request("GET", f"/sandboxes/{name}/logs")
+        return self._request_all(f"/sandboxes/{name}/logs")
 
     def batch_run(self, commands: list[list[str]]) -> BatchRunResponse:
         """Run multiple commands in parallel."""
@@ -225,6 +225,19 @@ class AgentKernel:
     # -- Internal --
 
     def _request(self, method: str, path: str, **kwargs: Any) -> Any:
+        return self._request_page(method, path, **kwargs).get("data")
+
+    def _request_all(self, path: str) -> list[Any]:
+        """GET every page of a paginated list, following ``next`` links."""
+        items: list[Any] = []
+        next_path: str | None = path
+        while next_path:
+            page = self._request_page("GET", next_path)
+            items.extend(page.get("data") or [])
+            next_path = page.get("next")
+        return items
+
+    def _request_page(self, method: str, path: str, **kwargs: Any) -> dict[str, Any]:
         try:
             response = self._http.request(method, path, **kwargs)
         except httpx.ConnectError as e:
@@ -238,4 +251,4 @@ class AgentKernel:
         data = response.json()
         if not data.get("success"):
             raise AgentKernelError(data.get("error", "Unknown error"))
-        return data.get("data")
+        return data
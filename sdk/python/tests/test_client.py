@@ -67,6 +67,25 @@ class TestListSandboxes:
         assert all(isinstance(s, SandboxInfo) for s in result)
         assert result[0].name == "sb-1"
 
+    def test_follows_next_page(self, httpx_mock: HTTPXMock) -> None:
+        httpx_mock.add_response(
+            url=f"{BASE_URL}/sandboxes",
+            json={
+                "success": True,
+                "data": [{"name": "sb-1", "status": "running", "backend": "docker"}],
+                "next": "/sandboxes?limit=1&offset=1",
+            },
+        )
+        httpx_mock.add_response(
+            url=f"{BASE_URL}/sandboxes?limit=1&offset=1",
+            json={
+                "success": True,
+                "data": [{"name": "sb-2", "status": "stopped", "backend": "docker"}],
+            },
+        )
+        result = make_client().list_sandboxes()
+        assert [s.name for s in result] == ["sb-1", "sb-2"]
+
 
 class TestCreateSandbox:
     def test_creates(self, httpx_mock: HTTPXMock) -> None:
@@ -0,0 +1,204 @@
+//! A blocking client, for scripts and other code without an async runtime.
+//!
+//! Enabled with the `blocking` feature. Mirrors reqwest's blocking client:
+//! each [`AgentKernel`] owns a current-thread Tokio runtime and blocks on the
+//! async client's calls.
+//!
+//! **Don't use it from within an async context** (e.g. inside `#[tokio::main]`
+//! or a spawned task): blocking on a runtime from inside another one panics.
+//! Use the async [`crate::AgentKernel`] there instead.
+//!
+//! ```no_run
+//! # fn example() -> agentkernel_sdk::Result<()> {
+//! use agentkernel_sdk::blocking::AgentKernel;
+//!
+//! let client = AgentKernel::builder().build()?;
+//! let output = client.run(&["echo", "hello"], None)?;
+//! println!("{}", output.output);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::types::*;
+
+/// Builder for a blocking [`AgentKernel`]. See [`crate::AgentKernelBuilder`].
+pub struct AgentKernelBuilder {
+    inner: crate::AgentKernelBuilder,
+}
+
+impl AgentKernelBuilder {
+    /// Set the base URL.
+    pub fn base_url(self, url: impl Into<String>) -> Self {
+        Self {
+            inner: self.inner.base_url(url),
+        }
+    }
+
+    /// Set the API key for Bearer authentication.
+    pub fn api_key(self, key: impl Into<String>) -> Self {
+        Self {
+            inner: self.inner.api_key(key),
+        }
+    }
+
+    /// Set the request timeout.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            inner: self.inner.timeout(timeout),
+        }
+    }
+
+    /// Retry idempotent requests up to `n` times. See [`crate::AgentKernelBuilder::retries`].
+    pub fn retries(self, n: u32) -> Self {
+        Self {
+            inner: self.inner.retries(n),
+        }
+    }
+
+    /// Set the base delay for exponential backoff between retries.
+    pub fn backoff(self, base: Duration) -> Self {
+        Self {
+            inner: self.inner.backoff(base),
+        }
+    }
+
+    /// Also retry `POST /run`.
+    pub fn retry_run(self, enabled: bool) -> Self {
+        Self {
+            inner: self.inner.retry_run(enabled),
+        }
+    }
+
+    /// Build the client and its runtime.
+    pub fn build(self) -> Result<AgentKernel> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(AgentKernel {
+            inner: self.inner.build()?,
+            runtime: Arc::new(runtime),
+        })
+    }
+}
+
+/// Blocking client for the agentkernel HTTP API.
+///
+/// Cloning is cheap; clones share the runtime.
+#[derive(Clone)]
+pub struct AgentKernel {
+    inner: crate::AgentKernel,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl AgentKernel {
+    /// Create a new builder with defaults resolved from env vars.
+    pub fn builder() -> AgentKernelBuilder {
+        AgentKernelBuilder {
+            inner: crate::AgentKernel::builder(),
+        }
+    }
+
+    /// Health check. Returns `"ok"`.
+    pub fn health(&self) -> Result<String> {
+        self.block_on(self.inner.health())
+    }
+
+    /// Run a command in a temporary sandbox.
+    pub fn run(&self, command: &[&str], opts: Option<RunOptions>) -> Result<RunOutput> {
+        self.block_on(self.inner.run(command, opts))
+    }
+
+    /// List all sandboxes.
+    pub fn list_sandboxes(&self) -> Result<Vec<SandboxInfo>> {
+        self.block_on(self.inner.list_sandboxes())
+    }
+
+    /// Create a new sandbox with optional resource limits.
+    pub fn create_sandbox(
+        &self,
+        name: &str,
+        image: Option<&str>,
+        vcpus: Option<u32>,
+        memory_mb: Option<u64>,
+        profile: Option<SecurityProfile>,
+    ) -> Result<SandboxInfo> {
+        self.block_on(
+            self.inner
+                .create_sandbox(name, image, vcpus, memory_mb, profile),
+        )
+    }
+
+    /// Get info about a sandbox.
+    pub fn get_sandbox(&self, name: &str) -> Result<SandboxInfo> {
+        self.block_on(self.inner.get_sandbox(name))
+    }
+
+    /// Remove a sandbox.
+    pub fn remove_sandbox(&self, name: &str) -> Result<()> {
+        self.block_on(self.inner.remove_sandbox(name))
+    }
+
+    /// Run a command in an existing sandbox. See [`crate::AgentKernel::exec`].
+    pub fn exec(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
+        self.block_on(self.inner.exec(name, command))
+    }
+
+    /// Run a command in an existing sandbox, failing if it exits non-zero.
+    pub fn exec_checked(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
+        self.block_on(self.inner.exec_checked(name, command))
+    }
+
+    /// Read a file from a sandbox.
+    pub fn read_file(&self, name: &str, path: &str) -> Result<FileReadResponse> {
+        self.block_on(self.inner.read_file(name, path))
+    }
+
+    /// Write a file to a sandbox.
+    pub fn write_file(
+        &self,
+        name: &str,
+        path: &str,
+        content: &str,
+        encoding: Option<&str>,
+    ) -> Result<String> {
+        self.block_on(self.inner.write_file(name, path, content, encoding))
+    }
+
+    /// Delete a file from a sandbox.
+    pub fn delete_file(&self, name: &str, path: &str) -> Result<String> {
+        self.block_on(self.inner.delete_file(name, path))
+    }
+
+    /// Get audit log entries for a sandbox.
+    pub fn get_sandbox_logs(&self, name: &str) -> Result<Vec<serde_json::Value>> {
+        self.block_on(self.inner.get_sandbox_logs(name))
+    }
+
+    /// Run multiple commands in parallel.
+    pub fn batch_run(&self, commands: Vec<BatchCommand>) -> Result<BatchRunResponse> {
+        self.block_on(self.inner.batch_run(commands))
+    }
+
+    /// Create a sandbox, run `f` with it, then remove it whatever `f` returns.
+    pub fn with_sandbox<T>(
+        &self,
+        name: &str,
+        image: Option<&str>,
+        f: impl FnOnce(&Self, &str) -> Result<T>,
+    ) -> Result<T> {
+        self.create_sandbox(name, image, None, None, None)?;
+        let result = f(self, name);
+        // Always clean up
+        let _ = self.remove_sandbox(name);
+        result
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
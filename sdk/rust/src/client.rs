@@ -1,4 +1,9 @@
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt};
+use reqwest::header::{
+    HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER, USER_AGENT,
+};
+use std::hash::{BuildHasher, Hasher};
 use std::time::Duration;
 
 use crate::error::{error_from_status, Error, Result};
@@ -7,12 +12,84 @@ use crate::types::*;
 const SDK_VERSION: &str = env!("CARGO_PKG_VERSION");
 const DEFAULT_BASE_URL: &str = "http://localhost:18888";
 const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_BACKOFF_MS: u64 = 200;
+/// Upper bound for a single backoff delay.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// When and how to retry failed requests.
+#[derive(Clone, Debug)]
+struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    retry_run: bool,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay in `[d/2, d]` where
+    /// `d = backoff * 2^attempt`, capped at [`MAX_BACKOFF`].
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF);
+        let half = exp / 2;
+        let random = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        half + Duration::from_nanos(random % (half.as_nanos() as u64 + 1))
+    }
+}
+
+/// Whether a failed request is worth retrying (connection errors and timeouts).
+fn is_transient(e: &reqwest::Error) -> bool {
+    // fetch doesn't tell connection failures apart from other request errors
+    #[cfg(target_arch = "wasm32")]
+    let failed_to_send = e.is_request();
+    #[cfg(not(target_arch = "wasm32"))]
+    let failed_to_send = e.is_connect();
+    failed_to_send || e.is_timeout()
+}
+
+/// Wait before the next retry.
+async fn sleep(delay: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::sleep(delay).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(delay).await;
+}
+
+/// Longest wait a `Retry-After` header can ask for.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Parse a `Retry-After` header given in seconds, capped at [`MAX_RETRY_AFTER`].
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|secs| Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
 
 /// Builder for constructing an [`AgentKernel`] client.
 pub struct AgentKernelBuilder {
     base_url: String,
     api_key: Option<String>,
     timeout: Duration,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for AgentKernelBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentKernelBuilder")
+            .field("base_url", &self.base_url)
+            .field("api_key", &self.api_key.as_ref().map(|_| "[redacted]"))
+            .field("timeout", &self.timeout)
+            .field("retry", &self.retry)
+            .finish()
+    }
 }
 
 impl AgentKernelBuilder {
@@ -34,6 +111,31 @@ impl AgentKernelBuilder {
         self
     }
 
+    /// Retry idempotent requests up to `n` times on connection errors,
+    /// 429, and 503 responses (default: 0, no retries).
+    ///
+    /// GET requests are retried; `POST /run` only with [`retry_run`](Self::retry_run).
+    /// Other 4xx errors are never retried.
+    pub fn retries(mut self, n: u32) -> Self {
+        self.retry.retries = n;
+        self
+    }
+
+    /// Set the base delay for exponential backoff between retries (default: 200ms).
+    ///
+    /// A `Retry-After` header from the server takes precedence (up to 60s).
+    pub fn backoff(mut self, base: Duration) -> Self {
+        self.retry.backoff = base;
+        self
+    }
+
+    /// Also retry `POST /run`. Only enable this if running the command
+    /// twice is harmless.
+    pub fn retry_run(mut self, enabled: bool) -> Self {
+        self.retry.retry_run = enabled;
+        self
+    }
+
     /// Build the client.
     pub fn build(self) -> Result<AgentKernel> {
         let mut headers = HeaderMap::new();
@@ -42,21 +144,22 @@ impl AgentKernelBuilder {
             HeaderValue::from_str(&format!("agentkernel-rust-sdk/{SDK_VERSION}")).unwrap(),
         );
         if let Some(ref key) = self.api_key {
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {key}"))
-                    .map_err(|e| Error::Auth(e.to_string()))?,
-            );
+            let mut value = HeaderValue::from_str(&format!("Bearer {key}"))
+                .map_err(|e| Error::Auth(e.to_string()))?;
+            // Keeps the key out of `Debug` output of requests and headers
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
         }
 
         let http = reqwest::Client::builder()
             .default_headers(headers)
-            .timeout(self.timeout)
             .build()?;
 
         Ok(AgentKernel {
             base_url: self.base_url.trim_end_matches('/').to_string(),
             http,
+            timeout: self.timeout,
+            retry: self.retry,
         })
     }
 }
@@ -76,6 +179,8 @@ impl AgentKernelBuilder {
 pub struct AgentKernel {
     base_url: String,
     http: reqwest::Client,
+    timeout: Duration,
+    retry: RetryPolicy,
 }
 
 impl AgentKernel {
@@ -86,6 +191,11 @@ impl AgentKernel {
                 .unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
             api_key: std::env::var("AGENTKERNEL_API_KEY").ok(),
             timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            retry: RetryPolicy {
+                retries: 0,
+                backoff: Duration::from_millis(DEFAULT_BACKOFF_MS),
+                retry_run: false,
+            },
         }
     }
 
@@ -108,10 +218,87 @@ impl AgentKernel {
             .await
     }
 
+    /// Run a command in a temporary sandbox, streaming its events as they arrive.
+    ///
+    /// The request timeout doesn't apply once the stream has started.
+    /// Dropping the stream closes the SSE connection.
+    pub async fn run_stream(
+        &self,
+        command: &[&str],
+        opts: Option<RunOptions>,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>>> {
+        let opts = opts.unwrap_or_default();
+        let body = RunRequest {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            image: opts.image,
+            profile: opts.profile,
+            fast: opts.fast.unwrap_or(true),
+        };
+        let response = self
+            .http
+            .post(format!("{}/run/stream", self.base_url))
+            .header(CONTENT_TYPE, "application/json")
+            .json(&body)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        if status >= 400 {
+            let text = response.text().await?;
+            return Err(error_from_status(status, &text));
+        }
+
+        Ok(response.bytes_stream().eventsource().map(|event| {
+            let event = event.map_err(|e| Error::Stream(e.to_string()))?;
+            let data = serde_json::from_str(&event.data)
+                .unwrap_or_else(|_| serde_json::json!({ "raw": event.data }));
+            Ok(StreamEvent {
+                event_type: event.event,
+                data,
+            })
+        }))
+    }
+
+    /// Run a command as an asynchronous job in its own sandbox.
+    ///
+    /// Returns as soon as the job is submitted. Unlike dropping a
+    /// [`run`](Self::run) future, [`JobHandle::cancel`] stops the command
+    /// on the server.
+    pub async fn submit_job(
+        &self,
+        command: &[&str],
+        opts: Option<RunOptions>,
+    ) -> Result<JobHandle> {
+        let opts = opts.unwrap_or_default();
+        let body = RunRequest {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            image: opts.image,
+            profile: opts.profile,
+            fast: opts.fast.unwrap_or(true),
+        };
+        let info: JobInfo = self
+            .request(reqwest::Method::POST, "/jobs", Some(&body))
+            .await?;
+        Ok(JobHandle {
+            id: info.id,
+            client: self.clone(),
+        })
+    }
+
+    /// Get the status of a job.
+    pub async fn get_job(&self, id: &str) -> Result<JobInfo> {
+        self.request(reqwest::Method::GET, &format!("/jobs/{id}"), None::<&()>)
+            .await
+    }
+
+    /// Cancel a job, removing the sandbox it runs in.
+    pub async fn cancel_job(&self, id: &str) -> Result<JobInfo> {
+        self.request(reqwest::Method::DELETE, &format!("/jobs/{id}"), None::<&()>)
+            .await
+    }
+
     /// List all sandboxes.
     pub async fn list_sandboxes(&self) -> Result<Vec<SandboxInfo>> {
-        self.request(reqwest::Method::GET, "/sandboxes", None::<&()>)
-            .await
+        self.request_all("/sandboxes").await
     }
 
     /// Create a new sandbox with optional resource limits.
@@ -134,6 +321,49 @@ impl AgentKernel {
             .await
     }
 
+    /// Start building a sandbox named `name`.
+    ///
+    /// Set options on the returned [`SandboxBuilder`], then call
+    /// [`create`](SandboxBuilder::create) to get a [`SandboxHandle`]. The
+    /// sandbox lives until [`SandboxHandle::remove`] (or
+    /// [`remove_sandbox`](Self::remove_sandbox)) is called; dropping the
+    /// handle doesn't remove it.
+    ///
+    /// ```no_run
+    /// # async fn example() -> agentkernel_sdk::Result<()> {
+    /// use agentkernel_sdk::{AgentKernel, SecurityProfile};
+    ///
+    /// let client = AgentKernel::builder().build()?;
+    /// let sandbox = client
+    ///     .sandbox("build-box")
+    ///     .image("python:3.12")
+    ///     .vcpus(2)
+    ///     .memory_mb(1024)
+    ///     .profile(SecurityProfile::Restrictive)
+    ///     .create()
+    ///     .await?;
+    ///
+    /// sandbox.write_file("tmp/hello.py", "print('hello')", None).await?;
+    /// let output = sandbox.exec(&["python3", "/tmp/hello.py"]).await?;
+    /// println!("{} (exit {})", output.output, output.exit_code);
+    ///
+    /// sandbox.remove().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn sandbox(&self, name: impl Into<String>) -> SandboxBuilder {
+        SandboxBuilder {
+            client: self.clone(),
+            request: CreateRequest {
+                name: name.into(),
+                image: None,
+                vcpus: None,
+                memory_mb: None,
+                profile: None,
+            },
+        }
+    }
+
     /// Get info about a sandbox.
     pub async fn get_sandbox(&self, name: &str) -> Result<SandboxInfo> {
         self.request(
@@ -157,9 +387,15 @@ impl AgentKernel {
     }
 
     /// Run a command in an existing sandbox.
-    pub async fn exec_in_sandbox(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
+    ///
+    /// Returns `Ok` whenever the command ran, whatever its exit code; check
+    /// [`RunOutput::exit_code`]. An `Err` means the command couldn't be run
+    /// (missing sandbox, network error, ...). Use [`exec_checked`](Self::exec_checked)
+    /// to treat a non-zero exit as an error.
+    pub async fn exec(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
         let body = ExecRequest {
             command: command.iter().map(|s| s.to_string()).collect(),
+            check: false,
         };
         self.request(
             reqwest::Method::POST,
@@ -169,6 +405,24 @@ impl AgentKernel {
         .await
     }
 
+    /// Run a command in an existing sandbox, failing with
+    /// [`Error::CommandFailed`] if it exits non-zero.
+    pub async fn exec_checked(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
+        let output = self.exec(name, command).await?;
+        if output.exit_code != 0 {
+            return Err(Error::CommandFailed {
+                exit_code: output.exit_code,
+                output: output.output,
+            });
+        }
+        Ok(output)
+    }
+
+    /// Run a command in an existing sandbox. Same as [`exec_checked`](Self::exec_checked).
+    pub async fn exec_in_sandbox(&self, name: &str, command: &[&str]) -> Result<RunOutput> {
+        self.exec_checked(name, command).await
+    }
+
     /// Create a sandbox and return a guard that removes it on drop.
     ///
     /// Use `with_sandbox` for guaranteed cleanup via a closure.
@@ -230,12 +484,7 @@ impl AgentKernel {
 
     /// Get audit log entries for a sandbox.
     pub async fn get_sandbox_logs(&self, name: &str) -> Result<Vec<serde_json::Value>> {
-        self.request(
-            reqwest::Method::GET,
-            &format!("/sandboxes/{name}/logs"),
-            None::<&()>,
-        )
-        .await
+        self.request_all(&format!("/sandboxes/{name}/logs")).await
     }
 
     /// Run multiple commands in parallel.
@@ -253,15 +502,68 @@ impl AgentKernel {
         path: &str,
         body: Option<&(impl serde::Serialize + ?Sized)>,
     ) -> Result<T> {
-        let url = format!("{}{path}", self.base_url);
-        let mut req = self.http.request(method, &url);
-        if let Some(b) = body {
-            req = req.header(CONTENT_TYPE, "application/json").json(b);
+        let (data, _) = self.request_page(method, path, body).await?;
+        Ok(data)
+    }
+
+    /// GET every page of a paginated list, following `next` links.
+    async fn request_all<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut next = Some(path.to_string());
+        while let Some(path) = next {
+            let (page, link): (Vec<T>, _) = self
+                .request_page(reqwest::Method::GET, &path, None::<&()>)
+                .await?;
+            items.extend(page);
+            next = link;
         }
+        Ok(items)
+    }
 
-        let response = req.send().await?;
-        let status = response.status().as_u16();
-        let text = response.text().await?;
+    /// Send a request and return its data with the link to the next page, if any.
+    async fn request_page<T: serde::de::DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&(impl serde::Serialize + ?Sized)>,
+    ) -> Result<(T, Option<String>)> {
+        let url = format!("{}{path}", self.base_url);
+        let idempotent = method == reqwest::Method::GET
+            || (self.retry.retry_run && method == reqwest::Method::POST && path == "/run");
+
+        #[cfg(feature = "tracing")]
+        let (status, text) = {
+            use tracing::Instrument;
+
+            // Only the method and URL are recorded; headers (and so the API key) never are
+            let span = tracing::debug_span!(
+                "agentkernel.request",
+                method = %method,
+                url = %url,
+                status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            );
+            #[cfg(target_arch = "wasm32")]
+            let start = web_time::Instant::now();
+            #[cfg(not(target_arch = "wasm32"))]
+            let start = std::time::Instant::now();
+            let result = self
+                .send(method, &url, idempotent, body)
+                .instrument(span.clone())
+                .await;
+            let latency_ms = start.elapsed().as_millis() as u64;
+            span.record("latency_ms", latency_ms);
+            span.in_scope(|| match &result {
+                Ok((status, _)) => {
+                    span.record("status", status);
+                    tracing::debug!(status, latency_ms, "response");
+                }
+                Err(e) => tracing::debug!(error = %e, latency_ms, "request failed"),
+            });
+            result?
+        };
+        #[cfg(not(feature = "tracing"))]
+        let (status, text) = self.send(method, &url, idempotent, body).await?;
 
         if status >= 400 {
             return Err(error_from_status(status, &text));
@@ -273,13 +575,103 @@ impl AgentKernel {
                 parsed.error.unwrap_or_else(|| "Unknown error".to_string()),
             ));
         }
-        parsed
+        let data = parsed
             .data
-            .ok_or_else(|| Error::Server("Missing data field".to_string()))
+            .ok_or_else(|| Error::Server("Missing data field".to_string()))?;
+        Ok((data, parsed.next))
+    }
+
+    /// Send a request, retrying if allowed, and return the status and body.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        idempotent: bool,
+        body: Option<&(impl serde::Serialize + ?Sized)>,
+    ) -> Result<(u16, String)> {
+        let mut attempt = 0;
+        let response = loop {
+            // Set per request: wasm's fetch client has no client-wide timeout
+            let mut req = self.http.request(method.clone(), url).timeout(self.timeout);
+            if let Some(b) = body {
+                req = req.header(CONTENT_TYPE, "application/json").json(b);
+            }
+            let can_retry = idempotent && attempt < self.retry.retries;
+
+            match req.send().await {
+                Err(e) if can_retry && is_transient(&e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, error = %e, "retrying");
+                    sleep(self.retry.delay(attempt)).await;
+                }
+                Err(e) => return Err(e.into()),
+                Ok(response) if can_retry && matches!(response.status().as_u16(), 429 | 503) => {
+                    let delay = retry_after(response.headers())
+                        .unwrap_or_else(|| self.retry.delay(attempt));
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(attempt, status = response.status().as_u16(), "retrying");
+                    sleep(delay).await;
+                }
+                Ok(response) => break response,
+            }
+            attempt += 1;
+        };
+
+        let status = response.status().as_u16();
+        let text = response.text().await?;
+        Ok((status, text))
     }
 }
 
-/// Handle to a sandbox within a `with_sandbox` closure.
+/// Builder for a new sandbox, returned by [`AgentKernel::sandbox`].
+///
+/// Unset options use the server's defaults.
+#[must_use = "the sandbox isn't created until `create` is awaited"]
+pub struct SandboxBuilder {
+    client: AgentKernel,
+    request: CreateRequest,
+}
+
+impl SandboxBuilder {
+    /// Set the container/VM image (e.g. `"python:3.12-alpine"`).
+    pub fn image(mut self, image: impl Into<String>) -> Self {
+        self.request.image = Some(image.into());
+        self
+    }
+
+    /// Set the number of vCPUs.
+    pub fn vcpus(mut self, vcpus: u32) -> Self {
+        self.request.vcpus = Some(vcpus);
+        self
+    }
+
+    /// Set the memory limit in MB.
+    pub fn memory_mb(mut self, memory_mb: u64) -> Self {
+        self.request.memory_mb = Some(memory_mb);
+        self
+    }
+
+    /// Set the security profile.
+    pub fn profile(mut self, profile: SecurityProfile) -> Self {
+        self.request.profile = Some(profile);
+        self
+    }
+
+    /// Create the sandbox and return a handle to it.
+    pub async fn create(self) -> Result<SandboxHandle> {
+        let info: SandboxInfo = self
+            .client
+            .request(reqwest::Method::POST, "/sandboxes", Some(&self.request))
+            .await?;
+        Ok(SandboxHandle {
+            name: info.name,
+            client: self.client,
+        })
+    }
+}
+
+/// Handle to a sandbox, from [`SandboxBuilder::create`] or within a
+/// `with_sandbox` closure.
 ///
 /// Owns a clone of the client (cheap — `reqwest::Client` is `Arc`-backed).
 pub struct SandboxHandle {
@@ -293,9 +685,14 @@ impl SandboxHandle {
         &self.name
     }
 
-    /// Run a command in this sandbox.
+    /// Run a command in this sandbox, failing if it exits non-zero.
     pub async fn run(&self, command: &[&str]) -> Result<RunOutput> {
-        self.client.exec_in_sandbox(&self.name, command).await
+        self.client.exec_checked(&self.name, command).await
+    }
+
+    /// Run a command in this sandbox and return its exit code. See [`AgentKernel::exec`].
+    pub async fn exec(&self, command: &[&str]) -> Result<RunOutput> {
+        self.client.exec(&self.name, command).await
     }
 
     /// Get sandbox info.
@@ -309,12 +706,49 @@ impl SandboxHandle {
     }
 
     /// Write a file to this sandbox.
-    pub async fn write_file(&self, path: &str, content: &str, encoding: Option<&str>) -> Result<String> {
-        self.client.write_file(&self.name, path, content, encoding).await
+    pub async fn write_file(
+        &self,
+        path: &str,
+        content: &str,
+        encoding: Option<&str>,
+    ) -> Result<String> {
+        self.client
+            .write_file(&self.name, path, content, encoding)
+            .await
     }
 
     /// Delete a file from this sandbox.
     pub async fn delete_file(&self, path: &str) -> Result<String> {
         self.client.delete_file(&self.name, path).await
     }
+
+    /// Remove this sandbox.
+    pub async fn remove(self) -> Result<()> {
+        self.client.remove_sandbox(&self.name).await
+    }
+}
+
+/// Handle to an asynchronous job, from [`AgentKernel::submit_job`].
+///
+/// Dropping the handle leaves the job running.
+pub struct JobHandle {
+    id: String,
+    client: AgentKernel,
+}
+
+impl JobHandle {
+    /// The job ID.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Get the job's current status.
+    pub async fn status(&self) -> Result<JobInfo> {
+        self.client.get_job(&self.id).await
+    }
+
+    /// Cancel the job, stopping its command on the server.
+    pub async fn cancel(&self) -> Result<JobInfo> {
+        self.client.cancel_job(&self.id).await
+    }
 }
@@ -13,10 +13,30 @@ pub enum Error {
     #[error("validation error: {0}")]
     Validation(String),
 
+    /// 403 Forbidden: the server's security policy denied the command.
+    #[error("policy denied: {0}")]
+    PolicyDenied(String),
+
+    /// 409 Conflict, e.g. the sandbox isn't running.
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    /// 408 Request Timeout.
+    #[error("timeout: {0}")]
+    Timeout(String),
+
+    /// 503 Service Unavailable: no sandbox backend can be used.
+    #[error("backend unavailable: {0}")]
+    Unavailable(String),
+
     /// 500 Internal Server Error.
     #[error("server error: {0}")]
     Server(String),
 
+    /// The command ran but exited non-zero (from `exec_checked`).
+    #[error("command exited with code {exit_code}")]
+    CommandFailed { exit_code: i32, output: String },
+
     /// Network / connection error.
     #[error("network error: {0}")]
     Network(#[from] reqwest::Error),
@@ -28,21 +48,44 @@ pub enum Error {
     /// JSON serialization/deserialization error.
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+
+    /// I/O error (e.g. starting the blocking client's runtime).
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Map an HTTP status + body to the appropriate error variant.
+///
+/// The body's `error_code` is used when present, the status otherwise.
 pub fn error_from_status(status: u16, body: &str) -> Error {
-    let message = serde_json::from_str::<serde_json::Value>(body)
-        .ok()
-        .and_then(|v| v.get("error").and_then(|e| e.as_str().map(String::from)))
-        .unwrap_or_else(|| body.to_string());
-
-    match status {
-        400 => Error::Validation(message),
-        401 => Error::Auth(message),
-        404 => Error::NotFound(message),
-        _ => Error::Server(message),
+    let value = serde_json::from_str::<serde_json::Value>(body).ok();
+    let field = |name: &str| {
+        value
+            .as_ref()
+            .and_then(|v| v.get(name).and_then(|e| e.as_str().map(String::from)))
+    };
+    let message = field("error").unwrap_or_else(|| body.to_string());
+
+    match field("error_code").as_deref() {
+        Some("INVALID_REQUEST" | "INVALID_IMAGE") => Error::Validation(message),
+        Some("UNAUTHORIZED") => Error::Auth(message),
+        Some("NOT_FOUND" | "SANDBOX_NOT_FOUND") => Error::NotFound(message),
+        Some("SANDBOX_NOT_RUNNING" | "CONFLICT") => Error::Conflict(message),
+        Some("POLICY_DENIED") => Error::PolicyDenied(message),
+        Some("TIMEOUT") => Error::Timeout(message),
+        Some("BACKEND_UNAVAILABLE") => Error::Unavailable(message),
+        Some("INTERNAL") => Error::Server(message),
+        _ => match status {
+            400 => Error::Validation(message),
+            401 => Error::Auth(message),
+            403 => Error::PolicyDenied(message),
+            404 => Error::NotFound(message),
+            408 => Error::Timeout(message),
+            409 => Error::Conflict(message),
+            503 => Error::Unavailable(message),
+            _ => Error::Server(message),
+        },
     }
 }
@@ -15,14 +15,38 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! ## Cancellation
+//!
+//! Every async method is cancel-safe: dropping its future (for example when a
+//! `tokio::select!` branch or `tokio::time::timeout` gives up on it) aborts the
+//! in-flight HTTP request. Dropping the stream from
+//! [`run_stream`](AgentKernel::run_stream) closes the SSE connection.
+//!
+//! Dropping a request only stops waiting for it; the server may still finish
+//! the command. To stop the command itself, run it as a job with
+//! [`submit_job`](AgentKernel::submit_job) and call [`JobHandle::cancel`].
+//!
+//! ## WebAssembly
+//!
+//! The async client also builds for `wasm32-unknown-unknown`, where reqwest
+//! sends requests with the browser's `fetch`. The `blocking` feature needs a
+//! Tokio runtime and is not available there.
+
+#[cfg(all(feature = "blocking", target_arch = "wasm32"))]
+compile_error!(
+    "the `blocking` feature is not supported on wasm32; use the async `AgentKernel` client"
+);
 
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
 mod client;
 mod error;
 mod types;
 
-pub use client::{AgentKernel, AgentKernelBuilder, SandboxHandle};
+pub use client::{AgentKernel, AgentKernelBuilder, JobHandle, SandboxBuilder, SandboxHandle};
 pub use error::{Error, Result};
 pub use types::{
-    BatchCommand, BatchResult, BatchRunResponse, FileReadResponse, RunOptions, RunOutput,
-    SandboxInfo, SecurityProfile, StreamEvent,
+    BatchCommand, BatchResult, BatchRunResponse, FileReadResponse, JobInfo, JobStatus, RunOptions,
+    RunOutput, SandboxInfo, SecurityProfile, StreamEvent,
 };
@@ -24,6 +24,14 @@ pub struct RunOptions {
 #[derive(Debug, Deserialize)]
 pub struct RunOutput {
     pub output: String,
+    /// Exit code of the command. Only reported by sandbox exec; other
+    /// calls fail on a non-zero exit, so this is 0.
+    #[serde(default)]
+    pub exit_code: i32,
+    /// `"base64"` when the output wasn't valid UTF-8 and `output` holds it
+    /// base64-encoded.
+    #[serde(default)]
+    pub encoding: Option<String>,
 }
 
 /// Information about a sandbox.
@@ -45,12 +53,46 @@ pub struct StreamEvent {
     pub data: serde_json::Value,
 }
 
+/// State of an asynchronous job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job has finished (successfully or not).
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Running)
+    }
+}
+
+/// An asynchronous job, from [`AgentKernel::submit_job`](crate::AgentKernel::submit_job).
+#[derive(Debug, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub status: JobStatus,
+    pub command: Vec<String>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    /// Command output, once the job has completed.
+    pub output: Option<String>,
+    /// Why the job failed.
+    pub error: Option<String>,
+}
+
 /// API response wrapper (internal).
 #[derive(Debug, Deserialize)]
 pub(crate) struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// Path of the next page, for paginated lists.
+    #[serde(default)]
+    pub next: Option<String>,
 }
 
 /// Run request body (internal).
@@ -82,6 +124,7 @@ pub(crate) struct CreateRequest {
 #[derive(Serialize)]
 pub(crate) struct ExecRequest {
     pub command: Vec<String>,
+    pub check: bool,
 }
 
 /// File write request body (internal).
@@ -0,0 +1,72 @@
+#![cfg(feature = "blocking")]
+
+use agentkernel_sdk::blocking::AgentKernel;
+use agentkernel_sdk::Error;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Start a mock server on its own runtime; the blocking client must run
+/// outside any async context.
+fn mock_server(mocks: Vec<Mock>) -> (tokio::runtime::Runtime, MockServer) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = rt.block_on(async {
+        let server = MockServer::start().await;
+        for mock in mocks {
+            mock.mount(&server).await;
+        }
+        server
+    });
+    (rt, server)
+}
+
+fn test_client(server: &MockServer) -> AgentKernel {
+    AgentKernel::builder()
+        .base_url(server.uri())
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn blocking_run_command() {
+    let (_rt, server) = mock_server(vec![Mock::given(method("POST"))
+        .and(path("/run"))
+        .and(body_partial_json(
+            serde_json::json!({"command": ["echo", "hello world"]}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"output": "hello world"}
+        })))]);
+
+    let client = test_client(&server);
+    let result = client.run(&["echo", "hello world"], None).unwrap();
+    assert_eq!(result.output, "hello world");
+}
+
+#[test]
+fn blocking_exec_and_errors() {
+    let (_rt, server) = mock_server(vec![
+        Mock::given(method("POST"))
+            .and(path("/sandboxes/sb1/exec"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true,
+                "data": {"output": "1 failing\n", "exit_code": 1}
+            }))),
+        Mock::given(method("GET"))
+            .and(path("/sandboxes/missing"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "success": false,
+                "error": "Sandbox not found"
+            }))),
+    ]);
+
+    let client = test_client(&server);
+    let output = client.exec("sb1", &["npm", "test"]).unwrap();
+    assert_eq!(output.exit_code, 1);
+
+    let err = client.exec_checked("sb1", &["npm", "test"]).unwrap_err();
+    assert!(matches!(err, Error::CommandFailed { exit_code: 1, .. }));
+
+    let err = client.get_sandbox("missing").unwrap_err();
+    assert!(matches!(err, Error::NotFound(_)));
+}
@@ -1,5 +1,7 @@
-use agentkernel_sdk::{AgentKernel, Error};
-use wiremock::matchers::{header, method, path};
+#![cfg(not(target_arch = "wasm32"))]
+
+use agentkernel_sdk::{AgentKernel, Error, JobStatus, SecurityProfile};
+use wiremock::matchers::{body_json, body_partial_json, header, method, path, query_param};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 async fn test_client(server: &MockServer) -> AgentKernel {
@@ -65,6 +67,34 @@ async fn list_sandboxes() {
     assert_eq!(sandboxes[1].status, "stopped");
 }
 
+#[tokio::test]
+async fn list_sandboxes_follows_next_page() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sandboxes"))
+        .and(query_param("offset", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": [{"name": "sb2", "status": "stopped", "backend": "docker"}]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/sandboxes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": [{"name": "sb1", "status": "running", "backend": "docker"}],
+            "next": "/sandboxes?limit=1&offset=1"
+        })))
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let sandboxes = client.list_sandboxes().await.unwrap();
+    let names: Vec<&str> = sandboxes.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["sb1", "sb2"]);
+}
+
 #[tokio::test]
 async fn create_sandbox() {
     let server = MockServer::start().await;
@@ -78,7 +108,10 @@ async fn create_sandbox() {
         .await;
 
     let client = test_client(&server).await;
-    let info = client.create_sandbox("test", None, None, None, None).await.unwrap();
+    let info = client
+        .create_sandbox("test", None, None, None, None)
+        .await
+        .unwrap();
     assert_eq!(info.name, "test");
     assert_eq!(info.status, "running");
 }
@@ -137,6 +170,37 @@ async fn exec_in_sandbox() {
     assert_eq!(result.output, "executed");
 }
 
+#[tokio::test]
+async fn exec_returns_nonzero_exit_code() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/sandboxes/mybox/exec"))
+        .and(body_partial_json(serde_json::json!({"check": false})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"output": "1 test failed", "exit_code": 1}
+        })))
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let result = client.exec("mybox", &["npm", "test"]).await.unwrap();
+    assert_eq!(result.exit_code, 1);
+    assert_eq!(result.output, "1 test failed");
+
+    let err = client
+        .exec_checked("mybox", &["npm", "test"])
+        .await
+        .unwrap_err();
+    match err {
+        Error::CommandFailed { exit_code, output } => {
+            assert_eq!(exit_code, 1);
+            assert_eq!(output, "1 test failed");
+        }
+        other => panic!("expected CommandFailed, got {other:?}"),
+    }
+}
+
 #[tokio::test]
 async fn error_401() {
     let server = MockServer::start().await;
@@ -181,7 +245,10 @@ async fn error_400() {
         .await;
 
     let client = test_client(&server).await;
-    let err = client.create_sandbox("", None, None, None, None).await.unwrap_err();
+    let err = client
+        .create_sandbox("", None, None, None, None)
+        .await
+        .unwrap_err();
     assert!(matches!(err, Error::Validation(_)));
 }
 
@@ -201,6 +268,35 @@ async fn error_500() {
     assert!(matches!(err, Error::Server(_)));
 }
 
+#[tokio::test]
+async fn error_code_overrides_status() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/sandboxes/idle/exec"))
+        .respond_with(ResponseTemplate::new(409).set_body_json(serde_json::json!({
+            "success": false,
+            "error": "Sandbox 'idle' is not running",
+            "error_code": "SANDBOX_NOT_RUNNING"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/run"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(serde_json::json!({
+            "success": false,
+            "error": "No sandbox backend available",
+            "error_code": "BACKEND_UNAVAILABLE"
+        })))
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let err = client.exec_in_sandbox("idle", &["ls"]).await.unwrap_err();
+    assert!(matches!(err, Error::Conflict(_)));
+    let err = client.run(&["ls"], None).await.unwrap_err();
+    assert!(matches!(err, Error::Unavailable(_)));
+}
+
 #[tokio::test]
 async fn user_agent_header() {
     let server = MockServer::start().await;
@@ -292,6 +388,101 @@ async fn with_sandbox_cleanup() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn sandbox_builder_create_request_body() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/sandboxes"))
+        .and(body_json(serde_json::json!({
+            "name": "built",
+            "image": "python:3.12",
+            "vcpus": 2,
+            "memory_mb": 1024,
+            "profile": "restrictive"
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"name": "built", "status": "running", "backend": "docker"}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // Unset options are left out of the body
+    Mock::given(method("POST"))
+        .and(path("/sandboxes"))
+        .and(body_json(serde_json::json!({"name": "plain"})))
+        .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"name": "plain", "status": "running", "backend": "docker"}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let sandbox = client
+        .sandbox("built")
+        .image("python:3.12")
+        .vcpus(2)
+        .memory_mb(1024)
+        .profile(SecurityProfile::Restrictive)
+        .create()
+        .await
+        .unwrap();
+    assert_eq!(sandbox.name(), "built");
+
+    let sandbox = client.sandbox("plain").create().await.unwrap();
+    assert_eq!(sandbox.name(), "plain");
+}
+
+#[tokio::test]
+async fn sandbox_handle_lifecycle() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/sandboxes"))
+        .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"name": "life", "status": "running", "backend": "docker"}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/sandboxes/life/files/tmp/a.txt"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": "Wrote /tmp/a.txt"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/sandboxes/life/exec"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"output": "hi", "exit_code": 0}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/sandboxes/life"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": "Sandbox removed"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let sandbox = client.sandbox("life").create().await.unwrap();
+    sandbox.write_file("tmp/a.txt", "hi", None).await.unwrap();
+    let output = sandbox.exec(&["cat", "/tmp/a.txt"]).await.unwrap();
+    assert_eq!(output.output, "hi");
+    sandbox.remove().await.unwrap();
+}
+
 #[tokio::test]
 async fn api_failure_response() {
     let server = MockServer::start().await;
@@ -309,3 +500,242 @@ async fn api_failure_response() {
     assert!(matches!(err, Error::Server(_)));
     assert!(err.to_string().contains("Service degraded"));
 }
+
+fn retrying_client(server: &MockServer, retries: u32) -> AgentKernel {
+    AgentKernel::builder()
+        .base_url(server.uri())
+        .retries(retries)
+        .backoff(std::time::Duration::from_millis(1))
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn retries_transient_errors_then_succeeds() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sandboxes/flaky"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/sandboxes/flaky"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"name": "flaky", "status": "running", "backend": "docker"}
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = retrying_client(&server, 3);
+    let info = client.get_sandbox("flaky").await.unwrap();
+    assert_eq!(info.name, "flaky");
+}
+
+#[tokio::test]
+async fn retry_honors_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"success": true, "data": "ok"})),
+        )
+        .mount(&server)
+        .await;
+
+    // A huge backoff would hang the test if Retry-After were ignored
+    let client = AgentKernel::builder()
+        .base_url(server.uri())
+        .retries(1)
+        .backoff(std::time::Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    assert_eq!(client.health().await.unwrap(), "ok");
+}
+
+#[tokio::test]
+async fn no_retry_on_client_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sandboxes/bad"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .set_body_json(serde_json::json!({"success": false, "error": "Invalid name"})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = retrying_client(&server, 3);
+    let err = client.get_sandbox("bad").await.unwrap_err();
+    assert!(matches!(err, Error::Validation(_)));
+}
+
+#[tokio::test]
+async fn run_not_retried_unless_opted_in() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/run"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = retrying_client(&server, 3);
+    assert!(client.run(&["echo", "hi"], None).await.is_err());
+}
+
+#[tokio::test]
+async fn run_retried_when_opted_in() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/run"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/run"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"output": "hi"}
+        })))
+        .mount(&server)
+        .await;
+
+    let client = AgentKernel::builder()
+        .base_url(server.uri())
+        .retries(2)
+        .backoff(std::time::Duration::from_millis(1))
+        .retry_run(true)
+        .build()
+        .unwrap();
+    let result = client.run(&["echo", "hi"], None).await.unwrap();
+    assert_eq!(result.output, "hi");
+}
+
+#[tokio::test]
+async fn run_stream_events() {
+    use futures::StreamExt;
+
+    let server = MockServer::start().await;
+    let body = "event: started\ndata: {\"sandbox\":\"run-1\"}\n\n\
+                event: output\ndata: {\"data\":\"hi\\n\",\"stream\":\"stdout\"}\n\n\
+                event: done\ndata: {\"exit_code\":0,\"success\":true}\n\n";
+    Mock::given(method("POST"))
+        .and(path("/run/stream"))
+        .and(body_partial_json(
+            serde_json::json!({"command": ["echo", "hi"]}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let stream = client.run_stream(&["echo", "hi"], None).await.unwrap();
+    let events: Vec<_> = stream.map(|e| e.unwrap()).collect().await;
+    let types: Vec<_> = events.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(types, ["started", "output", "done"]);
+    assert_eq!(events[1].data["data"], "hi\n");
+    assert_eq!(events[2].data["exit_code"], 0);
+}
+
+#[tokio::test]
+async fn dropping_run_aborts_request() {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    // A server that never answers and reports when the client hangs up
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (disconnected_tx, disconnected_rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0u8; 1024];
+        loop {
+            match conn.read(&mut buf) {
+                Ok(0) => {
+                    let _ = disconnected_tx.send(());
+                    return;
+                }
+                Ok(_) => continue,
+                Err(_) => return,
+            }
+        }
+    });
+
+    let client = AgentKernel::builder()
+        .base_url(format!("http://{addr}"))
+        .build()
+        .unwrap();
+    let run = client.run(&["sleep", "60"], None);
+    assert!(tokio::time::timeout(Duration::from_millis(200), run)
+        .await
+        .is_err());
+
+    tokio::task::spawn_blocking(move || {
+        disconnected_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("server should see the client disconnect")
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn submit_and_cancel_job() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/jobs"))
+        .and(body_partial_json(
+            serde_json::json!({"command": ["sleep", "60"]}),
+        ))
+        .respond_with(ResponseTemplate::new(202).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {
+                "id": "5f1c",
+                "status": "running",
+                "command": ["sleep", "60"],
+                "created_at": "2026-01-30T00:00:00Z"
+            }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/jobs/5f1c"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {
+                "id": "5f1c",
+                "status": "cancelled",
+                "command": ["sleep", "60"],
+                "created_at": "2026-01-30T00:00:00Z",
+                "finished_at": "2026-01-30T00:00:05Z"
+            }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = test_client(&server).await;
+    let job = client.submit_job(&["sleep", "60"], None).await.unwrap();
+    assert_eq!(job.id(), "5f1c");
+    let info = job.cancel().await.unwrap();
+    assert_eq!(info.status, JobStatus::Cancelled);
+    assert!(info.status.is_finished());
+}
@@ -0,0 +1,107 @@
+#![cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+
+use agentkernel_sdk::AgentKernel;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Subscriber that keeps every span name and field value it sees.
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<String>>>,
+    fields: Arc<Mutex<Vec<(String, String)>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    fn field(&self, name: &str) -> Option<String> {
+        let fields = self.fields.lock().unwrap();
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.clone())
+    }
+}
+
+impl Visit for Recorder {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.fields
+            .lock()
+            .unwrap()
+            .push((field.name().to_string(), format!("{value:?}")));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        self.spans
+            .lock()
+            .unwrap()
+            .push(attrs.metadata().name().to_string());
+        attrs.record(&mut self.clone());
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _: &Id, values: &Record<'_>) {
+        values.record(&mut self.clone());
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        event.record(&mut self.clone());
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[tokio::test]
+async fn run_emits_request_span_without_api_key() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/run"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "data": {"output": "hi"}
+        })))
+        .mount(&server)
+        .await;
+
+    let client = AgentKernel::builder()
+        .base_url(server.uri())
+        .api_key("sk-very-secret")
+        .build()
+        .unwrap();
+
+    let recorder = Recorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+    client.run(&["echo", "hi"], None).await.unwrap();
+
+    assert!(recorder
+        .spans
+        .lock()
+        .unwrap()
+        .contains(&"agentkernel.request".to_string()));
+    assert_eq!(recorder.field("method").as_deref(), Some("POST"));
+    assert_eq!(recorder.field("url"), Some(format!("{}/run", server.uri())));
+    assert_eq!(recorder.field("status").as_deref(), Some("200"));
+    assert!(recorder.field("latency_ms").is_some());
+
+    let fields = recorder.fields.lock().unwrap();
+    assert!(fields.iter().all(|(_, v)| !v.contains("sk-very-secret")));
+    assert!(
+        !format!("{:?}", AgentKernel::builder().api_key("sk-very-secret"))
+            .contains("sk-very-secret")
+    );
+}
@@ -0,0 +1,66 @@
+//! Compile checks for the wasm32 build. There's no wasm test runner in CI, so
+//! these are only type-checked:
+//!
+//! ```bash
+//! rustup target add wasm32-unknown-unknown
+//! cargo check --tests --target wasm32-unknown-unknown
+//! ```
+#![cfg(target_arch = "wasm32")]
+
+use std::time::Duration;
+
+use agentkernel_sdk::{AgentKernel, BatchCommand, Result, RunOptions, SecurityProfile};
+
+#[test]
+fn builder_options_build() {
+    let _client: Result<AgentKernel> = AgentKernel::builder()
+        .base_url("http://localhost:18888")
+        .api_key("sk-test")
+        .timeout(Duration::from_secs(5))
+        .retries(2)
+        .backoff(Duration::from_millis(100))
+        .build();
+}
+
+/// Every async method is part of the wasm subset.
+#[allow(dead_code)]
+async fn async_api(client: &AgentKernel) -> Result<()> {
+    client.health().await?;
+    client
+        .run(&["echo", "hi"], Some(RunOptions::default()))
+        .await?;
+    client.list_sandboxes().await?;
+    client
+        .create_sandbox("wasm", None, None, None, Some(SecurityProfile::Restrictive))
+        .await?;
+    client.get_sandbox("wasm").await?;
+    client.exec("wasm", &["true"]).await?;
+    client.exec_checked("wasm", &["true"]).await?;
+    client.write_file("wasm", "tmp/a.txt", "hi", None).await?;
+    client.read_file("wasm", "tmp/a.txt").await?;
+    client.delete_file("wasm", "tmp/a.txt").await?;
+    client.get_sandbox_logs("wasm").await?;
+    client
+        .batch_run(vec![BatchCommand {
+            command: vec!["true".to_string()],
+        }])
+        .await?;
+    client
+        .with_sandbox("scoped", None, |sb| async move {
+            sb.run(&["true"]).await?;
+            sb.info().await?;
+            Ok(())
+        })
+        .await?;
+    let sandbox = client
+        .sandbox("built")
+        .image("alpine:3.20")
+        .vcpus(1)
+        .memory_mb(256)
+        .profile(SecurityProfile::Moderate)
+        .create()
+        .await?;
+    sandbox.exec(&["true"]).await?;
+    sandbox.remove().await?;
+    client.remove_sandbox("wasm").await
+}
@@ -94,7 +94,15 @@ public actor AgentKernel {
 
     /// List all sandboxes.
     public func listSandboxes() async throws -> [SandboxInfo] {
-        try await request(method: "GET", path: "/sandboxes")
+        var sandboxes: [SandboxInfo] = []
+        var next: String? = "/sandboxes"
+        while let path = next {
+            let (page, link): ([SandboxInfo], String?) = try await requestPage(
+                method: "GET", path: path, body: nil as AnyEncodable?)
+            sandboxes.append(contentsOf: page)
+            next = link
+        }
+        return sandboxes
     }
 
     /// Create a new sandbox.
@@ -174,23 +182,29 @@ public actor AgentKernel {
     /// Get audit log entries for a sandbox.
     public func getSandboxLogs(_ name: String) async throws -> [[String: Any]] {
         // Use raw JSON approach since [String: Any] isn't Decodable
-        let url = URL(string: "\(config.baseURL)/sandboxes/\(name)/logs")!
-        var req = URLRequest(url: url)
-        req.httpMethod = "GET"
-        applyHeaders(&req)
-        let (data, response) = try await performRequest(req)
-        guard let httpResponse = response as? HTTPURLResponse else {
-            throw AgentKernelError.network(URLError(.badServerResponse))
-        }
-        if httpResponse.statusCode >= 400 {
-            let bodyText = String(data: data, encoding: .utf8) ?? ""
-            throw errorFromStatus(httpResponse.statusCode, body: bodyText)
-        }
-        let json = try JSONSerialization.jsonObject(with: data) as? [String: Any] ?? [:]
-        guard json["success"] as? Bool == true else {
-            throw AgentKernelError.server(json["error"] as? String ?? "Unknown error")
+        var entries: [[String: Any]] = []
+        var next: String? = "/sandboxes/\(name)/logs"
+        while let path = next {
+            let url = URL(string: "\(config.baseURL)\(path)")!
+            var req = URLRequest(url: url)
+            req.httpMethod = "GET"
+            applyHeaders(&req)
+            let (data, response) = try await performRequest(req)
+            guard let httpResponse = response as? HTTPURLResponse else {
+                throw AgentKernelError.network(URLError(.badServerResponse))
+            }
+            if httpResponse.statusCode >= 400 {
+                let bodyText = String(data: data, encoding: .utf8) ?? ""
+                throw errorFromStatus(httpResponse.statusCode, body: bodyText)
+            }
+            let json = try JSONSerialization.jsonObject(with: data) as? [String: Any] ?? [:]
+            guard json["success"] as? Bool == true else {
+                throw AgentKernelError.server(json["error"] as? String ?? "Unknown error")
+            }
+            entries.append(contentsOf: json["data"] as? [[String: Any]] ?? [])
+            next = json["next"] as? String
         }
-        return json["data"] as? [[String: Any]] ?? []
+        return entries
     }
 
     /// Run multiple commands in parallel.
@@ -213,6 +227,15 @@ public actor AgentKernel {
         path: String,
         body: B?
     ) async throws -> T {
+        try await requestPage(method: method, path: path, body: body).0
+    }
+
+    /// Send a request and return its data with the path of the next page, if any.
+    private func requestPage<T: Decodable, B: Encodable>(
+        method: String,
+        path: String,
+        body: B?
+    ) async throws -> (T, String?) {
         let url = URL(string: "\(config.baseURL)\(path)")!
         var req = URLRequest(url: url)
         req.httpMethod = method
@@ -247,7 +270,7 @@ public actor AgentKernel {
         guard let result = apiResponse.data else {
             throw AgentKernelError.server("Missing data field")
         }
-        return result
+        return (result, apiResponse.next)
     }
 
     private func applyHeaders(_ request: inout URLRequest) {
@@ -78,6 +78,8 @@ struct ApiResponse<T: Decodable>: Decodable {
     let success: Bool
     let data: T?
     let error: String?
+    /// Path of the next page, for paginated lists.
+    let next: String?
 }
 
 /// Run request body.
@@ -142,6 +142,18 @@ final class AgentKernelTests: XCTestCase {
         XCTAssertEqual(sandboxes[0].status, "running")
     }
 
+    func testListSandboxesFollowsNext() async throws {
+        let client = makeClient()
+        MockURLProtocol.requestHandler = { request in
+            if request.url?.query == "limit=1&offset=1" {
+                return jsonResponse(#"{"success":true,"data":[{"name":"sb2","status":"stopped","backend":"docker"}]}"#)
+            }
+            return jsonResponse(#"{"success":true,"data":[{"name":"sb1","status":"running","backend":"docker"}],"next":"/sandboxes?limit=1&offset=1"}"#)
+        }
+        let sandboxes = try await client.listSandboxes()
+        XCTAssertEqual(sandboxes.map(\.name), ["sb1", "sb2"])
+    }
+
     func testCreateSandbox() async throws {
         let client = makeClient()
         MockURLProtocol.requestHandler = { request in
@@ -5,10 +5,12 @@
 #![allow(dead_code)]
 
 use anyhow::Result;
+use serde::Serialize;
 use std::collections::HashMap;
 
 /// Agent type enum
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AgentType {
     #[default]
     Claude,
@@ -306,7 +308,9 @@ pub fn create_agent_from_str(agent_name: &str) -> Result<Box<dyn Agent>> {
 pub fn check_agent_availability(agent_type: AgentType) -> AgentStatus {
     let agent = create_agent(agent_type, None);
 
-    let installed = agent.is_available();
+    // A version means the agent runs, so the check is only repeated without one
+    let version = agent_version(agent_type.command());
+    let installed = version.is_some() || agent.is_available();
     let api_key_set = agent
         .api_key_env_var()
         .map(|var| std::env::var(var).is_ok())
@@ -316,17 +320,60 @@ pub fn check_agent_availability(agent_type: AgentType) -> AgentStatus {
         agent_type,
         installed,
         api_key_set,
+        api_key_env: agent.api_key_env_var(),
         install_instructions: agent.install_instructions().to_string(),
+        version,
     }
 }
 
+/// Version an agent reports with `--version`, if it runs
+fn agent_version(command: &str) -> Option<String> {
+    let output = std::process::Command::new(command)
+        .arg("--version")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// First non-empty line of `--version` output
+fn parse_version(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+}
+
+/// Warnings about the `[agent] preferred` agent: unknown, not installed, or
+/// missing its API key. Catches misconfiguration before the agent fails later.
+pub fn preferred_agent_warnings(preferred: &str) -> Vec<String> {
+    let Some(agent_type) = AgentType::from_str(preferred) else {
+        return vec![format!(
+            "Unknown agent '{}' in [agent] preferred (expected claude, gemini, codex, or opencode)",
+            preferred
+        )];
+    };
+    list_agents()
+        .iter()
+        .find(|status| status.agent_type == agent_type)
+        .map(AgentStatus::warnings)
+        .unwrap_or_default()
+}
+
 /// Status of an agent's availability
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AgentStatus {
     pub agent_type: AgentType,
     pub installed: bool,
     pub api_key_set: bool,
+    /// Environment variable holding the agent's API key, if it needs one
+    pub api_key_env: Option<&'static str>,
     pub install_instructions: String,
+    /// Version reported by the installed agent
+    pub version: Option<String>,
 }
 
 impl AgentStatus {
@@ -335,6 +382,27 @@ impl AgentStatus {
         self.installed && self.api_key_set
     }
 
+    /// What's missing before the agent can run, with a hint for each
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let name = self.agent_type.name();
+        if !self.installed {
+            warnings.push(format!(
+                "Preferred agent '{}' is not installed. {}",
+                name, self.install_instructions
+            ));
+        }
+        if !self.api_key_set
+            && let Some(var) = self.api_key_env
+        {
+            warnings.push(format!(
+                "{} is not set, so '{}' won't be able to authenticate. Run: export {}=...",
+                var, name, var
+            ));
+        }
+        warnings
+    }
+
     /// Print status
     pub fn print(&self) {
         let status = if self.is_ready() {
@@ -391,6 +459,59 @@ mod tests {
         assert_eq!(config.args, vec!["--flag".to_string()]);
     }
 
+    #[test]
+    fn test_preferred_agent_warnings() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [sandbox]
+            name = "test"
+
+            [agent]
+            preferred = "codex"
+            "#,
+        )
+        .unwrap();
+        let agent_type = AgentType::from_str(&config.agent.preferred).unwrap();
+        let status = AgentStatus {
+            agent_type,
+            installed: false,
+            api_key_set: false,
+            api_key_env: create_agent(agent_type, None).api_key_env_var(),
+            install_instructions: "Install Codex CLI: npm install -g @openai/codex".to_string(),
+            version: None,
+        };
+
+        let warnings = status.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'Codex' is not installed"));
+        assert!(warnings[0].contains("npm install -g @openai/codex"));
+        assert!(warnings[1].contains("export OPENAI_API_KEY="));
+
+        let ready = AgentStatus {
+            installed: true,
+            api_key_set: true,
+            ..status
+        };
+        assert!(ready.warnings().is_empty());
+
+        let warnings = preferred_agent_warnings("copilot");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Unknown agent 'copilot'"));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("1.0.51 (Claude Code)\n"),
+            Some("1.0.51 (Claude Code)".to_string())
+        );
+        assert_eq!(
+            parse_version("\ncodex-cli 0.8.0\nextra\n"),
+            Some("codex-cli 0.8.0".to_string())
+        );
+        assert_eq!(parse_version("  \n"), None);
+    }
+
     #[test]
     fn test_create_agent() {
         let agent = create_agent(AgentType::Claude, None);
@@ -293,6 +293,9 @@ pub fn read_asciicast(path: impl Into<PathBuf>) -> Result<(AsciicastHeader, Vec<
 
 /// Get the default recording directory
 pub fn default_recordings_dir() -> PathBuf {
+    if let Some(dir) = crate::setup::data_dir_override() {
+        return dir.join("recordings");
+    }
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join(".agentkernel")
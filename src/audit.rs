@@ -1,7 +1,12 @@
 //! Audit logging for agentkernel operations.
 //!
 //! Logs all sandbox operations to a JSONL file for security auditing.
-//! Default location: ~/.agentkernel/audit.jsonl
+//! Default location: ~/.agentkernel/audit.jsonl (or `audit.jsonl` in the
+//! `--data-dir`), changed with `[audit] path` or `AGENTKERNEL_AUDIT_PATH`. `[audit] enabled = false` or
+//! `AGENTKERNEL_AUDIT_DISABLED=1` turns file logging off.
+//!
+//! Events logged through [`log_event`] are also broadcast to live
+//! subscribers (see [`subscribe`]), e.g. the HTTP API's `GET /events` feed.
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
@@ -10,6 +15,25 @@ use std::fs::{self, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 
+use crate::config::AuditConfig;
+
+/// Environment variable that turns audit logging off when set to `1` or `true`
+pub const AUDIT_DISABLED_ENV: &str = "AGENTKERNEL_AUDIT_DISABLED";
+
+/// Environment variable overriding the audit log path
+pub const AUDIT_PATH_ENV: &str = "AGENTKERNEL_AUDIT_PATH";
+
+/// Config file `[audit]` is read from, if not ./agentkernel.toml (see
+/// [`set_config_path`])
+static CONFIG_PATH: std::sync::RwLock<Option<PathBuf>> = std::sync::RwLock::new(None);
+
+/// Read `[audit]` from `path` (an explicit `--config`) instead of
+/// ./agentkernel.toml. Call it before the first event is logged: the global
+/// logger reads its settings once.
+pub fn set_config_path(path: PathBuf) {
+    *CONFIG_PATH.write().unwrap_or_else(|e| e.into_inner()) = Some(path);
+}
+
 /// Audit event types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -24,6 +48,12 @@ pub enum AuditEvent {
     SandboxStarted {
         name: String,
         profile: Option<String>,
+        /// How long the backend took to start, in milliseconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        boot_ms: Option<u64>,
+        /// Backend command that started it, secrets redacted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
     },
     /// Sandbox stopped
     SandboxStopped { name: String },
@@ -41,6 +71,14 @@ pub enum AuditEvent {
     FileRead { sandbox: String, path: String },
     /// Session attached
     SessionAttached { sandbox: String },
+    /// Setup script run on a sandbox's first start
+    SetupScriptRun {
+        sandbox: String,
+        exit_code: i32,
+        output: String,
+    },
+    /// Network access turned on or off for a running sandbox
+    NetworkChanged { sandbox: String, enabled: bool },
     /// Policy violation (for future use)
     PolicyViolation {
         sandbox: String,
@@ -49,6 +87,42 @@ pub enum AuditEvent {
     },
 }
 
+impl AuditEvent {
+    /// The event's `type` tag, e.g. `sandbox_created`
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            AuditEvent::SandboxCreated { .. } => "sandbox_created",
+            AuditEvent::SandboxStarted { .. } => "sandbox_started",
+            AuditEvent::SandboxStopped { .. } => "sandbox_stopped",
+            AuditEvent::SandboxRemoved { .. } => "sandbox_removed",
+            AuditEvent::CommandExecuted { .. } => "command_executed",
+            AuditEvent::FileWritten { .. } => "file_written",
+            AuditEvent::FileRead { .. } => "file_read",
+            AuditEvent::SessionAttached { .. } => "session_attached",
+            AuditEvent::SetupScriptRun { .. } => "setup_script_run",
+            AuditEvent::NetworkChanged { .. } => "network_changed",
+            AuditEvent::PolicyViolation { .. } => "policy_violation",
+        }
+    }
+
+    /// The sandbox the event is about
+    pub fn sandbox(&self) -> &str {
+        match self {
+            AuditEvent::SandboxCreated { name, .. }
+            | AuditEvent::SandboxStarted { name, .. }
+            | AuditEvent::SandboxStopped { name }
+            | AuditEvent::SandboxRemoved { name } => name,
+            AuditEvent::CommandExecuted { sandbox, .. }
+            | AuditEvent::FileWritten { sandbox, .. }
+            | AuditEvent::FileRead { sandbox, .. }
+            | AuditEvent::SessionAttached { sandbox }
+            | AuditEvent::SetupScriptRun { sandbox, .. }
+            | AuditEvent::NetworkChanged { sandbox, .. }
+            | AuditEvent::PolicyViolation { sandbox, .. } => sandbox,
+        }
+    }
+}
+
 /// A logged audit entry with metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditEntry {
@@ -75,8 +149,11 @@ impl AuditEntry {
     }
 }
 
-/// Get the default audit log path
+/// Get the default audit log path (in the data directory when it's relocated)
 pub fn default_audit_path() -> PathBuf {
+    if let Some(dir) = crate::setup::data_dir_override() {
+        return dir.join("audit.jsonl");
+    }
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join(".agentkernel")
@@ -90,16 +167,37 @@ pub struct AuditLog {
 }
 
 impl AuditLog {
-    /// Create a new audit logger
+    /// Create a new audit logger from `[audit]` in the config file (see
+    /// [`set_config_path`], else ./agentkernel.toml) and the environment
     pub fn new() -> Self {
-        let enabled = std::env::var("AGENTKERNEL_AUDIT")
-            .map(|v| v != "0" && v.to_lowercase() != "false")
-            .unwrap_or(true); // Enabled by default
+        let path = CONFIG_PATH
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("agentkernel.toml"));
+        let config = AuditConfig::from_file(&path);
+        Self::from_config(&config, |key| std::env::var(key).ok())
+    }
 
-        Self {
-            path: default_audit_path(),
-            enabled,
-        }
+    /// Create a logger from `[audit]` settings; environment variables win
+    fn from_config(config: &AuditConfig, env: impl Fn(&str) -> Option<String>) -> Self {
+        let is_true = |v: &str| v == "1" || v.eq_ignore_ascii_case("true");
+        let is_false = |v: &str| v == "0" || v.eq_ignore_ascii_case("false");
+
+        let enabled = if env(AUDIT_DISABLED_ENV).is_some_and(|v| is_true(&v))
+            || env("AGENTKERNEL_AUDIT").is_some_and(|v| is_false(&v))
+        {
+            false
+        } else {
+            config.enabled.unwrap_or(true) // Enabled by default
+        };
+        let path = env(AUDIT_PATH_ENV)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| config.path.clone())
+            .unwrap_or_else(default_audit_path);
+
+        Self { path, enabled }
     }
 
     /// Create with a custom path
@@ -173,6 +271,8 @@ impl AuditLog {
                 AuditEvent::FileWritten { sandbox: s, .. } => s == sandbox,
                 AuditEvent::FileRead { sandbox: s, .. } => s == sandbox,
                 AuditEvent::SessionAttached { sandbox: s } => s == sandbox,
+                AuditEvent::SetupScriptRun { sandbox: s, .. } => s == sandbox,
+                AuditEvent::NetworkChanged { sandbox: s, .. } => s == sandbox,
                 AuditEvent::PolicyViolation { sandbox: s, .. } => s == sandbox,
             })
             .collect())
@@ -189,6 +289,11 @@ impl AuditLog {
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
+
+    /// Whether events are written to the log file
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
 }
 
 impl Default for AuditLog {
@@ -204,8 +309,25 @@ pub fn audit() -> &'static AuditLog {
     AUDIT.get_or_init(AuditLog::new)
 }
 
+/// Events buffered per live subscriber; a subscriber that falls further
+/// behind skips the oldest events (and is told how many it missed)
+pub const LIVE_BUFFER: usize = 256;
+
+fn live() -> &'static tokio::sync::broadcast::Sender<AuditEntry> {
+    use std::sync::OnceLock;
+    static LIVE: OnceLock<tokio::sync::broadcast::Sender<AuditEntry>> = OnceLock::new();
+    LIVE.get_or_init(|| tokio::sync::broadcast::channel(LIVE_BUFFER).0)
+}
+
+/// Receive events logged from now on, whether or not file logging is enabled
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<AuditEntry> {
+    live().subscribe()
+}
+
 /// Convenience function to log an event
 pub fn log_event(event: AuditEvent) {
+    // Sending only fails when nobody is subscribed
+    let _ = live().send(AuditEntry::new(event.clone()));
     if let Err(e) = audit().log(event) {
         eprintln!("Warning: failed to write audit log: {}", e);
     }
@@ -279,4 +401,76 @@ mod tests {
         let filtered = log.read_by_sandbox("test1").unwrap();
         assert_eq!(filtered.len(), 1);
     }
+
+    #[test]
+    fn test_disabled_log_writes_nothing() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = AuditConfig {
+            enabled: Some(false),
+            path: Some(path.clone()),
+        };
+
+        let log = AuditLog::from_config(&config, |_| None);
+        assert!(!log.is_enabled());
+        log.log(AuditEvent::SandboxStopped {
+            name: "quiet".to_string(),
+        })
+        .unwrap();
+        assert!(!path.exists());
+        assert!(log.read_all().unwrap().is_empty());
+
+        // The environment can turn it off too
+        let log = AuditLog::from_config(&AuditConfig::default(), |key| {
+            (key == AUDIT_DISABLED_ENV).then(|| "1".to_string())
+        });
+        assert!(!log.is_enabled());
+        assert_eq!(log.path(), &default_audit_path());
+    }
+
+    #[test]
+    fn test_custom_path_receives_events() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("from-config.jsonl");
+        let env_path = dir.path().join("from-env.jsonl");
+        let config = AuditConfig {
+            enabled: None,
+            path: Some(config_path.clone()),
+        };
+
+        let log = AuditLog::from_config(&config, |_| None);
+        assert!(log.is_enabled());
+        log.log(AuditEvent::SandboxStopped {
+            name: "custom".to_string(),
+        })
+        .unwrap();
+        let entries = AuditLog::with_path(config_path).read_all().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].event.sandbox(), "custom");
+
+        // AGENTKERNEL_AUDIT_PATH wins over the config
+        let env_value = env_path.to_string_lossy().to_string();
+        let log = AuditLog::from_config(&config, |key| {
+            (key == AUDIT_PATH_ENV).then(|| env_value.clone())
+        });
+        assert_eq!(log.path(), &env_path);
+    }
+
+    #[test]
+    fn test_subscribers_receive_logged_events() {
+        let mut first = subscribe();
+        let mut second = subscribe();
+        log_event(AuditEvent::SandboxStopped {
+            name: "audit-live-test".to_string(),
+        });
+
+        for rx in [&mut first, &mut second] {
+            let entry = std::iter::from_fn(|| rx.try_recv().ok())
+                .find(|e| e.event.sandbox() == "audit-live-test")
+                .unwrap();
+            assert_eq!(entry.event.event_type(), "sandbox_stopped");
+            let json = serde_json::to_value(&entry).unwrap();
+            assert_eq!(json["type"], entry.event.event_type());
+        }
+    }
 }
@@ -5,7 +5,10 @@ use async_trait::async_trait;
 use std::process::Command;
 use std::sync::atomic::{AtomicBool, Ordering};
 
-use super::{BackendType, ExecResult, Sandbox, SandboxConfig};
+use super::{
+    BackendType, ExecBytes, ExecOptions, ExecResult, Sandbox, SandboxConfig, WriteOptions,
+    append_command, append_staging_path,
+};
 
 /// Cached flag indicating if system is already verified running
 static SYSTEM_VERIFIED: AtomicBool = AtomicBool::new(false);
@@ -71,19 +74,103 @@ pub fn apple_containers_available() -> bool {
         .unwrap_or(false)
 }
 
+/// Oldest macOS release with the `container` CLI
+const MIN_MACOS_MAJOR: u32 = 26;
+
+/// macOS product version, e.g. "26.0.1"
+fn macos_version() -> Option<String> {
+    let output = Command::new("sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    Some(version.trim().to_string())
+}
+
+/// Major version number from a macOS product version
+fn macos_major(version: &str) -> Option<u32> {
+    version.split('.').next()?.parse().ok()
+}
+
 /// Check macOS version (needs 26+)
 pub fn macos_version_supported() -> bool {
-    let output = Command::new("sw_vers").arg("-productVersion").output().ok();
+    macos_version()
+        .and_then(|v| macos_major(&v))
+        .is_some_and(|major| major >= MIN_MACOS_MAJOR)
+}
+
+/// Fail with a clear message when this Mac can't run Apple containers
+fn ensure_supported() -> Result<()> {
+    let version = macos_version();
+    match version.as_deref().and_then(macos_major) {
+        Some(major) if major >= MIN_MACOS_MAJOR => {}
+        Some(_) => bail!(
+            "Apple containers require macOS {} or later (this Mac runs {}). \
+             Use the docker backend instead.",
+            MIN_MACOS_MAJOR,
+            version.unwrap_or_default()
+        ),
+        None => bail!(
+            "Apple containers are only available on macOS {}+",
+            MIN_MACOS_MAJOR
+        ),
+    }
+    if !apple_containers_available() {
+        bail!(
+            "The `container` CLI was not found. Install it from https://github.com/apple/container"
+        );
+    }
+    Ok(())
+}
+
+/// `container exec` arguments for a command with per-command options
+fn exec_args(container_id: &str, cmd: &[&str], opts: &ExecOptions) -> Vec<String> {
+    let mut args = vec!["exec".to_string()];
+    for e in &opts.env {
+        args.push("-e".to_string());
+        args.push(e.clone());
+    }
+    if let Some(ref workdir) = opts.workdir {
+        args.push("-w".to_string());
+        args.push(workdir.clone());
+    }
+    if let Some(ref user) = opts.user {
+        args.push("-u".to_string());
+        args.push(user.clone());
+    }
+    args.push(container_id.to_string());
+    args.extend(opts.combined_command(cmd).iter().map(|s| s.to_string()));
+    args
+}
 
-    if let Some(output) = output
-        && let Ok(version) = String::from_utf8(output.stdout)
-        && let Some(major) = version.trim().split('.').next()
-        && let Ok(major_num) = major.parse::<u32>()
-    {
-        return major_num >= 26;
+/// `container exec -it` arguments for an interactive shell
+fn attach_args(container_id: &str, shell: Option<&str>, env: &[String]) -> Vec<String> {
+    let mut args = vec!["exec".to_string(), "-i".to_string(), "-t".to_string()];
+    for e in env {
+        args.push("-e".to_string());
+        args.push(e.clone());
     }
+    args.push(container_id.to_string());
+    args.push(shell.unwrap_or("/bin/sh").to_string());
+    args
+}
+
+/// `container cp` arguments copying a host file into the container
+fn copy_in_args(local: &str, container_id: &str, path: &str) -> Vec<String> {
+    vec![
+        "cp".to_string(),
+        local.to_string(),
+        format!("{}:{}", container_id, path),
+    ]
+}
 
-    false
+/// `container cp` arguments copying a container file to the host
+fn copy_out_args(container_id: &str, path: &str, local: &str) -> Vec<String> {
+    vec![
+        "cp".to_string(),
+        format!("{}:{}", container_id, path),
+        local.to_string(),
+    ]
 }
 
 /// Apple Containers sandbox
@@ -109,9 +196,19 @@ impl AppleSandbox {
     }
 }
 
+/// Force-remove a sandbox's container without its running handle
+pub fn remove_container(name: &str) {
+    let _ = Command::new("container")
+        .args(["delete", "-f", &format!("agentkernel-{}", name)])
+        .output();
+}
+
 #[async_trait]
 impl Sandbox for AppleSandbox {
     async fn start(&mut self, config: &SandboxConfig) -> Result<()> {
+        ensure_supported()?;
+        config.check_gpus_supported(BackendType::Apple)?;
+
         // Ensure system is running
         start_apple_system()?;
 
@@ -154,6 +251,8 @@ impl Sandbox for AppleSandbox {
             args.push(format!("{}:/home/user:ro", home.to_string_lossy()));
         }
 
+        args.extend(crate::permissions::mount_args(&config.mounts));
+
         // Add environment variables
         for (key, value) in &config.env {
             args.push("-e".to_string());
@@ -187,35 +286,32 @@ impl Sandbox for AppleSandbox {
     }
 
     async fn exec_with_env(&mut self, cmd: &[&str], env: &[String]) -> Result<ExecResult> {
+        self.exec_with_options(cmd, &ExecOptions::with_env(env))
+            .await
+    }
+
+    async fn exec_with_options(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecResult> {
+        Ok(self.exec_bytes(cmd, opts).await?.into_lossy())
+    }
+
+    async fn exec_bytes(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecBytes> {
         let container_id = self
             .container_id
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Container not started"))?;
 
-        let mut args = vec!["exec".to_string()];
-
-        // Add environment variables
-        for e in env {
-            args.push("-e".to_string());
-            args.push(e.clone());
-        }
-
-        args.push(container_id.clone());
-        args.extend(cmd.iter().map(|s| s.to_string()));
-
+        let args = exec_args(container_id, cmd, opts);
         let output = Command::new("container")
             .args(&args)
             .output()
             .context("Failed to run command in Apple container")?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-
-        Ok(ExecResult {
-            exit_code,
-            stdout,
-            stderr,
+        Ok(ExecBytes {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+            rusage: None,
+            truncated: false,
         })
     }
 
@@ -261,7 +357,12 @@ impl Sandbox for AppleSandbox {
         }
     }
 
-    async fn write_file_unchecked(&mut self, path: &str, content: &[u8]) -> Result<()> {
+    async fn write_file_unchecked(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> Result<()> {
         let container_id = self
             .container_id
             .as_ref()
@@ -271,6 +372,12 @@ impl Sandbox for AppleSandbox {
         let temp_dir = std::env::temp_dir();
         let temp_file = temp_dir.join(format!("agentkernel-upload-{}", uuid::Uuid::new_v4()));
         std::fs::write(&temp_file, content).context("Failed to write temp file")?;
+        #[cfg(unix)]
+        if let Some(mode) = opts.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&temp_file, std::fs::Permissions::from_mode(mode))
+                .context("Failed to set file mode")?;
+        }
 
         // Ensure parent directory exists in container
         let parent = std::path::Path::new(path)
@@ -282,10 +389,20 @@ impl Sandbox for AppleSandbox {
             .args(["exec", container_id, "mkdir", "-p", &parent])
             .output();
 
+        // Appends are copied next to the file, then added to it in the container
+        let target = if opts.append {
+            append_staging_path(path)
+        } else {
+            path.to_string()
+        };
+
         // Copy file into container
-        let dest = format!("{}:{}", container_id, path);
         let output = Command::new("container")
-            .args(["cp", temp_file.to_str().unwrap(), &dest])
+            .args(copy_in_args(
+                &temp_file.to_string_lossy(),
+                container_id,
+                &target,
+            ))
             .output()
             .context("Failed to copy file to container")?;
 
@@ -296,6 +413,18 @@ impl Sandbox for AppleSandbox {
             bail!("container cp failed: {}", stderr);
         }
 
+        if opts.append {
+            let output = Command::new("container")
+                .args(["exec", container_id])
+                .args(append_command(&target, path))
+                .output()
+                .context("Failed to append to file in container")?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!("Failed to append to {}: {}", path, stderr.trim());
+            }
+        }
+
         Ok(())
     }
 
@@ -308,9 +437,12 @@ impl Sandbox for AppleSandbox {
         let temp_dir = std::env::temp_dir();
         let temp_file = temp_dir.join(format!("agentkernel-download-{}", uuid::Uuid::new_v4()));
 
-        let src = format!("{}:{}", container_id, path);
         let output = Command::new("container")
-            .args(["cp", &src, temp_file.to_str().unwrap()])
+            .args(copy_out_args(
+                container_id,
+                path,
+                &temp_file.to_string_lossy(),
+            ))
             .output()
             .context("Failed to copy file from container")?;
 
@@ -368,6 +500,38 @@ impl Sandbox for AppleSandbox {
 
         Ok(())
     }
+
+    async fn attach(&mut self, shell: Option<&str>) -> Result<i32> {
+        self.attach_with_env(shell, &[]).await
+    }
+
+    async fn attach_with_env(&mut self, shell: Option<&str>, env: &[String]) -> Result<i32> {
+        let container_id = self
+            .container_id
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Container not started"))?;
+        if !self.is_running() {
+            bail!("Container is not running");
+        }
+
+        let args = attach_args(&container_id, shell, env);
+
+        // Record with `script` when the attach command was given --record
+        let status = if let Ok(cast_path) = std::env::var("AGENTKERNEL_RECORD") {
+            Command::new("script")
+                .args(["-q", &cast_path, "container"])
+                .args(&args)
+                .status()
+                .context("Failed to record session with script")?
+        } else {
+            Command::new("container")
+                .args(&args)
+                .status()
+                .context("Failed to attach to Apple container")?
+        };
+
+        Ok(status.code().unwrap_or(-1))
+    }
 }
 
 impl Drop for AppleSandbox {
@@ -379,3 +543,94 @@ impl Drop for AppleSandbox {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macos_major() {
+        assert_eq!(macos_major("26.0.1"), Some(26));
+        assert_eq!(macos_major("15.5"), Some(15));
+        assert_eq!(macos_major(""), None);
+    }
+
+    #[test]
+    fn test_exec_args() {
+        let args = exec_args("agentkernel-box", &["ls"], &ExecOptions::default());
+        assert_eq!(args, vec!["exec", "agentkernel-box", "ls"]);
+
+        let opts = ExecOptions::with_env(&["A=1".to_string()])
+            .workdir(Some("/app"))
+            .user(Some("1000:1000"));
+        let args = exec_args("agentkernel-box", &["ls", "-la"], &opts);
+        assert_eq!(
+            args,
+            vec![
+                "exec",
+                "-e",
+                "A=1",
+                "-w",
+                "/app",
+                "-u",
+                "1000:1000",
+                "agentkernel-box",
+                "ls",
+                "-la"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attach_args() {
+        let args = attach_args("agentkernel-box", None, &[]);
+        assert_eq!(args, vec!["exec", "-i", "-t", "agentkernel-box", "/bin/sh"]);
+
+        let args = attach_args(
+            "agentkernel-box",
+            Some("/bin/bash"),
+            &["TERM=xterm".to_string()],
+        );
+        assert_eq!(
+            args,
+            vec![
+                "exec",
+                "-i",
+                "-t",
+                "-e",
+                "TERM=xterm",
+                "agentkernel-box",
+                "/bin/bash"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_args() {
+        assert_eq!(
+            copy_in_args("/tmp/upload", "agentkernel-box", "/app/main.py"),
+            vec!["cp", "/tmp/upload", "agentkernel-box:/app/main.py"]
+        );
+        assert_eq!(
+            copy_out_args("agentkernel-box", "/app/out.txt", "/tmp/download"),
+            vec!["cp", "agentkernel-box:/app/out.txt", "/tmp/download"]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_ensure_supported_matches_version_check() {
+        // On older macOS (or without the CLI) start fails up front with a clear error
+        match ensure_supported() {
+            Ok(()) => assert!(macos_version_supported() && apple_containers_available()),
+            Err(e) => {
+                let msg = e.to_string();
+                assert!(
+                    msg.contains("macOS") || msg.contains("container"),
+                    "unexpected error: {}",
+                    msg
+                );
+            }
+        }
+    }
+}
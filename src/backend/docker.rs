@@ -2,9 +2,17 @@
 
 use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
-use std::process::Command;
-
-use super::{BackendType, ExecResult, Sandbox, SandboxConfig};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+
+use super::{
+    BackendType, DEFAULT_HOSTNAME, DetachedPaths, ExecBytes, ExecOptions, ExecResult, ExitReason,
+    LogLine, LogStream, Sandbox, SandboxConfig, SandboxGone, TIMEOUT_EXIT_CODE, WriteOptions,
+    append_command, append_staging_path, detached_log_command, display_command, read_capped,
+    read_range_command, timeout_message,
+};
+use crate::docker_backend::ephemeral_label_args;
+use crate::permissions::{NetworkMode, dns_args, gpu_arg};
 
 /// Container runtime to use
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,15 +59,442 @@ pub fn podman_available() -> bool {
 
 /// Detect the best available container runtime
 pub fn detect_container_runtime() -> Option<ContainerRuntime> {
-    if podman_available() {
+    if super::backend_available(BackendType::Podman) {
         Some(ContainerRuntime::Podman)
-    } else if docker_available() {
+    } else if super::backend_available(BackendType::Docker) {
         Some(ContainerRuntime::Docker)
     } else {
         None
     }
 }
 
+/// Build the `--read-only` and writable tmpfs arguments for a config
+///
+/// `scratch_mb` sizes the workspace tmpfs, adding one even when the root
+/// is writable.
+fn read_only_args(config: &SandboxConfig) -> Vec<String> {
+    let mut args = Vec::new();
+    if config.read_only {
+        args.push("--read-only".to_string());
+    }
+    args.extend(crate::permissions::workspace_tmpfs_arg(
+        config.read_only,
+        config.writable_path.as_deref(),
+        config.scratch_mb,
+        config.mount_cwd && config.work_dir.is_some(),
+    ));
+    args
+}
+
+/// `docker run` arguments that start a sandbox's long-running container
+fn start_args(
+    runtime: ContainerRuntime,
+    container_name: &str,
+    config: &SandboxConfig,
+) -> Vec<String> {
+    // Note: We use --rm for ephemeral containers but persistent sandboxes
+    // will have their containers survive because Drop cleanup is skipped
+    let mut args = vec![
+        "run".to_string(),
+        "-d".to_string(),
+        "--name".to_string(),
+        container_name.to_string(),
+        "--hostname".to_string(),
+        config
+            .hostname
+            .clone()
+            .unwrap_or_else(|| DEFAULT_HOSTNAME.to_string()),
+    ];
+
+    // Add resource limits
+    args.push(format!("--cpus={}", config.vcpus));
+    args.push(format!("--memory={}m", config.memory_mb));
+    args.extend(gpu_arg(config.gpus.as_ref()));
+
+    // Network configuration
+    args.extend(network_args(config));
+    args.extend(dns_args(&config.dns, &config.extra_hosts));
+    args.extend(publish_args(config));
+    args.extend(label_args(config));
+    args.extend(user_args(config));
+    args.extend(init_args(runtime, config));
+
+    // Mount working directory if requested
+    if config.mount_cwd
+        && let Some(ref work_dir) = config.work_dir
+    {
+        args.push("-v".to_string());
+        args.push(format!("{}:/workspace", work_dir));
+        args.push("-w".to_string());
+        args.push("/workspace".to_string());
+    }
+
+    // Mount home directory if requested
+    if config.mount_home
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        args.push("-v".to_string());
+        args.push(format!("{}:/home/user:ro", home.to_string_lossy()));
+    }
+    args.extend(mount_args(config));
+
+    // Read-only root filesystem (with optional writable workspace)
+    args.extend(read_only_args(config));
+    args.extend(tmpfs_args(config));
+    args.extend(config.ulimits.docker_args());
+
+    // Add environment variables
+    for (key, value) in &config.env {
+        args.push("-e".to_string());
+        args.push(format!("{}={}", key, value));
+    }
+
+    // Label ephemeral sandboxes so leftovers can be reaped
+    if config.ephemeral {
+        args.extend(ephemeral_label_args());
+    }
+    args.extend(restart_args(config));
+
+    // Add entrypoint override to keep container running
+    args.extend([
+        "--entrypoint".to_string(),
+        "sh".to_string(),
+        config.image.clone(),
+        "-c".to_string(),
+        "while true; do sleep 3600; done".to_string(),
+    ]);
+
+    args
+}
+
+/// Build the `--tmpfs` arguments that bound /tmp and /run
+fn tmpfs_args(config: &SandboxConfig) -> Vec<String> {
+    crate::permissions::tmpfs_args(config.read_only, config.tmp_size_mb, config.run_size_mb)
+}
+
+/// Build the `--init` argument, which runs the runtime's init (tini for
+/// Docker) as PID 1 so orphaned background processes are reaped instead of
+/// piling up as zombies. On by default for Docker sandboxes that outlive a
+/// command. Podman's catatonit is often not installed, and `--init` fails
+/// without it, so there it's only used when asked for.
+fn init_args(runtime: ContainerRuntime, config: &SandboxConfig) -> Vec<String> {
+    let default = !config.ephemeral && runtime == ContainerRuntime::Docker;
+    if config.init.unwrap_or(default) {
+        vec!["--init".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Parse a container's exit state from `docker inspect` output.
+///
+/// Returns `None` while the container is still running or if the output
+/// can't be parsed.
+pub fn parse_inspect_state(json: &str) -> Option<ExitReason> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    // `docker inspect` prints an array, one entry per container
+    let container = value.as_array().and_then(|a| a.first()).unwrap_or(&value);
+    let state = container.get("State")?;
+
+    if state
+        .get("Running")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+    {
+        return None;
+    }
+
+    let error = state
+        .get("Error")
+        .and_then(|v| v.as_str())
+        .filter(|e| !e.is_empty())
+        .map(String::from);
+    // Docker reports the zero time for containers that never finished
+    let finished_at = state
+        .get("FinishedAt")
+        .and_then(|v| v.as_str())
+        .filter(|t| !t.starts_with("0001-"))
+        .map(String::from);
+
+    Some(ExitReason {
+        exit_code: state
+            .get("ExitCode")
+            .and_then(|v| v.as_i64())
+            .map(|c| c as i32),
+        oom_killed: state
+            .get("OOMKilled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        error,
+        finished_at,
+    })
+}
+
+/// Parse a container's disk usage in bytes from `docker inspect --size` output.
+///
+/// Only `SizeRw`, the writable layer: the image under it (in `SizeRootFs`)
+/// is shared with every container started from it.
+pub fn parse_inspect_size(json: &str) -> Result<u64> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).context("Failed to parse container inspect output")?;
+    let container = value.as_array().and_then(|a| a.first()).unwrap_or(&value);
+    container
+        .get("SizeRw")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Container inspect output has no size"))
+}
+
+/// Parse a container's IP address from `docker inspect` output.
+///
+/// Uses the default bridge address, or the first address on a user-defined
+/// network. `None` for containers with no address of their own (`none` or
+/// `host` networking) or output that can't be parsed.
+pub fn parse_inspect_ip(json: &str) -> Option<std::net::IpAddr> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    let container = value.as_array().and_then(|a| a.first()).unwrap_or(&value);
+    let settings = container.get("NetworkSettings")?;
+    let networks = settings
+        .get("Networks")
+        .and_then(|n| n.as_object())
+        .into_iter()
+        .flat_map(|n| n.values());
+    std::iter::once(settings)
+        .chain(networks)
+        .filter_map(|n| n.get("IPAddress").and_then(|v| v.as_str()))
+        .find_map(|ip| ip.parse().ok())
+}
+
+/// Parse `docker logs --timestamps` output, merging the two streams by time.
+///
+/// Each line starts with an RFC 3339 timestamp; lines without one keep their place.
+pub fn parse_docker_logs(stdout: &str, stderr: &str) -> Vec<LogLine> {
+    let parse = |output: &str, stream: LogStream| -> Vec<LogLine> {
+        output
+            .lines()
+            .map(|raw| match raw.split_once(' ') {
+                Some((ts, line)) if chrono::DateTime::parse_from_rfc3339(ts).is_ok() => LogLine {
+                    timestamp: Some(ts.to_string()),
+                    stream,
+                    line: line.to_string(),
+                },
+                _ => LogLine {
+                    timestamp: None,
+                    stream,
+                    line: raw.to_string(),
+                },
+            })
+            .collect()
+    };
+    let mut lines = parse(stdout, LogStream::Stdout);
+    lines.extend(parse(stderr, LogStream::Stderr));
+    // Stable, so each stream keeps its own order
+    lines.sort_by(|a, b| match (&a.timestamp, &b.timestamp) {
+        (Some(a), Some(b)) => a.cmp(b),
+        _ => std::cmp::Ordering::Equal,
+    });
+    lines
+}
+
+/// Build the `--network` argument for a config, plus a label recording the
+/// configured mode so `network on` can rejoin it later
+fn network_args(config: &SandboxConfig) -> Vec<String> {
+    let mut args: Vec<String> =
+        crate::permissions::network_arg(config.network, config.network_mode.as_ref())
+            .into_iter()
+            .collect();
+    if let Some(ref mode) = config.network_mode {
+        args.push(format!("--label={}={}", NETWORK_LABEL, mode));
+    }
+    args
+}
+
+/// Label recording the network a container was configured to join
+const NETWORK_LABEL: &str = "agentkernel.network";
+
+/// Build a read-only `-v` per host file in `[[mounts]]`
+fn mount_args(config: &SandboxConfig) -> Vec<String> {
+    crate::permissions::mount_args(&config.mounts)
+}
+
+/// Build the `--label` arguments for a config, sorted by key
+fn label_args(config: &SandboxConfig) -> Vec<String> {
+    let mut labels: Vec<_> = config.labels.iter().collect();
+    labels.sort();
+    labels
+        .into_iter()
+        .map(|(key, value)| format!("--label={}={}", key, value))
+        .collect()
+}
+
+/// Build the `-p` arguments that publish a config's ports
+fn publish_args(config: &SandboxConfig) -> Vec<String> {
+    config
+        .ports
+        .iter()
+        .flat_map(|port| ["-p".to_string(), port.publish_spec()])
+        .collect()
+}
+
+/// Build the `--user` argument for a config
+fn user_args(config: &SandboxConfig) -> Vec<String> {
+    config
+        .user
+        .iter()
+        .map(|user| format!("--user={}", user))
+        .collect()
+}
+
+/// Build the `--restart` argument for a config. Ephemeral sandboxes are
+/// never restarted.
+fn restart_args(config: &SandboxConfig) -> Vec<String> {
+    if config.ephemeral {
+        return Vec::new();
+    }
+    config.restart.docker_flag().into_iter().collect()
+}
+
+/// Build the `run` arguments for the helper container that installs the
+/// egress firewall in a sandbox's network namespace. None when outbound
+/// traffic isn't restricted.
+fn firewall_args(container_name: &str, config: &SandboxConfig) -> Result<Option<Vec<String>>> {
+    let Some(ref egress) = config.egress else {
+        return Ok(None);
+    };
+    if !config.network {
+        return Ok(None);
+    }
+    // The helper would install the rules in the host's own namespace
+    if config.network_mode == Some(NetworkMode::Host) {
+        bail!("The egress allowlist can't be applied with network_mode = \"host\"");
+    }
+    // A restarted container comes back in a fresh namespace without the rules
+    if !restart_args(config).is_empty() {
+        bail!(
+            "The egress allowlist can't be combined with a restart policy on Docker/Podman \
+             (a restarted container would come back without the firewall)"
+        );
+    }
+    let allowed = egress.resolve()?;
+    let dns: Vec<std::net::IpAddr> = config.dns.iter().filter_map(|s| s.parse().ok()).collect();
+    Ok(Some(vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        format!("--network=container:{}", container_name),
+        "--cap-add=NET_ADMIN".to_string(),
+        "--entrypoint".to_string(),
+        "sh".to_string(),
+        crate::egress::FIREWALL_IMAGE.to_string(),
+        "-c".to_string(),
+        crate::egress::firewall_script(&allowed, &dns),
+    ]))
+}
+
+/// Build the `exec` arguments for a command in a container
+fn exec_args(container_name: &str, cmd: &[&str], opts: &ExecOptions) -> Vec<String> {
+    let mut args = vec!["exec".to_string()];
+
+    // Add environment variables
+    for e in &opts.env {
+        args.push("-e".to_string());
+        args.push(e.clone());
+    }
+
+    if let Some(ref workdir) = opts.workdir {
+        args.push("-w".to_string());
+        args.push(workdir.clone());
+    }
+
+    if let Some(ref user) = opts.user {
+        args.push("-u".to_string());
+        args.push(user.clone());
+    }
+
+    args.push(container_name.to_string());
+    args.extend(opts.combined_command(cmd).iter().map(|s| s.to_string()));
+    args
+}
+
+/// Env var marking the processes of one exec, so they can be found and killed
+const EXEC_ID_ENV: &str = "AGENTKERNEL_EXEC_ID";
+
+/// Arguments that attach a container to its configured network (`enabled`)
+/// or detach it. Without a named network that's the runtime's default one.
+fn network_toggle_args(
+    runtime: ContainerRuntime,
+    container_name: &str,
+    mode: Option<&NetworkMode>,
+    enabled: bool,
+) -> Result<Vec<String>> {
+    let network = match mode {
+        Some(NetworkMode::Named(name)) => name.as_str(),
+        Some(NetworkMode::Host) => {
+            bail!("Sandboxes on the host network can't change network access while running")
+        }
+        _ => match runtime {
+            ContainerRuntime::Docker => "bridge",
+            ContainerRuntime::Podman => "podman",
+        },
+    };
+    Ok(vec![
+        "network".to_string(),
+        if enabled { "connect" } else { "disconnect" }.to_string(),
+        network.to_string(),
+        container_name.to_string(),
+    ])
+}
+
+/// `docker exec` arguments that kill every process carrying an exec's marker.
+///
+/// The marker is inherited, so this also catches anything the command started.
+fn kill_exec_args(container_name: &str, exec_id: &str) -> Vec<String> {
+    let script = format!(
+        "for p in /proc/[0-9]*; do \
+         cat $p/environ 2>/dev/null | tr '\\0' '\\n' | grep -qx '{}={}' && kill -KILL ${{p#/proc/}}; \
+         done; true",
+        EXEC_ID_ENV, exec_id
+    );
+    vec![
+        "exec".to_string(),
+        "-u".to_string(),
+        "0".to_string(),
+        container_name.to_string(),
+        "sh".to_string(),
+        "-c".to_string(),
+        script,
+    ]
+}
+
+/// Whether a failed `docker exec` means the container itself is gone.
+///
+/// Docker reports "Error response from daemon: No such container: <name>";
+/// Podman reports "Error: no container with name or ID ... no such container".
+/// Only the runtime's own first line counts, not output from the command.
+/// `docker cp`'s "No such container:path" is a missing file, not this.
+fn is_missing_container(stderr: &str) -> bool {
+    let first = stderr
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    first.starts_with("error")
+        && first.contains("no such container")
+        && !first.contains("no such container:path")
+}
+
+/// Error for a failed runtime command (`cp`, or `exec` of a file op):
+/// `SandboxGone` if the container itself is gone, otherwise `what` with the
+/// runtime's message
+fn runtime_error(what: &str, stderr: &[u8]) -> anyhow::Error {
+    let message = String::from_utf8_lossy(stderr);
+    if is_missing_container(&message) {
+        return SandboxGone {
+            detail: message.trim().to_string(),
+        }
+        .into();
+    }
+    anyhow::anyhow!("{}: {}", what, message.trim())
+}
+
 /// Docker/Podman container sandbox
 pub struct DockerSandbox {
     name: String,
@@ -68,6 +503,11 @@ pub struct DockerSandbox {
     running: bool,
     /// If true, don't clean up container in Drop (for persistent sandboxes)
     persistent: bool,
+    /// `docker run` command line of the last start, secrets redacted
+    launch_command: Option<String>,
+    /// Paths already resolved by `resolve_path`, dropped whenever a command
+    /// runs or a file op could change what they point to
+    resolved_paths: HashMap<String, String>,
 }
 
 impl DockerSandbox {
@@ -79,6 +519,8 @@ impl DockerSandbox {
             container_id: None,
             running: false,
             persistent: false,
+            launch_command: None,
+            resolved_paths: HashMap::new(),
         }
     }
 
@@ -90,6 +532,8 @@ impl DockerSandbox {
             container_id: None,
             running: false,
             persistent: true,
+            launch_command: None,
+            resolved_paths: HashMap::new(),
         }
     }
 
@@ -112,8 +556,104 @@ impl DockerSandbox {
 }
 
 impl DockerSandbox {
+    /// Run a command, streaming its output into bounded buffers.
+    ///
+    /// With a timeout, the command is killed inside the container if it runs
+    /// too long; killing only the `docker exec` client would leave it running.
+    async fn exec_streamed(&self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecBytes> {
+        let container_name = self.container_name();
+        let exec_id = uuid::Uuid::new_v4().simple().to_string();
+        let mut opts = opts.clone();
+        if opts.timeout.is_some() {
+            opts.env.push(format!("{}={}", EXEC_ID_ENV, exec_id));
+        }
+        let max_output = opts.max_output.unwrap_or(usize::MAX);
+
+        let mut child = tokio::process::Command::new(self.runtime.cmd())
+            .args(exec_args(&container_name, cmd, &opts))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to run command in container")?;
+
+        // Drain both pipes while waiting so a chatty command can't block
+        let stdout_pipe = child.stdout.take().context("stdout is piped")?;
+        let stderr_pipe = child.stderr.take().context("stderr is piped")?;
+        let stdout_reader = tokio::spawn(read_capped(stdout_pipe, max_output));
+        let stderr_reader = tokio::spawn(read_capped(stderr_pipe, max_output));
+
+        // Err(limit) when the command was killed for running too long
+        let status = match opts.timeout {
+            None => Ok(child
+                .wait()
+                .await
+                .context("Failed to run command in container")?),
+            Some(limit) => match tokio::time::timeout(limit, child.wait()).await {
+                Ok(status) => Ok(status.context("Failed to run command in container")?),
+                Err(_) => {
+                    let _ = Command::new(self.runtime.cmd())
+                        .args(kill_exec_args(&container_name, &exec_id))
+                        .output();
+                    let _ = child.kill().await;
+                    Err(limit)
+                }
+            },
+        };
+
+        let (stdout, stdout_truncated) = stdout_reader.await.unwrap_or_default();
+        let (mut stderr, stderr_truncated) = stderr_reader.await.unwrap_or_default();
+        let exit_code = match status {
+            Ok(status) => status.code().unwrap_or(-1),
+            Err(limit) => {
+                stderr.extend_from_slice(timeout_message(limit).as_bytes());
+                TIMEOUT_EXIT_CODE
+            }
+        };
+
+        if exit_code != 0 && stdout.is_empty() {
+            let message = String::from_utf8_lossy(&stderr);
+            if is_missing_container(&message) {
+                return Err(SandboxGone {
+                    detail: message.trim().to_string(),
+                }
+                .into());
+            }
+        }
+
+        Ok(ExecBytes {
+            exit_code,
+            stdout,
+            stderr,
+            rusage: None,
+            truncated: stdout_truncated || stderr_truncated,
+        })
+    }
+
+    /// The network mode the container was started with, from its label
+    fn configured_network(&self) -> Result<Option<NetworkMode>> {
+        let format = format!("{{{{index .Config.Labels \"{}\"}}}}", NETWORK_LABEL);
+        let output = Command::new(self.runtime.cmd())
+            .args(["inspect", "--format", &format, &self.container_name()])
+            .output()
+            .context("Failed to inspect container")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to inspect container: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let label = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Go templates print a missing label as "<no value>"
+        if label.is_empty() || label == "<no value>" {
+            return Ok(None);
+        }
+        label.parse().map(Some)
+    }
+
     /// Write a file to the container using docker cp
-    async fn write_file_impl(&self, path: &str, content: &[u8]) -> Result<()> {
+    async fn write_file_impl(&self, path: &str, content: &[u8], opts: &WriteOptions) -> Result<()> {
         let container_name = self.container_name();
         let cmd = self.runtime.cmd();
 
@@ -121,6 +661,13 @@ impl DockerSandbox {
         let temp_dir = std::env::temp_dir();
         let temp_file = temp_dir.join(format!("agentkernel-upload-{}", uuid::Uuid::new_v4()));
         std::fs::write(&temp_file, content).context("Failed to write temp file")?;
+        // docker cp keeps the file's mode, so the file arrives with it already set
+        #[cfg(unix)]
+        if let Some(mode) = opts.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&temp_file, std::fs::Permissions::from_mode(mode))
+                .context("Failed to set file mode")?;
+        }
 
         // Ensure parent directory exists in container
         let parent = std::path::Path::new(path)
@@ -132,8 +679,15 @@ impl DockerSandbox {
             .args(["exec", &container_name, "mkdir", "-p", &parent])
             .output();
 
+        // Appends are copied next to the file, then added to it in the container
+        let target = if opts.append {
+            append_staging_path(path)
+        } else {
+            path.to_string()
+        };
+
         // Copy file into container
-        let dest = format!("{}:{}", container_name, path);
+        let dest = format!("{}:{}", container_name, target);
         let output = Command::new(cmd)
             .args(["cp", temp_file.to_str().unwrap(), &dest])
             .output()
@@ -143,8 +697,19 @@ impl DockerSandbox {
         let _ = std::fs::remove_file(&temp_file);
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("docker cp failed: {}", stderr);
+            return Err(runtime_error("docker cp failed", &output.stderr));
+        }
+
+        if opts.append {
+            let output = Command::new(cmd)
+                .args(["exec", &container_name])
+                .args(append_command(&target, path))
+                .output()
+                .context("Failed to append to file in container")?;
+            if !output.status.success() {
+                let what = format!("Failed to append to {}", path);
+                return Err(runtime_error(&what, &output.stderr));
+            }
         }
 
         Ok(())
@@ -167,8 +732,7 @@ impl DockerSandbox {
             .context("Failed to copy file from container")?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("docker cp failed: {}", stderr);
+            return Err(runtime_error("docker cp failed", &output.stderr));
         }
 
         // Read and return content
@@ -186,70 +750,25 @@ impl Sandbox for DockerSandbox {
     async fn start(&mut self, config: &SandboxConfig) -> Result<()> {
         let cmd = self.runtime.cmd();
         let container_name = self.container_name();
+        self.resolved_paths.clear();
 
         // Remove any existing container with this name
         let _ = Command::new(cmd)
             .args(["rm", "-f", &container_name])
             .output();
 
-        // Build container arguments
-        // Note: We use --rm for ephemeral containers but persistent sandboxes
-        // will have their containers survive because Drop cleanup is skipped
-        let mut args = vec![
-            "run".to_string(),
-            "-d".to_string(),
-            "--name".to_string(),
-            container_name.clone(),
-            "--hostname".to_string(),
-            "agentkernel".to_string(),
-        ];
-
-        // Add resource limits
-        args.push(format!("--cpus={}", config.vcpus));
-        args.push(format!("--memory={}m", config.memory_mb));
-
-        // Network configuration
-        if !config.network {
-            args.push("--network=none".to_string());
-        }
-
-        // Mount working directory if requested
-        if config.mount_cwd
-            && let Some(ref work_dir) = config.work_dir
-        {
-            args.push("-v".to_string());
-            args.push(format!("{}:/workspace", work_dir));
-            args.push("-w".to_string());
-            args.push("/workspace".to_string());
-        }
-
-        // Mount home directory if requested
-        if config.mount_home
-            && let Some(home) = std::env::var_os("HOME")
-        {
-            args.push("-v".to_string());
-            args.push(format!("{}:/home/user:ro", home.to_string_lossy()));
-        }
-
-        // Read-only root filesystem
-        if config.read_only {
-            args.push("--read-only".to_string());
+        if config.gpus.is_some() {
+            crate::docker_backend::check_gpu_support(cmd)?;
         }
 
-        // Add environment variables
-        for (key, value) in &config.env {
-            args.push("-e".to_string());
-            args.push(format!("{}={}", key, value));
+        // Resolve allowed hosts up front so a bad allowlist fails before starting
+        let firewall = firewall_args(&container_name, config)?;
+        if firewall.is_some() {
+            crate::docker_backend::ensure_firewall_image(cmd)?;
         }
 
-        // Add entrypoint override to keep container running
-        args.extend([
-            "--entrypoint".to_string(),
-            "sh".to_string(),
-            config.image.clone(),
-            "-c".to_string(),
-            "while true; do sleep 3600; done".to_string(),
-        ]);
+        let args = start_args(self.runtime, &container_name, config);
+        self.launch_command = Some(display_command(cmd, &args));
 
         // Start container
         let output = Command::new(cmd)
@@ -259,7 +778,39 @@ impl Sandbox for DockerSandbox {
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("Failed to start container: {}", stderr);
+            if let Some(ref user) = config.user
+                && stderr.contains("unable to find user")
+            {
+                bail!(
+                    "User '{}' does not exist in image '{}'. Use a numeric uid:gid instead.",
+                    user,
+                    config.image
+                );
+            }
+            let runtime = match self.runtime {
+                ContainerRuntime::Docker => crate::docker_backend::ContainerRuntime::Docker,
+                ContainerRuntime::Podman => crate::docker_backend::ContainerRuntime::Podman,
+            };
+            return Err(crate::docker_backend::start_error(
+                runtime,
+                &config.image,
+                &stderr,
+            ));
+        }
+
+        // Install the egress firewall; fail closed if it can't be applied
+        if let Some(firewall) = firewall {
+            let output = Command::new(cmd)
+                .args(&firewall)
+                .output()
+                .context("Failed to run egress firewall helper")?;
+            if !output.status.success() {
+                let _ = Command::new(cmd)
+                    .args(["rm", "-f", &container_name])
+                    .output();
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                bail!("Failed to apply egress allowlist: {}", stderr.trim());
+            }
         }
 
         let container_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -274,34 +825,108 @@ impl Sandbox for DockerSandbox {
     }
 
     async fn exec_with_env(&mut self, cmd: &[&str], env: &[String]) -> Result<ExecResult> {
-        let runtime_cmd = self.runtime.cmd();
-        let container_name = self.container_name();
+        self.exec_with_options(cmd, &ExecOptions::with_env(env))
+            .await
+    }
 
-        let mut args = vec!["exec".to_string()];
+    async fn exec_with_options(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecResult> {
+        self.resolved_paths.clear();
+        Ok(self.exec_streamed(cmd, opts).await?.into_lossy())
+    }
 
-        // Add environment variables
-        for e in env {
-            args.push("-e".to_string());
-            args.push(e.clone());
-        }
+    async fn exec_bytes(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecBytes> {
+        self.resolved_paths.clear();
+        self.exec_streamed(cmd, opts).await
+    }
 
-        args.push(container_name);
-        args.extend(cmd.iter().map(|s| s.to_string()));
+    async fn stream_file_range_unchecked(
+        &mut self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<tokio::process::Child>> {
+        // One exec for the whole download rather than one per chunk
+        let cmd = read_range_command(path, offset, len);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let child = tokio::process::Command::new(self.runtime.cmd())
+            .args(exec_args(
+                &self.container_name(),
+                &cmd,
+                &ExecOptions::default(),
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to read file from container")?;
+        Ok(Some(child))
+    }
 
-        let output = Command::new(runtime_cmd)
-            .args(&args)
-            .output()
+    async fn stream_exec(&mut self, cmd: &[&str]) -> Result<Option<tokio::process::Child>> {
+        let child = tokio::process::Command::new(self.runtime.cmd())
+            .args(exec_args(
+                &self.container_name(),
+                cmd,
+                &ExecOptions::default(),
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
             .context("Failed to run command in container")?;
+        Ok(Some(child))
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
+    async fn set_network(&mut self, enabled: bool) -> Result<()> {
+        let mode = self.configured_network()?;
+        let output = Command::new(self.runtime.cmd())
+            .args(network_toggle_args(
+                self.runtime,
+                &self.container_name(),
+                mode.as_ref(),
+                enabled,
+            )?)
+            .output()
+            .context("Failed to change container network")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to {} network: {}",
+                if enabled { "connect" } else { "disconnect" },
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
 
-        Ok(ExecResult {
-            exit_code,
-            stdout,
-            stderr,
-        })
+    async fn logs(&mut self, tail: Option<usize>) -> Result<Vec<LogLine>> {
+        let mut args = vec!["logs".to_string(), "--timestamps".to_string()];
+        if let Some(n) = tail {
+            args.push(format!("--tail={}", n));
+        }
+        args.push(self.container_name());
+        let output = Command::new(self.runtime.cmd())
+            .args(&args)
+            .output()
+            .context("Failed to read container logs")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to read container logs: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let mut lines = parse_docker_logs(
+            &String::from_utf8_lossy(&output.stdout),
+            &String::from_utf8_lossy(&output.stderr),
+        );
+
+        // The container's main process is a keep-alive; background commands log to a file
+        let cmd = detached_log_command(&DetachedPaths::DEFAULT, tail);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let detached = self.exec(&cmd).await?;
+        lines.extend(LogLine::from_detached(&detached));
+        Ok(lines)
     }
 
     async fn stop(&mut self) -> Result<()> {
@@ -336,15 +961,88 @@ impl Sandbox for DockerSandbox {
             .unwrap_or(false)
     }
 
-    async fn write_file_unchecked(&mut self, path: &str, content: &[u8]) -> Result<()> {
-        self.write_file_impl(path, content).await
+    fn launch_command(&self) -> Option<String> {
+        self.launch_command.clone()
+    }
+
+    async fn disk_usage(&self) -> Result<u64> {
+        let output = Command::new(self.runtime.cmd())
+            .args(["inspect", "--size", &self.container_name()])
+            .output()
+            .context("Failed to inspect container")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to get container size: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        parse_inspect_size(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    async fn ip_address(&self) -> Result<Option<std::net::IpAddr>> {
+        let output = Command::new(self.runtime.cmd())
+            .args(["inspect", &self.container_name()])
+            .output()
+            .context("Failed to inspect container")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to inspect container: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(parse_inspect_ip(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn exit_reason(&mut self) -> Option<ExitReason> {
+        let output = Command::new(self.runtime.cmd())
+            .args(["inspect", &self.container_name()])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_inspect_state(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    async fn write_file_unchecked(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> Result<()> {
+        self.write_file_impl(path, content, opts).await
     }
 
     async fn read_file_unchecked(&mut self, path: &str) -> Result<Vec<u8>> {
         self.read_file_impl(path).await
     }
 
+    async fn resolve_path(&mut self, path: &str) -> Result<Option<String>> {
+        if let Some(resolved) = self.resolved_paths.get(path) {
+            return Ok(Some(resolved.clone()));
+        }
+        // The file may not exist yet (writes), so fall back to resolving its directory
+        let script = r#"readlink -f -- "$1" 2>/dev/null || printf '%s/%s\n' "$(readlink -f -- "$(dirname -- "$1")")" "$(basename -- "$1")""#;
+        // Resolution is best effort: the path itself is still validated, and
+        // the file op reports its own error if the container is unusable
+        let Ok(result) = self
+            .exec_streamed(&["sh", "-c", script, "sh", path], &ExecOptions::default())
+            .await
+        else {
+            return Ok(None);
+        };
+        let result = result.into_lossy();
+        let resolved = result.stdout.trim();
+        if !result.is_success() || !resolved.starts_with('/') {
+            return Ok(None);
+        }
+        self.resolved_paths
+            .insert(path.to_string(), resolved.to_string());
+        Ok(Some(resolved.to_string()))
+    }
+
     async fn remove_file_unchecked(&mut self, path: &str) -> Result<()> {
+        self.resolved_paths.clear();
         let container_name = self.container_name();
         let output = Command::new(self.runtime.cmd())
             .args(["exec", &container_name, "rm", "-f", path])
@@ -352,14 +1050,14 @@ impl Sandbox for DockerSandbox {
             .context("Failed to remove file in container")?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("rm failed: {}", stderr);
+            return Err(runtime_error("rm failed", &output.stderr));
         }
 
         Ok(())
     }
 
     async fn mkdir_unchecked(&mut self, path: &str, recursive: bool) -> Result<()> {
+        self.resolved_paths.clear();
         let container_name = self.container_name();
         let mut args = vec!["exec", &container_name, "mkdir"];
         if recursive {
@@ -373,8 +1071,7 @@ impl Sandbox for DockerSandbox {
             .context("Failed to create directory in container")?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("mkdir failed: {}", stderr);
+            return Err(runtime_error("mkdir failed", &output.stderr));
         }
 
         Ok(())
@@ -385,6 +1082,7 @@ impl Sandbox for DockerSandbox {
     }
 
     async fn attach_with_env(&mut self, shell: Option<&str>, env: &[String]) -> Result<i32> {
+        self.resolved_paths.clear();
         // Check Docker directly since we might be reconnecting to an existing container
         if !self.is_running() {
             bail!("Container is not running");
@@ -463,6 +1161,9 @@ impl DockerSandbox {
         config: &SandboxConfig,
     ) -> Result<ExecResult> {
         let runtime_cmd = runtime.cmd();
+        if config.gpus.is_some() {
+            crate::docker_backend::check_gpu_support(runtime_cmd)?;
+        }
 
         let mut args = vec![
             "run".to_string(),
@@ -472,12 +1173,16 @@ impl DockerSandbox {
         // Add resource limits
         args.push(format!("--cpus={}", config.vcpus));
         args.push(format!("--memory={}m", config.memory_mb));
+        args.extend(gpu_arg(config.gpus.as_ref()));
 
-        // Network configuration
-        if !config.network {
-            args.push("--network=none".to_string());
+        if let Some(ref hostname) = config.hostname {
+            args.push("--hostname".to_string());
+            args.push(hostname.clone());
         }
 
+        // Network configuration
+        args.extend(network_args(config));
+
         // Mount working directory if requested
         if config.mount_cwd
             && let Some(ref work_dir) = config.work_dir
@@ -495,11 +1200,13 @@ impl DockerSandbox {
             args.push("-v".to_string());
             args.push(format!("{}:/home/user:ro", home.to_string_lossy()));
         }
+        args.extend(mount_args(config));
 
-        // Read-only root filesystem
-        if config.read_only {
-            args.push("--read-only".to_string());
-        }
+        // Read-only root filesystem (with optional writable workspace)
+        args.extend(read_only_args(config));
+        args.extend(tmpfs_args(config));
+        args.extend(config.ulimits.docker_args());
+        args.extend(init_args(runtime, config));
 
         // Add environment variables
         for (key, value) in &config.env {
@@ -507,6 +1214,9 @@ impl DockerSandbox {
             args.push(format!("{}={}", key, value));
         }
 
+        // Label so a crashed run's container can be reaped later
+        args.extend(ephemeral_label_args());
+
         // Image and command
         args.push(image.to_string());
         args.extend(cmd.iter().cloned());
@@ -525,6 +1235,8 @@ impl DockerSandbox {
             exit_code,
             stdout,
             stderr,
+            rusage: None,
+            truncated: false,
         })
     }
 }
@@ -540,3 +1252,601 @@ impl Drop for DockerSandbox {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::RestartPolicy;
+
+    #[test]
+    fn test_read_only_args_disabled() {
+        let config = SandboxConfig::default();
+        assert!(read_only_args(&config).is_empty());
+    }
+
+    #[test]
+    fn test_read_only_args_with_writable_workspace() {
+        let config = SandboxConfig::default().with_read_only(true, Some("/workspace".to_string()));
+        assert_eq!(
+            read_only_args(&config),
+            vec!["--read-only", "--tmpfs=/workspace:rw,exec,nosuid"]
+        );
+    }
+
+    #[test]
+    fn test_read_only_args_custom_path() {
+        let config = SandboxConfig::default().with_read_only(true, Some("/data".to_string()));
+        assert_eq!(
+            read_only_args(&config),
+            vec!["--read-only", "--tmpfs=/data:rw,exec,nosuid"]
+        );
+    }
+
+    #[test]
+    fn test_read_only_args_skips_tmpfs_over_cwd_mount() {
+        let config = SandboxConfig::default()
+            .with_mount_cwd(true, Some("/home/me/project".to_string()))
+            .with_read_only(true, Some("/workspace".to_string()));
+        assert_eq!(read_only_args(&config), vec!["--read-only"]);
+    }
+
+    #[test]
+    fn test_read_only_args_scratch_size() {
+        let config = SandboxConfig::default().with_scratch_mb(Some(512));
+        assert_eq!(
+            read_only_args(&config),
+            vec!["--tmpfs=/workspace:rw,exec,nosuid,size=512m"]
+        );
+
+        let config = SandboxConfig::default()
+            .with_read_only(true, Some("/data".to_string()))
+            .with_scratch_mb(Some(64));
+        assert_eq!(
+            read_only_args(&config),
+            vec!["--read-only", "--tmpfs=/data:rw,exec,nosuid,size=64m"]
+        );
+    }
+
+    #[test]
+    fn test_start_command_records_flags_and_redacts_secrets() {
+        let config = SandboxConfig::with_image("python:3.12-alpine")
+            .with_resources(2, 1024)
+            .with_network(false)
+            .with_env(vec![
+                ("API_TOKEN".to_string(), "hunter2".to_string()),
+                ("LANG".to_string(), "C.UTF-8".to_string()),
+            ]);
+        let command = display_command(
+            "docker",
+            &start_args(ContainerRuntime::Docker, "agentkernel-dev", &config),
+        );
+
+        assert!(command.starts_with("docker run -d --name agentkernel-dev "));
+        assert!(command.contains(" python:3.12-alpine "));
+        for flag in ["--cpus=2", "--memory=1024m", "--network=none"] {
+            assert!(command.contains(flag), "{} missing from {}", flag, command);
+        }
+        assert!(command.contains("'API_TOKEN=***'"));
+        assert!(command.contains("-e 'LANG=***'"));
+        assert!(!command.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_start_args_hostname() {
+        let hostname = |config: &SandboxConfig| {
+            let args = start_args(ContainerRuntime::Docker, "agentkernel-dev", config);
+            let i = args.iter().position(|a| a == "--hostname").unwrap();
+            args[i + 1].clone()
+        };
+        assert_eq!(hostname(&SandboxConfig::default()), "agentkernel");
+        assert_eq!(
+            hostname(&SandboxConfig::default().with_hostname("ci-runner")),
+            "ci-runner"
+        );
+    }
+
+    #[test]
+    fn test_tmpfs_args() {
+        assert!(tmpfs_args(&SandboxConfig::default()).is_empty());
+
+        let config = SandboxConfig::default().with_tmpfs(Some(256), None);
+        assert_eq!(
+            tmpfs_args(&config),
+            vec!["--tmpfs=/tmp:rw,noexec,nosuid,size=256m"]
+        );
+
+        let config = SandboxConfig::default().with_tmpfs(Some(1024), Some(16));
+        assert_eq!(
+            tmpfs_args(&config),
+            vec![
+                "--tmpfs=/tmp:rw,noexec,nosuid,size=1024m",
+                "--tmpfs=/run:rw,nosuid,size=16m"
+            ]
+        );
+
+        // A read-only root gets a bounded /tmp even without a size
+        let config = SandboxConfig::default().with_read_only(true, None);
+        assert_eq!(
+            tmpfs_args(&config),
+            vec!["--tmpfs=/tmp:rw,noexec,nosuid,size=64m"]
+        );
+    }
+
+    #[test]
+    fn test_init_for_persistent_sandboxes() {
+        let has_init = |config: &SandboxConfig| {
+            start_args(ContainerRuntime::Docker, "agentkernel-dev", config)
+                .contains(&"--init".to_string())
+        };
+        assert!(has_init(&SandboxConfig::default()));
+        assert!(!has_init(&SandboxConfig::default().with_ephemeral(true)));
+
+        // Off by default on Podman, whose init may not be installed
+        let podman = start_args(
+            ContainerRuntime::Podman,
+            "agentkernel-dev",
+            &SandboxConfig::default(),
+        );
+        assert!(!podman.contains(&"--init".to_string()));
+        let podman = start_args(
+            ContainerRuntime::Podman,
+            "agentkernel-dev",
+            &SandboxConfig::default().with_init(Some(true)),
+        );
+        assert!(podman.contains(&"--init".to_string()));
+
+        // [sandbox] init overrides the default either way
+        assert!(!has_init(&SandboxConfig::default().with_init(Some(false))));
+        assert!(has_init(
+            &SandboxConfig::default()
+                .with_ephemeral(true)
+                .with_init(Some(true))
+        ));
+
+        // --init goes before the image, not into the entrypoint command
+        let args = start_args(
+            ContainerRuntime::Docker,
+            "agentkernel-dev",
+            &SandboxConfig::default(),
+        );
+        let init = args.iter().position(|a| a == "--init").unwrap();
+        let image = args.iter().position(|a| a == "alpine:3.20").unwrap();
+        assert!(init < image);
+    }
+
+    #[test]
+    fn test_firewall_args() {
+        let config = SandboxConfig::default();
+        assert!(firewall_args("agentkernel-x", &config).unwrap().is_none());
+
+        let egress = crate::egress::EgressAllowlist {
+            hosts: Vec::new(),
+            cidrs: vec!["10.0.0.0/8".to_string()],
+        };
+        let config = SandboxConfig::default().with_egress(Some(egress));
+        let args = firewall_args("agentkernel-x", &config).unwrap().unwrap();
+        assert!(args.contains(&"--network=container:agentkernel-x".to_string()));
+        assert!(args.contains(&"--cap-add=NET_ADMIN".to_string()));
+        assert!(
+            args.last()
+                .unwrap()
+                .contains("iptables -A OUTPUT -d 10.0.0.0/8 -j ACCEPT")
+        );
+
+        // Host networking would put the rules on the host
+        let mut host = config.clone();
+        host.network_mode = Some(NetworkMode::Host);
+        assert!(firewall_args("agentkernel-x", &host).is_err());
+
+        // Docker's own restart would drop the rules
+        let restarting = config.clone().with_restart(RestartPolicy::Always);
+        assert!(firewall_args("agentkernel-x", &restarting).is_err());
+
+        // No network means nothing to filter
+        let config = config.with_network(false);
+        assert!(firewall_args("agentkernel-x", &config).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_inspect_state_oom_killed() {
+        let json = r#"[{
+            "Id": "3f2a9c",
+            "Name": "/agentkernel-test",
+            "State": {
+                "Status": "exited",
+                "Running": false,
+                "Paused": false,
+                "Restarting": false,
+                "OOMKilled": true,
+                "Dead": false,
+                "Pid": 0,
+                "ExitCode": 137,
+                "Error": "",
+                "StartedAt": "2025-01-10T12:00:00.000000000Z",
+                "FinishedAt": "2025-01-10T12:05:00.000000000Z"
+            }
+        }]"#;
+        let reason = parse_inspect_state(json).unwrap();
+        assert!(reason.oom_killed);
+        assert_eq!(reason.exit_code, Some(137));
+        assert_eq!(
+            reason.finished_at.as_deref(),
+            Some("2025-01-10T12:05:00.000000000Z")
+        );
+        assert_eq!(
+            reason.to_string(),
+            "sandbox killed: out of memory, increase memory_mb (exit code 137)"
+        );
+    }
+
+    #[test]
+    fn test_parse_inspect_state_without_oom() {
+        let json = r#"[{"State": {
+            "Status": "exited",
+            "Running": false,
+            "OOMKilled": false,
+            "ExitCode": 2,
+            "Error": "",
+            "FinishedAt": "2025-01-10T12:05:00Z"
+        }}]"#;
+        let reason = parse_inspect_state(json).unwrap();
+        assert!(!reason.oom_killed);
+        assert_eq!(reason.exit_code, Some(2));
+        assert_eq!(reason.to_string(), "exited (exit code 2)");
+
+        // Runtime errors are reported as-is; the zero time means "never finished"
+        let json = r#"[{"State": {
+            "Running": false,
+            "OOMKilled": false,
+            "ExitCode": 127,
+            "Error": "exec: \"sh\": executable file not found in $PATH",
+            "FinishedAt": "0001-01-01T00:00:00Z"
+        }}]"#;
+        let reason = parse_inspect_state(json).unwrap();
+        assert!(reason.error.unwrap().contains("executable file not found"));
+        assert!(reason.finished_at.is_none());
+    }
+
+    #[test]
+    fn test_parse_inspect_state_running_or_invalid() {
+        let json = r#"[{"State": {"Running": true, "OOMKilled": false, "ExitCode": 0}}]"#;
+        assert!(parse_inspect_state(json).is_none());
+        assert!(parse_inspect_state("[]").is_none());
+        assert!(parse_inspect_state("not json").is_none());
+    }
+
+    #[test]
+    fn test_parse_inspect_ip() {
+        // Default bridge network
+        let json = r#"[{
+            "NetworkSettings": {
+                "IPAddress": "172.17.0.3",
+                "Networks": {
+                    "bridge": {"IPAddress": "172.17.0.3", "Gateway": "172.17.0.1"}
+                }
+            }
+        }]"#;
+        assert_eq!(parse_inspect_ip(json), Some("172.17.0.3".parse().unwrap()));
+
+        // A user-defined network leaves the top-level address empty
+        let json = r#"[{
+            "NetworkSettings": {
+                "IPAddress": "",
+                "Networks": {"ci-net": {"IPAddress": "10.89.0.7"}}
+            }
+        }]"#;
+        assert_eq!(parse_inspect_ip(json), Some("10.89.0.7".parse().unwrap()));
+
+        // No address of its own with none or host networking
+        let json = r#"[{
+            "NetworkSettings": {"IPAddress": "", "Networks": {"host": {"IPAddress": ""}}}
+        }]"#;
+        assert_eq!(parse_inspect_ip(json), None);
+        assert_eq!(parse_inspect_ip("[]"), None);
+        assert_eq!(parse_inspect_ip("not json"), None);
+    }
+
+    #[test]
+    fn test_parse_inspect_size() {
+        let json = r#"[{
+            "Id": "3f2a9c",
+            "Name": "/agentkernel-test",
+            "SizeRw": 52428800,
+            "SizeRootFs": 137363456,
+            "State": {"Running": true}
+        }]"#;
+        assert_eq!(parse_inspect_size(json).unwrap(), 52_428_800);
+
+        assert_eq!(parse_inspect_size(r#"[{"SizeRw": 4096}]"#).unwrap(), 4096);
+        // The shared image alone isn't the container's
+        assert!(parse_inspect_size(r#"[{"SizeRootFs": 4096}]"#).is_err());
+        // Plain `inspect` without --size
+        assert!(parse_inspect_size(r#"[{"State": {"Running": true}}]"#).is_err());
+        assert!(parse_inspect_size("not json").is_err());
+    }
+
+    #[test]
+    fn test_label_args() {
+        assert!(label_args(&SandboxConfig::default()).is_empty());
+
+        let labels = [("team", "infra"), ("project", "foo")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let config = SandboxConfig::default().with_labels(labels);
+        assert_eq!(
+            label_args(&config),
+            vec!["--label=project=foo", "--label=team=infra"]
+        );
+    }
+
+    #[test]
+    fn test_exec_args_workdir() {
+        let args = exec_args("box", &["ls"], &ExecOptions::default());
+        assert_eq!(args, vec!["exec", "box", "ls"]);
+
+        let opts = ExecOptions::with_env(&["A=1".to_string()]).workdir(Some("/app"));
+        let args = exec_args("box", &["ls", "-la"], &opts);
+        assert_eq!(
+            args,
+            vec!["exec", "-e", "A=1", "-w", "/app", "box", "ls", "-la"]
+        );
+
+        let opts = ExecOptions::default().user(Some("1000:1000"));
+        let args = exec_args("box", &["id"], &opts);
+        assert_eq!(args, vec!["exec", "-u", "1000:1000", "box", "id"]);
+    }
+
+    #[test]
+    fn test_exec_args_combined() {
+        let opts = ExecOptions::default().combined(true);
+        let args = exec_args("box", &["make", "-j4"], &opts);
+        assert_eq!(
+            args,
+            vec![
+                "exec",
+                "box",
+                "sh",
+                "-c",
+                "exec \"$@\" 2>&1",
+                "sh",
+                "make",
+                "-j4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_args() {
+        assert!(user_args(&SandboxConfig::default()).is_empty());
+
+        let config = SandboxConfig::default().with_user(Some("1000:1000".to_string()));
+        assert_eq!(user_args(&config), vec!["--user=1000:1000"]);
+    }
+
+    #[test]
+    fn test_kill_exec_args_targets_marker() {
+        let args = kill_exec_args("agentkernel-test", "abc123");
+        assert_eq!(&args[..4], &["exec", "-u", "0", "agentkernel-test"]);
+        let script = args.last().unwrap();
+        assert!(script.contains("grep -qx 'AGENTKERNEL_EXEC_ID=abc123'"));
+        assert!(script.contains("kill -KILL ${p#/proc/}"));
+    }
+
+    #[test]
+    fn test_parse_docker_logs() {
+        let stdout = "2024-05-01T10:00:00.000000001Z starting\n\
+                      2024-05-01T10:00:02.000000000Z listening on :8080\n";
+        let stderr = "2024-05-01T10:00:01.500000000Z warning: no config\n";
+        let lines = parse_docker_logs(stdout, stderr);
+
+        let text: Vec<&str> = lines.iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(
+            text,
+            vec!["starting", "warning: no config", "listening on :8080"]
+        );
+        assert_eq!(lines[1].stream, LogStream::Stderr);
+        assert_eq!(
+            lines[0].timestamp.as_deref(),
+            Some("2024-05-01T10:00:00.000000001Z")
+        );
+
+        // Lines without a timestamp are kept whole
+        let lines = parse_docker_logs("plain line\n", "");
+        assert_eq!(lines[0].line, "plain line");
+        assert_eq!(lines[0].timestamp, None);
+        assert!(parse_docker_logs("", "").is_empty());
+    }
+
+    #[test]
+    fn test_restart_args() {
+        assert!(restart_args(&SandboxConfig::default()).is_empty());
+
+        let config = SandboxConfig::default().with_restart(RestartPolicy::OnFailure);
+        assert_eq!(restart_args(&config), vec!["--restart=on-failure:5"]);
+        let config = SandboxConfig::default().with_restart(RestartPolicy::Always);
+        assert_eq!(restart_args(&config), vec!["--restart=always"]);
+
+        // A sandbox for one command shouldn't come back
+        let config = config.with_ephemeral(true);
+        assert!(restart_args(&config).is_empty());
+    }
+
+    #[test]
+    fn test_dns_args() {
+        let config = SandboxConfig::default();
+        assert!(dns_args(&config.dns, &config.extra_hosts).is_empty());
+
+        let config = SandboxConfig::default().with_dns(
+            vec!["1.1.1.1".to_string(), "8.8.8.8".to_string()],
+            vec![
+                ("mock.local".to_string(), "10.0.0.5".parse().unwrap()),
+                ("v6.local".to_string(), "fd00::5".parse().unwrap()),
+            ],
+        );
+        assert_eq!(
+            dns_args(&config.dns, &config.extra_hosts),
+            vec![
+                "--dns=1.1.1.1",
+                "--dns=8.8.8.8",
+                "--add-host=mock.local:10.0.0.5",
+                "--add-host=v6.local:fd00::5",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_oversized_output_is_truncated_and_exit_code_kept() {
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "head -c 200000 /dev/zero; echo done >&2; exit 3"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = tokio::spawn(read_capped(child.stdout.take().unwrap(), 1024));
+        let stderr = tokio::spawn(read_capped(child.stderr.take().unwrap(), 1024));
+
+        // The command runs to completion even though most of its output is dropped
+        let status = child.wait().await.unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let (stdout, stdout_truncated) = stdout.await.unwrap();
+        assert_eq!(stdout.len(), 1024);
+        assert!(stdout_truncated);
+
+        let (stderr, stderr_truncated) = stderr.await.unwrap();
+        assert_eq!(stderr, b"done\n");
+        assert!(!stderr_truncated);
+    }
+
+    #[test]
+    fn test_publish_args() {
+        assert!(publish_args(&SandboxConfig::default()).is_empty());
+
+        let config = SandboxConfig {
+            ports: vec![
+                "3000".parse().unwrap(),
+                "8080:80".parse().unwrap(),
+                "0.0.0.0:9000:9000".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            publish_args(&config),
+            vec![
+                "-p",
+                "127.0.0.1:3000:3000",
+                "-p",
+                "127.0.0.1:8080:80",
+                "-p",
+                "0.0.0.0:9000:9000"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_network_args() {
+        let mut config = SandboxConfig::default();
+        assert!(network_args(&config).is_empty());
+        for (mode, flag) in [
+            (NetworkMode::Bridge, "--network=bridge"),
+            (NetworkMode::Host, "--network=host"),
+            (NetworkMode::Named("ci".to_string()), "--network=ci"),
+        ] {
+            let label = format!("--label=agentkernel.network={}", mode);
+            config.network_mode = Some(mode);
+            assert_eq!(network_args(&config), vec![flag.to_string(), label]);
+        }
+        config.network = false;
+        assert_eq!(
+            network_args(&config),
+            vec!["--network=none", "--label=agentkernel.network=ci"]
+        );
+    }
+
+    #[test]
+    fn test_is_missing_container() {
+        assert!(is_missing_container(
+            "Error response from daemon: No such container: agentkernel-dev\n"
+        ));
+        assert!(is_missing_container(
+            "Error: no container with name or ID \"agentkernel-dev\" found: no such container"
+        ));
+        assert!(!is_missing_container(
+            "Error response from daemon: container abc is not running"
+        ));
+        // The command's own output doesn't count
+        assert!(!is_missing_container("grep: No such container\n"));
+        // A missing file in `docker cp`
+        assert!(!is_missing_container(
+            "Error: No such container:path: agentkernel-dev:/missing.txt"
+        ));
+        assert!(!is_missing_container(""));
+    }
+
+    #[test]
+    fn test_gpu_args() {
+        let mut config = SandboxConfig::default();
+        assert!(
+            !start_args(ContainerRuntime::Docker, "agentkernel-dev", &config)
+                .iter()
+                .any(|a| a.starts_with("--gpus"))
+        );
+
+        // Passed before the image, not to the entrypoint
+        config.gpus = Some("all".parse().unwrap());
+        let args = start_args(ContainerRuntime::Docker, "agentkernel-dev", &config);
+        let gpus = args.iter().position(|a| a.starts_with("--gpus")).unwrap();
+        let image = args.iter().position(|a| a == "alpine:3.20").unwrap();
+        assert!(gpus < image);
+    }
+
+    #[test]
+    fn test_mount_args() {
+        let mut config = SandboxConfig::default();
+        assert!(mount_args(&config).is_empty());
+
+        config.mounts = vec![crate::permissions::FileMount {
+            source: "/home/me/.aws/credentials".to_string(),
+            target: "/root/.aws/credentials".to_string(),
+        }];
+        let args = start_args(ContainerRuntime::Docker, "agentkernel-dev", &config);
+        let volume = "/home/me/.aws/credentials:/root/.aws/credentials:ro";
+        let pos = args.iter().position(|a| a == volume).unwrap();
+        assert_eq!(args[pos - 1], "-v");
+        assert!(pos < args.iter().position(|a| a == "alpine:3.20").unwrap());
+    }
+
+    #[test]
+    fn test_network_toggle_args() {
+        let toggle = |runtime, mode: Option<&NetworkMode>, enabled| {
+            network_toggle_args(runtime, "agentkernel-dev", mode, enabled).unwrap()
+        };
+        assert_eq!(
+            toggle(ContainerRuntime::Docker, None, false),
+            vec!["network", "disconnect", "bridge", "agentkernel-dev"]
+        );
+        assert_eq!(
+            toggle(ContainerRuntime::Docker, Some(&NetworkMode::Bridge), true),
+            vec!["network", "connect", "bridge", "agentkernel-dev"]
+        );
+        // Podman's default network has its own name
+        assert_eq!(
+            toggle(ContainerRuntime::Podman, None, true),
+            vec!["network", "connect", "podman", "agentkernel-dev"]
+        );
+        // A named network is rejoined rather than the default one
+        let ci = NetworkMode::Named("ci".to_string());
+        assert_eq!(
+            toggle(ContainerRuntime::Podman, Some(&ci), true),
+            vec!["network", "connect", "ci", "agentkernel-dev"]
+        );
+
+        // The host network can't be joined or left
+        let host = Some(&NetworkMode::Host);
+        assert!(
+            network_toggle_args(ContainerRuntime::Docker, "agentkernel-dev", host, false).is_err()
+        );
+    }
+}
@@ -6,10 +6,21 @@ use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
 use tokio::time::{Duration, sleep};
 
-use super::{BackendType, ExecResult, Sandbox, SandboxConfig};
+use super::{
+    BackendType, DetachedPaths, ExecOptions, ExecResult, ExitReason, FileInjection, LogBuffer,
+    LogLine, LogStream, Sandbox, SandboxConfig, WriteOptions, detached_log_command,
+    display_command, file_disk_usage,
+};
 use crate::firecracker_client::{BootSource, Drive, FirecrackerClient, MachineConfig, VsockDevice};
 use crate::languages::docker_image_to_firecracker_runtime;
-use crate::vsock::VsockClient;
+use crate::permissions::{FileMount, NetworkMode};
+use crate::vsock::{AGENT_BOOT_TIMEOUT, VsockClient};
+
+/// Guest network interface
+const GUEST_INTERFACE: &str = "eth0";
+
+/// Mode of a `[[mounts]]` file copied into the guest
+const MOUNTED_FILE_MODE: u32 = 0o444;
 
 /// Check if Firecracker is available
 pub fn firecracker_available() -> bool {
@@ -26,21 +37,18 @@ fn find_firecracker() -> Result<PathBuf> {
         }
     }
 
-    // Check user's local bin directories
+    // ~/.local/bin/firecracker (common user install location)
     if let Some(home) = std::env::var_os("HOME") {
-        let home = PathBuf::from(home);
-
-        // ~/.local/bin/firecracker (common user install location)
-        let local_bin = home.join(".local/bin/firecracker");
+        let local_bin = PathBuf::from(home).join(".local/bin/firecracker");
         if local_bin.exists() {
             return Ok(local_bin);
         }
+    }
 
-        // ~/.local/share/agentkernel/bin/firecracker (agentkernel managed)
-        let agentkernel_bin = home.join(".local/share/agentkernel/bin/firecracker");
-        if agentkernel_bin.exists() {
-            return Ok(agentkernel_bin);
-        }
+    // ~/.local/share/agentkernel/bin/firecracker (agentkernel managed)
+    let agentkernel_bin = crate::setup::default_data_dir().join("bin/firecracker");
+    if agentkernel_bin.exists() {
+        return Ok(agentkernel_bin);
     }
 
     // Check common system locations
@@ -70,6 +78,15 @@ fn find_firecracker() -> Result<PathBuf> {
     bail!("Firecracker binary not found")
 }
 
+/// Rootfs file recorded as the image, instead of a runtime name.
+///
+/// The `rootfs:<path>` marker is only produced by the CLI (Dockerfile
+/// conversion and `--image-archive`); image names from the API or MCP are
+/// validated so they can't carry it.
+pub fn local_rootfs(image: &str) -> Option<PathBuf> {
+    image.strip_prefix("rootfs:").map(PathBuf::from)
+}
+
 /// Firecracker microVM sandbox
 pub struct FirecrackerSandbox {
     name: String,
@@ -77,9 +94,21 @@ pub struct FirecrackerSandbox {
     vsock_path: PathBuf,
     process: Option<Child>,
     vsock_cid: u32,
+    /// Vsock port the guest agent listens on
+    agent_port: u32,
     kernel_path: Option<PathBuf>,
     rootfs_path: Option<PathBuf>,
     running: bool,
+    /// User commands run as inside the guest
+    user: Option<String>,
+    /// Backing file for the scratch disk, if one is attached
+    scratch_path: PathBuf,
+    /// Recent serial console (stdout) and Firecracker (stderr) output
+    console: LogBuffer,
+    /// Threads draining the process's output into `console`
+    readers: Vec<std::thread::JoinHandle<()>>,
+    /// Firecracker command line and VM config of the last start
+    launch_command: Option<String>,
 }
 
 impl FirecrackerSandbox {
@@ -87,6 +116,7 @@ impl FirecrackerSandbox {
     pub fn new(name: &str) -> Result<Self> {
         let socket_path = PathBuf::from(format!("/tmp/agentkernel-{}.sock", name));
         let vsock_path = PathBuf::from(format!("/tmp/agentkernel-{}-vsock.sock", name));
+        let scratch_path = scratch_image_path(name);
 
         // Clean up any existing sockets
         let _ = std::fs::remove_file(&socket_path);
@@ -106,9 +136,15 @@ impl FirecrackerSandbox {
             vsock_path,
             process: None,
             vsock_cid,
+            agent_port: crate::vsock::agent_port(),
             kernel_path: None,
             rootfs_path: None,
             running: false,
+            user: None,
+            scratch_path,
+            console: LogBuffer::default(),
+            readers: Vec::new(),
+            launch_command: None,
         })
     }
 
@@ -148,11 +184,9 @@ impl FirecrackerSandbox {
         }
 
         // Check ~/.local/share/agentkernel/kernel (installed)
-        if let Some(home) = std::env::var_os("HOME") {
-            let kernel_dir = PathBuf::from(home).join(".local/share/agentkernel/kernel");
-            if let Some(path) = find_vmlinux_in(&kernel_dir) {
-                return Ok(path);
-            }
+        let kernel_dir = crate::setup::default_data_dir().join("kernel");
+        if let Some(path) = find_vmlinux_in(&kernel_dir) {
+            return Ok(path);
         }
 
         bail!("Kernel not found. Run 'agentkernel setup' to install.")
@@ -160,13 +194,12 @@ impl FirecrackerSandbox {
 
     /// Find rootfs path for an image
     fn find_rootfs(image: &str) -> Result<PathBuf> {
-        // Check for explicit rootfs path (from Dockerfile conversion)
-        if let Some(path) = image.strip_prefix("rootfs:") {
-            let rootfs_path = PathBuf::from(path);
-            if rootfs_path.exists() {
+        // Check for an explicit rootfs path (from Dockerfile conversion or --image-archive)
+        if let Some(rootfs_path) = local_rootfs(image) {
+            if rootfs_path.is_file() {
                 return Ok(rootfs_path);
             }
-            bail!("Converted rootfs not found: {}", path);
+            bail!("Rootfs not found: {}", rootfs_path.display());
         }
 
         // Map Docker image name to Firecracker runtime
@@ -180,12 +213,11 @@ impl FirecrackerSandbox {
         }
 
         // Check ~/.local/share/agentkernel/rootfs (installed)
-        if let Some(home) = std::env::var_os("HOME") {
-            let rootfs_dir = PathBuf::from(home).join(".local/share/agentkernel/rootfs");
-            let rootfs_path = rootfs_dir.join(&rootfs_name);
-            if rootfs_path.exists() {
-                return Ok(rootfs_path);
-            }
+        let rootfs_path = crate::setup::default_data_dir()
+            .join("rootfs")
+            .join(&rootfs_name);
+        if rootfs_path.exists() {
+            return Ok(rootfs_path);
         }
 
         bail!(
@@ -205,8 +237,10 @@ impl FirecrackerSandbox {
         bail!("Firecracker API socket not available after 5 seconds")
     }
 
-    /// Configure the VM via the Firecracker API
-    async fn configure(&self, config: &SandboxConfig) -> Result<()> {
+    /// Configure the VM via the Firecracker API, keeping the rootfs it boots.
+    ///
+    /// Returns the configuration in `firecracker --config-file` format.
+    async fn configure(&mut self, config: &SandboxConfig) -> Result<serde_json::Value> {
         let client = FirecrackerClient::new(&self.socket_path);
 
         // Get kernel and rootfs paths
@@ -221,22 +255,36 @@ impl FirecrackerSandbox {
             .clone()
             .or_else(|| Self::find_rootfs(&config.image).ok())
             .ok_or_else(|| anyhow::anyhow!("Rootfs path not set"))?;
+        self.rootfs_path = Some(rootfs_path.clone());
 
         // Set boot source with optimized boot args
         let boot_source = BootSource {
             kernel_image_path: kernel_path.to_string_lossy().to_string(),
-            boot_args: "console=ttyS0 reboot=k panic=1 pci=off root=/dev/vda rw init=/init quiet loglevel=4 i8042.nokbd i8042.noaux".to_string(),
+            boot_args: boot_args(config),
         };
         client.set_boot_source(&boot_source).await?;
 
-        // Set root drive
+        // Set root drive (read-only root gets a tmpfs overlay from the guest init)
         let drive = Drive {
             drive_id: "rootfs".to_string(),
             path_on_host: rootfs_path.to_string_lossy().to_string(),
             is_root_device: true,
-            is_read_only: false,
+            is_read_only: config.read_only,
         };
         client.set_drive("rootfs", &drive).await?;
+        let mut drives = vec![drive];
+
+        // Scratch disk shows up as /dev/vdb and is mounted by the guest init
+        if config.scratch_mb.is_some() {
+            let scratch = Drive {
+                drive_id: "scratch".to_string(),
+                path_on_host: self.scratch_path.to_string_lossy().to_string(),
+                is_root_device: false,
+                is_read_only: false,
+            };
+            client.set_drive("scratch", &scratch).await?;
+            drives.push(scratch);
+        }
 
         // Set machine config
         let machine = MachineConfig {
@@ -252,7 +300,31 @@ impl FirecrackerSandbox {
         };
         client.set_vsock(&vsock).await?;
 
-        Ok(())
+        Ok(serde_json::json!({
+            "boot-source": boot_source,
+            "drives": drives,
+            "machine-config": machine,
+            "vsock": vsock,
+        }))
+    }
+
+    /// Client for the guest agent, acting as the agent's own user (root).
+    ///
+    /// Only for setting up and inspecting the VM; what a caller runs or
+    /// touches goes through [`Self::user_client`].
+    fn client(&self) -> VsockClient {
+        VsockClient::for_firecracker(&self.vsock_path).with_port(self.agent_port)
+    }
+
+    /// User an operation runs as: the one it asks for, else the sandbox's
+    fn run_as(&self, requested: Option<&String>) -> Option<String> {
+        requested.or(self.user.as_ref()).cloned()
+    }
+
+    /// Client for the guest agent acting as [`Self::run_as`]; the agent
+    /// drops privileges before running a command or touching a file
+    fn user_client(&self, requested: Option<&String>) -> VsockClient {
+        self.client().with_user(self.run_as(requested))
     }
 
     /// Start the VM instance
@@ -263,19 +335,258 @@ impl FirecrackerSandbox {
 
     /// Wait for the guest agent to become available
     async fn wait_for_agent(&self) -> Result<()> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
+        self.client().wait_until_ready(AGENT_BOOT_TIMEOUT).await
+    }
 
-        for i in 0..100 {
-            if client.ping().await.unwrap_or(false) {
-                return Ok(());
-            }
-            if i % 20 == 0 && i > 0 {
-                eprintln!("Waiting for guest agent... ({}s)", i / 10);
-            }
-            sleep(Duration::from_millis(100)).await;
+    /// Replace the hostname the guest init set, and write /etc/hostname
+    /// unless the root is read-only
+    async fn set_hostname(&mut self, hostname: &str, read_only: bool) -> Result<()> {
+        let cmd = ["hostname", hostname].map(String::from);
+        let result = self.client().run_command(&cmd).await?;
+        if result.exit_code != 0 {
+            bail!(
+                "Failed to set hostname '{}': {}",
+                hostname,
+                result.stderr.trim()
+            );
+        }
+        if !read_only {
+            self.write_file_unchecked(
+                "/etc/hostname",
+                format!("{}\n", hostname).as_bytes(),
+                &WriteOptions::default(),
+            )
+            .await
+            .context("Failed to write /etc/hostname")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reject network modes a microVM can't provide: there is no host network
+/// stack or container network to join
+fn check_network_mode(config: &SandboxConfig) -> Result<()> {
+    match config.network_mode {
+        Some(ref mode @ (NetworkMode::Host | NetworkMode::Named(_))) if config.network => bail!(
+            "network_mode = \"{}\" is not supported on Firecracker (use none or bridge)",
+            mode
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Directories a read-only root still has writable, as set up by the guest
+/// init from [`boot_args`]: the workspace, /tmp, and /run when sized
+fn writable_dirs(config: &SandboxConfig) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    if config.scratch_mb.is_some() {
+        dirs.push(config.writable_path.as_deref().unwrap_or("/workspace"));
+    } else if let Some(ref path) = config.writable_path {
+        dirs.push(path.as_str());
+    }
+    dirs.push("/tmp");
+    if config.run_size_mb.is_some() {
+        dirs.push("/run");
+    }
+    dirs
+}
+
+/// Reject settings written into the root filesystem after boot when it is
+/// mounted read-only, before the VM starts rather than halfway through
+fn check_read_only_root(config: &SandboxConfig) -> Result<()> {
+    if !config.read_only {
+        return Ok(());
+    }
+    if !config.dns.is_empty() {
+        bail!(
+            "dns is not supported on Firecracker with a read-only root: /etc/resolv.conf \
+             is written into the guest (set read_only = false, or use Docker or Podman)"
+        );
+    }
+    if !config.extra_hosts.is_empty() {
+        bail!(
+            "extra_hosts is not supported on Firecracker with a read-only root: /etc/hosts \
+             is written into the guest (set read_only = false, or use Docker or Podman)"
+        );
+    }
+    // Mounted files are copied in, so they need somewhere writable to land
+    let writable = writable_dirs(config);
+    for mount in &config.mounts {
+        let target = std::path::Path::new(&mount.target);
+        if !writable
+            .iter()
+            .any(|dir| target.starts_with(dir) && target != std::path::Path::new(dir))
+        {
+            bail!(
+                "Mount target {} is on the read-only root: Firecracker copies mounted files \
+                 into the guest, so the target must be under {} (or set read_only = false)",
+                mount.target,
+                writable.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parse the guest address from `ip -4 -o addr show` output, e.g.
+/// `2: eth0    inet 172.16.0.2/30 brd 172.16.0.3 scope global eth0`
+fn parse_ip_addr(output: &str) -> Option<std::net::IpAddr> {
+    let mut words = output.split_whitespace();
+    words.find(|w| *w == "inet")?;
+    let cidr = words.next()?;
+    cidr.split('/').next()?.parse().ok()
+}
+
+/// Kernel command line for a sandbox config.
+///
+/// A read-only root is mounted `ro`; the guest init then mounts a tmpfs at
+/// the path given by `agentkernel.writable=` so the workspace stays writable,
+/// and a bounded /tmp as Docker does.
+pub fn boot_args(config: &SandboxConfig) -> String {
+    let mut args = String::from("console=ttyS0 reboot=k panic=1 pci=off root=/dev/vda");
+    args.push_str(if config.read_only { " ro" } else { " rw" });
+    args.push_str(" init=/init quiet loglevel=4 i8042.nokbd i8042.noaux");
+    if config.scratch_mb.is_some() {
+        // The scratch disk also covers the writable path on a read-only root
+        let path = config.writable_path.as_deref().unwrap_or("/workspace");
+        args.push_str(&format!(" agentkernel.scratch={}", path));
+    } else if config.read_only
+        && let Some(ref path) = config.writable_path
+    {
+        args.push_str(&format!(" agentkernel.writable={}", path));
+    }
+    // The guest agent reads a non-default port from the command line
+    let port = config.agent_port.unwrap_or_else(crate::vsock::agent_port);
+    if port != crate::vsock::AGENT_PORT {
+        args.push_str(&format!(" agentkernel.agent_port={}", port));
+    }
+    // The guest init mounts size-limited tmpfs at /tmp and /run
+    if let Some(mb) = crate::permissions::tmp_size_mb(config.read_only, config.tmp_size_mb) {
+        args.push_str(&format!(" agentkernel.tmp_size={}m", mb));
+    }
+    if let Some(mb) = config.run_size_mb {
+        args.push_str(&format!(" agentkernel.run_size={}m", mb));
+    }
+    // ...and sets resource limits before starting the guest agent
+    for (name, pair) in config.ulimits.iter() {
+        args.push_str(&format!(" agentkernel.ulimit.{}={}", name, pair));
+    }
+    args
+}
+
+/// Where a sandbox's scratch disk image lives: under the data directory,
+/// which only the user can write, rather than the shared /tmp
+fn scratch_image_path(name: &str) -> PathBuf {
+    crate::setup::default_data_dir()
+        .join("scratch")
+        .join(format!("{}.ext4", name))
+}
+
+/// Create a sparse ext4 image of `size_mb` for the scratch disk.
+///
+/// A leftover image is replaced; the new one is created exclusively and
+/// without following symlinks, so a link planted at the path can't redirect
+/// the write.
+fn create_scratch_image(path: &std::path::Path, size_mb: u64) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to remove old {}", path.display()));
         }
+    }
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .with_context(|| format!("Failed to create scratch disk {}", path.display()))?;
+    file.set_len(size_mb * 1024 * 1024)?;
+    drop(file);
+
+    let output = Command::new("mkfs.ext4")
+        .args(["-F", "-q"])
+        .arg(path)
+        .output()
+        .context("Failed to run mkfs.ext4 (install e2fsprogs to use scratch_mb)")?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(path);
+        bail!(
+            "Failed to format scratch disk: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Contents of /etc/resolv.conf for the given DNS servers
+fn resolv_conf(dns: &[String]) -> String {
+    dns.iter()
+        .map(|server| format!("nameserver {}\n", server))
+        .collect()
+}
+
+/// Contents of /etc/hosts: the defaults, the sandbox's own hostname, then
+/// the given extra entries
+fn hosts_file(hostname: Option<&str>, extra_hosts: &[(String, std::net::IpAddr)]) -> String {
+    let mut hosts = String::from("127.0.0.1\tlocalhost\n::1\tlocalhost\n");
+    if let Some(hostname) = hostname {
+        hosts.push_str(&format!("127.0.1.1\t{}\n", hostname));
+    }
+    for (host, ip) in extra_hosts {
+        hosts.push_str(&format!("{}\t{}\n", ip, host));
+    }
+    hosts
+}
+
+/// Read the host files of `[[mounts]]`, which a VM can't bind, to copy them
+/// into the guest instead. Errors name the path, never the contents.
+fn mount_injections(mounts: &[FileMount]) -> Result<Vec<FileInjection>> {
+    mounts
+        .iter()
+        .map(|mount| {
+            let content = std::fs::read(&mount.source).with_context(|| {
+                format!(
+                    "Failed to read {} for mount at {}",
+                    mount.source, mount.target
+                )
+            })?;
+            Ok(FileInjection {
+                content,
+                dest: mount.target.clone(),
+            })
+        })
+        .collect()
+}
 
-        bail!("Guest agent not available after 10 seconds")
+/// Explain a Firecracker process exit from its exit code and serial console output.
+///
+/// The guest boots with `panic=1 reboot=k`, so a guest kernel panic shows up
+/// as the VMM exiting with the panic message on the console.
+fn classify_vm_exit(exit_code: Option<i32>, console: &str) -> ExitReason {
+    let panic = console.lines().find_map(|line| {
+        line.find("Kernel panic")
+            .map(|i| line[i..].trim().to_string())
+    });
+    let oom_killed = panic
+        .as_deref()
+        .is_some_and(|p| p.contains("Out of memory") || p.contains("out of memory"));
+
+    ExitReason {
+        exit_code,
+        oom_killed,
+        error: Some(panic.unwrap_or_else(|| "Firecracker VMM exited unexpectedly".to_string())),
+        finished_at: Some(chrono::Utc::now().to_rfc3339()),
     }
 }
 
@@ -283,9 +594,24 @@ impl FirecrackerSandbox {
 impl Sandbox for FirecrackerSandbox {
     async fn start(&mut self, config: &SandboxConfig) -> Result<()> {
         let firecracker_bin = find_firecracker()?;
+        check_network_mode(config)?;
+        check_read_only_root(config)?;
+        config.check_gpus_supported(BackendType::Firecracker)?;
+        // Read before booting, so a missing file fails fast
+        let mounted = mount_injections(&config.mounts)?;
+
+        if let Some(size_mb) = config.scratch_mb {
+            create_scratch_image(&self.scratch_path, size_mb)?;
+        }
+        if let Some(cid) = config.vsock_cid {
+            self.vsock_cid = cid;
+        }
+        if let Some(port) = config.agent_port {
+            self.agent_port = port;
+        }
 
         // Start firecracker process
-        let process = Command::new(&firecracker_bin)
+        let mut process = Command::new(&firecracker_bin)
             .arg("--api-sock")
             .arg(&self.socket_path)
             .stdin(Stdio::null())
@@ -296,13 +622,31 @@ impl Sandbox for FirecrackerSandbox {
                 format!("Failed to start firecracker: {}", firecracker_bin.display())
             })?;
 
+        // Drain output continuously so the pipes never fill and stall the VM
+        if let Some(stdout) = process.stdout.take() {
+            self.readers
+                .push(self.console.capture(stdout, LogStream::Stdout));
+        }
+        if let Some(stderr) = process.stderr.take() {
+            self.readers
+                .push(self.console.capture(stderr, LogStream::Stderr));
+        }
         self.process = Some(process);
 
         // Wait for socket
         self.wait_for_socket().await?;
 
-        // Configure the VM
-        self.configure(config).await?;
+        // Configure the VM, and record how it was launched
+        let vm_config = self.configure(config).await?;
+        let args = vec![
+            "--api-sock".to_string(),
+            self.socket_path.to_string_lossy().to_string(),
+        ];
+        self.launch_command = Some(format!(
+            "{}\n{}",
+            display_command(&firecracker_bin.to_string_lossy(), &args),
+            serde_json::to_string_pretty(&vm_config)?
+        ));
 
         // Start the VM instance
         self.start_instance().await?;
@@ -310,21 +654,85 @@ impl Sandbox for FirecrackerSandbox {
         // Wait for guest agent
         self.wait_for_agent().await?;
 
+        // DNS and host entries are written into the guest once the agent is
+        // up, as root rather than the sandbox's user
+        let agent = self.client();
+        if !config.dns.is_empty() {
+            agent
+                .write_file(
+                    "/etc/resolv.conf",
+                    resolv_conf(&config.dns).as_bytes(),
+                    &WriteOptions::default(),
+                )
+                .await
+                .context("Failed to write /etc/resolv.conf")?;
+        }
+        if let Some(ref hostname) = config.hostname {
+            self.set_hostname(hostname, config.read_only).await?;
+        }
+        // A read-only root keeps the image's files (entries are refused for it)
+        if !config.extra_hosts.is_empty() || (config.hostname.is_some() && !config.read_only) {
+            agent
+                .write_file(
+                    "/etc/hosts",
+                    hosts_file(config.hostname.as_deref(), &config.extra_hosts).as_bytes(),
+                    &WriteOptions::default(),
+                )
+                .await
+                .context("Failed to write /etc/hosts")?;
+        }
+        for file in &mounted {
+            if let Some(parent) = std::path::Path::new(&file.dest).parent()
+                && parent != std::path::Path::new("/")
+            {
+                agent.mkdir(&parent.to_string_lossy(), true).await?;
+            }
+            let opts = WriteOptions::default().mode(Some(MOUNTED_FILE_MODE));
+            agent
+                .write_file(&file.dest, &file.content, &opts)
+                .await
+                .with_context(|| format!("Failed to copy mounted file to {}", file.dest))?;
+        }
+
+        self.user = config.user.clone();
         self.running = true;
         Ok(())
     }
 
     async fn exec(&mut self, cmd: &[&str]) -> Result<ExecResult> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
+        self.exec_with_options(cmd, &ExecOptions::default()).await
+    }
+
+    async fn exec_with_env(&mut self, cmd: &[&str], env: &[String]) -> Result<ExecResult> {
+        self.exec_with_options(cmd, &ExecOptions::with_env(env))
+            .await
+    }
+
+    async fn exec_with_options(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecResult> {
+        let client = self
+            .user_client(opts.user.as_ref())
+            .with_combined(opts.combined);
 
         // Convert &str to String
         let command: Vec<String> = cmd.iter().map(|s| s.to_string()).collect();
-
-        match client.run_command(&command).await {
+        let env = (!opts.env.is_empty()).then(|| {
+            opts.env
+                .iter()
+                .filter_map(|e| e.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        });
+
+        match client
+            .run_command_with_timeout(&command, opts.workdir.as_deref(), env, opts.timeout)
+            .await
+        {
             Ok(result) => Ok(ExecResult {
                 exit_code: result.exit_code,
                 stdout: result.stdout,
                 stderr: result.stderr,
+                rusage: result.rusage,
+                truncated: false,
             }),
             Err(e) => Ok(ExecResult::failure(1, e.to_string())),
         }
@@ -344,9 +752,10 @@ impl Sandbox for FirecrackerSandbox {
             let _ = process.wait();
         }
 
-        // Clean up sockets
+        // Clean up sockets and the scratch disk
         let _ = std::fs::remove_file(&self.socket_path);
         let _ = std::fs::remove_file(&self.vsock_path);
+        let _ = std::fs::remove_file(&self.scratch_path);
 
         self.running = false;
         Ok(())
@@ -377,25 +786,228 @@ impl Sandbox for FirecrackerSandbox {
         }
     }
 
-    async fn write_file_unchecked(&mut self, path: &str, content: &[u8]) -> anyhow::Result<()> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
-        client.write_file(path, content).await
+    fn launch_command(&self) -> Option<String> {
+        self.launch_command.clone()
+    }
+
+    fn exit_reason(&mut self) -> Option<ExitReason> {
+        let process = self.process.as_mut()?;
+        let status = process.try_wait().ok()??;
+
+        // The process has exited, so the readers finish once the pipes drain
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+        let console: String = self
+            .console
+            .lines()
+            .iter()
+            .map(|l| format!("{}\n", l.line))
+            .collect();
+        Some(classify_vm_exit(status.code(), &console))
+    }
+
+    async fn disk_usage(&self) -> Result<u64> {
+        // The rootfs image is shared by every VM booted from it
+        if self.scratch_path.exists() {
+            file_disk_usage(&self.scratch_path)
+        } else {
+            Ok(0)
+        }
+    }
+
+    async fn ip_address(&self) -> Result<Option<std::net::IpAddr>> {
+        // Ask the guest: the address is assigned inside the VM
+        let cmd = ["ip", "-4", "-o", "addr", "show", "dev", GUEST_INTERFACE].map(String::from);
+        let result = self.client().run_command(&cmd).await?;
+        if result.exit_code != 0 {
+            // No such interface: the VM was started without one
+            return Ok(None);
+        }
+        Ok(parse_ip_addr(&result.stdout))
+    }
+
+    async fn set_network(&mut self, enabled: bool) -> Result<()> {
+        // The VM has no network device, and a guest-side `ip link` toggle
+        // could be undone by anyone with root in the guest
+        if enabled {
+            bail!("Firecracker sandboxes are started without a network interface");
+        }
+        Ok(())
+    }
+
+    async fn logs(&mut self, tail: Option<usize>) -> Result<Vec<LogLine>> {
+        let mut lines = self.console.lines();
+        if self.running {
+            let cmd = detached_log_command(&DetachedPaths::DEFAULT, tail);
+            let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+            let detached = self.exec(&cmd).await?;
+            lines.extend(LogLine::from_detached(&detached));
+        }
+        Ok(lines)
+    }
+
+    #[cfg(unix)]
+    async fn attach(&mut self, shell: Option<&str>) -> Result<i32> {
+        self.attach_with_env(shell, &[]).await
+    }
+
+    #[cfg(unix)]
+    async fn attach_with_env(&mut self, shell: Option<&str>, env: &[String]) -> Result<i32> {
+        use crate::terminal::{RawModeGuard, window_size};
+        use crate::vsock::{ShellMessage, VsockConnection};
+        use std::io::{Read, Write};
+        use std::os::unix::io::AsRawFd;
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let env: std::collections::HashMap<String, String> = env
+            .iter()
+            .filter_map(|e| e.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let command = vec![shell.unwrap_or("/bin/sh").to_string()];
+        let stdin_fd = std::io::stdin().as_raw_fd();
+        let stdout_fd = std::io::stdout().as_raw_fd();
+        let (rows, cols) = window_size(stdout_fd).unwrap_or((24, 80));
+
+        let connection = VsockConnection::connect(&self.vsock_path, self.agent_port).await?;
+        let mut session = connection
+            .start_shell(
+                Some(command),
+                rows,
+                cols,
+                (!env.is_empty()).then_some(env),
+                self.run_as(None),
+            )
+            .await?;
+
+        // Restored when dropped, including on early error returns
+        let _raw_mode = RawModeGuard::enable(stdin_fd)?;
+
+        // Stdin reads block, so feed them in from a plain thread
+        let (input_tx, mut input_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stdin.read(&mut buf) {
+                if n == 0 || input_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut resized = signal(SignalKind::window_change())?;
+        let mut stdout = std::io::stdout();
+        let mut stdin_open = true;
+        // Session recording (`attach --record`) captures the raw output stream
+        let mut record = std::env::var("AGENTKERNEL_RECORD")
+            .ok()
+            .and_then(|path| std::fs::File::create(path).ok());
+
+        let exit_code = loop {
+            tokio::select! {
+                message = session.next_message() => match message? {
+                    ShellMessage::Output(data) => {
+                        stdout.write_all(&data)?;
+                        stdout.flush()?;
+                        if let Some(file) = record.as_mut() {
+                            let _ = file.write_all(&data);
+                        }
+                    }
+                    ShellMessage::Exited(code) => break code,
+                },
+                input = input_rx.recv(), if stdin_open => match input {
+                    Some(data) => session.send_input(&data).await?,
+                    None => stdin_open = false,
+                },
+                _ = resized.recv() => {
+                    if let Some((rows, cols)) = window_size(stdout_fd) {
+                        session.resize(rows, cols).await?;
+                    }
+                }
+            }
+        };
+
+        let _ = session.close().await;
+        Ok(exit_code)
+    }
+
+    async fn write_file_unchecked(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        let client = self.user_client(None);
+        client.write_file(path, content, opts).await
     }
 
     async fn read_file_unchecked(&mut self, path: &str) -> anyhow::Result<Vec<u8>> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
+        let client = self.user_client(None);
         client.read_file(path).await
     }
 
+    async fn read_file_range_unchecked(
+        &mut self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        // The guest agent seeks, and its exec output isn't byte for byte
+        let client = self.user_client(None);
+        client.read_file_range(path, offset, len).await
+    }
+
     async fn remove_file_unchecked(&mut self, path: &str) -> anyhow::Result<()> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
+        let client = self.user_client(None);
         client.remove_file(path).await
     }
 
     async fn mkdir_unchecked(&mut self, path: &str, recursive: bool) -> anyhow::Result<()> {
-        let client = VsockClient::for_firecracker(&self.vsock_path);
+        let client = self.user_client(None);
         client.mkdir(path, recursive).await
     }
+
+    async fn link_unchecked(&mut self, target: &str, link: &str, hard: bool) -> Result<()> {
+        // The guest agent also checks where the link resolves to
+        let client = self.user_client(None);
+        client.link(target, link, hard).await
+    }
+}
+
+/// Kill the Firecracker process serving a sandbox's API socket and clean up
+/// its files, whichever process started it.
+///
+/// Used to remove leftovers without the running handle; the VM is found by
+/// its `--api-sock` argument.
+pub fn kill_vm(name: &str) {
+    let socket = format!("/tmp/agentkernel-{}.sock", name);
+    if let Ok(entries) = std::fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|p| p.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else {
+                continue;
+            };
+            let args: Vec<&[u8]> = cmdline.split(|b| *b == 0).collect();
+            if args
+                .windows(2)
+                .any(|w| w[0] == b"--api-sock" && w[1] == socket.as_bytes())
+            {
+                // SAFETY: kill has no memory-safety preconditions
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                }
+            }
+        }
+    }
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_file(format!("/tmp/agentkernel-{}-vsock.sock", name));
+    let _ = std::fs::remove_file(scratch_image_path(name));
 }
 
 impl Drop for FirecrackerSandbox {
@@ -405,5 +1017,312 @@ impl Drop for FirecrackerSandbox {
         }
         let _ = std::fs::remove_file(&self.socket_path);
         let _ = std::fs::remove_file(&self.vsock_path);
+        let _ = std::fs::remove_file(&self.scratch_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_rootfs() {
+        // A bare path is not a rootfs; only the CLI's marker is
+        assert_eq!(local_rootfs("/srv/images/app.ext4"), None);
+        assert_eq!(
+            local_rootfs("rootfs:/tmp/converted.img"),
+            Some(PathBuf::from("/tmp/converted.img"))
+        );
+        // Image names map to the installed runtimes
+        assert_eq!(local_rootfs("python:3.12-alpine"), None);
+        assert_eq!(local_rootfs("base"), None);
+    }
+
+    #[test]
+    fn test_find_rootfs_local_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let rootfs = dir.path().join("offline.ext4");
+        std::fs::write(&rootfs, b"").unwrap();
+        let image = format!("rootfs:{}", rootfs.display());
+        assert_eq!(FirecrackerSandbox::find_rootfs(&image).unwrap(), rootfs);
+
+        let missing = format!("rootfs:{}", dir.path().join("missing.ext4").display());
+        let err = FirecrackerSandbox::find_rootfs(&missing).unwrap_err();
+        assert!(err.to_string().contains("Rootfs not found"));
+    }
+
+    #[test]
+    fn test_parse_ip_addr() {
+        let output = "2: eth0    inet 172.16.0.2/30 brd 172.16.0.3 scope global eth0\\       valid_lft forever preferred_lft forever\n";
+        assert_eq!(parse_ip_addr(output), Some("172.16.0.2".parse().unwrap()));
+        // Interface up but without an address
+        assert_eq!(parse_ip_addr(""), None);
+    }
+
+    #[test]
+    fn test_check_network_mode() {
+        let mut config = SandboxConfig::default();
+        for mode in [None, Some(NetworkMode::None), Some(NetworkMode::Bridge)] {
+            config.network_mode = mode;
+            assert!(check_network_mode(&config).is_ok());
+        }
+        config.network_mode = Some(NetworkMode::Host);
+        assert!(check_network_mode(&config).is_err());
+        config.network_mode = Some(NetworkMode::Named("ci".to_string()));
+        assert!(check_network_mode(&config).is_err());
+
+        // Nothing to join with the network off
+        config.network = false;
+        assert!(check_network_mode(&config).is_ok());
+    }
+
+    #[test]
+    fn test_check_read_only_root() {
+        let read_only =
+            SandboxConfig::default().with_read_only(true, Some("/workspace".to_string()));
+        assert!(check_read_only_root(&read_only).is_ok());
+
+        let dns = SandboxConfig {
+            dns: vec!["1.1.1.1".to_string()],
+            ..read_only.clone()
+        };
+        let err = check_read_only_root(&dns).unwrap_err().to_string();
+        assert!(err.contains("/etc/resolv.conf"));
+
+        let hosts = SandboxConfig {
+            extra_hosts: vec![("mock.local".to_string(), "10.0.0.5".parse().unwrap())],
+            ..read_only.clone()
+        };
+        let err = check_read_only_root(&hosts).unwrap_err().to_string();
+        assert!(err.contains("/etc/hosts"));
+
+        // A writable root takes both
+        let writable = SandboxConfig {
+            read_only: false,
+            dns: dns.dns,
+            ..hosts
+        };
+        assert!(check_read_only_root(&writable).is_ok());
+    }
+
+    #[test]
+    fn test_check_read_only_root_mounts() {
+        let mount = |target: &str| FileMount {
+            source: "/etc/hostname".to_string(),
+            target: target.to_string(),
+        };
+        let config = SandboxConfig {
+            mounts: vec![mount("/workspace/.env"), mount("/tmp/token")],
+            ..SandboxConfig::default().with_read_only(true, Some("/workspace".to_string()))
+        };
+        assert!(check_read_only_root(&config).is_ok());
+
+        for target in [
+            "/etc/app.conf",
+            "/workspace",
+            "/workspaces/x",
+            "/run/secret",
+        ] {
+            let config = SandboxConfig {
+                mounts: vec![mount(target)],
+                ..config.clone()
+            };
+            let err = check_read_only_root(&config).unwrap_err().to_string();
+            assert!(err.contains(target), "{}", err);
+            assert!(err.contains("/workspace, /tmp"), "{}", err);
+        }
+
+        // The scratch disk sits at the workspace; /run is writable once sized
+        let config = SandboxConfig {
+            mounts: vec![mount("/workspace/.env"), mount("/run/secret")],
+            scratch_mb: Some(64),
+            run_size_mb: Some(8),
+            ..SandboxConfig::default().with_read_only(true, None)
+        };
+        assert!(check_read_only_root(&config).is_ok());
+
+        // Anywhere goes on a writable root
+        let config = SandboxConfig {
+            mounts: vec![mount("/etc/app.conf")],
+            ..Default::default()
+        };
+        assert!(check_read_only_root(&config).is_ok());
+    }
+
+    #[test]
+    fn test_boot_args_default_rw() {
+        let args = boot_args(&SandboxConfig::default());
+        assert!(args.contains("root=/dev/vda rw"));
+        assert!(!args.contains("agentkernel.writable"));
+    }
+
+    #[test]
+    fn test_boot_args_read_only_with_workspace() {
+        let config = SandboxConfig::default().with_read_only(true, Some("/workspace".to_string()));
+        let args = boot_args(&config);
+        assert!(args.contains("root=/dev/vda ro"));
+        assert!(args.contains("agentkernel.writable=/workspace"));
+        assert!(args.ends_with("agentkernel.tmp_size=64m"));
+    }
+
+    #[test]
+    fn test_boot_args_agent_port() {
+        let config = SandboxConfig {
+            agent_port: Some(6000),
+            ..Default::default()
+        };
+        assert!(boot_args(&config).ends_with("agentkernel.agent_port=6000"));
+
+        let config = SandboxConfig {
+            agent_port: Some(crate::vsock::AGENT_PORT),
+            ..Default::default()
+        };
+        assert!(!boot_args(&config).contains("agent_port"));
+    }
+
+    #[test]
+    fn test_boot_args_scratch_disk() {
+        let config = SandboxConfig::default().with_scratch_mb(Some(1024));
+        assert!(boot_args(&config).ends_with("agentkernel.scratch=/workspace"));
+
+        // The scratch disk replaces the tmpfs on a read-only root
+        let config = SandboxConfig::default()
+            .with_read_only(true, Some("/data".to_string()))
+            .with_scratch_mb(Some(1024));
+        let args = boot_args(&config);
+        assert!(args.contains("agentkernel.scratch=/data"));
+        assert!(!args.contains("agentkernel.writable"));
+    }
+
+    #[test]
+    fn test_boot_args_tmpfs_sizes() {
+        let args = boot_args(&SandboxConfig::default());
+        assert!(!args.contains("tmp_size") && !args.contains("run_size"));
+
+        let config = SandboxConfig::default().with_tmpfs(Some(256), Some(16));
+        assert!(
+            boot_args(&config).ends_with(" agentkernel.tmp_size=256m agentkernel.run_size=16m")
+        );
+    }
+
+    #[test]
+    fn test_boot_args_ulimits() {
+        assert!(!boot_args(&SandboxConfig::default()).contains("ulimit"));
+
+        let config = SandboxConfig::default().with_ulimits(crate::permissions::Ulimits {
+            nofile: Some(crate::permissions::UlimitPair::new(1024, 4096)),
+            nproc: Some(crate::permissions::UlimitPair::new(256, 256)),
+            ..Default::default()
+        });
+        assert!(
+            boot_args(&config)
+                .ends_with(" agentkernel.ulimit.nofile=1024:4096 agentkernel.ulimit.nproc=256:256")
+        );
+    }
+
+    #[test]
+    fn test_create_scratch_image() {
+        if Command::new("mkfs.ext4").arg("-V").output().is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scratch").join("box.ext4");
+        create_scratch_image(&path, 16).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 16 * 1024 * 1024);
+        // A second start replaces the old image
+        create_scratch_image(&path, 16).unwrap();
+    }
+
+    #[test]
+    fn test_create_scratch_image_replaces_symlink() {
+        if Command::new("mkfs.ext4").arg("-V").output().is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("victim");
+        std::fs::write(&target, b"keep").unwrap();
+        let path = dir.path().join("box.ext4");
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+
+        create_scratch_image(&path, 16).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"keep");
+        assert!(!std::fs::symlink_metadata(&path).unwrap().is_symlink());
+    }
+
+    #[test]
+    fn test_classify_vm_exit_guest_panic() {
+        let console = "[    0.512] Run /init as init process\n\
+                       [    9.871] Kernel panic - not syncing: Out of memory and no killable processes...\n\
+                       [    9.872] Rebooting in 1 seconds..\n";
+        let reason = classify_vm_exit(Some(0), console);
+        assert!(reason.oom_killed);
+        assert!(
+            reason
+                .error
+                .unwrap()
+                .starts_with("Kernel panic - not syncing")
+        );
+
+        let console = "Kernel panic - not syncing: Attempted to kill init! exitcode=0x00000100\n";
+        let reason = classify_vm_exit(Some(0), console);
+        assert!(!reason.oom_killed);
+        assert!(reason.error.unwrap().contains("Attempted to kill init"));
+    }
+
+    #[test]
+    fn test_classify_vm_exit_vmm_crash() {
+        let reason = classify_vm_exit(Some(1), "Error creating the vmm\n");
+        assert!(!reason.oom_killed);
+        assert_eq!(reason.exit_code, Some(1));
+        assert_eq!(
+            reason.to_string(),
+            "Firecracker VMM exited unexpectedly (exit code 1)"
+        );
+    }
+
+    #[test]
+    fn test_mount_injections() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("credentials");
+        std::fs::write(&source, "[default]\naws_access_key_id = AKIA\n").unwrap();
+        let mut mounts = vec![FileMount {
+            source: source.to_string_lossy().into_owned(),
+            target: "/root/.aws/credentials".to_string(),
+        }];
+
+        let files = mount_injections(&mounts).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].dest, "/root/.aws/credentials");
+        assert!(files[0].content.starts_with(b"[default]"));
+        assert_eq!(MOUNTED_FILE_MODE & 0o222, 0);
+
+        // A missing file is named in the error, which never holds contents
+        mounts[0].source = dir.path().join("missing").to_string_lossy().into_owned();
+        let err = format!("{:#}", mount_injections(&mounts).unwrap_err());
+        assert!(err.contains("missing"), "{}", err);
+        assert!(err.contains("/root/.aws/credentials"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolv_conf_and_hosts_file() {
+        assert_eq!(
+            resolv_conf(&["1.1.1.1".to_string(), "fd00::53".to_string()]),
+            "nameserver 1.1.1.1\nnameserver fd00::53\n"
+        );
+
+        let extra = [(
+            "mock.local".to_string(),
+            "10.0.0.5".parse::<std::net::IpAddr>().unwrap(),
+        )];
+        assert_eq!(
+            hosts_file(None, &extra),
+            "127.0.0.1\tlocalhost\n::1\tlocalhost\n10.0.0.5\tmock.local\n"
+        );
+
+        // The sandbox's hostname resolves to loopback
+        assert_eq!(
+            hosts_file(Some("ci-runner"), &extra),
+            "127.0.0.1\tlocalhost\n::1\tlocalhost\n127.0.1.1\tci-runner\n10.0.0.5\tmock.local\n"
+        );
     }
 }
@@ -12,7 +12,7 @@ use std::path::Path;
 #[cfg(all(target_os = "linux", feature = "hyperlight"))]
 use std::sync::Mutex;
 
-use super::{BackendType, ExecResult, Sandbox, SandboxConfig};
+use super::{BackendType, ExecResult, Sandbox, SandboxConfig, WriteOptions};
 
 /// Compile WAT (WebAssembly Text) to WASM binary
 pub fn compile_wat(wat_source: &str) -> Result<Vec<u8>> {
@@ -110,6 +110,11 @@ impl HyperlightSandbox {
 #[async_trait]
 impl Sandbox for HyperlightSandbox {
     async fn start(&mut self, config: &SandboxConfig) -> Result<()> {
+        config.check_gpus_supported(BackendType::Hyperlight)?;
+        if !config.mounts.is_empty() {
+            bail!("File mounts are not supported on the hyperlight backend");
+        }
+
         // Hyperlight requires a Wasm module to be loaded
         // The SandboxConfig.image field is used as a path to a .wasm or .wat file
 
@@ -191,7 +196,12 @@ impl Sandbox for HyperlightSandbox {
         }
     }
 
-    async fn write_file_unchecked(&mut self, _path: &str, _content: &[u8]) -> anyhow::Result<()> {
+    async fn write_file_unchecked(
+        &mut self,
+        _path: &str,
+        _content: &[u8],
+        _opts: &WriteOptions,
+    ) -> anyhow::Result<()> {
         bail!("File operations not supported for Hyperlight WebAssembly backend")
     }
 
@@ -16,10 +16,14 @@ pub mod docker;
 pub mod firecracker;
 pub mod hyperlight;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 #[cfg(target_os = "macos")]
 pub use apple::AppleSandbox;
@@ -72,6 +76,146 @@ impl std::str::FromStr for BackendType {
     }
 }
 
+/// Most times a sandbox is restarted after exiting on its own
+pub const MAX_RESTARTS: u32 = 5;
+
+/// What to do when a named sandbox exits on its own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave it stopped
+    #[default]
+    Never,
+    /// Restart only after a non-zero exit
+    OnFailure,
+    /// Restart whatever the exit code
+    Always,
+}
+
+impl RestartPolicy {
+    /// `--restart` value for Docker/Podman, capped at [`MAX_RESTARTS`] where
+    /// the runtime supports it. None for `never`.
+    pub fn docker_flag(self) -> Option<String> {
+        match self {
+            RestartPolicy::Never => None,
+            RestartPolicy::OnFailure => Some(format!("--restart=on-failure:{}", MAX_RESTARTS)),
+            RestartPolicy::Always => Some("--restart=always".to_string()),
+        }
+    }
+
+    /// Whether to restart after an exit with `exit_code` (None if unknown),
+    /// having already restarted `restarts` times
+    pub fn should_restart(self, exit_code: Option<i32>, restarts: u32) -> bool {
+        if restarts >= MAX_RESTARTS {
+            return false;
+        }
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit_code != Some(0),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::OnFailure => write!(f, "on-failure"),
+            RestartPolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "never" | "no" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!(
+                "Unknown restart policy '{}'. Valid options: never, on-failure, always",
+                s
+            )),
+        }
+    }
+}
+
+/// A sandbox port published on the host, from `--publish [IP:]HOST:SANDBOX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    /// Host address to bind (default: loopback only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<std::net::IpAddr>,
+    /// Port on the host
+    pub host: u16,
+    /// Port inside the sandbox
+    pub sandbox: u16,
+}
+
+impl PortMapping {
+    /// Host address used when none was given
+    pub const DEFAULT_HOST_IP: std::net::IpAddr =
+        std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    /// The `IP:HOST:SANDBOX` spec for `-p`. Binds loopback unless a host
+    /// address was given, so a sandbox isn't reachable from the network
+    /// by accident.
+    pub fn publish_spec(&self) -> String {
+        match self.host_ip.unwrap_or(Self::DEFAULT_HOST_IP) {
+            std::net::IpAddr::V4(ip) => format!("{}:{}:{}", ip, self.host, self.sandbox),
+            std::net::IpAddr::V6(ip) => format!("[{}]:{}:{}", ip, self.host, self.sandbox),
+        }
+    }
+}
+
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host_ip {
+            Some(_) => f.write_str(&self.publish_spec()),
+            None => write!(f, "{}:{}", self.host, self.sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for PortMapping {
+    type Err = String;
+
+    /// Parse `[IP:]HOST:SANDBOX`, or a single port used for both. IPv6
+    /// addresses go in brackets, e.g. `[::]:8080:80`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid port mapping '{}'. Use [IP:]HOST:SANDBOX, e.g. 3000:3000",
+                s
+            )
+        };
+        let port = |p: &str| match p.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(invalid()),
+        };
+        // The ports never contain ':', so split from the right
+        let (host_ip, host, sandbox) = match s.rsplitn(3, ':').collect::<Vec<_>>()[..] {
+            [sandbox, host, ip] => {
+                let ip = ip
+                    .strip_prefix('[')
+                    .and_then(|ip| ip.strip_suffix(']'))
+                    .unwrap_or(ip);
+                (Some(ip.parse().map_err(|_| invalid())?), host, sandbox)
+            }
+            [sandbox, host] => (None, host, sandbox),
+            _ => (None, s, s),
+        };
+        Ok(Self {
+            host_ip,
+            host: port(host)?,
+            sandbox: port(sandbox)?,
+        })
+    }
+}
+
 /// File to inject into sandbox at startup
 #[derive(Debug, Clone)]
 pub struct FileInjection {
@@ -81,6 +225,9 @@ pub struct FileInjection {
     pub dest: String,
 }
 
+/// Hostname used when a sandbox doesn't set one
+pub const DEFAULT_HOSTNAME: &str = "agentkernel";
+
 /// Configuration for starting a sandbox
 #[derive(Debug, Clone)]
 pub struct SandboxConfig {
@@ -98,12 +245,55 @@ pub struct SandboxConfig {
     pub env: Vec<(String, String)>,
     /// Network access enabled
     pub network: bool,
+    /// Network to join when `network` is on (container backends; None = the
+    /// runtime default)
+    pub network_mode: Option<crate::permissions::NetworkMode>,
     /// Make root filesystem read-only
     pub read_only: bool,
     /// Mount home directory (read-only)
     pub mount_home: bool,
+    /// Writable path (tmpfs) kept available when `read_only` is set
+    pub writable_path: Option<String>,
     /// Files to inject after sandbox starts
     pub files: Vec<FileInjection>,
+    /// Sandbox is tied to a single command and may be reaped if left behind
+    pub ephemeral: bool,
+    /// DNS servers (IP addresses); empty uses the backend default
+    pub dns: Vec<String>,
+    /// Extra /etc/hosts entries
+    pub extra_hosts: Vec<(String, IpAddr)>,
+    /// User-defined labels (applied as container labels where supported)
+    pub labels: HashMap<String, String>,
+    /// User to run as (`uid[:gid]` or `name[:group]`); None uses the image default
+    pub user: Option<String>,
+    /// Size in MB of a writable scratch space at the workspace
+    pub scratch_mb: Option<u64>,
+    /// Size in MB of a tmpfs at /tmp (None = no bound)
+    pub tmp_size_mb: Option<u64>,
+    /// Size in MB of a tmpfs at /run (None = no bound)
+    pub run_size_mb: Option<u64>,
+    /// Resource limits for processes in the sandbox
+    pub ulimits: crate::permissions::Ulimits,
+    /// Vsock port of the guest agent (VM backends; None = [`crate::vsock::agent_port`])
+    pub agent_port: Option<u32>,
+    /// Vsock CID for the guest (VM backends; None = backend picks one)
+    pub vsock_cid: Option<u32>,
+    /// Outbound destinations allowed when `network` is on (None = unrestricted)
+    pub egress: Option<crate::egress::EgressAllowlist>,
+    /// Restart policy for when the sandbox exits on its own
+    pub restart: RestartPolicy,
+    /// Run an init as PID 1 that reaps orphaned processes (container
+    /// backends; None = on for Docker unless `ephemeral`)
+    pub init: Option<bool>,
+    /// Sandbox ports published on the host (container backends)
+    pub ports: Vec<PortMapping>,
+    /// Hostname inside the sandbox (None = [`DEFAULT_HOSTNAME`] for
+    /// long-running sandboxes, the runtime's default for one-shot containers)
+    pub hostname: Option<String>,
+    /// GPUs passed to the sandbox (container backends; None = no GPU access)
+    pub gpus: Option<crate::permissions::Gpus>,
+    /// Host files bound read-only, with `~` already expanded
+    pub mounts: Vec<crate::permissions::FileMount>,
 }
 
 impl Default for SandboxConfig {
@@ -116,9 +306,30 @@ impl Default for SandboxConfig {
             work_dir: None,
             env: Vec::new(),
             network: true,
+            network_mode: None,
             read_only: false,
             mount_home: false,
+            writable_path: None,
             files: Vec::new(),
+            ephemeral: false,
+            dns: Vec::new(),
+            extra_hosts: Vec::new(),
+            labels: HashMap::new(),
+            user: None,
+            scratch_mb: None,
+            tmp_size_mb: None,
+            run_size_mb: None,
+            ulimits: Default::default(),
+            agent_port: None,
+            vsock_cid: None,
+            egress: None,
+            restart: RestartPolicy::Never,
+            init: None,
+            hostname: None,
+            max_output_bytes: None,
+            gpus: None,
+            mounts: Vec::new(),
+            ports: Vec::new(),
         }
     }
 }
@@ -152,6 +363,13 @@ impl SandboxConfig {
         self
     }
 
+    /// Make the root filesystem read-only, keeping `writable_path` writable
+    pub fn with_read_only(mut self, read_only: bool, writable_path: Option<String>) -> Self {
+        self.read_only = read_only;
+        self.writable_path = writable_path;
+        self
+    }
+
     /// Set environment variables
     pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
         self.env = env;
@@ -163,6 +381,749 @@ impl SandboxConfig {
         self.files = files;
         self
     }
+
+    /// Mark the sandbox as ephemeral (eligible for leftover cleanup)
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    /// Set DNS servers and extra /etc/hosts entries
+    pub fn with_dns(mut self, dns: Vec<String>, extra_hosts: Vec<(String, IpAddr)>) -> Self {
+        self.dns = dns;
+        self.extra_hosts = extra_hosts;
+        self
+    }
+
+    /// Set user-defined labels
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Set the user to run as
+    pub fn with_user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+    /// Set the size of the writable scratch space
+    pub fn with_scratch_mb(mut self, scratch_mb: Option<u64>) -> Self {
+        self.scratch_mb = scratch_mb;
+        self
+    }
+
+    /// Bound /tmp and /run with size-limited tmpfs mounts
+    pub fn with_tmpfs(mut self, tmp_size_mb: Option<u64>, run_size_mb: Option<u64>) -> Self {
+        self.tmp_size_mb = tmp_size_mb;
+        self.run_size_mb = run_size_mb;
+        self
+    }
+
+    /// Set resource limits for processes in the sandbox
+    pub fn with_ulimits(mut self, ulimits: crate::permissions::Ulimits) -> Self {
+        self.ulimits = ulimits;
+        self
+    }
+
+    /// Restrict outbound traffic to an allowlist
+    pub fn with_egress(mut self, egress: Option<crate::egress::EgressAllowlist>) -> Self {
+        self.egress = egress;
+        self
+    }
+
+    /// Set the restart policy
+    pub fn with_restart(mut self, restart: RestartPolicy) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Turn the init process on or off (None = the default for `ephemeral`)
+    pub fn with_init(mut self, init: Option<bool>) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// Set the hostname inside the sandbox
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Fail if GPUs were asked for on a backend that can't pass them through
+    pub fn check_gpus_supported(&self, backend: BackendType) -> Result<()> {
+        if self.gpus.is_some() && !matches!(backend, BackendType::Docker | BackendType::Podman) {
+            anyhow::bail!(
+                "GPU access is not supported on the {} backend. Use docker or podman, \
+                 or remove `gpus` from [security].",
+                backend
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Files a background command started by `run --detach` leaves in the sandbox
+#[derive(Debug, Clone, Copy)]
+pub struct DetachedPaths<'a> {
+    /// Standard output
+    pub log: &'a str,
+    /// Standard error
+    pub err: &'a str,
+    /// Exit code, written when the command finishes
+    pub exit: &'a str,
+    /// Process id, so a running command can be told from a missing one
+    pub pid: &'a str,
+}
+
+impl DetachedPaths<'static> {
+    pub const DEFAULT: Self = Self {
+        log: "/tmp/agentkernel-run.log",
+        err: "/tmp/agentkernel-run.err",
+        exit: "/tmp/agentkernel-run.exit",
+        pid: "/tmp/agentkernel-run.pid",
+    };
+}
+
+/// Directory holding the files of commands started with `exec --detach`
+pub const BACKGROUND_EXEC_DIR: &str = "/tmp/agentkernel-exec";
+
+/// Files of one `exec --detach` command, named by its id
+#[derive(Debug, Clone)]
+pub struct BackgroundExecPaths {
+    pub log: String,
+    pub err: String,
+    pub exit: String,
+    pub pid: String,
+    /// The command line, shown by `exec --list`
+    pub cmd: String,
+}
+
+impl BackgroundExecPaths {
+    pub fn new(dir: &str, id: &str) -> Self {
+        let path = |ext: &str| format!("{}/{}.{}", dir, id, ext);
+        Self {
+            log: path("log"),
+            err: path("err"),
+            exit: path("exit"),
+            pid: path("pid"),
+            cmd: path("cmd"),
+        }
+    }
+
+    pub fn detached(&self) -> DetachedPaths<'_> {
+        DetachedPaths {
+            log: &self.log,
+            err: &self.err,
+            exit: &self.exit,
+            pid: &self.pid,
+        }
+    }
+}
+
+/// Check a background exec id before it's used in a path
+pub fn validate_background_id(id: &str) -> Result<()> {
+    if id.is_empty() || id.len() > 64 || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        anyhow::bail!("Invalid background command id '{}'", id);
+    }
+    Ok(())
+}
+
+/// State of an `exec --detach` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundState {
+    Running,
+    Exited(i32),
+    /// Killed before it could record an exit code
+    Lost,
+}
+
+impl fmt::Display for BackgroundState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackgroundState::Running => write!(f, "running"),
+            BackgroundState::Exited(code) => write!(f, "exited ({})", code),
+            BackgroundState::Lost => write!(f, "lost"),
+        }
+    }
+}
+
+/// An `exec --detach` command, as listed by `background_list_command`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackgroundExec {
+    pub id: String,
+    pub state: BackgroundState,
+    pub command: String,
+}
+
+/// Command that prints a tab-separated `<id> <state> <command>` line per
+/// background exec in `dir`, where state is as in `wait_status_command`
+pub fn background_list_command(dir: &str) -> Vec<String> {
+    let script = format!(
+        "d='{dir}'; for p in \"$d\"/*.pid; do [ -f \"$p\" ] || continue; \
+         id=$(basename \"$p\" .pid); \
+         if [ -f \"$d/$id.exit\" ]; then s=\"exited $(cat \"$d/$id.exit\")\"; \
+         elif kill -0 \"$(cat \"$p\")\" 2>/dev/null; then s=running; else s=lost; fi; \
+         printf '%s\\t%s\\t%s\\n' \"$id\" \"$s\" \"$(cat \"$d/$id.cmd\" 2>/dev/null)\"; done",
+        dir = dir
+    );
+    vec!["sh".to_string(), "-c".to_string(), script]
+}
+
+/// Parse `background_list_command` output
+pub fn parse_background_list(output: &str) -> Result<Vec<BackgroundExec>> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let (id, state) = match (fields.next(), fields.next()) {
+                (Some(id), Some(state)) => (id, state),
+                _ => anyhow::bail!("Unexpected background command entry: {}", line),
+            };
+            let state = match state {
+                "running" => BackgroundState::Running,
+                "lost" => BackgroundState::Lost,
+                other => match other.strip_prefix("exited ").map(|c| c.trim().parse()) {
+                    Some(Ok(code)) => BackgroundState::Exited(code),
+                    // An empty or partial exit file reads as still finishing
+                    _ => BackgroundState::Running,
+                },
+            };
+            Ok(BackgroundExec {
+                id: id.to_string(),
+                state,
+                command: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Command that sends SIGTERM to everything a background exec started.
+///
+/// The command's process group is signalled, which also reaches processes
+/// that detached from their parent, then anything still descended from it
+/// (all there is in a sandbox without `setsid`). The wrapper shell itself is
+/// left alone, so it still records the exit code (143). Processes are found
+/// through /proc, which works without `ps`.
+pub fn background_kill_command(paths: &BackgroundExecPaths) -> Vec<String> {
+    let script = format!(
+        "[ -f '{exit}' ] && exit 0; \
+         kids() {{ for s in /proc/[0-9]*/stat; do \
+         read -r c _ _ pp _ 2>/dev/null < \"$s\" && [ \"$pp\" = \"$1\" ] && echo \"$c\"; done; }}; \
+         tree() {{ for k in $(kids \"$1\"); do echo \"$k\"; tree \"$k\"; done; }}; \
+         pid=$(cat '{pid}'); for k in $(kids \"$pid\"); do kill -- \"-$k\" 2>/dev/null; done; \
+         pids=$(tree \"$pid\"); [ -z \"$pids\" ] || kill $pids 2>/dev/null; true",
+        exit = paths.exit,
+        pid = paths.pid
+    );
+    vec!["sh".to_string(), "-c".to_string(), script]
+}
+
+/// Which output a captured log line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// A line of process output captured from a sandbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLine {
+    /// When the line was written (RFC 3339), if the backend records it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+impl LogLine {
+    /// Lines of a `detached_log_command` result, stdout first
+    pub fn from_detached(result: &ExecResult) -> Vec<Self> {
+        let mut lines = Self::from_output(&result.stdout, LogStream::Stdout);
+        lines.extend(Self::from_output(&result.stderr, LogStream::Stderr));
+        lines
+    }
+
+    /// Split captured output into lines from one stream
+    pub fn from_output(output: &str, stream: LogStream) -> Vec<Self> {
+        output
+            .lines()
+            .map(|line| LogLine {
+                timestamp: None,
+                stream,
+                line: line.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Console lines a VM sandbox keeps in memory
+pub const CONSOLE_BUFFER_LINES: usize = 1000;
+
+/// Ring buffer of the most recent output lines, shared with reader threads
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer {
+    lines: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<LogLine>>>,
+}
+
+impl LogBuffer {
+    /// Append a line, dropping the oldest once the buffer is full
+    pub fn push(&self, stream: LogStream, line: String) {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == CONSOLE_BUFFER_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(LogLine {
+            timestamp: Some(chrono::Utc::now().to_rfc3339()),
+            stream,
+            line,
+        });
+    }
+
+    /// The buffered lines, oldest first
+    pub fn lines(&self) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+
+    /// Read `reader` line by line into the buffer on a background thread,
+    /// until it reaches end of file
+    pub fn capture<R: std::io::Read + Send + 'static>(
+        &self,
+        reader: R,
+        stream: LogStream,
+    ) -> std::thread::JoinHandle<()> {
+        use std::io::BufRead;
+
+        let buffer = self.clone();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(reader).lines() {
+                match line {
+                    Ok(line) => buffer.push(stream, line),
+                    Err(_) => break,
+                }
+            }
+        })
+    }
+}
+
+/// Command that prints the background command's stdout log on stdout and its
+/// stderr log on stderr (the last `tail` lines of each), or nothing if there
+/// isn't one
+pub fn detached_log_command(paths: &DetachedPaths, tail: Option<usize>) -> Vec<String> {
+    let read = match tail {
+        Some(n) => format!("tail -n {}", n),
+        None => "cat".to_string(),
+    };
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        format!(
+            "{read} '{}' 2>/dev/null; {read} '{}' >&2 2>/dev/null; true",
+            paths.log, paths.err
+        ),
+    ]
+}
+
+/// How often `wait` checks whether the background command has finished
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Command that reports the background command's state as `exited <code>`,
+/// `running`, `lost` (it died without recording a code) or `none`
+pub fn wait_status_command(paths: &DetachedPaths) -> Vec<String> {
+    let script = format!(
+        "if [ -f '{exit}' ]; then echo \"exited $(cat '{exit}')\"; \
+         elif [ -f '{pid}' ] && kill -0 \"$(cat '{pid}')\" 2>/dev/null; then echo running; \
+         elif [ -f '{pid}' ]; then echo lost; else echo none; fi",
+        exit = paths.exit,
+        pid = paths.pid
+    );
+    vec!["sh".to_string(), "-c".to_string(), script]
+}
+
+/// Parse `wait_status_command` output: the exit code, or None while running
+pub fn parse_wait_status(output: &str) -> Result<Option<i32>> {
+    let output = output.trim();
+    if let Some(code) = output.strip_prefix("exited ") {
+        return code
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("Invalid exit status '{}'", code));
+    }
+    match output {
+        "running" => Ok(None),
+        "lost" => {
+            anyhow::bail!("The background command was killed before it could record an exit code")
+        }
+        "none" => {
+            anyhow::bail!("No background command was started in this sandbox (use run --detach)")
+        }
+        other => anyhow::bail!("Unexpected wait status: {}", other),
+    }
+}
+
+/// Exit code of a command killed for exceeding its timeout (as with `timeout(1)`)
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Error text for a command killed after `limit`
+pub fn timeout_message(limit: Duration) -> String {
+    format!(
+        "Command timed out after {}s and was killed",
+        limit.as_secs_f64()
+    )
+}
+
+/// Output the HTTP API and MCP server keep per stream unless configured
+/// otherwise, so stdout and stderr together fit in one guest agent message
+/// ([`crate::vsock::MAX_MESSAGE_SIZE`])
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Read a pipe to the end, keeping at most `limit` bytes.
+///
+/// Anything past the limit is read and dropped, so the command never blocks
+/// on a full pipe. Returns the kept bytes and whether any were dropped.
+pub async fn read_capped<R>(mut reader: R, limit: usize) -> (Vec<u8>, bool)
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let room = limit.saturating_sub(kept.len());
+        truncated |= n > room;
+        kept.extend_from_slice(&chunk[..n.min(room)]);
+    }
+    (kept, truncated)
+}
+
+/// Per-command execution options
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Environment variables as KEY=VALUE pairs
+    pub env: Vec<String>,
+    /// Working directory for the command (None = the sandbox default)
+    pub workdir: Option<String>,
+    /// User to run the command as, `uid[:gid]` or `name[:group]`
+    /// (None = the sandbox's user)
+    pub user: Option<String>,
+    /// Kill the command if it runs longer than this (None = backend default)
+    pub timeout: Option<Duration>,
+    /// Keep at most this many bytes of stdout and of stderr (None = all of it)
+    pub max_output: Option<usize>,
+    /// Capture stdout and stderr as one stream in the order they were
+    /// written (like `2>&1`), returned as stdout
+    pub combined: bool,
+}
+
+impl ExecOptions {
+    /// Options with just environment variables
+    pub fn with_env(env: &[String]) -> Self {
+        Self {
+            env: env.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the working directory
+    pub fn workdir(mut self, workdir: Option<&str>) -> Self {
+        self.workdir = workdir.map(str::to_string);
+        self
+    }
+
+    /// Set the user the command runs as
+    pub fn user(mut self, user: Option<&str>) -> Self {
+        self.user = user.map(str::to_string);
+        self
+    }
+
+    /// Set how long the command may run before it is killed
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how much of each output stream is kept
+    pub fn max_output(mut self, max_output: Option<usize>) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Capture stdout and stderr as one ordered stream
+    pub fn combined(mut self, combined: bool) -> Self {
+        self.combined = combined;
+        self
+    }
+
+    /// `cmd` wrapped so its stderr goes to stdout, for backends that run
+    /// commands through `exec` and can't merge the streams themselves
+    pub fn combined_command<'a>(&self, cmd: &[&'a str]) -> Vec<&'a str> {
+        if !self.combined {
+            return cmd.to_vec();
+        }
+        let mut wrapped = vec!["sh", "-c", "exec \"$@\" 2>&1", "sh"];
+        wrapped.extend_from_slice(cmd);
+        wrapped
+    }
+}
+
+/// Options for writing a file into a sandbox
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Append to the file instead of replacing it
+    pub append: bool,
+    /// Permission bits, applied when the file is created or overwritten
+    /// (appending keeps an existing file's permissions)
+    pub mode: Option<u32>,
+}
+
+impl WriteOptions {
+    /// Append instead of truncating
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Set the file's permission bits
+    pub fn mode(mut self, mode: Option<u32>) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+/// Line that ends a script read from stdin; anything after it becomes the
+/// script's own stdin
+pub const SCRIPT_INPUT_SEPARATOR: &str = "__END__";
+
+/// Read a script for `run --stdin` / `exec --stdin`.
+///
+/// The script runs up to a [`SCRIPT_INPUT_SEPARATOR`] line, or the end of
+/// the input. Returns the script, and the text after the separator if there
+/// is one.
+pub fn read_script(mut reader: impl std::io::BufRead) -> Result<(String, Option<String>)> {
+    let read_error = |e: std::io::Error| anyhow::anyhow!("Failed to read script from stdin: {}", e);
+    let mut script = String::new();
+    let mut input = None;
+    let mut line = String::new();
+    while reader.read_line(&mut line).map_err(read_error)? > 0 {
+        if line.trim_end_matches(['\n', '\r']) == SCRIPT_INPUT_SEPARATOR {
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).map_err(read_error)?;
+            input = Some(rest);
+            break;
+        }
+        script.push_str(&line);
+        line.clear();
+    }
+    if script.trim().is_empty() {
+        anyhow::bail!("No script on stdin");
+    }
+    Ok((script, input))
+}
+
+/// Command that runs `script` with `sh -c`, passing `args` as `$1`, `$2`, ...
+///
+/// `input` is fed to the script's stdin through a here-document, so it
+/// reaches the command on every backend.
+pub fn script_command(script: &str, input: Option<&str>, args: &[String]) -> Result<Vec<String>> {
+    let script = match input {
+        Some(input) => {
+            if input.contains('\0') {
+                anyhow::bail!(
+                    "The input after {} can't contain NUL bytes",
+                    SCRIPT_INPUT_SEPARATOR
+                );
+            }
+            let delimiter = format!("AGENTKERNEL_INPUT_{}", uuid::Uuid::new_v4().simple());
+            let newline = if input.is_empty() || input.ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            format!(
+                "{{\n{}\n}} <<'{}'\n{}{}{}\n",
+                script, delimiter, input, newline, delimiter
+            )
+        }
+        None => script.to_string(),
+    };
+    let mut cmd = vec!["sh".to_string(), "-c".to_string(), script, "sh".to_string()];
+    cmd.extend(args.iter().cloned());
+    Ok(cmd)
+}
+
+/// Render a command line to show or paste into a shell.
+///
+/// Arguments are quoted where needed. Environment values (`-e KEY=value`,
+/// `--env KEY=value` or `--env=KEY=value`) are replaced with `***`, since
+/// there's no telling from a name which ones are secret.
+pub fn display_command(program: &str, args: &[String]) -> String {
+    let mut words = vec![shell_word(program)];
+    let mut env_next = false;
+    for arg in args {
+        let word = match arg.strip_prefix("--env=") {
+            Some(var) => format!("--env={}", redact_env(var)),
+            None if env_next => redact_env(arg),
+            None => arg.clone(),
+        };
+        words.push(shell_word(&word));
+        env_next = arg == "-e" || arg == "--env";
+    }
+    words.join(" ")
+}
+
+/// `KEY=value` with the value hidden; a bare `KEY` (passed through from the
+/// caller's environment) carries no value to hide
+fn redact_env(var: &str) -> String {
+    match var.split_once('=') {
+        Some((key, _)) => format!("{}=***", key),
+        None => var.to_string(),
+    }
+}
+
+/// Quote a word for a POSIX shell, unless it's plain enough not to need it
+fn shell_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./:=,@%+".contains(&b));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Shells tried, in order, when `attach` isn't given one
+pub const SHELL_FALLBACKS: &[&str] = &["/bin/bash", "/bin/sh", "/bin/ash"];
+
+/// Check a shell given with `--shell` or `[sandbox] shell`
+pub fn validate_shell(shell: &str) -> Result<()> {
+    if !shell.starts_with('/') || shell.chars().any(char::is_whitespace) {
+        anyhow::bail!(
+            "Shell must be an absolute path like /bin/bash, got: {}",
+            shell
+        );
+    }
+    Ok(())
+}
+
+/// The first of `candidates` that runs in the sandbox
+pub async fn detect_shell(sandbox: &mut dyn Sandbox, candidates: &[&str]) -> Result<String> {
+    for shell in candidates {
+        // A missing shell fails to exec, or exits 126/127 from the runtime
+        if matches!(sandbox.exec(&[shell, "-c", "exit 0"]).await, Ok(r) if r.exit_code == 0) {
+            return Ok(shell.to_string());
+        }
+    }
+    anyhow::bail!(
+        "No shell found in the sandbox (tried {}). Set one with --shell or [sandbox] shell",
+        candidates.join(", ")
+    )
+}
+
+/// Where an appended upload is staged before being added to `path`
+pub fn append_staging_path(path: &str) -> String {
+    format!("{}.agentkernel-append-{}", path, uuid::Uuid::new_v4())
+}
+
+/// Command that appends a staged upload to `path`, or moves it into place
+/// (keeping the upload's mode) when `path` doesn't exist yet.
+///
+/// For backends whose copy command can only replace files.
+pub fn append_command(staged: &str, path: &str) -> Vec<String> {
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "if [ -e \"$2\" ]; then cat \"$1\" >> \"$2\"; rc=$?; rm -f \"$1\"; exit $rc; fi; mv \"$1\" \"$2\""
+            .to_string(),
+        "sh".to_string(),
+        staged.to_string(),
+        path.to_string(),
+    ]
+}
+
+/// Exit code of [`file_size_command`] when the file doesn't exist
+const FILE_MISSING_EXIT_CODE: i32 = 44;
+
+/// Command that prints the size in bytes of the file at `path`, exiting
+/// with [`FILE_MISSING_EXIT_CODE`] if there is no such file
+pub fn file_size_command(path: &str) -> Vec<String> {
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        format!(
+            "[ -e \"$1\" ] || exit {}; exec stat -L -c %s -- \"$1\"",
+            FILE_MISSING_EXIT_CODE
+        ),
+        "sh".to_string(),
+        path.to_string(),
+    ]
+}
+
+/// Command that prints at most `len` bytes of `path`, starting at `offset`.
+///
+/// For backends that read file ranges through exec.
+pub fn read_range_command(path: &str, offset: u64, len: u64) -> Vec<String> {
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "tail -c +\"$1\" -- \"$2\" | head -c \"$3\"".to_string(),
+        "sh".to_string(),
+        (offset + 1).to_string(),
+        path.to_string(),
+        len.to_string(),
+    ]
+}
+
+/// Parse an octal permission string such as "755" or "0640"
+pub fn parse_file_mode(mode: &str) -> Result<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    match u32::from_str_radix(digits, 8) {
+        Ok(bits) if !digits.is_empty() && bits <= 0o7777 => Ok(bits),
+        _ => anyhow::bail!(
+            "Invalid file mode '{}': expected octal like 644 or 0755",
+            mode
+        ),
+    }
+}
+
+/// Bytes of disk allocated to a file on the host.
+///
+/// Sparse disk images count only the blocks written, not their full size.
+pub fn file_disk_usage(path: &std::path::Path) -> Result<u64> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(metadata.blocks() * 512)
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(metadata.len())
+    }
+}
+
+/// Wrap a command so it runs from `workdir`, for backends without a native option
+pub fn in_workdir(cmd: &[&str], workdir: &str) -> Vec<String> {
+    let mut wrapped = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        format!("cd '{}' && exec \"$@\"", workdir.replace('\'', "'\\''")),
+        "sh".to_string(),
+    ];
+    wrapped.extend(cmd.iter().map(|s| s.to_string()));
+    wrapped
 }
 
 /// Result of executing a command in a sandbox
@@ -174,6 +1135,10 @@ pub struct ExecResult {
     pub stdout: String,
     /// Standard error
     pub stderr: String,
+    /// Resource usage, when the backend can report it
+    pub rusage: Option<crate::vsock::ResourceUsage>,
+    /// Output went over the limit and only the start of it was kept
+    pub truncated: bool,
 }
 
 impl ExecResult {
@@ -183,35 +1148,198 @@ impl ExecResult {
             exit_code: 0,
             stdout,
             stderr: String::new(),
+            rusage: None,
+            truncated: false,
         }
     }
 
-    /// Create a failed result
-    pub fn failure(exit_code: i32, stderr: String) -> Self {
-        Self {
-            exit_code,
-            stdout: String::new(),
-            stderr,
+    /// Create a failed result
+    pub fn failure(exit_code: i32, stderr: String) -> Self {
+        Self {
+            exit_code,
+            stdout: String::new(),
+            stderr,
+            rusage: None,
+            truncated: false,
+        }
+    }
+
+    /// Check if the command succeeded
+    pub fn is_success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Get combined output (stdout + stderr)
+    pub fn output(&self) -> String {
+        if self.stderr.is_empty() {
+            self.stdout.clone()
+        } else if self.stdout.is_empty() {
+            self.stderr.clone()
+        } else {
+            format!("{}\n{}", self.stdout, self.stderr)
+        }
+    }
+}
+
+/// Result of a command with its output kept as raw bytes
+#[derive(Debug, Clone, Default)]
+pub struct ExecBytes {
+    /// Exit code (0 = success)
+    pub exit_code: i32,
+    /// Standard output, byte for byte
+    pub stdout: Vec<u8>,
+    /// Standard error, byte for byte
+    pub stderr: Vec<u8>,
+    /// Resource usage, when the backend can report it
+    pub rusage: Option<crate::vsock::ResourceUsage>,
+    /// Output went over the limit and only the start of it was kept
+    pub truncated: bool,
+}
+
+impl ExecBytes {
+    /// Combined output (stdout + stderr), joined like `ExecResult::output`
+    pub fn output(&self) -> Vec<u8> {
+        if self.stderr.is_empty() {
+            self.stdout.clone()
+        } else if self.stdout.is_empty() {
+            self.stderr.clone()
+        } else {
+            [self.stdout.as_slice(), b"\n", self.stderr.as_slice()].concat()
+        }
+    }
+
+    /// Decode the output, replacing invalid UTF-8
+    pub fn into_lossy(self) -> ExecResult {
+        ExecResult {
+            exit_code: self.exit_code,
+            stdout: String::from_utf8_lossy(&self.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&self.stderr).into_owned(),
+            rusage: self.rusage,
+            truncated: self.truncated,
+        }
+    }
+}
+
+impl From<ExecResult> for ExecBytes {
+    fn from(result: ExecResult) -> Self {
+        Self {
+            exit_code: result.exit_code,
+            stdout: result.stdout.into_bytes(),
+            stderr: result.stderr.into_bytes(),
+            rusage: result.rusage,
+            truncated: result.truncated,
+        }
+    }
+}
+
+/// Error shown when a sandbox was killed for exceeding its memory limit
+pub const OOM_MESSAGE: &str = "sandbox killed: out of memory, increase memory_mb";
+
+/// Why a sandbox stopped without being asked to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitReason {
+    /// Exit code of the container's main process or the VMM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Killed by the kernel OOM killer
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// Runtime error or guest panic message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the sandbox exited (RFC 3339), if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.oom_killed {
+            write!(f, "{}", OOM_MESSAGE)?;
+        } else if let Some(ref error) = self.error {
+            write!(f, "{}", error)?;
+        } else {
+            write!(f, "exited")?;
+        }
+        if let Some(code) = self.exit_code {
+            write!(f, " (exit code {})", code)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned (inside `anyhow::Error`) by exec calls when the sandbox's container
+/// no longer exists, e.g. after a `docker rm` outside agentkernel.
+///
+/// The manager downcasts to it to drop the sandbox from its running set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxGone {
+    /// What the runtime reported
+    pub detail: String,
+}
+
+impl fmt::Display for SandboxGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sandbox no longer exists: {}", self.detail)
+    }
+}
+
+impl std::error::Error for SandboxGone {}
+
+/// Returned (inside `anyhow::Error`) when the backend asked for, or any
+/// backend at all, isn't usable on this host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendUnavailable {
+    /// The backend that was asked for isn't available
+    Requested(BackendType),
+    /// Auto-detection found nothing
+    NoneFound,
+}
+
+impl fmt::Display for BackendUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendUnavailable::Requested(backend) => {
+                write!(f, "Backend '{}' is not available on this system", backend)
+            }
+            BackendUnavailable::NoneFound => write!(
+                f,
+                "No sandbox backend available. Need one of: KVM (Linux), Apple containers \
+                 (macOS 26+), or Docker/Podman."
+            ),
         }
     }
+}
 
-    /// Check if the command succeeded
-    pub fn is_success(&self) -> bool {
-        self.exit_code == 0
+impl std::error::Error for BackendUnavailable {}
+
+/// Returned (inside `anyhow::Error`) by the one-shot ephemeral run on a
+/// backend whose sandboxes go through create, start, exec and remove instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EphemeralUnsupported(pub BackendType);
+
+impl fmt::Display for EphemeralUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Ephemeral mode not supported on {}", self.0)
     }
+}
 
-    /// Get combined output (stdout + stderr)
-    pub fn output(&self) -> String {
-        if self.stderr.is_empty() {
-            self.stdout.clone()
-        } else if self.stdout.is_empty() {
-            self.stderr.clone()
-        } else {
-            format!("{}\n{}", self.stdout, self.stderr)
-        }
+impl std::error::Error for EphemeralUnsupported {}
+
+/// Returned (inside `anyhow::Error`) when a file in the sandbox doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNotFound {
+    pub path: String,
+}
+
+impl fmt::Display for FileNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No such file in the sandbox: {}", self.path)
     }
 }
 
+impl std::error::Error for FileNotFound {}
+
 /// Unified sandbox interface for all backends
 ///
 /// Each backend implements this trait to provide a consistent API for:
@@ -243,6 +1371,86 @@ pub trait Sandbox: Send + Sync {
         self.exec(cmd).await
     }
 
+    /// Execute a command with per-command options
+    ///
+    /// The default implementation changes directory in a wrapper shell and
+    /// can't switch users.
+    async fn exec_with_options(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecResult> {
+        if opts.user.is_some() {
+            anyhow::bail!(
+                "The {} backend doesn't support running commands as another user",
+                self.backend_type()
+            );
+        }
+        match opts.workdir {
+            Some(ref workdir) => {
+                let wrapped = in_workdir(cmd, workdir);
+                let wrapped_refs: Vec<&str> = wrapped.iter().map(|s| s.as_str()).collect();
+                self.exec_with_env(&wrapped_refs, &opts.env).await
+            }
+            None => self.exec_with_env(cmd, &opts.env).await,
+        }
+    }
+
+    /// Execute a command and keep its output as raw bytes.
+    ///
+    /// The default implementation goes through `exec_with_options`, so
+    /// output that isn't valid UTF-8 has already been replaced. Backends
+    /// that read the command's output directly override it.
+    async fn exec_bytes(&mut self, cmd: &[&str], opts: &ExecOptions) -> Result<ExecBytes> {
+        Ok(self.exec_with_options(cmd, opts).await?.into())
+    }
+
+    /// Exit code of the background command started by `run --detach`, or
+    /// None while it's still running
+    async fn detached_status(&mut self) -> Result<Option<i32>> {
+        let cmd = wait_status_command(&DetachedPaths::DEFAULT);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = self.exec(&cmd).await?;
+        if result.exit_code != 0 {
+            anyhow::bail!(
+                "Failed to check background command: {}",
+                result.output().trim()
+            );
+        }
+        parse_wait_status(&result.stdout)
+    }
+
+    /// Block until the background command exits and return its exit code.
+    ///
+    /// Fails if `timeout` passes first. The sandbox's own main process is a
+    /// keep-alive, so this waits on the detached command rather than on
+    /// `docker wait` or the VM.
+    async fn wait(&mut self, timeout: Option<Duration>) -> Result<i32> {
+        let started = Instant::now();
+        loop {
+            if let Some(code) = self.detached_status().await? {
+                return Ok(code);
+            }
+            if let Some(timeout) = timeout
+                && started.elapsed() >= timeout
+            {
+                anyhow::bail!(
+                    "Timed out after {}s waiting for the background command",
+                    timeout.as_secs()
+                );
+            }
+            tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Output captured from processes in the sandbox, oldest first.
+    ///
+    /// By default this is the log of the command started by `run --detach`,
+    /// its stdout lines before its stderr lines. With `tail`, at least the
+    /// last `tail` lines are included; callers trim the rest.
+    async fn logs(&mut self, tail: Option<usize>) -> Result<Vec<LogLine>> {
+        let cmd = detached_log_command(&DetachedPaths::DEFAULT, tail);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = self.exec(&cmd).await?;
+        Ok(LogLine::from_detached(&result))
+    }
+
     /// Stop the sandbox and clean up resources
     async fn stop(&mut self) -> Result<()>;
 
@@ -255,6 +1463,45 @@ pub trait Sandbox: Send + Sync {
     /// Check if the sandbox is running
     fn is_running(&self) -> bool;
 
+    /// The command that started the sandbox, with secret environment
+    /// values redacted. `None` if the backend doesn't record one.
+    fn launch_command(&self) -> Option<String> {
+        None
+    }
+
+    /// Why the sandbox exited on its own, if it has.
+    ///
+    /// Only meaningful once `is_running` returns false; backends that can't
+    /// tell return `None`.
+    fn exit_reason(&mut self) -> Option<ExitReason> {
+        None
+    }
+
+    /// Bytes of host disk used by the sandbox's own files: its writable
+    /// layer or disks, not the image or rootfs it shares with others
+    async fn disk_usage(&self) -> Result<u64> {
+        anyhow::bail!(
+            "The {} backend can't report disk usage",
+            self.backend_type()
+        )
+    }
+
+    /// The sandbox's address on its network, for reaching its services
+    /// directly. `None` when it has no network address or the backend can't
+    /// tell.
+    async fn ip_address(&self) -> Result<Option<IpAddr>> {
+        Ok(None)
+    }
+
+    /// Turn network access on or off while the sandbox runs
+    async fn set_network(&mut self, enabled: bool) -> Result<()> {
+        let _ = enabled;
+        anyhow::bail!(
+            "The {} backend can't change network access of a running sandbox",
+            self.backend_type()
+        )
+    }
+
     // --- File Operations ---
 
     /// Write a file to the sandbox filesystem
@@ -266,12 +1513,32 @@ pub trait Sandbox: Send + Sync {
     /// # Security
     /// Path is validated to prevent traversal attacks and writes to system paths
     async fn write_file(&mut self, path: &str, content: &[u8]) -> Result<()> {
+        self.write_file_with_options(path, content, &WriteOptions::default())
+            .await
+    }
+
+    /// Write a file to the sandbox filesystem, optionally appending or
+    /// setting its permissions
+    async fn write_file_with_options(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> Result<()> {
         validate_sandbox_path(path)?;
-        self.write_file_unchecked(path, content).await
+        if let Some(resolved) = self.resolve_path(path).await? {
+            validate_sandbox_path(&resolved)?;
+        }
+        self.write_file_unchecked(path, content, opts).await
     }
 
     /// Internal write implementation (no validation, called by write_file)
-    async fn write_file_unchecked(&mut self, path: &str, content: &[u8]) -> Result<()>;
+    async fn write_file_unchecked(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> Result<()>;
 
     /// Read a file from the sandbox filesystem
     ///
@@ -282,12 +1549,114 @@ pub trait Sandbox: Send + Sync {
     /// File content as bytes
     async fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
         validate_sandbox_path(path)?;
+        if let Some(resolved) = self.resolve_path(path).await? {
+            validate_sandbox_path(&resolved)?;
+        }
         self.read_file_unchecked(path).await
     }
 
     /// Internal read implementation (no validation, called by read_file)
     async fn read_file_unchecked(&mut self, path: &str) -> Result<Vec<u8>>;
 
+    /// Size of a file in the sandbox, in bytes
+    async fn file_size(&mut self, path: &str) -> Result<u64> {
+        validate_sandbox_path(path)?;
+        if let Some(resolved) = self.resolve_path(path).await? {
+            validate_sandbox_path(&resolved)?;
+        }
+        let cmd = file_size_command(path);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = self.exec(&cmd).await?;
+        if result.exit_code == FILE_MISSING_EXIT_CODE {
+            return Err(FileNotFound {
+                path: path.to_string(),
+            }
+            .into());
+        }
+        if !result.is_success() {
+            anyhow::bail!("Failed to read file {}: {}", path, result.stderr.trim());
+        }
+        result
+            .stdout
+            .trim()
+            .parse()
+            .with_context(|| format!("Unexpected size for {}: {}", path, result.stdout.trim()))
+    }
+
+    /// Read at most `len` bytes of a file, starting at `offset`.
+    ///
+    /// Lets large files be downloaded a chunk at a time.
+    async fn read_file_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        validate_sandbox_path(path)?;
+        if let Some(resolved) = self.resolve_path(path).await? {
+            validate_sandbox_path(&resolved)?;
+        }
+        self.read_file_range_unchecked(path, offset, len).await
+    }
+
+    /// Internal ranged read (no validation, called by read_file_range).
+    ///
+    /// The default goes through `exec_bytes`; backends whose exec output
+    /// isn't byte for byte override it.
+    async fn read_file_range_unchecked(
+        &mut self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        let cmd = read_range_command(path, offset, len);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let opts = ExecOptions {
+            max_output: Some(len as usize),
+            ..Default::default()
+        };
+        let result = self.exec_bytes(&cmd, &opts).await?;
+        if result.exit_code != 0 {
+            anyhow::bail!(
+                "Failed to read file {}: {}",
+                path,
+                String::from_utf8_lossy(&result.stderr).trim()
+            );
+        }
+        Ok(result.stdout)
+    }
+
+    /// Start reading `len` bytes of a file from `offset` in one go, as a host
+    /// process whose stdout carries the bytes.
+    ///
+    /// Returns None for backends that download a chunk at a time with
+    /// `read_file_range`.
+    async fn stream_file_range(
+        &mut self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<tokio::process::Child>> {
+        validate_sandbox_path(path)?;
+        if let Some(resolved) = self.resolve_path(path).await? {
+            validate_sandbox_path(&resolved)?;
+        }
+        self.stream_file_range_unchecked(path, offset, len).await
+    }
+
+    /// Internal streamed read (no validation, called by stream_file_range)
+    async fn stream_file_range_unchecked(
+        &mut self,
+        _path: &str,
+        _offset: u64,
+        _len: u64,
+    ) -> Result<Option<tokio::process::Child>> {
+        Ok(None)
+    }
+
+    /// Start a command as a host process whose stdout carries the
+    /// command's output byte for byte, and whose stderr carries its errors.
+    ///
+    /// Returns None for backends that can only run a command to completion.
+    async fn stream_exec(&mut self, _cmd: &[&str]) -> Result<Option<tokio::process::Child>> {
+        Ok(None)
+    }
+
     /// Remove a file from the sandbox filesystem
     async fn remove_file(&mut self, path: &str) -> Result<()> {
         validate_sandbox_path(path)?;
@@ -306,6 +1675,47 @@ pub trait Sandbox: Send + Sync {
     /// Internal mkdir implementation
     async fn mkdir_unchecked(&mut self, path: &str, recursive: bool) -> Result<()>;
 
+    /// Create a symbolic link at `link` pointing to `target`
+    ///
+    /// # Security
+    /// The link path and the resolved target are both validated, so a link
+    /// can't point at a blocked system path.
+    async fn symlink(&mut self, target: &str, link: &str) -> Result<()> {
+        validate_link(target, link)?;
+        self.link_unchecked(target, link, false).await
+    }
+
+    /// Create a hard link at `link` to the existing file `target`
+    async fn hardlink(&mut self, target: &str, link: &str) -> Result<()> {
+        validate_sandbox_path(target)?;
+        validate_sandbox_path(link)?;
+        self.link_unchecked(target, link, true).await
+    }
+
+    /// Internal link implementation (runs `ln` in the sandbox by default)
+    async fn link_unchecked(&mut self, target: &str, link: &str, hard: bool) -> Result<()> {
+        let mut cmd = vec!["ln"];
+        if !hard {
+            cmd.push("-s");
+        }
+        cmd.extend(["--", target, link]);
+        let result = self.exec(&cmd).await?;
+        if !result.is_success() {
+            anyhow::bail!("Failed to create link: {}", result.output().trim());
+        }
+        Ok(())
+    }
+
+    /// Resolve symlinks in `path` inside the sandbox.
+    ///
+    /// File reads and writes validate the resolved path too, so a link can't
+    /// be used to reach a blocked path. Returns `None` when the backend
+    /// resolves paths itself (or can't).
+    async fn resolve_path(&mut self, path: &str) -> Result<Option<String>> {
+        let _ = path;
+        Ok(None)
+    }
+
     /// Inject files from config into the sandbox
     ///
     /// Called automatically after start() when files are specified in config.
@@ -402,14 +1812,75 @@ pub fn validate_sandbox_path(path: &str) -> Result<()> {
     Ok(())
 }
 
+/// Validate a symlink: the link path, and its target resolved against the
+/// link's directory when relative
+pub fn validate_link(target: &str, link: &str) -> Result<()> {
+    validate_sandbox_path(link)?;
+    if target.is_empty() {
+        anyhow::bail!("Link target must not be empty");
+    }
+    let resolved = if target.starts_with('/') {
+        target.to_string()
+    } else {
+        let parent = std::path::Path::new(link)
+            .parent()
+            .unwrap_or(std::path::Path::new("/"));
+        parent.join(target).to_string_lossy().into_owned()
+    };
+    validate_sandbox_path(&resolved)
+}
+
+/// Env var that makes backend detection run again instead of using the
+/// results cached for this process
+pub const REDETECT_ENV: &str = "AGENTKERNEL_REDETECT";
+
+/// Best backend, once one was found
+static BEST_BACKEND: Mutex<Option<BackendType>> = Mutex::new(None);
+
+/// Backends found to be available so far
+static AVAILABLE: Mutex<Vec<BackendType>> = Mutex::new(Vec::new());
+
+/// Whether `AGENTKERNEL_REDETECT` asks to skip the detection caches
+fn redetect_requested() -> bool {
+    std::env::var(REDETECT_ENV)
+        .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
 /// Detect the best available backend for the current platform
+///
+/// Detection shells out to the runtimes, so a backend once found is cached
+/// for the life of the process unless `AGENTKERNEL_REDETECT` is set. Finding
+/// none isn't cached, so a runtime started later is picked up.
 pub fn detect_best_backend() -> Option<BackendType> {
+    best_backend_cached(
+        &BEST_BACKEND,
+        redetect_requested(),
+        detect_best_backend_uncached,
+    )
+}
+
+fn best_backend_cached(
+    cache: &Mutex<Option<BackendType>>,
+    redetect: bool,
+    detect: impl FnOnce() -> Option<BackendType>,
+) -> Option<BackendType> {
+    let mut best = cache.lock().unwrap_or_else(|e| e.into_inner());
+    match *best {
+        Some(backend) if !redetect => Some(backend),
+        _ => {
+            *best = detect();
+            *best
+        }
+    }
+}
+
+fn detect_best_backend_uncached() -> Option<BackendType> {
     // On Linux, prefer Firecracker if KVM is available
     #[cfg(target_os = "linux")]
     {
         if std::path::Path::new("/dev/kvm").exists() {
             // Check if firecracker is available
-            if firecracker::firecracker_available() {
+            if backend_available(BackendType::Firecracker) {
                 return Some(BackendType::Firecracker);
             }
         }
@@ -418,16 +1889,24 @@ pub fn detect_best_backend() -> Option<BackendType> {
     // On macOS 26+, check for Apple Containers
     #[cfg(target_os = "macos")]
     {
-        if apple::apple_containers_available() {
+        if backend_available(BackendType::Apple) {
             return Some(BackendType::Apple);
         }
     }
 
+    // Nested in a container with the host's Docker socket mounted, use the
+    // host daemon: Podman inside a container rarely works
+    if crate::nested::detect().is_some_and(|env| env.docker_socket.is_some())
+        && backend_available(BackendType::Docker)
+    {
+        return Some(BackendType::Docker);
+    }
+
     // Fall back to containers (prefer Podman over Docker)
-    if docker::podman_available() {
+    if backend_available(BackendType::Podman) {
         return Some(BackendType::Podman);
     }
-    if docker::docker_available() {
+    if backend_available(BackendType::Docker) {
         return Some(BackendType::Docker);
     }
 
@@ -435,7 +1914,42 @@ pub fn detect_best_backend() -> Option<BackendType> {
 }
 
 /// Check if a specific backend is available
+///
+/// Cached like [`detect_best_backend`]: only backends found are remembered.
 pub fn backend_available(backend: BackendType) -> bool {
+    backend_available_cached(
+        &AVAILABLE,
+        backend,
+        redetect_requested(),
+        backend_available_uncached,
+    )
+}
+
+fn backend_available_cached(
+    cache: &Mutex<Vec<BackendType>>,
+    backend: BackendType,
+    redetect: bool,
+    detect: impl FnOnce(BackendType) -> bool,
+) -> bool {
+    if !redetect
+        && cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&backend)
+    {
+        return true;
+    }
+    // Don't hold the lock while detecting: checking a runtime can take a while
+    let ok = detect(backend);
+    let mut available = cache.lock().unwrap_or_else(|e| e.into_inner());
+    available.retain(|b| *b != backend);
+    if ok {
+        available.push(backend);
+    }
+    ok
+}
+
+fn backend_available_uncached(backend: BackendType) -> bool {
     match backend {
         BackendType::Docker => docker::docker_available(),
         BackendType::Podman => docker::podman_available(),
@@ -477,8 +1991,459 @@ pub fn create_sandbox(backend: BackendType, name: &str) -> Result<Box<dyn Sandbo
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_background_list() {
+        let output = "a1b2\trunning\tsleep 30\n\
+                      c3d4\texited 2\tmake test\n\
+                      e5f6\tlost\t\n\
+                      0a0b\texited \tnpm start\n";
+        let execs = parse_background_list(output).unwrap();
+        let states: Vec<_> = execs.iter().map(|e| (e.id.as_str(), e.state)).collect();
+        assert_eq!(
+            states,
+            vec![
+                ("a1b2", BackgroundState::Running),
+                ("c3d4", BackgroundState::Exited(2)),
+                ("e5f6", BackgroundState::Lost),
+                // The exit code is still being written
+                ("0a0b", BackgroundState::Running),
+            ]
+        );
+        assert_eq!(execs[1].command, "make test");
+        assert!(parse_background_list("").unwrap().is_empty());
+        assert!(parse_background_list("garbage").is_err());
+
+        assert!(validate_background_id("a1b2c3d4e5f6").is_ok());
+        assert!(validate_background_id("../x").is_err());
+        assert!(validate_background_id("").is_err());
+    }
+
+    #[test]
+    fn test_check_gpus_supported() {
+        let mut config = SandboxConfig::default();
+        assert!(
+            config
+                .check_gpus_supported(BackendType::Firecracker)
+                .is_ok()
+        );
+
+        config.gpus = Some(crate::permissions::Gpus::All);
+        assert!(config.check_gpus_supported(BackendType::Docker).is_ok());
+        assert!(config.check_gpus_supported(BackendType::Podman).is_ok());
+        let err = config
+            .check_gpus_supported(BackendType::Firecracker)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("not supported on the firecracker backend")
+        );
+    }
+
+    #[test]
+    fn test_read_range_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let content: Vec<u8> = (0..=255).collect();
+        std::fs::write(&path, &content).unwrap();
+        let path = path.to_str().unwrap();
+        let run = |cmd: Vec<String>| {
+            let output = std::process::Command::new(&cmd[0])
+                .args(&cmd[1..])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            output.stdout
+        };
+
+        assert_eq!(run(file_size_command(path)), b"256\n");
+        let missing = dir.path().join("missing.bin");
+        let status = std::process::Command::new("sh")
+            .args(&file_size_command(missing.to_str().unwrap())[1..])
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(FILE_MISSING_EXIT_CODE));
+        assert_eq!(run(read_range_command(path, 0, 100)), &content[..100]);
+        assert_eq!(run(read_range_command(path, 200, 100)), &content[200..]);
+        assert!(run(read_range_command(path, 300, 10)).is_empty());
+    }
+
+    #[test]
+    fn test_append_command_accumulates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        let path = path.to_str().unwrap();
+        let append = |content: &str| {
+            let staged = append_staging_path(path);
+            std::fs::write(&staged, content).unwrap();
+            let cmd = append_command(&staged, path);
+            let status = std::process::Command::new(&cmd[0])
+                .args(&cmd[1..])
+                .status()
+                .unwrap();
+            assert!(status.success());
+            assert!(!std::path::Path::new(&staged).exists());
+        };
+
+        append("one\n");
+        append("two\n");
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_display_command_quotes_and_redacts() {
+        let args: Vec<String> = [
+            "run",
+            "-e",
+            "API_TOKEN=hunter2",
+            "-e",
+            "LANG=C.UTF-8",
+            "--env=DEBUG=1",
+            "-e",
+            "HOME",
+            "--label",
+            "note=has space",
+            "alpine:3.20",
+            "sh",
+            "-c",
+            "it's",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            display_command("docker", &args),
+            "docker run -e 'API_TOKEN=***' -e 'LANG=***' '--env=DEBUG=***' -e HOME \
+             --label 'note=has space' alpine:3.20 sh -c 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn test_detached_log_command_keeps_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let (log, err) = (path("run.log"), path("run.err"));
+        std::fs::write(&log, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&err, "oops\n").unwrap();
+        let paths = DetachedPaths {
+            log: &log,
+            err: &err,
+            exit: "",
+            pid: "",
+        };
+        let read = |tail: Option<usize>| {
+            let cmd = detached_log_command(&paths, tail);
+            let output = std::process::Command::new(&cmd[0])
+                .args(&cmd[1..])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            let result = ExecResult {
+                exit_code: 0,
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                rusage: None,
+                truncated: false,
+            };
+            LogLine::from_detached(&result)
+                .into_iter()
+                .map(|l| (l.stream, l.line))
+                .collect::<Vec<_>>()
+        };
+
+        let line = |stream, line: &str| (stream, line.to_string());
+        assert_eq!(
+            read(Some(2)),
+            vec![
+                line(LogStream::Stdout, "two"),
+                line(LogStream::Stdout, "three"),
+                line(LogStream::Stderr, "oops"),
+            ]
+        );
+        assert_eq!(read(None).len(), 4);
+
+        // No background command: no output and no error
+        std::fs::remove_file(&log).unwrap();
+        std::fs::remove_file(&err).unwrap();
+        assert!(read(None).is_empty());
+    }
+
+    #[test]
+    fn test_script_command_runs_multiline_script() {
+        let input = "greeting=\"hello $1\"\nfor n in 1 2; do\n  echo \"$greeting $n\"\ndone\necho \"args: $#\"\n";
+        let (script, rest) = read_script(input.as_bytes()).unwrap();
+        assert!(rest.is_none());
+        let cmd = script_command(&script, None, &["world".to_string(), "x y".to_string()]).unwrap();
+        let output = std::process::Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "hello world 1\nhello world 2\nargs: 2\n"
+        );
+
+        assert!(read_script(" \n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_script_input_after_separator() {
+        let input = "read name\necho \"hi $name\"\ncat\n__END__\nworld\nline two";
+        let (script, rest) = read_script(input.as_bytes()).unwrap();
+        assert_eq!(script, "read name\necho \"hi $name\"\ncat\n");
+        assert_eq!(rest.as_deref(), Some("world\nline two"));
+
+        let cmd = script_command(&script, rest.as_deref(), &[]).unwrap();
+        let output = std::process::Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "hi world\nline two\n"
+        );
+
+        assert!(script_command("cat", Some("a\0b"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_file_mode() {
+        assert_eq!(parse_file_mode("755").unwrap(), 0o755);
+        assert_eq!(parse_file_mode("0640").unwrap(), 0o640);
+        assert_eq!(parse_file_mode("0o600").unwrap(), 0o600);
+        assert!(parse_file_mode("").is_err());
+        assert!(parse_file_mode("999").is_err());
+        assert!(parse_file_mode("17777").is_err());
+        assert!(parse_file_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn test_restart_policy_parse_and_flags() {
+        for policy in [
+            RestartPolicy::Never,
+            RestartPolicy::OnFailure,
+            RestartPolicy::Always,
+        ] {
+            assert_eq!(policy.to_string().parse::<RestartPolicy>(), Ok(policy));
+        }
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+
+        assert_eq!(RestartPolicy::Never.docker_flag(), None);
+        assert_eq!(
+            RestartPolicy::OnFailure.docker_flag().as_deref(),
+            Some("--restart=on-failure:5")
+        );
+        assert_eq!(
+            RestartPolicy::Always.docker_flag().as_deref(),
+            Some("--restart=always")
+        );
+    }
+
+    #[test]
+    fn test_backend_detection_caches_only_hits() {
+        use std::cell::Cell;
+
+        let cache = Mutex::new(None);
+        let detections = Cell::new(0);
+        let detect = |found| {
+            detections.set(detections.get() + 1);
+            found
+        };
+        // Nothing found yet: detected again next time
+        assert_eq!(best_backend_cached(&cache, false, || detect(None)), None);
+        let found = Some(BackendType::Docker);
+        assert_eq!(best_backend_cached(&cache, false, || detect(found)), found);
+        assert_eq!(best_backend_cached(&cache, false, || detect(None)), found);
+        assert_eq!(detections.get(), 2);
+        // AGENTKERNEL_REDETECT bypasses the cache
+        assert_eq!(best_backend_cached(&cache, true, || detect(None)), None);
+        assert_eq!(detections.get(), 3);
+
+        let cache = Mutex::new(Vec::new());
+        let detections = Cell::new(0);
+        let detect = |ok| {
+            move |_| {
+                detections.set(detections.get() + 1);
+                ok
+            }
+        };
+        let podman = BackendType::Podman;
+        assert!(!backend_available_cached(
+            &cache,
+            podman,
+            false,
+            detect(false)
+        ));
+        assert!(backend_available_cached(
+            &cache,
+            podman,
+            false,
+            detect(true)
+        ));
+        assert!(backend_available_cached(
+            &cache,
+            podman,
+            false,
+            detect(false)
+        ));
+        assert_eq!(detections.get(), 2);
+        assert!(!backend_available_cached(
+            &cache,
+            podman,
+            true,
+            detect(false)
+        ));
+        assert!(!backend_available_cached(
+            &cache,
+            podman,
+            false,
+            detect(false)
+        ));
+        assert_eq!(detections.get(), 4);
+    }
+
+    #[test]
+    fn test_port_mapping_parse() {
+        assert_eq!(
+            "8080:80".parse::<PortMapping>(),
+            Ok(PortMapping {
+                host_ip: None,
+                host: 8080,
+                sandbox: 80
+            })
+        );
+        assert_eq!(
+            "3000".parse::<PortMapping>(),
+            Ok(PortMapping {
+                host_ip: None,
+                host: 3000,
+                sandbox: 3000
+            })
+        );
+        assert_eq!(
+            PortMapping {
+                host_ip: None,
+                host: 8080,
+                sandbox: 80
+            }
+            .to_string(),
+            "8080:80"
+        );
+        // A host address binds more than loopback
+        let wide = "0.0.0.0:8080:80".parse::<PortMapping>().unwrap();
+        assert_eq!(wide.host_ip, Some("0.0.0.0".parse().unwrap()));
+        assert_eq!(wide.to_string(), "0.0.0.0:8080:80");
+        let v6 = "[::]:8080:80".parse::<PortMapping>().unwrap();
+        assert_eq!(v6.publish_spec(), "[::]:8080:80");
+        assert_eq!(
+            "8080:80".parse::<PortMapping>().unwrap().publish_spec(),
+            "127.0.0.1:8080:80"
+        );
+
+        for bad in [
+            "",
+            "0:80",
+            "80:",
+            "http:80",
+            "70000:80",
+            "1:2:3",
+            "host:80:80",
+        ] {
+            assert!(bad.parse::<PortMapping>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_restart_policy_retry_cap() {
+        assert!(!RestartPolicy::Never.should_restart(Some(1), 0));
+
+        assert!(RestartPolicy::OnFailure.should_restart(Some(1), 0));
+        assert!(RestartPolicy::OnFailure.should_restart(None, 0));
+        assert!(!RestartPolicy::OnFailure.should_restart(Some(0), 0));
+
+        assert!(RestartPolicy::Always.should_restart(Some(0), 0));
+        assert!(RestartPolicy::Always.should_restart(Some(0), MAX_RESTARTS - 1));
+        assert!(!RestartPolicy::Always.should_restart(Some(0), MAX_RESTARTS));
+        assert!(!RestartPolicy::OnFailure.should_restart(Some(1), MAX_RESTARTS));
+    }
+
+    #[test]
+    fn test_log_buffer_keeps_most_recent_lines() {
+        let buffer = LogBuffer::default();
+        for i in 0..CONSOLE_BUFFER_LINES + 5 {
+            buffer.push(LogStream::Stdout, format!("line {}", i));
+        }
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), CONSOLE_BUFFER_LINES);
+        assert_eq!(lines[0].line, "line 5");
+        assert_eq!(
+            lines.last().unwrap().line,
+            format!("line {}", CONSOLE_BUFFER_LINES + 4)
+        );
+
+        let buffer = LogBuffer::default();
+        buffer
+            .capture(&b"booting\nready\n"[..], LogStream::Stderr)
+            .join()
+            .unwrap();
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].line, "ready");
+        assert_eq!(lines[1].stream, LogStream::Stderr);
+    }
+
+    #[test]
+    fn test_parse_wait_status() {
+        assert_eq!(parse_wait_status("exited 3\n").unwrap(), Some(3));
+        assert_eq!(parse_wait_status("exited 0").unwrap(), Some(0));
+        assert_eq!(parse_wait_status("running\n").unwrap(), None);
+        assert!(parse_wait_status("lost").is_err());
+        assert!(parse_wait_status("none").is_err());
+        assert!(parse_wait_status("exited ").is_err());
+    }
+
     // === BackendType tests ===
 
+    #[test]
+    fn test_in_workdir_wraps_command() {
+        let wrapped = in_workdir(&["ls", "-la"], "/app/it's");
+        assert_eq!(
+            wrapped,
+            vec![
+                "sh",
+                "-c",
+                "cd '/app/it'\\''s' && exec \"$@\"",
+                "sh",
+                "ls",
+                "-la"
+            ]
+        );
+
+        // The wrapper really runs the command from that directory
+        let wrapped = in_workdir(&["pwd"], "/");
+        let output = std::process::Command::new(&wrapped[0])
+            .args(&wrapped[1..])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "/");
+    }
+
+    #[test]
+    fn test_validate_link() {
+        assert!(validate_link("/app/config.toml", "/app/current.toml").is_ok());
+        // Relative targets resolve against the link's directory
+        assert!(validate_link("config.toml", "/app/current.toml").is_ok());
+        assert!(validate_link("shadow", "/etc/link").is_err());
+        // Links can't point at blocked paths or escape with traversal
+        assert!(validate_link("/etc/shadow", "/app/x").is_err());
+        assert!(validate_link("../../etc/passwd", "/app/x").is_err());
+        assert!(validate_link("/proc/self/environ", "/tmp/env").is_err());
+        assert!(validate_link("/app/x", "relative/link").is_err());
+        assert!(validate_link("", "/app/x").is_err());
+    }
+
     #[test]
     fn test_backend_type_display() {
         assert_eq!(format!("{}", BackendType::Docker), "docker");
@@ -618,6 +2583,8 @@ mod tests {
             exit_code: 0,
             stdout: "stdout output".to_string(),
             stderr: String::new(),
+            rusage: None,
+            truncated: false,
         };
         assert_eq!(result.output(), "stdout output");
     }
@@ -628,6 +2595,8 @@ mod tests {
             exit_code: 1,
             stdout: String::new(),
             stderr: "stderr output".to_string(),
+            rusage: None,
+            truncated: false,
         };
         assert_eq!(result.output(), "stderr output");
     }
@@ -638,10 +2607,31 @@ mod tests {
             exit_code: 0,
             stdout: "stdout".to_string(),
             stderr: "stderr".to_string(),
+            rusage: None,
+            truncated: false,
         };
         assert_eq!(result.output(), "stdout\nstderr");
     }
 
+    #[test]
+    fn test_exec_bytes_keeps_invalid_utf8() {
+        let result = ExecBytes {
+            exit_code: 0,
+            stdout: vec![0xff, b'o', b'k'],
+            stderr: b"warn".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(result.output(), b"\xffok\nwarn");
+
+        // The string form replaces what isn't UTF-8
+        let lossy = result.into_lossy();
+        assert_eq!(lossy.stdout, "\u{fffd}ok");
+        assert_eq!(lossy.stderr, "warn");
+
+        let bytes = ExecBytes::from(ExecResult::success("hi".to_string()));
+        assert_eq!(bytes.stdout, b"hi");
+    }
+
     // === Path validation tests ===
 
     #[test]
@@ -7,8 +7,9 @@ use anyhow::{Context, Result, bail};
 use std::path::Path;
 use std::process::Command;
 
+use crate::backend::{BackendType, backend_available};
 use crate::config::BuildConfig;
-use crate::docker_backend::{ContainerRuntime, docker_available, podman_available};
+use crate::docker_backend::ContainerRuntime;
 use crate::languages::dockerfile_image_name;
 
 /// Result of a Docker build operation
@@ -48,9 +49,9 @@ pub fn build_image(
     config: &BuildConfig,
 ) -> Result<BuildResult> {
     // Determine which runtime to use
-    let runtime = if docker_available() {
+    let runtime = if backend_available(BackendType::Docker) {
         ContainerRuntime::Docker
-    } else if podman_available() {
+    } else if backend_available(BackendType::Podman) {
         ContainerRuntime::Podman
     } else {
         bail!("No container runtime available (need Docker or Podman)");
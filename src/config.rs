@@ -1,8 +1,9 @@
 //! Configuration parsing for agentkernel.toml files.
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 use crate::backend::FileInjection;
 use crate::permissions::SecurityProfile;
@@ -61,6 +62,163 @@ pub struct Config {
     /// Files to inject into the sandbox at startup
     #[serde(default, rename = "files")]
     pub files: Vec<FileEntry>,
+    /// Host files bound read-only into the sandbox, e.g. credentials
+    #[serde(default)]
+    pub mounts: Vec<crate::permissions::FileMount>,
+    /// Per-compatibility-mode overrides, e.g. `[compat.codex]`
+    #[serde(default)]
+    pub compat: std::collections::HashMap<String, CompatOverride>,
+    /// Firecracker VM settings
+    #[serde(default)]
+    pub firecracker: FirecrackerConfig,
+    /// Named sandbox presets, e.g. `[presets.ci]`
+    #[serde(default)]
+    pub presets: std::collections::BTreeMap<String, PresetConfig>,
+    /// Audit log settings
+    #[serde(default)]
+    pub audit: AuditConfig,
+}
+
+/// Reusable sandbox settings selected with `--preset <name>`.
+///
+/// Unset fields fall back to the rest of the config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresetConfig {
+    /// Docker image (overrides `runtime`)
+    pub image: Option<String>,
+    /// Runtime shorthand: base, python, node, go, rust, ruby, java, c, dotnet
+    pub runtime: Option<String>,
+    pub vcpus: Option<u32>,
+    pub memory_mb: Option<u64>,
+    /// Security profile: permissive, moderate, restrictive
+    pub profile: Option<SecurityProfile>,
+    pub network: Option<bool>,
+    pub mount_cwd: Option<bool>,
+    pub mount_home: Option<bool>,
+    /// User to run as: uid[:gid], name[:group] or "current"
+    pub user: Option<String>,
+    /// Environment variables set in the sandbox
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+}
+
+/// A preset with config defaults filled in
+#[derive(Debug, Clone)]
+pub struct ResolvedPreset {
+    pub image: String,
+    pub vcpus: u32,
+    pub memory_mb: u64,
+    /// Permissions, with the preset's resources as limits and its env included
+    pub permissions: crate::permissions::Permissions,
+}
+
+/// Command-line flags that take precedence over a preset
+#[derive(Debug, Clone, Default)]
+pub struct PresetFlags {
+    pub image: Option<String>,
+    pub profile: Option<SecurityProfile>,
+    pub no_network: bool,
+    pub user: Option<String>,
+}
+
+impl ResolvedPreset {
+    /// Apply explicit flags. A `--profile` replaces the preset's profile
+    /// defaults, but the preset's own settings still apply on top of it.
+    pub fn apply_flags(&mut self, flags: &PresetFlags, preset: &PresetConfig) {
+        if let Some(ref image) = flags.image {
+            self.image = image.clone();
+        }
+        if let Some(profile) = flags.profile {
+            let mut perms = profile.permissions();
+            perms.dns = std::mem::take(&mut self.permissions.dns);
+            perms.extra_hosts = std::mem::take(&mut self.permissions.extra_hosts);
+            perms.egress = self.permissions.egress.take();
+            perms.scratch_mb = self.permissions.scratch_mb;
+            perms.env = std::mem::take(&mut self.permissions.env);
+            perms.restart = self.permissions.restart;
+            perms.init = self.permissions.init;
+            perms.hostname = self.permissions.hostname.take();
+            perms.mounts = std::mem::take(&mut self.permissions.mounts);
+            self.permissions = perms;
+            preset.apply(&mut self.permissions);
+            self.apply_resources();
+        }
+        if flags.no_network {
+            self.permissions.network = false;
+        }
+        if flags.user.is_some() {
+            self.permissions.user = flags.user.clone();
+        }
+    }
+
+    fn apply_resources(&mut self) {
+        self.permissions.max_memory_mb = Some(self.memory_mb);
+        self.permissions.max_cpu_percent = Some(self.vcpus * 100);
+    }
+}
+
+impl PresetConfig {
+    /// Apply the preset's permission settings that are set
+    fn apply(&self, perms: &mut crate::permissions::Permissions) {
+        if let Some(network) = self.network {
+            perms.network = network;
+        }
+        if let Some(mount_cwd) = self.mount_cwd {
+            perms.mount_cwd = mount_cwd;
+        }
+        if let Some(mount_home) = self.mount_home {
+            perms.mount_home = mount_home;
+        }
+        if self.user.is_some() {
+            perms.user = self.user.clone();
+        }
+    }
+}
+
+/// Overrides layered on top of a compatibility mode's preset permissions.
+///
+/// Only fields that are set replace the mode's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompatOverride {
+    pub network: Option<bool>,
+    pub mount_cwd: Option<bool>,
+    pub mount_home: Option<bool>,
+    pub pass_env: Option<bool>,
+    pub read_only: Option<bool>,
+    pub max_memory_mb: Option<u64>,
+    pub max_cpu_percent: Option<u32>,
+    pub seccomp: Option<String>,
+}
+
+impl CompatOverride {
+    /// Apply the fields that are set to `perms`
+    pub fn apply(&self, perms: &mut crate::permissions::Permissions) {
+        if let Some(network) = self.network {
+            perms.network = network;
+        }
+        if let Some(mount_cwd) = self.mount_cwd {
+            perms.mount_cwd = mount_cwd;
+        }
+        if let Some(mount_home) = self.mount_home {
+            perms.mount_home = mount_home;
+        }
+        if let Some(pass_env) = self.pass_env {
+            perms.pass_env = pass_env;
+        }
+        if let Some(read_only) = self.read_only {
+            perms.read_only_root = read_only;
+        }
+        if let Some(mem) = self.max_memory_mb {
+            perms.max_memory_mb = Some(mem);
+        }
+        if let Some(cpu) = self.max_cpu_percent {
+            perms.max_cpu_percent = Some(cpu);
+        }
+        if let Some(ref seccomp) = self.seccomp {
+            perms.seccomp = Some(seccomp.clone());
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -70,8 +228,20 @@ pub struct SecurityConfig {
     pub profile: SecurityProfile,
     /// Allow network access (overrides profile)
     pub network: Option<bool>,
+    /// Network to join: none, bridge, host, or a network name (supersedes `network`)
+    #[serde(default)]
+    pub network_mode: Option<crate::permissions::NetworkMode>,
     /// Mount current directory (overrides profile)
     pub mount_cwd: Option<bool>,
+    /// Make the root filesystem read-only (overrides profile)
+    #[serde(default)]
+    pub read_only: Option<bool>,
+    /// Keep a writable workspace when the root is read-only
+    #[serde(default)]
+    pub writable_workspace: Option<bool>,
+    /// Path for the writable workspace (default: /workspace)
+    #[serde(default)]
+    pub workspace_path: Option<String>,
     /// Network domain filtering rules
     #[serde(default)]
     pub domains: DomainConfig,
@@ -81,6 +251,45 @@ pub struct SecurityConfig {
     /// Seccomp profile name or path
     #[serde(default)]
     pub seccomp: Option<String>,
+    /// User to run as: uid[:gid], name[:group] or "current" (overrides profile)
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Only allow images pinned to a digest (`name@sha256:...`)
+    #[serde(default)]
+    pub require_image_digest: bool,
+    /// Mount /tmp as a tmpfs of this size, e.g. "256m" (overrides profile)
+    #[serde(default)]
+    pub tmp_size: Option<String>,
+    /// Mount /run as a tmpfs of this size, e.g. "64m"
+    #[serde(default)]
+    pub run_size: Option<String>,
+    /// Resource limits, e.g. `nofile = "1024:4096"` (override the profile's)
+    #[serde(default)]
+    pub ulimits: crate::permissions::Ulimits,
+    /// GPUs to pass through: "all", a count, or "device=0,1" (Docker and Podman only)
+    #[serde(default)]
+    pub gpus: Option<crate::permissions::Gpus>,
+}
+
+/// Parse a tmpfs size like "256m" or "1g" into megabytes.
+///
+/// A bare number is megabytes.
+pub fn parse_size_mb(size: &str) -> Result<u64> {
+    let lower = size.trim().to_ascii_lowercase();
+    let lower = lower.strip_suffix('b').unwrap_or(&lower);
+    let (digits, multiplier) = match lower.strip_suffix('g') {
+        Some(digits) => (digits, 1024),
+        None => (lower.strip_suffix('m').unwrap_or(lower), 1),
+    };
+    let mb = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&mb| mb > 0);
+    match mb {
+        Some(mb) => Ok(mb),
+        None => bail!("Invalid size '{}': expected e.g. 256m or 1g", size),
+    }
 }
 
 /// Domain filtering configuration for network access control
@@ -106,21 +315,35 @@ impl DomainConfig {
     /// Check if a domain is allowed
     pub fn is_allowed(&self, domain: &str) -> bool {
         // First check blocklist
-        for pattern in &self.block {
-            if Self::matches_pattern(domain, pattern) {
-                return false;
-            }
+        if self.matching_block(domain).is_some() {
+            return false;
         }
 
         // If allowlist_only mode, must be in allow list
         if self.allowlist_only {
-            return self.allow.iter().any(|p| Self::matches_pattern(domain, p));
+            return self.matching_allow(domain).is_some();
         }
 
         // Otherwise allow by default
         true
     }
 
+    /// First block pattern matching the domain, if any
+    pub fn matching_block(&self, domain: &str) -> Option<&str> {
+        self.block
+            .iter()
+            .find(|p| Self::matches_pattern(domain, p))
+            .map(String::as_str)
+    }
+
+    /// First allow pattern matching the domain, if any
+    pub fn matching_allow(&self, domain: &str) -> Option<&str> {
+        self.allow
+            .iter()
+            .find(|p| Self::matches_pattern(domain, p))
+            .map(String::as_str)
+    }
+
     /// Check if domain matches a pattern (supports * wildcard prefix)
     fn matches_pattern(domain: &str, pattern: &str) -> bool {
         if pattern.starts_with("*.") {
@@ -149,26 +372,43 @@ pub struct CommandConfig {
 impl CommandConfig {
     /// Check if a command is allowed
     pub fn is_allowed(&self, command: &str) -> bool {
-        // Extract the binary name (first part of command)
-        let binary = command.split_whitespace().next().unwrap_or(command);
-        let binary_name = std::path::Path::new(binary)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(binary);
-
         // Check blocklist
-        if self.block.iter().any(|b| b == binary_name || b == binary) {
+        if self.matching_block(command).is_some() {
             return false;
         }
 
         // If allowlist_only mode, must be in allow list
         if self.allowlist_only {
-            return self.allow.iter().any(|a| a == binary_name || a == binary);
+            return self.matching_allow(command).is_some();
         }
 
         // Otherwise allow by default
         true
     }
+
+    /// First block entry matching the command's binary, if any
+    pub fn matching_block(&self, command: &str) -> Option<&str> {
+        Self::find_rule(&self.block, command)
+    }
+
+    /// First allow entry matching the command's binary, if any
+    pub fn matching_allow(&self, command: &str) -> Option<&str> {
+        Self::find_rule(&self.allow, command)
+    }
+
+    /// Match rules against the binary (first word), by full path or file name
+    fn find_rule<'a>(rules: &'a [String], command: &str) -> Option<&'a str> {
+        let binary = command.split_whitespace().next().unwrap_or(command);
+        let binary_name = std::path::Path::new(binary)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(binary);
+
+        rules
+            .iter()
+            .find(|r| *r == binary_name || *r == binary)
+            .map(String::as_str)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -180,12 +420,78 @@ pub struct SandboxConfig {
     /// Custom Docker image (overrides runtime if specified)
     #[serde(default)]
     pub base_image: Option<String>,
+    /// Restart policy when the sandbox exits on its own: never, on-failure, always
+    #[serde(default)]
+    pub restart: crate::backend::RestartPolicy,
+    /// Script run once, on the sandbox's first start: inline, or a path to a
+    /// script file relative to the config file
+    #[serde(default)]
+    pub setup_script: Option<String>,
+    /// Copy the current directory into the workspace at start, for backends
+    /// that can't mount it (Firecracker). Honors `.agentkernelignore`.
+    #[serde(default)]
+    pub copy_cwd: bool,
+    /// Size limit for `copy_cwd` in MB (default: 100)
+    #[serde(default)]
+    pub copy_cwd_max_mb: Option<u64>,
+    /// Backend used when `--backend` isn't given (docker, podman, firecracker, ...)
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Shell for `attach` (default: the first of bash, sh, ash in the sandbox)
+    #[serde(default)]
+    pub shell: Option<String>,
+    /// Run an init as PID 1 to reap orphaned processes (default: on for
+    /// persistent Docker sandboxes, off for Podman and one-shot runs)
+    #[serde(default)]
+    pub init: Option<bool>,
+    /// Hostname inside the sandbox (default: the sandbox name)
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Bytes of stdout and of stderr kept per command by the HTTP API and MCP
+    /// server, and by `exec` (default: 4 MB for the servers, all of it for `exec`)
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// Largest multipart file upload the HTTP API accepts, in bytes
+    /// (default: 100 MB)
+    #[serde(default)]
+    pub max_upload_bytes: Option<usize>,
 }
 
 fn default_runtime() -> String {
     "base".to_string()
 }
 
+/// Runtime shorthands with their own image (everything else is `base`)
+const RUNTIMES: &[&str] = &[
+    "python", "node", "go", "rust", "ruby", "java", "c", "dotnet",
+];
+
+/// The runtime shorthand for an image, if it's one of the runtime defaults
+pub fn runtime_for_image(image: &str) -> Option<&'static str> {
+    if image == runtime_image("base") {
+        return Some("base");
+    }
+    RUNTIMES
+        .iter()
+        .copied()
+        .find(|runtime| runtime_image(runtime) == image)
+}
+
+/// Default Docker image for a runtime shorthand
+fn runtime_image(runtime: &str) -> String {
+    match runtime {
+        "python" => "python:3.12-alpine".to_string(),
+        "node" => "node:22-alpine".to_string(),
+        "go" => "golang:1.23-alpine".to_string(),
+        "rust" => "rust:1.85-alpine".to_string(),
+        "ruby" => "ruby:3.3-alpine".to_string(),
+        "java" => "eclipse-temurin:21-alpine".to_string(),
+        "c" => "gcc:14-bookworm".to_string(),
+        "dotnet" => "mcr.microsoft.com/dotnet/sdk:8.0".to_string(),
+        _ => "alpine:3.20".to_string(),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
     /// Preferred AI agent: claude, gemini, codex, opencode
@@ -229,6 +535,24 @@ impl Default for ResourcesConfig {
     }
 }
 
+impl ResourcesConfig {
+    /// vCPUs and memory for a sandbox: explicit values win, then the
+    /// preset, then this section
+    pub fn resolve(
+        &self,
+        vcpus: Option<u32>,
+        memory_mb: Option<u64>,
+        preset: Option<&ResolvedPreset>,
+    ) -> (u32, u64) {
+        (
+            vcpus.or(preset.map(|p| p.vcpus)).unwrap_or(self.vcpus),
+            memory_mb
+                .or(preset.map(|p| p.memory_mb))
+                .unwrap_or(self.memory_mb),
+        )
+    }
+}
+
 fn default_vcpus() -> u32 {
     1
 }
@@ -237,10 +561,181 @@ fn default_memory_mb() -> u64 {
     512
 }
 
+/// Audit log settings (`[audit]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Write sandbox operations to the audit log (default: true)
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Audit log file (default: ~/.agentkernel/audit.jsonl)
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+impl AuditConfig {
+    /// Read only the `[audit]` section of a config file.
+    ///
+    /// The audit log is opened for every command, so a missing or invalid
+    /// file gives the defaults rather than an error.
+    pub fn from_file(path: &Path) -> Self {
+        #[derive(Deserialize)]
+        struct AuditOnly {
+            #[serde(default)]
+            audit: AuditConfig,
+        }
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<AuditOnly>(&content).ok())
+            .map(|config| config.audit)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FirecrackerConfig {
+    /// Size in MB of a writable scratch disk mounted at the workspace.
+    /// Docker sandboxes get a tmpfs of the same size instead.
+    #[serde(default)]
+    pub scratch_mb: Option<u64>,
+    /// Fail to start a VM whose guest agent isn't the version this build
+    /// expects, rather than warn (default: false)
+    #[serde(default)]
+    pub strict_agent_version: Option<bool>,
+}
+
+impl FirecrackerConfig {
+    /// Read only the `[firecracker]` section of a config file.
+    ///
+    /// Like [`AuditConfig::from_file`], a missing or invalid file gives the
+    /// defaults; commands that use the rest of the file report its errors.
+    pub fn from_file(path: &Path) -> Self {
+        #[derive(Deserialize)]
+        struct FirecrackerOnly {
+            #[serde(default)]
+            firecracker: FirecrackerConfig,
+        }
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<FirecrackerOnly>(&content).ok())
+            .map(|config| config.firecracker)
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkConfig {
     /// vsock CID for host-guest communication (auto-assigned if not specified)
     pub vsock_cid: Option<u32>,
+    /// Vsock port of the guest agent (default: `AGENTKERNEL_AGENT_PORT` or 52000)
+    #[serde(default)]
+    pub agent_port: Option<u32>,
+    /// DNS servers for the sandbox (IP addresses)
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Extra /etc/hosts entries as "hostname:ip" (e.g. "mock.local:10.0.0.5")
+    #[serde(default)]
+    pub extra_hosts: Vec<String>,
+    /// Restrict outbound traffic to these hosts (resolved at sandbox start)
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Restrict outbound traffic to these addresses or CIDR ranges
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    /// Add common package registries (PyPI, npm, crates.io, ...) to the allowlist
+    #[serde(default)]
+    pub package_registries: bool,
+}
+
+impl NetworkConfig {
+    /// Check that DNS servers and host entries are valid
+    pub fn validate(&self) -> Result<()> {
+        for server in &self.dns {
+            if server.parse::<IpAddr>().is_err() {
+                bail!(
+                    "Invalid DNS server '{}' in [network]: must be an IP address",
+                    server
+                );
+            }
+        }
+        for entry in &self.extra_hosts {
+            parse_extra_host(entry)?;
+        }
+        if self.agent_port == Some(0) {
+            bail!("Invalid agent_port 0 in [network]");
+        }
+        if let Some(egress) = self.egress() {
+            egress.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Egress allowlist, if any destinations are restricted
+    pub fn egress(&self) -> Option<crate::egress::EgressAllowlist> {
+        let mut hosts = self.allowed_hosts.clone();
+        if self.package_registries {
+            hosts.extend(
+                crate::egress::PACKAGE_REGISTRIES
+                    .iter()
+                    .map(|h| h.to_string()),
+            );
+        }
+        if hosts.is_empty() && self.allowed_cidrs.is_empty() {
+            return None;
+        }
+        Some(crate::egress::EgressAllowlist {
+            hosts,
+            cidrs: self.allowed_cidrs.clone(),
+        })
+    }
+
+    /// Parsed extra host entries (invalid entries are skipped; see `validate`)
+    pub fn parsed_extra_hosts(&self) -> Vec<(String, IpAddr)> {
+        self.extra_hosts
+            .iter()
+            .filter_map(|entry| parse_extra_host(entry).ok())
+            .collect()
+    }
+}
+
+/// Parse a "hostname:ip" host entry. IPv6 addresses may contain colons, so
+/// the hostname ends at the first one.
+pub fn parse_extra_host(entry: &str) -> Result<(String, IpAddr)> {
+    let Some((host, ip)) = entry.split_once(':') else {
+        bail!(
+            "Invalid extra_hosts entry '{}': expected \"hostname:ip\"",
+            entry
+        );
+    };
+    let valid_host = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !valid_host {
+        bail!(
+            "Invalid hostname '{}' in extra_hosts entry '{}'",
+            host,
+            entry
+        );
+    }
+    let ip = ip.parse::<IpAddr>().with_context(|| {
+        format!(
+            "Invalid IP address '{}' in extra_hosts entry '{}'",
+            ip, entry
+        )
+    })?;
+    Ok((host.to_string(), ip))
+}
+
+/// Fail unless `image` is pinned to a digest (`name@sha256:<digest>`)
+pub fn require_pinned_image(image: &str) -> Result<()> {
+    if !crate::docker_backend::is_digest_pinned(image) {
+        bail!(
+            "Image '{}' is not pinned to a digest, but [security] require_image_digest is set. \
+             Use name@sha256:<digest> (see `docker images --digests`)",
+            image
+        );
+    }
+    Ok(())
 }
 
 impl Config {
@@ -254,7 +749,139 @@ impl Config {
     /// Parse configuration from a TOML string.
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(content: &str) -> Result<Self> {
-        toml::from_str(content).context("Failed to parse TOML configuration")
+        let config: Self = toml::from_str(content).context("Failed to parse TOML configuration")?;
+        config.network.validate()?;
+        if config.firecracker.scratch_mb == Some(0) {
+            bail!("[firecracker] scratch_mb must be greater than 0");
+        }
+        if config.sandbox.copy_cwd_max_mb == Some(0) {
+            bail!("[sandbox] copy_cwd_max_mb must be greater than 0");
+        }
+        if config.sandbox.max_output_bytes == Some(0) {
+            bail!("[sandbox] max_output_bytes must be greater than 0");
+        }
+        if config.sandbox.max_upload_bytes == Some(0) {
+            bail!("[sandbox] max_upload_bytes must be greater than 0");
+        }
+        if let Some(ref backend) = config.sandbox.backend {
+            backend
+                .parse::<crate::backend::BackendType>()
+                .map_err(|e| anyhow::anyhow!("Invalid backend in [sandbox]: {}", e))?;
+        }
+        if let Some(ref shell) = config.sandbox.shell {
+            crate::backend::validate_shell(shell).context("Invalid shell in [sandbox]")?;
+        }
+        if let Some(ref hostname) = config.sandbox.hostname {
+            crate::validation::validate_hostname(hostname)
+                .context("Invalid hostname in [sandbox]")?;
+        }
+        if let Some(ref user) = config.security.user {
+            crate::permissions::validate_user(user).context("Invalid user in [security]")?;
+        }
+        for mount in &config.mounts {
+            mount.validate().context("Invalid entry in [[mounts]]")?;
+        }
+        for size in [&config.security.tmp_size, &config.security.run_size]
+            .into_iter()
+            .flatten()
+        {
+            parse_size_mb(size).context("Invalid tmpfs size in [security]")?;
+        }
+        config.check_image_pinned(&config.docker_image())?;
+        for (name, preset) in &config.presets {
+            if preset.vcpus == Some(0) || preset.memory_mb == Some(0) {
+                bail!(
+                    "[presets.{}] vcpus and memory_mb must be greater than 0",
+                    name
+                );
+            }
+            if let Some(ref user) = preset.user {
+                crate::permissions::validate_user(user)
+                    .with_context(|| format!("Invalid user in [presets.{}]", name))?;
+            }
+            if let Some(ref image) = preset.image {
+                config.check_image_pinned(image)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Output the HTTP API and MCP server keep per stream: `[sandbox]
+    /// max_output_bytes` from ./agentkernel.toml, else
+    /// [`DEFAULT_MAX_OUTPUT_BYTES`](crate::backend::DEFAULT_MAX_OUTPUT_BYTES)
+    pub fn server_max_output() -> usize {
+        Self::server_sandbox_setting("max_output_bytes", |s| s.max_output_bytes)
+            .unwrap_or(crate::backend::DEFAULT_MAX_OUTPUT_BYTES)
+    }
+
+    /// Largest upload the HTTP API accepts: `[sandbox] max_upload_bytes`
+    /// from ./agentkernel.toml, else `default`
+    pub fn server_max_upload(default: usize) -> usize {
+        Self::server_sandbox_setting("max_upload_bytes", |s| s.max_upload_bytes).unwrap_or(default)
+    }
+
+    /// A `[sandbox]` setting for the servers from ./agentkernel.toml; a
+    /// config that doesn't load is ignored with a warning
+    fn server_sandbox_setting<T>(
+        name: &str,
+        get: impl FnOnce(&SandboxConfig) -> Option<T>,
+    ) -> Option<T> {
+        let path = Path::new("agentkernel.toml");
+        if !path.exists() {
+            return None;
+        }
+        match Self::from_file(path) {
+            Ok(config) => get(&config.sandbox),
+            Err(e) => {
+                eprintln!("Warning: ignoring {} in agentkernel.toml: {:#}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Reject floating tags when `require_image_digest` is set
+    pub fn check_image_pinned(&self, image: &str) -> Result<()> {
+        if self.security.require_image_digest {
+            require_pinned_image(image)?;
+        }
+        Ok(())
+    }
+
+    /// Whether ./agentkernel.toml sets `[security] require_image_digest`.
+    ///
+    /// Only that key is read, so a file that fails validation (say, because
+    /// its own image isn't pinned) still holds every create to the rule.
+    pub fn local_requires_image_digest() -> bool {
+        std::fs::read_to_string("agentkernel.toml")
+            .ok()
+            .and_then(|content| toml::from_str::<toml::Value>(&content).ok())
+            .and_then(|value| {
+                value
+                    .get("security")?
+                    .get("require_image_digest")?
+                    .as_bool()
+            })
+            .unwrap_or(false)
+    }
+
+    /// The `[sandbox] setup_script` to run, reading it from a file if the
+    /// value names one (relative paths are resolved against `base_dir`)
+    pub fn setup_script(&self, base_dir: &Path) -> Result<Option<String>> {
+        let Some(ref script) = self.sandbox.setup_script else {
+            return Ok(None);
+        };
+        if script.trim().is_empty() {
+            return Ok(None);
+        }
+        if !script.contains('\n') {
+            let path = base_dir.join(script.trim());
+            if path.is_file() {
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read setup script {}", path.display()))?;
+                return Ok(Some(content));
+            }
+        }
+        Ok(Some(script.clone()))
     }
 
     /// Create a minimal config with just a name and agent type.
@@ -264,6 +891,16 @@ impl Config {
                 name: name.to_string(),
                 runtime: default_runtime(),
                 base_image: None,
+                restart: crate::backend::RestartPolicy::Never,
+                setup_script: None,
+                copy_cwd: false,
+                copy_cwd_max_mb: None,
+                backend: None,
+                shell: None,
+                init: None,
+                hostname: None,
+                max_output_bytes: None,
+                max_upload_bytes: None,
             },
             agent: AgentConfig {
                 preferred: agent.to_string(),
@@ -274,6 +911,11 @@ impl Config {
             security: SecurityConfig::default(),
             build: BuildConfig::default(),
             files: Vec::new(),
+            mounts: Vec::new(),
+            compat: std::collections::HashMap::new(),
+            firecracker: FirecrackerConfig::default(),
+            presets: std::collections::BTreeMap::new(),
+            audit: AuditConfig::default(),
         }
     }
 
@@ -286,31 +928,97 @@ impl Config {
         if let Some(ref mode_str) = self.agent.compatibility_mode
             && let Some(mode) = crate::permissions::CompatibilityMode::from_str(mode_str)
         {
-            let mut perms = mode.profile().permissions;
+            let mut perms = self.compat_permissions(mode);
 
             // Still apply explicit overrides from [security]
-            if let Some(network) = self.security.network {
-                perms.network = network;
-            }
-            if let Some(mount_cwd) = self.security.mount_cwd {
-                perms.mount_cwd = mount_cwd;
-            }
+            self.apply_security_overrides(&mut perms);
+            self.apply_network(&mut perms);
+            perms.restart = self.sandbox.restart;
+            perms.init = self.sandbox.init;
+            perms.hostname = self.sandbox.hostname.clone();
+            perms.mounts = self.mounts.clone();
 
             return perms;
         }
 
         // Fall back to security profile
         let mut perms = self.security.profile.permissions();
+        self.apply_security_overrides(&mut perms);
+        self.apply_network(&mut perms);
+        perms.restart = self.sandbox.restart;
+        perms.init = self.sandbox.init;
+        perms.hostname = self.sandbox.hostname.clone();
+        perms.mounts = self.mounts.clone();
+        perms
+    }
+
+    /// Apply `[network]` DNS and host entries and the `[firecracker]` scratch disk
+    fn apply_network(&self, perms: &mut crate::permissions::Permissions) {
+        perms.dns = self.network.dns.clone();
+        perms.extra_hosts = self.network.parsed_extra_hosts();
+        perms.egress = self.network.egress();
+        perms.scratch_mb = self.firecracker.scratch_mb;
+        perms.agent_port = self.network.agent_port;
+    }
+
+    /// Permissions for a compatibility mode with any `[compat.<mode>]` override applied
+    pub fn compat_permissions(
+        &self,
+        mode: crate::permissions::CompatibilityMode,
+    ) -> crate::permissions::Permissions {
+        let mut perms = mode.profile().permissions;
+        if let Some(over) = self.compat_override(mode) {
+            over.apply(&mut perms);
+        }
+        perms
+    }
 
-        // Apply overrides
+    /// Find the `[compat.<mode>]` override for a mode (keys accept mode aliases)
+    pub fn compat_override(
+        &self,
+        mode: crate::permissions::CompatibilityMode,
+    ) -> Option<&CompatOverride> {
+        self.compat.iter().find_map(|(key, over)| {
+            (crate::permissions::CompatibilityMode::from_str(key) == Some(mode)).then_some(over)
+        })
+    }
+
+    /// Apply explicit `[security]` overrides on top of a base permission set
+    fn apply_security_overrides(&self, perms: &mut crate::permissions::Permissions) {
         if let Some(network) = self.security.network {
             perms.network = network;
         }
+        if let Some(ref mode) = self.security.network_mode {
+            perms.set_network_mode(mode.clone());
+        }
         if let Some(mount_cwd) = self.security.mount_cwd {
             perms.mount_cwd = mount_cwd;
         }
-
-        perms
+        if let Some(read_only) = self.security.read_only {
+            perms.read_only_root = read_only;
+        }
+        if self.security.writable_workspace == Some(true) {
+            perms.writable_workspace = Some(
+                self.security
+                    .workspace_path
+                    .clone()
+                    .unwrap_or_else(|| crate::permissions::DEFAULT_WORKSPACE_PATH.to_string()),
+            );
+        }
+        if let Some(ref user) = self.security.user {
+            perms.user = Some(user.clone());
+        }
+        // Sizes are checked when the config is loaded
+        if let Some(ref size) = self.security.tmp_size {
+            perms.tmp_size_mb = parse_size_mb(size).ok();
+        }
+        if let Some(ref size) = self.security.run_size {
+            perms.run_size_mb = parse_size_mb(size).ok();
+        }
+        perms.ulimits.merge(&self.security.ulimits);
+        if let Some(ref gpus) = self.security.gpus {
+            perms.gpus = Some(gpus.clone());
+        }
     }
 
     /// Get the agent profile if a compatibility mode is configured
@@ -349,6 +1057,22 @@ impl Config {
             }
         }
 
+        if perms.network_mode() == crate::permissions::NetworkMode::Host {
+            warnings.push(
+                "network_mode = \"host\" shares the host's network stack: the sandbox \
+                 can reach services on the host's localhost and bind its ports."
+                    .to_string(),
+            );
+        }
+
+        if self.sandbox.copy_cwd && perms.mount_cwd {
+            warnings.push(
+                "[sandbox] copy_cwd is ignored because mount_cwd already gives \
+                 the sandbox the working directory."
+                    .to_string(),
+            );
+        }
+
         // Warn that domain filtering is not yet enforced at runtime
         if self.security.domains.has_rules() && perms.network {
             warnings.push(
@@ -369,17 +1093,51 @@ impl Config {
         }
 
         // Map runtime to default Docker image
-        match self.sandbox.runtime.as_str() {
-            "python" => "python:3.12-alpine".to_string(),
-            "node" => "node:22-alpine".to_string(),
-            "go" => "golang:1.23-alpine".to_string(),
-            "rust" => "rust:1.85-alpine".to_string(),
-            "ruby" => "ruby:3.3-alpine".to_string(),
-            "java" => "eclipse-temurin:21-alpine".to_string(),
-            "c" => "gcc:14-bookworm".to_string(),
-            "dotnet" => "mcr.microsoft.com/dotnet/sdk:8.0".to_string(),
-            _ => "alpine:3.20".to_string(),
-        }
+        runtime_image(&self.sandbox.runtime)
+    }
+
+    /// The preset named `name`, resolved against the rest of the config
+    pub fn preset(&self, name: &str) -> Result<ResolvedPreset> {
+        let Some(preset) = self.presets.get(name) else {
+            let available: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+            if available.is_empty() {
+                bail!("Preset '{}' not found: no [presets] are defined", name);
+            }
+            bail!(
+                "Preset '{}' not found. Available presets: {}",
+                name,
+                available.join(", ")
+            );
+        };
+
+        let image = match (&preset.image, &preset.runtime) {
+            (Some(image), _) => image.clone(),
+            (None, Some(runtime)) => runtime_image(runtime),
+            (None, None) => self.docker_image(),
+        };
+        let mut permissions = match preset.profile {
+            Some(profile) => {
+                let mut perms = profile.permissions();
+                self.apply_network(&mut perms);
+                perms
+            }
+            None => self.get_permissions(),
+        };
+        preset.apply(&mut permissions);
+        permissions.env = preset
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut resolved = ResolvedPreset {
+            image,
+            vcpus: preset.vcpus.unwrap_or(self.resources.vcpus),
+            memory_mb: preset.memory_mb.unwrap_or(self.resources.memory_mb),
+            permissions,
+        };
+        resolved.apply_resources();
+        Ok(resolved)
     }
 
     /// Get the Dockerfile path if one is configured or auto-detected
@@ -454,19 +1212,147 @@ impl Config {
 
         Ok(injections)
     }
+
+    /// The current directory as file injections when `[sandbox] copy_cwd` is
+    /// set, copied to the workspace path
+    pub fn load_cwd_copy(&self) -> Result<Vec<FileInjection>> {
+        // Writing the copy into a mounted directory would write to the host
+        if !self.sandbox.copy_cwd || self.get_permissions().mount_cwd {
+            return Ok(Vec::new());
+        }
+        let cwd = std::env::current_dir().context("Failed to read the current directory")?;
+        let dest = self
+            .security
+            .workspace_path
+            .as_deref()
+            .unwrap_or(crate::permissions::DEFAULT_WORKSPACE_PATH);
+        let max_mb = self
+            .sandbox
+            .copy_cwd_max_mb
+            .unwrap_or(crate::cwd_copy::DEFAULT_MAX_MB);
+        crate::cwd_copy::collect(&cwd, dest, max_mb * 1024 * 1024)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The `agentkernel.toml` written by `agentkernel init`
+#[derive(Debug, Clone, Default)]
+pub struct InitTemplate {
+    pub name: String,
+    pub agent: String,
+    /// Image detected from the project files (sets `runtime` or `base_image`)
+    pub detected_image: Option<String>,
+    /// Default backend for the sandbox
+    pub backend: Option<String>,
+    /// Security profile; without one the `[security]` section is commented out
+    pub profile: Option<SecurityProfile>,
+    /// Add a `[network]` section with examples
+    pub with_network: bool,
+}
 
-    #[test]
-    fn test_parse_minimal_config() {
-        let toml = r#"
-            [sandbox]
-            name = "test-app"
-        "#;
-        let config = Config::from_str(toml).unwrap();
+impl InitTemplate {
+    /// Render the config file
+    pub fn render(&self) -> String {
+        let (runtime, base_image) = match self.detected_image.as_deref() {
+            Some(image) => match runtime_for_image(image) {
+                Some(runtime) => (runtime, None),
+                None => ("base", Some(image)),
+            },
+            None => ("base", None),
+        };
+
+        let mut out = String::from(
+            "# Agentkernel configuration\n# See: https://github.com/thrashr888/agentkernel\n\n",
+        );
+
+        out.push_str("[sandbox]\n");
+        out.push_str(&format!("name = {}\n", toml_string(&self.name)));
+        out.push_str(&format!(
+            "runtime = \"{}\"    # base, python, node, go, rust, ruby, java, c, dotnet\n",
+            runtime
+        ));
+        if let Some(image) = base_image {
+            out.push_str(&format!("base_image = \"{}\"\n", image));
+        }
+        match self.backend {
+            Some(ref backend) => out.push_str(&format!(
+                "backend = \"{}\"    # docker, podman, firecracker, apple, hyperlight\n",
+                backend
+            )),
+            None => out.push_str(
+                "# backend = \"docker\"    # docker, podman, firecracker, apple, hyperlight\n",
+            ),
+        }
+
+        out.push_str("\n[agent]\n");
+        out.push_str(&format!(
+            "preferred = {}    # claude, gemini, codex, opencode\n",
+            toml_string(&self.agent)
+        ));
+
+        out.push_str("\n[resources]\nvcpus = 1\nmemory_mb = 512\n");
+
+        // Commented out unless a profile was chosen
+        let (header, profile) = match self.profile {
+            Some(profile) => ("[security]\n", profile),
+            None => ("# [security]\n# ", SecurityProfile::default()),
+        };
+        out.push('\n');
+        out.push_str(header);
+        out.push_str(&format!(
+            "profile = \"{}\"    # permissive, moderate, restrictive\n",
+            profile_name(profile)
+        ));
+        out.push_str("# network = true       # Allow network access\n");
+        out.push_str("# mount_cwd = true     # Mount the current directory at /workspace\n");
+        out.push_str("# mount_home = false   # Mount $HOME read-only at /home/user\n");
+
+        if self.with_network {
+            out.push_str("\n[network]\n");
+            out.push_str("# dns = [\"1.1.1.1\", \"8.8.8.8\"]\n");
+            out.push_str("# extra_hosts = [\"mock.local:10.0.0.5\"]\n");
+            out.push_str("# allowed_hosts = [\"api.anthropic.com\"]    # Only allow traffic to these hosts\n");
+            out.push_str("# package_registries = true    # Also allow PyPI, npm, crates.io, ...\n");
+        }
+
+        out.push_str(&format!("\n{}", MOUNT_SCAFFOLD));
+
+        out
+    }
+}
+
+/// Commented-out `[[mounts]]` example written by `agentkernel init`
+const MOUNT_SCAFFOLD: &str = "\
+# [[mounts]]    # Bind a host file into the sandbox, read-only
+# source = \"~/.aws/credentials\"
+# target = \"/root/.aws/credentials\"
+";
+
+/// A quoted TOML string
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// How a profile is written in the config file
+fn profile_name(profile: SecurityProfile) -> &'static str {
+    match profile {
+        SecurityProfile::Permissive => "permissive",
+        SecurityProfile::Moderate => "moderate",
+        SecurityProfile::Restrictive => "restrictive",
+        SecurityProfile::Custom => "custom",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_minimal_config() {
+        let toml = r#"
+            [sandbox]
+            name = "test-app"
+        "#;
+        let config = Config::from_str(toml).unwrap();
         assert_eq!(config.sandbox.name, "test-app");
         assert_eq!(config.sandbox.runtime, "base");
         assert_eq!(config.agent.preferred, "claude");
@@ -490,6 +1376,7 @@ mod tests {
 
             [network]
             vsock_cid = 5
+            agent_port = 6000
         "#;
         let config = Config::from_str(toml).unwrap();
         assert_eq!(config.sandbox.name, "python-app");
@@ -498,6 +1385,558 @@ mod tests {
         assert_eq!(config.resources.vcpus, 2);
         assert_eq!(config.resources.memory_mb, 1024);
         assert_eq!(config.network.vsock_cid, Some(5));
+        assert_eq!(config.network.agent_port, Some(6000));
+        assert_eq!(config.get_permissions().agent_port, Some(6000));
+    }
+
+    #[test]
+    fn test_parse_network_dns_and_hosts() {
+        let toml = r#"
+            [sandbox]
+            name = "mock-api"
+
+            [network]
+            dns = ["1.1.1.1", "2606:4700:4700::1111"]
+            extra_hosts = ["mock.local:10.0.0.5", "v6.local:fd00::5"]
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let perms = config.get_permissions();
+        assert_eq!(perms.dns, vec!["1.1.1.1", "2606:4700:4700::1111"]);
+        assert_eq!(
+            perms.extra_hosts,
+            vec![
+                ("mock.local".to_string(), "10.0.0.5".parse().unwrap()),
+                ("v6.local".to_string(), "fd00::5".parse().unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_network_rejects_invalid_addresses() {
+        let bad_dns = r#"
+            [sandbox]
+            name = "bad"
+            [network]
+            dns = ["dns.google"]
+        "#;
+        let err = Config::from_str(bad_dns).unwrap_err().to_string();
+        assert!(err.contains("Invalid DNS server"), "{}", err);
+
+        assert!(parse_extra_host("mock.local").is_err());
+        assert!(parse_extra_host("mock.local:not-an-ip").is_err());
+        assert!(parse_extra_host(":10.0.0.5").is_err());
+        assert!(parse_extra_host("bad host:10.0.0.5").is_err());
+    }
+
+    #[test]
+    fn test_require_image_digest_rejects_floating_tag() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            base_image = "python:latest"
+            [security]
+            require_image_digest = true
+        "#;
+        let err = Config::from_str(toml).unwrap_err();
+        assert!(err.to_string().contains("not pinned"));
+
+        // Runtime shorthands resolve to tags too
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            runtime = "python"
+            [security]
+            require_image_digest = true
+        "#;
+        assert!(Config::from_str(toml).is_err());
+
+        let pinned = format!("python@sha256:{}", "0".repeat(64));
+        let toml = format!(
+            "[sandbox]\nname = \"app\"\nbase_image = \"{}\"\n[security]\nrequire_image_digest = true\n",
+            pinned
+        );
+        let config = Config::from_str(&toml).unwrap();
+        assert!(config.check_image_pinned(&pinned).is_ok());
+        assert!(config.check_image_pinned("alpine:3.20").is_err());
+
+        // Off by default
+        assert!(
+            Config::minimal("app", "claude")
+                .check_image_pinned("python:latest")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_parse_firecracker_scratch() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [firecracker]
+            scratch_mb = 1024
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.get_permissions().scratch_mb, Some(1024));
+
+        let minimal = Config::minimal("app", "claude");
+        assert_eq!(minimal.get_permissions().scratch_mb, None);
+
+        assert!(Config::from_str(&toml.replace("1024", "0")).is_err());
+    }
+
+    #[test]
+    fn test_parse_sandbox_hostname() {
+        let toml = r#"
+            [sandbox]
+            name = "svc"
+            hostname = "ci-runner"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.sandbox.hostname.as_deref(), Some("ci-runner"));
+        assert_eq!(
+            config.get_permissions().hostname.as_deref(),
+            Some("ci-runner")
+        );
+
+        let err = Config::from_str(&toml.replace("ci-runner", "ci_runner")).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid hostname"));
+    }
+
+    #[test]
+    fn test_parse_sandbox_max_output_bytes() {
+        let toml = r#"
+            [sandbox]
+            name = "svc"
+            max_output_bytes = 65536
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.sandbox.max_output_bytes, Some(65536));
+
+        let err = Config::from_str(&toml.replace("65536", "0")).unwrap_err();
+        assert!(err.to_string().contains("max_output_bytes"));
+
+        let toml = toml.replace("max_output_bytes", "max_upload_bytes");
+        let config = Config::from_str(&toml).unwrap();
+        assert_eq!(config.sandbox.max_upload_bytes, Some(65536));
+        let err = Config::from_str(&toml.replace("65536", "0")).unwrap_err();
+        assert!(err.to_string().contains("max_upload_bytes"));
+    }
+
+    #[test]
+    fn test_parse_sandbox_init() {
+        let toml = r#"
+            [sandbox]
+            name = "svc"
+            init = false
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.sandbox.init, Some(false));
+        assert_eq!(config.get_permissions().init, Some(false));
+
+        // Unset leaves the choice to the backend
+        assert_eq!(
+            Config::minimal("app", "claude").get_permissions().init,
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_sandbox_restart() {
+        use crate::backend::RestartPolicy;
+
+        let toml = r#"
+            [sandbox]
+            name = "svc"
+            restart = "on-failure"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.sandbox.restart, RestartPolicy::OnFailure);
+        assert_eq!(config.get_permissions().restart, RestartPolicy::OnFailure);
+
+        let minimal = Config::minimal("app", "claude");
+        assert_eq!(minimal.get_permissions().restart, RestartPolicy::Never);
+
+        assert!(Config::from_str(&toml.replace("on-failure", "sometimes")).is_err());
+    }
+
+    #[test]
+    fn test_setup_script_inline_or_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("setup.sh"), "apk add git\n").unwrap();
+
+        let config = Config::from_str(
+            r#"
+            [sandbox]
+            name = "app"
+            setup_script = "setup.sh"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.setup_script(dir.path()).unwrap().as_deref(),
+            Some("apk add git\n")
+        );
+
+        let config = Config::from_str(
+            r#"
+            [sandbox]
+            name = "app"
+            setup_script = """
+            pip install -r requirements.txt
+            git clone https://example.com/repo.git
+            """
+        "#,
+        )
+        .unwrap();
+        let script = config.setup_script(dir.path()).unwrap().unwrap();
+        assert!(script.contains("pip install"));
+        assert!(script.contains("git clone"));
+
+        // A single line that isn't a file is run as a command
+        let config = Config::from_str(
+            r#"
+            [sandbox]
+            name = "app"
+            setup_script = "npm ci"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.setup_script(dir.path()).unwrap().as_deref(),
+            Some("npm ci")
+        );
+
+        let minimal = Config::minimal("app", "claude");
+        assert_eq!(minimal.setup_script(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_network_egress() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [network]
+            allowed_hosts = ["api.anthropic.com"]
+            allowed_cidrs = ["10.0.0.0/8"]
+            package_registries = true
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let egress = config.get_permissions().egress.unwrap();
+        assert_eq!(egress.hosts[0], "api.anthropic.com");
+        assert!(egress.hosts.contains(&"pypi.org".to_string()));
+        assert_eq!(egress.cidrs, vec!["10.0.0.0/8"]);
+
+        let minimal = Config::minimal("app", "claude");
+        assert!(minimal.get_permissions().egress.is_none());
+
+        assert!(Config::from_str(&toml.replace("10.0.0.0/8", "10.0.0.0/99")).is_err());
+        assert!(Config::from_str(&toml.replace("api.anthropic.com", "*.anthropic.com")).is_err());
+    }
+
+    #[test]
+    fn test_preset_resolution() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            runtime = "node"
+            [resources]
+            memory_mb = 1024
+            [presets.ci]
+            runtime = "python"
+            vcpus = 4
+            profile = "restrictive"
+            network = true
+            env = { CI = "1" }
+            [presets.quick]
+        "#;
+        let config = Config::from_str(toml).unwrap();
+
+        let ci = config.preset("ci").unwrap();
+        assert_eq!(ci.image, "python:3.12-alpine");
+        assert_eq!(ci.vcpus, 4);
+        // Unset fields fall back to the rest of the config
+        assert_eq!(ci.memory_mb, 1024);
+        assert_eq!(ci.permissions.max_memory_mb, Some(1024));
+        assert_eq!(ci.permissions.max_cpu_percent, Some(400));
+        // The profile applies, with the preset's own settings on top
+        assert!(ci.permissions.read_only_root);
+        assert!(ci.permissions.network);
+        assert_eq!(
+            ci.permissions.env,
+            vec![("CI".to_string(), "1".to_string())]
+        );
+
+        let quick = config.preset("quick").unwrap();
+        assert_eq!(quick.image, "node:22-alpine");
+        assert_eq!(quick.vcpus, 1);
+
+        let err = config.preset("missing").unwrap_err().to_string();
+        assert!(err.contains("ci, quick"));
+        assert!(Config::from_str(&toml.replace("vcpus = 4", "vcpus = 0")).is_err());
+        assert!(Config::from_str(&toml.replace("vcpus = 4", "cpus = 4")).is_err());
+    }
+
+    #[test]
+    fn test_resources_resolve() {
+        let config = Config::from_str(
+            r#"
+            [sandbox]
+            name = "app"
+            [resources]
+            vcpus = 2
+            memory_mb = 2048
+            [presets.big]
+            memory_mb = 8192
+        "#,
+        )
+        .unwrap();
+        let resources = &config.resources;
+        assert_eq!(resources.resolve(None, None, None), (2, 2048));
+        assert_eq!(resources.resolve(Some(4), None, None), (4, 2048));
+
+        // The preset fills in from [resources], and flags win over both
+        let preset = config.preset("big").unwrap();
+        assert_eq!(resources.resolve(None, None, Some(&preset)), (2, 8192));
+        assert_eq!(
+            resources.resolve(None, Some(1024), Some(&preset)),
+            (2, 1024)
+        );
+
+        assert_eq!(
+            ResourcesConfig::default().resolve(None, None, None),
+            (1, 512)
+        );
+    }
+
+    #[test]
+    fn test_preset_flags_take_precedence() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [presets.ci]
+            image = "python:3.12-alpine"
+            profile = "permissive"
+            user = "1000:1000"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let preset = &config.presets["ci"];
+
+        let mut ci = config.preset("ci").unwrap();
+        ci.apply_flags(
+            &PresetFlags {
+                image: Some("node:22-alpine".to_string()),
+                profile: Some(SecurityProfile::Restrictive),
+                no_network: false,
+                user: Some("current".to_string()),
+            },
+            preset,
+        );
+        assert_eq!(ci.image, "node:22-alpine");
+        assert!(ci.permissions.read_only_root);
+        assert!(!ci.permissions.mount_home);
+        assert_eq!(ci.permissions.user.as_deref(), Some("current"));
+        // The preset's resources still apply under the new profile
+        assert_eq!(ci.permissions.max_memory_mb, Some(512));
+
+        let mut ci = config.preset("ci").unwrap();
+        ci.apply_flags(
+            &PresetFlags {
+                no_network: true,
+                ..Default::default()
+            },
+            preset,
+        );
+        assert_eq!(ci.image, "python:3.12-alpine");
+        assert!(!ci.permissions.network);
+        assert_eq!(ci.permissions.user.as_deref(), Some("1000:1000"));
+    }
+
+    #[test]
+    fn test_parse_security_user() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [security]
+            profile = "moderate"
+            user = "1000:1000"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(config.get_permissions().user.as_deref(), Some("1000:1000"));
+
+        let bad = toml.replace("1000:1000", "root;id");
+        assert!(Config::from_str(&bad).is_err());
+    }
+
+    #[test]
+    fn test_parse_security_network_mode() {
+        use crate::permissions::NetworkMode;
+
+        let config = |security: &str| {
+            Config::from_str(&format!(
+                "[sandbox]\nname = \"app\"\n[security]\n{}",
+                security
+            ))
+        };
+        let mode = |security: &str| config(security).unwrap().get_permissions().network_mode();
+
+        // The boolean still works on its own
+        assert_eq!(mode("network = false"), NetworkMode::None);
+        assert_eq!(mode("network = true"), NetworkMode::Bridge);
+        assert_eq!(mode("profile = \"restrictive\""), NetworkMode::None);
+
+        // The mode supersedes both the boolean and the profile
+        assert_eq!(
+            mode("network = false\nnetwork_mode = \"host\""),
+            NetworkMode::Host
+        );
+        assert_eq!(
+            mode("profile = \"restrictive\"\nnetwork_mode = \"ci-net\""),
+            NetworkMode::Named("ci-net".to_string())
+        );
+        assert_eq!(
+            mode("network = true\nnetwork_mode = \"none\""),
+            NetworkMode::None
+        );
+        assert!(
+            !config("network_mode = \"none\"")
+                .unwrap()
+                .get_permissions()
+                .network
+        );
+
+        assert!(config("network_mode = \"container:db\"").is_err());
+
+        // Host networking comes with a warning
+        let warnings = config("network_mode = \"host\"").unwrap().validate();
+        assert!(warnings.iter().any(|w| w.contains("host's network stack")));
+        assert!(
+            config("network_mode = \"bridge\"")
+                .unwrap()
+                .validate()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_size_mb() {
+        assert_eq!(parse_size_mb("256m").unwrap(), 256);
+        assert_eq!(parse_size_mb("256M").unwrap(), 256);
+        assert_eq!(parse_size_mb("64mb").unwrap(), 64);
+        assert_eq!(parse_size_mb("2g").unwrap(), 2048);
+        assert_eq!(parse_size_mb("128").unwrap(), 128);
+        for bad in ["", "0m", "m", "-1m", "256k", "1.5g", "lots"] {
+            assert!(parse_size_mb(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_security_tmp_size() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [security]
+            profile = "moderate"
+            tmp_size = "256m"
+            run_size = "16m"
+        "#;
+        let perms = Config::from_str(toml).unwrap().get_permissions();
+        assert_eq!(perms.tmp_size_mb, Some(256));
+        assert_eq!(perms.run_size_mb, Some(16));
+
+        // The restrictive profile bounds /tmp by default
+        let restrictive = Config::from_str(
+            r#"
+            [sandbox]
+            name = "app"
+            [security]
+            profile = "restrictive"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(restrictive.get_permissions().tmp_size_mb, Some(64));
+        assert_eq!(
+            Config::minimal("app", "claude")
+                .get_permissions()
+                .tmp_size_mb,
+            None
+        );
+
+        assert!(Config::from_str(&toml.replace("256m", "big")).is_err());
+    }
+
+    #[test]
+    fn test_parse_security_ulimits() {
+        use crate::permissions::UlimitPair;
+
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [security]
+            profile = "restrictive"
+            [security.ulimits]
+            nofile = "2048:8192"
+            fsize = 1073741824
+        "#;
+        let ulimits = Config::from_str(toml).unwrap().get_permissions().ulimits;
+        assert_eq!(ulimits.nofile, Some(UlimitPair::new(2048, 8192)));
+        assert_eq!(ulimits.fsize, Some(UlimitPair::new(1 << 30, 1 << 30)));
+        // Limits not given keep the profile's
+        assert_eq!(ulimits.core, Some(UlimitPair::new(0, 0)));
+
+        // Soft above hard, a bad value, or an unknown limit
+        for bad in [r#""8192:2048""#, r#""many""#] {
+            assert!(Config::from_str(&toml.replace(r#""2048:8192""#, bad)).is_err());
+        }
+        assert!(Config::from_str(&toml.replace("fsize", "stack")).is_err());
+    }
+
+    #[test]
+    fn test_parse_security_gpus() {
+        use crate::permissions::Gpus;
+
+        let toml = r#"
+            [sandbox]
+            name = "app"
+            [security]
+            gpus = "all"
+        "#;
+        let gpus = |toml: &str| Config::from_str(toml).unwrap().get_permissions().gpus;
+        assert_eq!(gpus(toml), Some(Gpus::All));
+        assert_eq!(gpus(&toml.replace(r#""all""#, "2")), Some(Gpus::Count(2)));
+        assert_eq!(
+            gpus(&toml.replace("all", "device=0,1")),
+            Some(Gpus::Devices(vec!["0".into(), "1".into()]))
+        );
+        assert_eq!(gpus(&toml.replace(r#"gpus = "all""#, "")), None);
+
+        assert!(Config::from_str(&toml.replace(r#""all""#, "0")).is_err());
+        assert!(Config::from_str(&toml.replace("all", "some")).is_err());
+    }
+
+    #[test]
+    fn test_parse_mounts() {
+        let toml = r#"
+            [sandbox]
+            name = "app"
+
+            [[mounts]]
+            source = "~/.aws/credentials"
+            target = "/root/.aws/credentials"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let mounts = config.get_permissions().mounts;
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].source, "~/.aws/credentials");
+        assert_eq!(mounts[0].target, "/root/.aws/credentials");
+
+        // Relative paths, `..` and `:` are rejected
+        for (from, to) in [
+            ("~/.aws/credentials", "creds"),
+            ("/root/.aws/credentials", "../credentials"),
+            ("/root/.aws/credentials", "/root/a:b"),
+        ] {
+            assert!(Config::from_str(&toml.replace(from, to)).is_err());
+        }
     }
 
     #[test]
@@ -627,6 +2066,59 @@ mod tests {
         assert!(!perms.network); // Overridden by [security]
     }
 
+    #[test]
+    fn test_compat_override_changes_only_specified_field() {
+        use crate::permissions::CompatibilityMode;
+
+        let toml = r#"
+            [sandbox]
+            name = "codex-offline"
+
+            [agent]
+            compatibility_mode = "codex"
+
+            [compat.codex]
+            network = false
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let base = CompatibilityMode::Codex.profile().permissions;
+        let perms = config.get_permissions();
+
+        assert!(base.network);
+        assert!(!perms.network); // Overridden
+        assert_eq!(perms.mount_cwd, base.mount_cwd);
+        assert_eq!(perms.read_only_root, base.read_only_root);
+        assert_eq!(perms.max_memory_mb, base.max_memory_mb);
+        assert_eq!(perms.seccomp, base.seccomp);
+    }
+
+    #[test]
+    fn test_compat_override_other_mode_untouched() {
+        use crate::permissions::CompatibilityMode;
+
+        let toml = r#"
+            [sandbox]
+            name = "multi-agent"
+
+            [compat.claude-code]
+            mount_home = true
+        "#;
+        let config = Config::from_str(toml).unwrap();
+
+        // Aliases resolve to the same mode
+        assert!(
+            config
+                .compat_permissions(CompatibilityMode::ClaudeCode)
+                .mount_home
+        );
+        // Other modes keep their presets
+        let gemini = config.compat_permissions(CompatibilityMode::Gemini);
+        assert_eq!(
+            gemini.mount_home,
+            CompatibilityMode::Gemini.profile().permissions.mount_home
+        );
+    }
+
     #[test]
     fn test_domain_config_allow() {
         let config = DomainConfig {
@@ -758,6 +2250,36 @@ mod tests {
         assert_eq!(config.security.seccomp, Some("default".to_string()));
     }
 
+    #[test]
+    fn test_security_config_writable_workspace() {
+        let toml = r#"
+            [sandbox]
+            name = "locked-down"
+
+            [security]
+            profile = "moderate"
+            read_only = true
+            writable_workspace = true
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let perms = config.get_permissions();
+        assert!(perms.read_only_root);
+        assert_eq!(perms.writable_workspace, Some("/workspace".to_string()));
+
+        let toml = r#"
+            [sandbox]
+            name = "locked-down"
+
+            [security]
+            profile = "restrictive"
+            writable_workspace = true
+            workspace_path = "/data"
+        "#;
+        let config = Config::from_str(toml).unwrap();
+        let perms = config.get_permissions();
+        assert_eq!(perms.writable_workspace, Some("/data".to_string()));
+    }
+
     #[test]
     fn test_domain_config_has_rules() {
         let empty = DomainConfig::default();
@@ -804,4 +2326,143 @@ mod tests {
         let warnings = config.validate();
         assert!(warnings.is_empty());
     }
+
+    #[test]
+    fn test_audit_section_read_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agentkernel.toml");
+        std::fs::write(
+            &path,
+            r#"
+                [sandbox]
+                name = "ci"
+
+                [audit]
+                enabled = false
+                path = "/tmp/ci-audit.jsonl"
+            "#,
+        )
+        .unwrap();
+
+        let audit = AuditConfig::from_file(&path);
+        assert_eq!(audit.enabled, Some(false));
+        assert_eq!(audit.path, Some(PathBuf::from("/tmp/ci-audit.jsonl")));
+
+        // A missing file gives the defaults
+        let audit = AuditConfig::from_file(&dir.path().join("missing.toml"));
+        assert!(audit.enabled.is_none() && audit.path.is_none());
+    }
+
+    #[test]
+    fn test_firecracker_section_read_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agentkernel.toml");
+        std::fs::write(
+            &path,
+            r#"
+                [sandbox]
+                name = "ci"
+
+                [firecracker]
+                strict_agent_version = true
+            "#,
+        )
+        .unwrap();
+
+        let firecracker = FirecrackerConfig::from_file(&path);
+        assert_eq!(firecracker.strict_agent_version, Some(true));
+
+        let firecracker = FirecrackerConfig::from_file(&dir.path().join("missing.toml"));
+        assert!(firecracker.strict_agent_version.is_none());
+    }
+
+    #[test]
+    fn test_init_template_minimal_parses() {
+        let template = InitTemplate {
+            name: "my-app".to_string(),
+            agent: "claude".to_string(),
+            ..Default::default()
+        };
+        let content = template.render();
+        let config = Config::from_str(&content).unwrap();
+        assert_eq!(config.sandbox.name, "my-app");
+        assert_eq!(config.sandbox.runtime, "base");
+        assert!(config.sandbox.backend.is_none());
+        assert!(content.contains("# [security]"));
+        assert!(!content.contains("[network]"));
+        assert!(config.mounts.is_empty());
+    }
+
+    #[test]
+    fn test_init_template_mount_scaffold() {
+        let template = InitTemplate {
+            name: "my-app".to_string(),
+            agent: "claude".to_string(),
+            ..Default::default()
+        };
+        let content = template.render();
+        assert!(content.contains(MOUNT_SCAFFOLD));
+
+        // Uncommented, the example is a valid mount
+        let uncommented = content.replace(
+            MOUNT_SCAFFOLD,
+            &MOUNT_SCAFFOLD.replace("# [[", "[[").replace("\n# ", "\n"),
+        );
+        let config = Config::from_str(&uncommented).unwrap();
+        assert_eq!(config.mounts.len(), 1);
+        assert_eq!(config.mounts[0].source, "~/.aws/credentials");
+        assert_eq!(config.mounts[0].target, "/root/.aws/credentials");
+    }
+
+    #[test]
+    fn test_init_template_with_sections_parses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agentkernel.toml");
+        let template = InitTemplate {
+            name: "api".to_string(),
+            agent: "codex".to_string(),
+            detected_image: Some("rust:1.85-alpine".to_string()),
+            backend: Some("firecracker".to_string()),
+            profile: Some(SecurityProfile::Restrictive),
+            with_network: true,
+        };
+        let content = template.render();
+        std::fs::write(&path, &content).unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.sandbox.runtime, "rust");
+        assert_eq!(config.sandbox.backend.as_deref(), Some("firecracker"));
+        assert_eq!(config.agent.preferred, "codex");
+        assert_eq!(config.security.profile, SecurityProfile::Restrictive);
+        assert!(content.contains("\n[security]\n"));
+        assert!(content.contains("\n[network]\n"));
+    }
+
+    #[test]
+    fn test_init_template_image_without_runtime() {
+        let template = InitTemplate {
+            name: "site".to_string(),
+            agent: "claude".to_string(),
+            detected_image: Some("php:8.3-alpine".to_string()),
+            ..Default::default()
+        };
+        let config = Config::from_str(&template.render()).unwrap();
+        assert_eq!(config.sandbox.runtime, "base");
+        assert_eq!(config.docker_image(), "php:8.3-alpine");
+    }
+
+    #[test]
+    fn test_invalid_sandbox_backend_rejected() {
+        let err = Config::from_str("[sandbox]\nname = \"x\"\nbackend = \"qemu\"\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid backend"), "{}", err);
+    }
+
+    #[test]
+    fn test_sandbox_shell() {
+        let config = Config::from_str("[sandbox]\nname = \"x\"\nshell = \"/bin/bash\"\n").unwrap();
+        assert_eq!(config.sandbox.shell.as_deref(), Some("/bin/bash"));
+
+        let err = Config::from_str("[sandbox]\nname = \"x\"\nshell = \"bash\"\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid shell"), "{}", err);
+    }
 }
@@ -0,0 +1,251 @@
+//! Copying the working directory into a sandbox (`[sandbox] copy_cwd`).
+//!
+//! Backends that can't bind-mount the host (Firecracker) get the current
+//! directory as file injections, written to the workspace at start. Paths
+//! matched by `.agentkernelignore` are left out, and the total size is capped.
+
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+use crate::backend::FileInjection;
+
+/// Ignore file read from the top of the copied directory
+pub const IGNORE_FILE: &str = ".agentkernelignore";
+
+/// Left out when there's no ignore file
+pub const DEFAULT_IGNORES: &[&str] = &[".git", "node_modules", "target"];
+
+/// Default size limit for a copy (MB)
+pub const DEFAULT_MAX_MB: u64 = 100;
+
+/// Paths to leave out, in a gitignore-like syntax.
+///
+/// Each line is a pattern; `*` and `?` match within one path component.
+/// A pattern without a `/` matches a file or directory of that name at any
+/// depth, a leading `/` (or a `/` in the middle) anchors it to the copied
+/// directory, and a trailing `/` matches only directories. Blank lines and
+/// `#` comments are skipped. Negation (`!`) is not supported.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// Parse the contents of an ignore file
+    pub fn parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let dir_only = line.ends_with('/');
+                let line = line.trim_end_matches('/');
+                let anchored = line.contains('/');
+                let glob = line.trim_start_matches('/');
+                (!glob.is_empty()).then(|| Pattern {
+                    glob: glob.to_string(),
+                    anchored,
+                    dir_only,
+                })
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Rules from `dir`'s ignore file, or [`DEFAULT_IGNORES`] if it has none
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(IGNORE_FILE);
+        if !path.exists() {
+            return Ok(Self::parse(&DEFAULT_IGNORES.join("\n")));
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::parse(&content))
+    }
+
+    /// Whether a `/`-separated path relative to the copied directory is ignored
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        let name = rel.rsplit('/').next().unwrap_or(rel);
+        self.patterns.iter().any(|p| {
+            (is_dir || !p.dir_only) && glob_match(&p.glob, if p.anchored { rel } else { name })
+        })
+    }
+}
+
+/// Match `text` against a glob where `*` and `?` don't cross `/`
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // Position after the last `*`, and the text position it's matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == t[ti] || (p[pi] == '?' && t[ti] != '/')) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi + 1, ti));
+            pi += 1;
+        } else if let Some((after_star, matched)) = star.filter(|&(_, m)| t[m] != '/') {
+            star = Some((after_star, matched + 1));
+            pi = after_star;
+            ti = matched + 1;
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Read `dir` into injections under `dest`, skipping ignored paths and symlinks.
+///
+/// Fails once the files read add up to more than `max_bytes`.
+pub fn collect(dir: &Path, dest: &str, max_bytes: u64) -> Result<Vec<FileInjection>> {
+    let rules = IgnoreRules::load(dir)?;
+    let dest = dest.trim_end_matches('/');
+    let mut files = Vec::new();
+    let mut total = 0u64;
+    let mut pending = vec![String::new()];
+
+    while let Some(rel_dir) = pending.pop() {
+        let path = dir.join(&rel_dir);
+        let mut entries = std::fs::read_dir(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let rel = if rel_dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", rel_dir, name)
+            };
+            let meta = std::fs::symlink_metadata(entry.path())?;
+            if meta.is_dir() {
+                if !rules.is_ignored(&rel, true) {
+                    pending.push(rel);
+                }
+                continue;
+            }
+            if !meta.is_file() || rules.is_ignored(&rel, false) {
+                continue;
+            }
+
+            total += meta.len();
+            if total > max_bytes {
+                bail!(
+                    "Working directory is larger than the copy_cwd limit of {} MB (reached at {}). \
+                     Exclude large paths in {} or raise [sandbox] copy_cwd_max_mb",
+                    max_bytes / (1024 * 1024),
+                    rel,
+                    IGNORE_FILE
+                );
+            }
+            let content = std::fs::read(entry.path())
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            files.push(FileInjection {
+                content,
+                dest: format!("{}/{}", dest, rel),
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dests(files: &[FileInjection]) -> Vec<&str> {
+        let mut dests: Vec<&str> = files.iter().map(|f| f.dest.as_str()).collect();
+        dests.sort();
+        dests
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "debug.log"));
+        assert!(glob_match("build?", "build2"));
+        assert!(glob_match("src/*.rs", "src/main.rs"));
+        assert!(!glob_match("src/*.rs", "src/nested/main.rs"));
+        assert!(!glob_match("*.log", "debug.txt"));
+        assert!(glob_match("node_modules", "node_modules"));
+    }
+
+    #[test]
+    fn test_ignore_file_filters_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in [
+            "src/main.rs",
+            "src/app.log",
+            "node_modules/left-pad/index.js",
+            "dist/bundle.js",
+            "web/dist/keep.js",
+            "build",
+            "README.md",
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x").unwrap();
+        }
+        std::fs::write(
+            root.join(IGNORE_FILE),
+            "# deps\nnode_modules\n*.log\n/dist\nbuild/\n",
+        )
+        .unwrap();
+
+        let files = collect(root, "/workspace/", 1024).unwrap();
+        assert_eq!(
+            dests(&files),
+            vec![
+                "/workspace/.agentkernelignore",
+                "/workspace/README.md",
+                // `build/` only matches directories
+                "/workspace/build",
+                "/workspace/src/main.rs",
+                // `/dist` is anchored to the top
+                "/workspace/web/dist/keep.js",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_ignores_without_ignore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in [".git/HEAD", "target/debug/app", "Cargo.toml"] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x").unwrap();
+        }
+
+        let files = collect(root, "/workspace", 1024).unwrap();
+        assert_eq!(dests(&files), vec!["/workspace/Cargo.toml"]);
+    }
+
+    #[test]
+    fn test_copy_over_size_cap_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("small.txt"), vec![b'a'; 600 * 1024]).unwrap();
+        std::fs::write(dir.path().join("data.bin"), vec![0u8; 600 * 1024]).unwrap();
+
+        let err = collect(dir.path(), "/workspace", 1024 * 1024).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("copy_cwd limit of 1 MB"), "{}", message);
+        assert!(message.contains(IGNORE_FILE), "{}", message);
+
+        // Ignoring the large file brings it under the cap
+        std::fs::write(dir.path().join(IGNORE_FILE), "*.bin\n").unwrap();
+        let files = collect(dir.path(), "/workspace", 1024 * 1024).unwrap();
+        assert_eq!(files.len(), 2);
+    }
+}
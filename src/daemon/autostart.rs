@@ -0,0 +1,186 @@
+//! Starting the daemon automatically from `agentkernel run --auto-daemon`.
+
+use anyhow::{Context, Result};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::server::DaemonServer;
+
+/// Minimum time between auto-start attempts, so a daemon that fails to
+/// come up isn't respawned on every run
+pub const COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long to wait for a freshly spawned daemon to accept connections
+const STARTUP_WAIT: Duration = Duration::from_secs(5);
+
+/// Outcome of [`ensure_running`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum AutoStart {
+    /// The daemon was already up
+    Running,
+    /// The daemon was spawned and is accepting connections
+    Started,
+    /// The daemon wasn't started (or didn't come up in time)
+    Skipped(&'static str),
+}
+
+/// Whether `AGENTKERNEL_AUTO_DAEMON` asks for auto-start
+pub fn enabled_by_env() -> bool {
+    std::env::var("AGENTKERNEL_AUTO_DAEMON")
+        .is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "yes"))
+}
+
+/// Make sure the daemon is running, calling `spawn` to start it if needed.
+///
+/// Nothing is spawned without KVM or within [`COOLDOWN`] of the last attempt.
+pub async fn ensure_running(
+    socket_path: &Path,
+    kvm_available: bool,
+    spawn: impl FnOnce() -> Result<()>,
+) -> Result<AutoStart> {
+    if DaemonServer::is_running(socket_path) {
+        return Ok(AutoStart::Running);
+    }
+    if !kvm_available {
+        return Ok(AutoStart::Skipped("KVM is not available"));
+    }
+
+    let stamp = sibling(socket_path, "daemon-autostart");
+    if within_cooldown(&stamp, SystemTime::now()) {
+        return Ok(AutoStart::Skipped("a start was attempted recently"));
+    }
+    // Record the attempt first so a daemon that crashes on startup still backs off
+    std::fs::write(&stamp, b"").context("Failed to record daemon start")?;
+    spawn()?;
+
+    let deadline = Instant::now() + STARTUP_WAIT;
+    while Instant::now() < deadline {
+        if DaemonServer::is_running(socket_path) {
+            return Ok(AutoStart::Started);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(AutoStart::Skipped("the daemon did not start in time"))
+}
+
+/// Spawn `agentkernel daemon start` detached from this process.
+///
+/// Output goes to `daemon.log` next to the socket.
+pub fn spawn_daemon(socket_path: &Path) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to find the agentkernel binary")?;
+    let log = std::fs::File::create(log_path(socket_path))?;
+    let mut command = Command::new(exe);
+    command
+        .args(["daemon", "start"])
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Own process group, so Ctrl-C in this terminal doesn't stop the daemon
+    #[cfg(unix)]
+    command.process_group(0);
+    command.spawn().context("Failed to start the daemon")?;
+    Ok(())
+}
+
+/// Log file for an auto-started daemon
+pub fn log_path(socket_path: &Path) -> PathBuf {
+    sibling(socket_path, "daemon.log")
+}
+
+/// Returns true the first time it's called for this socket directory, so
+/// the "start the daemon" hint is only shown once.
+pub fn take_hint(socket_path: &Path) -> bool {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(sibling(socket_path, "daemon-hint-shown"))
+        .is_ok()
+}
+
+/// Whether the last attempt (the stamp's mtime) is within the cooldown
+fn within_cooldown(stamp: &Path, now: SystemTime) -> bool {
+    std::fs::metadata(stamp)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|at| now.duration_since(at).ok())
+        .is_some_and(|elapsed| elapsed < COOLDOWN)
+}
+
+fn sibling(socket_path: &Path, name: &str) -> PathBuf {
+    socket_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::os::unix::net::UnixListener;
+
+    #[tokio::test]
+    async fn test_not_running_is_started_then_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+
+        // The "daemon" comes up by binding the socket
+        let spawned = Cell::new(0);
+        let listener = std::cell::RefCell::new(None);
+        let outcome = ensure_running(&socket, true, || {
+            spawned.set(spawned.get() + 1);
+            *listener.borrow_mut() = Some(UnixListener::bind(&socket)?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(outcome, AutoStart::Started);
+        assert_eq!(spawned.get(), 1);
+
+        // Later runs use it without spawning again
+        let outcome = ensure_running(&socket, true, || panic!("spawned twice"))
+            .await
+            .unwrap();
+        assert_eq!(outcome, AutoStart::Running);
+    }
+
+    #[tokio::test]
+    async fn test_cooldown_and_kvm_skip_spawn() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+
+        let outcome = ensure_running(&socket, false, || panic!("spawned without KVM"))
+            .await
+            .unwrap();
+        assert!(matches!(outcome, AutoStart::Skipped(_)));
+
+        // A recent attempt blocks another spawn
+        std::fs::write(dir.path().join("daemon-autostart"), b"").unwrap();
+        let outcome = ensure_running(&socket, true, || panic!("spawned during cooldown"))
+            .await
+            .unwrap();
+        assert!(matches!(outcome, AutoStart::Skipped(_)));
+    }
+
+    #[test]
+    fn test_within_cooldown() {
+        let dir = tempfile::tempdir().unwrap();
+        let stamp = dir.path().join("daemon-autostart");
+        assert!(!within_cooldown(&stamp, SystemTime::now()));
+
+        std::fs::write(&stamp, b"").unwrap();
+        assert!(within_cooldown(&stamp, SystemTime::now()));
+        assert!(!within_cooldown(
+            &stamp,
+            SystemTime::now() + COOLDOWN + Duration::from_secs(1)
+        ));
+    }
+
+    #[test]
+    fn test_take_hint_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        assert!(take_hint(&socket));
+        assert!(!take_hint(&socket));
+    }
+}
@@ -1,11 +1,12 @@
 //! Daemon client for CLI to connect to the daemon.
 
 use anyhow::{Result, bail};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 
-use super::protocol::{DaemonCompatibilityMode, DaemonRequest, DaemonResponse};
+use super::protocol::{BootLatency, DaemonCompatibilityMode, DaemonRequest, DaemonResponse};
 use super::server::DaemonServer;
 
 /// Client for connecting to the daemon
@@ -28,6 +29,17 @@ pub struct RunResult {
     pub stderr: String,
 }
 
+/// Pool status reported by the daemon
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PoolStatus {
+    pub warm: usize,
+    pub in_use: usize,
+    pub min_warm: usize,
+    pub max_warm: usize,
+    /// Boot latency per runtime
+    pub boot_latency: HashMap<String, BootLatency>,
+}
+
 impl DaemonClient {
     /// Create a new client with default socket path
     pub fn new() -> Self {
@@ -128,7 +140,7 @@ impl DaemonClient {
     }
 
     /// Get daemon status
-    pub async fn status(&self) -> Result<(usize, usize, usize, usize)> {
+    pub async fn status(&self) -> Result<PoolStatus> {
         let request = DaemonRequest::Status;
 
         match self.send_request(&request).await? {
@@ -137,8 +149,15 @@ impl DaemonClient {
                 in_use,
                 min_warm,
                 max_warm,
+                boot_latency,
                 ..
-            } => Ok((warm, in_use, min_warm, max_warm)),
+            } => Ok(PoolStatus {
+                warm,
+                in_use,
+                min_warm,
+                max_warm,
+                boot_latency,
+            }),
             DaemonResponse::Error { message } => {
                 bail!("Daemon error: {}", message)
             }
@@ -3,12 +3,15 @@
 //! The daemon maintains a pool of pre-warmed Firecracker VMs for fast execution.
 //! The CLI connects to the daemon via Unix socket to acquire VMs from the pool.
 
+pub mod autostart;
 mod client;
 mod health;
 mod pool;
 mod protocol;
 mod server;
+mod snapshot;
 
-pub use client::DaemonClient;
-pub use pool::PoolConfig;
+pub use client::{DaemonClient, PoolStatus};
+pub use pool::{AgentPoolConfig, PoolConfig};
 pub use server::DaemonServer;
+pub use snapshot::default_dir as default_snapshot_dir;
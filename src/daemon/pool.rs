@@ -2,16 +2,20 @@
 
 use anyhow::{Context, Result, bail};
 use std::collections::{HashMap, VecDeque};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::{Mutex, Notify, Semaphore};
 use tokio::time::sleep;
 
+use super::protocol::BootLatency;
+use super::snapshot::{self, Manifest, SavedVm};
+use crate::backend::SandboxConfig;
+use crate::backend::firecracker::boot_args;
 use crate::firecracker_client::{BootSource, Drive, FirecrackerClient, MachineConfig, VsockDevice};
 use crate::permissions::CompatibilityMode;
-use crate::vsock::VsockClient;
+use crate::vsock::{AGENT_BOOT_TIMEOUT, VsockClient};
 
 /// VM handle returned to clients (without process ownership)
 #[derive(Debug, Clone)]
@@ -85,6 +89,11 @@ impl AgentPoolConfig {
             vcpu_count: 1,
         }
     }
+
+    /// Whether a sandbox needing these resources fits in this pool's VMs
+    pub fn fits(&self, vcpus: u32, memory_mb: u64) -> bool {
+        vcpus <= self.vcpu_count && memory_mb <= self.mem_size_mib
+    }
 }
 
 impl Default for AgentPoolConfig {
@@ -110,6 +119,9 @@ pub struct PoolConfig {
     pub agent_configs: HashMap<CompatibilityMode, AgentPoolConfig>,
     /// Which agents to pre-warm on startup
     pub prewarm_agents: Vec<CompatibilityMode>,
+    /// Snapshot warm VMs here on shutdown and restore them on startup
+    /// (None disables persistence)
+    pub persist_dir: Option<PathBuf>,
 }
 
 impl Default for PoolConfig {
@@ -122,6 +134,7 @@ impl Default for PoolConfig {
             default_runtime: "base".to_string(),
             agent_configs: HashMap::new(),
             prewarm_agents: vec![],
+            persist_dir: None,
         }
     }
 }
@@ -147,6 +160,7 @@ impl PoolConfig {
             default_runtime: "base".to_string(),
             agent_configs,
             prewarm_agents: vec![CompatibilityMode::Native], // Only pre-warm native by default
+            persist_dir: None,
         }
     }
 
@@ -219,6 +233,10 @@ pub struct FirecrackerPool {
     rootfs_dir: PathBuf,
     /// Shutdown flag
     shutdown: std::sync::atomic::AtomicBool,
+    /// Wakes `shutdown_requested` waiters
+    shutdown_notify: Notify,
+    /// Boot latency per runtime
+    boot_latency: Mutex<HashMap<String, BootLatency>>,
 }
 
 impl FirecrackerPool {
@@ -233,6 +251,8 @@ impl FirecrackerPool {
             kernel_path,
             rootfs_dir,
             shutdown: std::sync::atomic::AtomicBool::new(false),
+            shutdown_notify: Notify::new(),
+            boot_latency: Mutex::new(HashMap::new()),
         }
     }
 
@@ -262,6 +282,11 @@ impl FirecrackerPool {
         stats
     }
 
+    /// Get boot latency broken down by runtime
+    pub async fn boot_latency(&self) -> HashMap<String, BootLatency> {
+        self.boot_latency.lock().await.clone()
+    }
+
     /// Acquire a VM from the pool by runtime type
     #[allow(dead_code)]
     pub async fn acquire(&self, runtime: &str) -> Result<VmHandle> {
@@ -381,6 +406,7 @@ impl FirecrackerPool {
     async fn start_vm_with_mode(&self, runtime: &str, mode: CompatibilityMode) -> Result<PooledVm> {
         // Acquire semaphore to limit concurrent starts
         let _permit = self.start_semaphore.acquire().await?;
+        let boot_started = Instant::now();
 
         // Get agent-specific config for memory/CPU
         let agent_config = self.config.get_agent_config(mode);
@@ -398,35 +424,9 @@ impl FirecrackerPool {
         let vsock_path = PathBuf::from(format!("/tmp/agentkernel-{}-vsock.sock", id));
 
         // Clean up any existing sockets
-        let _ = std::fs::remove_file(&api_socket_path);
         let _ = std::fs::remove_file(&vsock_path);
 
-        // Find firecracker binary
-        let firecracker_bin = Self::find_firecracker()?;
-
-        // Start firecracker process
-        let process = Command::new(&firecracker_bin)
-            .arg("--api-sock")
-            .arg(&api_socket_path)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .with_context(|| {
-                format!("Failed to start firecracker: {}", firecracker_bin.display())
-            })?;
-
-        // Wait for socket
-        for _ in 0..50 {
-            if api_socket_path.exists() {
-                break;
-            }
-            sleep(Duration::from_millis(100)).await;
-        }
-
-        if !api_socket_path.exists() {
-            bail!("Firecracker API socket not available after 5 seconds");
-        }
+        let process = Self::spawn_firecracker(&api_socket_path).await?;
 
         // Configure the VM
         let client = FirecrackerClient::new(&api_socket_path);
@@ -434,7 +434,7 @@ impl FirecrackerPool {
         // Boot source
         let boot_source = BootSource {
             kernel_image_path: self.kernel_path.to_string_lossy().to_string(),
-            boot_args: "console=ttyS0 reboot=k panic=1 pci=off init=/init quiet loglevel=4 i8042.nokbd i8042.noaux".to_string(),
+            boot_args: pool_boot_args(),
         };
         client.set_boot_source(&boot_source).await?;
 
@@ -444,11 +444,12 @@ impl FirecrackerPool {
             bail!("Rootfs not found: {}", rootfs_path.display());
         }
 
+        // Shared by every VM of the runtime, so it's never written to
         let drive = Drive {
             drive_id: "rootfs".to_string(),
             path_on_host: rootfs_path.to_string_lossy().to_string(),
             is_root_device: true,
-            is_read_only: false,
+            is_read_only: true,
         };
         client.set_drive("rootfs", &drive).await?;
 
@@ -470,21 +471,17 @@ impl FirecrackerPool {
         client.start_instance().await?;
 
         // Wait for guest agent
-        let vsock_client = VsockClient::for_firecracker(vsock_path.clone());
-        for i in 0..100 {
-            if vsock_client.ping().await.unwrap_or(false) {
-                break;
-            }
-            if i == 99 {
-                bail!("Guest agent not available after 10 seconds");
-            }
-            if i % 20 == 0 && i > 0 {
-                eprintln!("Waiting for guest agent... ({}s)", i / 10);
-            }
-            sleep(Duration::from_millis(100)).await;
-        }
+        VsockClient::for_firecracker(vsock_path.clone())
+            .wait_until_ready(AGENT_BOOT_TIMEOUT)
+            .await?;
 
         let now = Instant::now();
+        self.boot_latency
+            .lock()
+            .await
+            .entry(runtime.to_string())
+            .or_default()
+            .record(now.duration_since(boot_started).as_millis() as u64);
 
         Ok(PooledVm {
             id,
@@ -499,6 +496,191 @@ impl FirecrackerPool {
         })
     }
 
+    /// Start a Firecracker process and wait for its API socket
+    async fn spawn_firecracker(api_socket_path: &Path) -> Result<Child> {
+        let _ = std::fs::remove_file(api_socket_path);
+
+        // Find firecracker binary
+        let firecracker_bin = Self::find_firecracker()?;
+
+        // Start firecracker process
+        let mut process = Command::new(&firecracker_bin)
+            .arg("--api-sock")
+            .arg(api_socket_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| {
+                format!("Failed to start firecracker: {}", firecracker_bin.display())
+            })?;
+
+        // Wait for socket
+        for _ in 0..50 {
+            if api_socket_path.exists() {
+                return Ok(process);
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        let _ = process.kill();
+        let _ = process.wait();
+        bail!("Firecracker API socket not available after 5 seconds");
+    }
+
+    /// Snapshot the warm VMs to `dir` for the next daemon to restore.
+    ///
+    /// Each warm VM is paused, snapshotted, and killed; VMs in use are left
+    /// alone. Returns how many were saved.
+    pub async fn persist(&self, dir: &Path) -> Result<usize> {
+        snapshot::clear(dir);
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let vms: Vec<PooledVm> = self.warm_pool.lock().await.drain(..).collect();
+        let mut saved = Vec::new();
+        for mut vm in vms {
+            let entry = SavedVm {
+                id: vm.id.clone(),
+                cid: vm.cid,
+                runtime: vm.runtime.clone(),
+                compatibility_mode: vm.compatibility_mode,
+                vsock_path: vm.vsock_path.clone(),
+            };
+            let client = FirecrackerClient::new(&vm.api_socket_path);
+            let result = async {
+                client.pause().await?;
+                client
+                    .create_snapshot(&entry.snapshot_path(dir), &entry.mem_path(dir))
+                    .await
+            }
+            .await;
+            vm.kill();
+            match result {
+                Ok(()) => saved.push(entry),
+                Err(e) => {
+                    eprintln!("Failed to snapshot VM {}: {}", entry.id, e);
+                    let _ = std::fs::remove_file(entry.snapshot_path(dir));
+                    let _ = std::fs::remove_file(entry.mem_path(dir));
+                }
+            }
+        }
+        if saved.is_empty() {
+            return Ok(0);
+        }
+
+        let mut rootfs: Vec<PathBuf> = saved
+            .iter()
+            .map(|vm| self.rootfs_dir.join(format!("{}.ext4", vm.runtime)))
+            .collect();
+        rootfs.sort();
+        rootfs.dedup();
+        let count = saved.len();
+        let manifest = Self::find_firecracker()
+            .and_then(|bin| Manifest::new(&bin, &self.kernel_path, &rootfs, saved));
+        match manifest.and_then(|m| m.save(dir)) {
+            Ok(()) => Ok(count),
+            Err(e) => {
+                snapshot::clear(dir);
+                Err(e)
+            }
+        }
+    }
+
+    /// Restore the VMs saved by `persist` into the warm pool.
+    ///
+    /// Snapshots are used once and deleted afterwards. They're skipped if
+    /// the Firecracker binary, kernel, or a rootfs changed since, and VMs
+    /// that fail to restore are left for `warm_up` to boot cold. Returns how
+    /// many were restored.
+    pub async fn restore(&self, dir: &Path) -> usize {
+        let manifest = match Manifest::load(dir) {
+            Ok(Some(manifest)) => manifest,
+            Ok(None) => return 0,
+            Err(e) => {
+                eprintln!("Ignoring pool snapshots: {}", e);
+                snapshot::clear(dir);
+                return 0;
+            }
+        };
+        let compatible = Self::find_firecracker()
+            .and_then(|bin| manifest.check_compatible(&bin, &self.kernel_path));
+        if let Err(e) = compatible {
+            eprintln!("Not restoring pool snapshots: {}", e);
+            snapshot::clear(dir);
+            return 0;
+        }
+
+        // Restored VMs keep their CIDs, so new ones are numbered after them
+        if let Some(max) = manifest.vms.iter().map(|vm| vm.cid).max() {
+            self.next_cid.fetch_max(max + 1, Ordering::SeqCst);
+        }
+
+        let mut restored = 0;
+        for saved in &manifest.vms {
+            match self.restore_vm(dir, saved).await {
+                Ok(vm) => {
+                    self.warm_pool.lock().await.push_back(vm);
+                    restored += 1;
+                }
+                Err(e) => eprintln!("Failed to restore VM {}: {}", saved.id, e),
+            }
+        }
+        snapshot::clear(dir);
+        restored
+    }
+
+    /// Start a Firecracker process from one saved VM
+    async fn restore_vm(&self, dir: &Path, saved: &SavedVm) -> Result<PooledVm> {
+        let started = Instant::now();
+        let api_socket_path = PathBuf::from(format!("/tmp/agentkernel-{}.sock", saved.id));
+        let _ = std::fs::remove_file(&saved.vsock_path);
+
+        let mut process = Self::spawn_firecracker(&api_socket_path).await?;
+        let client = FirecrackerClient::new(&api_socket_path);
+        let ready = async {
+            client
+                .load_snapshot(&saved.snapshot_path(dir), &saved.mem_path(dir))
+                .await?;
+            VsockClient::for_firecracker(saved.vsock_path.clone())
+                .wait_until_ready(AGENT_BOOT_TIMEOUT)
+                .await
+        }
+        .await;
+        if let Err(e) = ready {
+            let _ = process.kill();
+            let _ = process.wait();
+            let _ = std::fs::remove_file(&api_socket_path);
+            let _ = std::fs::remove_file(&saved.vsock_path);
+            return Err(e);
+        }
+
+        let now = Instant::now();
+        self.boot_latency
+            .lock()
+            .await
+            .entry(saved.runtime.clone())
+            .or_default()
+            .record(now.duration_since(started).as_millis() as u64);
+
+        Ok(PooledVm {
+            id: saved.id.clone(),
+            cid: saved.cid,
+            vsock_path: saved.vsock_path.clone(),
+            api_socket_path,
+            process,
+            runtime: saved.runtime.clone(),
+            compatibility_mode: saved.compatibility_mode,
+            created_at: now,
+            last_used: now,
+        })
+    }
+
+    /// Where warm VMs are persisted, if persistence is on
+    pub fn persist_dir(&self) -> Option<&Path> {
+        self.config.persist_dir.as_deref()
+    }
+
     /// Find the firecracker binary
     fn find_firecracker() -> Result<PathBuf> {
         // Check FIRECRACKER_BIN env var first
@@ -510,11 +692,9 @@ impl FirecrackerPool {
         }
 
         // Check agentkernel's own bin directory
-        if let Some(home) = std::env::var_os("HOME") {
-            let local_fc = PathBuf::from(home).join(".local/share/agentkernel/bin/firecracker");
-            if local_fc.exists() {
-                return Ok(local_fc);
-            }
+        let local_fc = crate::setup::default_data_dir().join("bin/firecracker");
+        if local_fc.exists() {
+            return Ok(local_fc);
         }
 
         // Check common locations
@@ -693,10 +873,17 @@ impl FirecrackerPool {
     /// Signal shutdown
     pub fn shutdown(&self) {
         self.shutdown.store(true, Ordering::SeqCst);
+        self.shutdown_notify.notify_one();
+    }
+
+    /// Wait until `shutdown` is called
+    pub async fn shutdown_requested(&self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            self.shutdown_notify.notified().await;
+        }
     }
 
     /// Destroy all VMs
-    #[allow(dead_code)]
     pub async fn destroy_all(&self) {
         // Destroy warm pool
         {
@@ -722,3 +909,77 @@ impl Drop for FirecrackerPool {
         // Note: async cleanup happens via destroy_all() before drop
     }
 }
+
+/// Kernel command line for a pooled VM.
+///
+/// All the VMs of a runtime boot the same rootfs image, so it's mounted
+/// read-only; the guest init puts tmpfs at /tmp and the workspace for writes.
+fn pool_boot_args() -> String {
+    let config = SandboxConfig::default().with_read_only(true, Some("/workspace".to_string()));
+    boot_args(&config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_boots_shared_rootfs_read_only() {
+        let args = pool_boot_args();
+        assert!(args.contains("root=/dev/vda ro "), "{}", args);
+        assert!(args.contains("agentkernel.writable=/workspace"), "{}", args);
+        assert!(args.contains("agentkernel.tmp_size="), "{}", args);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires KVM, Firecracker, and the images from `agentkernel setup`
+    async fn test_persisted_pool_restores() {
+        let data_dir = crate::setup::default_data_dir();
+        let Some(kernel) = crate::setup::find_kernel(&data_dir) else {
+            eprintln!("Skipping test: kernel not installed");
+            return;
+        };
+        if !Path::new("/dev/kvm").exists() {
+            eprintln!("Skipping test: KVM not available");
+            return;
+        }
+        let rootfs_dir = data_dir.join("images/rootfs");
+        let snapshots = tempfile::TempDir::new().unwrap();
+        let config = PoolConfig {
+            min_warm: 2,
+            persist_dir: Some(snapshots.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let pool = FirecrackerPool::new(config.clone(), kernel.clone(), rootfs_dir.clone());
+        pool.warm_up().await.unwrap();
+        assert_eq!(pool.stats().await, (2, 0));
+        assert_eq!(pool.persist(snapshots.path()).await.unwrap(), 2);
+        assert_eq!(pool.stats().await, (0, 0));
+        let saved: Vec<String> = Manifest::load(snapshots.path())
+            .unwrap()
+            .unwrap()
+            .vms
+            .into_iter()
+            .map(|vm| vm.id)
+            .collect();
+
+        let pool = FirecrackerPool::new(config, kernel, rootfs_dir);
+        assert_eq!(pool.restore(snapshots.path()).await, 2);
+        assert_eq!(pool.stats().await, (2, 0));
+        // Snapshots are only good for one restore
+        assert_eq!(Manifest::load(snapshots.path()).unwrap(), None);
+
+        // Both restored VMs run commands
+        for _ in 0..2 {
+            let vm = pool.acquire("base").await.unwrap();
+            assert!(saved.contains(&vm.id), "{} wasn't restored", vm.id);
+            let result = VsockClient::for_firecracker(vm.vsock_path.clone())
+                .run_command(&["echo".to_string(), "restored".to_string()])
+                .await
+                .unwrap();
+            assert_eq!(result.stdout.trim(), "restored");
+        }
+        pool.destroy_all().await;
+    }
+}
@@ -33,6 +33,38 @@ pub enum DaemonCompatibilityMode {
     Gemini,
 }
 
+/// Boot latency observed for one runtime's VMs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootLatency {
+    /// Number of VMs booted
+    pub count: u64,
+    /// Sum of all boot times (ms)
+    pub total_ms: u64,
+    /// Fastest boot (ms)
+    pub min_ms: u64,
+    /// Slowest boot (ms)
+    pub max_ms: u64,
+}
+
+impl BootLatency {
+    /// Add one boot to the totals
+    pub fn record(&mut self, ms: u64) {
+        self.min_ms = if self.count == 0 {
+            ms
+        } else {
+            self.min_ms.min(ms)
+        };
+        self.max_ms = self.max_ms.max(ms);
+        self.total_ms += ms;
+        self.count += 1;
+    }
+
+    /// Mean boot time (ms)
+    pub fn avg_ms(&self) -> u64 {
+        self.total_ms.checked_div(self.count).unwrap_or(0)
+    }
+}
+
 /// Request from CLI to daemon
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
@@ -119,6 +151,9 @@ pub enum DaemonResponse {
         /// Per-agent pool stats (warm count per compatibility mode)
         #[serde(default, skip_serializing_if = "HashMap::is_empty")]
         agent_stats: HashMap<String, usize>,
+        /// Boot latency of pool VMs per runtime
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        boot_latency: HashMap<String, BootLatency>,
     },
     /// Pool pre-warmed for agent
     Prewarmed {
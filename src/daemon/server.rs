@@ -11,7 +11,7 @@ use tokio::sync::Mutex;
 use super::pool::{FirecrackerPool, PoolConfig};
 use super::protocol::{DaemonCompatibilityMode, DaemonRequest, DaemonResponse};
 use crate::permissions::CompatibilityMode;
-use crate::vsock::{AGENT_PORT, VsockClient, VsockConnection};
+use crate::vsock::{VsockClient, VsockConnection, agent_port};
 
 /// Convert daemon compatibility mode to internal compatibility mode
 fn to_internal_mode(mode: DaemonCompatibilityMode) -> CompatibilityMode {
@@ -50,7 +50,10 @@ impl DaemonServer {
 
     /// Get the default socket path
     pub fn default_socket_path() -> PathBuf {
-        if let Some(home) = std::env::var_os("HOME") {
+        if let Some(dir) = crate::setup::data_dir_override() {
+            let _ = std::fs::create_dir_all(&dir);
+            dir.join("daemon.sock")
+        } else if let Some(home) = std::env::var_os("HOME") {
             let dir = PathBuf::from(home).join(".agentkernel");
             let _ = std::fs::create_dir_all(&dir);
             dir.join("daemon.sock")
@@ -87,6 +90,14 @@ impl DaemonServer {
         let listener = UnixListener::bind(&self.socket_path)?;
         eprintln!("Daemon listening on {}", self.socket_path.display());
 
+        // Bring back the VMs saved at the last shutdown
+        if let Some(dir) = self.pool.persist_dir() {
+            let restored = self.pool.restore(dir).await;
+            if restored > 0 {
+                eprintln!("Restored {} VMs from snapshots", restored);
+            }
+        }
+
         // Warm up the pool
         eprintln!("Warming up pool...");
         self.pool.warm_up().await?;
@@ -99,9 +110,14 @@ impl DaemonServer {
             pool_clone.run_maintenance().await;
         });
 
-        // Accept connections
+        // Accept connections until a shutdown request or signal
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = self.pool.shutdown_requested() => break,
+                _ = termination_signal() => break,
+            };
+            match accepted {
                 Ok((stream, _)) => {
                     let pool = Arc::clone(&self.pool);
                     let connections = Arc::clone(&self.connections);
@@ -116,6 +132,10 @@ impl DaemonServer {
                 }
             }
         }
+
+        eprintln!("Shutting down...");
+        self.shutdown().await;
+        Ok(())
     }
 
     /// Get pool reference
@@ -124,15 +144,37 @@ impl DaemonServer {
         &self.pool
     }
 
-    /// Shutdown the daemon
-    #[allow(dead_code)]
+    /// Shutdown the daemon, saving the warm VMs first if persistence is on
     pub async fn shutdown(&self) {
         self.pool.shutdown();
+        if let Some(dir) = self.pool.persist_dir() {
+            match self.pool.persist(dir).await {
+                Ok(saved) => eprintln!("Saved {} warm VMs to {}", saved, dir.display()),
+                Err(e) => eprintln!("Failed to save the pool: {}", e),
+            }
+        }
         self.pool.destroy_all().await;
         let _ = std::fs::remove_file(&self.socket_path);
     }
 }
 
+/// Resolves on Ctrl-C or SIGTERM
+async fn termination_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
 /// Handle a single client connection
 async fn handle_client(
     stream: UnixStream,
@@ -165,8 +207,9 @@ async fn handle_client(
         let json = serde_json::to_string(&response)? + "\n";
         writer.write_all(json.as_bytes()).await?;
 
-        // Check for shutdown
+        // Shut down once the client has its answer
         if matches!(response, DaemonResponse::ShuttingDown) {
+            pool.shutdown();
             break;
         }
     }
@@ -246,7 +289,7 @@ async fn handle_request(
                     // No cached connection, create new one
                     drop(cache); // Release lock before async operation
 
-                    match VsockConnection::connect(&vm.vsock_path, AGENT_PORT).await {
+                    match VsockConnection::connect(&vm.vsock_path, agent_port()).await {
                         Ok(mut conn) => {
                             let result = conn.run_command(&command).await;
                             // Cache the connection for future use
@@ -294,6 +337,7 @@ async fn handle_request(
         DaemonRequest::Status => {
             let (warm, in_use) = pool.stats().await;
             let agent_stats = pool.stats_by_agent().await;
+            let boot_latency = pool.boot_latency().await;
             DaemonResponse::Status {
                 warm,
                 in_use,
@@ -301,11 +345,9 @@ async fn handle_request(
                 max_warm: 5,
                 backends: vec!["firecracker".to_string()],
                 agent_stats,
+                boot_latency,
             }
         }
-        DaemonRequest::Shutdown => {
-            pool.shutdown();
-            DaemonResponse::ShuttingDown
-        }
+        DaemonRequest::Shutdown => DaemonResponse::ShuttingDown,
     }
 }
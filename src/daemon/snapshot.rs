@@ -0,0 +1,260 @@
+//! Keeping the warm pool across daemon restarts.
+//!
+//! With `daemon start --persist`, the daemon pauses each warm VM on shutdown
+//! and writes a Firecracker snapshot of it to disk, then restores them on the
+//! next start instead of booting cold. A manifest records the Firecracker
+//! binary, kernel, and rootfs images the snapshots were taken with, and they
+//! are only restored while those are unchanged.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::permissions::CompatibilityMode;
+
+/// Manifest file in the snapshot directory
+const MANIFEST: &str = "manifest.json";
+
+/// Default snapshot directory, next to the daemon socket
+pub fn default_dir(socket_path: &Path) -> PathBuf {
+    socket_path.with_file_name("pool-snapshots")
+}
+
+/// A file a snapshot depends on. Rebuilding or writing to it gives it a
+/// different stamp.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub path: PathBuf,
+    pub len: u64,
+    /// Modification time, nanoseconds since the epoch
+    pub modified_ns: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Result<Self> {
+        let meta = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let modified_ns = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Ok(Self {
+            path: path.to_path_buf(),
+            len: meta.len(),
+            modified_ns,
+        })
+    }
+
+    /// Whether the file at `self.path` is still the one stamped
+    fn is_current(&self) -> bool {
+        FileStamp::of(&self.path).is_ok_and(|now| now == *self)
+    }
+}
+
+/// A warm VM saved to disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedVm {
+    pub id: String,
+    /// vsock CID, kept by the restored VM
+    pub cid: u32,
+    pub runtime: String,
+    pub compatibility_mode: CompatibilityMode,
+    /// vsock UDS, recreated at the same path on restore
+    pub vsock_path: PathBuf,
+}
+
+impl SavedVm {
+    /// Device state snapshot
+    pub fn snapshot_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.snap", self.id))
+    }
+
+    /// Guest memory file
+    pub fn mem_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.mem", self.id))
+    }
+}
+
+/// The saved VMs and what they were taken with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub agentkernel_version: String,
+    pub firecracker: FileStamp,
+    pub kernel: FileStamp,
+    /// Rootfs image of each runtime with a saved VM
+    pub rootfs: Vec<FileStamp>,
+    pub vms: Vec<SavedVm>,
+}
+
+impl Manifest {
+    pub fn new(
+        firecracker: &Path,
+        kernel: &Path,
+        rootfs: &[PathBuf],
+        vms: Vec<SavedVm>,
+    ) -> Result<Self> {
+        Ok(Self {
+            agentkernel_version: env!("CARGO_PKG_VERSION").to_string(),
+            firecracker: FileStamp::of(firecracker)?,
+            kernel: FileStamp::of(kernel)?,
+            rootfs: rootfs
+                .iter()
+                .map(|path| FileStamp::of(path))
+                .collect::<Result<_>>()?,
+            vms,
+        })
+    }
+
+    /// Read the manifest in `dir`, if there is one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let manifest = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid snapshot manifest {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", MANIFEST));
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, dir.join(MANIFEST))?;
+        Ok(())
+    }
+
+    /// Check the snapshots can be restored with this Firecracker binary and
+    /// kernel: nothing they depend on may have changed since they were taken
+    pub fn check_compatible(&self, firecracker: &Path, kernel: &Path) -> Result<()> {
+        let version = env!("CARGO_PKG_VERSION");
+        if self.agentkernel_version != version {
+            bail!(
+                "taken by agentkernel {}, this is {}",
+                self.agentkernel_version,
+                version
+            );
+        }
+        if self.firecracker.path != firecracker || !self.firecracker.is_current() {
+            bail!("the Firecracker binary changed");
+        }
+        if self.kernel.path != kernel || !self.kernel.is_current() {
+            bail!("the kernel changed");
+        }
+        if let Some(rootfs) = self.rootfs.iter().find(|stamp| !stamp.is_current()) {
+            bail!("rootfs {} changed", rootfs.path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Delete the snapshots and manifest in `dir`. Snapshots share their rootfs
+/// with live VMs, so they're only good for one restore.
+pub fn clear(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(MANIFEST) || name.ends_with(".snap") || name.ends_with(".mem") {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Files {
+        dir: TempDir,
+        firecracker: PathBuf,
+        kernel: PathBuf,
+        rootfs: PathBuf,
+    }
+
+    fn files() -> Files {
+        let dir = TempDir::new().unwrap();
+        let firecracker = dir.path().join("firecracker");
+        let kernel = dir.path().join("vmlinux");
+        let rootfs = dir.path().join("base.ext4");
+        for path in [&firecracker, &kernel, &rootfs] {
+            std::fs::write(path, "image").unwrap();
+        }
+        Files {
+            dir,
+            firecracker,
+            kernel,
+            rootfs,
+        }
+    }
+
+    fn saved_vm() -> SavedVm {
+        SavedVm {
+            id: "pool-native-base-100".to_string(),
+            cid: 100,
+            runtime: "base".to_string(),
+            compatibility_mode: CompatibilityMode::Native,
+            vsock_path: PathBuf::from("/tmp/agentkernel-pool-native-base-100-vsock.sock"),
+        }
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let f = files();
+        let snapshots = f.dir.path().join("snapshots");
+        std::fs::create_dir(&snapshots).unwrap();
+        assert_eq!(Manifest::load(&snapshots).unwrap(), None);
+
+        let manifest = Manifest::new(
+            &f.firecracker,
+            &f.kernel,
+            std::slice::from_ref(&f.rootfs),
+            vec![saved_vm()],
+        )
+        .unwrap();
+        manifest.save(&snapshots).unwrap();
+        let vm = &manifest.vms[0];
+        std::fs::write(vm.snapshot_path(&snapshots), "state").unwrap();
+        std::fs::write(vm.mem_path(&snapshots), "memory").unwrap();
+        assert_eq!(Manifest::load(&snapshots).unwrap(), Some(manifest));
+
+        clear(&snapshots);
+        assert_eq!(Manifest::load(&snapshots).unwrap(), None);
+        assert_eq!(std::fs::read_dir(&snapshots).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_manifest_compatibility() {
+        let f = files();
+        let manifest = Manifest::new(
+            &f.firecracker,
+            &f.kernel,
+            std::slice::from_ref(&f.rootfs),
+            vec![saved_vm()],
+        )
+        .unwrap();
+        assert!(manifest.check_compatible(&f.firecracker, &f.kernel).is_ok());
+
+        // A different binary or kernel path
+        let other = f.dir.path().join("other");
+        assert!(manifest.check_compatible(&other, &f.kernel).is_err());
+        assert!(manifest.check_compatible(&f.firecracker, &other).is_err());
+
+        // A rebuilt rootfs
+        std::fs::write(&f.rootfs, "rebuilt image").unwrap();
+        let err = manifest
+            .check_compatible(&f.firecracker, &f.kernel)
+            .unwrap_err();
+        assert!(err.to_string().contains("base.ext4"));
+
+        let mut old = manifest.clone();
+        old.rootfs.clear();
+        old.agentkernel_version = "0.0.1".to_string();
+        let err = old.check_compatible(&f.firecracker, &f.kernel).unwrap_err();
+        assert!(err.to_string().contains("agentkernel 0.0.1"));
+    }
+}
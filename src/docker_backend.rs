@@ -4,8 +4,11 @@
 //! Uses Docker or Podman containers instead of Firecracker microVMs.
 
 use anyhow::{Context, Result, bail};
-use std::process::Command;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
 
+use crate::backend::{BackendType, backend_available};
 use crate::permissions::Permissions;
 
 /// Container runtime to use
@@ -25,12 +28,111 @@ impl ContainerRuntime {
     }
 }
 
+/// Environment problem behind a failed container runtime command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The image doesn't exist locally or in its registry
+    ImageNotFound {
+        runtime: ContainerRuntime,
+        image: String,
+    },
+    /// The daemon isn't running or can't be reached
+    DaemonUnreachable { runtime: ContainerRuntime },
+    /// The current user may not use the daemon socket
+    PermissionDenied { runtime: ContainerRuntime },
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::ImageNotFound { runtime, image } => write!(
+                f,
+                "Image '{}' not found. Check the name and tag, or pull it first: {} pull {}",
+                image,
+                runtime.cmd(),
+                image
+            ),
+            RuntimeError::DaemonUnreachable {
+                runtime: ContainerRuntime::Docker,
+            } => write!(
+                f,
+                "Cannot connect to the Docker daemon. Start Docker (Docker Desktop, or \
+                 `sudo systemctl start docker`) and try again."
+            ),
+            RuntimeError::DaemonUnreachable {
+                runtime: ContainerRuntime::Podman,
+            } => write!(
+                f,
+                "Cannot connect to Podman. Start it with `podman machine start` \
+                 (macOS/Windows) or check `podman system connection list`."
+            ),
+            RuntimeError::PermissionDenied { runtime } => write!(
+                f,
+                "Permission denied connecting to the {} daemon. Add your user to the \
+                 '{}' group (`sudo usermod -aG {} $USER`), then log out and back in.",
+                runtime.cmd(),
+                runtime.cmd(),
+                runtime.cmd()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Recognize environment problems in a runtime's stderr.
+///
+/// Returns None for anything else (e.g. the command itself failing).
+pub fn classify_runtime_error(
+    runtime: ContainerRuntime,
+    image: &str,
+    stderr: &str,
+) -> Option<RuntimeError> {
+    let err = stderr.to_lowercase();
+    // Checked first: the message also mentions connecting to the daemon
+    if err.contains("permission denied") && (err.contains("daemon socket") || err.contains(".sock"))
+    {
+        return Some(RuntimeError::PermissionDenied { runtime });
+    }
+    if err.contains("cannot connect to the docker daemon")
+        || err.contains("is the docker daemon running")
+        || err.contains("error during connect")
+        || err.contains("cannot connect to podman")
+        || err.contains("unable to connect to podman")
+    {
+        return Some(RuntimeError::DaemonUnreachable { runtime });
+    }
+    if err.contains("unable to find image")
+        || err.contains("pull access denied")
+        || err.contains("manifest unknown")
+        || err.contains("image not known")
+        || err.contains("repository does not exist")
+        || err.contains("requested access to the resource is denied")
+        || (err.contains("manifest for") && err.contains("not found"))
+    {
+        return Some(RuntimeError::ImageNotFound {
+            runtime,
+            image: image.to_string(),
+        });
+    }
+    None
+}
+
+/// Error for a failed container start: a [`RuntimeError`] when the cause is
+/// recognized, otherwise the runtime's own message
+pub fn start_error(runtime: ContainerRuntime, image: &str, stderr: &str) -> anyhow::Error {
+    match classify_runtime_error(runtime, image, stderr) {
+        Some(e) => e.into(),
+        None => anyhow::anyhow!("Failed to start container: {}", stderr),
+    }
+}
+
 /// Detect the best available container runtime
 pub fn detect_container_runtime() -> Option<ContainerRuntime> {
     // Prefer Podman (rootless, daemonless) over Docker
-    if podman_available() {
+    if backend_available(BackendType::Podman) {
         Some(ContainerRuntime::Podman)
-    } else if docker_available() {
+    } else if backend_available(BackendType::Docker) {
         Some(ContainerRuntime::Docker)
     } else {
         None
@@ -82,7 +184,9 @@ impl ContainerSandbox {
             .await
     }
 
-    /// Start the container with the specified image and permissions
+    /// Start the container with the specified image and permissions.
+    ///
+    /// `perms.mounts` must already be resolved.
     pub async fn start_with_permissions(&mut self, image: &str, perms: &Permissions) -> Result<()> {
         let cmd = self.runtime.cmd();
 
@@ -90,6 +194,10 @@ impl ContainerSandbox {
         // Also use --force-rm style by directly replacing any existing container
         let container_name = format!("agentkernel-{}", self.name);
 
+        if perms.gpus.is_some() {
+            check_gpu_support(cmd)?;
+        }
+
         // Fast-path: remove any existing container (no check, just force remove)
         let _ = Command::new(cmd)
             .args(["rm", "-f", &container_name])
@@ -103,13 +211,19 @@ impl ContainerSandbox {
             "--name".to_string(),
             format!("agentkernel-{}", self.name),
             "--hostname".to_string(),
-            "agentkernel".to_string(),
+            perms
+                .hostname
+                .clone()
+                .unwrap_or_else(|| crate::backend::DEFAULT_HOSTNAME.to_string()),
         ];
 
         // Add permission-based security args
         args.extend(perms.to_docker_args());
+        args.extend(perms.ulimits.for_image(image).docker_args());
         args.extend(perms.get_env_args());
         args.extend(perms.get_mount_args(None));
+        args.extend(perms.workspace_tmpfs_arg(None));
+        args.extend(crate::permissions::mount_args(&perms.mounts));
 
         // Add entrypoint override for tool images
         args.extend([
@@ -128,7 +242,7 @@ impl ContainerSandbox {
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("Failed to start container: {}", stderr);
+            return Err(start_error(self.runtime, image, &stderr));
         }
 
         let container_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -205,12 +319,14 @@ impl ContainerSandbox {
     }
 
     /// Run a command in a temporary container using `docker run --rm`
-    /// This is faster than create→start→exec→stop for one-shot commands
+    /// This is faster than create→start→exec→stop for one-shot commands.
+    /// `perms.mounts` must already be resolved.
     pub fn run_ephemeral_cmd(
         runtime: ContainerRuntime,
         image: &str,
         cmd: &[String],
         perms: &Permissions,
+        workdir: Option<&str>,
     ) -> Result<(i32, String, String)> {
         let runtime_cmd = runtime.cmd();
 
@@ -233,11 +349,14 @@ impl ContainerSandbox {
         }
 
         // Mount working directory if requested
+        let cwd = std::env::current_dir()
+            .ok()
+            .map(|dir| dir.display().to_string());
         if perms.mount_cwd
-            && let Ok(cwd) = std::env::current_dir()
+            && let Some(ref cwd) = cwd
         {
             args.push("-v".to_string());
-            args.push(format!("{}:/workspace", cwd.display()));
+            args.push(format!("{}:/workspace", cwd));
             args.push("-w".to_string());
             args.push("/workspace".to_string());
         }
@@ -250,19 +369,28 @@ impl ContainerSandbox {
             args.push(format!("{}:/home/user:ro", home.to_string_lossy()));
         }
 
-        // Read-only root filesystem
+        // Single host files, read-only
+        args.extend(crate::permissions::mount_args(&perms.mounts));
+
+        // Explicit working directory (overrides the /workspace default above)
+        if let Some(dir) = workdir {
+            args.push("-w".to_string());
+            args.push(dir.to_string());
+        }
+
+        // Read-only root filesystem and workspace tmpfs
         if perms.read_only_root {
             args.push("--read-only".to_string());
         }
+        args.extend(perms.workspace_tmpfs_arg(cwd.as_deref()));
+        args.extend(perms.ulimits.for_image(image).docker_args());
 
         // Environment variables
-        if perms.pass_env {
-            for var in ["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM"] {
-                if let Ok(val) = std::env::var(var) {
-                    args.push("-e".to_string());
-                    args.push(format!("{}={}", var, val));
-                }
-            }
+        args.extend(perms.get_env_args());
+
+        // Run as a non-root user
+        if let Some(ref user) = perms.user {
+            args.push(format!("--user={}", crate::permissions::resolve_user(user)));
         }
 
         // Seccomp profile
@@ -270,6 +398,9 @@ impl ContainerSandbox {
             args.push(format!("--security-opt=seccomp={}", seccomp_path.display()));
         }
 
+        // Label so a crashed run's container can be reaped later
+        args.extend(ephemeral_label_args());
+
         // Image and command
         args.push(image.to_string());
         args.extend(cmd.iter().cloned());
@@ -284,30 +415,544 @@ impl ContainerSandbox {
         let stderr = String::from_utf8_lossy(&output.stderr).to_string();
         let exit_code = output.status.code().unwrap_or(-1);
 
+        // 125 means the runtime itself failed, before the command ran
+        if exit_code == 125
+            && let Some(e) = classify_runtime_error(runtime, image, &stderr)
+        {
+            return Err(e.into());
+        }
+
         Ok((exit_code, stdout, stderr))
     }
 }
 
-/// Check if Docker is available
-pub fn docker_available() -> bool {
-    Command::new("docker")
-        .arg("version")
+/// Label marking containers that must not outlive the command that created them
+pub const EPHEMERAL_LABEL: &str = "agentkernel.ephemeral";
+
+/// Label recording when a container was created (Unix seconds)
+pub const CREATED_LABEL: &str = "agentkernel.created";
+
+/// Label recording the process that created an ephemeral container (`host:pid`)
+pub const OWNER_LABEL: &str = "agentkernel.owner";
+
+/// `--label` arguments that mark a container as ephemeral
+pub fn ephemeral_label_args() -> Vec<String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    vec![
+        "--label".to_string(),
+        format!("{}=true", EPHEMERAL_LABEL),
+        "--label".to_string(),
+        format!("{}={}", CREATED_LABEL, now),
+        "--label".to_string(),
+        format!("{}={}", OWNER_LABEL, current_owner()),
+    ]
+}
+
+/// This process as the owner of an ephemeral sandbox: `host:pid`
+pub fn current_owner() -> String {
+    format!("{}:{}", host_name(), std::process::id())
+}
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its full length
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Whether the owner of an ephemeral sandbox (`host:pid`) may still be running.
+///
+/// Owners on another host can't be checked, so they count as running; a
+/// malformed owner counts as gone, leaving the sandbox to the age check.
+pub fn owner_alive(owner: &str) -> bool {
+    let Some((host, pid)) = owner.rsplit_once(':') else {
+        return false;
+    };
+    let Ok(pid) = pid.parse::<u32>() else {
+        return false;
+    };
+    if host != host_name() {
+        return true;
+    }
+    pid_alive(pid)
+}
+
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // SAFETY: signal 0 only checks that the process exists
+    let ret = unsafe { libc::kill(pid, 0) };
+    // EPERM: it exists but belongs to another user
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn pid_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Pick stale ephemeral containers out of a `ps` listing.
+///
+/// Each line is `name<TAB>ephemeral label<TAB>created label<TAB>owner label`.
+/// Containers without the ephemeral label, whose age can't be determined, or
+/// whose owner is still running are never selected.
+pub fn select_stale_ephemeral(listing: &str, now_secs: u64, max_age_secs: u64) -> Vec<String> {
+    select_stale_ephemeral_with(listing, now_secs, max_age_secs, owner_alive)
+}
+
+fn select_stale_ephemeral_with(
+    listing: &str,
+    now_secs: u64,
+    max_age_secs: u64,
+    owner_alive: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split('\t');
+            let name = fields.next()?.trim();
+            let ephemeral = fields.next()?.trim();
+            let created: u64 = fields.next()?.trim().parse().ok()?;
+            // Containers from before the owner label have none
+            let owner = fields.next().map(str::trim).unwrap_or("");
+            (!name.is_empty()
+                && ephemeral == "true"
+                && now_secs.saturating_sub(created) >= max_age_secs
+                && (owner.is_empty() || !owner_alive(owner)))
+            .then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// Force-remove ephemeral containers older than `max_age_secs`.
+///
+/// Returns the names of the removed containers.
+pub fn reap_ephemeral_containers(
+    runtime: ContainerRuntime,
+    max_age_secs: u64,
+) -> Result<Vec<String>> {
+    let output = Command::new(runtime.cmd())
+        .args([
+            "ps",
+            "-a",
+            "--filter",
+            &format!("label={}=true", EPHEMERAL_LABEL),
+            "--format",
+            &format!(
+                "{{{{.Names}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}",
+                EPHEMERAL_LABEL, CREATED_LABEL, OWNER_LABEL
+            ),
+        ])
         .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+        .context("Failed to list containers")?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to list containers: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let stale = select_stale_ephemeral(&String::from_utf8_lossy(&output.stdout), now, max_age_secs);
+
+    let mut removed = Vec::new();
+    for name in stale {
+        let status = Command::new(runtime.cmd())
+            .args(["rm", "-f", &name])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if status {
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether an image reference is pinned to a content digest (`name@sha256:...`)
+pub fn is_digest_pinned(image: &str) -> bool {
+    image.rsplit_once("@sha256:").is_some_and(|(name, hex)| {
+        !name.is_empty() && hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Pick the digest from `image inspect --format '{{.Id}} {{join .RepoDigests " "}}'`.
+///
+/// Prefers a registry digest (`name@sha256:...`), which can be pulled
+/// elsewhere, over the local image ID.
+pub fn parse_image_digest(output: &str) -> Option<String> {
+    let mut fields = output.split_whitespace();
+    let id = fields.next()?;
+    fields
+        .find(|d| is_digest_pinned(d))
+        .or_else(|| id.starts_with("sha256:").then_some(id))
+        .map(String::from)
 }
 
-/// Check if Podman is available
-pub fn podman_available() -> bool {
-    Command::new("podman")
-        .arg("version")
+/// Resolve the digest of a local image, if it can be inspected
+pub fn image_digest(runtime: ContainerRuntime, image: &str) -> Option<String> {
+    let output = Command::new(runtime.cmd())
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{.Id}} {{join .RepoDigests \" \"}}",
+            image,
+        ])
         .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_image_digest(&String::from_utf8_lossy(&output.stdout))
 }
 
-/// Check if any container runtime is available
-#[allow(dead_code)]
-pub fn container_runtime_available() -> bool {
-    docker_available() || podman_available()
+/// Pick the loaded image from `docker load` or `podman load` output.
+///
+/// Docker prints `Loaded image: name:tag`, or `Loaded image ID: sha256:...`
+/// for an untagged image; older Podman prints `Loaded image(s): name:tag`.
+/// A tag wins over an ID, and the first one is used if the archive has several.
+pub fn parse_load_output(output: &str) -> Option<String> {
+    let loaded: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(label, _)| label.trim().starts_with("Loaded image"))
+        .flat_map(|(_, images)| images.split(','))
+        .map(str::trim)
+        .filter(|image| !image.is_empty())
+        .collect();
+    loaded
+        .iter()
+        .find(|image| !image.starts_with("sha256:"))
+        .or_else(|| loaded.first())
+        .map(|image| image.to_string())
+}
+
+/// Load a `docker save` or OCI archive into the runtime's image store.
+///
+/// Returns the loaded image, so it can be used without pulling.
+pub fn load_image_archive(runtime: ContainerRuntime, archive: &Path) -> Result<String> {
+    let output = Command::new(runtime.cmd())
+        .arg("load")
+        .arg("--input")
+        .arg(archive)
+        .output()
+        .with_context(|| format!("Failed to run {} load", runtime.cmd()))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to load image archive {}: {}",
+            archive.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    parse_load_output(&String::from_utf8_lossy(&output.stdout)).with_context(|| {
+        format!(
+            "{} load didn't report an image for {}",
+            runtime.cmd(),
+            archive.display()
+        )
+    })
+}
+
+/// CDI specs the NVIDIA Container Toolkit generates (used by Podman)
+const NVIDIA_CDI_SPECS: &[&str] = &["/etc/cdi/nvidia.yaml", "/var/run/cdi/nvidia.yaml"];
+
+/// Whether the NVIDIA Container Toolkit, which `--gpus` needs, is set up
+/// for the runtime `runtime_cmd`
+fn nvidia_toolkit_available(runtime_cmd: &str) -> bool {
+    let registered = Command::new(runtime_cmd)
+        .args(["info", "--format", "{{json .Runtimes}}"])
+        .output()
+        .is_ok_and(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).contains("nvidia"));
+    let hook = Command::new("which")
+        .arg("nvidia-container-runtime-hook")
+        .output()
+        .is_ok_and(|o| o.status.success());
+    registered || hook || NVIDIA_CDI_SPECS.iter().any(|p| Path::new(p).exists())
+}
+
+/// Fail with setup instructions unless GPUs can be passed to containers
+pub fn check_gpu_support(runtime_cmd: &str) -> Result<()> {
+    if !nvidia_toolkit_available(runtime_cmd) {
+        bail!(
+            "GPU access needs the NVIDIA Container Toolkit, which isn't set up for {}. \
+             Install it (https://docs.nvidia.com/datacenter/cloud-native/container-toolkit/), \
+             restart {}, and try again, or remove `gpus` from [security].",
+            runtime_cmd,
+            runtime_cmd
+        );
+    }
+    Ok(())
+}
+
+/// Runtimes whose firewall helper image is known to exist, so it's only
+/// checked (and built) once per process
+static FIREWALL_IMAGE_READY: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+/// Build the egress firewall helper image unless it already exists
+pub fn ensure_firewall_image(runtime_cmd: &str) -> Result<()> {
+    let mut ready = FIREWALL_IMAGE_READY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if ready.iter().any(|r| r == runtime_cmd) {
+        return Ok(());
+    }
+    let image = crate::egress::FIREWALL_IMAGE;
+    let exists = Command::new(runtime_cmd)
+        .args(["image", "inspect", image])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if !exists {
+        let mut child = Command::new(runtime_cmd)
+            .args(["build", "-q", "-t", image, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to build the egress firewall image")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(crate::egress::FIREWALL_DOCKERFILE.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "Failed to build the egress firewall image {}: {}",
+                image,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    ready.push(runtime_cmd.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_runtime_error() {
+        use ContainerRuntime::{Docker, Podman};
+        let image = "pyhton:3.12";
+        let not_found = |runtime| {
+            Some(RuntimeError::ImageNotFound {
+                runtime,
+                image: image.to_string(),
+            })
+        };
+
+        let cases = [
+            (
+                Docker,
+                "Unable to find image 'pyhton:3.12' locally\n\
+                 docker: Error response from daemon: pull access denied for pyhton, \
+                 repository does not exist or may require 'docker login'.",
+                not_found(Docker),
+            ),
+            (
+                Docker,
+                "docker: Error response from daemon: manifest for python:9.9 not found: \
+                 manifest unknown: manifest unknown.",
+                not_found(Docker),
+            ),
+            (
+                Podman,
+                "Error: initializing source docker://pyhton:3.12: reading manifest 3.12 \
+                 in docker.io/library/pyhton: requested access to the resource is denied",
+                not_found(Podman),
+            ),
+            (
+                Podman,
+                "Error: pyhton:3.12: image not known",
+                not_found(Podman),
+            ),
+            (
+                Docker,
+                "docker: Cannot connect to the Docker daemon at unix:///var/run/docker.sock. \
+                 Is the docker daemon running?",
+                Some(RuntimeError::DaemonUnreachable { runtime: Docker }),
+            ),
+            (
+                Docker,
+                "error during connect: Post \"http://%2F%2F.%2Fpipe%2Fdocker_engine/v1.24/containers/create\": \
+                 open //./pipe/docker_engine: The system cannot find the file specified.",
+                Some(RuntimeError::DaemonUnreachable { runtime: Docker }),
+            ),
+            (
+                Podman,
+                "Cannot connect to Podman. Please verify your connection to the Linux system \
+                 using `podman system connection list`, or try `podman machine init` and \
+                 `podman machine start` to manage a new Linux VM",
+                Some(RuntimeError::DaemonUnreachable { runtime: Podman }),
+            ),
+            (
+                Docker,
+                "docker: permission denied while trying to connect to the Docker daemon socket \
+                 at unix:///var/run/docker.sock: Post \"http://%2Fvar%2Frun%2Fdocker.sock/v1.24/containers/create\": \
+                 dial unix /var/run/docker.sock: connect: permission denied.",
+                Some(RuntimeError::PermissionDenied { runtime: Docker }),
+            ),
+            (
+                Docker,
+                "docker: Error response from daemon: Conflict. The container name \
+                 \"/agentkernel-dev\" is already in use.",
+                None,
+            ),
+        ];
+        for (runtime, stderr, expected) in cases {
+            assert_eq!(
+                classify_runtime_error(runtime, image, stderr),
+                expected,
+                "{}",
+                stderr
+            );
+        }
+
+        // Unrecognized failures keep the runtime's message
+        let err = start_error(Docker, image, "something else");
+        assert!(err.downcast_ref::<RuntimeError>().is_none());
+        assert!(err.to_string().contains("something else"));
+
+        let err = start_error(Docker, image, "Unable to find image 'pyhton:3.12' locally");
+        assert!(err.to_string().contains("docker pull pyhton:3.12"));
+    }
+
+    #[test]
+    fn test_is_digest_pinned() {
+        let digest = "a".repeat(64);
+        assert!(is_digest_pinned(&format!("python@sha256:{}", digest)));
+        assert!(is_digest_pinned(&format!(
+            "ghcr.io/org/app:1.2@sha256:{}",
+            digest
+        )));
+        assert!(!is_digest_pinned("python:latest"));
+        assert!(!is_digest_pinned("python:3.12-alpine"));
+        assert!(!is_digest_pinned("python@sha256:abc"));
+        assert!(!is_digest_pinned(&format!("@sha256:{}", digest)));
+    }
+
+    #[test]
+    fn test_parse_image_digest() {
+        let id = format!("sha256:{}", "1".repeat(64));
+        let repo = format!("python@sha256:{}", "2".repeat(64));
+
+        // Registry digest wins over the local ID
+        let output = format!("{} {}\n", id, repo);
+        assert_eq!(parse_image_digest(&output), Some(repo));
+
+        // Locally built images have no repo digests
+        assert_eq!(parse_image_digest(&format!("{} \n", id)), Some(id));
+        assert_eq!(parse_image_digest(""), None);
+        assert_eq!(parse_image_digest("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_load_output() {
+        assert_eq!(
+            parse_load_output("Loaded image: offline/app:1.0\n"),
+            Some("offline/app:1.0".to_string())
+        );
+        // Podman before 4.0
+        assert_eq!(
+            parse_load_output(
+                "Getting image source signatures\nLoaded image(s): localhost/app:latest\n"
+            ),
+            Some("localhost/app:latest".to_string())
+        );
+
+        // An untagged image only has an ID; a tag wins over it
+        let id = format!("sha256:{}", "3".repeat(64));
+        assert_eq!(
+            parse_load_output(&format!("Loaded image ID: {}\n", id)),
+            Some(id.clone())
+        );
+        let output = format!("Loaded image ID: {}\nLoaded image: app:2\n", id);
+        assert_eq!(parse_load_output(&output), Some("app:2".to_string()));
+
+        assert_eq!(parse_load_output(""), None);
+        assert_eq!(parse_load_output("open app.tar: no such file"), None);
+    }
+
+    #[test]
+    fn test_ephemeral_label_args() {
+        let args = ephemeral_label_args();
+        assert_eq!(args[0], "--label");
+        assert_eq!(args[1], "agentkernel.ephemeral=true");
+        assert_eq!(args[2], "--label");
+        assert!(args[3].starts_with("agentkernel.created="));
+        assert!(
+            args[3]["agentkernel.created=".len()..]
+                .parse::<u64>()
+                .is_ok()
+        );
+        assert_eq!(args[5], format!("agentkernel.owner={}", current_owner()));
+    }
+
+    #[test]
+    fn test_select_stale_ephemeral_skips_live_owners() {
+        let listing = "crashed\ttrue\t1000\thost:1\n\
+                       running\ttrue\t1000\thost:2\n\
+                       legacy\ttrue\t1000\n";
+        let stale = select_stale_ephemeral_with(listing, 10_000, 60, |owner| owner == "host:2");
+        assert_eq!(stale, vec!["crashed", "legacy"]);
+    }
+
+    #[test]
+    fn test_owner_alive() {
+        assert!(owner_alive(&current_owner()));
+        // Can't be checked from here
+        assert!(owner_alive("some-other-host.invalid:1"));
+        assert!(!owner_alive("not an owner"));
+        assert!(!owner_alive(&format!("{}:0", host_name())));
+    }
+
+    #[test]
+    fn test_select_stale_ephemeral_by_label() {
+        let listing = "agentkernel-ephemeral-aaaa\ttrue\t1000\n\
+                       agentkernel-my-project\t\t1000\n\
+                       agentkernel-other\tfalse\t1000\n";
+        let stale = select_stale_ephemeral(listing, 10_000, 60);
+        assert_eq!(stale, vec!["agentkernel-ephemeral-aaaa"]);
+    }
+
+    #[test]
+    fn test_select_stale_ephemeral_age_threshold() {
+        let listing = "old\ttrue\t1000\nfresh\ttrue\t9990\nedge\ttrue\t9940\n";
+        let stale = select_stale_ephemeral(listing, 10_000, 60);
+        assert_eq!(stale, vec!["old", "edge"]);
+
+        // A zero threshold selects everything labelled ephemeral
+        let stale = select_stale_ephemeral(listing, 10_000, 0);
+        assert_eq!(stale.len(), 3);
+    }
+
+    #[test]
+    fn test_select_stale_ephemeral_unknown_age_is_kept() {
+        let listing = "no-created\ttrue\t\ngarbage\ttrue\tyesterday\nshort\ttrue\n";
+        assert!(select_stale_ephemeral(listing, 10_000, 0).is_empty());
+    }
 }
@@ -0,0 +1,591 @@
+//! Active diagnostics for `agentkernel doctor`.
+//!
+//! Unlike `status`, which only reports whether components are installed, each
+//! check here exercises the component (opens /dev/kvm, boots a VM, talks to
+//! the Docker and agentkernel daemons) and explains how to fix what fails.
+
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+
+use crate::backend::BackendType;
+use crate::daemon::DaemonClient;
+use crate::nested::ContainerEnv;
+use crate::setup::{default_data_dir, find_firecracker, find_kernel};
+use crate::vmm::VmManager;
+use crate::vsock::AgentIncompatible;
+
+/// How long the test VM may take to boot and answer
+const BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Pass,
+    /// Not checked because a prerequisite is missing
+    Skip,
+    /// Optional component missing or degraded
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "PASS"),
+            CheckStatus::Skip => write!(f, "SKIP"),
+            CheckStatus::Warn => write!(f, "WARN"),
+            CheckStatus::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// Result of one diagnostic
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How to fix the problem (unset for passing checks)
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Overall health across all checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Healthy,
+    /// Usable, but some optional components are missing
+    Degraded,
+    Unhealthy,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Healthy => write!(f, "healthy"),
+            Verdict::Degraded => write!(f, "healthy with warnings"),
+            Verdict::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+/// Combine check results into a verdict.
+///
+/// Any failure is unhealthy, and so is having no working sandbox backend.
+pub fn verdict(checks: &[Check]) -> Verdict {
+    let worst = checks.iter().map(|c| c.status).max();
+    let has_backend = checks
+        .iter()
+        .any(|c| c.status == CheckStatus::Pass && (c.name == "firecracker" || c.name == "docker"));
+
+    if worst == Some(CheckStatus::Fail) || !has_backend {
+        Verdict::Unhealthy
+    } else if worst == Some(CheckStatus::Warn) {
+        Verdict::Degraded
+    } else {
+        Verdict::Healthy
+    }
+}
+
+/// Try to open the KVM device read/write
+pub fn check_kvm(device: &Path) -> Check {
+    if !device.exists() {
+        return Check::new(
+            "kvm",
+            CheckStatus::Warn,
+            format!(
+                "{} not present; Firecracker backend unavailable",
+                device.display()
+            ),
+        )
+        .with_hint(
+            "Enable hardware virtualization (or nested virtualization), or use the Docker backend",
+        );
+    }
+
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+    {
+        Ok(_) => Check::new(
+            "kvm",
+            CheckStatus::Pass,
+            format!("{} is accessible", device.display()),
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Check::new(
+            "kvm",
+            CheckStatus::Fail,
+            format!(
+                "{} exists but cannot be opened: permission denied",
+                device.display()
+            ),
+        )
+        .with_hint("sudo usermod -aG kvm $USER && newgrp kvm"),
+        Err(e) => Check::new(
+            "kvm",
+            CheckStatus::Fail,
+            format!("Failed to open {}: {}", device.display(), e),
+        )
+        .with_hint("Check that the kvm kernel module is loaded (lsmod | grep kvm)"),
+    }
+}
+
+/// Report whether agentkernel runs nested in a container, and what that rules out
+pub fn check_container(env: Option<&ContainerEnv>, kvm_ok: bool) -> Check {
+    let Some(env) = env else {
+        return Check::new("container", CheckStatus::Pass, "Not running in a container");
+    };
+    let detail = format!("Running {}", env.describe());
+    match (kvm_ok, &env.docker_socket) {
+        (true, _) => Check::new(
+            "container",
+            CheckStatus::Pass,
+            format!("{}, with /dev/kvm passed through", detail),
+        ),
+        (false, Some(_)) => Check::new(
+            "container",
+            CheckStatus::Warn,
+            format!(
+                "{}; Firecracker is unavailable without /dev/kvm, using the host's Docker",
+                detail
+            ),
+        )
+        .with_hint(
+            "Run the container with --device /dev/kvm to use Firecracker. With the host's \
+             Docker socket, mounted paths (mount_cwd) are resolved on the host",
+        ),
+        (false, None) => Check::new(
+            "container",
+            CheckStatus::Warn,
+            format!("{}; Firecracker is unavailable without /dev/kvm", detail),
+        )
+        .with_hint(
+            "Mount the host's Docker socket (-v /var/run/docker.sock:/var/run/docker.sock) \
+             or pass --device /dev/kvm to the container",
+        ),
+    }
+}
+
+/// Check that the Docker daemon answers `docker info`
+pub fn check_docker() -> Check {
+    docker_check_from(
+        Command::new("docker")
+            .args(["info", "--format", "{{.ServerVersion}}"])
+            .output(),
+    )
+}
+
+/// Interpret the output of `docker info`
+fn docker_check_from(output: std::io::Result<Output>) -> Check {
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Check::new("docker", CheckStatus::Warn, "docker CLI not installed")
+                .with_hint("Install Docker or Podman to use the container backend");
+        }
+        Err(e) => {
+            return Check::new(
+                "docker",
+                CheckStatus::Fail,
+                format!("Failed to run docker: {}", e),
+            );
+        }
+    };
+
+    if output.status.success() {
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return Check::new(
+            "docker",
+            CheckStatus::Pass,
+            format!("Docker daemon reachable (server {})", version),
+        );
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let hint = if stderr.to_lowercase().contains("permission denied") {
+        "sudo usermod -aG docker $USER && newgrp docker"
+    } else {
+        "Start the Docker daemon (e.g. sudo systemctl start docker, or open Docker Desktop)"
+    };
+    Check::new(
+        "docker",
+        CheckStatus::Fail,
+        format!("Docker daemon not reachable: {}", stderr),
+    )
+    .with_hint(hint)
+}
+
+/// Check the agentkernel daemon socket answers a status request
+pub async fn check_daemon(client: &DaemonClient) -> Check {
+    let socket = client.socket_path().display().to_string();
+    if !client.socket_path().exists() {
+        return Check::new("daemon", CheckStatus::Skip, "Daemon not running")
+            .with_hint("Optional: start the VM pool with agentkernel daemon start");
+    }
+    if !client.is_available() {
+        return Check::new(
+            "daemon",
+            CheckStatus::Fail,
+            format!("Stale daemon socket at {} (nothing is listening)", socket),
+        )
+        .with_hint(format!(
+            "Remove the socket and restart: rm {} && agentkernel daemon start",
+            socket
+        ));
+    }
+
+    match client.status().await {
+        Ok(status) => Check::new(
+            "daemon",
+            CheckStatus::Pass,
+            format!(
+                "Daemon healthy ({} warm, {} in use)",
+                status.warm, status.in_use
+            ),
+        ),
+        Err(e) => Check::new(
+            "daemon",
+            CheckStatus::Fail,
+            format!("Daemon did not answer a status request: {}", e),
+        )
+        .with_hint("Restart it: agentkernel daemon stop && agentkernel daemon start"),
+    }
+}
+
+/// Why the Firecracker boot test can't run, if it can't
+pub fn firecracker_prerequisites(
+    kvm_ok: bool,
+    data_dir: &Path,
+    firecracker_installed: bool,
+) -> Option<Check> {
+    if !kvm_ok {
+        return Some(Check::new(
+            "firecracker",
+            CheckStatus::Skip,
+            "KVM not usable",
+        ));
+    }
+    let missing: Vec<&str> = [
+        (!firecracker_installed).then_some("firecracker binary"),
+        find_kernel(data_dir).is_none().then_some("kernel"),
+        (!data_dir.join("images/rootfs/base.ext4").exists()).then_some("base rootfs"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if missing.is_empty() {
+        None
+    } else {
+        Some(
+            Check::new(
+                "firecracker",
+                CheckStatus::Fail,
+                format!("Missing {}", missing.join(", ")),
+            )
+            .with_hint("agentkernel setup"),
+        )
+    }
+}
+
+/// Boot a throwaway Firecracker VM and run a command through the guest agent
+async fn boot_test_vm() -> Result<Duration> {
+    let started = Instant::now();
+    let mut manager = VmManager::with_backend(Some(BackendType::Firecracker))?;
+    let name = format!("doctor-{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    manager
+        .create_ephemeral(&name, "alpine:3.20", 1, 256)
+        .await?;
+
+    let result = async {
+        manager.start(&name).await?;
+        manager
+            .exec_cmd(&name, &["echo".to_string(), "ok".to_string()])
+            .await
+    }
+    .await;
+    let _ = manager.remove(&name).await;
+
+    result?;
+    Ok(started.elapsed())
+}
+
+/// Turn the boot test outcome into the firecracker and guest-agent checks
+pub fn boot_checks(result: &Result<Duration>) -> (Check, Check) {
+    match result {
+        Ok(elapsed) => (
+            Check::new(
+                "firecracker",
+                CheckStatus::Pass,
+                format!(
+                    "Test VM booted and ran a command in {} ms",
+                    elapsed.as_millis()
+                ),
+            ),
+            Check::new(
+                "guest-agent",
+                CheckStatus::Pass,
+                format!(
+                    "Guest agent speaks protocol v{}",
+                    crate::vsock::PROTOCOL_VERSION
+                ),
+            ),
+        ),
+        Err(e) => {
+            let message = format!("{:#}", e);
+            // The VM booted, but the agent in the rootfs is out of date
+            if e.downcast_ref::<AgentIncompatible>().is_some() {
+                (
+                    Check::new("firecracker", CheckStatus::Pass, "Test VM booted"),
+                    Check::new("guest-agent", CheckStatus::Fail, message)
+                        .with_hint("Rebuild the rootfs with the current agent: agentkernel setup"),
+                )
+            } else {
+                (
+                    Check::new(
+                        "firecracker",
+                        CheckStatus::Fail,
+                        format!("Test VM failed: {}", message),
+                    )
+                    .with_hint("Re-run agentkernel setup; check dmesg for KVM errors"),
+                    Check::new("guest-agent", CheckStatus::Skip, "Test VM did not boot"),
+                )
+            }
+        }
+    }
+}
+
+/// Run every diagnostic in order
+pub async fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let kvm = check_kvm(Path::new("/dev/kvm"));
+    let kvm_ok = kvm.status == CheckStatus::Pass;
+    checks.push(check_container(crate::nested::detect().as_ref(), kvm_ok));
+    checks.push(kvm);
+
+    let data_dir = default_data_dir();
+    match firecracker_prerequisites(kvm_ok, &data_dir, find_firecracker().is_some()) {
+        Some(check) => {
+            checks.push(check);
+            checks.push(Check::new(
+                "guest-agent",
+                CheckStatus::Skip,
+                "Needs a Firecracker test VM",
+            ));
+        }
+        None => {
+            let result = match tokio::time::timeout(BOOT_TIMEOUT, boot_test_vm()).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!(
+                    "timed out after {}s",
+                    BOOT_TIMEOUT.as_secs()
+                )),
+            };
+            let (boot, agent) = boot_checks(&result);
+            checks.push(boot);
+            checks.push(agent);
+        }
+    }
+
+    checks.push(check_docker());
+    checks.push(check_daemon(&DaemonClient::new()).await);
+    checks
+}
+
+/// Print checks and the verdict
+pub fn print_report(checks: &[Check]) -> Verdict {
+    for check in checks {
+        println!("[{}] {:<12} {}", check.status, check.name, check.detail);
+        if check.status != CheckStatus::Pass
+            && let Some(hint) = &check.hint
+        {
+            println!("       {:<12} fix: {}", "", hint);
+        }
+    }
+    let verdict = verdict(checks);
+    println!("\nOverall: {}", verdict);
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::process::ExitStatusExt;
+    #[cfg(unix)]
+    use std::process::ExitStatus;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    fn output(code: i32, stdout: &str, stderr: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_check_kvm_missing_and_accessible() {
+        let temp_dir = TempDir::new().unwrap();
+        let device = temp_dir.path().join("kvm");
+
+        let check = check_kvm(&device);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.hint.is_some());
+
+        std::fs::write(&device, b"").unwrap();
+        assert_eq!(check_kvm(&device).status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_check_container() {
+        assert_eq!(check_container(None, false).status, CheckStatus::Pass);
+
+        let mut env = ContainerEnv {
+            runtime: "docker".to_string(),
+            docker_socket: None,
+        };
+        assert_eq!(check_container(Some(&env), true).status, CheckStatus::Pass);
+
+        let check = check_container(Some(&env), false);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.detail.contains("Firecracker is unavailable"));
+        assert!(check.hint.unwrap().contains("docker.sock"));
+
+        env.docker_socket = Some("/var/run/docker.sock".into());
+        let check = check_container(Some(&env), false);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert!(check.detail.contains("using the host's Docker"));
+        assert!(check.hint.unwrap().contains("--device /dev/kvm"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_docker_check_from_output() {
+        let check = docker_check_from(Ok(output(0, "27.1.1\n", "")));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.detail.contains("27.1.1"));
+
+        let check = docker_check_from(Ok(output(
+            1,
+            "",
+            "permission denied while trying to connect to the Docker daemon socket",
+        )));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.hint.unwrap().contains("docker"));
+
+        let check = docker_check_from(Ok(output(1, "", "Cannot connect to the Docker daemon")));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.hint.unwrap().contains("Start the Docker daemon"));
+
+        let check = docker_check_from(Err(std::io::Error::from(std::io::ErrorKind::NotFound)));
+        assert_eq!(check.status, CheckStatus::Warn);
+    }
+
+    #[tokio::test]
+    async fn test_check_daemon_socket_states() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("daemon.sock");
+
+        let check = check_daemon(&DaemonClient::with_socket_path(socket.clone())).await;
+        assert_eq!(check.status, CheckStatus::Skip);
+
+        // A socket file with nothing listening is stale
+        std::fs::write(&socket, b"").unwrap();
+        let check = check_daemon(&DaemonClient::with_socket_path(socket)).await;
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("Stale"));
+    }
+
+    #[test]
+    fn test_firecracker_prerequisites() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path();
+
+        let check = firecracker_prerequisites(false, data_dir, true).unwrap();
+        assert_eq!(check.status, CheckStatus::Skip);
+
+        let check = firecracker_prerequisites(true, data_dir, false).unwrap();
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert_eq!(
+            check.detail,
+            "Missing firecracker binary, kernel, base rootfs"
+        );
+
+        std::fs::create_dir_all(data_dir.join("images/kernel")).unwrap();
+        std::fs::create_dir_all(data_dir.join("images/rootfs")).unwrap();
+        std::fs::write(
+            data_dir.join("images/kernel/vmlinux-6.1.70-agentkernel"),
+            b"",
+        )
+        .unwrap();
+        std::fs::write(data_dir.join("images/rootfs/base.ext4"), b"").unwrap();
+        assert!(firecracker_prerequisites(true, data_dir, true).is_none());
+    }
+
+    #[test]
+    fn test_boot_checks_classify_stale_agent() {
+        let (boot, agent) = boot_checks(&Ok(Duration::from_millis(150)));
+        assert_eq!(boot.status, CheckStatus::Pass);
+        assert_eq!(agent.status, CheckStatus::Pass);
+
+        let (boot, agent) = boot_checks(&Err(anyhow::Error::from(AgentIncompatible {
+            detail: "Guest agent did not announce a protocol version".to_string(),
+        })
+        .context("Failed to run echo")));
+        assert_eq!(boot.status, CheckStatus::Pass);
+        assert_eq!(agent.status, CheckStatus::Fail);
+        assert!(agent.hint.unwrap().contains("agentkernel setup"));
+
+        // Mentioning the protocol isn't enough to blame the agent
+        let (boot, agent) = boot_checks(&Err(anyhow::anyhow!(
+            "Firecracker exited early: vsock protocol error"
+        )));
+        assert_eq!(boot.status, CheckStatus::Fail);
+        assert_eq!(agent.status, CheckStatus::Skip);
+    }
+
+    #[test]
+    fn test_verdict() {
+        let pass = |name| Check::new(name, CheckStatus::Pass, "");
+        let warn = |name| Check::new(name, CheckStatus::Warn, "");
+        let fail = |name| Check::new(name, CheckStatus::Fail, "");
+        let skip = |name| Check::new(name, CheckStatus::Skip, "");
+
+        assert_eq!(
+            verdict(&[pass("kvm"), pass("firecracker"), skip("daemon")]),
+            Verdict::Healthy
+        );
+        assert_eq!(
+            verdict(&[warn("kvm"), skip("firecracker"), pass("docker")]),
+            Verdict::Degraded
+        );
+        assert_eq!(
+            verdict(&[pass("docker"), fail("daemon")]),
+            Verdict::Unhealthy
+        );
+        // No usable backend at all
+        assert_eq!(verdict(&[warn("kvm"), warn("docker")]), Verdict::Unhealthy);
+    }
+}
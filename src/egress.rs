@@ -0,0 +1,292 @@
+//! Network egress allowlists.
+//!
+//! With `[network] allowed_hosts` / `allowed_cidrs` set, a sandbox keeps its
+//! network but outbound connections are limited to those destinations (plus
+//! DNS). Docker and Podman sandboxes get iptables rules installed in the
+//! container's network namespace by a short-lived helper container, so the
+//! sandbox itself never holds `CAP_NET_ADMIN` and can't remove them.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// Image used to install the firewall rules, built locally from
+/// `FIREWALL_DOCKERFILE` the first time it's needed
+pub const FIREWALL_IMAGE: &str = "agentkernel-firewall:alpine3.20";
+
+/// Alpine with iptables baked in, so starts don't install packages
+pub const FIREWALL_DOCKERFILE: &str =
+    "FROM alpine:3.20\nRUN apk add --no-cache iptables ip6tables\n";
+
+/// Hosts allowed by `[network] package_registries = true`
+pub const PACKAGE_REGISTRIES: &[&str] = &[
+    "pypi.org",
+    "files.pythonhosted.org",
+    "registry.npmjs.org",
+    "crates.io",
+    "index.crates.io",
+    "static.crates.io",
+    "proxy.golang.org",
+    "sum.golang.org",
+    "rubygems.org",
+    "repo.maven.apache.org",
+    "github.com",
+    "codeload.github.com",
+    "objects.githubusercontent.com",
+];
+
+/// Destinations a sandbox may connect to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EgressAllowlist {
+    /// Hostnames, resolved to addresses when the sandbox starts
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Addresses or CIDR ranges (e.g. "10.0.0.0/8")
+    #[serde(default)]
+    pub cidrs: Vec<String>,
+}
+
+impl EgressAllowlist {
+    /// Check that hostnames and CIDRs are well-formed
+    pub fn validate(&self) -> Result<()> {
+        for host in &self.hosts {
+            if host.is_empty()
+                || host.contains('*')
+                || host.contains('/')
+                || host.contains(char::is_whitespace)
+            {
+                bail!(
+                    "Invalid allowed host '{}' in [network]: must be a plain hostname \
+                     (wildcards can't be enforced by address)",
+                    host
+                );
+            }
+        }
+        for cidr in &self.cidrs {
+            cidr.parse::<Cidr>()
+                .with_context(|| format!("Invalid allowed CIDR '{}' in [network]", cidr))?;
+        }
+        Ok(())
+    }
+
+    /// All allowed destinations, resolving hostnames to their current addresses
+    pub fn resolve(&self) -> Result<Vec<Cidr>> {
+        let mut allowed = Vec::new();
+        for cidr in &self.cidrs {
+            allowed.push(cidr.parse::<Cidr>()?);
+        }
+        for host in &self.hosts {
+            let addrs = (host.as_str(), 0)
+                .to_socket_addrs()
+                .with_context(|| format!("Failed to resolve allowed host '{}'", host))?;
+            for addr in addrs {
+                let cidr = Cidr::host(addr.ip());
+                if !allowed.contains(&cidr) {
+                    allowed.push(cidr);
+                }
+            }
+        }
+        Ok(allowed)
+    }
+}
+
+/// An address range in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    /// A range holding a single address
+    pub fn host(addr: IpAddr) -> Self {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    /// Parse "addr/prefix", or a bare address
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().context("not an IP address")?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .with_context(|| format!("prefix length must be between 0 and {}", max))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// iptables commands limiting outbound traffic to `allowed`.
+///
+/// Loopback, replies on established connections, and DNS stay open;
+/// everything else is rejected so blocked connections fail fast rather than
+/// hang. DNS is only allowed to the `dns` servers, or when none are
+/// configured to the resolvers in the sandbox's `/etc/resolv.conf` (read by
+/// the helper, which shares it), so port 53 can't tunnel to anywhere else.
+pub fn iptables_rules(allowed: &[Cidr], dns: &[IpAddr], ipv6: bool) -> Vec<String> {
+    let tool = if ipv6 { "ip6tables" } else { "iptables" };
+    let mut rules = vec![
+        format!("{} -F OUTPUT", tool),
+        format!("{} -A OUTPUT -o lo -j ACCEPT", tool),
+        format!(
+            "{} -A OUTPUT -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT",
+            tool
+        ),
+    ];
+
+    if dns.is_empty() {
+        // Scope IDs (fe80::1%eth0) aren't part of the address
+        let family = if ipv6 { "grep ':'" } else { "grep -v ':'" };
+        rules.push(format!(
+            "for ns in $(awk '/^nameserver/ {{ sub(/%.*/, \"\", $2); print $2 }}' /etc/resolv.conf | {family}); do \
+             {tool} -A OUTPUT -d \"$ns\" -p udp --dport 53 -j ACCEPT; \
+             {tool} -A OUTPUT -d \"$ns\" -p tcp --dport 53 -j ACCEPT; done"
+        ));
+    }
+    let dns_servers: Vec<_> = dns.iter().filter(|ip| ip.is_ipv6() == ipv6).collect();
+    for proto in ["udp", "tcp"] {
+        for server in &dns_servers {
+            rules.push(format!(
+                "{} -A OUTPUT -d {} -p {} --dport 53 -j ACCEPT",
+                tool,
+                Cidr::host(**server),
+                proto
+            ));
+        }
+    }
+
+    for cidr in allowed.iter().filter(|c| c.addr.is_ipv6() == ipv6) {
+        rules.push(format!("{} -A OUTPUT -d {} -j ACCEPT", tool, cidr));
+    }
+    rules.push(format!("{} -A OUTPUT -j REJECT", tool));
+    rules
+}
+
+/// Shell script for the helper container that installs the rules
+pub fn firewall_script(allowed: &[Cidr], dns: &[IpAddr]) -> String {
+    let mut script = vec!["set -e".to_string()];
+    script.extend(iptables_rules(allowed, dns, false));
+    script.push("if [ -e /proc/net/if_inet6 ]; then".to_string());
+    script.extend(
+        iptables_rules(allowed, dns, true)
+            .into_iter()
+            .map(|rule| format!("  {}", rule)),
+    );
+    script.push("fi".to_string());
+    script.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert_eq!("1.2.3.4".parse::<Cidr>().unwrap().to_string(), "1.2.3.4/32");
+        assert_eq!(
+            "2001:db8::/32".parse::<Cidr>().unwrap().to_string(),
+            "2001:db8::/32"
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_rules_from_allowlist() {
+        let allowed = vec![
+            "10.1.0.0/16".parse().unwrap(),
+            "151.101.0.223".parse().unwrap(),
+            "2a04:4e42::223".parse().unwrap(),
+        ];
+        let rules = iptables_rules(&allowed, &[], false);
+        assert_eq!(rules[0], "iptables -F OUTPUT");
+        assert!(rules.contains(&"iptables -A OUTPUT -o lo -j ACCEPT".to_string()));
+        // DNS goes only to the sandbox's own resolvers, never to any address
+        assert!(
+            !rules
+                .iter()
+                .any(|r| r.starts_with("iptables -A OUTPUT -p udp --dport 53"))
+        );
+        let resolvers = rules.iter().find(|r| r.starts_with("for ns in")).unwrap();
+        assert!(resolvers.contains("/etc/resolv.conf | grep -v ':'"));
+        assert!(resolvers.contains("iptables -A OUTPUT -d \"$ns\" -p udp --dport 53 -j ACCEPT"));
+        assert!(rules.contains(&"iptables -A OUTPUT -d 10.1.0.0/16 -j ACCEPT".to_string()));
+        assert!(rules.contains(&"iptables -A OUTPUT -d 151.101.0.223/32 -j ACCEPT".to_string()));
+        // IPv6 destinations only go into the ip6tables rules
+        assert!(!rules.iter().any(|r| r.contains("2a04")));
+        // Everything else is rejected last
+        assert_eq!(rules.last().unwrap(), "iptables -A OUTPUT -j REJECT");
+
+        let rules = iptables_rules(&allowed, &[], true);
+        assert!(rules.contains(&"ip6tables -A OUTPUT -d 2a04:4e42::223/128 -j ACCEPT".to_string()));
+        assert_eq!(rules.last().unwrap(), "ip6tables -A OUTPUT -j REJECT");
+    }
+
+    #[test]
+    fn test_rules_limit_dns_to_configured_servers() {
+        let dns = vec!["1.1.1.1".parse().unwrap()];
+        let rules = iptables_rules(&[], &dns, false);
+        assert!(
+            rules.contains(
+                &"iptables -A OUTPUT -d 1.1.1.1/32 -p udp --dport 53 -j ACCEPT".to_string()
+            )
+        );
+        assert!(
+            !rules
+                .iter()
+                .any(|r| r.contains("-p udp --dport 53") && !r.contains("1.1.1.1"))
+        );
+        assert!(!rules.iter().any(|r| r.contains("resolv.conf")));
+
+        let script = firewall_script(&[], &dns);
+        assert!(script.starts_with("set -e\n"));
+        // The tools come with the image; nothing is installed at start
+        assert!(!script.contains("apk"));
+        assert!(script.contains("  ip6tables -A OUTPUT -j REJECT\nfi"));
+    }
+
+    #[test]
+    fn test_validate_and_resolve() {
+        let allow = EgressAllowlist {
+            hosts: vec!["localhost".to_string()],
+            cidrs: vec!["192.168.0.0/24".to_string()],
+        };
+        allow.validate().unwrap();
+        let resolved = allow.resolve().unwrap();
+        assert_eq!(resolved[0].to_string(), "192.168.0.0/24");
+        assert!(resolved.iter().any(|c| c.addr.is_loopback()));
+
+        let wildcard = EgressAllowlist {
+            hosts: vec!["*.npmjs.com".to_string()],
+            cidrs: Vec::new(),
+        };
+        assert!(wildcard.validate().is_err());
+        let bad_cidr = EgressAllowlist {
+            hosts: Vec::new(),
+            cidrs: vec!["10.0.0.0/40".to_string()],
+        };
+        assert!(bad_cidr.validate().is_err());
+    }
+}
@@ -58,6 +58,35 @@ pub struct InstanceAction {
     pub action_type: String,
 }
 
+/// VM state change (pause or resume)
+#[derive(Debug, Serialize)]
+pub struct VmState {
+    pub state: String,
+}
+
+/// Full snapshot of a paused VM
+#[derive(Debug, Serialize)]
+pub struct SnapshotCreateParams {
+    pub snapshot_type: String,
+    pub snapshot_path: String,
+    pub mem_file_path: String,
+}
+
+/// Guest memory backing for a restored snapshot
+#[derive(Debug, Serialize)]
+pub struct MemoryBackend {
+    pub backend_type: String,
+    pub backend_path: String,
+}
+
+/// Snapshot to restore into a fresh Firecracker process
+#[derive(Debug, Serialize)]
+pub struct SnapshotLoadParams {
+    pub snapshot_path: String,
+    pub mem_backend: MemoryBackend,
+    pub resume_vm: bool,
+}
+
 /// Network interface configuration
 #[derive(Debug, Serialize)]
 pub struct NetworkInterface {
@@ -144,21 +173,45 @@ impl FirecrackerClient {
     }
 
     /// Pause the VM
-    #[allow(dead_code)]
     pub async fn pause(&self) -> Result<()> {
-        let action = InstanceAction {
-            action_type: "Pause".to_string(),
+        let state = VmState {
+            state: "Paused".to_string(),
         };
-        self.put("/actions", &action).await
+        self.patch("/vm", &state).await
     }
 
     /// Resume the VM
     #[allow(dead_code)]
     pub async fn resume(&self) -> Result<()> {
-        let action = InstanceAction {
-            action_type: "Resume".to_string(),
+        let state = VmState {
+            state: "Resumed".to_string(),
         };
-        self.put("/actions", &action).await
+        self.patch("/vm", &state).await
+    }
+
+    /// Write a full snapshot of the paused VM: device state to
+    /// `snapshot_path`, guest memory to `mem_file_path`
+    pub async fn create_snapshot(&self, snapshot_path: &Path, mem_file_path: &Path) -> Result<()> {
+        let params = SnapshotCreateParams {
+            snapshot_type: "Full".to_string(),
+            snapshot_path: snapshot_path.to_string_lossy().to_string(),
+            mem_file_path: mem_file_path.to_string_lossy().to_string(),
+        };
+        self.put("/snapshot/create", &params).await
+    }
+
+    /// Restore a snapshot into this (unconfigured) Firecracker process and
+    /// resume it
+    pub async fn load_snapshot(&self, snapshot_path: &Path, mem_file_path: &Path) -> Result<()> {
+        let params = SnapshotLoadParams {
+            snapshot_path: snapshot_path.to_string_lossy().to_string(),
+            mem_backend: MemoryBackend {
+                backend_type: "File".to_string(),
+                backend_path: mem_file_path.to_string_lossy().to_string(),
+            },
+            resume_vm: true,
+        };
+        self.put("/snapshot/load", &params).await
     }
 
     /// Make a PUT request
@@ -167,6 +220,12 @@ impl FirecrackerClient {
         Ok(())
     }
 
+    /// Make a PATCH request
+    async fn patch<T: Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        let _ = self.request(Method::PATCH, path, Some(body)).await?;
+        Ok(())
+    }
+
     /// Make an HTTP request to the Firecracker API
     async fn request<T: Serialize>(
         &self,
@@ -275,4 +334,20 @@ mod tests {
         assert!(json.contains("vcpu_count"));
         assert!(json.contains("mem_size_mib"));
     }
+
+    #[test]
+    fn test_snapshot_load_serialize() {
+        let params = SnapshotLoadParams {
+            snapshot_path: "/snap/vm.snap".to_string(),
+            mem_backend: MemoryBackend {
+                backend_type: "File".to_string(),
+                backend_path: "/snap/vm.mem".to_string(),
+            },
+            resume_vm: true,
+        };
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["mem_backend"]["backend_type"], "File");
+        assert_eq!(json["mem_backend"]["backend_path"], "/snap/vm.mem");
+        assert_eq!(json["resume_vm"], true);
+    }
 }
@@ -14,21 +14,37 @@
 //! ```
 
 use anyhow::Result;
-use http_body_util::{BodyExt, Full};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-
+use tokio::sync::{Mutex, OnceCell, OwnedMutexGuard};
+
+use crate::agents;
+use crate::audit::{self, AuditEvent};
+use crate::backend::{
+    BackendUnavailable, DEFAULT_MAX_OUTPUT_BYTES, ExecOptions, FileInjection, FileNotFound,
+    LogStream, RestartPolicy, SandboxGone, WAIT_POLL_INTERVAL, WriteOptions, parse_file_mode,
+};
+use crate::config::Config;
+use crate::daemon::{DaemonClient, PoolStatus};
+use crate::docker_backend::RuntimeError;
+use crate::idempotency::{self, Claim, IdempotencyStore, StoredResponse};
+use crate::jobs::{JobInfo, JobStatus, JobStore};
 use crate::languages;
 use crate::permissions::SecurityProfile;
+use crate::policy::PolicyDenial;
+use crate::pool::PoolStats;
 use crate::validation;
-use crate::vmm::VmManager;
+use crate::vmm::{LabelFilter, SandboxLookupError, VmManager, probe_sandboxes};
+use crate::vsock::ResourceUsage;
 
 type BoxBody = http_body_util::combinators::BoxBody<bytes::Bytes, hyper::Error>;
 
@@ -47,6 +63,9 @@ struct RunRequest {
     /// Use container pool for faster execution (default: true for /run)
     #[serde(default = "default_fast")]
     fast: bool,
+    /// Keep at most this many bytes of stdout and of stderr
+    #[serde(default)]
+    max_output: Option<usize>,
 }
 
 fn default_fast() -> bool {
@@ -61,6 +80,14 @@ struct CreateRequest {
     vcpus: Option<u32>,
     memory_mb: Option<u64>,
     profile: Option<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    /// Restart policy: never (default), on-failure or always
+    #[serde(default)]
+    restart: RestartPolicy,
+    /// Shell script run once after the sandbox first starts
+    #[serde(default)]
+    setup_script: Option<String>,
 }
 
 /// Request to write a file
@@ -70,12 +97,41 @@ struct FileWriteRequest {
     /// "utf8" (default) or "base64"
     #[serde(default = "default_encoding")]
     encoding: String,
+    /// Append to the file instead of replacing it
+    #[serde(default)]
+    append: bool,
+    /// Octal permissions, e.g. "755"
+    mode: Option<String>,
+}
+
+impl FileWriteRequest {
+    fn write_options(&self) -> Result<WriteOptions> {
+        let mode = self.mode.as_deref().map(parse_file_mode).transpose()?;
+        Ok(WriteOptions::default().append(self.append).mode(mode))
+    }
 }
 
 fn default_encoding() -> String {
     "utf8".to_string()
 }
 
+/// A file from a multipart upload
+#[derive(Debug, PartialEq)]
+struct UploadedFile {
+    /// Destination path inside the sandbox (absolute)
+    path: String,
+    content: Vec<u8>,
+}
+
+/// Per-file result of a multipart upload
+#[derive(Debug, Serialize)]
+struct FileUploadResult {
+    path: String,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
 /// Response for file read
 #[derive(Debug, Serialize)]
 struct FileReadResponse {
@@ -105,12 +161,156 @@ struct BatchRunResponse {
 struct BatchResult {
     output: Option<String>,
     error: Option<String>,
+    /// Output went over the server's limit and was cut short
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+/// Request for bulk sandbox operations
+#[derive(Debug, Deserialize)]
+struct BulkRequest {
+    operations: Vec<BulkOperation>,
+    /// Run operations on different sandboxes concurrently
+    #[serde(default)]
+    parallel: bool,
+    /// Undo completed operations if any operation fails
+    #[serde(default)]
+    atomic: bool,
+}
+
+/// One operation in a bulk request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BulkOperation {
+    /// Register a sandbox without starting it
+    Create {
+        name: String,
+        image: Option<String>,
+        vcpus: Option<u32>,
+        memory_mb: Option<u64>,
+        #[serde(default)]
+        labels: HashMap<String, String>,
+    },
+    /// Start a sandbox with an optional security profile
+    Start {
+        name: String,
+        profile: Option<String>,
+    },
+    Stop {
+        name: String,
+    },
+    Delete {
+        name: String,
+    },
+}
+
+impl BulkOperation {
+    fn name(&self) -> &str {
+        match self {
+            Self::Create { name, .. }
+            | Self::Start { name, .. }
+            | Self::Stop { name }
+            | Self::Delete { name } => name,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Create { .. } => "create",
+            Self::Start { .. } => "start",
+            Self::Stop { .. } => "stop",
+            Self::Delete { .. } => "delete",
+        }
+    }
+
+    /// Check arguments before anything runs
+    fn validate(&self) -> Result<()> {
+        validation::validate_sandbox_name(self.name())?;
+        match self {
+            Self::Create {
+                image,
+                vcpus,
+                memory_mb,
+                labels,
+                ..
+            } => {
+                if let Some(img) = image {
+                    validation::validate_docker_image(img)?;
+                }
+                validation::validate_resources(vcpus.unwrap_or(1), memory_mb.unwrap_or(512))?;
+                for (key, value) in labels {
+                    crate::vmm::validate_label(key, value)?;
+                }
+            }
+            Self::Start {
+                profile: Some(profile),
+                ..
+            } if resolve_profile(profile).is_none() => {
+                anyhow::bail!(
+                    "Invalid profile '{}'. Use: permissive, moderate, restrictive",
+                    profile
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Response for bulk operations, one result per operation in request order
+#[derive(Debug, Serialize)]
+struct BulkResponse {
+    results: Vec<BulkResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkResult {
+    op: &'static str,
+    name: String,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Undone because a later operation in an atomic batch failed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    rolled_back: bool,
+}
+
+impl BulkResult {
+    fn new(op: &BulkOperation, result: Result<()>) -> Self {
+        Self {
+            op: op.kind(),
+            name: op.name().to_string(),
+            success: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+            rolled_back: false,
+        }
+    }
 }
 
 /// Request to execute in a sandbox
 #[derive(Debug, Deserialize)]
 struct ExecRequest {
     command: Vec<String>,
+    /// Report a non-zero exit as an error (default: true). When false the
+    /// exit code is returned in the response instead.
+    #[serde(default = "default_check")]
+    check: bool,
+    /// Kill the command if it runs longer than this many seconds
+    #[serde(default)]
+    timeout: Option<u64>,
+    /// Keep at most this many bytes of stdout and of stderr
+    #[serde(default)]
+    max_output: Option<usize>,
+}
+
+fn default_check() -> bool {
+    true
+}
+
+/// Request to turn a sandbox's network access on or off
+#[derive(Debug, Deserialize)]
+struct NetworkRequest {
+    enabled: bool,
 }
 
 /// API response
@@ -121,6 +321,15 @@ struct ApiResponse<T: Serialize> {
     data: Option<T>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
+    /// Machine-readable reason for `error`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<ErrorCode>,
+    /// Link to the next page of a paginated list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+    /// Why a successful response is missing data, e.g. audit logging is off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
 }
 
 impl<T: Serialize> ApiResponse<T> {
@@ -129,14 +338,102 @@ impl<T: Serialize> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            error_code: None,
+            next: None,
+            note: None,
         }
     }
 
-    fn error(msg: impl Into<String>) -> ApiResponse<()> {
+    fn error(code: ErrorCode, msg: impl Into<String>) -> ApiResponse<()> {
         ApiResponse {
             success: false,
             data: None,
             error: Some(msg.into()),
+            error_code: Some(code),
+            next: None,
+            note: None,
+        }
+    }
+}
+
+/// Stable, machine-readable reason a request failed, sent as `error_code`
+/// so clients don't have to match on the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum ErrorCode {
+    /// Malformed body, or a missing or invalid parameter
+    InvalidRequest,
+    /// Missing or wrong API key
+    Unauthorized,
+    /// No such route, job, or file
+    NotFound,
+    SandboxNotFound,
+    SandboxNotRunning,
+    /// Clashes with a request in progress or the sandbox's state
+    Conflict,
+    /// The image name is invalid or the image doesn't exist
+    InvalidImage,
+    /// No sandbox backend, or its daemon, can be used
+    BackendUnavailable,
+    /// The security policy denied the command
+    PolicyDenied,
+    Timeout,
+    Internal,
+}
+
+impl ErrorCode {
+    /// The code for a status when nothing more specific is known
+    fn for_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST
+            | StatusCode::RANGE_NOT_SATISFIABLE
+            | StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::InvalidRequest,
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::REQUEST_TIMEOUT => ErrorCode::Timeout,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// The status a response with this code is sent with
+    fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::InvalidImage => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotFound | ErrorCode::SandboxNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::SandboxNotRunning | ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::BackendUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::PolicyDenied => StatusCode::FORBIDDEN,
+            ErrorCode::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The code for an error the manager or a backend recognized, if any
+    fn classify(e: &anyhow::Error) -> Option<Self> {
+        if e.downcast_ref::<PolicyDenial>().is_some() {
+            return Some(ErrorCode::PolicyDenied);
+        }
+        if let Some(lookup) = e.downcast_ref::<SandboxLookupError>() {
+            return Some(match lookup {
+                SandboxLookupError::NotFound { .. } => ErrorCode::SandboxNotFound,
+                SandboxLookupError::NotRunning { .. } => ErrorCode::SandboxNotRunning,
+            });
+        }
+        if e.downcast_ref::<SandboxGone>().is_some() {
+            return Some(ErrorCode::SandboxNotFound);
+        }
+        if e.downcast_ref::<BackendUnavailable>().is_some() {
+            return Some(ErrorCode::BackendUnavailable);
+        }
+        if e.downcast_ref::<FileNotFound>().is_some() {
+            return Some(ErrorCode::NotFound);
+        }
+        match e.downcast_ref::<RuntimeError>() {
+            Some(RuntimeError::ImageNotFound { .. }) => Some(ErrorCode::InvalidImage),
+            Some(_) => Some(ErrorCode::BackendUnavailable),
+            None => None,
         }
     }
 }
@@ -155,18 +452,120 @@ struct SandboxInfo {
     memory_mb: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     created_at: Option<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    labels: HashMap<String, String>,
+    /// Image digest resolved at the last start
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_digest: Option<String>,
+    /// Address on the sandbox's network, while it runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_address: Option<std::net::IpAddr>,
+}
+
+/// Response for `GET /sandboxes/{name}/stats`
+#[derive(Debug, Serialize)]
+struct SandboxStats {
+    name: String,
+    /// Host disk used by the root filesystem and writable layer or disks
+    disk_bytes: u64,
+}
+
+/// Response for `DELETE /sandboxes?all=true`
+#[derive(Debug, Serialize)]
+struct StopAllResponse {
+    stopped: Vec<String>,
+    failed: Vec<StopFailure>,
+    /// Whether a VM pool daemon was running and was shut down
+    daemon_stopped: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct StopFailure {
+    name: String,
+    error: String,
+}
+
+/// Wait response
+#[derive(Debug, Serialize)]
+struct WaitResponse {
+    exit_code: i32,
 }
 
 /// Run command response
 #[derive(Debug, Serialize)]
 struct RunResponse {
     output: String,
+    /// Resource usage, when the backend reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rusage: Option<ResourceUsage>,
+    /// Exit code, reported by sandbox exec
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    /// Output went over the backend's limit and was cut short
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    /// "base64" when the output isn't valid UTF-8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
 }
 
 /// Shared state for the HTTP server
 struct AppState {
     /// Optional API key for authentication
     api_key: Option<String>,
+    /// Asynchronous jobs submitted via /jobs
+    jobs: Arc<JobStore>,
+    /// Responses kept for replay by Idempotency-Key
+    idempotency: Arc<IdempotencyStore>,
+    /// Backend manager shared by all requests, created on first use
+    manager: OnceCell<Arc<Mutex<VmManager>>>,
+    /// Per-sandbox locks held by [`SandboxLease`]s
+    sandbox_locks: std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    /// Bytes of stdout and of stderr kept per command unless a request asks for fewer
+    max_output: usize,
+    /// Largest multipart upload body accepted, in bytes
+    max_upload: usize,
+}
+
+/// One sandbox split off the shared manager by [`AppState::lease`].
+///
+/// Dropping it merges the sandbox's handle back into the shared manager.
+struct SandboxLease {
+    manager: Option<VmManager>,
+    shared: Arc<Mutex<VmManager>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl std::ops::Deref for SandboxLease {
+    type Target = VmManager;
+
+    fn deref(&self) -> &VmManager {
+        self.manager.as_ref().expect("lease already released")
+    }
+}
+
+impl std::ops::DerefMut for SandboxLease {
+    fn deref_mut(&mut self) -> &mut VmManager {
+        self.manager.as_mut().expect("lease already released")
+    }
+}
+
+impl Drop for SandboxLease {
+    fn drop(&mut self) {
+        let (Some(manager), guard) = (self.manager.take(), self.guard.take()) else {
+            return;
+        };
+        if let Ok(mut shared) = self.shared.try_lock() {
+            shared.merge(manager);
+            return;
+        }
+        // The sandbox stays locked until its handle is back
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            shared.lock().await.merge(manager);
+            drop(guard);
+        });
+    }
 }
 
 impl AppState {
@@ -176,7 +575,15 @@ impl AppState {
         if api_key.is_some() {
             eprintln!("API key authentication enabled");
         }
-        Self { api_key }
+        Self {
+            api_key,
+            jobs: Arc::new(JobStore::default()),
+            idempotency: Arc::new(IdempotencyStore::default()),
+            manager: OnceCell::new(),
+            sandbox_locks: Default::default(),
+            max_output: Config::server_max_output(),
+            max_upload: Config::server_max_upload(DEFAULT_MAX_UPLOAD_BYTES),
+        }
     }
 
     /// Create state with explicit API key
@@ -185,10 +592,135 @@ impl AppState {
         if api_key.is_some() {
             eprintln!("API key authentication enabled");
         }
-        Self { api_key }
+        Self {
+            api_key,
+            jobs: Arc::new(JobStore::default()),
+            idempotency: Arc::new(IdempotencyStore::default()),
+            manager: OnceCell::new(),
+            sandbox_locks: Default::default(),
+            max_output: DEFAULT_MAX_OUTPUT_BYTES,
+            max_upload: DEFAULT_MAX_UPLOAD_BYTES,
+        }
+    }
+
+    /// Create state around an existing manager
+    #[cfg(test)]
+    fn with_manager(manager: VmManager) -> Self {
+        Self {
+            api_key: None,
+            jobs: Arc::new(JobStore::default()),
+            idempotency: Arc::new(IdempotencyStore::default()),
+            manager: OnceCell::new_with(Some(Arc::new(Mutex::new(manager)))),
+            sandbox_locks: Default::default(),
+            max_output: DEFAULT_MAX_OUTPUT_BYTES,
+            max_upload: DEFAULT_MAX_UPLOAD_BYTES,
+        }
+    }
+
+    /// Output kept per stream for a request: what it asked for, up to the
+    /// server's limit
+    fn max_output(&self, requested: Option<usize>) -> usize {
+        requested.map_or(self.max_output, |n| n.min(self.max_output))
+    }
+
+    /// Lock the shared manager, creating it on first use.
+    ///
+    /// State files changed by other processes are read again; containers are
+    /// left to [`AppState::detect_running`]. The lock serializes changes to
+    /// the shared state.
+    async fn get_manager(&self) -> Result<OwnedMutexGuard<VmManager>> {
+        let manager = self
+            .manager
+            .get_or_try_init(|| async { VmManager::new().map(|m| Arc::new(Mutex::new(m))) })
+            .await?;
+        let mut manager = manager.clone().lock_owned().await;
+        // Sandboxes may have been created or removed by the CLI meanwhile
+        manager.refresh()?;
+        Ok(manager)
+    }
+
+    /// Take one sandbox out of the shared manager for the length of a request.
+    ///
+    /// The shared lock is held only while the sandbox's handle is split
+    /// off; requests for the same sandbox queue on its own lock instead.
+    async fn lease(&self, name: &str) -> Result<SandboxLease> {
+        let lock = {
+            let mut locks = self.sandbox_locks.lock().unwrap();
+            // Drop locks nobody holds or waits for
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(name.to_string()).or_default().clone()
+        };
+        let guard = lock.lock_owned().await;
+        let mut shared = self.get_manager().await?;
+        let mut lease = SandboxLease {
+            manager: Some(shared.split_off(name)),
+            shared: OwnedMutexGuard::mutex(&shared).clone(),
+            guard: Some(guard),
+        };
+        drop(shared);
+
+        // No handle yet: it may have been started or stopped outside the server
+        let unwatched: Vec<_> = lease
+            .unwatched()
+            .into_iter()
+            .filter(|s| s.name == name)
+            .collect();
+        if !unwatched.is_empty()
+            && let Ok(detected) =
+                tokio::task::spawn_blocking(move || probe_sandboxes(unwatched)).await
+        {
+            lease.apply_detected(detected);
+        }
+        Ok(lease)
+    }
+
+    /// Sandboxes currently leased by a request
+    fn leased(&self) -> HashSet<String> {
+        self.sandbox_locks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, lock)| lock.try_lock().is_err())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Pick up containers started or stopped outside the server.
+    ///
+    /// The container runtimes are asked on a blocking thread, without the
+    /// shared lock. Leased sandboxes are skipped: a request may be starting
+    /// or stopping them, and [`AppState::lease`] looks for itself.
+    async fn detect_running(&self) -> Result<()> {
+        let unwatched: Vec<_> = {
+            let manager = self.get_manager().await?;
+            let leased = self.leased();
+            manager
+                .unwatched()
+                .into_iter()
+                .filter(|s| !leased.contains(&s.name))
+                .collect()
+        };
+        if unwatched.is_empty() {
+            return Ok(());
+        }
+        let detected = tokio::task::spawn_blocking(move || probe_sandboxes(unwatched)).await?;
+
+        let mut manager = self.get_manager().await?;
+        let leased = self.leased();
+        manager.apply_detected(
+            detected
+                .into_iter()
+                .filter(|(name, _)| !leased.contains(name))
+                .collect(),
+        );
+        Ok(())
     }
 
-    async fn get_manager(&self) -> Result<VmManager> {
+    /// A manager private to one ephemeral run.
+    ///
+    /// Runs own their sandbox outright, so they don't hold the shared lock
+    /// for however long the command takes.
+    fn run_manager(&self) -> Result<VmManager> {
         VmManager::new()
     }
 
@@ -213,19 +745,16 @@ impl AppState {
                 if token == api_key {
                     Ok(())
                 } else {
-                    Err(json_response(
-                        StatusCode::UNAUTHORIZED,
-                        &ApiResponse::<()>::error("Invalid API key"),
-                    ))
+                    Err(error_response(StatusCode::UNAUTHORIZED, "Invalid API key"))
                 }
             }
-            Some(_) => Err(json_response(
+            Some(_) => Err(error_response(
                 StatusCode::UNAUTHORIZED,
-                &ApiResponse::<()>::error("Invalid authorization format. Use: Bearer <api_key>"),
+                "Invalid authorization format. Use: Bearer <api_key>",
             )),
-            None => Err(json_response(
+            None => Err(error_response(
                 StatusCode::UNAUTHORIZED,
-                &ApiResponse::<()>::error("Missing Authorization header"),
+                "Missing Authorization header",
             )),
         }
     }
@@ -252,7 +781,143 @@ async fn handle_request(
         return Ok(resp);
     }
 
-    let response = match (method, segments.as_slice()) {
+    // Retries with the same Idempotency-Key get the first response
+    let key = match idempotency_key(&req, &method, &segments) {
+        Ok(key) => key,
+        Err(resp) => return Ok(resp),
+    };
+    let Some(key) = key else {
+        return Ok(route_request(req.map(BodyExt::boxed), method, &segments, state).await);
+    };
+    // The body is read up front, so a reused key with a different body is caught
+    let (parts, body) = req.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "Failed to read body",
+            ));
+        }
+    };
+    let fingerprint = idempotency::fingerprint(&body);
+    let req = Request::from_parts(parts, full(body));
+    let store = state.idempotency.clone();
+    let response = route_request(req, method, &segments, state);
+    Ok(with_idempotency(&store, &key, fingerprint, response).await)
+}
+
+/// POST endpoints that create sandboxes or run commands, where a retried
+/// request would do the work twice
+fn accepts_idempotency_key(method: &Method, segments: &[&str]) -> bool {
+    *method == Method::POST
+        && matches!(
+            segments,
+            ["run"]
+                | ["batch", "run"]
+                | ["jobs"]
+                | ["sandboxes"]
+                | ["sandboxes", "bulk"]
+                | ["sandboxes", _, "exec"]
+        )
+}
+
+/// The request's Idempotency-Key, scoped to the caller's credential and the
+/// endpoint so other clients and endpoints can't replay its response
+#[allow(clippy::result_large_err)]
+fn idempotency_key(
+    req: &Request<Incoming>,
+    method: &Method,
+    segments: &[&str],
+) -> Result<Option<String>, Response<BoxBody>> {
+    let Some(header) = req.headers().get("idempotency-key") else {
+        return Ok(None);
+    };
+    if !accepts_idempotency_key(method, segments) {
+        return Ok(None);
+    }
+    let key = String::from_utf8_lossy(header.as_bytes());
+    if let Err(e) = idempotency::validate_key(&key) {
+        return Err(error_response(StatusCode::BAD_REQUEST, e.to_string()));
+    }
+    // Hashed, so the API key isn't kept in the store
+    let principal = req
+        .headers()
+        .get("authorization")
+        .map_or(0, |v| idempotency::fingerprint(v.as_bytes()));
+    Ok(Some(format!(
+        "{:016x}\n{} /{}\n{}",
+        principal,
+        method,
+        segments.join("/"),
+        key
+    )))
+}
+
+/// Run `handler` for the first request with `key`, and send its response
+/// again to later requests with the same key
+async fn with_idempotency(
+    store: &Arc<IdempotencyStore>,
+    key: &str,
+    fingerprint: u64,
+    handler: impl Future<Output = Response<BoxBody>>,
+) -> Response<BoxBody> {
+    let pending = match store.claim(key, fingerprint) {
+        Claim::New(pending) => pending,
+        Claim::Replay(stored) => return replay_response(stored),
+        Claim::InProgress => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still in progress",
+            );
+        }
+        Claim::Mismatch => {
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request body",
+            );
+        }
+    };
+
+    let (parts, body) = handler.await.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        // Dropping `pending` frees the key for a retry
+        Err(e) => {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        }
+    };
+    pending.complete(StoredResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+        body: body.to_vec(),
+    });
+    Response::from_parts(parts, full(body))
+}
+
+/// Rebuild a stored response, marked as a replay
+fn replay_response(stored: StoredResponse) -> Response<BoxBody> {
+    let mut builder = Response::builder()
+        .status(stored.status)
+        .header("Idempotent-Replayed", "true");
+    if let Some(content_type) = stored.content_type {
+        builder = builder.header("Content-Type", content_type);
+    }
+    builder.body(full(stored.body)).unwrap()
+}
+
+/// Dispatch an authenticated request to its handler
+async fn route_request(
+    req: Request<BoxBody>,
+    method: Method,
+    segments: &[&str],
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    match (method, segments) {
         // Run a command in a temporary sandbox
         (Method::POST, ["run"]) => handle_run(req, state).await,
 
@@ -262,25 +927,86 @@ async fn handle_request(
         // Batch run commands in parallel
         (Method::POST, ["batch", "run"]) => handle_batch_run(req, state).await,
 
+        // Submit an asynchronous job
+        (Method::POST, ["jobs"]) => handle_create_job(req, state).await,
+
+        // Poll job status
+        (Method::GET, ["jobs", id]) => handle_get_job(id, state),
+
+        // Stream job output (SSE, completes when the job finishes)
+        (Method::GET, ["jobs", id, "stream"]) => handle_job_stream(id, state),
+
+        // Cancel a job
+        (Method::DELETE, ["jobs", id]) => handle_cancel_job(id, state),
+
+        // Audit log entries
+        (Method::GET, ["audit"]) => handle_audit(req.uri().query()),
+
+        // Live feed of audit events (SSE)
+        (Method::GET, ["events"]) => handle_events(req.uri().query()),
+
+        // Container pool and daemon pool stats
+        (Method::GET, ["pool", "stats"]) => handle_pool_stats().await,
+
+        // Which agents are installed and have their API key set
+        (Method::GET, ["agents"]) => handle_list_agents().await,
+
         // List sandboxes
-        (Method::GET, ["sandboxes"]) => handle_list_sandboxes(state).await,
+        (Method::GET, ["sandboxes"]) => {
+            let query = req.uri().query().map(String::from);
+            handle_list_sandboxes(query.as_deref(), state).await
+        }
 
         // Create a sandbox
         (Method::POST, ["sandboxes"]) => handle_create_sandbox(req, state).await,
 
+        // Bulk create/start/stop/delete
+        (Method::POST, ["sandboxes", "bulk"]) => handle_bulk(req, state).await,
+
         // Get sandbox info
         (Method::GET, ["sandboxes", name]) => handle_get_sandbox(name, state).await,
 
         // Execute in a sandbox
         (Method::POST, ["sandboxes", name, "exec"]) => handle_exec_sandbox(req, name, state).await,
 
+        // Wait for the background command to exit
+        (Method::POST, ["sandboxes", name, "wait"]) => {
+            handle_wait_sandbox(name, req.uri().query(), state).await
+        }
+
+        // Turn network access on or off
+        (Method::POST, ["sandboxes", name, "network"]) => {
+            handle_sandbox_network(req, name, state).await
+        }
+
         // Sandbox logs
-        (Method::GET, ["sandboxes", name, "logs"]) => handle_sandbox_logs(name, state).await,
+        (Method::GET, ["sandboxes", name, "logs"]) => {
+            handle_sandbox_logs(name, req.uri().query(), state).await
+        }
+
+        // Sandbox resource usage
+        (Method::GET, ["sandboxes", name, "stats"]) => handle_sandbox_stats(name, state).await,
+
+        // Upload several files: POST /sandboxes/{name}/files (multipart/form-data)
+        (Method::POST, ["sandboxes", name, "files"]) => handle_file_upload(req, name, state).await,
 
         // File operations: GET /sandboxes/{name}/files/{path...}
         (Method::GET, ["sandboxes", name, "files", ..]) => {
             let file_path = segments[3..].join("/");
-            handle_file_read(name, &file_path, state).await
+            let header = |header: hyper::header::HeaderName| {
+                req.headers()
+                    .get(header)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from)
+            };
+            // Raw bytes for `Accept: application/octet-stream`, otherwise JSON
+            if header(hyper::header::ACCEPT).is_some_and(|accept| accepts_octet_stream(&accept)) {
+                let range = header(hyper::header::RANGE);
+                handle_file_download(name, &file_path, range.as_deref(), state).await
+            } else {
+                let if_none_match = header(hyper::header::IF_NONE_MATCH);
+                handle_file_read(name, &file_path, if_none_match.as_deref(), state).await
+            }
         }
 
         // File operations: PUT /sandboxes/{name}/files/{path...}
@@ -298,14 +1024,12 @@ async fn handle_request(
         // Delete a sandbox
         (Method::DELETE, ["sandboxes", name]) => handle_delete_sandbox(name, state).await,
 
-        // 404 for everything else
-        _ => json_response(
-            StatusCode::NOT_FOUND,
-            &ApiResponse::<()>::error("Not found"),
-        ),
-    };
+        // Stop every running sandbox: DELETE /sandboxes?all=true
+        (Method::DELETE, ["sandboxes"]) => handle_stop_all(req.uri().query(), state).await,
 
-    Ok(response)
+        // 404 for everything else
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
 }
 
 fn json_response<T: Serialize>(status: StatusCode, data: &T) -> Response<BoxBody> {
@@ -317,40 +1041,86 @@ fn json_response<T: Serialize>(status: StatusCode, data: &T) -> Response<BoxBody
         .unwrap()
 }
 
+/// Error response with the code that goes with `status`
+fn error_response(status: StatusCode, msg: impl Into<String>) -> Response<BoxBody> {
+    json_response(
+        status,
+        &ApiResponse::<()>::error(ErrorCode::for_status(status), msg),
+    )
+}
+
+/// Error response with a specific code, sent with that code's status
+fn coded_error_response(code: ErrorCode, msg: impl Into<String>) -> Response<BoxBody> {
+    json_response(code.status(), &ApiResponse::<()>::error(code, msg))
+}
+
+/// Response for a failed operation: a recognized error (sandbox not found,
+/// backend unavailable, ...) gets its own code and status, anything else `status`
+fn failure_response(status: StatusCode, e: &anyhow::Error) -> Response<BoxBody> {
+    match ErrorCode::classify(e) {
+        Some(code) => coded_error_response(code, e.to_string()),
+        None => error_response(status, e.to_string()),
+    }
+}
+
+/// Response for a failed command: `403` with the decision when the security
+/// policy denied it, otherwise `500`
+fn exec_error_response(e: &anyhow::Error) -> Response<BoxBody> {
+    match e.downcast_ref::<PolicyDenial>() {
+        Some(denial) => json_response(
+            StatusCode::FORBIDDEN,
+            &ApiResponse {
+                success: false,
+                data: Some(denial),
+                error: Some(e.to_string()),
+                error_code: Some(ErrorCode::PolicyDenied),
+                next: None,
+                note: None,
+            },
+        ),
+        None => failure_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// `error` event for a failed streaming run, with the decision when the
+/// security policy denied it
+fn error_event(e: &anyhow::Error) -> serde_json::Value {
+    let code = ErrorCode::classify(e).unwrap_or(ErrorCode::Internal);
+    let mut event = serde_json::json!({ "message": e.to_string(), "error_code": code });
+    if let Some(denial) = e.downcast_ref::<PolicyDenial>() {
+        event["policy"] = serde_json::json!(denial);
+    }
+    event
+}
 async fn read_json_body<T: for<'de> Deserialize<'de>>(
-    req: Request<Incoming>,
+    req: Request<BoxBody>,
 ) -> Result<T, Response<BoxBody>> {
     let body_bytes = req
         .collect()
         .await
-        .map_err(|_| {
-            json_response(
-                StatusCode::BAD_REQUEST,
-                &ApiResponse::<()>::error("Failed to read body"),
-            )
-        })?
+        .map_err(|_| error_response(StatusCode::BAD_REQUEST, "Failed to read body"))?
         .to_bytes();
 
-    serde_json::from_slice(&body_bytes).map_err(|e| {
-        json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(format!("Invalid JSON: {}", e)),
-        )
-    })
+    serde_json::from_slice(&body_bytes)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)))
 }
 
-async fn handle_run(req: Request<Incoming>, state: Arc<AppState>) -> Response<BoxBody> {
+async fn handle_run(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
     let body: RunRequest = match read_json_body(req).await {
         Ok(b) => b,
         Err(resp) => return resp,
     };
 
     if body.command.is_empty() {
-        return json_response(
+        return error_response(StatusCode::BAD_REQUEST, "command is required");
+    }
+    if body.max_output == Some(0) {
+        return error_response(
             StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error("command is required"),
+            "max_output must be at least 1 byte",
         );
     }
+    let max_output = state.max_output(body.max_output);
 
     // Fast path: use container pool (default for HTTP API)
     if body.fast {
@@ -359,19 +1129,20 @@ async fn handle_run(req: Request<Incoming>, state: Arc<AppState>) -> Response<Bo
             eprintln!("Warning: custom image ignored in fast mode (pool uses alpine:3.20)");
         }
 
-        match VmManager::run_pooled(&body.command).await {
-            Ok(output) => {
+        match VmManager::run_pooled(&body.command, Some(max_output)).await {
+            Ok((output, truncated)) => {
                 return json_response(
                     StatusCode::OK,
-                    &ApiResponse::success(RunResponse { output }),
-                );
-            }
-            Err(e) => {
-                return json_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    &ApiResponse::<()>::error(e.to_string()),
+                    &ApiResponse::success(RunResponse {
+                        output,
+                        rusage: None,
+                        exit_code: None,
+                        truncated,
+                        encoding: None,
+                    }),
                 );
             }
+            Err(e) => return exec_error_response(&e),
         }
     }
 
@@ -381,10 +1152,7 @@ async fn handle_run(req: Request<Incoming>, state: Arc<AppState>) -> Response<Bo
     if let Some(ref img) = body.image
         && let Err(e) = validation::validate_docker_image(img)
     {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return coded_error_response(ErrorCode::InvalidImage, e.to_string());
     }
 
     let image = body
@@ -395,51 +1163,69 @@ async fn handle_run(req: Request<Incoming>, state: Arc<AppState>) -> Response<Bo
         .unwrap_or_default()
         .permissions();
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.run_manager() {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
     let sandbox_name = format!("api-run-{}", &uuid::Uuid::new_v4().to_string()[..8]);
 
     // Create
-    if let Err(e) = manager.create(&sandbox_name, &image, 1, 512).await {
-        return json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+    if let Err(e) = manager
+        .create_ephemeral(&sandbox_name, &image, 1, 512)
+        .await
+    {
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
     }
 
     // Start
     if let Err(e) = manager.start_with_permissions(&sandbox_name, &perms).await {
         let _ = manager.remove(&sandbox_name).await;
-        return json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
     }
 
     // Execute
-    let result = manager.exec_cmd(&sandbox_name, &body.command).await;
+    let result = run_checked(&mut manager, &sandbox_name, &body.command, max_output).await;
 
     // Cleanup
     let _ = manager.remove(&sandbox_name).await;
 
     match result {
-        Ok(output) => json_response(
+        Ok((output, truncated)) => json_response(
             StatusCode::OK,
-            &ApiResponse::success(RunResponse { output }),
-        ),
-        Err(e) => json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
+            &ApiResponse::success(RunResponse {
+                output,
+                rusage: None,
+                exit_code: None,
+                truncated,
+                encoding: None,
+            }),
         ),
+        Err(e) => exec_error_response(&e),
+    }
+}
+
+/// Run a command in a `/run` sandbox; a non-zero exit is an error.
+///
+/// Returns the output and whether it was cut short at `max_output` bytes.
+async fn run_checked(
+    manager: &mut VmManager,
+    name: &str,
+    command: &[String],
+    max_output: usize,
+) -> Result<(String, bool)> {
+    let opts = ExecOptions::default().max_output(Some(max_output));
+    let result = manager.exec_cmd_full(name, command, &opts).await?;
+    if result.exit_code != 0 {
+        anyhow::bail!(
+            "Command exited with code {}: {}",
+            result.exit_code,
+            result.output()
+        );
     }
+    Ok((result.output(), result.truncated))
 }
 
 /// Server-Sent Events response for streaming command output
@@ -463,7 +1249,7 @@ fn sse_response(events: Vec<(&str, serde_json::Value)>) -> Response<BoxBody> {
 }
 
 /// Handle /run/stream - runs command with SSE streaming output
-async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Response<BoxBody> {
+async fn handle_run_stream(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
     let body: RunRequest = match read_json_body(req).await {
         Ok(b) => b,
         Err(resp) => return resp,
@@ -475,6 +1261,13 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
             serde_json::json!({"message": "command is required"}),
         )]);
     }
+    if body.max_output == Some(0) {
+        return sse_response(vec![(
+            "error",
+            serde_json::json!({"message": "max_output must be at least 1 byte"}),
+        )]);
+    }
+    let max_output = state.max_output(body.max_output);
 
     let mut events = vec![];
 
@@ -490,8 +1283,8 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
 
     // Fast path: use container pool (default for HTTP API)
     if body.fast {
-        match VmManager::run_pooled(&body.command).await {
-            Ok(output) => {
+        match VmManager::run_pooled(&body.command, Some(max_output)).await {
+            Ok((output, truncated)) => {
                 events.push((
                     "output",
                     serde_json::json!({
@@ -503,30 +1296,38 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
                     "done",
                     serde_json::json!({
                         "exit_code": 0,
-                        "success": true
+                        "success": true,
+                        "truncated": truncated
                     }),
                 ));
             }
             Err(e) => {
-                events.push((
-                    "error",
-                    serde_json::json!({
-                        "message": e.to_string()
-                    }),
-                ));
+                events.push(("error", error_event(&e)));
             }
         }
         return sse_response(events);
     }
 
     // Slow path: full sandbox lifecycle
-    let profile = body.profile.as_deref().unwrap_or("moderate");
-    let perms = SecurityProfile::from_str(profile)
-        .unwrap_or_default()
-        .permissions();
 
-    let mut manager = match state.get_manager().await {
-        Ok(m) => m,
+    // Validate Docker image name if provided (security: prevents injection)
+    if let Some(ref img) = body.image
+        && let Err(e) = validation::validate_docker_image(img)
+    {
+        events.push((
+            "error",
+            serde_json::json!({"message": e.to_string(), "error_code": ErrorCode::InvalidImage}),
+        ));
+        return sse_response(events);
+    }
+
+    let profile = body.profile.as_deref().unwrap_or("moderate");
+    let perms = SecurityProfile::from_str(profile)
+        .unwrap_or_default()
+        .permissions();
+
+    let mut manager = match state.run_manager() {
+        Ok(m) => m,
         Err(e) => {
             events.push(("error", serde_json::json!({"message": e.to_string()})));
             return sse_response(events);
@@ -541,7 +1342,10 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
     let sandbox_name = format!("api-stream-{}", &uuid::Uuid::new_v4().to_string()[..8]);
 
     // Create
-    if let Err(e) = manager.create(&sandbox_name, &image, 1, 512).await {
+    if let Err(e) = manager
+        .create_ephemeral(&sandbox_name, &image, 1, 512)
+        .await
+    {
         events.push(("error", serde_json::json!({"message": e.to_string()})));
         return sse_response(events);
     }
@@ -569,13 +1373,13 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
     ));
 
     // Execute
-    let result = manager.exec_cmd(&sandbox_name, &body.command).await;
+    let result = run_checked(&mut manager, &sandbox_name, &body.command, max_output).await;
 
     // Cleanup
     let _ = manager.remove(&sandbox_name).await;
 
     match result {
-        Ok(output) => {
+        Ok((output, truncated)) => {
             events.push((
                 "output",
                 serde_json::json!({
@@ -587,36 +1391,281 @@ async fn handle_run_stream(req: Request<Incoming>, state: Arc<AppState>) -> Resp
                 "done",
                 serde_json::json!({
                     "exit_code": 0,
-                    "success": true
+                    "success": true,
+                    "truncated": truncated
                 }),
             ));
         }
         Err(e) => {
-            events.push((
-                "error",
-                serde_json::json!({
-                    "message": e.to_string()
-                }),
-            ));
+            events.push(("error", error_event(&e)));
         }
     }
 
     sse_response(events)
 }
 
-async fn handle_list_sandboxes(state: Arc<AppState>) -> Response<BoxBody> {
+/// How often an idle event stream sends a comment, so dead clients are noticed
+const EVENTS_KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Streaming response body fed by a channel of chunks (SSE events, file downloads)
+struct ChannelBody {
+    rx: tokio::sync::mpsc::Receiver<bytes::Bytes>,
+}
+
+impl hyper::body::Body for ChannelBody {
+    type Data = bytes::Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        self.rx
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|data| Ok(hyper::body::Frame::data(data))))
+    }
+}
+
+/// Filters for `GET /events`
+#[derive(Debug, Default, PartialEq)]
+struct EventFilter {
+    sandbox: Option<String>,
+    event_type: Option<String>,
+}
+
+impl EventFilter {
+    fn from_query(query: Option<&str>) -> Self {
+        let mut filter = Self::default();
+        for (key, value) in query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            match key {
+                "sandbox" => filter.sandbox = Some(percent_decode(value)),
+                "event_type" => filter.event_type = Some(percent_decode(value)),
+                _ => {}
+            }
+        }
+        filter
+    }
+
+    fn matches(&self, event: &AuditEvent) -> bool {
+        self.sandbox.as_deref().is_none_or(|s| s == event.sandbox())
+            && self
+                .event_type
+                .as_deref()
+                .is_none_or(|t| t == event.event_type())
+    }
+}
+
+fn sse_chunk(event_type: &str, data: &impl Serialize) -> bytes::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string());
+    format!("event: {}\ndata: {}\n\n", event_type, data).into()
+}
+
+/// Stream audit events as they're logged, until the client disconnects.
+///
+/// Each event is sent with its type as the SSE event name. A client that
+/// can't keep up skips events and gets a `lagged` event with the count.
+fn handle_events(query: Option<&str>) -> Response<BoxBody> {
+    let filter = EventFilter::from_query(query);
+    // Subscribe before responding so nothing logged after this call is missed
+    let mut events = audit::subscribe();
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+    tokio::spawn(async move {
+        let mut keepalive = tokio::time::interval(EVENTS_KEEPALIVE);
+        keepalive.tick().await;
+        loop {
+            let chunk = tokio::select! {
+                received = events.recv() => match received {
+                    Ok(entry) if filter.matches(&entry.event) => {
+                        sse_chunk(entry.event.event_type(), &entry)
+                    }
+                    Ok(_) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        sse_chunk("lagged", &serde_json::json!({ "skipped": skipped }))
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => bytes::Bytes::from_static(b": keepalive\n\n"),
+            };
+            if tx.send(chunk).await.is_err() {
+                break; // Client went away
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .body(ChannelBody { rx }.boxed())
+        .unwrap()
+}
+
+/// Decode a percent-encoded query component (`+` is a space)
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Page size when a list request doesn't give `limit`
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Largest `limit` a list request may ask for
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// `limit`/`offset` pagination for list endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Page {
+    limit: usize,
+    offset: usize,
+}
+
+impl Page {
+    /// Parse `limit` and `offset` from a query string (first page by default)
+    fn from_query(query: Option<&str>) -> Result<Self> {
+        let mut page = Page {
+            limit: DEFAULT_PAGE_SIZE,
+            offset: 0,
+        };
+        for (key, value) in query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            match key {
+                "limit" => {
+                    page.limit = value
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=MAX_PAGE_SIZE).contains(n))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("limit must be between 1 and {}", MAX_PAGE_SIZE)
+                        })?;
+                }
+                "offset" => {
+                    page.offset = value
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("offset must be a non-negative number"))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+
+    /// Cut this page out of `items`, returning it with the total count and,
+    /// if there are more items, a link to the next page
+    fn apply<T>(
+        self,
+        items: Vec<T>,
+        path: &str,
+        query: Option<&str>,
+    ) -> (Vec<T>, usize, Option<String>) {
+        let total = items.len();
+        let end = self.offset.saturating_add(self.limit);
+        let next = (end < total).then(|| {
+            // Keep the caller's other parameters (e.g. label filters)
+            let mut params: Vec<&str> = query
+                .unwrap_or_default()
+                .split('&')
+                .filter(|pair| {
+                    !pair.is_empty() && !pair.starts_with("limit=") && !pair.starts_with("offset=")
+                })
+                .collect();
+            let paging = format!("limit={}&offset={}", self.limit, end);
+            params.push(&paging);
+            format!("{}?{}", path, params.join("&"))
+        });
+        let items = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .collect();
+        (items, total, next)
+    }
+}
+
+/// Respond with one page of a list, with `X-Total-Count` and a `next` link
+fn paginated_response<T: Serialize>(
+    items: Vec<T>,
+    query: Option<&str>,
+    path: &str,
+) -> Response<BoxBody> {
+    let page = match Page::from_query(query) {
+        Ok(page) => page,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+    let (items, total, next) = page.apply(items, path, query);
+    let mut response = json_response(
+        StatusCode::OK,
+        &ApiResponse {
+            success: true,
+            data: Some(items),
+            error: None,
+            next,
+            note: None,
+        },
+    );
+    response
+        .headers_mut()
+        .insert("X-Total-Count", hyper::header::HeaderValue::from(total));
+    response
+}
+
+/// Label filters from `label=key` / `label=key=value` query parameters
+fn label_filters(query: Option<&str>) -> Result<Vec<LabelFilter>> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, _)| *key == "label")
+        .map(|(_, selector)| LabelFilter::parse(&percent_decode(selector)))
+        .collect()
+}
+
+async fn handle_list_sandboxes(query: Option<&str>, state: Arc<AppState>) -> Response<BoxBody> {
+    let filters = match label_filters(query) {
+        Ok(filters) => filters,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+
     let manager = match state.get_manager().await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
-    let sandboxes: Vec<SandboxInfo> = manager
-        .list()
+    // Sorted so pages are stable between requests
+    let mut listed = manager.list_filtered(&filters);
+    listed.sort_by(|a, b| a.0.cmp(b.0));
+    let sandboxes: Vec<SandboxInfo> = listed
         .into_iter()
         .map(|(name, running, backend)| SandboxInfo {
             name: name.to_string(),
@@ -628,13 +1677,19 @@ async fn handle_list_sandboxes(state: Arc<AppState>) -> Response<BoxBody> {
             vcpus: None,
             memory_mb: None,
             created_at: None,
+            labels: manager
+                .get_state(name)
+                .map(|s| s.labels.clone())
+                .unwrap_or_default(),
+            image_digest: None,
+            ip_address: None,
         })
         .collect();
 
-    json_response(StatusCode::OK, &ApiResponse::success(sandboxes))
+    paginated_response(sandboxes, query, "/sandboxes")
 }
 
-async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) -> Response<BoxBody> {
+async fn handle_create_sandbox(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
     let body: CreateRequest = match read_json_body(req).await {
         Ok(b) => b,
         Err(resp) => return resp,
@@ -642,41 +1697,56 @@ async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) ->
 
     // Validate sandbox name (security: prevents command injection)
     if let Err(e) = validation::validate_sandbox_name(&body.name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    for (key, value) in &body.labels {
+        if let Err(e) = crate::vmm::validate_label(key, value) {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
     }
 
     let image = body.image.as_deref().unwrap_or("alpine:3.20");
     let vcpus = body.vcpus.unwrap_or(1);
     let memory_mb = body.memory_mb.unwrap_or(512);
+    if let Err(e) = validation::validate_resources(vcpus, memory_mb) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
 
     // Validate Docker image name if provided
     if let Some(ref img) = body.image
         && let Err(e) = validation::validate_docker_image(img)
     {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return coded_error_response(ErrorCode::InvalidImage, e.to_string());
     }
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.lease(&body.name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
     if let Err(e) = manager.create(&body.name, image, vcpus, memory_mb).await {
-        return json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+    }
+    if !body.labels.is_empty()
+        && let Err(e) = manager.set_labels(&body.name, body.labels.clone())
+    {
+        let _ = manager.remove(&body.name).await;
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+    }
+    if body.restart != RestartPolicy::Never
+        && let Err(e) = manager.set_restart_policy(&body.name, body.restart)
+    {
+        let _ = manager.remove(&body.name).await;
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+    }
+    if let Some(ref script) = body.setup_script
+        && let Err(e) = manager.set_setup_script(&body.name, script)
+    {
+        let _ = manager.remove(&body.name).await;
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
     }
 
     // Resolve profile for start_with_permissions
@@ -685,12 +1755,12 @@ async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) ->
             Some(profile) => profile.permissions(),
             None => {
                 let _ = manager.remove(&body.name).await;
-                return json_response(
+                return error_response(
                     StatusCode::BAD_REQUEST,
-                    &ApiResponse::<()>::error(format!(
+                    format!(
                         "Invalid profile '{}'. Use: permissive, moderate, restrictive",
                         profile_str
-                    )),
+                    ),
                 );
             }
         }
@@ -700,10 +1770,7 @@ async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) ->
 
     if let Err(e) = manager.start_with_permissions(&body.name, &perms).await {
         let _ = manager.remove(&body.name).await;
-        return json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
     }
 
     json_response(
@@ -716,6 +1783,9 @@ async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) ->
             vcpus: Some(vcpus),
             memory_mb: Some(memory_mb),
             created_at: None,
+            labels: body.labels,
+            image_digest: None,
+            ip_address: None,
         }),
     )
 }
@@ -723,19 +1793,13 @@ async fn handle_create_sandbox(req: Request<Incoming>, state: Arc<AppState>) ->
 async fn handle_get_sandbox(name: &str, state: Arc<AppState>) -> Response<BoxBody> {
     // Validate sandbox name (security: prevents command injection)
     if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
     let manager = match state.get_manager().await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
@@ -743,6 +1807,11 @@ async fn handle_get_sandbox(name: &str, state: Arc<AppState>) -> Response<BoxBod
     for (sandbox_name, running, backend) in &sandboxes {
         if *sandbox_name == name {
             let state_info = manager.get_state(name);
+            let ip_address = if *running {
+                manager.ip_address(name).await.ok().flatten()
+            } else {
+                None
+            };
             return json_response(
                 StatusCode::OK,
                 &ApiResponse::success(SandboxInfo {
@@ -755,28 +1824,25 @@ async fn handle_get_sandbox(name: &str, state: Arc<AppState>) -> Response<BoxBod
                     vcpus: state_info.map(|s| s.vcpus),
                     memory_mb: state_info.map(|s| s.memory_mb),
                     created_at: state_info.map(|s| s.created_at.clone()),
+                    labels: state_info.map(|s| s.labels.clone()).unwrap_or_default(),
+                    image_digest: state_info.and_then(|s| s.image_digest.clone()),
+                    ip_address,
                 }),
             );
         }
     }
 
-    json_response(
-        StatusCode::NOT_FOUND,
-        &ApiResponse::<()>::error("Sandbox not found"),
-    )
+    coded_error_response(ErrorCode::SandboxNotFound, "Sandbox not found")
 }
 
 async fn handle_exec_sandbox(
-    req: Request<Incoming>,
+    req: Request<BoxBody>,
     name: &str,
     state: Arc<AppState>,
 ) -> Response<BoxBody> {
     // Validate sandbox name (security: prevents command injection)
     if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
     let body: ExecRequest = match read_json_body(req).await {
@@ -784,161 +1850,631 @@ async fn handle_exec_sandbox(
         Err(resp) => return resp,
     };
 
+    exec_sandbox(name, body, &state).await
+}
+
+/// Run a command in a sandbox for `POST /sandboxes/{name}/exec`
+async fn exec_sandbox(name: &str, body: ExecRequest, state: &AppState) -> Response<BoxBody> {
     if body.command.is_empty() {
-        return json_response(
+        return error_response(StatusCode::BAD_REQUEST, "command is required");
+    }
+    if body.timeout == Some(0) {
+        return error_response(StatusCode::BAD_REQUEST, "timeout must be at least 1 second");
+    }
+    if body.max_output == Some(0) {
+        return error_response(
             StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error("command is required"),
+            "max_output must be at least 1 byte",
         );
     }
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.lease(name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
-    match manager.exec_cmd(name, &body.command).await {
-        Ok(output) => json_response(
-            StatusCode::OK,
-            &ApiResponse::success(RunResponse { output }),
-        ),
-        Err(e) => json_response(
+    let opts = ExecOptions::default()
+        .timeout(body.timeout.map(std::time::Duration::from_secs))
+        .max_output(Some(state.max_output(body.max_output)));
+    match manager.exec_bytes(name, &body.command, &opts).await {
+        Ok(result) if body.check && result.exit_code != 0 => error_response(
             StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
+            format!(
+                "Command exited with code {}: {}",
+                result.exit_code,
+                String::from_utf8_lossy(&result.output())
+            ),
+        ),
+        Ok(result) => {
+            let (output, encoding) = encode_output(result.output());
+            json_response(
+                StatusCode::OK,
+                &ApiResponse::success(RunResponse {
+                    output,
+                    rusage: result.rusage,
+                    exit_code: Some(result.exit_code),
+                    truncated: result.truncated,
+                    encoding,
+                }),
+            )
+        }
+        Err(e) => exec_error_response(&e),
+    }
+}
+
+/// Output as text, or base64 with its encoding when it isn't valid UTF-8
+fn encode_output(output: Vec<u8>) -> (String, Option<&'static str>) {
+    match String::from_utf8(output) {
+        Ok(text) => (text, None),
+        Err(e) => (
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, e.as_bytes()),
+            Some("base64"),
         ),
     }
 }
 
+/// `timeout` (seconds) from a query string
+fn wait_timeout_from_query(query: Option<&str>) -> Result<Option<std::time::Duration>> {
+    for (key, value) in query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+    {
+        if key == "timeout" {
+            let secs: u64 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("timeout must be a number of seconds"))?;
+            return Ok(Some(std::time::Duration::from_secs(secs)));
+        }
+    }
+    Ok(None)
+}
+
+/// Block until the sandbox's background command exits.
+///
+/// The manager lock is only held for each status check, so other requests
+/// keep being served while this one waits.
+async fn handle_wait_sandbox(
+    name: &str,
+    query: Option<&str>,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    // Validate sandbox name (security: prevents command injection)
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+    let timeout = match wait_timeout_from_query(query) {
+        Ok(t) => t,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+
+    let start = std::time::Instant::now();
+    loop {
+        let status = {
+            let mut manager = match state.lease(name).await {
+                Ok(m) => m,
+                Err(e) => {
+                    return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+                }
+            };
+            if !manager.exists(name) {
+                return coded_error_response(ErrorCode::SandboxNotFound, "Sandbox not found");
+            }
+            manager.detached_status(name).await
+        };
+
+        match status {
+            Ok(Some(exit_code)) => {
+                return json_response(
+                    StatusCode::OK,
+                    &ApiResponse::success(WaitResponse { exit_code }),
+                );
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return failure_response(StatusCode::CONFLICT, &e);
+            }
+        }
+
+        if let Some(timeout) = timeout
+            && start.elapsed() >= timeout
+        {
+            return error_response(
+                StatusCode::REQUEST_TIMEOUT,
+                format!(
+                    "Timed out after {}s waiting for the background command",
+                    timeout.as_secs()
+                ),
+            );
+        }
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+    }
+}
+
 async fn handle_delete_sandbox(name: &str, state: Arc<AppState>) -> Response<BoxBody> {
     // Validate sandbox name (security: prevents command injection)
     if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.lease(name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
     match manager.remove(name).await {
         Ok(_) => json_response(StatusCode::OK, &ApiResponse::success("Sandbox removed")),
-        Err(e) => json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        ),
+        Err(e) => failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
     }
 }
 
-/// Resolve a profile name to a SecurityProfile
-fn resolve_profile(name: &str) -> Option<SecurityProfile> {
-    match name.to_lowercase().as_str() {
-        "permissive" => Some(SecurityProfile::Permissive),
-        "moderate" => Some(SecurityProfile::Moderate),
-        "restrictive" => Some(SecurityProfile::Restrictive),
-        _ => None,
-    }
+/// Whether the query confirms stopping everything (`all=true`)
+fn stop_all_confirmed(query: Option<&str>) -> bool {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .any(|(key, value)| key == "all" && value == "true")
 }
 
-// --- File operation handlers ---
-
-async fn handle_file_read(name: &str, file_path: &str, state: Arc<AppState>) -> Response<BoxBody> {
-    if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
-    }
-
-    let abs_path = format!("/{}", file_path);
-    if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
-        return json_response(
+async fn handle_stop_all(query: Option<&str>, state: Arc<AppState>) -> Response<BoxBody> {
+    // Guard against stopping everything with a stray DELETE
+    if !stop_all_confirmed(query) {
+        return error_response(
             StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
+            "Pass ?all=true to stop every running sandbox",
         );
     }
 
     let mut manager = match state.get_manager().await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
-    match manager.read_file(name, &abs_path).await {
-        Ok(content) => {
-            let size = content.len();
-            let (content_str, encoding) = match String::from_utf8(content.clone()) {
-                Ok(s) => (s, "utf8"),
-                Err(_) => (
-                    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &content),
-                    "base64",
-                ),
-            };
-            json_response(
-                StatusCode::OK,
-                &ApiResponse::success(FileReadResponse {
-                    content: content_str,
-                    encoding: encoding.to_string(),
-                    size,
-                }),
-            )
+    let mut response = StopAllResponse {
+        stopped: Vec::new(),
+        failed: Vec::new(),
+        daemon_stopped: false,
+    };
+    for (name, result) in manager.stop_all().await {
+        match result {
+            Ok(_) => response.stopped.push(name),
+            Err(e) => response.failed.push(StopFailure {
+                name,
+                error: e.to_string(),
+            }),
         }
-        Err(e) => json_response(
-            StatusCode::NOT_FOUND,
-            &ApiResponse::<()>::error(e.to_string()),
-        ),
     }
+    drop(manager);
+
+    match VmManager::stop_daemon_pool().await {
+        Ok(stopped) => response.daemon_stopped = stopped,
+        Err(e) => response.failed.push(StopFailure {
+            name: "daemon".to_string(),
+            error: e.to_string(),
+        }),
+    }
+
+    if !response.failed.is_empty() {
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &ApiResponse {
+                success: false,
+                error: Some(format!(
+                    "Failed to stop: {}",
+                    response
+                        .failed
+                        .iter()
+                        .map(|f| f.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+                error_code: Some(ErrorCode::Internal),
+                data: Some(response),
+                next: None,
+                note: None,
+            },
+        );
+    }
+    json_response(StatusCode::OK, &ApiResponse::success(response))
 }
 
-async fn handle_file_write(
-    req: Request<Incoming>,
+async fn handle_sandbox_network(
+    req: Request<BoxBody>,
     name: &str,
-    file_path: &str,
     state: Arc<AppState>,
 ) -> Response<BoxBody> {
+    // Validate sandbox name (security: prevents command injection)
     if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
-    let abs_path = format!("/{}", file_path);
-    if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
-    }
-
-    let body: FileWriteRequest = match read_json_body(req).await {
+    let body: NetworkRequest = match read_json_body(req).await {
         Ok(b) => b,
         Err(resp) => return resp,
     };
 
+    let mut manager = match state.lease(name).await {
+        Ok(m) => m,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+
+    match manager.set_network(name, body.enabled).await {
+        Ok(()) => json_response(
+            StatusCode::OK,
+            &ApiResponse::success(if body.enabled {
+                "Network enabled"
+            } else {
+                "Network disabled"
+            }),
+        ),
+        Err(e) => failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+async fn handle_sandbox_stats(name: &str, state: Arc<AppState>) -> Response<BoxBody> {
+    // Validate sandbox name (security: prevents command injection)
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let manager = match state.lease(name).await {
+        Ok(m) => m,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+
+    if !manager.exists(name) {
+        return coded_error_response(ErrorCode::SandboxNotFound, "Sandbox not found");
+    }
+
+    match manager.disk_usage(name).await {
+        Ok(disk_bytes) => json_response(
+            StatusCode::OK,
+            &ApiResponse::success(SandboxStats {
+                name: name.to_string(),
+                disk_bytes,
+            }),
+        ),
+        Err(e) => failure_response(StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+/// Resolve a profile name to a SecurityProfile
+fn resolve_profile(name: &str) -> Option<SecurityProfile> {
+    match name.to_lowercase().as_str() {
+        "permissive" => Some(SecurityProfile::Permissive),
+        "moderate" => Some(SecurityProfile::Moderate),
+        "restrictive" => Some(SecurityProfile::Restrictive),
+        _ => None,
+    }
+}
+
+// --- File operation handlers ---
+
+async fn handle_file_read(
+    name: &str,
+    file_path: &str,
+    if_none_match: Option<&str>,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let abs_path = format!("/{}", file_path);
+    if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let mut manager = match state.lease(name).await {
+        Ok(m) => m,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+
+    match manager.read_file(name, &abs_path).await {
+        Ok(content) => file_read_response(content, if_none_match),
+        Err(e) => failure_response(StatusCode::NOT_FOUND, &e),
+    }
+}
+
+/// Strong ETag for file content (size and CRC32)
+fn content_etag(content: &[u8]) -> String {
+    format!(
+        "\"{:x}-{:08x}\"",
+        content.len(),
+        crate::vsock::crc32(content)
+    )
+}
+
+/// Whether an `If-None-Match` header matches the current ETag
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        // Weak comparison, as RFC 9110 requires for If-None-Match
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+/// Build the response for a file read, or `304 Not Modified` if the client's copy is current
+fn file_read_response(content: Vec<u8>, if_none_match: Option<&str>) -> Response<BoxBody> {
+    let etag = content_etag(&content);
+    if if_none_match.is_some_and(|tags| etag_matches(tags, &etag)) {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("ETag", etag)
+            .body(full(String::new()))
+            .unwrap();
+    }
+
+    let size = content.len();
+    let (content_str, encoding) = match String::from_utf8(content) {
+        Ok(s) => (s, "utf8"),
+        Err(e) => (
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, e.as_bytes()),
+            "base64",
+        ),
+    };
+    let mut response = json_response(
+        StatusCode::OK,
+        &ApiResponse::success(FileReadResponse {
+            content: content_str,
+            encoding: encoding.to_string(),
+            size,
+        }),
+    );
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(hyper::header::ETAG, value);
+    }
+    response
+}
+
+/// Bytes read from the sandbox per chunk of a download
+const DOWNLOAD_CHUNK_BYTES: u64 = 1024 * 1024;
+
+/// Whether an `Accept` header asks for raw file bytes
+fn accepts_octet_stream(accept: &str) -> bool {
+    accept.split(',').any(|media| {
+        media
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case("application/octet-stream")
+    })
+}
+
+/// A `Range` header resolved against the size of the file
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No range, or one we don't serve: send the whole file
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    /// The range starts past the end of the file
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=first-last`, `bytes=first-` or `bytes=-suffix` range.
+///
+/// Malformed headers and multiple ranges are ignored, as RFC 9110 allows,
+/// and get the whole file.
+fn parse_byte_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        // The last `suffix` bytes
+        return match last.parse::<u64>() {
+            Ok(suffix) if suffix > 0 && size > 0 => {
+                ByteRange::Partial(size.saturating_sub(suffix), size - 1)
+            }
+            Ok(_) => ByteRange::Unsatisfiable,
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let Ok(first) = first.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let last = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(last) if last >= first => last,
+            _ => return ByteRange::Full,
+        }
+    };
+    if first >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(first, last.min(size - 1))
+}
+
+/// Stream a file's raw bytes from the sandbox, a chunk at a time.
+///
+/// Supports a single `Range` for partial and resumed downloads.
+async fn handle_file_download(
+    name: &str,
+    file_path: &str,
+    range: Option<&str>,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let abs_path = format!("/{}", file_path);
+    if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let mut manager = match state.lease(name).await {
+        Ok(m) => m,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+    let size = match manager.begin_download(name, &abs_path).await {
+        Ok(size) => size,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+
+    let (status, first, len) = match parse_byte_range(range, size) {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(first, last) => (StatusCode::PARTIAL_CONTENT, first, last - first + 1),
+        ByteRange::Unsatisfiable => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{}", size))
+                .body(full(String::new()))
+                .unwrap();
+        }
+    };
+
+    let stream = match manager.stream_file_range(name, &abs_path, first, len).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+    drop(manager);
+
+    let (tx, rx) = tokio::sync::mpsc::channel(2);
+    let name = name.to_string();
+    let end = first + len;
+    match stream {
+        Some(child) => {
+            tokio::spawn(send_streamed_range(child, len, tx));
+        }
+        None => {
+            tokio::spawn(async move {
+                let mut offset = first;
+                while offset < end {
+                    // Leased per chunk, so a long download doesn't hold up other requests
+                    let chunk_len = DOWNLOAD_CHUNK_BYTES.min(end - offset);
+                    let chunk = match state.lease(&name).await {
+                        Ok(mut manager) => {
+                            manager
+                                .read_file_range(&name, &abs_path, offset, chunk_len)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    // A file that can't be read any more (or shrank) ends the body early
+                    let mut chunk = match chunk {
+                        Ok(chunk) if !chunk.is_empty() => chunk,
+                        _ => break,
+                    };
+                    // Never send more than Content-Length promised
+                    chunk.truncate(chunk_len as usize);
+                    offset += chunk.len() as u64;
+                    if tx.send(bytes::Bytes::from(chunk)).await.is_err() {
+                        break; // Client went away
+                    }
+                }
+            });
+        }
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/octet-stream")
+        .header("Content-Length", len)
+        .header("Accept-Ranges", "bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", first, first + len - 1, size),
+        );
+    }
+    response.body(ChannelBody { rx }.boxed()).unwrap()
+}
+
+/// Forward at most `len` bytes of a streamed read to a download body
+async fn send_streamed_range(
+    mut child: tokio::process::Child,
+    len: u64,
+    tx: tokio::sync::mpsc::Sender<bytes::Bytes>,
+) {
+    use tokio::io::AsyncReadExt;
+
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+    let mut stdout = stdout.take(len);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match stdout.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if tx
+                    .send(bytes::Bytes::copy_from_slice(&buf[..n]))
+                    .await
+                    .is_err()
+                {
+                    break; // Client went away
+                }
+            }
+        }
+    }
+    // Stop the read if the client left before the end
+    let _ = child.start_kill();
+    let _ = child.wait().await;
+}
+
+async fn handle_file_write(
+    req: Request<BoxBody>,
+    name: &str,
+    file_path: &str,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let abs_path = format!("/{}", file_path);
+    if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let body: FileWriteRequest = match read_json_body(req).await {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+    let opts = match body.write_options() {
+        Ok(opts) => opts,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+
     let bytes = if body.encoding == "base64" {
         match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &body.content) {
             Ok(b) => b,
             Err(e) => {
-                return json_response(
-                    StatusCode::BAD_REQUEST,
-                    &ApiResponse::<()>::error(format!("Invalid base64: {}", e)),
-                );
+                return error_response(StatusCode::BAD_REQUEST, format!("Invalid base64: {}", e));
             }
         }
     } else {
@@ -947,55 +2483,242 @@ async fn handle_file_write(
 
     let size = bytes.len();
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.lease(name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
-    match manager.write_file(name, &abs_path, &bytes).await {
+    match manager
+        .write_file_with_options(name, &abs_path, &bytes, &opts)
+        .await
+    {
         Ok(()) => json_response(
             StatusCode::OK,
-            &ApiResponse::success(format!("Wrote {} bytes to {}", size, abs_path)),
-        ),
-        Err(e) => json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
+            &ApiResponse::success(format!(
+                "{} {} bytes to {}",
+                if opts.append { "Appended" } else { "Wrote" },
+                size,
+                abs_path
+            )),
         ),
+        Err(e) => failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
     }
 }
 
-async fn handle_file_delete(
+/// Largest upload body accepted unless `[sandbox] max_upload_bytes` is set
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 100 * 1024 * 1024;
+
+/// `POST /sandboxes/{name}/files`: read a multipart body of at most
+/// `max_upload` bytes (413 beyond that) and write its files
+async fn handle_file_upload(
+    req: Request<BoxBody>,
     name: &str,
-    file_path: &str,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    let content_type = req
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = match Limited::new(req.into_body(), state.max_upload)
+        .collect()
+        .await
+    {
+        Ok(b) => b.to_bytes(),
+        Err(e) if e.downcast_ref::<LengthLimitError>().is_some() => {
+            return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload is larger than {} bytes", state.max_upload),
+            );
+        }
+        Err(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "Failed to read body");
+        }
+    };
+    upload_files(name, &content_type, &body, state).await
+}
+
+/// Write the files of a multipart body. Nothing is written unless every
+/// destination path is valid.
+async fn upload_files(
+    name: &str,
+    content_type: &str,
+    body: &[u8],
     state: Arc<AppState>,
 ) -> Response<BoxBody> {
     if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let files = match parse_multipart(content_type, body) {
+        Ok(files) if files.is_empty() => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "No files in upload (parts need a filename)",
+            );
+        }
+        Ok(files) => files,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+
+    let mut results: Vec<FileUploadResult> = files
+        .iter()
+        .map(|f| FileUploadResult {
+            path: f.path.clone(),
+            size: f.content.len(),
+            error: crate::backend::validate_sandbox_path(&f.path)
+                .err()
+                .map(|e| e.to_string()),
+        })
+        .collect();
+    if results.iter().any(|r| r.error.is_some()) {
         return json_response(
             StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
+            &ApiResponse {
+                success: false,
+                data: Some(results),
+                error: Some("Invalid file path in upload; nothing was written".to_string()),
+                error_code: Some(ErrorCode::InvalidRequest),
+                next: None,
+                note: None,
+            },
         );
     }
 
+    let mut manager = match state.lease(name).await {
+        Ok(m) => m,
+        Err(e) => {
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+        }
+    };
+
+    let injections: Vec<FileInjection> = files
+        .into_iter()
+        .map(|f| FileInjection {
+            content: f.content,
+            dest: f.path,
+        })
+        .collect();
+    match manager.write_files(name, &injections).await {
+        Ok(()) => json_response(StatusCode::OK, &ApiResponse::success(results)),
+        Err(e) => {
+            for result in &mut results {
+                result.error = Some(e.to_string());
+            }
+            let code = ErrorCode::classify(&e).unwrap_or(ErrorCode::Internal);
+            json_response(
+                code.status(),
+                &ApiResponse {
+                    success: false,
+                    data: Some(results),
+                    error: Some(e.to_string()),
+                    error_code: Some(code),
+                    next: None,
+                    note: None,
+                },
+            )
+        }
+    }
+}
+
+/// Split a `multipart/form-data` body into files.
+///
+/// Each part's `filename` is its destination; relative names are taken from
+/// `/`. Parts without a filename (plain form fields) are skipped.
+fn parse_multipart(content_type: &str, body: &[u8]) -> Result<Vec<UploadedFile>> {
+    let (mime, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        anyhow::bail!("Expected Content-Type multipart/form-data");
+    }
+    let boundary = params
+        .split(';')
+        .find_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .filter(|b| !b.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Missing multipart boundary"))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next_part = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut pos = find_bytes(body, &delimiter, 0)
+        .ok_or_else(|| anyhow::anyhow!("Multipart body has no parts"))?
+        + delimiter.len();
+    let mut files = Vec::new();
+    loop {
+        if body[pos..].starts_with(b"--") {
+            return Ok(files);
+        }
+        let headers_start = if body[pos..].starts_with(b"\r\n") {
+            pos + 2
+        } else {
+            anyhow::bail!("Malformed multipart body");
+        };
+        let headers_end = find_bytes(body, b"\r\n\r\n", headers_start)
+            .ok_or_else(|| anyhow::anyhow!("Malformed multipart part headers"))?;
+        let content_end = find_bytes(body, &next_part, headers_end + 4)
+            .ok_or_else(|| anyhow::anyhow!("Multipart body is not terminated"))?;
+
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        if let Some(filename) = multipart_filename(&headers) {
+            let path = if filename.starts_with('/') {
+                filename
+            } else {
+                format!("/{}", filename)
+            };
+            files.push(UploadedFile {
+                path,
+                content: body[headers_end + 4..content_end].to_vec(),
+            });
+        }
+        pos = content_end + next_part.len();
+    }
+}
+
+/// The `filename` of a part's Content-Disposition header
+fn multipart_filename(headers: &str) -> Option<String> {
+    headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("content-disposition"))
+        .and_then(|(_, value)| {
+            value
+                .split(';')
+                .find_map(|p| p.trim().strip_prefix("filename="))
+                .map(|f| f.trim_matches('"').to_string())
+        })
+        .filter(|f| !f.is_empty())
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+async fn handle_file_delete(
+    name: &str,
+    file_path: &str,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
+    if let Err(e) = validation::validate_sandbox_name(name) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
     let abs_path = format!("/{}", file_path);
     if let Err(e) = crate::backend::validate_sandbox_path(&abs_path) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
-    let mut manager = match state.get_manager().await {
+    let mut manager = match state.lease(name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
@@ -1004,97 +2727,279 @@ async fn handle_file_delete(
             StatusCode::OK,
             &ApiResponse::success(format!("Deleted {}", abs_path)),
         ),
-        Err(e) => json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        ),
+        Err(e) => failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
     }
 }
 
 // --- Sandbox logs handler ---
 
-async fn handle_sandbox_logs(name: &str, state: Arc<AppState>) -> Response<BoxBody> {
+async fn handle_sandbox_logs(
+    name: &str,
+    query: Option<&str>,
+    state: Arc<AppState>,
+) -> Response<BoxBody> {
     if let Err(e) = validation::validate_sandbox_name(name) {
-        return json_response(
-            StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
     }
 
     // Verify sandbox exists
-    let manager = match state.get_manager().await {
+    let source = match log_source_from_query(query) {
+        Ok(source) => source,
+        Err(e) => {
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+
+    let mut manager = match state.lease(name).await {
         Ok(m) => m,
         Err(e) => {
-            return json_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &ApiResponse::<()>::error(e.to_string()),
-            );
+            return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
         }
     };
 
     if !manager.exists(name) {
-        return json_response(
-            StatusCode::NOT_FOUND,
-            &ApiResponse::<()>::error("Sandbox not found"),
-        );
+        return coded_error_response(ErrorCode::SandboxNotFound, "Sandbox not found");
+    }
+
+    let path = format!("/sandboxes/{}/logs", name);
+    if source == LogSource::Audit {
+        drop(manager);
+        let audit = crate::audit::audit();
+        if !audit.is_enabled() {
+            return audit_disabled_response();
+        }
+        return match audit.read_by_sandbox(name) {
+            Ok(entries) => paginated_response(entries, query, &path),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+    }
+
+    match manager.logs(name, None).await {
+        Ok(mut lines) => {
+            match source {
+                LogSource::Stdout => lines.retain(|l| l.stream == LogStream::Stdout),
+                LogSource::Stderr => lines.retain(|l| l.stream == LogStream::Stderr),
+                _ => {}
+            }
+            paginated_response(lines, query, &path)
+        }
+        Err(e) => failure_response(StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+/// Which log `GET /sandboxes/{name}/logs` returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LogSource {
+    /// Audit events for the sandbox (the default)
+    Audit,
+    /// Process standard output
+    Stdout,
+    /// Process standard error
+    Stderr,
+    /// Both process streams, interleaved
+    Output,
+}
+
+/// `source` from a query string
+fn log_source_from_query(query: Option<&str>) -> Result<LogSource> {
+    for (key, value) in query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+    {
+        if key == "source" {
+            return match value {
+                "audit" => Ok(LogSource::Audit),
+                "stdout" => Ok(LogSource::Stdout),
+                "stderr" => Ok(LogSource::Stderr),
+                "output" => Ok(LogSource::Output),
+                _ => anyhow::bail!("source must be one of: audit, stdout, stderr, output"),
+            };
+        }
+    }
+    Ok(LogSource::Audit)
+}
+
+// --- Audit log handler ---
+
+/// The whole audit log, oldest entry first
+fn handle_audit(query: Option<&str>) -> Response<BoxBody> {
+    if !audit::audit().is_enabled() {
+        return audit_disabled_response();
+    }
+    match audit::audit().read_all() {
+        Ok(entries) => paginated_response(entries, query, "/audit"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// An empty audit listing that says why it's empty
+fn audit_disabled_response() -> Response<BoxBody> {
+    let mut response = json_response(
+        StatusCode::OK,
+        &ApiResponse {
+            success: true,
+            data: Some(Vec::<audit::AuditEntry>::new()),
+            error: None,
+            next: None,
+            note: Some("Audit logging is disabled".to_string()),
+        },
+    );
+    response
+        .headers_mut()
+        .insert("X-Total-Count", hyper::header::HeaderValue::from(0));
+    response
+}
+
+// --- Pool stats handler ---
+
+/// Response for `GET /pool/stats`
+#[derive(Debug, Serialize)]
+struct PoolStatsResponse {
+    /// Whether the container pool has been started (it starts on the first pooled run)
+    initialized: bool,
+    pool: Option<ContainerPoolStats>,
+    /// Firecracker VM pool stats, when a daemon is running
+    daemon: Option<DaemonPoolStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct ContainerPoolStats {
+    warm: usize,
+    in_use: usize,
+    min_warm: usize,
+    max_warm: usize,
+    cleanup_pending: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct DaemonPoolStats {
+    warm: usize,
+    in_use: usize,
+    min_warm: usize,
+    max_warm: usize,
+    /// Boot latency of pool VMs per runtime
+    boot_latency: std::collections::BTreeMap<String, BootLatencyStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct BootLatencyStats {
+    count: u64,
+    avg_ms: u64,
+    min_ms: u64,
+    max_ms: u64,
+}
+
+impl PoolStatsResponse {
+    fn new(pool: Option<PoolStats>, daemon: Option<PoolStatus>) -> Self {
+        Self {
+            initialized: pool.is_some(),
+            pool: pool.map(|stats| ContainerPoolStats {
+                warm: stats.warm_count,
+                in_use: stats.in_use,
+                min_warm: stats.target_size,
+                max_warm: stats.max_size,
+                cleanup_pending: stats.cleanup_pending,
+            }),
+            daemon: daemon.map(|status| DaemonPoolStats {
+                warm: status.warm,
+                in_use: status.in_use,
+                min_warm: status.min_warm,
+                max_warm: status.max_warm,
+                boot_latency: status
+                    .boot_latency
+                    .into_iter()
+                    .map(|(runtime, latency)| {
+                        let stats = BootLatencyStats {
+                            count: latency.count,
+                            avg_ms: latency.avg_ms(),
+                            min_ms: latency.min_ms,
+                            max_ms: latency.max_ms,
+                        };
+                        (runtime, stats)
+                    })
+                    .collect(),
+            }),
+        }
     }
+}
+
+async fn handle_pool_stats() -> Response<BoxBody> {
+    let pool = VmManager::pool_stats().await;
+    let client = DaemonClient::new();
+    let daemon = if client.is_available() {
+        client.status().await.ok()
+    } else {
+        None
+    };
+    json_response(
+        StatusCode::OK,
+        &ApiResponse::success(PoolStatsResponse::new(pool, daemon)),
+    )
+}
+
+// --- Agents handler ---
 
-    let audit = crate::audit::audit();
-    match audit.read_by_sandbox(name) {
-        Ok(entries) => json_response(StatusCode::OK, &ApiResponse::success(entries)),
-        Err(e) => json_response(
+async fn handle_list_agents() -> Response<BoxBody> {
+    // Each check runs the agent's `--version`
+    match tokio::task::spawn_blocking(agents::list_agents).await {
+        Ok(statuses) => json_response(StatusCode::OK, &ApiResponse::success(statuses)),
+        Err(e) => error_response(
             StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
+            format!("Failed to check agents: {}", e),
         ),
     }
 }
 
 // --- Batch run handler ---
 
-async fn handle_batch_run(req: Request<Incoming>, state: Arc<AppState>) -> Response<BoxBody> {
+async fn handle_batch_run(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
     let body: BatchRunRequest = match read_json_body(req).await {
         Ok(b) => b,
         Err(resp) => return resp,
     };
 
     if body.commands.is_empty() {
-        return json_response(
+        return error_response(
             StatusCode::BAD_REQUEST,
-            &ApiResponse::<()>::error("commands array is required and must not be empty"),
+            "commands array is required and must not be empty",
         );
     }
 
     // Verify we can get a manager (validates backend availability)
     if let Err(e) = state.get_manager().await {
-        return json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &ApiResponse::<()>::error(e.to_string()),
-        );
+        return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
     }
 
     // Run all commands in parallel using the container pool
+    let max_output = state.max_output;
     let handles: Vec<_> = body
         .commands
         .into_iter()
         .map(|batch_cmd| {
-            tokio::spawn(async move { VmManager::run_pooled(&batch_cmd.command).await })
+            tokio::spawn(async move {
+                VmManager::run_pooled(&batch_cmd.command, Some(max_output)).await
+            })
         })
         .collect();
 
     let mut results = Vec::new();
     for handle in handles {
         match handle.await {
-            Ok(Ok(output)) => results.push(BatchResult {
+            Ok(Ok((output, truncated))) => results.push(BatchResult {
                 output: Some(output),
                 error: None,
+                truncated,
             }),
             Ok(Err(e)) => results.push(BatchResult {
                 output: None,
                 error: Some(e.to_string()),
+                truncated: false,
             }),
             Err(e) => results.push(BatchResult {
                 output: None,
                 error: Some(format!("Task failed: {}", e)),
+                truncated: false,
             }),
         }
     }
@@ -1105,11 +3010,438 @@ async fn handle_batch_run(req: Request<Incoming>, state: Arc<AppState>) -> Respo
     )
 }
 
+// --- Bulk operations handler ---
+
+async fn handle_bulk(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
+    let body: BulkRequest = match read_json_body(req).await {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+
+    if let Err(e) = validate_bulk(&body) {
+        return error_response(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    let results = if body.parallel {
+        run_bulk_parallel(&state, body.operations).await
+    } else {
+        match run_bulk_sequential(&state, &body.operations, body.atomic).await {
+            Ok(results) => results,
+            Err(e) => return failure_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        }
+    };
+
+    if body.atomic
+        && let Some(failed) = results.iter().position(|r| !r.success)
+    {
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &ApiResponse {
+                success: false,
+                error: Some(format!(
+                    "Operation {} failed; earlier operations were rolled back",
+                    failed
+                )),
+                error_code: Some(ErrorCode::Internal),
+                data: Some(BulkResponse { results }),
+                next: None,
+                note: None,
+            },
+        );
+    }
+
+    json_response(
+        StatusCode::OK,
+        &ApiResponse::success(BulkResponse { results }),
+    )
+}
+
+/// Reject malformed bulk requests before running anything
+fn validate_bulk(body: &BulkRequest) -> Result<()> {
+    if body.operations.is_empty() {
+        anyhow::bail!("operations array is required and must not be empty");
+    }
+    if body.atomic && body.parallel {
+        anyhow::bail!("atomic and parallel cannot be combined");
+    }
+    for (i, op) in body.operations.iter().enumerate() {
+        op.validate()
+            .map_err(|e| anyhow::anyhow!("operations[{}]: {}", i, e))?;
+        // Only operations that can be undone are allowed in an atomic batch
+        if body.atomic
+            && matches!(
+                op,
+                BulkOperation::Stop { .. } | BulkOperation::Delete { .. }
+            )
+        {
+            anyhow::bail!(
+                "operations[{}]: {} cannot be rolled back, so it is not allowed with atomic",
+                i,
+                op.kind()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Run operations in order. With `atomic`, stop at the first failure and
+/// undo everything that succeeded before it.
+///
+/// Every sandbox the batch names stays leased until it is done, so nothing
+/// else can change them in between and a rollback only undoes this batch's
+/// own work. Leases are taken in name order, so two batches can't deadlock.
+async fn run_bulk_sequential(
+    state: &AppState,
+    operations: &[BulkOperation],
+    atomic: bool,
+) -> Result<Vec<BulkResult>> {
+    let names: std::collections::BTreeSet<&str> = operations.iter().map(|op| op.name()).collect();
+    let mut leases = HashMap::new();
+    for name in names {
+        leases.insert(name, state.lease(name).await?);
+    }
+
+    let mut results: Vec<BulkResult> = Vec::new();
+    for op in operations {
+        if atomic && results.iter().any(|r| !r.success) {
+            results.push(BulkResult::new(
+                op,
+                Err(anyhow::anyhow!("Skipped: an earlier operation failed")),
+            ));
+            continue;
+        }
+        let manager = leases.get_mut(op.name()).expect("every sandbox is leased");
+        let result = run_bulk_op(manager, op).await;
+        results.push(BulkResult::new(op, result));
+    }
+
+    if atomic && let Some(failed) = results.iter().position(|r| !r.success) {
+        for i in (0..failed).rev() {
+            let op = &operations[i];
+            let manager = leases.get_mut(op.name()).expect("every sandbox is leased");
+            match undo_bulk_op(manager, op).await {
+                Ok(()) => results[i].rolled_back = true,
+                Err(e) => results[i].error = Some(format!("Rollback failed: {}", e)),
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Run operations concurrently, one task per sandbox. Operations on the
+/// same sandbox still run in request order.
+///
+/// Each task leases its sandbox, so the handle goes back to the shared
+/// manager even if the task panics.
+async fn run_bulk_parallel(
+    state: &Arc<AppState>,
+    operations: Vec<BulkOperation>,
+) -> Vec<BulkResult> {
+    let mut groups: Vec<(String, Vec<(usize, BulkOperation)>)> = Vec::new();
+    for (i, op) in operations.iter().cloned().enumerate() {
+        match groups.iter_mut().find(|(name, _)| name == op.name()) {
+            Some((_, ops)) => ops.push((i, op)),
+            None => groups.push((op.name().to_string(), vec![(i, op)])),
+        }
+    }
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (name, ops) in groups {
+        let state = state.clone();
+        tasks.spawn(async move {
+            let mut lease = match state.lease(&name).await {
+                Ok(lease) => lease,
+                Err(e) => {
+                    return ops
+                        .into_iter()
+                        .map(|(i, op)| (i, BulkResult::new(&op, Err(anyhow::anyhow!("{:#}", e)))))
+                        .collect();
+                }
+            };
+            let mut results = Vec::new();
+            for (i, op) in ops {
+                let result = run_bulk_op(&mut lease, &op).await;
+                results.push((i, BulkResult::new(&op, result)));
+            }
+            results
+        });
+    }
+
+    let mut slots: Vec<Option<BulkResult>> = operations.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(results) = joined {
+            for (i, result) in results {
+                slots[i] = Some(result);
+            }
+        }
+    }
+
+    slots
+        .into_iter()
+        .zip(&operations)
+        .map(|(slot, op)| {
+            slot.unwrap_or_else(|| BulkResult::new(op, Err(anyhow::anyhow!("Task failed"))))
+        })
+        .collect()
+}
+
+async fn run_bulk_op(manager: &mut VmManager, op: &BulkOperation) -> Result<()> {
+    if !matches!(op, BulkOperation::Create { .. }) && !manager.exists(op.name()) {
+        anyhow::bail!("Sandbox '{}' not found", op.name());
+    }
+    match op {
+        BulkOperation::Create {
+            name,
+            image,
+            vcpus,
+            memory_mb,
+            labels,
+        } => {
+            manager
+                .create(
+                    name,
+                    image.as_deref().unwrap_or("alpine:3.20"),
+                    vcpus.unwrap_or(1),
+                    memory_mb.unwrap_or(512),
+                )
+                .await?;
+            if !labels.is_empty()
+                && let Err(e) = manager.set_labels(name, labels.clone())
+            {
+                let _ = manager.remove(name).await;
+                return Err(e);
+            }
+            Ok(())
+        }
+        BulkOperation::Start { name, profile } => {
+            let profile = profile
+                .as_deref()
+                .and_then(resolve_profile)
+                .unwrap_or_default();
+            manager
+                .start_with_permissions(name, &profile.permissions())
+                .await
+        }
+        BulkOperation::Stop { name } => manager.stop(name).await.map(|_| ()),
+        BulkOperation::Delete { name } => manager.remove(name).await,
+    }
+}
+
+/// Reverse a completed create or start
+async fn undo_bulk_op(manager: &mut VmManager, op: &BulkOperation) -> Result<()> {
+    match op {
+        BulkOperation::Create { name, .. } => manager.remove(name).await,
+        BulkOperation::Start { name, .. } => manager.stop(name).await.map(|_| ()),
+        BulkOperation::Stop { .. } | BulkOperation::Delete { .. } => {
+            anyhow::bail!("{} cannot be rolled back", op.kind())
+        }
+    }
+}
+
+// --- Async job handlers ---
+
+async fn handle_create_job(req: Request<BoxBody>, state: Arc<AppState>) -> Response<BoxBody> {
+    let body: RunRequest = match read_json_body(req).await {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+
+    if body.command.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "command is required");
+    }
+
+    if let Some(ref img) = body.image
+        && let Err(e) = validation::validate_docker_image(img)
+    {
+        return coded_error_response(ErrorCode::InvalidImage, e.to_string());
+    }
+
+    // Jobs always get a dedicated sandbox so cancellation can stop the command
+    let image = body
+        .image
+        .unwrap_or_else(|| languages::detect_image(&body.command));
+    let profile = body.profile.as_deref().unwrap_or("moderate");
+    let perms = SecurityProfile::from_str(profile)
+        .unwrap_or_default()
+        .permissions();
+    let sandbox_name = format!("api-job-{}", &uuid::Uuid::new_v4().to_string()[..8]);
+
+    let command = body.command.clone();
+    let name = sandbox_name.clone();
+    let job_state = state.clone();
+    let id = state
+        .jobs
+        .submit(body.command, Some(sandbox_name), async move {
+            let mut manager = job_state.run_manager()?;
+            manager.create_ephemeral(&name, &image, 1, 512).await?;
+            if let Err(e) = manager.start_with_permissions(&name, &perms).await {
+                let _ = manager.remove(&name).await;
+                return Err(e);
+            }
+            let opts = ExecOptions::default().max_output(Some(job_state.max_output));
+            let result = manager.exec_cmd_full(&name, &command, &opts).await;
+            let _ = manager.remove(&name).await;
+            result
+        });
+
+    match state.jobs.get(&id) {
+        Some(info) => json_response(StatusCode::ACCEPTED, &ApiResponse::success(info)),
+        None => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Job disappeared after submission",
+        ),
+    }
+}
+
+fn handle_get_job(id: &str, state: Arc<AppState>) -> Response<BoxBody> {
+    match state.jobs.get(id) {
+        Some(info) => json_response(StatusCode::OK, &ApiResponse::success(info)),
+        None => error_response(StatusCode::NOT_FOUND, "Job not found"),
+    }
+}
+
+/// Stream a job as SSE: `started` right away, then `output` and `done` (or
+/// `error`) when it finishes
+fn handle_job_stream(id: &str, state: Arc<AppState>) -> Response<BoxBody> {
+    let Some(info) = state.jobs.get(id) else {
+        return error_response(StatusCode::NOT_FOUND, "Job not found");
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let id = id.to_string();
+    tokio::spawn(async move {
+        let started = sse_chunk(
+            "started",
+            &serde_json::json!({
+                "job_id": info.id,
+                "command": info.command,
+                "timestamp": info.created_at
+            }),
+        );
+        if tx.send(started).await.is_err() {
+            return; // Client went away
+        }
+
+        let mut events = Vec::new();
+        match state.jobs.wait(&id).await {
+            None => events.push(sse_chunk(
+                "error",
+                &serde_json::json!({"message": "Job expired"}),
+            )),
+            Some(info) if info.status == JobStatus::Cancelled => events.push(sse_chunk(
+                "error",
+                &serde_json::json!({"message": "Job cancelled"}),
+            )),
+            Some(JobInfo {
+                exit_code: Some(exit_code),
+                output,
+                ..
+            }) => {
+                events.push(sse_chunk(
+                    "output",
+                    &serde_json::json!({
+                        "data": output.unwrap_or_default(),
+                        "stream": "stdout"
+                    }),
+                ));
+                events.push(sse_chunk(
+                    "done",
+                    &serde_json::json!({
+                        "exit_code": exit_code,
+                        "success": exit_code == 0
+                    }),
+                ));
+            }
+            Some(info) => events.push(sse_chunk(
+                "error",
+                &serde_json::json!({
+                    "message": info.error.unwrap_or_else(|| "Job failed".to_string())
+                }),
+            )),
+        }
+        for event in events {
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .body(ChannelBody { rx }.boxed())
+        .unwrap()
+}
+
+fn handle_cancel_job(id: &str, state: Arc<AppState>) -> Response<BoxBody> {
+    let Some((info, sandbox)) = state.jobs.cancel(id) else {
+        return error_response(StatusCode::NOT_FOUND, "Job not found");
+    };
+
+    // Removing the sandbox kills whatever is still running inside it
+    if let Some(name) = sandbox {
+        tokio::spawn(async move {
+            if let Ok(mut lease) = state.lease(&name).await {
+                let _ = lease.remove(&name).await;
+            }
+        });
+    }
+
+    json_response(StatusCode::OK, &ApiResponse::success(info))
+}
+
 /// Run the HTTP API server
 pub async fn run_server(addr: SocketAddr) -> Result<()> {
     let state = Arc::new(AppState::new());
     let listener = TcpListener::bind(addr).await?;
 
+    // Remove sandboxes past their TTL while the server runs
+    let sweep_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(crate::vmm::EXPIRY_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Ok(mut manager) = sweep_state.get_manager().await {
+                for name in manager.reap_expired() {
+                    eprintln!("Removed expired sandbox '{}'", name);
+                }
+            }
+        }
+    });
+
+    // Pick up containers started or stopped by the CLI or the runtime
+    let detect_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(crate::vmm::DETECT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = detect_state.detect_running().await;
+        }
+    });
+
+    // Restart crashed sandboxes on backends without a native restart policy
+    let restart_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(crate::vmm::RESTART_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let candidates = match restart_state.get_manager().await {
+                Ok(manager) => manager.restart_candidates(),
+                Err(_) => continue,
+            };
+            // One sandbox at a time, so a slow restart doesn't stall other requests
+            for name in candidates {
+                if let Ok(mut lease) = restart_state.lease(&name).await {
+                    for name in lease.restart_exited().await {
+                        eprintln!("Restarted sandbox '{}' (restart policy)", name);
+                    }
+                }
+            }
+        }
+    });
+
     eprintln!("agentkernel HTTP API server listening on http://{}", addr);
 
     loop {
@@ -1133,6 +3465,7 @@ pub async fn run_server(addr: SocketAddr) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::BackendType;
 
     // === ApiResponse tests ===
 
@@ -1146,10 +3479,11 @@ mod tests {
 
     #[test]
     fn test_api_response_error() {
-        let response = ApiResponse::<()>::error("test error");
+        let response = ApiResponse::<()>::error(ErrorCode::Internal, "test error");
         assert!(!response.success);
         assert!(response.data.is_none());
         assert_eq!(response.error, Some("test error".to_string()));
+        assert_eq!(response.error_code, Some(ErrorCode::Internal));
     }
 
     #[test]
@@ -1163,11 +3497,70 @@ mod tests {
 
     #[test]
     fn test_api_response_error_serialization() {
-        let response = ApiResponse::<()>::error("failed");
+        let response = ApiResponse::<()>::error(ErrorCode::SandboxNotRunning, "failed");
         let json = serde_json::to_string(&response).unwrap();
         assert!(json.contains("\"success\":false"));
         assert!(!json.contains("\"data\"")); // data is skipped when None
         assert!(json.contains("\"error\":\"failed\""));
+        assert!(json.contains("\"error_code\":\"SANDBOX_NOT_RUNNING\""));
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        async fn body(resp: Response<BoxBody>) -> (StatusCode, serde_json::Value) {
+            let status = resp.status();
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice(&bytes).unwrap())
+        }
+
+        // Plain errors take their code from the status
+        let (status, json) = body(error_response(StatusCode::BAD_REQUEST, "bad")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["error_code"], "INVALID_REQUEST");
+
+        // Recognized errors override the fallback status
+        let cases = [
+            (
+                anyhow::Error::new(SandboxLookupError::NotFound {
+                    name: "x".to_string(),
+                }),
+                StatusCode::NOT_FOUND,
+                "SANDBOX_NOT_FOUND",
+            ),
+            (
+                anyhow::Error::new(SandboxLookupError::NotRunning {
+                    name: "x".to_string(),
+                    reason: None,
+                }),
+                StatusCode::CONFLICT,
+                "SANDBOX_NOT_RUNNING",
+            ),
+            (
+                anyhow::Error::new(BackendUnavailable::NoneFound),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "BACKEND_UNAVAILABLE",
+            ),
+            (
+                anyhow::anyhow!("disk full"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "INTERNAL",
+            ),
+        ];
+        for (err, want_status, want_code) in cases {
+            let message = err.to_string();
+            let (status, json) =
+                body(failure_response(StatusCode::INTERNAL_SERVER_ERROR, &err)).await;
+            assert_eq!(status, want_status, "{}", message);
+            assert_eq!(json["error_code"], want_code, "{}", message);
+            assert_eq!(json["error"], message);
+        }
+
+        // Context added on the way up doesn't hide the cause
+        let err = anyhow::Error::new(SandboxLookupError::NotFound {
+            name: "x".to_string(),
+        })
+        .context("Failed to exec");
+        assert_eq!(ErrorCode::classify(&err), Some(ErrorCode::SandboxNotFound));
     }
 
     // === Request deserialization tests ===
@@ -1195,7 +3588,20 @@ mod tests {
     fn test_run_request_deserialize_fast_false() {
         let json = r#"{"command": ["ls"], "fast": false}"#;
         let req: RunRequest = serde_json::from_str(json).unwrap();
-        assert!(!req.fast);
+        assert!(!req.fast);
+    }
+
+    #[test]
+    fn test_max_output_is_capped_at_server_limit() {
+        let mut state = AppState::with_api_key(None);
+        state.max_output = 1000;
+        assert_eq!(state.max_output(None), 1000);
+        assert_eq!(state.max_output(Some(10)), 10);
+        assert_eq!(state.max_output(Some(1_000_000)), 1000);
+
+        let json = r#"{"command": ["ls"], "max_output": 10}"#;
+        let req: RunRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.max_output, Some(10));
     }
 
     #[test]
@@ -1219,6 +3625,36 @@ mod tests {
         let json = r#"{"command": ["npm", "test"]}"#;
         let req: ExecRequest = serde_json::from_str(json).unwrap();
         assert_eq!(req.command, vec!["npm", "test"]);
+        assert!(req.check);
+
+        let json = r#"{"command": ["npm", "test"], "check": false}"#;
+        let req: ExecRequest = serde_json::from_str(json).unwrap();
+        assert!(!req.check);
+        assert_eq!(req.timeout, None);
+
+        let json = r#"{"command": ["sleep", "60"], "timeout": 5}"#;
+        let req: ExecRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.timeout, Some(5));
+        assert_eq!(req.max_output, None);
+    }
+
+    #[test]
+    fn test_label_filters_from_query() {
+        assert!(label_filters(None).unwrap().is_empty());
+
+        let filters = label_filters(Some("label=project%3Dfoo&label=owner&other=x")).unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[0].key, "project");
+        assert_eq!(filters[0].value.as_deref(), Some("foo"));
+        assert_eq!(filters[1].key, "owner");
+        assert_eq!(filters[1].value, None);
+
+        // Unencoded '=' in the value works too
+        let filters = label_filters(Some("label=project=my+app")).unwrap();
+        assert_eq!(filters[0].value.as_deref(), Some("my app"));
+
+        assert!(label_filters(Some("label=bad%20key")).is_err());
+        assert_eq!(percent_decode("100%"), "100%");
     }
 
     // === SandboxInfo tests ===
@@ -1233,10 +3669,21 @@ mod tests {
             vcpus: None,
             memory_mb: None,
             created_at: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            ip_address: None,
         };
         let json = serde_json::to_string(&info).unwrap();
         assert!(json.contains("\"name\":\"test-sandbox\""));
         assert!(json.contains("\"status\":\"running\""));
+        assert!(!json.contains("ip_address"));
+
+        let info = SandboxInfo {
+            ip_address: Some("172.17.0.3".parse().unwrap()),
+            ..info
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(json.contains("\"ip_address\":\"172.17.0.3\""));
     }
 
     // === RunResponse tests ===
@@ -1245,9 +3692,46 @@ mod tests {
     fn test_run_response_serialize() {
         let response = RunResponse {
             output: "hello world".to_string(),
+            rusage: None,
+            exit_code: None,
+            truncated: false,
+            encoding: None,
         };
         let json = serde_json::to_string(&response).unwrap();
         assert!(json.contains("\"output\":\"hello world\""));
+        assert!(!json.contains("rusage"));
+        assert!(!json.contains("exit_code"));
+        assert!(!json.contains("truncated"));
+        assert!(!json.contains("encoding"));
+    }
+
+    #[test]
+    fn test_encode_output() {
+        assert_eq!(encode_output(b"hi\n".to_vec()), ("hi\n".to_string(), None));
+        // Invalid UTF-8 is base64-encoded rather than replaced
+        assert_eq!(
+            encode_output(vec![0xff, 0xfe, b'o', b'k']),
+            ("//5vaw==".to_string(), Some("base64"))
+        );
+    }
+
+    #[test]
+    fn test_run_response_serialize_rusage() {
+        let response = RunResponse {
+            output: String::new(),
+            exit_code: Some(0),
+            truncated: false,
+            rusage: Some(ResourceUsage {
+                user_time_ms: 40,
+                system_time_ms: 10,
+                max_rss_kb: 18432,
+            }),
+            encoding: None,
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains(
+            "\"rusage\":{\"user_time_ms\":40,\"system_time_ms\":10,\"max_rss_kb\":18432}"
+        ));
     }
 
     // === AppState tests ===
@@ -1285,10 +3769,7 @@ mod tests {
 
     #[test]
     fn test_json_response_not_found() {
-        let response = json_response(
-            StatusCode::NOT_FOUND,
-            &ApiResponse::<()>::error("not found"),
-        );
+        let response = error_response(StatusCode::NOT_FOUND, "not found");
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
@@ -1302,6 +3783,9 @@ mod tests {
             vcpus: None,
             memory_mb: None,
             created_at: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            ip_address: None,
         };
         let response = json_response(StatusCode::CREATED, &ApiResponse::success(info));
         assert_eq!(response.status(), StatusCode::CREATED);
@@ -1396,6 +3880,9 @@ mod tests {
             vcpus: Some(4),
             memory_mb: Some(2048),
             created_at: Some("2026-01-30T12:00:00Z".to_string()),
+            labels: HashMap::new(),
+            image_digest: None,
+            ip_address: None,
         };
         let json = serde_json::to_string(&info).unwrap();
         assert!(json.contains("\"image\":\"python:3.12\""));
@@ -1414,6 +3901,9 @@ mod tests {
             vcpus: None,
             memory_mb: None,
             created_at: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            ip_address: None,
         };
         let json = serde_json::to_string(&info).unwrap();
         assert!(!json.contains("image"));
@@ -1455,6 +3945,34 @@ mod tests {
         assert!(json.contains("\"size\":13"));
     }
 
+    #[test]
+    fn test_etag_matches() {
+        let etag = content_etag(b"plan: v1");
+        assert!(etag_matches(&etag, &etag));
+        assert!(etag_matches(&format!("W/{}", etag), &etag));
+        assert!(etag_matches(&format!("\"other\", {}", etag), &etag));
+        assert!(etag_matches("*", &etag));
+        assert!(!etag_matches("\"other\"", &etag));
+    }
+
+    #[test]
+    fn test_file_read_response_conditional() {
+        let content = b"plan: v1".to_vec();
+        let resp = file_read_response(content.clone(), None);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+
+        // Unchanged file: the client's ETag still matches
+        let resp = file_read_response(content, Some(&etag));
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()["ETag"].to_str().unwrap(), etag);
+
+        // Changed file: full response with a new ETag
+        let resp = file_read_response(b"plan: v2".to_vec(), Some(&etag));
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(resp.headers()["ETag"].to_str().unwrap(), etag);
+    }
+
     // === BatchRunRequest tests ===
 
     #[test]
@@ -1488,16 +4006,19 @@ mod tests {
                 BatchResult {
                     output: Some("hello".to_string()),
                     error: None,
+                    truncated: true,
                 },
                 BatchResult {
                     output: None,
                     error: Some("failed".to_string()),
+                    truncated: false,
                 },
             ],
         };
         let json = serde_json::to_string(&resp).unwrap();
         assert!(json.contains("\"output\":\"hello\""));
         assert!(json.contains("\"error\":\"failed\""));
+        assert_eq!(json.matches("\"truncated\":true").count(), 1);
     }
 
     // === resolve_profile tests ===
@@ -1562,6 +4083,930 @@ mod tests {
         assert_eq!(segments, vec!["sandboxes", "my-sandbox", "logs"]);
     }
 
+    #[test]
+    fn test_path_segments_job_stream() {
+        let path = "/jobs/5f1c/stream";
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        assert_eq!(segments, vec!["jobs", "5f1c", "stream"]);
+    }
+
+    // === Job handler tests ===
+
+    #[tokio::test]
+    async fn test_job_poll_and_cancel_handlers() {
+        let state = Arc::new(AppState::with_api_key(None));
+
+        let resp = handle_get_job("missing", state.clone());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let id = state.jobs.submit(vec!["sleep".to_string()], None, async {
+            tokio::time::sleep(std::time::Duration::from_secs(600)).await;
+            Ok(String::new())
+        });
+
+        let resp = handle_get_job(&id, state.clone());
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = handle_cancel_job(&id, state.clone());
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.jobs.get(&id).unwrap().status, JobStatus::Cancelled);
+
+        let resp = handle_cancel_job("missing", state);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_lease_locks_only_its_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        let state = Arc::new(AppState::with_manager(manager));
+        let wait = std::time::Duration::from_millis(50);
+
+        let mut lease = state.lease("first").await.unwrap();
+        lease.create("first", "alpine:3.20", 1, 256).await.unwrap();
+
+        // Other sandboxes and the shared manager stay available
+        let other = tokio::time::timeout(wait, state.lease("second")).await;
+        assert!(other.is_ok());
+        drop(other);
+        let shared = tokio::time::timeout(wait, state.get_manager()).await;
+        assert!(shared.unwrap().unwrap().exists("first"));
+
+        // The same sandbox waits for the lease to be dropped
+        assert!(
+            tokio::time::timeout(wait, state.lease("first"))
+                .await
+                .is_err()
+        );
+        drop(lease);
+        let lease = tokio::time::timeout(wait, state.lease("first")).await;
+        assert!(lease.unwrap().unwrap().exists("first"));
+    }
+
+    #[tokio::test]
+    async fn test_shared_manager_sees_earlier_creates() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        let state = Arc::new(AppState::with_manager(manager));
+
+        state
+            .get_manager()
+            .await
+            .unwrap()
+            .create("first", "alpine:3.20", 1, 256)
+            .await
+            .unwrap();
+        {
+            let mut manager = state.get_manager().await.unwrap();
+            assert!(manager.exists("first"));
+            manager
+                .create("second", "alpine:3.20", 1, 256)
+                .await
+                .unwrap();
+        }
+
+        let mut manager = state.get_manager().await.unwrap();
+        let mut names: Vec<_> = manager.list().into_iter().map(|(n, _, _)| n).collect();
+        names.sort();
+        assert_eq!(names, vec!["first", "second"]);
+        assert!(
+            manager
+                .create("first", "alpine:3.20", 1, 256)
+                .await
+                .is_err()
+        );
+        drop(manager);
+
+        // Sandboxes created by another process show up on the next request
+        let mut other =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        other.create("third", "alpine:3.20", 1, 256).await.unwrap();
+        assert!(state.get_manager().await.unwrap().exists("third"));
+    }
+
+    fn multipart_body(boundary: &str, parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (filename, content) in parts {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n{}\r\n",
+                    boundary, filename, content
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        body
+    }
+
+    #[test]
+    fn test_parse_multipart() {
+        let body = multipart_body("xyz", &[("/workspace/a.txt", "one\r\ntwo"), ("b.txt", "")]);
+        let files = parse_multipart("multipart/form-data; boundary=xyz", &body).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                UploadedFile {
+                    path: "/workspace/a.txt".to_string(),
+                    content: b"one\r\ntwo".to_vec(),
+                },
+                UploadedFile {
+                    path: "/b.txt".to_string(),
+                    content: Vec::new(),
+                },
+            ]
+        );
+
+        // Quoted boundary; plain form fields are skipped
+        let body = b"--q\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n--q--\r\n";
+        assert!(
+            parse_multipart("multipart/form-data; boundary=\"q\"", body)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(parse_multipart("application/json", &body[..]).is_err());
+        assert!(parse_multipart("multipart/form-data", &body[..]).is_err());
+        assert!(parse_multipart("multipart/form-data; boundary=q", b"--q\r\nbroken").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_files_writes_every_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let (manager, _) = crate::vmm::tests::manager_with_host_sandbox(&dir, "up");
+        let state = Arc::new(AppState::with_manager(manager));
+
+        let a = dest.path().join("a.txt");
+        let b = dest.path().join("src/main.rs");
+        let body = multipart_body(
+            "boundary42",
+            &[
+                (a.to_str().unwrap(), "hello"),
+                (b.to_str().unwrap(), "fn main() {}\n"),
+            ],
+        );
+        let content_type = "multipart/form-data; boundary=boundary42";
+        let resp = upload_files("up", content_type, &body, state.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "hello");
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "fn main() {}\n");
+
+        // One bad path rejects the whole upload
+        let c = dest.path().join("c.txt");
+        let body = multipart_body(
+            "boundary42",
+            &[(c.to_str().unwrap(), "x"), ("/proc/evil", "x")],
+        );
+        let resp = upload_files("up", content_type, &body, state).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let json: serde_json::Value =
+            serde_json::from_slice(&resp.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert!(json["data"][0]["error"].is_null());
+        assert!(json["data"][1]["error"].is_string());
+        assert!(!c.exists());
+    }
+
+    #[tokio::test]
+    async fn test_file_upload_over_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let (manager, _) = crate::vmm::tests::manager_with_host_sandbox(&dir, "up");
+        let state = Arc::new(AppState {
+            max_upload: 64,
+            ..AppState::with_manager(manager)
+        });
+
+        let a = dest.path().join("a.txt");
+        let body = multipart_body(
+            "boundary42",
+            &[(a.to_str().unwrap(), "x".repeat(100).as_str())],
+        );
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/sandboxes/up/files")
+            .header("content-type", "multipart/form-data; boundary=boundary42")
+            .body(full(body))
+            .unwrap();
+        let resp = handle_file_upload(req, "up", state).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!a.exists());
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(parse_byte_range(None, 1000), ByteRange::Full);
+        assert_eq!(
+            parse_byte_range(Some("bytes=0-99"), 1000),
+            ByteRange::Partial(0, 99)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=900-"), 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-100"), 1000),
+            ByteRange::Partial(900, 999)
+        );
+        // Clamped to the file
+        assert_eq!(
+            parse_byte_range(Some("bytes=990-2000"), 1000),
+            ByteRange::Partial(990, 999)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-5000"), 1000),
+            ByteRange::Partial(0, 999)
+        );
+
+        assert_eq!(
+            parse_byte_range(Some("bytes=1000-"), 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-0"), 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=0-"), 0),
+            ByteRange::Unsatisfiable
+        );
+
+        // Ignored: the whole file is sent
+        assert_eq!(
+            parse_byte_range(Some("bytes=0-1,5-9"), 1000),
+            ByteRange::Full
+        );
+        assert_eq!(parse_byte_range(Some("bytes=9-5"), 1000), ByteRange::Full);
+        assert_eq!(parse_byte_range(Some("items=0-9"), 1000), ByteRange::Full);
+        assert_eq!(parse_byte_range(Some("bytes=x-9"), 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_accepts_octet_stream() {
+        assert!(accepts_octet_stream("application/octet-stream"));
+        assert!(accepts_octet_stream(
+            "application/json;q=0.5, Application/Octet-Stream"
+        ));
+        assert!(!accepts_octet_stream("application/json"));
+        assert!(!accepts_octet_stream("*/*"));
+    }
+
+    #[tokio::test]
+    async fn test_file_download_full_and_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, _) = crate::vmm::tests::manager_with_host_sandbox(&dir, "dl");
+        let state = Arc::new(AppState::with_manager(manager));
+        let path = dir.path().join("artifact.bin");
+        let content: Vec<u8> = (0..1000u32).map(|i| b'a' + (i % 26) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let file_path = path.to_str().unwrap().trim_start_matches('/');
+
+        let resp = handle_file_download("dl", file_path, None, state.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["Content-Type"], "application/octet-stream");
+        assert_eq!(resp.headers()["Content-Length"], "1000");
+        assert_eq!(resp.headers()["Accept-Ranges"], "bytes");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.as_ref(), content.as_slice());
+
+        let resp = handle_file_download("dl", file_path, Some("bytes=0-99"), state.clone()).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()["Content-Range"], "bytes 0-99/1000");
+        assert_eq!(resp.headers()["Content-Length"], "100");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.as_ref(), &content[..100]);
+
+        let resp = handle_file_download("dl", file_path, Some("bytes=5000-"), state.clone()).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()["Content-Range"], "bytes */1000");
+
+        let missing = dir.path().join("missing.bin");
+        let missing = missing.to_str().unwrap().trim_start_matches('/');
+        let resp = handle_file_download("dl", missing, None, state).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    // === Bulk operation tests ===
+
+    fn bulk_state() -> (tempfile::TempDir, Arc<AppState>) {
+        let dir = tempfile::tempdir().unwrap();
+        let manager =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        (dir, Arc::new(AppState::with_manager(manager)))
+    }
+
+    #[tokio::test]
+    async fn test_handler_error_codes() {
+        async fn code(resp: Response<BoxBody>) -> (StatusCode, String) {
+            let status = resp.status();
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, json["error_code"].as_str().unwrap().to_string())
+        }
+
+        let (_dir, state) = bulk_state();
+        state
+            .get_manager()
+            .await
+            .unwrap()
+            .create("stopped", "alpine:3.20", 1, 256)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            code(handle_get_sandbox("bad name", state.clone()).await).await,
+            (StatusCode::BAD_REQUEST, "INVALID_REQUEST".to_string())
+        );
+        assert_eq!(
+            code(handle_get_sandbox("missing", state.clone()).await).await,
+            (StatusCode::NOT_FOUND, "SANDBOX_NOT_FOUND".to_string())
+        );
+        assert_eq!(
+            code(handle_sandbox_stats("stopped", state.clone()).await).await,
+            (StatusCode::CONFLICT, "SANDBOX_NOT_RUNNING".to_string())
+        );
+        assert_eq!(
+            code(handle_get_job("missing", state)).await,
+            (StatusCode::NOT_FOUND, "NOT_FOUND".to_string())
+        );
+    }
+
+    #[test]
+    fn test_file_write_request_options() {
+        let body: FileWriteRequest =
+            serde_json::from_str(r#"{"content": "x", "append": true, "mode": "0755"}"#).unwrap();
+        assert_eq!(
+            body.write_options().unwrap(),
+            WriteOptions::default().append(true).mode(Some(0o755))
+        );
+
+        let body: FileWriteRequest = serde_json::from_str(r#"{"content": "x"}"#).unwrap();
+        assert_eq!(body.write_options().unwrap(), WriteOptions::default());
+
+        let body: FileWriteRequest =
+            serde_json::from_str(r#"{"content": "x", "mode": "u+x"}"#).unwrap();
+        assert!(body.write_options().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exec_missing_sandbox_is_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        let state = AppState::with_manager(manager);
+
+        let body: ExecRequest = serde_json::from_str(r#"{"command": ["true"]}"#).unwrap();
+        let resp = exec_sandbox("missing", body, &state).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let json: serde_json::Value =
+            serde_json::from_slice(&resp.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!(json["error_code"], "SANDBOX_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_policy_denial_reaches_error_payload() {
+        use crate::config::{CommandConfig, SecurityConfig};
+        use crate::policy::{PolicyAction, PolicyEngine};
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = PolicyEngine::new(SecurityConfig {
+            commands: CommandConfig {
+                allow: Vec::new(),
+                block: vec!["sudo".to_string()],
+                allowlist_only: false,
+            },
+            ..Default::default()
+        })
+        .with_audit_log(audit::AuditLog::with_path(dir.path().join("audit.jsonl")));
+        let err = engine
+            .enforce(PolicyAction::Run, "sudo reboot", "api-run-1")
+            .unwrap_err();
+
+        let resp = exec_error_response(&err);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let json: serde_json::Value =
+            serde_json::from_slice(&resp.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!(json["success"], false);
+        assert!(
+            json["error"]
+                .as_str()
+                .unwrap()
+                .contains("blocked by security policy")
+        );
+        assert_eq!(json["error_code"], "POLICY_DENIED");
+        assert_eq!(json["data"]["reason"], "command is on the block list");
+        assert_eq!(
+            json["data"]["matched"],
+            serde_json::json!(["security.commands.block = \"sudo\""])
+        );
+        assert_eq!(json["data"]["principal"], "api-run-1");
+        assert_eq!(json["data"]["action"], "run");
+        assert_eq!(json["data"]["resource"], "sudo reboot");
+
+        // Streaming runs carry it in the error event
+        let event = error_event(&err);
+        assert_eq!(event["policy"]["reason"], "command is on the block list");
+        assert_eq!(event["error_code"], "POLICY_DENIED");
+
+        // Other failures stay 500s without a decision
+        let other = anyhow::anyhow!("Sandbox 'x' is not running");
+        assert_eq!(
+            exec_error_response(&other).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(error_event(&other)["policy"].is_null());
+        assert_eq!(error_event(&other)["error_code"], "INTERNAL");
+    }
+
+    #[test]
+    fn test_stop_all_confirmed() {
+        assert!(stop_all_confirmed(Some("all=true")));
+        assert!(stop_all_confirmed(Some("force=1&all=true")));
+        assert!(!stop_all_confirmed(None));
+        assert!(!stop_all_confirmed(Some("all=1")));
+        assert!(!stop_all_confirmed(Some("all=false")));
+    }
+
+    #[test]
+    fn test_wait_timeout_from_query() {
+        assert_eq!(wait_timeout_from_query(None).unwrap(), None);
+        assert_eq!(
+            wait_timeout_from_query(Some("timeout=30")).unwrap(),
+            Some(std::time::Duration::from_secs(30))
+        );
+        assert!(wait_timeout_from_query(Some("timeout=soon")).is_err());
+    }
+
+    #[test]
+    fn test_page_from_query() {
+        assert_eq!(
+            Page::from_query(None).unwrap(),
+            Page {
+                limit: DEFAULT_PAGE_SIZE,
+                offset: 0
+            }
+        );
+        assert_eq!(
+            Page::from_query(Some("label=a&limit=5&offset=10")).unwrap(),
+            Page {
+                limit: 5,
+                offset: 10
+            }
+        );
+        assert!(Page::from_query(Some("limit=0")).is_err());
+        assert!(Page::from_query(Some("limit=1001")).is_err());
+        assert!(Page::from_query(Some("offset=-1")).is_err());
+    }
+
+    #[test]
+    fn test_page_apply_boundaries() {
+        let items: Vec<u32> = (0..5).collect();
+        let page = Page {
+            limit: 2,
+            offset: 0,
+        };
+        let (first, total, next) = page.apply(items.clone(), "/audit", Some("limit=2"));
+        assert_eq!(first, vec![0, 1]);
+        assert_eq!(total, 5);
+        assert_eq!(next.as_deref(), Some("/audit?limit=2&offset=2"));
+
+        let page = Page {
+            limit: 2,
+            offset: 4,
+        };
+        let (last, _, next) = page.apply(items.clone(), "/audit", None);
+        assert_eq!(last, vec![4]);
+        assert_eq!(next, None);
+
+        let page = Page {
+            limit: 2,
+            offset: 9,
+        };
+        let (past_end, total, next) = page.apply(items, "/audit", None);
+        assert!(past_end.is_empty());
+        assert_eq!(total, 5);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn test_log_source_from_query() {
+        assert_eq!(log_source_from_query(None).unwrap(), LogSource::Audit);
+        assert_eq!(
+            log_source_from_query(Some("limit=10&source=stdout")).unwrap(),
+            LogSource::Stdout
+        );
+        assert_eq!(
+            log_source_from_query(Some("source=output")).unwrap(),
+            LogSource::Output
+        );
+        assert!(log_source_from_query(Some("source=serial")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_sandboxes_paginates() {
+        let (_dir, state) = bulk_state();
+        {
+            let mut manager = state.get_manager().await.unwrap();
+            for name in ["page-c", "page-a", "page-b"] {
+                manager.create(name, "alpine:3.20", 1, 256).await.unwrap();
+            }
+        }
+
+        async fn list(state: &Arc<AppState>, query: Option<&str>) -> (String, serde_json::Value) {
+            let resp = handle_list_sandboxes(query, state.clone()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let total = resp.headers()["X-Total-Count"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (total, serde_json::from_slice(&body).unwrap())
+        }
+
+        // No parameters: the first (default-sized) page, with the total
+        let (total, body) = list(&state, None).await;
+        assert_eq!(total, "3");
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+        assert!(body.get("next").is_none());
+
+        let (total, body) = list(&state, Some("limit=2")).await;
+        assert_eq!(total, "3");
+        let names: Vec<&str> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["page-a", "page-b"]);
+        assert_eq!(body["next"], "/sandboxes?limit=2&offset=2");
+
+        let (_, body) = list(&state, Some("limit=2&offset=2")).await;
+        assert_eq!(body["data"][0]["name"], "page-c");
+        assert!(body.get("next").is_none());
+
+        let resp = handle_list_sandboxes(Some("limit=0"), state).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_list_agents_reports_each_agent() {
+        let resp = handle_list_agents().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let agents = body["data"].as_array().unwrap();
+        let types: Vec<&str> = agents
+            .iter()
+            .map(|a| a["agent_type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, ["claude", "gemini", "codex", "opencode"]);
+        for agent in agents {
+            assert!(agent["installed"].is_boolean(), "{}", agent);
+            assert!(agent["api_key_set"].is_boolean(), "{}", agent);
+            assert!(agent["install_instructions"].is_string(), "{}", agent);
+            // Null unless the agent is installed here
+            assert!(agent["version"].is_null() || agent["version"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_unknown_sandbox_is_not_found() {
+        let (_dir, state) = bulk_state();
+        let resp = handle_wait_sandbox("missing", None, state).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_event_filter_from_query() {
+        let filter = EventFilter::from_query(Some("sandbox=my%2Dbox&event_type=file_written"));
+        assert_eq!(filter.sandbox.as_deref(), Some("my-box"));
+        assert!(filter.matches(&AuditEvent::FileWritten {
+            sandbox: "my-box".to_string(),
+            path: "/tmp/x".to_string(),
+        }));
+        assert!(!filter.matches(&AuditEvent::FileRead {
+            sandbox: "my-box".to_string(),
+            path: "/tmp/x".to_string(),
+        }));
+        assert_eq!(EventFilter::from_query(None), EventFilter::default());
+    }
+
+    #[tokio::test]
+    async fn test_events_stream_receives_created_sandbox() {
+        let (_dir, state) = bulk_state();
+        let mut body = handle_events(Some("sandbox=events-test")).into_body();
+
+        // Events for other sandboxes are filtered out
+        audit::log_event(AuditEvent::SandboxStopped {
+            name: "events-other".to_string(),
+        });
+        let mut manager = state.get_manager().await.unwrap();
+        manager
+            .create("events-test", "alpine:3.20", 1, 256)
+            .await
+            .unwrap();
+
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let chunk = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        assert!(chunk.starts_with("event: sandbox_created\n"));
+        assert!(chunk.contains("\"name\":\"events-test\""));
+    }
+
+    #[tokio::test]
+    async fn test_job_stream_reports_exit_code() {
+        let state = Arc::new(AppState::with_api_key(None));
+        let resp = handle_job_stream("no-such-job", state.clone());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let id = state.jobs.submit(vec!["false".to_string()], None, async {
+            let _ = rx.await;
+            Ok(crate::backend::ExecResult::failure(2, "nope\n".to_string()))
+        });
+        let mut body = handle_job_stream(&id, state.clone()).into_body();
+
+        // `started` arrives while the job is still running
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let chunk = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        assert!(chunk.starts_with("event: started\n"), "{}", chunk);
+
+        tx.send(()).unwrap();
+        let rest = body.collect().await.unwrap().to_bytes();
+        let rest = String::from_utf8(rest.to_vec()).unwrap();
+        assert!(rest.contains("event: output\n"), "{}", rest);
+        assert!(
+            rest.contains("event: done\ndata: {\"exit_code\":2,\"success\":false}"),
+            "{}",
+            rest
+        );
+    }
+
+    #[test]
+    fn test_bulk_request_deserialize() {
+        let json = r#"{"operations": [
+            {"op": "create", "name": "a", "image": "python:3.12-alpine"},
+            {"op": "start", "name": "a", "profile": "restrictive"},
+            {"op": "delete", "name": "b"}
+        ], "parallel": true}"#;
+        let req: BulkRequest = serde_json::from_str(json).unwrap();
+        assert!(req.parallel);
+        assert!(!req.atomic);
+        assert_eq!(req.operations.len(), 3);
+        assert_eq!(req.operations[1].kind(), "start");
+        assert_eq!(req.operations[2].name(), "b");
+
+        assert!(
+            serde_json::from_str::<BulkRequest>(
+                r#"{"operations": [{"op": "reboot", "name": "a"}]}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_bulk() {
+        let parse = |json: &str| serde_json::from_str::<BulkRequest>(json).unwrap();
+        assert!(validate_bulk(&parse(r#"{"operations": []}"#)).is_err());
+        assert!(
+            validate_bulk(&parse(
+                r#"{"operations": [{"op": "stop", "name": "a; rm -rf /"}]}"#
+            ))
+            .is_err()
+        );
+        assert!(
+            validate_bulk(&parse(
+                r#"{"operations": [{"op": "start", "name": "a", "profile": "bogus"}]}"#
+            ))
+            .is_err()
+        );
+        // Deletes can't be undone
+        assert!(
+            validate_bulk(&parse(
+                r#"{"operations": [{"op": "delete", "name": "a"}], "atomic": true}"#
+            ))
+            .is_err()
+        );
+        assert!(
+            validate_bulk(&parse(
+                r#"{"operations": [{"op": "create", "name": "a"}], "atomic": true, "parallel": true}"#
+            ))
+            .is_err()
+        );
+        assert!(
+            validate_bulk(&parse(
+                r#"{"operations": [{"op": "create", "name": "a"}], "atomic": true}"#
+            ))
+            .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_mixed_results() {
+        let (_dir, state) = bulk_state();
+        let ops: Vec<BulkOperation> = serde_json::from_str(
+            r#"[
+                {"op": "create", "name": "one"},
+                {"op": "create", "name": "one"},
+                {"op": "stop", "name": "missing"},
+                {"op": "create", "name": "two", "labels": {"team": "api"}},
+                {"op": "delete", "name": "one"}
+            ]"#,
+        )
+        .unwrap();
+
+        let results = run_bulk_sequential(&state, &ops, false).await.unwrap();
+        let manager = state.get_manager().await.unwrap();
+        let outcome: Vec<bool> = results.iter().map(|r| r.success).collect();
+        assert_eq!(outcome, vec![true, false, false, true, true]);
+        assert!(
+            results[1]
+                .error
+                .as_ref()
+                .unwrap()
+                .contains("already exists")
+        );
+        assert!(results[2].error.as_ref().unwrap().contains("not found"));
+
+        // Failures didn't stop later operations
+        assert!(!manager.exists("one"));
+        assert!(manager.exists("two"));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_atomic_rolls_back() {
+        let (_dir, state) = bulk_state();
+        let ops: Vec<BulkOperation> = serde_json::from_str(
+            r#"[
+                {"op": "create", "name": "one"},
+                {"op": "create", "name": "two"},
+                {"op": "create", "name": "one"},
+                {"op": "create", "name": "three"}
+            ]"#,
+        )
+        .unwrap();
+
+        let results = run_bulk_sequential(&state, &ops, true).await.unwrap();
+        let manager = state.get_manager().await.unwrap();
+        assert!(results[0].rolled_back && results[1].rolled_back);
+        assert!(!results[2].success && !results[2].rolled_back);
+        assert!(results[3].error.as_ref().unwrap().starts_with("Skipped"));
+
+        assert!(manager.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_parallel_keeps_per_sandbox_order() {
+        let (_dir, state) = bulk_state();
+        let ops: Vec<BulkOperation> = serde_json::from_str(
+            r#"[
+                {"op": "create", "name": "one"},
+                {"op": "create", "name": "two"},
+                {"op": "delete", "name": "one"},
+                {"op": "delete", "name": "missing"}
+            ]"#,
+        )
+        .unwrap();
+
+        let results = run_bulk_parallel(&state, ops).await;
+        let manager = state.get_manager().await.unwrap();
+        let outcome: Vec<(&str, bool)> = results.iter().map(|r| (r.op, r.success)).collect();
+        assert_eq!(
+            outcome,
+            vec![
+                ("create", true),
+                ("create", true),
+                ("delete", true),
+                ("delete", false)
+            ]
+        );
+        let names: Vec<_> = manager.list().into_iter().map(|(n, _, _)| n).collect();
+        assert_eq!(names, vec!["two"]);
+    }
+
+    // === Pool stats tests ===
+
+    #[test]
+    fn test_pool_stats_before_pool_starts() {
+        let json =
+            serde_json::to_value(ApiResponse::success(PoolStatsResponse::new(None, None))).unwrap();
+        assert_eq!(
+            json["data"],
+            serde_json::json!({"initialized": false, "pool": null, "daemon": null})
+        );
+    }
+
+    #[test]
+    fn test_pool_stats_with_pool_and_daemon() {
+        let pool = PoolStats {
+            warm_count: 3,
+            in_use: 2,
+            cleanup_pending: 1,
+            target_size: 5,
+            max_size: 20,
+        };
+        let mut daemon = PoolStatus {
+            warm: 4,
+            in_use: 1,
+            min_warm: 3,
+            max_warm: 5,
+            boot_latency: HashMap::new(),
+        };
+        let latency = daemon.boot_latency.entry("python".to_string()).or_default();
+        latency.record(120);
+        latency.record(80);
+        let json = serde_json::to_value(PoolStatsResponse::new(Some(pool), Some(daemon))).unwrap();
+        assert_eq!(json["initialized"], true);
+        assert_eq!(
+            json["pool"],
+            serde_json::json!({
+                "warm": 3,
+                "in_use": 2,
+                "min_warm": 5,
+                "max_warm": 20,
+                "cleanup_pending": 1
+            })
+        );
+        assert_eq!(json["daemon"]["warm"], 4);
+        assert_eq!(json["daemon"]["max_warm"], 5);
+        assert_eq!(
+            json["daemon"]["boot_latency"]["python"],
+            serde_json::json!({"count": 2, "avg_ms": 100, "min_ms": 80, "max_ms": 120})
+        );
+    }
+
+    // === Idempotency-Key tests ===
+
+    #[test]
+    fn test_accepts_idempotency_key() {
+        assert!(accepts_idempotency_key(&Method::POST, &["run"]));
+        assert!(accepts_idempotency_key(&Method::POST, &["sandboxes"]));
+        assert!(accepts_idempotency_key(
+            &Method::POST,
+            &["sandboxes", "box", "exec"]
+        ));
+        assert!(!accepts_idempotency_key(&Method::POST, &["run", "stream"]));
+        assert!(!accepts_idempotency_key(&Method::GET, &["sandboxes"]));
+        assert!(!accepts_idempotency_key(
+            &Method::DELETE,
+            &["sandboxes", "box"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_runs_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let store = Arc::new(IdempotencyStore::default());
+        let executions = AtomicUsize::new(0);
+        let run = || async {
+            let n = executions.fetch_add(1, Ordering::SeqCst) + 1;
+            json_response(StatusCode::OK, &ApiResponse::success(format!("run {}", n)))
+        };
+        let send = |key: &'static str, body: &[u8]| {
+            let store = store.clone();
+            let handler = run();
+            let fingerprint = idempotency::fingerprint(body);
+            async move {
+                let resp = with_idempotency(&store, key, fingerprint, handler).await;
+                let replayed = resp.headers().contains_key("idempotent-replayed");
+                let status = resp.status();
+                let body = resp.into_body().collect().await.unwrap().to_bytes();
+                (status, replayed, body)
+            }
+        };
+
+        let first = send("key-a", b"{}").await;
+        let retry = send("key-a", b"{}").await;
+        assert_eq!(executions.load(Ordering::SeqCst), 1);
+        assert_eq!(first.0, retry.0);
+        assert_eq!(first.2, retry.2);
+        assert!(!first.1 && retry.1);
+        assert!(String::from_utf8_lossy(&first.2).contains("run 1"));
+
+        // A different key runs again
+        let other = send("key-b", b"{}").await;
+        assert_eq!(executions.load(Ordering::SeqCst), 2);
+        assert!(!other.1);
+        assert!(String::from_utf8_lossy(&other.2).contains("run 2"));
+
+        // The same key with another body is rejected, not replayed
+        let reused = send("key-a", b"{\"command\": [\"rm\"]}").await;
+        assert_eq!(reused.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(executions.load(Ordering::SeqCst), 2);
+    }
+
     // === default_encoding tests ===
 
     #[test]
@@ -0,0 +1,354 @@
+//! Idempotency keys for the HTTP API.
+//!
+//! A client retrying a request after a timeout sends the same
+//! `Idempotency-Key` header again. The first response for a key is kept
+//! for a TTL and replayed to the retries, so the sandbox isn't created or
+//! the command run a second time. A key reused with a different request
+//! body is rejected instead of replaying an unrelated response.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default time to keep a response for replay (1 hour)
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(3600);
+
+/// Most keys kept at once; the oldest responses are dropped first
+const DEFAULT_MAX_KEYS: usize = 10_000;
+
+/// Most response bytes kept at once; the oldest responses are dropped first
+const DEFAULT_MAX_STORED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Longest key accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// A response kept for replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What to do with a request that carries a key
+#[derive(Debug)]
+pub enum Claim {
+    /// First use of the key: handle the request and `complete` it
+    New(PendingKey),
+    /// Seen before: send this response again
+    Replay(StoredResponse),
+    /// The first request with this key hasn't finished yet
+    InProgress,
+    /// The key was used before with a different request body
+    Mismatch,
+}
+
+enum Entry {
+    InProgress {
+        fingerprint: u64,
+    },
+    Done {
+        fingerprint: u64,
+        response: StoredResponse,
+        finished: Instant,
+    },
+}
+
+impl Entry {
+    fn fingerprint(&self) -> u64 {
+        match self {
+            Entry::InProgress { fingerprint } | Entry::Done { fingerprint, .. } => *fingerprint,
+        }
+    }
+
+    fn stored_bytes(&self) -> usize {
+        match self {
+            Entry::InProgress { .. } => 0,
+            Entry::Done { response, .. } => response.body.len(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Body bytes of all `Done` entries
+    stored_bytes: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.by_key.remove(key)?;
+        self.stored_bytes -= entry.stored_bytes();
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        self.stored_bytes += entry.stored_bytes();
+        if let Some(old) = self.by_key.insert(key, entry) {
+            self.stored_bytes -= old.stored_bytes();
+        }
+    }
+
+    /// Drop the response finished longest ago; false if there is none
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .by_key
+            .iter()
+            .filter_map(|(key, entry)| match entry {
+                Entry::Done { finished, .. } => Some((*finished, key)),
+                Entry::InProgress { .. } => None,
+            })
+            .min()
+            .map(|(_, key)| key.clone());
+        match oldest {
+            Some(key) => self.remove(&key).is_some(),
+            None => false,
+        }
+    }
+}
+
+/// Thread-safe store of responses keyed by idempotency key
+pub struct IdempotencyStore {
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    max_keys: usize,
+    max_stored_bytes: usize,
+}
+
+impl IdempotencyStore {
+    /// Create a store that keeps responses for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_limits(ttl, DEFAULT_MAX_KEYS, DEFAULT_MAX_STORED_BYTES)
+    }
+
+    /// Create a store that keeps at most `max_keys` keys and
+    /// `max_stored_bytes` of response bodies, dropping the oldest responses
+    /// to make room
+    pub fn with_limits(ttl: Duration, max_keys: usize, max_stored_bytes: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            ttl,
+            max_keys,
+            max_stored_bytes,
+        }
+    }
+
+    /// Claim `key` for a request whose body hashes to `fingerprint` (see
+    /// [`fingerprint`]). Callers scope the key (e.g. by API key and
+    /// endpoint) so unrelated requests can't replay each other.
+    pub fn claim(self: &Arc<Self>, key: &str, fingerprint: u64) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        let expired: Vec<String> = entries
+            .by_key
+            .iter()
+            .filter(|(_, entry)| {
+                matches!(entry, Entry::Done { finished, .. } if finished.elapsed() >= self.ttl)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            entries.remove(&key);
+        }
+        match entries.by_key.get(key) {
+            Some(entry) if entry.fingerprint() != fingerprint => Claim::Mismatch,
+            Some(Entry::Done { response, .. }) => Claim::Replay(response.clone()),
+            Some(Entry::InProgress { .. }) => Claim::InProgress,
+            None => {
+                while entries.by_key.len() >= self.max_keys && entries.evict_oldest() {}
+                entries.insert(key.to_string(), Entry::InProgress { fingerprint });
+                Claim::New(PendingKey {
+                    store: Arc::clone(self),
+                    key: Some(key.to_string()),
+                    fingerprint,
+                })
+            }
+        }
+    }
+}
+
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_TTL)
+    }
+}
+
+/// Hash of a request body, to tell a retry from a different request that
+/// reuses its key
+pub fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A claimed key whose request is being handled.
+///
+/// Dropping it without `complete` (e.g. the client disconnected) frees the
+/// key, so a retry runs the request.
+#[derive(Debug)]
+pub struct PendingKey {
+    store: Arc<IdempotencyStore>,
+    key: Option<String>,
+    fingerprint: u64,
+}
+
+impl PendingKey {
+    /// Keep `response` for replay. A response larger than the store's byte
+    /// limit isn't kept, and frees the key like a dropped claim.
+    pub fn complete(mut self, response: StoredResponse) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let store = &self.store;
+        let mut entries = store.entries.lock().unwrap();
+        if response.body.len() > store.max_stored_bytes {
+            entries.remove(&key);
+            return;
+        }
+        let entry = Entry::Done {
+            fingerprint: self.fingerprint,
+            response,
+            finished: Instant::now(),
+        };
+        entries.insert(key, entry);
+        while entries.stored_bytes > store.max_stored_bytes && entries.evict_oldest() {}
+    }
+}
+
+impl Drop for PendingKey {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take()
+            && let Ok(mut entries) = self.store.entries.lock()
+        {
+            entries.remove(&key);
+        }
+    }
+}
+
+impl std::fmt::Debug for IdempotencyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdempotencyStore")
+            .field("ttl", &self.ttl)
+            .field("max_keys", &self.max_keys)
+            .field("max_stored_bytes", &self.max_stored_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Check a client's key: 1 to 255 visible ASCII characters
+pub fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        bail!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LEN);
+    }
+    if !key.bytes().all(|b| b.is_ascii_graphic()) {
+        bail!("Idempotency-Key must be visible ASCII characters");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> StoredResponse {
+        StoredResponse {
+            status: 200,
+            content_type: Some("application/json".to_string()),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_claim_then_replay() {
+        let store = Arc::new(IdempotencyStore::default());
+        let Claim::New(pending) = store.claim("a", 1) else {
+            panic!("first claim should be new");
+        };
+        assert!(matches!(store.claim("a", 1), Claim::InProgress));
+        // Other keys are independent
+        assert!(matches!(store.claim("b", 1), Claim::New(_)));
+
+        pending.complete(response("first"));
+        match store.claim("a", 1) {
+            Claim::Replay(replayed) => assert_eq!(replayed, response("first")),
+            other => panic!("expected a replay, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dropped_claim_frees_key() {
+        let store = Arc::new(IdempotencyStore::default());
+        let claim = store.claim("a", 1);
+        assert!(matches!(claim, Claim::New(_)));
+        drop(claim);
+        assert!(matches!(store.claim("a", 1), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_responses_expire() {
+        let store = Arc::new(IdempotencyStore::new(Duration::ZERO));
+        let Claim::New(pending) = store.claim("a", 1) else {
+            panic!("first claim should be new");
+        };
+        pending.complete(response("first"));
+        assert!(matches!(store.claim("a", 1), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_key_reused_with_other_body() {
+        let store = Arc::new(IdempotencyStore::default());
+        let Claim::New(pending) = store.claim("a", fingerprint(b"{}")) else {
+            panic!("first claim should be new");
+        };
+        assert!(matches!(
+            store.claim("a", fingerprint(b"{\"x\":1}")),
+            Claim::Mismatch
+        ));
+        pending.complete(response("first"));
+        assert!(matches!(
+            store.claim("a", fingerprint(b"{\"x\":1}")),
+            Claim::Mismatch
+        ));
+        assert!(matches!(
+            store.claim("a", fingerprint(b"{}")),
+            Claim::Replay(_)
+        ));
+    }
+
+    #[test]
+    fn test_oldest_responses_are_evicted() {
+        let store = Arc::new(IdempotencyStore::with_limits(
+            DEFAULT_IDEMPOTENCY_TTL,
+            2,
+            10,
+        ));
+        for key in ["a", "b", "c"] {
+            let Claim::New(pending) = store.claim(key, 1) else {
+                panic!("first claim of {} should be new", key);
+            };
+            pending.complete(response("1234"));
+        }
+        // Over the key limit: "a" made room for "c"
+        assert!(matches!(store.claim("a", 1), Claim::New(_)));
+        assert!(matches!(store.claim("c", 1), Claim::Replay(_)));
+
+        // Over the byte limit: the response isn't kept
+        let Claim::New(pending) = store.claim("big", 1) else {
+            panic!("first claim should be new");
+        };
+        pending.complete(response("more than ten bytes"));
+        assert!(matches!(store.claim("big", 1), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("retry-7f3a9c").is_ok());
+        assert!(validate_key(&"k".repeat(255)).is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key(&"k".repeat(256)).is_err());
+        assert!(validate_key("has space").is_err());
+        assert!(validate_key("naïve").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! In-memory job store for asynchronous command execution.
+//!
+//! Used by the HTTP API so clients can submit long-running commands,
+//! disconnect, and poll for the result later. Finished jobs are kept
+//! for a configurable TTL and then pruned.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+
+use crate::backend::ExecResult;
+
+/// Default time to keep finished jobs around (1 hour)
+pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(3600);
+
+/// Lifecycle state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job has finished (successfully or not)
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Running)
+    }
+}
+
+/// Public view of a job
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub status: JobStatus,
+    pub command: Vec<String>,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Job {
+    info: JobInfo,
+    /// Sandbox the job runs in (removed on cancellation)
+    sandbox: Option<String>,
+    abort: Option<AbortHandle>,
+    finished: Option<Instant>,
+    status_tx: watch::Sender<JobStatus>,
+}
+
+/// Thread-safe store of asynchronous jobs keyed by UUID
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    ttl: Duration,
+}
+
+impl JobStore {
+    /// Create a job store that keeps finished jobs for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Submit a job and spawn it in the background. Returns the job ID.
+    ///
+    /// `sandbox` names the sandbox the job runs in, so cancellation can tear it down.
+    pub fn submit<F>(
+        self: &Arc<Self>,
+        command: Vec<String>,
+        sandbox: Option<String>,
+        fut: F,
+    ) -> String
+    where
+        F: Future<Output = Result<ExecResult>> + Send + 'static,
+    {
+        self.prune();
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let (status_tx, _) = watch::channel(JobStatus::Running);
+        let job = Job {
+            info: JobInfo {
+                id: id.clone(),
+                status: JobStatus::Running,
+                command,
+                created_at: chrono::Utc::now().to_rfc3339(),
+                finished_at: None,
+                exit_code: None,
+                output: None,
+                error: None,
+            },
+            sandbox,
+            abort: None,
+            finished: None,
+            status_tx,
+        };
+        self.jobs.lock().unwrap().insert(id.clone(), job);
+
+        let store = Arc::clone(self);
+        let job_id = id.clone();
+        let handle = tokio::spawn(async move {
+            let result = fut.await;
+            store.finish(&job_id, result);
+        });
+
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.abort = Some(handle.abort_handle());
+        }
+
+        id
+    }
+
+    /// Record the result of a job, unless it was already cancelled.
+    ///
+    /// A command that exits non-zero fails the job but keeps its output.
+    fn finish(&self, id: &str, result: Result<ExecResult>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        if job.info.status.is_finished() {
+            return;
+        }
+
+        match result {
+            Ok(result) => {
+                if result.is_success() {
+                    job.info.status = JobStatus::Completed;
+                } else {
+                    job.info.status = JobStatus::Failed;
+                    job.info.error = Some(format!("Command exited with code {}", result.exit_code));
+                }
+                job.info.exit_code = Some(result.exit_code);
+                job.info.output = Some(result.output());
+            }
+            Err(e) => {
+                job.info.status = JobStatus::Failed;
+                job.info.error = Some(e.to_string());
+            }
+        }
+        job.info.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        job.finished = Some(Instant::now());
+        job.abort = None;
+        job.status_tx.send_replace(job.info.status);
+    }
+
+    /// Get a snapshot of a job
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.prune();
+        self.jobs.lock().unwrap().get(id).map(|j| j.info.clone())
+    }
+
+    /// Wait until a job finishes and return its final state
+    pub async fn wait(&self, id: &str) -> Option<JobInfo> {
+        let mut rx = {
+            let jobs = self.jobs.lock().unwrap();
+            jobs.get(id)?.status_tx.subscribe()
+        };
+        // The sender lives in the store; an error means the job was pruned
+        let _ = rx.wait_for(|status| status.is_finished()).await;
+        self.get(id)
+    }
+
+    /// Cancel a running job.
+    ///
+    /// Returns the job's final state and the sandbox that should be removed
+    /// to stop the underlying command. Finished jobs are returned unchanged.
+    pub fn cancel(&self, id: &str) -> Option<(JobInfo, Option<String>)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(id)?;
+        if job.info.status.is_finished() {
+            return Some((job.info.clone(), None));
+        }
+
+        if let Some(abort) = job.abort.take() {
+            abort.abort();
+        }
+        job.info.status = JobStatus::Cancelled;
+        job.info.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        job.finished = Some(Instant::now());
+        job.status_tx.send_replace(JobStatus::Cancelled);
+
+        Some((job.info.clone(), job.sandbox.clone()))
+    }
+
+    /// Drop finished jobs older than the TTL
+    fn prune(&self) {
+        let ttl = self.ttl;
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|_, job| job.finished.is_none_or(|t| t.elapsed() < ttl));
+    }
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOB_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let store = Arc::new(JobStore::default());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+
+        let id = store.submit(vec!["echo".to_string(), "hi".to_string()], None, async {
+            let _ = rx.await;
+            Ok(ExecResult::success("hi\n".to_string()))
+        });
+
+        let info = store.get(&id).unwrap();
+        assert_eq!(info.status, JobStatus::Running);
+        assert!(info.output.is_none());
+
+        tx.send(()).unwrap();
+        let info = store.wait(&id).await.unwrap();
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.output.as_deref(), Some("hi\n"));
+        assert_eq!(info.exit_code, Some(0));
+        assert!(info.finished_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_job_failure() {
+        let store = Arc::new(JobStore::default());
+        let id = store.submit(vec!["false".to_string()], None, async {
+            Ok(ExecResult::failure(3, "boom\n".to_string()))
+        });
+
+        let info = store.wait(&id).await.unwrap();
+        assert_eq!(info.status, JobStatus::Failed);
+        assert_eq!(info.exit_code, Some(3));
+        assert!(info.error.unwrap().contains("code 3"));
+
+        let id = store.submit(vec!["true".to_string()], None, async {
+            anyhow::bail!("Sandbox failed to start")
+        });
+        let info = store.wait(&id).await.unwrap();
+        assert_eq!(info.status, JobStatus::Failed);
+        assert_eq!(info.exit_code, None);
+    }
+
+    #[tokio::test]
+    async fn test_job_cancel() {
+        let store = Arc::new(JobStore::default());
+        let id = store.submit(
+            vec!["sleep".to_string(), "600".to_string()],
+            Some("api-job-test".to_string()),
+            async {
+                tokio::time::sleep(Duration::from_secs(600)).await;
+                Ok(ExecResult::success(String::new()))
+            },
+        );
+
+        let (info, sandbox) = store.cancel(&id).unwrap();
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert_eq!(sandbox.as_deref(), Some("api-job-test"));
+
+        // Cancelling again is a no-op and doesn't hand out the sandbox twice
+        let (info, sandbox) = store.cancel(&id).unwrap();
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert!(sandbox.is_none());
+
+        let info = store.wait(&id).await.unwrap();
+        assert_eq!(info.status, JobStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_job_ttl_prunes_finished() {
+        let store = Arc::new(JobStore::new(Duration::ZERO));
+        let id = store.submit(vec!["true".to_string()], None, async {
+            Ok(ExecResult::success(String::new()))
+        });
+
+        // Wait for completion via the status channel, then the next lookup prunes it
+        let mut rx = store
+            .jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .unwrap()
+            .status_tx
+            .subscribe();
+        let _ = rx.wait_for(|s| s.is_finished()).await;
+        assert!(store.get(&id).is_none());
+    }
+
+    #[test]
+    fn test_unknown_job() {
+        let store = JobStore::default();
+        assert!(store.get("missing").is_none());
+        assert!(store.cancel("missing").is_none());
+    }
+}
@@ -13,6 +13,8 @@ struct Runtime {
     project_files: &'static [&'static str],
     /// Commands that indicate this runtime
     commands: &'static [&'static str],
+    /// Script file extensions that indicate this runtime
+    extensions: &'static [&'static str],
 }
 
 /// All supported runtimes
@@ -30,18 +32,22 @@ const RUNTIMES: &[Runtime] = &[
         commands: &[
             "node", "npm", "npx", "yarn", "pnpm", "bun", "tsx", "ts-node",
         ],
+        // Not "ts": plain node can't run TypeScript without a loader
+        extensions: &["js", "mjs", "cjs"],
     },
     // Rust
     Runtime {
         image: "rust:1.85-alpine",
         project_files: &["Cargo.toml", "Cargo.lock"],
         commands: &["cargo", "rustc", "rustup", "rustfmt", "clippy"],
+        extensions: &[],
     },
     // Go
     Runtime {
         image: "golang:1.23-alpine",
         project_files: &["go.mod", "go.sum"],
         commands: &["go", "gofmt"],
+        extensions: &[],
     },
     // Python
     Runtime {
@@ -58,12 +64,14 @@ const RUNTIMES: &[Runtime] = &[
         commands: &[
             "python", "python3", "pip", "pip3", "poetry", "uv", "pytest", "ruff",
         ],
+        extensions: &["py"],
     },
     // Ruby
     Runtime {
         image: "ruby:3.3-alpine",
         project_files: &["Gemfile", "Gemfile.lock", "*.gemspec"],
         commands: &["ruby", "gem", "bundle", "bundler", "rake", "rails"],
+        extensions: &["rb"],
     },
     // Java
     Runtime {
@@ -75,18 +83,21 @@ const RUNTIMES: &[Runtime] = &[
             "settings.gradle",
         ],
         commands: &["java", "javac", "mvn", "gradle", "gradlew"],
+        extensions: &["java"],
     },
     // Kotlin (JVM) - uses JDK image, most Kotlin projects use Gradle
     Runtime {
         image: "eclipse-temurin:21-alpine",
         project_files: &["*.kt"],
         commands: &[],
+        extensions: &[],
     },
     // .NET / C#
     Runtime {
         image: "mcr.microsoft.com/dotnet/sdk:8.0",
         project_files: &["*.csproj", "*.fsproj", "*.sln", "global.json"],
         commands: &["dotnet", "csc", "fsc"],
+        extensions: &[],
     },
     // C/C++
     Runtime {
@@ -100,36 +111,42 @@ const RUNTIMES: &[Runtime] = &[
             "*.h",
         ],
         commands: &["gcc", "g++", "clang", "clang++", "make", "cmake", "cc"],
+        extensions: &[],
     },
     // PHP
     Runtime {
         image: "php:8.3-alpine",
         project_files: &["composer.json", "composer.lock", "*.php"],
         commands: &["php", "composer"],
+        extensions: &["php"],
     },
     // Elixir
     Runtime {
         image: "elixir:1.16-alpine",
         project_files: &["mix.exs", "mix.lock"],
         commands: &["elixir", "mix", "iex"],
+        extensions: &["exs"],
     },
     // Shell scripts (uses lightweight alpine)
     Runtime {
         image: "alpine:3.20",
         project_files: &["*.sh"],
         commands: &["sh", "bash", "zsh", "ash"],
+        extensions: &["sh", "bash"],
     },
     // Lua
     Runtime {
         image: "nickblah/lua:5.4-alpine",
         project_files: &["*.lua", ".luacheckrc"],
         commands: &["lua", "luajit", "luarocks"],
+        extensions: &["lua"],
     },
     // HCL / Terraform
     Runtime {
         image: "hashicorp/terraform:1.10",
         project_files: &["*.tf", "*.tfvars", "terraform.tfstate"],
         commands: &["terraform"],
+        extensions: &[],
     },
 ];
 
@@ -262,6 +279,61 @@ pub fn detect_from_command(command: &[String]) -> Option<String> {
     None
 }
 
+/// Runtime image for an interpreter name, e.g. `python3.12` or `/usr/bin/node`
+fn image_for_interpreter(interpreter: &str) -> Option<String> {
+    let name = Path::new(interpreter).file_name()?.to_str()?;
+    // Versioned interpreters (python3.12, ruby3.3) match their base command
+    let unversioned = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    RUNTIMES
+        .iter()
+        .find(|r| r.commands.contains(&name) || r.commands.contains(&unversioned))
+        .map(|r| r.image.to_string())
+}
+
+/// Interpreter named by a shebang line (`#!/usr/bin/python3`, `#!/usr/bin/env -S node --flag`)
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?;
+    if Path::new(program).file_name().is_some_and(|n| n == "env") {
+        // Skip env's own options (-S, -i, ...) and VAR=value assignments
+        words.find(|w| !w.starts_with('-') && !w.contains('='))
+    } else {
+        Some(program)
+    }
+}
+
+/// Detect Docker image from a local script given as the command.
+///
+/// Reads the script's shebang, falling back to its file extension
+/// (`.py`, `.js`, `.rb`, ...). Returns `None` if the command isn't a readable file.
+pub fn detect_from_script(command: &[String]) -> Option<String> {
+    use std::io::Read;
+
+    let path = Path::new(command.first()?);
+    if !path.is_file() {
+        return None;
+    }
+
+    // Only the first line matters; don't read a whole large binary
+    let mut head = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(256)
+        .read_to_end(&mut head)
+        .ok()?;
+    let head = String::from_utf8_lossy(&head);
+    let first_line = head.lines().next().unwrap_or_default();
+    if let Some(image) = shebang_interpreter(first_line).and_then(image_for_interpreter) {
+        return Some(image);
+    }
+
+    let ext = path.extension()?.to_str()?;
+    RUNTIMES
+        .iter()
+        .find(|r| r.extensions.contains(&ext))
+        .map(|r| r.image.to_string())
+}
+
 /// Detect Docker image using all available methods
 /// Priority: project files > Procfile > command > default
 pub fn detect_image(command: &[String]) -> String {
@@ -339,6 +411,67 @@ mod tests {
         assert_eq!(detect_from_command(&["unknown-command".to_string()]), None);
     }
 
+    #[test]
+    fn test_detect_ruby_and_java_commands() {
+        for cmd in ["ruby", "gem", "bundle"] {
+            assert_eq!(
+                detect_from_command(&[cmd.to_string()]),
+                Some("ruby:3.3-alpine".to_string()),
+                "{} should map to ruby",
+                cmd
+            );
+        }
+        for cmd in ["java", "javac", "mvn", "gradle"] {
+            assert_eq!(
+                detect_from_command(&[cmd.to_string()]),
+                Some("eclipse-temurin:21-alpine".to_string()),
+                "{} should map to java",
+                cmd
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_ruby_and_java_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Gemfile"),
+            "source 'https://rubygems.org'\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_from_project(dir.path()),
+            Some("ruby:3.3-alpine".to_string())
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("pom.xml"), "<project/>\n").unwrap();
+        assert_eq!(
+            detect_from_project(dir.path()),
+            Some("eclipse-temurin:21-alpine".to_string())
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("build.gradle"), "").unwrap();
+        assert_eq!(
+            detect_from_project(dir.path()),
+            Some("eclipse-temurin:21-alpine".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ruby_and_java_firecracker_runtime() {
+        assert_eq!(
+            docker_image_to_firecracker_runtime("ruby:3.3-alpine"),
+            "ruby"
+        );
+        assert_eq!(
+            docker_image_to_firecracker_runtime("eclipse-temurin:21-alpine"),
+            "java"
+        );
+        assert_eq!(docker_image_to_firecracker_runtime("openjdk:17"), "java");
+    }
+
     #[test]
     fn test_detect_image_fallback() {
         // Unknown command in a directory without project files should return default
@@ -359,6 +492,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_detect_from_script_shebang() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("#!/usr/bin/env python3\nprint(1)\n", "python:3.12-alpine"),
+            ("#!/usr/bin/python3.12 -u\n", "python:3.12-alpine"),
+            ("#!/usr/bin/env -S node --no-warnings\n", "node:22-alpine"),
+            ("#!/usr/local/bin/ruby\n", "ruby:3.3-alpine"),
+            ("#!/bin/bash\nset -e\n", "alpine:3.20"),
+        ];
+        for (i, (content, image)) in cases.iter().enumerate() {
+            // No extension, so only the shebang can identify it
+            let path = dir.path().join(format!("script{}", i));
+            std::fs::write(&path, content).unwrap();
+            let command = vec![path.to_string_lossy().to_string()];
+            assert_eq!(
+                detect_from_script(&command).as_deref(),
+                Some(*image),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_from_script_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("tool.py", Some("python:3.12-alpine")),
+            ("tool.js", Some("node:22-alpine")),
+            ("tool.rb", Some("ruby:3.3-alpine")),
+            ("tool.ts", None),
+            ("tool.unknown", None),
+        ];
+        for (name, image) in cases {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "no shebang here\n").unwrap();
+            let command = vec![path.to_string_lossy().to_string()];
+            assert_eq!(detect_from_script(&command).as_deref(), image, "{}", name);
+        }
+
+        // An unknown interpreter falls back to the extension
+        let path = dir.path().join("odd.py");
+        std::fs::write(&path, "#!/opt/custom/interp\n").unwrap();
+        assert_eq!(
+            detect_from_script(&[path.to_string_lossy().to_string()]).as_deref(),
+            Some("python:3.12-alpine")
+        );
+
+        // Missing files and directories aren't scripts
+        let missing = dir.path().join("missing.py");
+        assert_eq!(
+            detect_from_script(&[missing.to_string_lossy().to_string()]),
+            None
+        );
+        assert_eq!(
+            detect_from_script(&[dir.path().to_string_lossy().to_string()]),
+            None
+        );
+        assert_eq!(detect_from_script(&[]), None);
+    }
+
     #[test]
     fn test_detect_from_procfile() {
         use std::io::Write;
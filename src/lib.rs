@@ -7,11 +7,15 @@ pub mod audit;
 pub mod backend;
 pub mod build;
 pub mod config;
+pub mod cwd_copy;
 pub mod docker_backend;
+pub mod egress;
 pub mod firecracker_client;
 pub mod hyperlight_backend;
 pub mod languages;
+pub mod nested;
 pub mod permissions;
 pub mod rootfs;
 pub mod sandbox_pool;
+pub mod terminal;
 pub mod vsock;
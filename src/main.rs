@@ -5,29 +5,41 @@ mod audit;
 mod backend;
 mod build;
 mod config;
+mod cwd_copy;
 mod daemon;
 mod docker_backend;
+mod doctor;
+mod egress;
 mod firecracker_client;
 mod http_api;
 mod hyperlight_backend;
+mod idempotency;
+mod jobs;
 mod languages;
 mod mcp;
+mod nested;
 mod permissions;
 mod plugin_installer;
+mod policy;
 mod pool;
 mod rootfs;
+mod run_report;
 mod sandbox_pool;
 mod seatbelt;
 mod setup;
+mod terminal;
 mod validation;
+mod version;
 mod vmm;
 mod vsock;
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand};
 use std::path::{Path, PathBuf};
 
+use crate::backend::{EphemeralUnsupported, ExecOptions};
 use crate::config::Config;
+use crate::policy::PolicyScope;
 use crate::setup::{check_installation, run_setup};
 use crate::vmm::VmManager;
 
@@ -38,6 +50,13 @@ use crate::vmm::VmManager;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Don't enforce the [security.commands] policy from agentkernel.toml
+    #[arg(long, global = true)]
+    no_policy: bool,
+    /// Directory for sandbox state, images, the audit log, and the daemon
+    /// socket (default: ~/.local/share/agentkernel; also AGENTKERNEL_DATA_DIR)
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -47,9 +66,20 @@ enum Commands {
         /// Run non-interactively with defaults
         #[arg(short = 'y', long)]
         yes: bool,
+        /// Build rootfs for a specific runtime (repeatable: base, python, node, go, rust, ruby, java)
+        #[arg(long = "runtime")]
+        runtimes: Vec<String>,
     },
     /// Show installation status
     Status,
+    /// Run diagnostics (KVM, Firecracker boot, guest agent, Docker, daemon)
+    Doctor,
+    /// Show version information
+    Version {
+        /// Include build details, backend versions, and installed guest components
+        #[arg(short, long)]
+        verbose: bool,
+    },
     /// Initialize a new agentkernel.toml in the current directory
     Init {
         /// Name of the sandbox (defaults to directory name)
@@ -58,6 +88,15 @@ enum Commands {
         /// Agent type (claude, gemini, codex, opencode)
         #[arg(short, long, default_value = "claude")]
         agent: String,
+        /// Default backend for the sandbox (docker, podman, firecracker, apple, hyperlight)
+        #[arg(long)]
+        backend: Option<String>,
+        /// Security profile to write to [security] (permissive, moderate, restrictive)
+        #[arg(long)]
+        profile: Option<String>,
+        /// Add a [network] section with DNS and egress allowlist examples
+        #[arg(long)]
+        with_network: bool,
     },
     /// Create a new sandbox (microVM)
     Create {
@@ -75,6 +114,37 @@ enum Commands {
         /// Backend to use: docker, podman, firecracker, apple, hyperlight (default: auto-detect)
         #[arg(short = 'B', long)]
         backend: Option<String>,
+        /// Label to attach to the sandbox (repeatable)
+        #[arg(short, long = "label", value_name = "KEY=VALUE")]
+        labels: Vec<String>,
+        /// Use the image and resources of a [presets.<name>] entry in the config
+        #[arg(long)]
+        preset: Option<String>,
+        /// Remove the sandbox automatically after this long (e.g. 30m, 2h, 1d; 0 = never)
+        #[arg(long, value_name = "DURATION")]
+        ttl: Option<String>,
+        /// Restart the sandbox when it exits on its own: never, on-failure, always
+        #[arg(long, value_name = "POLICY")]
+        restart: Option<String>,
+        /// Seed the filesystem from a tarball saved by `checkpoint` (on first start)
+        #[arg(long, value_name = "FILE")]
+        from_checkpoint: Option<PathBuf>,
+        /// Load the image from a `docker save`/OCI tarball instead of pulling it
+        /// (a rootfs .ext4 file for firecracker)
+        #[arg(long, value_name = "FILE")]
+        image_archive: Option<PathBuf>,
+        /// Publish a sandbox port on the host, on 127.0.0.1 unless an IP is given (docker/podman only; repeatable)
+        #[arg(long = "publish", value_name = "[IP:]HOST:SANDBOX")]
+        publish: Vec<String>,
+        /// Start the sandbox once it's created (removed again if it fails to start)
+        #[arg(long)]
+        start: bool,
+        /// Security profile to start with: permissive, moderate, restrictive
+        #[arg(short, long)]
+        profile: Option<String>,
+        /// Start the sandbox and open a shell in it (implies --start)
+        #[arg(long)]
+        attach: bool,
     },
     /// Start a sandbox
     Start {
@@ -87,13 +157,24 @@ enum Commands {
     /// Stop a running sandbox
     Stop {
         /// Name of the sandbox to stop
-        name: String,
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        /// Stop every running sandbox and the VM pool daemon (e.g. before a reboot)
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
     },
     /// Remove a sandbox
     Remove {
         /// Name of the sandbox to remove
         name: String,
     },
+    /// Rename a sandbox
+    Rename {
+        /// Current name of the sandbox
+        old: String,
+        /// New name for the sandbox
+        new: String,
+    },
     /// Attach to a running sandbox (opens interactive shell)
     Attach {
         /// Name of the sandbox to attach to
@@ -104,6 +185,9 @@ enum Commands {
         /// Record session to asciicast v2 file (for replay with asciinema)
         #[arg(long)]
         record: Option<PathBuf>,
+        /// Shell to run (default: [sandbox] shell, else the first of bash, sh, ash)
+        #[arg(long)]
+        shell: Option<String>,
     },
     /// Execute a command in a running sandbox
     Exec {
@@ -112,10 +196,72 @@ enum Commands {
         /// Environment variables to set (KEY=VALUE format, can be repeated)
         #[arg(short, long = "env", value_name = "KEY=VALUE")]
         env: Vec<String>,
+        /// User to run as: uid[:gid], name[:group], or "current" for the host user
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Working directory for the command inside the sandbox (absolute path)
+        #[arg(short, long)]
+        workdir: Option<String>,
+        /// Kill the command if it runs longer than this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+        /// Read a script from stdin and run it with `sh -c` (also: `-` as the command).
+        /// Any command arguments become the script's $1, $2, ... A line `__END__`
+        /// ends the script; the rest of stdin becomes the script's stdin.
+        #[arg(long)]
+        stdin: bool,
+        /// Capture stderr together with stdout, in the order written (like 2>&1)
+        #[arg(long)]
+        combined: bool,
+        /// Keep at most this many bytes of stdout and of stderr (default: all of it)
+        #[arg(long, value_name = "BYTES")]
+        max_output: Option<usize>,
+        /// Start the command in the background and print its id (see `logs --exec`)
+        #[arg(short, long, conflicts_with_all = ["timeout", "combined", "max_output"])]
+        detach: bool,
+        /// List the commands started with --detach
+        #[arg(long, conflicts_with_all = ["command", "detach", "kill"])]
+        list: bool,
+        /// Stop a command started with --detach; the sandbox keeps running
+        #[arg(long, value_name = "ID", conflicts_with_all = ["command", "detach"])]
+        kill: Option<String>,
         /// Command to execute
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         command: Vec<String>,
     },
+    /// Wait for a sandbox's background command (from `run --detach`) to exit
+    ///
+    /// Exits with the command's exit code.
+    Wait {
+        /// Name of the sandbox
+        name: String,
+        /// Give up after this many seconds
+        #[arg(short, long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+    },
+    /// Show the captured output of a sandbox's processes
+    ///
+    /// Docker/Podman: the container log plus any background command's log.
+    /// Firecracker: the most recent serial console and VMM output.
+    Logs {
+        /// Name of the sandbox
+        name: String,
+        /// Only show standard output
+        #[arg(long, conflicts_with = "stderr")]
+        stdout: bool,
+        /// Only show standard error
+        #[arg(long)]
+        stderr: bool,
+        /// Only show the last N lines
+        #[arg(short = 'n', long, value_name = "N")]
+        tail: Option<usize>,
+        /// Prefix each line with its timestamp
+        #[arg(short, long)]
+        timestamps: bool,
+        /// Show the output of a command started with `exec --detach` instead
+        #[arg(long, value_name = "ID", conflicts_with = "timestamps")]
+        exec: Option<String>,
+    },
     /// Copy files to/from a running sandbox
     ///
     /// Examples:
@@ -127,25 +273,79 @@ enum Commands {
         /// Destination path (./local/file or sandbox:/path)
         dest: String,
     },
+    /// Turn network access on or off for a running sandbox
+    ///
+    /// Examples:
+    ///   agentkernel network my-sandbox off
+    Network {
+        /// Name of the sandbox
+        name: String,
+        /// `on` or `off`
+        state: String,
+    },
+    /// Save a running sandbox's filesystem to a tarball
+    ///
+    /// Examples:
+    ///   agentkernel checkpoint my-sandbox state.tar
+    ///   agentkernel create repro --from-checkpoint state.tar
+    Checkpoint {
+        /// Name of the sandbox
+        name: String,
+        /// Tarball to write
+        file: PathBuf,
+    },
     /// List all sandboxes
-    List,
+    List {
+        /// Only show sandboxes matching a filter, e.g. label=project=foo (repeatable)
+        #[arg(short, long, value_name = "FILTER")]
+        filter: Vec<String>,
+    },
+    /// Show details of a sandbox, including why it last exited
+    Info {
+        /// Name of the sandbox
+        name: String,
+        /// Print the backend command that last started it (secrets redacted)
+        #[arg(long)]
+        show_command: bool,
+    },
+    /// Remove leftover sandboxes
+    Prune {
+        /// Remove ephemeral sandboxes left behind by interrupted runs
+        #[arg(long)]
+        ephemeral: bool,
+        /// Only remove sandboxes at least this many seconds old
+        #[arg(long, default_value = "300", value_name = "SECONDS")]
+        older_than: u64,
+    },
     /// Run a command in a temporary sandbox (create, start, exec, stop, remove)
     Run {
         /// Command to execute
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            required_unless_present = "stdin"
+        )]
         command: Vec<String>,
+        /// Read a script from stdin and run it with `sh -c` (also: `-` as the command).
+        /// Any command arguments become the script's $1, $2, ... A line `__END__`
+        /// ends the script; the rest of stdin becomes the script's stdin.
+        #[arg(long)]
+        stdin: bool,
         /// Path to agentkernel.toml config file
         #[arg(short, long)]
         config: Option<PathBuf>,
         /// Keep the sandbox after execution (don't remove)
         #[arg(short, long)]
         keep: bool,
+        /// Run the command in the background and return immediately (implies --keep)
+        #[arg(short, long)]
+        detach: bool,
         /// Docker image to use (overrides config)
         #[arg(short, long)]
         image: Option<String>,
         /// Security profile: permissive, moderate (default), restrictive
-        #[arg(short, long, default_value = "moderate")]
-        profile: String,
+        #[arg(short, long)]
+        profile: Option<String>,
         /// Disable network access
         #[arg(long)]
         no_network: bool,
@@ -155,6 +355,35 @@ enum Commands {
         /// Backend to use: docker, podman, firecracker, apple, hyperlight (default: auto-detect)
         #[arg(short = 'B', long)]
         backend: Option<String>,
+        /// User to run as: uid[:gid], name[:group], or "current" for the host user
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Start the VM pool daemon in the background if it isn't running
+        /// (also AGENTKERNEL_AUTO_DAEMON=1)
+        #[arg(long)]
+        auto_daemon: bool,
+        /// Working directory for the command inside the sandbox (absolute path)
+        #[arg(short, long)]
+        workdir: Option<String>,
+        /// Apply a [presets.<name>] entry from the config (flags still win)
+        #[arg(long)]
+        preset: Option<String>,
+        /// With --keep or --detach, remove the sandbox after this long (e.g. 30m, 2h; 0 = never)
+        #[arg(long, value_name = "DURATION")]
+        ttl: Option<String>,
+        /// Publish a sandbox port on the host, on 127.0.0.1 unless an IP is given (docker/podman only; repeatable)
+        #[arg(long = "publish", value_name = "[IP:]HOST:SANDBOX")]
+        publish: Vec<String>,
+        /// Number of vCPUs (overrides the preset and [resources])
+        #[arg(long)]
+        vcpus: Option<u32>,
+        /// Memory in MB (overrides the preset and [resources])
+        #[arg(long, value_name = "MB")]
+        memory: Option<u64>,
+        /// Output format: text (default), or json for one object with the
+        /// exit code, stdout, stderr, sandbox, duration, image, and backend
+        #[arg(long, value_name = "FORMAT")]
+        output: Option<String>,
     },
     /// Start MCP server for Claude Code integration (JSON-RPC over stdio)
     McpServer,
@@ -179,6 +408,11 @@ enum Commands {
         #[command(subcommand)]
         action: DaemonAction,
     },
+    /// Inspect the security policy in agentkernel.toml
+    Policy {
+        #[command(subcommand)]
+        action: PolicyCommand,
+    },
     /// View audit log
     Audit {
         /// Show only events for this sandbox
@@ -227,6 +461,22 @@ enum PluginAction {
     List,
 }
 
+#[derive(Subcommand)]
+enum PolicyCommand {
+    /// Evaluate a request against the policy without enforcing it
+    Test {
+        /// Action to evaluate: run (command) or connect (network domain)
+        #[arg(long)]
+        action: String,
+        /// Command or domain the action applies to
+        #[arg(long)]
+        resource: String,
+        /// Policy to evaluate (default: ./agentkernel.toml)
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 enum DaemonAction {
     /// Start the daemon in foreground
@@ -234,6 +484,9 @@ enum DaemonAction {
         /// Run in background (daemonize)
         #[arg(short, long)]
         background: bool,
+        /// Snapshot warm VMs on shutdown and restore them on the next start
+        #[arg(long)]
+        persist: bool,
     },
     /// Stop the running daemon
     Stop,
@@ -245,9 +498,48 @@ enum DaemonAction {
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Backends and child processes (e.g. the daemon) read the override from
+    // the environment
+    if let Some(ref dir) = cli.data_dir {
+        let dir = std::path::absolute(dir)
+            .with_context(|| format!("Invalid data directory: {}", dir.display()))?;
+        // SAFETY: no other threads read or write the environment yet
+        unsafe { std::env::set_var(setup::DATA_DIR_ENV, &dir) };
+    }
+
+    // The command policy follows an explicit --config, and --no-policy wins
+    let policy_scope = match &cli.command {
+        _ if cli.no_policy => PolicyScope::Disabled,
+        Commands::Run {
+            config: Some(path), ..
+        } => PolicyScope::File(path.clone()),
+        _ => PolicyScope::WorkingDir,
+    };
+    PolicyScope::set_global(policy_scope);
+
+    // So do the audit settings, before anything is logged, and the guest
+    // agent version check
+    let config_path = match &cli.command {
+        Commands::Create {
+            config: Some(path), ..
+        }
+        | Commands::Run {
+            config: Some(path), ..
+        }
+        | Commands::Test {
+            config: Some(path), ..
+        } => {
+            audit::set_config_path(path.clone());
+            path.clone()
+        }
+        _ => PathBuf::from("agentkernel.toml"),
+    };
+    let firecracker = config::FirecrackerConfig::from_file(&config_path);
+    vsock::set_strict_agent_version(firecracker.strict_agent_version.unwrap_or(false));
+
     match cli.command {
-        Commands::Setup { yes } => {
-            run_setup(yes).await?;
+        Commands::Setup { yes, runtimes } => {
+            run_setup(yes, &runtimes).await?;
         }
         Commands::Status => {
             let status = check_installation();
@@ -259,7 +551,26 @@ async fn main() -> Result<()> {
                 println!("\nRun 'agentkernel setup' to complete installation.");
             }
         }
-        Commands::Init { name, agent } => {
+        Commands::Doctor => {
+            let checks = doctor::run_checks().await;
+            if doctor::print_report(&checks) == doctor::Verdict::Unhealthy {
+                std::process::exit(1);
+            }
+        }
+        Commands::Version { verbose } => {
+            if verbose {
+                print!("{}", version::verbose(&setup::default_data_dir()));
+            } else {
+                println!("{}", version::short());
+            }
+        }
+        Commands::Init {
+            name,
+            agent,
+            backend,
+            profile,
+            with_network,
+        } => {
             let current_dir = std::env::current_dir()?;
             let sandbox_name = name.unwrap_or_else(|| {
                 current_dir
@@ -273,23 +584,31 @@ async fn main() -> Result<()> {
                 bail!("agentkernel.toml already exists in this directory");
             }
 
-            let config_content = format!(
-                r#"# Agentkernel configuration
-# See: https://github.com/thrashr888/agentkernel
-
-[sandbox]
-name = "{}"
-runtime = "base"    # base, python, node, go, rust
-
-[agent]
-preferred = "{}"    # claude, gemini, codex, opencode
-
-[resources]
-vcpus = 1
-memory_mb = 512
-"#,
-                sandbox_name, agent
-            );
+            if let Some(ref b) = backend {
+                b.parse::<crate::backend::BackendType>()
+                    .map_err(|e| anyhow::anyhow!(e))?;
+            }
+            let profile = profile
+                .map(|p| {
+                    permissions::SecurityProfile::from_str(&p).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Invalid profile '{}'. Use: permissive, moderate, restrictive",
+                            p
+                        )
+                    })
+                })
+                .transpose()?;
+
+            let detected_image = languages::detect_from_project(&current_dir);
+            let config_content = config::InitTemplate {
+                name: sandbox_name.clone(),
+                agent,
+                detected_image,
+                backend,
+                profile,
+                with_network,
+            }
+            .render();
 
             std::fs::write(&config_path, config_content)?;
             println!("Created agentkernel.toml for sandbox '{}'", sandbox_name);
@@ -304,9 +623,62 @@ memory_mb = 512
             config,
             dir: _,
             backend,
+            labels,
+            preset,
+            ttl,
+            restart,
+            from_checkpoint,
+            image_archive,
+            publish,
+            start,
+            profile,
+            attach,
         } => {
             // Validate sandbox name first (security: prevents command injection)
             validation::validate_sandbox_name(&name)?;
+            let start = start || attach;
+            if profile.is_some() && !start {
+                bail!("--profile applies when starting; add --start or --attach");
+            }
+            let profile = profile
+                .map(|p| {
+                    permissions::SecurityProfile::from_str(&p).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Invalid profile '{}'. Use: permissive, moderate, restrictive",
+                            p
+                        )
+                    })
+                })
+                .transpose()?;
+            let shell = if attach {
+                load_local_config(config.as_deref()).and_then(|c| c.sandbox.shell)
+            } else {
+                None
+            };
+            if let Some(ref shell) = shell {
+                crate::backend::validate_shell(shell)?;
+            }
+            if let Some(ref path) = from_checkpoint
+                && !path.is_file()
+            {
+                bail!("Checkpoint not found: {}", path.display());
+            }
+            if let Some(ref path) = image_archive
+                && !path.is_file()
+            {
+                bail!("Image archive not found: {}", path.display());
+            }
+            let labels = labels
+                .iter()
+                .map(|l| vmm::parse_label(l))
+                .collect::<Result<std::collections::HashMap<_, _>>>()?;
+            let ttl = ttl.as_deref().map(vmm::parse_ttl).transpose()?.flatten();
+            let restart = restart
+                .as_deref()
+                .map(|r| r.parse::<crate::backend::RestartPolicy>())
+                .transpose()
+                .map_err(|e| anyhow::anyhow!(e))?;
+            let ports = parse_ports(&publish)?;
 
             // Check setup status first
             let status = check_installation();
@@ -331,8 +703,12 @@ memory_mb = 512
             for warning in cfg.validate() {
                 eprintln!("Warning: {}", warning);
             }
+            for warning in agents::preferred_agent_warnings(&cfg.agent.preferred) {
+                eprintln!("Warning: {}", warning);
+            }
 
-            // Parse backend option if provided
+            // Parse backend option if provided (--backend wins over the config)
+            let backend = backend.or_else(|| cfg.sandbox.backend.clone());
             let backend_type = if let Some(ref b) = backend {
                 Some(
                     b.parse::<crate::backend::BackendType>()
@@ -342,35 +718,108 @@ memory_mb = 512
                 None
             };
             let mut manager = VmManager::with_backend(backend_type)?;
+            if !ports.is_empty() {
+                check_can_publish(manager.backend())?;
+            }
 
-            // Build from Dockerfile if configured, otherwise use base image
-            let docker_image = if let Some(ref base_dir) = config_base_dir {
-                let base_image = cfg.docker_image();
-                build::build_or_use_image(&name, &base_image, base_dir, &cfg)?
-            } else {
-                cfg.docker_image()
+            // A preset supplies the image and resources; otherwise the config does
+            let preset = match preset {
+                Some(ref preset_name) => {
+                    let (_, resolved) = load_preset(config.as_deref(), preset_name)?;
+                    Some(resolved)
+                }
+                None => None,
+            };
+            let (vcpus, memory_mb) = match preset {
+                Some(ref p) => (p.vcpus, p.memory_mb),
+                None => (cfg.resources.vcpus, cfg.resources.memory_mb),
             };
+            validation::validate_resources(vcpus, memory_mb)?;
 
-            println!(
-                "Creating sandbox '{}' with image '{}'...",
-                name, docker_image
-            );
-            println!("  vCPUs: {}", cfg.resources.vcpus);
-            println!("  Memory: {} MB", cfg.resources.memory_mb);
+            let setup_script =
+                cfg.setup_script(config_base_dir.as_deref().unwrap_or(Path::new(".")))?;
 
-            manager
-                .create(
-                    &name,
-                    &docker_image,
-                    cfg.resources.vcpus,
-                    cfg.resources.memory_mb,
-                )
-                .await?;
+            if let Some(ref archive) = image_archive {
+                // The archive replaces the preset, config, or Dockerfile image
+                println!(
+                    "Creating sandbox '{}' from image archive '{}'...",
+                    name,
+                    archive.display()
+                );
+                let image = manager
+                    .create_from_archive(&name, archive, vcpus, memory_mb)
+                    .await?;
+                println!("  Image: {}", image);
+            } else {
+                // Build from Dockerfile if configured, otherwise use base image
+                let docker_image = if let Some(ref p) = preset {
+                    p.image.clone()
+                } else if let Some(ref base_dir) = config_base_dir {
+                    let base_image = cfg.docker_image();
+                    build::build_or_use_image(&name, &base_image, base_dir, &cfg)?
+                } else {
+                    cfg.docker_image()
+                };
+
+                println!(
+                    "Creating sandbox '{}' with image '{}'...",
+                    name, docker_image
+                );
+                manager
+                    .create(&name, &docker_image, vcpus, memory_mb)
+                    .await?;
+            }
+            println!("  vCPUs: {}", vcpus);
+            println!("  Memory: {} MB", memory_mb);
+            if !labels.is_empty() {
+                manager.set_labels(&name, labels)?;
+            }
+            if ttl.is_some() {
+                manager.set_ttl(&name, ttl)?;
+            }
+            let restart = restart.unwrap_or(cfg.sandbox.restart);
+            if restart != crate::backend::RestartPolicy::Never {
+                manager.set_restart_policy(&name, restart)?;
+            }
+            if let Some(ref script) = setup_script {
+                manager.set_setup_script(&name, script)?;
+                println!("  Setup script: runs now");
+            }
+            if let Some(ref path) = from_checkpoint {
+                manager.set_checkpoint(&name, path)?;
+                println!("  Checkpoint: restored on first start");
+            }
+            if !ports.is_empty() {
+                println!("  Ports: {}", format_ports(&ports));
+                manager.set_ports(&name, ports)?;
+            }
 
             println!("\nSandbox '{}' created.", name);
-            println!("\nNext steps:");
-            println!("  agentkernel start {}", name);
-            println!("  agentkernel attach {}", name);
+            // Provisioning is part of creating: the sandbox starts to run the
+            // setup script, and is removed again if it fails
+            if !start && setup_script.is_none() {
+                println!("\nNext steps:");
+                println!("  agentkernel start {}", name);
+                println!("  agentkernel attach {}", name);
+                return Ok(());
+            }
+
+            // Same permissions as `agentkernel start` unless a profile was given
+            let perms = profile.map(|p| p.permissions()).unwrap_or_default();
+            println!("Starting sandbox '{}'...", name);
+            manager.start_created(&name, &perms).await?;
+            println!("Sandbox '{}' started.", name);
+            if !attach {
+                println!("\nTo attach: agentkernel attach {}", name);
+                return Ok(());
+            }
+
+            let exit_code = manager
+                .attach_with_env(&name, shell.as_deref(), &[])
+                .await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
         }
         Commands::Start { name, backend } => {
             validation::validate_sandbox_name(&name)?;
@@ -404,7 +853,47 @@ memory_mb = 512
             println!("Sandbox '{}' started.", name);
             println!("\nTo attach: agentkernel attach {}", name);
         }
-        Commands::Stop { name } => {
+        Commands::Stop { all: true, .. } => {
+            let mut manager = VmManager::new()?;
+            let results = manager.stop_all().await;
+            let mut failed = 0;
+            for (name, result) in &results {
+                match result {
+                    Ok(_) => println!("Stopped {}", name),
+                    Err(e) => {
+                        eprintln!("Failed to stop {}: {}", name, e);
+                        failed += 1;
+                    }
+                }
+            }
+            // Report a daemon failure alongside the sandboxes, not instead of them
+            let daemon_failed = match VmManager::stop_daemon_pool().await {
+                Ok(true) => {
+                    println!("Stopped the VM pool daemon");
+                    false
+                }
+                Ok(false) => false,
+                Err(e) => {
+                    eprintln!("Failed to stop the VM pool daemon: {}", e);
+                    true
+                }
+            };
+            if results.is_empty() {
+                println!("No running sandboxes.");
+            } else {
+                println!("\nStopped {} sandbox(es).", results.len() - failed);
+            }
+            match (failed, daemon_failed) {
+                (0, false) => {}
+                (0, true) => bail!("Failed to stop the VM pool daemon"),
+                (n, false) => bail!("Failed to stop {} sandbox(es)", n),
+                (n, true) => bail!("Failed to stop {} sandbox(es) and the VM pool daemon", n),
+            }
+        }
+        Commands::Stop { name, .. } => {
+            let Some(name) = name else {
+                bail!("Give a sandbox name, or --all to stop every sandbox");
+            };
             validation::validate_sandbox_name(&name)?;
 
             let mut manager = VmManager::new()?;
@@ -414,7 +903,9 @@ memory_mb = 512
             }
 
             println!("Stopping sandbox '{}'...", name);
-            manager.stop(&name).await?;
+            if let Some(reason) = manager.stop(&name).await? {
+                println!("Sandbox '{}' had already exited: {}", name, reason);
+            }
             println!("Sandbox '{}' stopped.", name);
         }
         Commands::Remove { name } => {
@@ -425,8 +916,25 @@ memory_mb = 512
             manager.remove(&name).await?;
             println!("Sandbox '{}' removed.", name);
         }
-        Commands::Attach { name, env, record } => {
+        Commands::Rename { old, new } => {
+            validation::validate_sandbox_name(&old)?;
+            validation::validate_sandbox_name(&new)?;
+
+            let mut manager = VmManager::new()?;
+            manager.rename(&old, &new)?;
+            println!("Sandbox '{}' renamed to '{}'.", old, new);
+        }
+        Commands::Attach {
+            name,
+            env,
+            record,
+            shell,
+        } => {
             validation::validate_sandbox_name(&name)?;
+            let shell = shell.or_else(|| load_local_config(None)?.sandbox.shell);
+            if let Some(ref shell) = shell {
+                crate::backend::validate_shell(shell)?;
+            }
 
             let mut manager = VmManager::new()?;
 
@@ -468,7 +976,9 @@ memory_mb = 512
             }
 
             // Attach to the sandbox's shell with environment variables
-            let exit_code = manager.attach_with_env(&name, &env).await?;
+            let exit_code = manager
+                .attach_with_env(&name, shell.as_deref(), &env)
+                .await?;
 
             // Convert script typescript to asciicast format
             if let (Some(tmp), Some(cast_path)) = (&script_tmp, &record_path) {
@@ -499,12 +1009,131 @@ memory_mb = 512
                 std::process::exit(exit_code);
             }
         }
-        Commands::Exec { name, env, command } => {
+        Commands::Exec {
+            name,
+            env,
+            user,
+            workdir,
+            timeout,
+            stdin,
+            combined,
+            max_output,
+            detach,
+            list,
+            kill,
+            command,
+        } => {
             validation::validate_sandbox_name(&name)?;
 
+            if list || kill.is_some() {
+                let mut manager = VmManager::new()?;
+                if !manager.exists(&name) {
+                    bail!("Sandbox '{}' not found", name);
+                }
+                if let Some(id) = kill {
+                    manager.kill_background(&name, &id).await?;
+                    println!("Stopped background command {}", id);
+                    return Ok(());
+                }
+                let execs = manager.background_execs(&name).await?;
+                if execs.is_empty() {
+                    println!("No background commands in '{}'", name);
+                    return Ok(());
+                }
+                println!("{:<14} {:<12} COMMAND", "ID", "STATE");
+                for exec in execs {
+                    println!(
+                        "{:<14} {:<12} {}",
+                        exec.id,
+                        exec.state.to_string(),
+                        exec.command
+                    );
+                }
+                return Ok(());
+            }
+
+            let command = command_from_stdin(command, stdin)?;
             if command.is_empty() {
                 bail!("No command specified. Usage: agentkernel exec <name> <command...>");
             }
+            if timeout == Some(0) {
+                bail!("--timeout must be at least 1 second");
+            }
+            if max_output == Some(0) {
+                bail!("--max-output must be at least 1 byte");
+            }
+            if let Some(ref user) = user {
+                permissions::validate_user(user)?;
+            }
+            if let Some(ref dir) = workdir {
+                validation::validate_workdir(dir)?;
+            }
+
+            let mut manager = VmManager::new()?;
+
+            if !manager.exists(&name) {
+                bail!("Sandbox '{}' not found", name);
+            }
+
+            let user = user.as_deref().map(permissions::resolve_user);
+            let max_output =
+                max_output.or_else(|| load_local_config(None)?.sandbox.max_output_bytes);
+            let opts = ExecOptions::with_env(&env)
+                .workdir(workdir.as_deref())
+                .user(user.as_deref())
+                .timeout(timeout.map(std::time::Duration::from_secs))
+                .combined(combined)
+                .max_output(max_output);
+            if detach {
+                let id = manager.exec_background(&name, &command, &opts).await?;
+                println!("{}", id);
+                eprintln!("Running in background.");
+                eprintln!("  View logs:  agentkernel logs {} --exec {}", name, id);
+                eprintln!("  List:       agentkernel exec {} --list", name);
+                eprintln!("  Stop:       agentkernel exec {} --kill {}", name, id);
+                return Ok(());
+            }
+            // Exit with the command's own code, so `exec box -- test -f x && ...` works.
+            // Output is passed through as bytes, so binary output can be piped
+            let result = manager.exec_bytes(&name, &command, &opts).await?;
+            {
+                use std::io::Write;
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&result.stdout)?;
+                stdout.flush()?;
+                std::io::stderr().write_all(&result.stderr)?;
+            }
+            if result.truncated {
+                eprintln!("Warning: output was truncated");
+            }
+            if result.exit_code != 0 {
+                std::process::exit(result.exit_code);
+            }
+        }
+        Commands::Wait { name, timeout } => {
+            validation::validate_sandbox_name(&name)?;
+
+            let mut manager = VmManager::new()?;
+
+            if !manager.exists(&name) {
+                bail!("Sandbox '{}' not found", name);
+            }
+
+            let exit_code = manager
+                .wait(&name, timeout.map(std::time::Duration::from_secs))
+                .await?;
+            println!("{}", exit_code);
+            std::process::exit(exit_code);
+        }
+        Commands::Logs {
+            name,
+            stdout,
+            stderr,
+            tail,
+            timestamps,
+            exec,
+        } => {
+            validation::validate_sandbox_name(&name)?;
 
             let mut manager = VmManager::new()?;
 
@@ -512,8 +1141,54 @@ memory_mb = 512
                 bail!("Sandbox '{}' not found", name);
             }
 
-            let output = manager.exec_cmd_with_env(&name, &command, &env).await?;
-            print!("{}", output);
+            // The backend can only cut to the last N lines before any filtering
+            let fetch_tail = tail.filter(|_| !stdout && !stderr);
+            let mut lines = match exec {
+                Some(id) => manager.background_logs(&name, &id, fetch_tail).await?,
+                None => manager.logs(&name, fetch_tail).await?,
+            };
+            if stdout {
+                lines.retain(|l| l.stream == crate::backend::LogStream::Stdout);
+            } else if stderr {
+                lines.retain(|l| l.stream == crate::backend::LogStream::Stderr);
+            }
+            if let Some(n) = tail {
+                lines.drain(..lines.len().saturating_sub(n));
+            }
+            for line in lines {
+                match (&line.timestamp, timestamps) {
+                    (Some(ts), true) => println!("{} {}", ts, line.line),
+                    _ => println!("{}", line.line),
+                }
+            }
+        }
+        Commands::Network { name, state } => {
+            validation::validate_sandbox_name(&name)?;
+            let enabled = match state.as_str() {
+                "on" => true,
+                "off" => false,
+                other => bail!("Invalid network state '{}'. Use: on, off", other),
+            };
+            let mut manager = VmManager::new()?;
+            if !manager.exists(&name) {
+                bail!("Sandbox '{}' not found", name);
+            }
+            manager.set_network(&name, enabled).await?;
+            println!("Network {} for '{}'", state, name);
+        }
+        Commands::Checkpoint { name, file } => {
+            validation::validate_sandbox_name(&name)?;
+            let mut manager = VmManager::new()?;
+            if !manager.exists(&name) {
+                bail!("Sandbox '{}' not found", name);
+            }
+            let size = manager.checkpoint(&name, &file).await?;
+            println!(
+                "Saved checkpoint of '{}' to {} ({} bytes)",
+                name,
+                file.display(),
+                size
+            );
         }
         Commands::Cp { source, dest } => {
             // Parse source and destination to determine direction
@@ -574,11 +1249,17 @@ memory_mb = 512
                 }
             }
         }
-        Commands::List => {
+        Commands::List { filter } => {
+            let filters = filter
+                .iter()
+                .map(|f| vmm::LabelFilter::from_filter(f))
+                .collect::<Result<Vec<_>>>()?;
             let manager = VmManager::new()?;
-            let vms = manager.list();
+            let vms = manager.list_filtered(&filters);
 
-            if vms.is_empty() {
+            if vms.is_empty() && !filters.is_empty() {
+                println!("No sandboxes match the filter.");
+            } else if vms.is_empty() {
                 println!("No sandboxes found.");
                 println!("\nCreate one with: agentkernel create <name>");
             } else {
@@ -592,41 +1273,300 @@ memory_mb = 512
                 }
             }
         }
+        Commands::Info { name, show_command } => {
+            validation::validate_sandbox_name(&name)?;
+
+            let manager = VmManager::new()?;
+            let state = manager
+                .get_state(&name)
+                .ok_or_else(|| anyhow::anyhow!("Sandbox '{}' not found", name))?;
+
+            let status = if manager.is_running(&name) {
+                "running"
+            } else {
+                "stopped"
+            };
+            let backend = state
+                .backend
+                .map(|b| b.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+
+            println!("Name:      {}", state.name);
+            println!("Status:    {}", status);
+            println!("Backend:   {}", backend);
+            println!("Image:     {}", state.image);
+            if let Some(ref digest) = state.image_digest {
+                println!("Digest:    {}", digest);
+            }
+            println!("vCPUs:     {}", state.vcpus);
+            println!("Memory:    {} MB", state.memory_mb);
+            if status == "running" {
+                match manager.disk_usage(&name).await {
+                    Ok(bytes) => println!("Disk:      {}", format_disk_usage(bytes)),
+                    Err(_) => println!("Disk:      -"),
+                }
+                if let Ok(Some(ip)) = manager.ip_address(&name).await {
+                    println!("IP:        {}", ip);
+                }
+            }
+            println!("Created:   {}", state.created_at);
+            if let Some(ref expires) = state.expires_at {
+                println!("Expires:   {}", expires);
+            }
+            if state.restart != crate::backend::RestartPolicy::Never {
+                println!(
+                    "Restart:   {} ({} restarts)",
+                    state.restart, state.restart_count
+                );
+            }
+            if !state.labels.is_empty() {
+                let mut labels: Vec<_> = state.labels.iter().collect();
+                labels.sort();
+                let labels: Vec<String> =
+                    labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                println!("Labels:    {}", labels.join(", "));
+            }
+            if !state.ports.is_empty() {
+                println!("Ports:     {}", format_ports(&state.ports));
+            }
+            if let Some(ms) = state.last_boot_ms {
+                println!("Boot time: {} ms", ms);
+            }
+            match &state.last_exit {
+                Some(reason) => {
+                    println!("Last exit: {}", reason);
+                    if let Some(ref at) = reason.finished_at {
+                        println!("           at {}", at);
+                    }
+                }
+                None => println!("Last exit: -"),
+            }
+            if show_command {
+                match state.launch_command {
+                    Some(ref command) => println!("Command:   {}", command),
+                    None => println!("Command:   -"),
+                }
+            }
+        }
+        Commands::Prune {
+            ephemeral,
+            older_than,
+        } => {
+            if !ephemeral {
+                bail!("Nothing to prune. Use --ephemeral to remove leftover ephemeral sandboxes");
+            }
+
+            let mut manager = VmManager::new()?;
+            let removed = manager.reap_ephemeral(std::time::Duration::from_secs(older_than));
+            if removed.is_empty() {
+                println!("No leftover ephemeral sandboxes found.");
+            } else {
+                for name in &removed {
+                    println!("Removed {}", name);
+                }
+                println!("\nPruned {} ephemeral sandbox(es).", removed.len());
+            }
+        }
         Commands::Run {
             command,
+            stdin,
             config,
             keep,
+            detach,
             image,
             profile,
             no_network,
             fast,
             backend,
+            user,
+            workdir,
+            auto_daemon,
+            preset,
+            ttl,
+            publish,
+            vcpus,
+            memory,
+            output,
         } => {
+            let started = std::time::Instant::now();
+            let command = command_from_stdin(command, stdin)?;
             if command.is_empty() {
                 bail!("No command specified. Usage: agentkernel run [OPTIONS] <command...>");
             }
+            if let Some(ref user) = user {
+                permissions::validate_user(user)?;
+            }
+            if let Some(ref dir) = workdir {
+                validation::validate_workdir(dir)?;
+            }
+
+            // A detached sandbox has to outlive this process
+            let keep = keep || detach;
+            let ttl = ttl.as_deref().map(vmm::parse_ttl).transpose()?.flatten();
+            if ttl.is_some() && !keep {
+                bail!("--ttl only applies to sandboxes kept with --keep or --detach");
+            }
+            let ports = parse_ports(&publish)?;
+            let resource_flags = vcpus.is_some() || memory.is_some();
+            let json = output
+                .as_deref()
+                .map(str::parse::<run_report::OutputFormat>)
+                .transpose()?
+                .unwrap_or_default()
+                == run_report::OutputFormat::Json;
+            if json && detach {
+                bail!("Cannot use --output json with --detach (the command is still running)");
+            }
+
+            // --backend wins over [sandbox] backend
+            let backend = backend.or_else(|| config_backend(config.as_deref()));
+
+            // Resolve the preset with explicit flags taking precedence
+            let profile = profile
+                .as_deref()
+                .and_then(permissions::SecurityProfile::from_str);
+            let preset = match preset {
+                Some(ref preset_name) => {
+                    let (cfg, mut resolved) = load_preset(config.as_deref(), preset_name)?;
+                    let flags = config::PresetFlags {
+                        image: image.clone(),
+                        profile,
+                        no_network,
+                        user: user.clone(),
+                    };
+                    resolved.apply_flags(&flags, &cfg.presets[preset_name]);
+                    Some(resolved)
+                }
+                None => None,
+            };
+
+            // --vcpus/--memory win over the preset, then [resources]
+            let local_config = load_local_config(config.as_deref());
+            // Only explicit sizes may loosen the profile's limits
+            let explicit_size = resource_flags || preset.is_some();
+            let restricts_egress = local_config
+                .as_ref()
+                .is_some_and(|cfg| cfg.network.egress().is_some())
+                || preset
+                    .as_ref()
+                    .is_some_and(|p| p.permissions.egress.is_some());
+            let local_config_sized = local_config.is_some();
+            let (vcpus, memory_mb) = local_config
+                .map(|cfg| cfg.resources)
+                .unwrap_or_default()
+                .resolve(vcpus, memory, preset.as_ref());
+            validation::validate_resources(vcpus, memory_mb)?;
 
             // Fast path: use container pool for ephemeral runs
             if fast {
+                if detach {
+                    bail!("Cannot use --fast with --detach (pooled containers are ephemeral)");
+                }
                 if keep {
                     bail!("Cannot use --fast with --keep (pooled containers are ephemeral)");
                 }
-                if image.is_some() || config.is_some() {
+                if user.is_some() {
+                    bail!(
+                        "Cannot use --fast with --user (pooled containers run as the image default)"
+                    );
+                }
+                if workdir.is_some() {
+                    bail!(
+                        "Cannot use --fast with --workdir (pooled commands use the image default)"
+                    );
+                }
+                if !ports.is_empty() {
+                    bail!("Cannot use --fast with --publish (pooled containers have no ports)");
+                }
+                if resource_flags {
+                    bail!(
+                        "Cannot use --fast with --vcpus or --memory (pooled containers have fixed resources)"
+                    );
+                }
+                // Running without the firewall would silently drop the allowlist
+                if restricts_egress {
+                    bail!(
+                        "Cannot use --fast with an egress allowlist in [network] (pooled containers aren't firewalled)"
+                    );
+                }
+                if image.is_some() || config.is_some() || preset.is_some() {
                     eprintln!(
-                        "Warning: --image and --config are ignored with --fast (pool uses alpine:3.20)"
+                        "Warning: --image, --config and --preset are ignored with --fast (pool uses {})",
+                        vmm::POOL_IMAGE
                     );
                 }
 
-                let output = VmManager::run_pooled(&command).await?;
+                if json {
+                    let result = VmManager::run_pooled_full(&command, None).await?;
+                    let backend = docker_backend::detect_container_runtime()
+                        .map_or("docker", |runtime| runtime.cmd());
+                    return run_report::RunReport::new(
+                        result.exit_code,
+                        result.stdout,
+                        result.stderr,
+                        started,
+                        vmm::POOL_IMAGE,
+                        backend,
+                    )
+                    .print_and_exit();
+                }
+                let (output, _) = VmManager::run_pooled(&command, None).await?;
                 print!("{}", output);
                 return Ok(());
             }
 
             // Daemon path: try daemon VM pool first (single round-trip)
             // Skip is_available() check - just try and fall back on error
-            if !keep {
+            // Pooled VMs run as the image default, so --user/--workdir skip the daemon,
+            // and they have no scratch disk or published ports. Runs needing more
+            // vCPUs or memory than a pooled VM has go the ephemeral way instead
+            let wants_scratch = match config {
+                Some(ref config_path) => Config::from_file(config_path)?
+                    .firecracker
+                    .scratch_mb
+                    .is_some(),
+                None => false,
+            };
+            if !keep
+                && user.is_none()
+                && workdir.is_none()
+                && !wants_scratch
+                && preset.is_none()
+                && ports.is_empty()
+                // Pooled VMs boot the installed rootfs images
+                && !image
+                    .as_deref()
+                    .is_some_and(|i| crate::backend::firecracker::local_rootfs(i).is_some())
+                && daemon::AgentPoolConfig::native().fits(vcpus, memory_mb)
+            {
                 let daemon_client = daemon::DaemonClient::new();
 
+                // The daemon pools Firecracker VMs, so only start it for that backend
+                let firecracker_ok = backend
+                    .as_deref()
+                    .is_none_or(|b| b == "firecracker" || b == "fc");
+                if firecracker_ok && (auto_daemon || daemon::autostart::enabled_by_env()) {
+                    let socket = daemon_client.socket_path().to_path_buf();
+                    let kvm = check_installation().kvm_available;
+                    let outcome = daemon::autostart::ensure_running(&socket, kvm, || {
+                        daemon::autostart::spawn_daemon(&socket)
+                    })
+                    .await;
+                    match outcome {
+                        Ok(daemon::autostart::AutoStart::Started) => eprintln!(
+                            "Started the agentkernel daemon in the background (log: {}). \
+                             Stop it with 'agentkernel daemon stop'.",
+                            daemon::autostart::log_path(&socket).display()
+                        ),
+                        Ok(_) => {}
+                        // The run still works without the pool
+                        Err(e) => eprintln!(
+                            "Warning: failed to start the daemon, running without it: {:#}",
+                            e
+                        ),
+                    }
+                }
+
                 // Determine runtime from image/config
                 let runtime = if let Some(ref img) = image {
                     languages::docker_image_to_firecracker_runtime(img).to_string()
@@ -640,6 +1580,17 @@ memory_mb = 512
                 // Try daemon (single round-trip: acquire + exec + release)
                 if let Ok(result) = daemon_client.run_in_pool(&runtime, &command).await {
                     eprintln!("Using daemon ({})", runtime);
+                    if json {
+                        return run_report::RunReport::new(
+                            result.exit_code,
+                            result.stdout,
+                            result.stderr,
+                            started,
+                            &runtime,
+                            "firecracker",
+                        )
+                        .print_and_exit();
+                    }
                     print!("{}", result.stdout);
                     if !result.stderr.is_empty() {
                         eprint!("{}", result.stderr);
@@ -650,18 +1601,35 @@ memory_mb = 512
                     return Ok(());
                 }
                 // Daemon not available or failed, fall through to ephemeral mode
+                if !daemon_client.is_available()
+                    && check_installation().kvm_available
+                    && daemon::autostart::take_hint(daemon_client.socket_path())
+                {
+                    eprintln!(
+                        "Hint: runs are faster with the VM pool daemon. Start it with \
+                         'agentkernel daemon start', or pass --auto-daemon to start it when needed."
+                    );
+                }
             }
 
             // Determine Docker image: --image > --config > Dockerfile > command > ./agentkernel.toml > project files > default
             // For `run`, command detection has higher priority than project files
             // because user is explicitly specifying what to run
             let (docker_image, cfg_for_build) = if let Some(img) = image {
+                // A config requiring pinned images applies to --image too
+                if let Some(ref config_path) = config {
+                    Config::from_file(config_path)?.check_image_pinned(&img)?;
+                }
                 (img, None)
+            } else if let Some(ref p) = preset {
+                (p.image.clone(), None)
             } else if let Some(ref config_path) = config {
                 let cfg = Config::from_file(config_path)?;
                 (cfg.docker_image(), Some(cfg))
-            } else if let Some(img) = languages::detect_from_command(&command) {
-                // Command-based detection first for `run`
+            } else if let Some(img) = languages::detect_from_command(&command)
+                .or_else(|| languages::detect_from_script(&command))
+            {
+                // Command-based detection first for `run`, then a local script's shebang
                 (img, None)
             } else {
                 // Try current directory config
@@ -721,10 +1689,11 @@ memory_mb = 512
                 docker_image
             };
 
-            // Get permissions from profile
-            let mut perms = permissions::SecurityProfile::from_str(&profile)
-                .unwrap_or_default()
-                .permissions();
+            // Get permissions from the preset (already merged with flags) or profile
+            let mut perms = match preset {
+                Some(ref p) => p.permissions.clone(),
+                None => profile.unwrap_or_default().permissions(),
+            };
 
             // Apply --no-network override
             if no_network {
@@ -738,26 +1707,49 @@ memory_mb = 512
                     eprintln!("Warning: {}", warning);
                 }
                 let cfg_perms = cfg.get_permissions();
-                // Config overrides take precedence over CLI profile
-                if cfg.security.network.is_some() {
-                    perms.network = cfg_perms.network;
+                // Config overrides take precedence over CLI profile (a preset
+                // was already resolved against the config)
+                if preset.is_none() {
+                    if cfg.security.network.is_some() {
+                        perms.network = cfg_perms.network;
+                    }
+                    if cfg.security.user.is_some() {
+                        perms.user = cfg_perms.user;
+                    }
+                    if cfg.firecracker.scratch_mb.is_some() {
+                        perms.scratch_mb = cfg_perms.scratch_mb;
+                    }
+                    perms.mounts = cfg_perms.mounts;
                 }
                 // Load files relative to config file directory
                 let config_dir = config_path
                     .parent()
                     .unwrap_or_else(|| std::path::Path::new("."));
-                cfg.load_files(config_dir)?
+                let mut files = cfg.load_files(config_dir)?;
+                files.extend(cfg.load_cwd_copy()?);
+                files
             } else {
                 // Check for default config file and load files if present
                 let default_config = PathBuf::from("agentkernel.toml");
                 if default_config.exists() {
                     let cfg = Config::from_file(&default_config)?;
-                    cfg.load_files(std::path::Path::new("."))?
+                    let mut files = cfg.load_files(std::path::Path::new("."))?;
+                    files.extend(cfg.load_cwd_copy()?);
+                    files
                 } else {
                     Vec::new()
                 }
             };
 
+            // --user wins over the profile and config
+            if user.is_some() {
+                perms.user = user;
+            }
+
+            if explicit_size || local_config_sized {
+                perms.apply_resources(vcpus, memory_mb, explicit_size);
+            }
+
             // Parse backend option if provided
             let backend_type = if let Some(ref b) = backend {
                 Some(
@@ -768,15 +1760,44 @@ memory_mb = 512
                 None
             };
             let mut manager = VmManager::with_backend(backend_type)?;
+            if !ports.is_empty() {
+                check_can_publish(manager.backend())?;
+            }
 
             // Optimized path: use run_ephemeral for single-operation execution
             // This is faster than create→start→exec→stop→remove cycle:
             // - Docker: single `docker run --rm` command
             // - Apple containers: single `container run --rm` (~940ms vs ~2200ms)
-            // Only used when --keep is not specified
-            if !keep {
+            // Only used when --keep and --publish are not specified
+            if json && !keep && ports.is_empty() {
+                match manager
+                    .run_ephemeral_full(&docker_image, &command, &perms, &files, workdir.as_deref())
+                    .await
+                {
+                    Ok(result) => {
+                        return run_report::RunReport::new(
+                            result.exit_code,
+                            result.stdout,
+                            result.stderr,
+                            started,
+                            &docker_image,
+                            &manager.backend().to_string(),
+                        )
+                        .print_and_exit();
+                    }
+                    // Firecracker doesn't support ephemeral mode, fall through to multi-step
+                    Err(e) if e.downcast_ref::<EphemeralUnsupported>().is_some() => {}
+                    Err(e) => bail!("{}", e),
+                }
+            } else if !keep && ports.is_empty() {
                 match manager
-                    .run_ephemeral_with_files(&docker_image, &command, &perms, &files)
+                    .run_ephemeral_with_files(
+                        &docker_image,
+                        &command,
+                        &perms,
+                        &files,
+                        workdir.as_deref(),
+                    )
                     .await
                 {
                     Ok(output) => {
@@ -785,7 +1806,7 @@ memory_mb = 512
                     }
                     Err(e) => {
                         // Firecracker doesn't support ephemeral mode, fall through to multi-step
-                        if !e.to_string().contains("Ephemeral mode not supported") {
+                        if e.downcast_ref::<EphemeralUnsupported>().is_none() {
                             // Real error, bail out
                             bail!("{}", e);
                         }
@@ -799,8 +1820,27 @@ memory_mb = 512
             let run_id = uuid::Uuid::new_v4().to_string()[..8].to_string();
             let sandbox_name = format!("run-{}", run_id);
 
-            // Create
-            manager.create(&sandbox_name, &docker_image, 1, 512).await?;
+            // Create (kept sandboxes are not eligible for the ephemeral reaper)
+            if let Some(rootfs) = crate::backend::firecracker::local_rootfs(&docker_image) {
+                // A rootfs converted from the project's Dockerfile
+                manager
+                    .create_from_rootfs(&sandbox_name, &rootfs, vcpus, memory_mb, !keep)
+                    .await?;
+            } else if keep {
+                manager
+                    .create(&sandbox_name, &docker_image, vcpus, memory_mb)
+                    .await?;
+            } else {
+                manager
+                    .create_ephemeral(&sandbox_name, &docker_image, vcpus, memory_mb)
+                    .await?;
+            }
+            if keep && ttl.is_some() {
+                manager.set_ttl(&sandbox_name, ttl)?;
+            }
+            if !ports.is_empty() {
+                manager.set_ports(&sandbox_name, ports)?;
+            }
 
             // Start with permissions and inject files
             if let Err(e) = manager
@@ -812,8 +1852,48 @@ memory_mb = 512
                 bail!("Failed to start sandbox: {}", e);
             }
 
+            let exec_opts = ExecOptions::default().workdir(workdir.as_deref());
+            if detach {
+                if let Err(e) = manager
+                    .exec_detached(&sandbox_name, &command, &exec_opts)
+                    .await
+                {
+                    let _ = manager.remove(&sandbox_name).await;
+                    bail!("{}", e);
+                }
+                println!("{}", sandbox_name);
+                eprintln!("Running in background.");
+                eprintln!("  View logs:  agentkernel logs {}", sandbox_name);
+                eprintln!("  Wait:       agentkernel wait {}", sandbox_name);
+                eprintln!("  Remove:     agentkernel remove {}", sandbox_name);
+                return Ok(());
+            }
+
+            if json {
+                let result = manager
+                    .exec_cmd_full(&sandbox_name, &command, &exec_opts)
+                    .await;
+                let _ = manager.stop(&sandbox_name).await;
+                if !keep {
+                    let _ = manager.remove(&sandbox_name).await;
+                }
+                let result = result?;
+                return run_report::RunReport::new(
+                    result.exit_code,
+                    result.stdout,
+                    result.stderr,
+                    started,
+                    &docker_image,
+                    &manager.backend().to_string(),
+                )
+                .with_sandbox(&sandbox_name)
+                .print_and_exit();
+            }
+
             // Execute command
-            let result = manager.exec_cmd(&sandbox_name, &command).await;
+            let result = manager
+                .exec_cmd_with_options(&sandbox_name, &command, &exec_opts)
+                .await;
 
             // Print output
             match &result {
@@ -847,8 +1927,8 @@ memory_mb = 512
             http_api::run_server(addr).await?;
         }
         Commands::Agents => {
-            println!("{:<15} {:<15} API KEY", "AGENT", "STATUS");
-            println!("{:-<45}", "");
+            println!("{:<15} {:<15} {:<9} VERSION", "AGENT", "STATUS", "API KEY");
+            println!("{:-<55}", "");
             for status in agents::list_agents() {
                 let install_status = if status.installed {
                     "installed"
@@ -857,10 +1937,11 @@ memory_mb = 512
                 };
                 let key_status = if status.api_key_set { "set" } else { "missing" };
                 println!(
-                    "{:<15} {:<15} {}",
+                    "{:<15} {:<15} {:<9} {}",
                     status.agent_type.name(),
                     install_status,
-                    key_status
+                    key_status,
+                    status.version.as_deref().unwrap_or("-")
                 );
                 if !status.installed {
                     println!("  → {}", status.install_instructions);
@@ -901,9 +1982,46 @@ memory_mb = 512
                 plugin_installer::list_plugins();
             }
         },
+        Commands::Policy { action } => match action {
+            PolicyCommand::Test {
+                action,
+                resource,
+                config,
+            } => {
+                let action: policy::PolicyAction =
+                    action.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+                let config_path = config.unwrap_or_else(|| PathBuf::from("agentkernel.toml"));
+                let engine = if config_path.exists() {
+                    policy::PolicyEngine::from_file(&config_path)?
+                } else {
+                    eprintln!(
+                        "No policy found at {}, evaluating default rules",
+                        config_path.display()
+                    );
+                    policy::PolicyEngine::new(Default::default())
+                };
+
+                let result = engine.simulate(action, &resource);
+                println!("Decision: {}", result.decision);
+                println!("Action:   {}", action);
+                println!("Resource: {}", resource);
+                if result.matched.is_empty() {
+                    println!("Matched:  (none)");
+                } else {
+                    for (i, rule) in result.matched.iter().enumerate() {
+                        let label = if i == 0 { "Matched: " } else { "         " };
+                        println!("{} {}", label, rule);
+                    }
+                }
+                println!("Reason:   {}", result.reason);
+            }
+        },
         Commands::Daemon { action } => {
             match action {
-                DaemonAction::Start { background } => {
+                DaemonAction::Start {
+                    background,
+                    persist,
+                } => {
                     // Check setup status first
                     let status = check_installation();
                     if !status.kvm_available {
@@ -927,7 +2045,10 @@ memory_mb = 512
                     let kernel_path = find_kernel(&base_dir)?;
                     let rootfs_dir = base_dir.join("rootfs");
 
-                    let config = daemon::PoolConfig::default();
+                    let config = daemon::PoolConfig {
+                        persist_dir: persist.then(|| daemon::default_snapshot_dir(&socket_path)),
+                        ..Default::default()
+                    };
                     let server = daemon::DaemonServer::new(config, kernel_path, rootfs_dir);
 
                     if background {
@@ -956,13 +2077,28 @@ memory_mb = 512
                         return Ok(());
                     }
 
-                    let (warm, in_use, min_warm, max_warm) = client.status().await?;
+                    let status = client.status().await?;
                     println!("Daemon: running");
                     println!("Socket: {}", client.socket_path().display());
                     println!("Pool:");
-                    println!("  Warm VMs:    {}", warm);
-                    println!("  In use:      {}", in_use);
-                    println!("  Min/Max:     {}/{}", min_warm, max_warm);
+                    println!("  Warm VMs:    {}", status.warm);
+                    println!("  In use:      {}", status.in_use);
+                    println!("  Min/Max:     {}/{}", status.min_warm, status.max_warm);
+                    if !status.boot_latency.is_empty() {
+                        let mut runtimes: Vec<_> = status.boot_latency.iter().collect();
+                        runtimes.sort_by_key(|(runtime, _)| *runtime);
+                        println!("Boot latency:");
+                        for (runtime, latency) in runtimes {
+                            println!(
+                                "  {:<12} {} boots, avg {} ms (min {}, max {})",
+                                format!("{}:", runtime),
+                                latency.count,
+                                latency.avg_ms(),
+                                latency.min_ms,
+                                latency.max_ms
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -986,7 +2122,9 @@ memory_mb = 512
             };
 
             if entries.is_empty() {
-                if let Some(ref name) = sandbox {
+                if !audit_log.is_enabled() {
+                    println!("Audit logging is disabled");
+                } else if let Some(ref name) = sandbox {
                     println!("No audit entries for sandbox '{}'", name);
                 } else {
                     println!("No audit entries found");
@@ -1010,13 +2148,20 @@ memory_mb = 512
                         audit::AuditEvent::SandboxCreated { name, image, .. } => {
                             ("sandbox_created", name.as_str(), format!("image={}", image))
                         }
-                        audit::AuditEvent::SandboxStarted { name, profile } => (
+                        audit::AuditEvent::SandboxStarted {
+                            name,
+                            profile,
+                            boot_ms,
+                            ..
+                        } => (
                             "sandbox_started",
                             name.as_str(),
-                            profile
-                                .as_ref()
-                                .map(|p| format!("profile={}", p))
-                                .unwrap_or_default(),
+                            boot_ms
+                                .map(|ms| format!("boot={}ms", ms))
+                                .into_iter()
+                                .chain(profile.as_ref().map(|p| format!("profile={}", p)))
+                                .collect::<Vec<_>>()
+                                .join(" "),
                         ),
                         audit::AuditEvent::SandboxStopped { name } => {
                             ("sandbox_stopped", name.as_str(), String::new())
@@ -1046,6 +2191,18 @@ memory_mb = 512
                         audit::AuditEvent::SessionAttached { sandbox } => {
                             ("session_attached", sandbox.as_str(), String::new())
                         }
+                        audit::AuditEvent::SetupScriptRun {
+                            sandbox, exit_code, ..
+                        } => (
+                            "setup_script_run",
+                            sandbox.as_str(),
+                            format!("exit={}", exit_code),
+                        ),
+                        audit::AuditEvent::NetworkChanged { sandbox, enabled } => (
+                            "network_changed",
+                            sandbox.as_str(),
+                            format!("enabled={}", enabled),
+                        ),
                         audit::AuditEvent::PolicyViolation {
                             sandbox,
                             policy,
@@ -1116,12 +2273,10 @@ memory_mb = 512
 /// Find the images directory
 fn find_images_dir() -> Result<PathBuf> {
     // Check installed location first (preferred)
-    if let Some(home) = std::env::var_os("HOME") {
-        let home_path = PathBuf::from(home).join(".local/share/agentkernel/images");
-        // Check if it has actual content (kernel or rootfs)
-        if home_path.join("kernel").exists() || home_path.join("rootfs").exists() {
-            return Ok(home_path);
-        }
+    let installed = setup::default_data_dir().join("images");
+    // Check if it has actual content (kernel or rootfs)
+    if installed.join("kernel").exists() || installed.join("rootfs").exists() {
+        return Ok(installed);
     }
 
     // Check relative to current dir (development mode)
@@ -1134,7 +2289,8 @@ fn find_images_dir() -> Result<PathBuf> {
     }
 
     bail!(
-        "Images directory not found. Run 'agentkernel setup' first, or check ~/.local/share/agentkernel/images"
+        "Images directory not found. Run 'agentkernel setup' first, or check {}",
+        installed.display()
     );
 }
 
@@ -1160,6 +2316,76 @@ fn find_kernel(base_dir: &Path) -> Result<PathBuf> {
     );
 }
 
+/// Load the config holding presets (`--config`, else ./agentkernel.toml)
+/// and resolve the named preset
+fn load_preset(config: Option<&Path>, name: &str) -> Result<(Config, config::ResolvedPreset)> {
+    let path = config.unwrap_or(Path::new("agentkernel.toml"));
+    if !path.exists() {
+        bail!(
+            "--preset '{}' needs a config with [presets]: {} not found",
+            name,
+            path.display()
+        );
+    }
+    let cfg = Config::from_file(path)?;
+    let resolved = cfg.preset(name)?;
+    Ok((cfg, resolved))
+}
+
+/// `[sandbox] backend` from `--config`, else ./agentkernel.toml
+///
+/// A config that fails to load is reported where it's loaded for the run.
+fn config_backend(config: Option<&Path>) -> Option<String> {
+    load_local_config(config)?.sandbox.backend
+}
+
+/// `--config`, else ./agentkernel.toml, if it exists and loads
+fn load_local_config(config: Option<&Path>) -> Option<Config> {
+    let path = config.unwrap_or(Path::new("agentkernel.toml"));
+    if !path.exists() {
+        return None;
+    }
+    Config::from_file(path).ok()
+}
+
+/// Parse `--publish` values
+fn parse_ports(values: &[String]) -> Result<Vec<crate::backend::PortMapping>> {
+    values
+        .iter()
+        .map(|v| v.parse().map_err(|e: String| anyhow::anyhow!(e)))
+        .collect()
+}
+
+/// Format a disk size as "131.0 MB (137363456 bytes)"
+fn format_disk_usage(bytes: u64) -> String {
+    format!(
+        "{:.1} MB ({} bytes)",
+        bytes as f64 / (1024.0 * 1024.0),
+        bytes
+    )
+}
+
+/// Format ports as "3000:3000, 8080:80"
+fn format_ports(ports: &[crate::backend::PortMapping]) -> String {
+    ports
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Fail early when `--publish` is used with a backend that can't publish ports
+fn check_can_publish(backend: crate::backend::BackendType) -> Result<()> {
+    use crate::backend::BackendType;
+    if !matches!(backend, BackendType::Docker | BackendType::Podman) {
+        bail!(
+            "--publish needs the docker or podman backend (got {})",
+            backend
+        );
+    }
+    Ok(())
+}
+
 fn missing_components(status: &setup::SetupStatus) -> String {
     let mut missing = Vec::new();
     if !status.kernel_installed {
@@ -1177,6 +2403,24 @@ fn missing_components(status: &setup::SetupStatus) -> String {
     missing.join(", ")
 }
 
+/// With `--stdin` or a `-` command, read a script from stdin and run it with
+/// `sh -c`; the remaining arguments become its positional parameters
+fn command_from_stdin(command: Vec<String>, stdin: bool) -> Result<Vec<String>> {
+    use std::io::IsTerminal;
+
+    let args = match (stdin, command.first().map(String::as_str)) {
+        (true, _) => &command[..],
+        (false, Some("-")) => &command[1..],
+        _ => return Ok(command),
+    };
+    let input = std::io::stdin();
+    if input.is_terminal() {
+        bail!("--stdin expects a script piped on stdin (e.g. agentkernel run --stdin < script.sh)");
+    }
+    let (script, script_input) = backend::read_script(input.lock())?;
+    backend::script_command(&script, script_input.as_deref(), args)
+}
+
 /// Parse a cp-style path (sandbox:/path or ./local/path)
 /// Returns (Some(sandbox_name), path) for sandbox paths
 /// Returns (None, path) for local paths
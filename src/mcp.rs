@@ -6,15 +6,81 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
 use tokio::runtime::Handle;
 
+use crate::backend::{
+    DEFAULT_MAX_OUTPUT_BYTES, EphemeralUnsupported, ExecOptions, ExecResult, WriteOptions,
+    parse_file_mode,
+};
+use crate::config::Config;
 use crate::languages;
 use crate::permissions::{CompatibilityMode, SecurityProfile};
+use crate::validation;
 use crate::vmm::VmManager;
 
+/// Env var selecting which tools are exposed: `all` (default) or `read-only`
+pub const MCP_TOOLS_ENV: &str = "AGENTKERNEL_MCP_TOOLS";
+
+/// `sandbox_create` defaults, matching `POST /sandboxes`
+const DEFAULT_IMAGE: &str = "alpine:3.20";
+const DEFAULT_VCPUS: u32 = 1;
+const DEFAULT_MEMORY_MB: u64 = 512;
+
+/// Tools that only inspect existing sandboxes
+const READ_ONLY_TOOLS: &[&str] = &["sandbox_list", "sandbox_file_read"];
+
+/// Which tools the server exposes to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolExposure {
+    /// Every tool
+    #[default]
+    All,
+    /// Inspection-only tools; anything that runs or changes sandboxes is hidden
+    ReadOnly,
+}
+
+impl FromStr for ToolExposure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(ToolExposure::All),
+            "read-only" | "readonly" | "read_only" => Ok(ToolExposure::ReadOnly),
+            _ => anyhow::bail!(
+                "Invalid {} value '{}'. Use: all, read-only",
+                MCP_TOOLS_ENV,
+                s
+            ),
+        }
+    }
+}
+
+impl ToolExposure {
+    /// Read the exposure from `AGENTKERNEL_MCP_TOOLS` (unset means all tools)
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(MCP_TOOLS_ENV) {
+            Ok(value) if !value.is_empty() => value.parse(),
+            _ => Ok(ToolExposure::All),
+        }
+    }
+
+    /// Whether a tool may be listed and called
+    pub fn allows(&self, tool: &str) -> bool {
+        match self {
+            ToolExposure::All => true,
+            ToolExposure::ReadOnly => READ_ONLY_TOOLS.contains(&tool),
+        }
+    }
+}
+
 /// MCP server for agentkernel
 pub struct McpServer {
     initialized: bool,
+    tools: ToolExposure,
+    /// Bytes of stdout and of stderr kept per command
+    max_output: usize,
 }
 
 // JSON-RPC 2.0 types
@@ -48,7 +114,23 @@ struct JsonRpcError {
 
 impl McpServer {
     pub fn new() -> Self {
-        Self { initialized: false }
+        Self {
+            initialized: false,
+            tools: ToolExposure::All,
+            max_output: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+
+    /// Limit which tools are exposed
+    pub fn with_tool_exposure(mut self, tools: ToolExposure) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Keep at most this many bytes of each command's stdout and stderr
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
     }
 
     /// Run the MCP server (reads from stdin, writes to stdout)
@@ -157,11 +239,11 @@ impl McpServer {
     }
 
     fn handle_tools_list(&self, id: Value) -> JsonRpcResponse {
-        let tools = json!({
+        let mut tools = json!({
             "tools": [
                 {
                     "name": "sandbox_run",
-                    "description": "Run a command in an isolated sandbox (SAFE: executes in isolation, cannot affect host). By default uses a pre-warmed container pool for fast execution (~50ms). Set fast=false for custom images or advanced options.",
+                    "description": "Run a command in an isolated sandbox (SAFE: executes in isolation, cannot affect host). Returns JSON with exit_code, stdout, and stderr; a non-zero exit code is reported, not treated as an error. By default uses a pre-warmed container pool for fast execution (~50ms). Set fast=false for custom images or advanced options.",
                     "inputSchema": {
                         "type": "object",
                         "properties": {
@@ -226,6 +308,26 @@ impl McpServer {
                             "image": {
                                 "type": "string",
                                 "description": "Docker image to use (default: alpine:3.20)"
+                            },
+                            "vcpus": {
+                                "type": "integer",
+                                "description": "Number of vCPUs",
+                                "minimum": 1,
+                                "maximum": validation::MAX_VCPUS,
+                                "default": DEFAULT_VCPUS
+                            },
+                            "memory_mb": {
+                                "type": "integer",
+                                "description": "Memory in MB",
+                                "minimum": validation::MIN_MEMORY_MB,
+                                "maximum": validation::MAX_MEMORY_MB,
+                                "default": DEFAULT_MEMORY_MB
+                            },
+                            "profile": {
+                                "type": "string",
+                                "enum": ["permissive", "moderate", "restrictive"],
+                                "description": "Security profile to start the sandbox with",
+                                "default": "moderate"
                             }
                         },
                         "required": ["name"]
@@ -289,6 +391,14 @@ impl McpServer {
                             "content": {
                                 "type": "string",
                                 "description": "Content to write to the file"
+                            },
+                            "append": {
+                                "type": "boolean",
+                                "description": "Append to the file instead of replacing it (default: false)"
+                            },
+                            "mode": {
+                                "type": "string",
+                                "description": "Octal permissions for the file, e.g. \"755\" for a script"
                             }
                         },
                         "required": ["name", "path", "content"]
@@ -312,6 +422,32 @@ impl McpServer {
                         "required": ["name", "path"]
                     }
                 },
+                {
+                    "name": "sandbox_file_link",
+                    "description": "Create a symlink (or hard link) inside a running sandbox (SAFE: link and target must stay within allowed sandbox paths).",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "name": {
+                                "type": "string",
+                                "description": "Name of the sandbox"
+                            },
+                            "target": {
+                                "type": "string",
+                                "description": "Path the link points to (relative targets resolve against the link's directory)"
+                            },
+                            "link": {
+                                "type": "string",
+                                "description": "Absolute path of the link to create"
+                            },
+                            "hard": {
+                                "type": "boolean",
+                                "description": "Create a hard link instead of a symlink (default: false)"
+                            }
+                        },
+                        "required": ["name", "target", "link"]
+                    }
+                },
                 {
                     "name": "sandbox_start",
                     "description": "Start a stopped sandbox (SAFE: starts existing isolated container).",
@@ -342,6 +478,12 @@ impl McpServer {
                 }
             ]
         });
+        if let Some(list) = tools.get_mut("tools").and_then(|t| t.as_array_mut()) {
+            list.retain(|tool| {
+                let name = tool.get("name").and_then(|n| n.as_str()).unwrap_or("");
+                self.tools.allows(name)
+            });
+        }
 
         JsonRpcResponse {
             jsonrpc: "2.0",
@@ -355,6 +497,22 @@ impl McpServer {
         let tool_name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
         let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
 
+        if !self.tools.allows(tool_name) {
+            return JsonRpcResponse {
+                jsonrpc: "2.0",
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32602,
+                    message: format!(
+                        "Tool '{}' is not available: this server only exposes read-only tools ({}=read-only)",
+                        tool_name, MCP_TOOLS_ENV
+                    ),
+                    data: None,
+                }),
+            };
+        }
+
         let result = match tool_name {
             "sandbox_run" => self.tool_sandbox_run(&arguments),
             "sandbox_create" => self.tool_sandbox_create(&arguments),
@@ -363,35 +521,17 @@ impl McpServer {
             "sandbox_remove" => self.tool_sandbox_remove(&arguments),
             "sandbox_file_write" => self.tool_sandbox_file_write(&arguments),
             "sandbox_file_read" => self.tool_sandbox_file_read(&arguments),
+            "sandbox_file_link" => self.tool_sandbox_file_link(&arguments),
             "sandbox_start" => self.tool_sandbox_start(&arguments),
             "sandbox_stop" => self.tool_sandbox_stop(&arguments),
             _ => Err(anyhow::anyhow!("Unknown tool: {}", tool_name)),
         };
 
-        match result {
-            Ok(content) => JsonRpcResponse {
-                jsonrpc: "2.0",
-                id,
-                result: Some(json!({
-                    "content": [{
-                        "type": "text",
-                        "text": content
-                    }]
-                })),
-                error: None,
-            },
-            Err(e) => JsonRpcResponse {
-                jsonrpc: "2.0",
-                id,
-                result: Some(json!({
-                    "content": [{
-                        "type": "text",
-                        "text": format!("Error: {}", e)
-                    }],
-                    "isError": true
-                })),
-                error: None,
-            },
+        JsonRpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result: Some(tool_call_result(result)),
+            error: None,
         }
     }
 
@@ -419,9 +559,12 @@ impl McpServer {
                 eprintln!("Warning: custom image ignored in fast mode (pool uses alpine:3.20)");
             }
 
-            return tokio::task::block_in_place(|| {
-                Handle::current().block_on(async { VmManager::run_pooled(&command).await })
-            });
+            let result = tokio::task::block_in_place(|| {
+                Handle::current().block_on(async {
+                    VmManager::run_pooled_full(&command, Some(self.max_output)).await
+                })
+            })?;
+            return Ok(exec_result_json(&result));
         }
 
         // Slow path: full sandbox lifecycle (when fast=false or custom image needed)
@@ -430,6 +573,7 @@ impl McpServer {
             .and_then(|v| v.as_str())
             .map(String::from)
             .unwrap_or_else(|| languages::detect_image(&command));
+        validation::validate_docker_image(&image)?;
 
         // Check for compatibility mode first (takes precedence over profile)
         let mut perms =
@@ -440,7 +584,13 @@ impl McpServer {
                     "Using {} compatibility mode (API: {:?})",
                     mode_str, profile.api_key_env
                 );
-                profile.permissions
+                // Layer any [compat.<mode>] override from agentkernel.toml on top
+                let config_path = Path::new("agentkernel.toml");
+                if config_path.exists() {
+                    Config::from_file(config_path)?.compat_permissions(mode)
+                } else {
+                    profile.permissions
+                }
             } else {
                 // Fall back to security profile
                 let profile_str = args
@@ -459,37 +609,40 @@ impl McpServer {
         }
 
         // Use the current runtime via block_in_place
-        tokio::task::block_in_place(|| {
+        let result = tokio::task::block_in_place(|| {
             Handle::current().block_on(async {
                 let mut manager = VmManager::new()?;
 
                 // Use optimized ephemeral run with permissions
-                manager
-                    .run_ephemeral_with_files(&image, &command, &perms, &[])
+                match manager
+                    .run_ephemeral_full(&image, &command, &perms, &[], None)
                     .await
+                {
+                    Err(e) if e.downcast_ref::<EphemeralUnsupported>().is_some() => {
+                        manager.run_in_new_sandbox(&image, &command, &perms).await
+                    }
+                    result => result,
+                }
             })
-        })
+        })?;
+        Ok(exec_result_json(&result))
     }
 
     fn tool_sandbox_create(&self, args: &Value) -> Result<String> {
-        let name = args
-            .get("name")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("name is required"))?;
-
-        let image = args
-            .get("image")
-            .and_then(|v| v.as_str())
-            .unwrap_or("alpine:3.20");
+        let args = CreateArgs::parse(args)?;
 
         tokio::task::block_in_place(|| {
             Handle::current().block_on(async {
                 let mut manager = VmManager::new()?;
-                manager.create(name, image, 1, 512).await?;
-                manager.start(name).await?;
+                manager
+                    .create(&args.name, &args.image, args.vcpus, args.memory_mb)
+                    .await?;
+                manager
+                    .start_created(&args.name, &args.profile.permissions())
+                    .await?;
                 Ok(format!(
-                    "Sandbox '{}' created and started with image '{}'",
-                    name, image
+                    "Sandbox '{}' created and started with image '{}' ({} vCPUs, {} MB)",
+                    args.name, args.image, args.vcpus, args.memory_mb
                 ))
             })
         })
@@ -518,7 +671,20 @@ impl McpServer {
         tokio::task::block_in_place(|| {
             Handle::current().block_on(async {
                 let mut manager = VmManager::new()?;
-                manager.exec_cmd(name, &command).await
+                let opts = ExecOptions::default().max_output(Some(self.max_output));
+                let result = manager.exec_cmd_full(name, &command, &opts).await?;
+                if result.exit_code != 0 {
+                    anyhow::bail!(
+                        "Command exited with code {}: {}",
+                        result.exit_code,
+                        result.output()
+                    );
+                }
+                let mut output = result.output();
+                if let Some(rusage) = result.rusage {
+                    output.push_str(&format!("\n[resource usage: {}]", rusage));
+                }
+                Ok(output)
             })
         })
     }
@@ -577,6 +743,17 @@ impl McpServer {
             .and_then(|v| v.as_str())
             .ok_or_else(|| anyhow::anyhow!("content is required"))?;
 
+        let append = args
+            .get("append")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let mode = args
+            .get("mode")
+            .and_then(|v| v.as_str())
+            .map(parse_file_mode)
+            .transpose()?;
+        let opts = WriteOptions::default().append(append).mode(mode);
+
         tokio::task::block_in_place(|| {
             Handle::current().block_on(async {
                 let mut manager = VmManager::new()?;
@@ -588,9 +765,12 @@ impl McpServer {
                     );
                 }
 
-                manager.write_file(name, path, content.as_bytes()).await?;
+                manager
+                    .write_file_with_options(name, path, content.as_bytes(), &opts)
+                    .await?;
                 Ok(format!(
-                    "Wrote {} bytes to '{}' in sandbox '{}'",
+                    "{} {} bytes to '{}' in sandbox '{}'",
+                    if append { "Appended" } else { "Wrote" },
                     content.len(),
                     path,
                     name
@@ -599,6 +779,44 @@ impl McpServer {
         })
     }
 
+    fn tool_sandbox_file_link(&self, args: &Value) -> Result<String> {
+        let name = args
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+
+        let target = args
+            .get("target")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("target is required"))?;
+
+        let link = args
+            .get("link")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("link is required"))?;
+
+        let hard = args.get("hard").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        tokio::task::block_in_place(|| {
+            Handle::current().block_on(async {
+                let mut manager = VmManager::new()?;
+
+                if !manager.is_running(name) {
+                    anyhow::bail!(
+                        "Sandbox '{}' is not running. Start it first with sandbox_start.",
+                        name
+                    );
+                }
+
+                manager.link(name, target, link, hard).await?;
+                Ok(format!(
+                    "Linked '{}' -> '{}' in sandbox '{}'",
+                    link, target, name
+                ))
+            })
+        })
+    }
+
     fn tool_sandbox_file_read(&self, args: &Value) -> Result<String> {
         let name = args
             .get("name")
@@ -699,10 +917,116 @@ impl Default for McpServer {
 
 /// Run the MCP server
 pub async fn run_server() -> Result<()> {
-    let mut server = McpServer::new();
+    let tools = ToolExposure::from_env()?;
+    if tools == ToolExposure::ReadOnly {
+        eprintln!(
+            "MCP tools limited to read-only: {}",
+            READ_ONLY_TOOLS.join(", ")
+        );
+    }
+    let mut server = McpServer::new()
+        .with_tool_exposure(tools)
+        .with_max_output(Config::server_max_output());
     server.run()
 }
 
+/// Arguments of the `sandbox_create` tool, with defaults filled in
+#[derive(Debug, PartialEq)]
+struct CreateArgs {
+    name: String,
+    image: String,
+    vcpus: u32,
+    memory_mb: u64,
+    profile: SecurityProfile,
+}
+
+impl CreateArgs {
+    fn parse(args: &Value) -> Result<Self> {
+        let name = args
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("name is required"))?;
+        validation::validate_sandbox_name(name)?;
+
+        let image = args
+            .get("image")
+            .and_then(|v| v.as_str())
+            .unwrap_or(DEFAULT_IMAGE);
+        validation::validate_docker_image(image)?;
+
+        let integer = |key: &str| -> Result<Option<u64>> {
+            args.get(key)
+                .map(|v| {
+                    v.as_u64()
+                        .ok_or_else(|| anyhow::anyhow!("{} must be a positive integer", key))
+                })
+                .transpose()
+        };
+        let vcpus = match integer("vcpus")? {
+            Some(n) => u32::try_from(n).unwrap_or(u32::MAX),
+            None => DEFAULT_VCPUS,
+        };
+        let memory_mb = integer("memory_mb")?.unwrap_or(DEFAULT_MEMORY_MB);
+        validation::validate_resources(vcpus, memory_mb)?;
+
+        let profile = match args.get("profile").and_then(|v| v.as_str()) {
+            Some(p) => SecurityProfile::from_str(p).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid profile '{}'. Use: permissive, moderate, restrictive",
+                    p
+                )
+            })?,
+            None => SecurityProfile::default(),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            image: image.to_string(),
+            vcpus,
+            memory_mb,
+            profile,
+        })
+    }
+}
+
+/// The `tools/call` result for a tool's output; only failures to run the
+/// tool set `isError`
+fn tool_call_result(result: Result<String>) -> Value {
+    match result {
+        Ok(content) => json!({
+            "content": [{
+                "type": "text",
+                "text": content
+            }],
+            "isError": false
+        }),
+        Err(e) => json!({
+            "content": [{
+                "type": "text",
+                "text": format!("Error: {}", e)
+            }],
+            "isError": true
+        }),
+    }
+}
+
+/// A command's result as JSON text, so a non-zero exit reads as the
+/// command's outcome rather than a tool error
+fn exec_result_json(result: &ExecResult) -> String {
+    let mut value = json!({
+        "exit_code": result.exit_code,
+        "stdout": result.stdout,
+        "stderr": result.stderr,
+    });
+    if let Some(ref rusage) = result.rusage {
+        value["rusage"] = json!(rusage);
+    }
+    if result.truncated {
+        value["truncated"] = json!(true);
+    }
+    value.to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -856,10 +1180,58 @@ mod tests {
         assert!(tool_names.contains(&"sandbox_remove"));
         assert!(tool_names.contains(&"sandbox_file_write"));
         assert!(tool_names.contains(&"sandbox_file_read"));
+        assert!(tool_names.contains(&"sandbox_file_link"));
         assert!(tool_names.contains(&"sandbox_start"));
         assert!(tool_names.contains(&"sandbox_stop"));
     }
 
+    #[test]
+    fn test_tool_exposure_parse() {
+        assert_eq!("all".parse::<ToolExposure>().unwrap(), ToolExposure::All);
+        assert_eq!(
+            "read-only".parse::<ToolExposure>().unwrap(),
+            ToolExposure::ReadOnly
+        );
+        assert_eq!(
+            "READONLY".parse::<ToolExposure>().unwrap(),
+            ToolExposure::ReadOnly
+        );
+        assert!("none".parse::<ToolExposure>().is_err());
+    }
+
+    #[test]
+    fn test_read_only_mode_hides_and_rejects_tools() {
+        let server = McpServer::new().with_tool_exposure(ToolExposure::ReadOnly);
+        let response = server.handle_tools_list(Value::Number(1.into()));
+        let result = response.result.unwrap();
+        let tool_names: Vec<&str> = result["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t.get("name").and_then(|n| n.as_str()))
+            .collect();
+        assert_eq!(tool_names, vec!["sandbox_list", "sandbox_file_read"]);
+
+        for tool in ["sandbox_run", "sandbox_remove"] {
+            let response = server.handle_tools_call(
+                Value::Number(2.into()),
+                &json!({"name": tool, "arguments": {"name": "test"}}),
+            );
+            assert!(response.result.is_none());
+            let error = response.error.unwrap();
+            assert_eq!(error.code, -32602);
+            assert!(error.message.contains(tool));
+            assert!(error.message.contains("read-only"));
+        }
+
+        // Read-only tools still reach their handlers
+        let response = server.handle_tools_call(
+            Value::Number(3.into()),
+            &json!({"name": "sandbox_file_read", "arguments": {}}),
+        );
+        assert!(response.error.is_none());
+    }
+
     // === handle_request tests ===
 
     #[test]
@@ -941,6 +1313,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sandbox_run_nonzero_exit_is_not_a_tool_error() {
+        let failed = ExecResult {
+            exit_code: 1,
+            stdout: "collected 3 items".to_string(),
+            stderr: "1 failed".to_string(),
+            rusage: None,
+            truncated: false,
+        };
+        let result = tool_call_result(Ok(exec_result_json(&failed)));
+        assert_eq!(result["isError"], false);
+
+        let text = result["content"][0]["text"].as_str().unwrap();
+        let output: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(output["exit_code"], 1);
+        assert_eq!(output["stdout"], "collected 3 items");
+        assert_eq!(output["stderr"], "1 failed");
+        assert!(output.get("rusage").is_none());
+    }
+
+    #[test]
+    fn test_tool_errors_are_flagged() {
+        let result = tool_call_result(Err(anyhow::anyhow!("Docker is not running")));
+        assert_eq!(result["isError"], true);
+        assert_eq!(result["content"][0]["text"], "Error: Docker is not running");
+    }
+
     #[test]
     fn test_tool_sandbox_create_missing_name() {
         let server = McpServer::new();
@@ -949,6 +1348,51 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("name is required"));
     }
 
+    #[test]
+    fn test_create_args_defaults() {
+        let args = CreateArgs::parse(&json!({"name": "box"})).unwrap();
+        assert_eq!(
+            args,
+            CreateArgs {
+                name: "box".to_string(),
+                image: "alpine:3.20".to_string(),
+                vcpus: 1,
+                memory_mb: 512,
+                profile: SecurityProfile::Moderate,
+            }
+        );
+    }
+
+    #[test]
+    fn test_create_args_resources_and_profile() {
+        let args = CreateArgs::parse(&json!({
+            "name": "build",
+            "image": "rust:1.85",
+            "vcpus": 4,
+            "memory_mb": 8192,
+            "profile": "restrictive",
+        }))
+        .unwrap();
+        assert_eq!(args.image, "rust:1.85");
+        assert_eq!(args.vcpus, 4);
+        assert_eq!(args.memory_mb, 8192);
+        assert_eq!(args.profile, SecurityProfile::Restrictive);
+
+        for (bad, expected) in [
+            (json!({"name": "b", "vcpus": 0}), "vcpus"),
+            (json!({"name": "b", "vcpus": 1000}), "vcpus"),
+            (json!({"name": "b", "vcpus": "4"}), "positive integer"),
+            (json!({"name": "b", "vcpus": 5_000_000_000u64}), "vcpus"),
+            (json!({"name": "b", "memory_mb": 16}), "memory_mb"),
+            (json!({"name": "b", "memory_mb": -1}), "positive integer"),
+            (json!({"name": "b", "profile": "yolo"}), "Invalid profile"),
+            (json!({"name": "b;id"}), "name"),
+        ] {
+            let err = CreateArgs::parse(&bad).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", bad, err);
+        }
+    }
+
     #[test]
     fn test_tool_sandbox_exec_missing_name() {
         let server = McpServer::new();
@@ -994,6 +1438,21 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("path is required"));
     }
 
+    #[test]
+    fn test_tool_sandbox_file_write_invalid_mode() {
+        let server = McpServer::new();
+        let result = server.tool_sandbox_file_write(
+            &json!({"name": "test", "path": "/test", "content": "x", "mode": "rwx"}),
+        );
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid file mode")
+        );
+    }
+
     #[test]
     fn test_tool_sandbox_file_write_missing_content() {
         let server = McpServer::new();
@@ -1023,6 +1482,20 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("path is required"));
     }
 
+    #[test]
+    fn test_tool_sandbox_file_link_missing_args() {
+        let server = McpServer::new();
+        let result = server.tool_sandbox_file_link(&json!({"name": "test", "link": "/app/x"}));
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("target is required")
+        );
+        let result = server.tool_sandbox_file_link(&json!({"name": "test", "target": "/app/x"}));
+        assert!(result.unwrap_err().to_string().contains("link is required"));
+    }
+
     #[test]
     fn test_tool_sandbox_start_missing_name() {
         let server = McpServer::new();
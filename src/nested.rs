@@ -0,0 +1,186 @@
+//! Detecting that agentkernel itself runs inside a container, as it often
+//! does in CI.
+//!
+//! Nested, Firecracker needs the host's /dev/kvm passed through, and the
+//! container backends work best through the host's Docker socket mounted
+//! into the container.
+
+use std::path::{Path, PathBuf};
+
+/// Docker socket paths checked inside a container, in order
+const DOCKER_SOCKETS: &[&str] = &["var/run/docker.sock", "run/docker.sock"];
+
+/// cgroup path fragments that mean "inside a container", and the runtime they name
+const CGROUP_MARKERS: &[(&str, &str)] = &[
+    ("kubepods", "kubernetes"),
+    ("libpod", "podman"),
+    ("docker", "docker"),
+    ("containerd", "containerd"),
+    ("lxc", "lxc"),
+];
+
+/// The container agentkernel is running in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEnv {
+    /// Container runtime, e.g. "docker", "podman", "kubernetes"
+    pub runtime: String,
+    /// Host Docker socket mounted into the container, if any
+    pub docker_socket: Option<PathBuf>,
+}
+
+impl ContainerEnv {
+    /// One-line summary for `status` and `doctor`
+    pub fn describe(&self) -> String {
+        match self.docker_socket {
+            Some(ref socket) => format!(
+                "inside a {} container (Docker socket at {})",
+                self.runtime,
+                socket.display()
+            ),
+            None => format!("inside a {} container (no Docker socket)", self.runtime),
+        }
+    }
+}
+
+/// Detect whether this process runs inside a container
+pub fn detect() -> Option<ContainerEnv> {
+    detect_in(
+        Path::new("/"),
+        std::env::var("container").ok().as_deref(),
+        std::env::var("DOCKER_HOST").ok().as_deref(),
+    )
+}
+
+/// Detection against a filesystem root, for testing.
+///
+/// `container_var` is the `container` environment variable that Podman,
+/// systemd-nspawn, and LXC set; `docker_host` is `DOCKER_HOST`.
+fn detect_in(
+    root: &Path,
+    container_var: Option<&str>,
+    docker_host: Option<&str>,
+) -> Option<ContainerEnv> {
+    let runtime = if root.join(".dockerenv").exists() {
+        "docker".to_string()
+    } else if root.join("run/.containerenv").exists() {
+        "podman".to_string()
+    } else if let Some(name) = container_var.filter(|v| !v.is_empty()) {
+        name.to_string()
+    } else {
+        let cgroup = std::fs::read_to_string(root.join("proc/1/cgroup")).unwrap_or_default();
+        cgroup_runtime(&cgroup)?.to_string()
+    };
+
+    let docker_socket = match docker_host.and_then(|h| h.strip_prefix("unix://")) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => DOCKER_SOCKETS
+            .iter()
+            .find(|p| root.join(p).exists())
+            .map(|p| Path::new("/").join(p)),
+    };
+
+    Some(ContainerEnv {
+        runtime,
+        docker_socket,
+    })
+}
+
+/// Runtime named by PID 1's cgroup paths (cgroup v1, or v2 without a namespace)
+fn cgroup_runtime(cgroup: &str) -> Option<&'static str> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .find_map(|path| {
+            CGROUP_MARKERS
+                .iter()
+                .find(|(marker, _)| path.contains(marker))
+                .map(|(_, runtime)| *runtime)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_detect_on_host() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), "proc/1/cgroup", "0::/init.scope\n");
+        assert_eq!(detect_in(root.path(), None, None), None);
+        assert_eq!(detect_in(root.path(), Some(""), None), None);
+    }
+
+    #[test]
+    fn test_detect_marker_files_and_env() {
+        let root = TempDir::new().unwrap();
+        assert_eq!(
+            detect_in(root.path(), Some("lxc"), None).unwrap().runtime,
+            "lxc"
+        );
+
+        write(root.path(), "run/.containerenv", "");
+        assert_eq!(
+            detect_in(root.path(), None, None).unwrap().runtime,
+            "podman"
+        );
+
+        // .dockerenv wins over the others
+        write(root.path(), ".dockerenv", "");
+        let env = detect_in(root.path(), Some("podman"), None).unwrap();
+        assert_eq!(env.runtime, "docker");
+        assert_eq!(env.docker_socket, None);
+        assert_eq!(
+            env.describe(),
+            "inside a docker container (no Docker socket)"
+        );
+    }
+
+    #[test]
+    fn test_detect_from_cgroup() {
+        assert_eq!(
+            cgroup_runtime("12:pids:/docker/3f2a9c\n0::/docker/3f2a9c\n"),
+            Some("docker")
+        );
+        assert_eq!(
+            cgroup_runtime("0::/kubepods/burstable/pod1234/abcd\n"),
+            Some("kubernetes")
+        );
+        assert_eq!(cgroup_runtime("0::/user.slice/session-1.scope\n"), None);
+        // A "docker" in the controller list isn't a container path
+        assert_eq!(cgroup_runtime("0:docker:/\n"), None);
+
+        let root = TempDir::new().unwrap();
+        write(
+            root.path(),
+            "proc/1/cgroup",
+            "0::/system.slice/containerd-abc.scope\n",
+        );
+        assert_eq!(
+            detect_in(root.path(), None, None).unwrap().runtime,
+            "containerd"
+        );
+    }
+
+    #[test]
+    fn test_detect_mounted_docker_socket() {
+        let root = TempDir::new().unwrap();
+        write(root.path(), ".dockerenv", "");
+        write(root.path(), "var/run/docker.sock", "");
+        let env = detect_in(root.path(), None, None).unwrap();
+        assert_eq!(
+            env.docker_socket,
+            Some(PathBuf::from("/var/run/docker.sock"))
+        );
+
+        // DOCKER_HOST points elsewhere
+        let env = detect_in(root.path(), None, Some("unix:///tmp/docker.sock")).unwrap();
+        assert_eq!(env.docker_socket, Some(PathBuf::from("/tmp/docker.sock")));
+    }
+}
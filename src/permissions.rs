@@ -25,6 +25,7 @@ impl SecurityProfile {
         match self {
             SecurityProfile::Permissive => Permissions {
                 network: true,
+                network_mode: None,
                 mount_cwd: true,
                 mount_home: true,
                 pass_env: true,
@@ -33,9 +34,27 @@ impl SecurityProfile {
                 max_memory_mb: None,
                 max_cpu_percent: None,
                 seccomp: Some("default".to_string()),
+                writable_workspace: None,
+                dns: Vec::new(),
+                extra_hosts: Vec::new(),
+                user: None,
+                scratch_mb: None,
+                tmp_size_mb: None,
+                run_size_mb: None,
+                ulimits: Ulimits::default(),
+                agent_port: None,
+                egress: None,
+                env: Vec::new(),
+                restart: crate::backend::RestartPolicy::Never,
+                init: None,
+                hostname: None,
+                max_output_bytes: None,
+                gpus: None,
+                mounts: Vec::new(),
             },
             SecurityProfile::Moderate => Permissions {
                 network: true,
+                network_mode: None,
                 mount_cwd: false,
                 mount_home: false,
                 pass_env: false,
@@ -44,9 +63,30 @@ impl SecurityProfile {
                 max_memory_mb: Some(512),
                 max_cpu_percent: Some(100),
                 seccomp: Some("moderate".to_string()),
+                writable_workspace: None,
+                dns: Vec::new(),
+                extra_hosts: Vec::new(),
+                user: None,
+                scratch_mb: None,
+                tmp_size_mb: None,
+                run_size_mb: None,
+                ulimits: Ulimits {
+                    core: Some(UlimitPair::new(0, 0)),
+                    ..Default::default()
+                },
+                agent_port: None,
+                egress: None,
+                env: Vec::new(),
+                restart: crate::backend::RestartPolicy::Never,
+                init: None,
+                hostname: None,
+                max_output_bytes: None,
+                gpus: None,
+                mounts: Vec::new(),
             },
             SecurityProfile::Restrictive => Permissions {
                 network: false,
+                network_mode: None,
                 mount_cwd: false,
                 mount_home: false,
                 pass_env: false,
@@ -55,6 +95,28 @@ impl SecurityProfile {
                 max_memory_mb: Some(256),
                 max_cpu_percent: Some(50),
                 seccomp: Some("restrictive".to_string()),
+                writable_workspace: None,
+                dns: Vec::new(),
+                extra_hosts: Vec::new(),
+                user: Some(NOBODY_USER.to_string()),
+                scratch_mb: None,
+                tmp_size_mb: Some(RESTRICTIVE_TMP_MB),
+                run_size_mb: None,
+                ulimits: Ulimits {
+                    nofile: Some(UlimitPair::new(4096, 4096)),
+                    nproc: Some(UlimitPair::new(1024, 1024)),
+                    fsize: None,
+                    core: Some(UlimitPair::new(0, 0)),
+                },
+                agent_port: None,
+                egress: None,
+                env: Vec::new(),
+                restart: crate::backend::RestartPolicy::Never,
+                init: None,
+                hostname: None,
+                max_output_bytes: None,
+                gpus: None,
+                mounts: Vec::new(),
             },
             SecurityProfile::Custom => Permissions::default(),
         }
@@ -78,6 +140,9 @@ impl SecurityProfile {
 pub struct Permissions {
     /// Allow network access
     pub network: bool,
+    /// Network to join when `network` is on (None = the runtime's default bridge)
+    #[serde(default)]
+    pub network_mode: Option<NetworkMode>,
     /// Mount current working directory
     pub mount_cwd: bool,
     /// Mount home directory (read-only)
@@ -94,6 +159,567 @@ pub struct Permissions {
     pub max_cpu_percent: Option<u32>,
     /// Seccomp profile to use (None = Docker default, or "default", "moderate", "restrictive", "ai-agent")
     pub seccomp: Option<String>,
+    /// Writable path kept available when the root filesystem is read-only (e.g. "/workspace")
+    #[serde(default)]
+    pub writable_workspace: Option<String>,
+    /// DNS servers (IP addresses); empty uses the backend default
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Extra /etc/hosts entries
+    #[serde(default)]
+    pub extra_hosts: Vec<(String, std::net::IpAddr)>,
+    /// User to run as: `uid[:gid]`, `name[:group]` or `current` (None = image default)
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Size in MB of the writable scratch space at the workspace (disk on
+    /// Firecracker, tmpfs on Docker)
+    #[serde(default)]
+    pub scratch_mb: Option<u64>,
+    /// Size in MB of a tmpfs at /tmp (None = /tmp on the root filesystem,
+    /// or 64 MB when the root is read-only)
+    #[serde(default)]
+    pub tmp_size_mb: Option<u64>,
+    /// Size in MB of a tmpfs at /run (None = /run on the root filesystem)
+    #[serde(default)]
+    pub run_size_mb: Option<u64>,
+    /// Resource limits (`ulimit`) for processes in the sandbox
+    #[serde(default)]
+    pub ulimits: Ulimits,
+    /// Vsock port of the guest agent (None = `AGENTKERNEL_AGENT_PORT` or 52000)
+    #[serde(default)]
+    pub agent_port: Option<u32>,
+    /// Outbound destinations allowed when networking is on (None = unrestricted)
+    #[serde(default)]
+    pub egress: Option<crate::egress::EgressAllowlist>,
+    /// Environment variables to set in the sandbox
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Restart policy for named sandboxes that exit on their own
+    #[serde(default)]
+    pub restart: crate::backend::RestartPolicy,
+    /// Run an init that reaps orphaned processes (None = on for persistent
+    /// container sandboxes, off for one-shot runs)
+    #[serde(default)]
+    pub init: Option<bool>,
+    /// Hostname inside the sandbox (None = the sandbox name)
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// GPUs passed to the sandbox (container backends; None = no GPU access)
+    #[serde(default)]
+    pub gpus: Option<Gpus>,
+    /// Host files bound read-only into the sandbox
+    #[serde(default)]
+    pub mounts: Vec<FileMount>,
+}
+
+/// Unprivileged user for the restrictive profile. Numeric so it works even
+/// when the image has no passwd entry for it.
+pub const NOBODY_USER: &str = "65534:65534";
+
+/// Validate a user spec: `current`, or `user[:group]` where each part is a
+/// numeric id or a plain name.
+pub fn validate_user(spec: &str) -> anyhow::Result<()> {
+    if spec == "current" {
+        return Ok(());
+    }
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    for part in std::iter::once(user).chain(group) {
+        let valid = !part.is_empty()
+            && part.len() <= 32
+            && !part.starts_with('-')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid {
+            anyhow::bail!(
+                "Invalid user '{}': expected uid[:gid], name[:group] or 'current'",
+                spec
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a user spec for a backend: `current` becomes the host's `uid:gid`.
+pub fn resolve_user(spec: &str) -> String {
+    #[cfg(unix)]
+    if spec == "current" {
+        return unsafe { format!("{}:{}", libc::getuid(), libc::getgid()) };
+    }
+    spec.to_string()
+}
+
+/// Read-only `-v source:target:ro` arguments binding `[[mounts]]` files into
+/// a Docker/Podman or Apple container. The mounts must already be
+/// [resolved](FileMount::resolve).
+pub fn mount_args(mounts: &[FileMount]) -> Vec<String> {
+    mounts
+        .iter()
+        .flat_map(|mount| {
+            [
+                "-v".to_string(),
+                format!("{}:{}:ro", mount.source, mount.target),
+            ]
+        })
+        .collect()
+}
+
+/// A single host file bound read-only into the sandbox, e.g. credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMount {
+    /// Path on the host: absolute, or starting with `~/`
+    pub source: String,
+    /// Absolute path inside the sandbox
+    pub target: String,
+}
+
+impl FileMount {
+    /// Check the paths without touching the host file, which may not exist yet
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.source.starts_with('/') && !self.source.starts_with("~/") {
+            anyhow::bail!(
+                "Mount source must be an absolute path or start with ~/, got '{}'",
+                self.source
+            );
+        }
+        if !self.target.starts_with('/') {
+            anyhow::bail!(
+                "Mount target must be an absolute path, got '{}'",
+                self.target
+            );
+        }
+        for path in [&self.source, &self.target] {
+            // `:` separates the parts of a Docker volume spec
+            if path.contains(':') || path.chars().any(|c| c.is_control()) {
+                anyhow::bail!("Mount path '{}' contains ':' or control characters", path);
+            }
+            if std::path::Path::new(path)
+                .components()
+                .any(|c| c == std::path::Component::ParentDir)
+            {
+                anyhow::bail!("Mount path cannot contain '..': {}", path);
+            }
+        }
+        Ok(())
+    }
+
+    /// The mount with `~/` expanded, failing unless the source is a regular file
+    pub fn resolve(&self) -> anyhow::Result<Self> {
+        self.validate()?;
+        let source = match self.source.strip_prefix("~/") {
+            Some(rest) => {
+                let home = std::env::var_os("HOME")
+                    .ok_or_else(|| anyhow::anyhow!("HOME is not set, so ~ can't be expanded"))?;
+                std::path::Path::new(&home).join(rest)
+            }
+            None => std::path::PathBuf::from(&self.source),
+        };
+        if !source.is_file() {
+            anyhow::bail!(
+                "Mount source {} is not a file (only single files can be mounted)",
+                source.display()
+            );
+        }
+        Ok(Self {
+            source: source.to_string_lossy().into_owned(),
+            target: self.target.clone(),
+        })
+    }
+}
+
+/// How a sandbox is networked: `none`, `bridge`, `host`, or the name of a
+/// network created with `docker network create`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum NetworkMode {
+    /// No network access
+    None,
+    /// The runtime's default bridge network
+    Bridge,
+    /// The host's network stack, with no isolation
+    Host,
+    /// A user-defined network
+    Named(String),
+}
+
+impl NetworkMode {
+    /// The mode the `network` boolean stands for
+    pub fn from_enabled(enabled: bool) -> Self {
+        if enabled {
+            NetworkMode::Bridge
+        } else {
+            NetworkMode::None
+        }
+    }
+
+    /// Whether the sandbox has any network access
+    pub fn is_enabled(&self) -> bool {
+        *self != NetworkMode::None
+    }
+
+    /// `--network` argument for Docker/Podman
+    pub fn docker_flag(&self) -> String {
+        format!("--network={}", self)
+    }
+}
+
+impl std::str::FromStr for NetworkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "none" => Ok(NetworkMode::None),
+            "bridge" => Ok(NetworkMode::Bridge),
+            "host" => Ok(NetworkMode::Host),
+            // Other `--network` forms like `container:<id>` aren't network names
+            name if !name.is_empty()
+                && !name.starts_with('-')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) =>
+            {
+                Ok(NetworkMode::Named(name.to_string()))
+            }
+            _ => anyhow::bail!(
+                "Invalid network mode '{}': expected none, bridge, host, or a network name",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Bridge => write!(f, "bridge"),
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl TryFrom<String> for NetworkMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<NetworkMode> for String {
+    fn from(mode: NetworkMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// `--gpus` argument, if GPU access was requested
+pub fn gpu_arg(gpus: Option<&Gpus>) -> Option<String> {
+    gpus.map(Gpus::docker_flag)
+}
+
+/// `--network` argument: none when the network is off, the configured mode
+/// if there is one, otherwise nothing so the runtime default bridge is used
+pub fn network_arg(network: bool, network_mode: Option<&NetworkMode>) -> Option<String> {
+    match network_mode {
+        _ if !network => Some(NetworkMode::None.docker_flag()),
+        Some(mode) => Some(mode.docker_flag()),
+        None => None,
+    }
+}
+
+/// `--dns` and `--add-host` arguments for DNS servers and /etc/hosts entries
+pub fn dns_args(dns: &[String], extra_hosts: &[(String, std::net::IpAddr)]) -> Vec<String> {
+    let mut args: Vec<String> = dns
+        .iter()
+        .map(|server| format!("--dns={}", server))
+        .collect();
+    args.extend(
+        extra_hosts
+            .iter()
+            .map(|(host, ip)| format!("--add-host={}:{}", host, ip)),
+    );
+    args
+}
+
+/// GPUs passed to a container: `"all"`, a count, or specific devices
+/// (`"device=0,1"`, by index or UUID)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "GpusValue", into = "String")]
+pub enum Gpus {
+    All,
+    Count(u32),
+    Devices(Vec<String>),
+}
+
+impl Gpus {
+    /// `--gpus` argument for Docker/Podman
+    pub fn docker_flag(&self) -> String {
+        match self {
+            // The value is parsed as CSV, so a device list has to be quoted
+            Gpus::Devices(_) => format!("--gpus=\"{}\"", self),
+            _ => format!("--gpus={}", self),
+        }
+    }
+}
+
+impl std::str::FromStr for Gpus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid gpus '{}': expected \"all\", a count, or \"device=<id>[,<id>...]\"",
+                s
+            )
+        };
+        if s == "all" {
+            return Ok(Gpus::All);
+        }
+        if let Some(ids) = s.strip_prefix("device=") {
+            let ids: Vec<String> = ids.split(',').map(|id| id.trim().to_string()).collect();
+            let valid_id = |id: &String| {
+                !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            };
+            if !ids.iter().all(valid_id) {
+                return Err(invalid());
+            }
+            return Ok(Gpus::Devices(ids));
+        }
+        match s.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(Gpus::Count(n)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Gpus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Gpus::All => write!(f, "all"),
+            Gpus::Count(n) => write!(f, "{}", n),
+            Gpus::Devices(ids) => write!(f, "device={}", ids.join(",")),
+        }
+    }
+}
+
+/// How GPUs are written in config: `2` or `"all"`/`"device=0,1"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GpusValue {
+    Number(u32),
+    Text(String),
+}
+
+impl TryFrom<GpusValue> for Gpus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: GpusValue) -> anyhow::Result<Self> {
+        match value {
+            GpusValue::Number(n) => n.to_string().parse(),
+            GpusValue::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl From<Gpus> for String {
+    fn from(gpus: Gpus) -> Self {
+        gpus.to_string()
+    }
+}
+
+/// A soft:hard limit pair, written `"soft:hard"` or a single number for both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UlimitValue", into = "String")]
+pub struct UlimitPair {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl UlimitPair {
+    pub const fn new(soft: u64, hard: u64) -> Self {
+        Self { soft, hard }
+    }
+}
+
+impl std::str::FromStr for UlimitPair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parse = |n: &str| {
+            n.trim().parse::<u64>().map_err(|_| {
+                anyhow::anyhow!("Invalid ulimit '{}': expected a number or soft:hard", s)
+            })
+        };
+        let pair = match s.split_once(':') {
+            Some((soft, hard)) => Self::new(parse(soft)?, parse(hard)?),
+            None => {
+                let n = parse(s)?;
+                Self::new(n, n)
+            }
+        };
+        if pair.soft > pair.hard {
+            anyhow::bail!("Invalid ulimit '{}': soft limit is above the hard limit", s);
+        }
+        Ok(pair)
+    }
+}
+
+impl std::fmt::Display for UlimitPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.soft, self.hard)
+    }
+}
+
+/// How a limit is written in config: `4096` or `"1024:4096"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UlimitValue {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<UlimitValue> for UlimitPair {
+    type Error = anyhow::Error;
+
+    fn try_from(value: UlimitValue) -> anyhow::Result<Self> {
+        match value {
+            UlimitValue::Number(n) => Ok(Self::new(n, n)),
+            UlimitValue::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl From<UlimitPair> for String {
+    fn from(pair: UlimitPair) -> Self {
+        pair.to_string()
+    }
+}
+
+/// Open files limit for node images; see [`Ulimits::for_image`]
+pub const NODE_NOFILE: UlimitPair = UlimitPair::new(65536, 65536);
+
+/// Per-process resource limits. Unset ones keep the backend default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ulimits {
+    /// Open files
+    #[serde(default)]
+    pub nofile: Option<UlimitPair>,
+    /// Processes (counted per user)
+    #[serde(default)]
+    pub nproc: Option<UlimitPair>,
+    /// Largest file written, in bytes
+    #[serde(default)]
+    pub fsize: Option<UlimitPair>,
+    /// Core dump size, in bytes (0 disables core dumps)
+    #[serde(default)]
+    pub core: Option<UlimitPair>,
+}
+
+impl Ulimits {
+    /// The limits that are set, by their `ulimit` name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, UlimitPair)> {
+        [
+            ("nofile", self.nofile),
+            ("nproc", self.nproc),
+            ("fsize", self.fsize),
+            ("core", self.core),
+        ]
+        .into_iter()
+        .filter_map(|(name, pair)| Some((name, pair?)))
+    }
+
+    /// Replace the limits that `other` sets
+    pub fn merge(&mut self, other: &Ulimits) {
+        self.nofile = other.nofile.or(self.nofile);
+        self.nproc = other.nproc.or(self.nproc);
+        self.fsize = other.fsize.or(self.fsize);
+        self.core = other.core.or(self.core);
+    }
+
+    /// The limits for a sandbox running `image`.
+    ///
+    /// Node.js tools like `npm install` run out of open files at the usual
+    /// 1024, so node images get [`NODE_NOFILE`] unless `nofile` is set.
+    pub fn for_image(mut self, image: &str) -> Self {
+        if crate::languages::docker_image_to_firecracker_runtime(image) == "node" {
+            self.nofile = self.nofile.or(Some(NODE_NOFILE));
+        }
+        self
+    }
+
+    /// `--ulimit` arguments for `docker run`
+    pub fn docker_args(&self) -> Vec<String> {
+        self.iter()
+            .map(|(name, pair)| format!("--ulimit={}={}", name, pair))
+            .collect()
+    }
+}
+
+/// Size of /tmp under the restrictive profile and on a read-only root, so
+/// an untrusted agent can't fill the host disk or memory through it
+pub const RESTRICTIVE_TMP_MB: u64 = 64;
+
+/// Default path for the writable workspace on a read-only root
+pub const DEFAULT_WORKSPACE_PATH: &str = "/workspace";
+
+/// `--tmpfs` argument for the writable workspace, if one is needed.
+///
+/// A read-only root keeps the writable workspace on a tmpfs, and
+/// `scratch_mb` sizes it (adding one at the workspace if there isn't one).
+/// Nothing is added when a project directory is bind-mounted there.
+pub fn workspace_tmpfs_arg(
+    read_only_root: bool,
+    writable_workspace: Option<&str>,
+    scratch_mb: Option<u64>,
+    cwd_mounted: bool,
+) -> Option<String> {
+    let path = match (writable_workspace, scratch_mb) {
+        (Some(path), _) if read_only_root => path,
+        (path, Some(_)) => path.unwrap_or(DEFAULT_WORKSPACE_PATH),
+        _ => return None,
+    };
+    if cwd_mounted && path == DEFAULT_WORKSPACE_PATH {
+        return None;
+    }
+    let size = scratch_mb
+        .map(|mb| format!(",size={}m", mb))
+        .unwrap_or_default();
+    Some(format!("--tmpfs={}:rw,exec,nosuid{}", path, size))
+}
+
+/// Size in MB of the tmpfs at /tmp, if there is one.
+///
+/// A read-only root always gets a /tmp so programs can still write temp
+/// files, bounded to [`RESTRICTIVE_TMP_MB`] unless `tmp_size_mb` is set.
+pub fn tmp_size_mb(read_only_root: bool, tmp_size_mb: Option<u64>) -> Option<u64> {
+    match tmp_size_mb {
+        Some(mb) => Some(mb),
+        None if read_only_root => Some(RESTRICTIVE_TMP_MB),
+        None => None,
+    }
+}
+
+/// `--tmpfs` arguments for /tmp and /run.
+///
+/// /tmp is `noexec`, as it is in Firecracker guests.
+pub fn tmpfs_args(
+    read_only_root: bool,
+    tmp_size_mb: Option<u64>,
+    run_size_mb: Option<u64>,
+) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(mb) = self::tmp_size_mb(read_only_root, tmp_size_mb) {
+        args.push(format!("--tmpfs=/tmp:rw,noexec,nosuid,size={}m", mb));
+    }
+    if let Some(mb) = run_size_mb {
+        args.push(format!("--tmpfs=/run:rw,nosuid,size={}m", mb));
+    }
+    args
 }
 
 impl Default for Permissions {
@@ -103,6 +729,39 @@ impl Default for Permissions {
 }
 
 impl Permissions {
+    /// The network the sandbox joins. `network = false` always means none,
+    /// so turning the network off (e.g. `--no-network`) wins over the mode.
+    pub fn network_mode(&self) -> NetworkMode {
+        match self.network_mode {
+            Some(ref mode) if self.network => mode.clone(),
+            _ => NetworkMode::from_enabled(self.network),
+        }
+    }
+
+    /// Set the network mode, keeping `network` in step with it
+    pub fn set_network_mode(&mut self, mode: NetworkMode) {
+        self.network = mode.is_enabled();
+        self.network_mode = Some(mode);
+    }
+
+    /// Apply requested vCPUs and memory to the limits.
+    ///
+    /// Explicitly requested sizes (flags or a preset) replace the profile's
+    /// limits; sizes from a project config can only tighten them.
+    pub fn apply_resources(&mut self, vcpus: u32, memory_mb: u64, explicit: bool) {
+        let cpu_percent = vcpus * 100;
+        if explicit {
+            self.max_memory_mb = Some(memory_mb);
+            self.max_cpu_percent = Some(cpu_percent);
+        } else {
+            self.max_memory_mb = Some(self.max_memory_mb.map_or(memory_mb, |m| m.min(memory_mb)));
+            self.max_cpu_percent = Some(
+                self.max_cpu_percent
+                    .map_or(cpu_percent, |c| c.min(cpu_percent)),
+            );
+        }
+    }
+
     /// Resolve seccomp profile path from name or path
     ///
     /// Built-in profiles: "default", "moderate", "restrictive", "ai-agent"
@@ -148,14 +807,31 @@ impl Permissions {
         }
     }
 
+    /// `--tmpfs` argument for the workspace, if one is needed.
+    ///
+    /// `cwd` is the directory bind-mounted at the workspace, as passed to
+    /// [`Self::get_mount_args`]; see [`workspace_tmpfs_arg`].
+    pub fn workspace_tmpfs_arg(&self, cwd: Option<&str>) -> Option<String> {
+        workspace_tmpfs_arg(
+            self.read_only_root,
+            self.writable_workspace.as_deref(),
+            self.scratch_mb,
+            self.mount_cwd && cwd.is_some(),
+        )
+    }
+
+    /// `--tmpfs` arguments for /tmp and /run; see [`tmpfs_args`]
+    pub fn tmpfs_args(&self) -> Vec<String> {
+        tmpfs_args(self.read_only_root, self.tmp_size_mb, self.run_size_mb)
+    }
+
     /// Convert permissions to Docker run arguments
     pub fn to_docker_args(&self) -> Vec<String> {
         let mut args = Vec::new();
 
-        // Network
-        if !self.network {
-            args.push("--network=none".to_string());
-        }
+        args.extend(network_arg(self.network, self.network_mode.as_ref()));
+
+        args.extend(gpu_arg(self.gpus.as_ref()));
 
         // Memory limit
         if let Some(mem) = self.max_memory_mb {
@@ -173,8 +849,15 @@ impl Permissions {
         // Read-only root
         if self.read_only_root {
             args.push("--read-only".to_string());
-            // Add tmpfs for /tmp so programs can still write temp files
-            args.push("--tmpfs=/tmp:rw,noexec,nosuid,size=64m".to_string());
+        }
+        args.extend(self.tmpfs_args());
+
+        // DNS servers and host entries
+        args.extend(dns_args(&self.dns, &self.extra_hosts));
+
+        // Run as a non-root user
+        if let Some(ref user) = self.user {
+            args.push(format!("--user={}", resolve_user(user)));
         }
 
         // Security options (always apply some baseline security)
@@ -208,6 +891,10 @@ impl Permissions {
                 }
             }
         }
+        for (key, value) in &self.env {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
 
         args
     }
@@ -482,6 +1169,30 @@ mod tests {
         assert!(restrictive.read_only_root);
     }
 
+    #[test]
+    fn test_apply_resources() {
+        // A project config can't loosen --profile restrictive
+        let mut perms = SecurityProfile::Restrictive.permissions();
+        perms.apply_resources(4, 4096, false);
+        assert_eq!(perms.max_memory_mb, Some(256));
+        assert_eq!(perms.max_cpu_percent, Some(50));
+        perms.apply_resources(1, 128, false);
+        assert_eq!(perms.max_memory_mb, Some(128));
+        assert_eq!(perms.max_cpu_percent, Some(50));
+
+        // Explicit flags or a preset replace the limits
+        let mut perms = SecurityProfile::Restrictive.permissions();
+        perms.apply_resources(4, 4096, true);
+        assert_eq!(perms.max_memory_mb, Some(4096));
+        assert_eq!(perms.max_cpu_percent, Some(400));
+
+        // No profile limit: the config sets one
+        let mut perms = SecurityProfile::Permissive.permissions();
+        perms.apply_resources(2, 1024, false);
+        assert_eq!(perms.max_memory_mb, Some(1024));
+        assert_eq!(perms.max_cpu_percent, Some(200));
+    }
+
     #[test]
     fn test_docker_args() {
         let restrictive = SecurityProfile::Restrictive.permissions();
@@ -491,6 +1202,139 @@ mod tests {
         assert!(args.contains(&"--read-only".to_string()));
     }
 
+    #[test]
+    fn test_network_mode_parse() {
+        assert_eq!("none".parse::<NetworkMode>().unwrap(), NetworkMode::None);
+        assert_eq!(
+            "bridge".parse::<NetworkMode>().unwrap(),
+            NetworkMode::Bridge
+        );
+        assert_eq!("host".parse::<NetworkMode>().unwrap(), NetworkMode::Host);
+        assert_eq!(
+            "ci_net".parse::<NetworkMode>().unwrap(),
+            NetworkMode::Named("ci_net".to_string())
+        );
+        for bad in ["", "container:db", "--privileged", "a b"] {
+            assert!(bad.parse::<NetworkMode>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_gpus_parse() {
+        assert_eq!("all".parse::<Gpus>().unwrap(), Gpus::All);
+        assert_eq!("2".parse::<Gpus>().unwrap(), Gpus::Count(2));
+        assert_eq!(
+            "device=0,GPU-3a2b".parse::<Gpus>().unwrap(),
+            Gpus::Devices(vec!["0".to_string(), "GPU-3a2b".to_string()])
+        );
+        for bad in [
+            "",
+            "0",
+            "none",
+            "device=",
+            "device=0,",
+            "device=0;rm",
+            "--privileged",
+        ] {
+            assert!(bad.parse::<Gpus>().is_err(), "{}", bad);
+        }
+
+        // A count can be written as a TOML integer
+        #[derive(Deserialize)]
+        struct Section {
+            gpus: Gpus,
+        }
+        let section: Section = toml::from_str("gpus = 1").unwrap();
+        assert_eq!(section.gpus, Gpus::Count(1));
+        assert!(toml::from_str::<Section>("gpus = 0").is_err());
+    }
+
+    #[test]
+    fn test_file_mount_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("token");
+        std::fs::write(&file, "secret").unwrap();
+        let mount = |source: &std::path::Path| FileMount {
+            source: source.to_string_lossy().into_owned(),
+            target: "/run/secrets/token".to_string(),
+        };
+
+        let resolved = mount(&file).resolve().unwrap();
+        assert_eq!(
+            mount_args(&[resolved]),
+            vec![
+                "-v".to_string(),
+                format!("{}:/run/secrets/token:ro", file.display())
+            ]
+        );
+
+        // Only existing regular files can be mounted
+        assert!(mount(dir.path()).resolve().is_err());
+        assert!(mount(&dir.path().join("missing")).resolve().is_err());
+        assert!(mount(std::path::Path::new("token")).resolve().is_err());
+    }
+
+    #[test]
+    fn test_gpus_docker_flag() {
+        assert_eq!(Gpus::All.docker_flag(), "--gpus=all");
+        assert_eq!(Gpus::Count(2).docker_flag(), "--gpus=2");
+        assert_eq!(
+            "device=0,1".parse::<Gpus>().unwrap().docker_flag(),
+            "--gpus=\"device=0,1\""
+        );
+        assert_eq!(gpu_arg(None), None);
+        assert_eq!(gpu_arg(Some(&Gpus::Count(2))).as_deref(), Some("--gpus=2"));
+
+        let perms = Permissions {
+            gpus: Some(Gpus::All),
+            ..Default::default()
+        };
+        assert!(perms.to_docker_args().contains(&"--gpus=all".to_string()));
+        assert!(
+            !Permissions::default()
+                .to_docker_args()
+                .iter()
+                .any(|a| a.starts_with("--gpus"))
+        );
+    }
+
+    #[test]
+    fn test_docker_args_network_mode() {
+        let network_args = |perms: &Permissions| -> Vec<String> {
+            perms
+                .to_docker_args()
+                .into_iter()
+                .filter(|a| a.starts_with("--network"))
+                .collect()
+        };
+
+        // Each mode maps to its own --network flag
+        for (mode, flag) in [
+            (NetworkMode::None, "--network=none"),
+            (NetworkMode::Bridge, "--network=bridge"),
+            (NetworkMode::Host, "--network=host"),
+            (NetworkMode::Named("ci".to_string()), "--network=ci"),
+        ] {
+            let mut perms = Permissions::default();
+            perms.set_network_mode(mode.clone());
+            assert_eq!(perms.network, mode.is_enabled());
+            assert_eq!(network_args(&perms), vec![flag]);
+        }
+
+        // The boolean alone: false is none, true keeps the default bridge
+        let mut perms = Permissions::default();
+        assert_eq!(perms.network_mode(), NetworkMode::Bridge);
+        assert!(network_args(&perms).is_empty());
+        perms.network = false;
+        assert_eq!(perms.network_mode(), NetworkMode::None);
+        assert_eq!(network_args(&perms), vec!["--network=none"]);
+
+        // Turning the network off wins over a mode
+        perms.set_network_mode(NetworkMode::Host);
+        perms.network = false;
+        assert_eq!(network_args(&perms), vec!["--network=none"]);
+    }
+
     #[test]
     fn test_compatibility_modes() {
         assert_eq!(
@@ -606,6 +1450,257 @@ mod tests {
         assert!(perms.resolve_seccomp_path().is_none());
     }
 
+    #[test]
+    fn test_docker_args_read_only_with_writable_workspace() {
+        let perms = Permissions {
+            read_only_root: true,
+            writable_workspace: Some(DEFAULT_WORKSPACE_PATH.to_string()),
+            ..SecurityProfile::Restrictive.permissions()
+        };
+        let args = perms.to_docker_args();
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(args.contains(&"--tmpfs=/workspace:rw,exec,nosuid".to_string()));
+
+        // Without read-only root, the workspace is writable anyway
+        let perms = Permissions {
+            read_only_root: false,
+            writable_workspace: Some(DEFAULT_WORKSPACE_PATH.to_string()),
+            ..Default::default()
+        };
+        let args = perms.to_docker_args();
+        assert!(!args.iter().any(|a| a.starts_with("--tmpfs=/workspace")));
+    }
+
+    #[test]
+    fn test_docker_args_writable_workspace_skipped_for_cwd_mount() {
+        // The project bind mount at /workspace is already writable
+        let perms = Permissions {
+            read_only_root: true,
+            mount_cwd: true,
+            writable_workspace: Some(DEFAULT_WORKSPACE_PATH.to_string()),
+            ..Default::default()
+        };
+        let args = perms.to_docker_args();
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(!args.iter().any(|a| a.starts_with("--tmpfs=/workspace")));
+    }
+
+    #[test]
+    fn test_docker_args_dns_and_hosts() {
+        let perms = Permissions {
+            dns: vec!["1.1.1.1".to_string()],
+            extra_hosts: vec![("mock.local".to_string(), "10.0.0.5".parse().unwrap())],
+            ..Default::default()
+        };
+        let args = perms.to_docker_args();
+        assert!(args.contains(&"--dns=1.1.1.1".to_string()));
+        assert!(args.contains(&"--add-host=mock.local:10.0.0.5".to_string()));
+
+        // Nothing added by default
+        let args = Permissions::default().to_docker_args();
+        assert!(
+            !args
+                .iter()
+                .any(|a| a.starts_with("--dns") || a.starts_with("--add-host"))
+        );
+    }
+
+    #[test]
+    fn test_docker_args_tmp_tmpfs() {
+        // Writable root: /tmp stays on the root filesystem
+        let perms = Permissions::default();
+        assert!(perms.tmpfs_args().is_empty());
+
+        let perms = Permissions {
+            tmp_size_mb: Some(256),
+            run_size_mb: Some(16),
+            ..Default::default()
+        };
+        assert_eq!(
+            perms.tmpfs_args(),
+            vec![
+                "--tmpfs=/tmp:rw,noexec,nosuid,size=256m",
+                "--tmpfs=/run:rw,nosuid,size=16m"
+            ]
+        );
+
+        // A read-only root gets a bounded /tmp either way
+        let perms = Permissions {
+            read_only_root: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            perms.tmpfs_args(),
+            vec!["--tmpfs=/tmp:rw,noexec,nosuid,size=64m"]
+        );
+
+        let args = SecurityProfile::Restrictive.permissions().to_docker_args();
+        assert_eq!(
+            args.iter()
+                .filter(|a| a.starts_with("--tmpfs=/tmp"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_docker_args_ulimits() {
+        let perms = Permissions {
+            ulimits: Ulimits {
+                nofile: Some(UlimitPair::new(1024, 4096)),
+                fsize: Some(UlimitPair::new(1 << 30, 1 << 30)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            perms.ulimits.docker_args(),
+            vec![
+                "--ulimit=nofile=1024:4096",
+                "--ulimit=fsize=1073741824:1073741824"
+            ]
+        );
+
+        // Profiles turn off core dumps; permissive keeps the Docker defaults
+        let moderate = SecurityProfile::Moderate.permissions().ulimits;
+        assert_eq!(moderate.docker_args(), vec!["--ulimit=core=0:0"]);
+        let permissive = SecurityProfile::Permissive.permissions().ulimits;
+        assert!(permissive.docker_args().is_empty());
+    }
+
+    #[test]
+    fn test_ulimits_for_image() {
+        // Only node images get more open files
+        let moderate = SecurityProfile::Moderate.permissions().ulimits;
+        assert_eq!(
+            moderate.for_image("node:22-alpine").nofile,
+            Some(NODE_NOFILE)
+        );
+        assert_eq!(moderate.for_image("python:3.12-alpine").nofile, None);
+        assert_eq!(moderate.for_image("alpine:3.20"), moderate);
+
+        // A configured limit wins
+        let restrictive = SecurityProfile::Restrictive.permissions().ulimits;
+        assert_eq!(
+            restrictive.for_image("node:22-alpine").nofile,
+            Some(UlimitPair::new(4096, 4096))
+        );
+    }
+
+    #[test]
+    fn test_ulimit_pair_parse() {
+        assert_eq!(
+            "1024:4096".parse::<UlimitPair>().unwrap(),
+            UlimitPair::new(1024, 4096)
+        );
+        assert_eq!(
+            "2048".parse::<UlimitPair>().unwrap(),
+            UlimitPair::new(2048, 2048)
+        );
+        assert_eq!(UlimitPair::new(0, 0).to_string(), "0:0");
+        for bad in ["", "4096:1024", "1024:", ":4096", "lots", "-1", "1:2:3"] {
+            assert!(
+                bad.parse::<UlimitPair>().is_err(),
+                "{} should be rejected",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_ulimits_merge() {
+        let mut limits = SecurityProfile::Restrictive.permissions().ulimits;
+        limits.merge(&Ulimits {
+            nofile: Some(UlimitPair::new(8192, 8192)),
+            ..Default::default()
+        });
+        assert_eq!(limits.nofile, Some(UlimitPair::new(8192, 8192)));
+        assert_eq!(limits.core, Some(UlimitPair::new(0, 0)));
+    }
+
+    #[test]
+    fn test_docker_args_scratch_tmpfs() {
+        let perms = Permissions {
+            scratch_mb: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            perms.workspace_tmpfs_arg(None).as_deref(),
+            Some("--tmpfs=/workspace:rw,exec,nosuid,size=1024m")
+        );
+        assert!(!perms.to_docker_args().contains(&"--read-only".to_string()));
+
+        // On a read-only root the writable workspace gets the size
+        let perms = Permissions {
+            read_only_root: true,
+            writable_workspace: Some("/data".to_string()),
+            scratch_mb: Some(256),
+            ..Default::default()
+        };
+        assert_eq!(
+            perms.workspace_tmpfs_arg(None).as_deref(),
+            Some("--tmpfs=/data:rw,exec,nosuid,size=256m")
+        );
+
+        // The bind-mounted project already provides the workspace, but
+        // `mount_cwd` alone doesn't mount anything
+        let perms = Permissions {
+            mount_cwd: true,
+            scratch_mb: Some(256),
+            ..Default::default()
+        };
+        assert!(
+            perms
+                .workspace_tmpfs_arg(Some("/home/me/project"))
+                .is_none()
+        );
+        assert!(perms.workspace_tmpfs_arg(None).is_some());
+    }
+
+    #[test]
+    fn test_docker_args_user() {
+        let perms = Permissions {
+            user: Some("1000:1000".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            perms
+                .to_docker_args()
+                .contains(&"--user=1000:1000".to_string())
+        );
+
+        // Restrictive runs as nobody; other profiles keep the image default
+        let args = SecurityProfile::Restrictive.permissions().to_docker_args();
+        assert!(args.contains(&format!("--user={}", NOBODY_USER)));
+        let args = Permissions::default().to_docker_args();
+        assert!(!args.iter().any(|a| a.starts_with("--user")));
+    }
+
+    #[test]
+    fn test_resolve_current_user() {
+        let expected = unsafe { format!("{}:{}", libc::getuid(), libc::getgid()) };
+        assert_eq!(resolve_user("current"), expected);
+        assert_eq!(resolve_user("1000:1000"), "1000:1000");
+        assert_eq!(resolve_user("node"), "node");
+    }
+
+    #[test]
+    fn test_validate_user() {
+        for ok in [
+            "current",
+            "1000",
+            "1000:1000",
+            "node",
+            "app:staff",
+            "www-data",
+        ] {
+            assert!(validate_user(ok).is_ok(), "{}", ok);
+        }
+        for bad in ["", ":", "1000:", "-u", "root;id", "a b", "1000:1000:1"] {
+            assert!(validate_user(bad).is_err(), "{}", bad);
+        }
+    }
+
     #[test]
     fn test_docker_args_include_seccomp() {
         // When seccomp profile resolves, it should be included in docker args
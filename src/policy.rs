@@ -0,0 +1,519 @@
+//! Evaluation of the `[security]` policy rules in agentkernel.toml.
+//!
+//! `PolicyEngine::enforce` runs on the execution path and records a
+//! `PolicyViolation` audit event when a request is denied. `simulate` runs the
+//! same evaluation without side effects, so a policy can be checked before it
+//! is rolled out. A denial is returned as a [`PolicyDenial`] error, so callers
+//! can show why a request was refused.
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Once, RwLock};
+
+use crate::audit::{AuditEvent, AuditLog, log_event};
+use crate::config::{Config, SecurityConfig};
+
+/// Env var that turns off command policy enforcement in trusted contexts
+pub const DISABLE_COMMAND_POLICY_ENV: &str = "AGENTKERNEL_DISABLE_COMMAND_POLICY";
+
+/// Command policy scope for this process (see `PolicyScope::set_global`)
+static POLICY_SCOPE: RwLock<PolicyScope> = RwLock::new(PolicyScope::WorkingDir);
+
+/// Logs the first time a command policy is applied
+static POLICY_NOTICE: Once = Once::new();
+
+/// Which agentkernel.toml, if any, the command policy is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyScope {
+    /// `./agentkernel.toml`, when present
+    WorkingDir,
+    /// A config file given explicitly (e.g. `--config`)
+    File(PathBuf),
+    /// No command policy (`--no-policy`)
+    Disabled,
+}
+
+impl PolicyScope {
+    /// Set the scope for the rest of this process
+    pub fn set_global(scope: PolicyScope) {
+        *POLICY_SCOPE.write().unwrap_or_else(|e| e.into_inner()) = scope;
+    }
+
+    /// The scope in effect, honouring `AGENTKERNEL_DISABLE_COMMAND_POLICY`
+    pub fn current() -> PolicyScope {
+        let disabled = std::env::var(DISABLE_COMMAND_POLICY_ENV)
+            .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"));
+        if disabled {
+            return PolicyScope::Disabled;
+        }
+        POLICY_SCOPE
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Narrow the scope for pooled runs, which share containers across
+    /// directories and so only follow an explicitly chosen config
+    pub fn for_pooled(self) -> PolicyScope {
+        match self {
+            PolicyScope::WorkingDir => PolicyScope::Disabled,
+            scope => scope,
+        }
+    }
+
+    /// Load the policy in scope, returning the engine and the file it came from.
+    ///
+    /// No config file means no policy, but one that can't be read or parsed
+    /// is an error, so a broken policy never lets commands through.
+    pub fn engine(&self) -> Result<Option<(PolicyEngine, PathBuf)>> {
+        let path = match self {
+            PolicyScope::WorkingDir => PathBuf::from("agentkernel.toml"),
+            PolicyScope::File(path) => path.clone(),
+            PolicyScope::Disabled => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let engine = PolicyEngine::from_file(&path)
+            .with_context(|| format!("Failed to load command policy from {}", path.display()))?;
+        Ok(Some((engine, path)))
+    }
+
+    /// Check a command against the policy in scope.
+    /// Logs a PolicyViolation audit event and returns an error if blocked.
+    pub fn enforce_command(&self, cmd: &[String], sandbox: &str) -> Result<()> {
+        let Some(binary) = cmd.first() else {
+            return Ok(());
+        };
+        let Some((engine, path)) = self.engine()? else {
+            return Ok(());
+        };
+        POLICY_NOTICE.call_once(|| {
+            eprintln!(
+                "Command policy active: enforcing [security.commands] from {} (disable with --no-policy)",
+                path.display()
+            );
+        });
+        engine.enforce(PolicyAction::Run, binary, sandbox)
+    }
+}
+
+/// Kind of request being evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Run a command (resource is the command line or binary)
+    Run,
+    /// Connect to a network domain (resource is the domain)
+    Connect,
+}
+
+impl FromStr for PolicyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "run" | "exec" => Ok(PolicyAction::Run),
+            "connect" | "network" => Ok(PolicyAction::Connect),
+            _ => Err(format!(
+                "Unknown policy action '{}'. Valid actions: run, connect",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyAction::Run => write!(f, "run"),
+            PolicyAction::Connect => write!(f, "connect"),
+        }
+    }
+}
+
+/// Outcome of a policy evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Permit,
+    Deny,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Permit => write!(f, "PERMIT"),
+            Decision::Deny => write!(f, "DENY"),
+        }
+    }
+}
+
+/// Decision plus the rules that produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyResult {
+    pub decision: Decision,
+    /// Rules that matched, as `section.list = "entry"` (empty for default decisions)
+    pub matched: Vec<String>,
+    /// Human-readable explanation
+    pub reason: String,
+}
+
+impl PolicyResult {
+    pub fn is_permitted(&self) -> bool {
+        self.decision == Decision::Permit
+    }
+}
+
+/// A request refused by the security policy.
+///
+/// Returned (inside `anyhow::Error`) by the enforcing calls; downcast to it to
+/// report the decision in a structured form, as the HTTP API does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyDenial {
+    /// Sandbox the request was made in ("pooled" for the container pool)
+    pub principal: String,
+    pub action: String,
+    /// Command line or domain that was evaluated
+    pub resource: String,
+    /// Rules that matched, as `section.list = "entry"` (empty for allowlist misses)
+    pub matched: Vec<String>,
+    /// Human-readable explanation
+    pub reason: String,
+}
+
+impl fmt::Display for PolicyDenial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (noun, section) = match self.action.as_str() {
+            "connect" => ("Domain", "domains"),
+            _ => ("Command", "commands"),
+        };
+        write!(
+            f,
+            "{} '{}' blocked by security policy: {}",
+            noun, self.resource, self.reason
+        )?;
+        if !self.matched.is_empty() {
+            write!(f, " ({})", self.matched.join(", "))?;
+        }
+        write!(f, ". Check [security.{}] in agentkernel.toml", section)
+    }
+}
+
+impl std::error::Error for PolicyDenial {}
+
+/// Evaluates requests against a set of security rules
+pub struct PolicyEngine {
+    security: SecurityConfig,
+    /// Audit log for enforcement events (the global log when unset)
+    audit: Option<AuditLog>,
+}
+
+impl PolicyEngine {
+    /// Create an engine for the given rules
+    pub fn new(security: SecurityConfig) -> Self {
+        Self {
+            security,
+            audit: None,
+        }
+    }
+
+    /// Load rules from an agentkernel.toml file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Ok(Self::new(Config::from_file(path)?.security))
+    }
+
+    /// Write enforcement events to a specific audit log
+    #[allow(dead_code)]
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Evaluate a request without enforcing it.
+    ///
+    /// Never blocks and never writes audit entries.
+    pub fn simulate(&self, action: PolicyAction, resource: &str) -> PolicyResult {
+        match action {
+            PolicyAction::Run => self.evaluate_command(resource),
+            PolicyAction::Connect => self.evaluate_domain(resource),
+        }
+    }
+
+    /// Evaluate a request and fail if it is denied.
+    ///
+    /// Denials are recorded as `PolicyViolation` audit events.
+    pub fn enforce(&self, action: PolicyAction, resource: &str, sandbox: &str) -> Result<()> {
+        let result = self.simulate(action, resource);
+        if result.is_permitted() {
+            return Ok(());
+        }
+
+        let (policy, details) = match action {
+            PolicyAction::Run => ("commands", format!("blocked command: {}", resource)),
+            PolicyAction::Connect => ("domains", format!("blocked domain: {}", resource)),
+        };
+        let event = AuditEvent::PolicyViolation {
+            sandbox: sandbox.to_string(),
+            policy: policy.to_string(),
+            details,
+        };
+        match &self.audit {
+            Some(audit) => {
+                if let Err(e) = audit.log(event) {
+                    eprintln!("Warning: failed to write audit log: {}", e);
+                }
+            }
+            None => log_event(event),
+        }
+
+        Err(PolicyDenial {
+            principal: sandbox.to_string(),
+            action: action.to_string(),
+            resource: resource.to_string(),
+            matched: result.matched,
+            reason: result.reason,
+        }
+        .into())
+    }
+
+    fn evaluate_command(&self, command: &str) -> PolicyResult {
+        let rules = &self.security.commands;
+        decide(
+            "commands",
+            "command",
+            rules.is_allowed(command),
+            rules.matching_block(command),
+            rules.matching_allow(command),
+        )
+    }
+
+    fn evaluate_domain(&self, domain: &str) -> PolicyResult {
+        let rules = &self.security.domains;
+        decide(
+            "domains",
+            "domain",
+            rules.is_allowed(domain),
+            rules.matching_block(domain),
+            rules.matching_allow(domain),
+        )
+    }
+}
+
+/// Explain an allow/deny decision in terms of the rules that matched
+fn decide(
+    section: &str,
+    noun: &str,
+    allowed: bool,
+    block: Option<&str>,
+    allow: Option<&str>,
+) -> PolicyResult {
+    let mut matched = Vec::new();
+    if let Some(rule) = block {
+        matched.push(format!("security.{}.block = \"{}\"", section, rule));
+    } else if let Some(rule) = allow {
+        matched.push(format!("security.{}.allow = \"{}\"", section, rule));
+    }
+
+    let reason = match (allowed, block, allow) {
+        (false, Some(_), _) => format!("{} is on the block list", noun),
+        (false, None, _) => "allowlist_only is set and no allow rule matches".to_string(),
+        (true, _, Some(_)) => format!("{} is on the allow list", noun),
+        (true, _, None) => format!("no rule matches; {}s are allowed by default", noun),
+    };
+
+    PolicyResult {
+        decision: if allowed {
+            Decision::Permit
+        } else {
+            Decision::Deny
+        },
+        matched,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CommandConfig, DomainConfig};
+    use tempfile::TempDir;
+
+    fn engine() -> PolicyEngine {
+        PolicyEngine::new(SecurityConfig {
+            commands: CommandConfig {
+                allow: vec!["python".to_string()],
+                block: vec!["sudo".to_string()],
+                allowlist_only: false,
+            },
+            domains: DomainConfig {
+                allow: vec!["*.github.com".to_string()],
+                block: vec!["169.254.169.254".to_string()],
+                allowlist_only: true,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_policy_action_from_str() {
+        assert_eq!("run".parse::<PolicyAction>(), Ok(PolicyAction::Run));
+        assert_eq!("exec".parse::<PolicyAction>(), Ok(PolicyAction::Run));
+        assert_eq!("Connect".parse::<PolicyAction>(), Ok(PolicyAction::Connect));
+        assert!("delete".parse::<PolicyAction>().is_err());
+    }
+
+    #[test]
+    fn test_simulate_permit() {
+        let result = engine().simulate(PolicyAction::Run, "/usr/bin/python script.py");
+        assert_eq!(result.decision, Decision::Permit);
+        assert_eq!(result.matched, vec!["security.commands.allow = \"python\""]);
+
+        let result = engine().simulate(PolicyAction::Connect, "api.github.com");
+        assert_eq!(result.decision, Decision::Permit);
+        assert_eq!(
+            result.matched,
+            vec!["security.domains.allow = \"*.github.com\""]
+        );
+    }
+
+    #[test]
+    fn test_simulate_deny() {
+        let result = engine().simulate(PolicyAction::Run, "sudo rm -rf /");
+        assert_eq!(result.decision, Decision::Deny);
+        assert_eq!(result.matched, vec!["security.commands.block = \"sudo\""]);
+
+        let result = engine().simulate(PolicyAction::Connect, "169.254.169.254");
+        assert_eq!(result.decision, Decision::Deny);
+
+        // allowlist_only denies anything not explicitly allowed
+        let result = engine().simulate(PolicyAction::Connect, "example.com");
+        assert_eq!(result.decision, Decision::Deny);
+        assert!(result.matched.is_empty());
+        assert!(result.reason.contains("allowlist_only"));
+    }
+
+    #[test]
+    fn test_simulate_default() {
+        let result =
+            PolicyEngine::new(SecurityConfig::default()).simulate(PolicyAction::Run, "bash");
+        assert_eq!(result.decision, Decision::Permit);
+        assert!(result.matched.is_empty());
+        assert!(result.reason.contains("default"));
+
+        let result = engine().simulate(PolicyAction::Run, "node");
+        assert_eq!(result.decision, Decision::Permit);
+        assert!(result.matched.is_empty());
+    }
+
+    #[test]
+    fn test_simulate_does_not_audit() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("audit.jsonl");
+        let engine = engine().with_audit_log(AuditLog::with_path(log_path.clone()));
+
+        let result = engine.simulate(PolicyAction::Run, "sudo");
+        assert_eq!(result.decision, Decision::Deny);
+        assert!(!log_path.exists());
+
+        // Enforcing the same request records a violation and fails
+        let err = engine
+            .enforce(PolicyAction::Run, "sudo", "test-sandbox")
+            .unwrap_err();
+        assert!(err.to_string().contains("blocked by security policy"));
+
+        let entries = AuditLog::with_path(log_path).read_all().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(
+            &entries[0].event,
+            AuditEvent::PolicyViolation { sandbox, policy, .. }
+                if sandbox == "test-sandbox" && policy == "commands"
+        ));
+    }
+
+    #[test]
+    fn test_policy_scope_enforced_vs_bypassed() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("agentkernel.toml");
+        std::fs::write(
+            &config_path,
+            "[sandbox]\nname = \"test\"\n\n[security.commands]\nblock = [\"sudo\"]\n",
+        )
+        .unwrap();
+
+        // An explicit config is enforced, even for pooled runs
+        let scope = PolicyScope::File(config_path.clone()).for_pooled();
+        let (engine, path) = scope.engine().unwrap().unwrap();
+        assert_eq!(path, config_path);
+        let log_path = temp_dir.path().join("audit.jsonl");
+        let err = engine
+            .with_audit_log(AuditLog::with_path(log_path))
+            .enforce(PolicyAction::Run, "sudo", "test-sandbox")
+            .unwrap_err();
+        assert!(err.to_string().contains("blocked by security policy"));
+
+        // --no-policy and pooled runs without an explicit config bypass it
+        assert!(PolicyScope::Disabled.engine().unwrap().is_none());
+        assert_eq!(PolicyScope::WorkingDir.for_pooled(), PolicyScope::Disabled);
+        PolicyScope::Disabled
+            .enforce_command(&["sudo".to_string()], "test-sandbox")
+            .unwrap();
+
+        // A missing config means no policy rather than an error
+        let missing = PolicyScope::File(temp_dir.path().join("missing.toml"));
+        assert!(missing.engine().unwrap().is_none());
+
+        // A broken one fails closed
+        std::fs::write(&config_path, "[security.commands\nblock = [\"sudo\"]\n").unwrap();
+        let scope = PolicyScope::File(config_path);
+        assert!(scope.engine().is_err());
+        assert!(
+            scope
+                .enforce_command(&["sudo".to_string()], "test-sandbox")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_enforce_denial_carries_decision() {
+        let temp_dir = TempDir::new().unwrap();
+        let engine = engine().with_audit_log(AuditLog::with_path(temp_dir.path().join("a.jsonl")));
+
+        let err = engine
+            .enforce(PolicyAction::Run, "sudo rm -rf /", "build-box")
+            .unwrap_err();
+        let denial = err.downcast_ref::<PolicyDenial>().unwrap();
+        assert_eq!(denial.principal, "build-box");
+        assert_eq!(denial.action, "run");
+        assert_eq!(denial.resource, "sudo rm -rf /");
+        assert_eq!(denial.matched, vec!["security.commands.block = \"sudo\""]);
+        assert_eq!(denial.reason, "command is on the block list");
+        assert_eq!(
+            err.to_string(),
+            "Command 'sudo rm -rf /' blocked by security policy: command is on the block list \
+             (security.commands.block = \"sudo\"). Check [security.commands] in agentkernel.toml"
+        );
+
+        // An allowlist miss has no matched rule
+        let err = engine
+            .enforce(PolicyAction::Connect, "example.com", "build-box")
+            .unwrap_err();
+        let denial = err.downcast_ref::<PolicyDenial>().unwrap();
+        assert!(denial.matched.is_empty());
+        assert!(denial.reason.contains("allowlist_only"));
+        assert!(err.to_string().contains("[security.domains]"));
+    }
+
+    #[test]
+    fn test_enforce_permit_is_silent() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("audit.jsonl");
+        let engine = engine().with_audit_log(AuditLog::with_path(log_path.clone()));
+
+        engine
+            .enforce(PolicyAction::Run, "python", "test-sandbox")
+            .unwrap();
+        assert!(!log_path.exists());
+    }
+}
@@ -22,6 +22,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::sync::{Mutex, Semaphore};
 use tokio::time::{Duration, interval};
 
+use crate::backend::{ExecResult, read_capped};
 use crate::docker_backend::{ContainerRuntime, ContainerSandbox, detect_container_runtime};
 use crate::permissions::Permissions;
 
@@ -59,7 +60,14 @@ impl PersistentShell {
 
     /// Run a command through the persistent shell
     /// Uses a sentinel to detect end of output
-    pub fn run_command(&mut self, cmd: &[String]) -> Result<String> {
+    ///
+    /// Keeps at most `max_output` bytes (None = all of it); returns the output
+    /// and whether any was dropped.
+    pub fn run_command(
+        &mut self,
+        cmd: &[String],
+        max_output: Option<usize>,
+    ) -> Result<(String, bool)> {
         // Build the command with sentinel
         let cmd_str = cmd.join(" ");
         let full_cmd = format!("({}) 2>&1; echo '{}'\n", cmd_str, OUTPUT_SENTINEL);
@@ -76,28 +84,38 @@ impl PersistentShell {
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Failed to get stdout"))?;
 
-        let mut output = String::new();
+        let limit = max_output.unwrap_or(usize::MAX);
+        let sentinel = OUTPUT_SENTINEL.as_bytes();
+        let mut kept = Vec::new();
+        let mut truncated = false;
+        // Bytes read but not yet kept: the sentinel may span two reads
+        let mut pending = Vec::new();
         let mut buf = [0u8; 4096];
 
         use std::io::Read;
         loop {
             let n = stdout.read(&mut buf)?;
-            if n == 0 {
-                break;
-            }
-            output.push_str(&String::from_utf8_lossy(&buf[..n]));
-            if output.contains(OUTPUT_SENTINEL) {
+            pending.extend_from_slice(&buf[..n]);
+            let found = pending
+                .windows(sentinel.len())
+                .position(|window| window == sentinel);
+            let end = match found {
+                Some(pos) => pos,
+                None if n == 0 => pending.len(),
+                None => pending.len().saturating_sub(sentinel.len() - 1),
+            };
+            let room = limit.saturating_sub(kept.len());
+            truncated |= end > room;
+            kept.extend_from_slice(&pending[..end.min(room)]);
+            if found.is_some() || n == 0 {
                 break;
             }
-        }
-
-        // Remove sentinel from output
-        if let Some(pos) = output.find(OUTPUT_SENTINEL) {
-            output.truncate(pos);
+            pending.drain(..end);
         }
 
         // Trim trailing newline
-        Ok(output.trim_end().to_string())
+        let output = String::from_utf8_lossy(&kept).trim_end().to_string();
+        Ok((output, truncated))
     }
 
     /// Check if the shell is still alive
@@ -143,21 +161,44 @@ impl std::fmt::Debug for PooledContainer {
 
 impl PooledContainer {
     /// Run a command in this container using the fastest available method
-    pub async fn run_command(&self, cmd: &[String]) -> Result<String> {
+    ///
+    /// Returns the output and whether it was cut short at `max_output` bytes.
+    pub async fn run_command(
+        &self,
+        cmd: &[String],
+        max_output: Option<usize>,
+    ) -> Result<(String, bool)> {
         // Try persistent shell first (faster: ~15-20ms vs ~100ms for docker exec)
         if let Some(ref shell_mutex) = self.persistent_shell
             && let Ok(mut shell) = shell_mutex.lock()
             && shell.is_alive()
         {
-            return shell.run_command(cmd);
+            return shell.run_command(cmd, max_output);
         }
 
         // Fallback to docker exec
-        self.run_command_exec(cmd).await
+        self.run_command_exec(cmd, max_output).await
     }
 
     /// Run a command using docker exec (slower but more reliable)
-    pub async fn run_command_exec(&self, cmd: &[String]) -> Result<String> {
+    pub async fn run_command_exec(
+        &self,
+        cmd: &[String],
+        max_output: Option<usize>,
+    ) -> Result<(String, bool)> {
+        let result = self.exec(cmd, max_output).await?;
+        if !result.is_success() {
+            bail!("Command failed: {}", result.stderr);
+        }
+        Ok((result.stdout, result.truncated))
+    }
+
+    /// Run a command using docker exec and return its exit code and both streams.
+    ///
+    /// The persistent shell merges the streams and drops the exit code, so
+    /// this always goes through exec. Keeps at most `max_output` bytes of
+    /// each stream (None = all of it).
+    pub async fn exec(&self, cmd: &[String], max_output: Option<usize>) -> Result<ExecResult> {
         let runtime_cmd = self.runtime.cmd();
         let container_name = format!("agentkernel-{}", self.name);
 
@@ -165,16 +206,27 @@ impl PooledContainer {
         let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
         args.extend(cmd_refs);
 
-        let output = std::process::Command::new(runtime_cmd)
+        let mut child = tokio::process::Command::new(runtime_cmd)
             .args(&args)
-            .output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("Command failed: {}", stderr);
-        }
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let limit = max_output.unwrap_or(usize::MAX);
+        let stdout = read_capped(child.stdout.take().expect("stdout is piped"), limit);
+        let stderr = read_capped(child.stderr.take().expect("stderr is piped"), limit);
+        let ((stdout, stdout_truncated), (stderr, stderr_truncated)) = tokio::join!(stdout, stderr);
+        let status = child.wait().await?;
+
+        Ok(ExecResult {
+            exit_code: status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+            rusage: None,
+            truncated: stdout_truncated || stderr_truncated,
+        })
     }
 
     /// Initialize the persistent shell for this container
@@ -204,6 +256,8 @@ pub struct ContainerPool {
     target_size: usize,
     /// Maximum pool size
     max_size: usize,
+    /// Containers acquired and not yet released
+    in_use: AtomicUsize,
     /// Whether the pool is running
     running: Arc<std::sync::atomic::AtomicBool>,
 }
@@ -223,6 +277,7 @@ impl ContainerPool {
             image: DEFAULT_IMAGE.to_string(),
             target_size: DEFAULT_POOL_SIZE,
             max_size: DEFAULT_MAX_POOL_SIZE,
+            in_use: AtomicUsize::new(0),
             running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
         })
     }
@@ -277,16 +332,22 @@ impl ContainerPool {
             if let Some(container) = pool.pop_front() {
                 // Trigger async refill
                 self.spawn_refill_task();
+                self.in_use.fetch_add(1, Ordering::SeqCst);
                 return Ok(container);
             }
         }
 
         // Pool empty, create a new container
-        self.create_container().await
+        let container = self.create_container().await?;
+        self.in_use.fetch_add(1, Ordering::SeqCst);
+        Ok(container)
     }
 
     /// Release a container back to the pool or queue for cleanup
     pub async fn release(&self, container: PooledContainer) {
+        let _ = self
+            .in_use
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
         let pool_size = {
             let pool = self.warm_pool.lock().await;
             pool.len()
@@ -315,6 +376,7 @@ impl ContainerPool {
         let cleanup = self.cleanup_queue.lock().await;
         PoolStats {
             warm_count: warm.len(),
+            in_use: self.in_use.load(Ordering::SeqCst),
             cleanup_pending: cleanup.len(),
             target_size: self.target_size,
             max_size: self.max_size,
@@ -604,6 +666,8 @@ impl ContainerPoolHandle {
 #[derive(Debug, Clone)]
 pub struct PoolStats {
     pub warm_count: usize,
+    /// Containers acquired and not yet released
+    pub in_use: usize,
     pub cleanup_pending: usize,
     pub target_size: usize,
     pub max_size: usize,
@@ -629,6 +693,7 @@ mod tests {
     fn test_pool_stats_display() {
         let stats = PoolStats {
             warm_count: 5,
+            in_use: 0,
             cleanup_pending: 2,
             target_size: 10,
             max_size: 50,
@@ -642,6 +707,7 @@ mod tests {
     fn test_pool_stats_display_zero() {
         let stats = PoolStats {
             warm_count: 0,
+            in_use: 0,
             cleanup_pending: 0,
             target_size: 5,
             max_size: 20,
@@ -655,6 +721,7 @@ mod tests {
     fn test_pool_stats_debug() {
         let stats = PoolStats {
             warm_count: 3,
+            in_use: 0,
             cleanup_pending: 1,
             target_size: 5,
             max_size: 10,
@@ -670,6 +737,7 @@ mod tests {
     fn test_pool_stats_clone() {
         let stats = PoolStats {
             warm_count: 5,
+            in_use: 0,
             cleanup_pending: 2,
             target_size: 10,
             max_size: 50,
@@ -743,8 +811,8 @@ mod tests {
         assert!(!container.name.is_empty());
 
         // Run a command
-        let output = container
-            .run_command(&["echo".into(), "hello".into()])
+        let (output, _) = container
+            .run_command(&["echo".into(), "hello".into()], None)
             .await
             .unwrap();
         assert!(output.contains("hello"));
@@ -768,8 +836,8 @@ mod tests {
         // Acquire and release multiple times
         for i in 0..3 {
             let container = pool.acquire().await.unwrap();
-            let output = container
-                .run_command(&["echo".into(), format!("iteration-{}", i)])
+            let (output, _) = container
+                .run_command(&["echo".into(), format!("iteration-{}", i)], None)
                 .await
                 .unwrap();
             assert!(output.contains(&format!("iteration-{}", i)));
@@ -779,6 +847,27 @@ mod tests {
         pool.stop().await.unwrap();
     }
 
+    #[tokio::test]
+    #[ignore] // Requires Docker
+    async fn test_pool_caps_output() {
+        let pool = ContainerPool::with_config(1, 2, "alpine:3.20").unwrap();
+        pool.start().await.unwrap();
+        let container = pool.acquire().await.unwrap();
+
+        let cmd = vec!["sh".into(), "-c".into(), "yes | head -c 100000".into()];
+        let result = container.exec(&cmd, Some(1000)).await.unwrap();
+        assert!(result.is_success());
+        assert_eq!(result.stdout.len(), 1000);
+        assert!(result.truncated);
+
+        let (output, truncated) = container.run_command(&cmd, Some(1000)).await.unwrap();
+        assert!(output.len() <= 1000);
+        assert!(truncated);
+
+        pool.release(container).await;
+        pool.stop().await.unwrap();
+    }
+
     #[tokio::test]
     #[ignore] // Requires Docker
     async fn test_pool_stats_after_operations() {
@@ -7,6 +7,11 @@ use anyhow::{Context, Result, bail};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Init fragment that applies the mounts and limits requested on the
+/// kernel command line. Every rootfs init includes it, so images built by
+/// `setup`, `build-rootfs.sh` and this conversion boot the same way.
+pub const GUEST_INIT: &str = include_str!("../images/build/guest-init.sh");
+
 /// Result of a rootfs conversion
 #[derive(Debug)]
 pub struct ConversionResult {
@@ -117,11 +122,9 @@ fn find_guest_agent(explicit_path: Option<&Path>) -> Result<PathBuf> {
     }
 
     // Check installed location
-    if let Some(home) = std::env::var_os("HOME") {
-        let installed = PathBuf::from(home).join(".local/share/agentkernel/bin/agent");
-        if installed.exists() {
-            return Ok(installed);
-        }
+    let installed = crate::setup::default_data_dir().join("bin/agent");
+    if installed.exists() {
+        return Ok(installed);
     }
 
     bail!(
@@ -198,7 +201,7 @@ for layer in */layer.tar; do
 done
 
 # Create essential directories (must come after layer extraction to avoid overwrite)
-mkdir -p /mnt/rootfs/dev /mnt/rootfs/proc /mnt/rootfs/sys /mnt/rootfs/tmp /mnt/rootfs/run /mnt/rootfs/root /mnt/rootfs/app /mnt/rootfs/usr/bin /mnt/rootfs/etc
+mkdir -p /mnt/rootfs/dev /mnt/rootfs/proc /mnt/rootfs/sys /mnt/rootfs/tmp /mnt/rootfs/run /mnt/rootfs/root /mnt/rootfs/app /mnt/rootfs/workspace /mnt/rootfs/usr/bin /mnt/rootfs/etc
 chmod 1777 /mnt/rootfs/tmp
 
 # Create device nodes
@@ -225,6 +228,7 @@ mount -t devtmpfs devtmpfs /dev 2>/dev/null || true
 # Set hostname
 hostname agentkernel
 
+{guest_init}
 # Start guest agent in background
 /usr/bin/agent &
 
@@ -254,7 +258,8 @@ fi
 
 echo "Conversion complete"
 "#,
-        size_mb = size_mb
+        size_mb = size_mb,
+        guest_init = GUEST_INIT
     );
 
     // Get the current user's UID and GID for ownership fix
@@ -348,6 +353,19 @@ pub fn rootfs_path_for_image(image: &str, output_dir: &Path) -> PathBuf {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_guest_init_handles_kernel_args() {
+        for arg in [
+            "agentkernel.writable=",
+            "agentkernel.scratch=",
+            "agentkernel.tmp_size=",
+            "agentkernel.run_size=",
+            "agentkernel.ulimit.",
+        ] {
+            assert!(GUEST_INIT.contains(arg), "{}", arg);
+        }
+    }
+
     #[test]
     fn test_image_to_rootfs_name() {
         assert_eq!(image_to_rootfs_name("alpine:3.20"), "alpine-3.20.ext4");
@@ -0,0 +1,156 @@
+//! Machine-readable results for `agentkernel run --output json`.
+//!
+//! Harnesses that run commands in a sandbox get the exit code, both output
+//! streams, and where the command ran as one JSON object on stdout, instead
+//! of scraping the human output.
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// How `run` prints its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The command's output as is (default)
+    #[default]
+    Text,
+    /// A single [`RunReport`] object
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("Invalid output format '{}': expected text or json", s),
+        }
+    }
+}
+
+/// Result of a run, as printed by `--output json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    /// Sandbox the command ran in (None for one-shot and pooled runs)
+    pub sandbox: Option<String>,
+    /// Wall-clock time of the run, including starting the sandbox
+    pub duration_ms: u64,
+    pub image: String,
+    pub backend: String,
+}
+
+impl RunReport {
+    pub fn new(
+        exit_code: i32,
+        stdout: String,
+        stderr: String,
+        started: Instant,
+        image: &str,
+        backend: &str,
+    ) -> Self {
+        Self {
+            exit_code,
+            stdout,
+            stderr,
+            sandbox: None,
+            duration_ms: started.elapsed().as_millis() as u64,
+            image: image.to_string(),
+            backend: backend.to_string(),
+        }
+    }
+
+    /// Set the sandbox the command ran in
+    pub fn with_sandbox(mut self, name: &str) -> Self {
+        self.sandbox = Some(name.to_string());
+        self
+    }
+
+    /// Print the report on stdout and exit with the command's exit status
+    pub fn print_and_exit(&self) -> Result<()> {
+        println!("{}", serde_json::to_string(self)?);
+        let status = process_exit_status(self.exit_code);
+        if status != 0 {
+            std::process::exit(status);
+        }
+        Ok(())
+    }
+}
+
+/// Process exit status for a command's exit code.
+///
+/// Codes outside 0-255 (e.g. -1 when the backend couldn't get one) would
+/// wrap, possibly to 0, so they become 1.
+pub fn process_exit_status(exit_code: i32) -> i32 {
+    if (0..=255).contains(&exit_code) {
+        exit_code
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_format() {
+        assert_eq!("text".parse::<OutputFormat>().unwrap(), OutputFormat::Text);
+        assert_eq!("JSON".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_report_json_shape() {
+        let report = RunReport::new(
+            3,
+            "out\n".to_string(),
+            "err\n".to_string(),
+            Instant::now(),
+            "python:3.12-alpine",
+            "docker",
+        )
+        .with_sandbox("run-1a2b3c4d");
+        let value = serde_json::to_value(&report).unwrap();
+        let mut keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "backend",
+                "duration_ms",
+                "exit_code",
+                "image",
+                "sandbox",
+                "stderr",
+                "stdout"
+            ]
+        );
+        assert_eq!(value["exit_code"], 3);
+        assert_eq!(value["stdout"], "out\n");
+        assert_eq!(value["stderr"], "err\n");
+        assert_eq!(value["sandbox"], "run-1a2b3c4d");
+        assert_eq!(value["image"], "python:3.12-alpine");
+        assert_eq!(value["backend"], "docker");
+        assert!(value["duration_ms"].is_u64());
+
+        // One-shot runs have no named sandbox
+        let report = RunReport::new(0, String::new(), String::new(), Instant::now(), "a", "b");
+        assert!(serde_json::to_value(&report).unwrap()["sandbox"].is_null());
+    }
+
+    #[test]
+    fn test_process_exit_status() {
+        assert_eq!(process_exit_status(0), 0);
+        assert_eq!(process_exit_status(3), 3);
+        assert_eq!(process_exit_status(137), 137);
+        assert_eq!(process_exit_status(255), 255);
+        // Would otherwise wrap to 0 or 255
+        assert_eq!(process_exit_status(256), 1);
+        assert_eq!(process_exit_status(-1), 1);
+    }
+}
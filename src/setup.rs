@@ -3,6 +3,7 @@
 //! Handles downloading/building kernel, rootfs, and Firecracker.
 
 use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -16,6 +17,8 @@ pub const RUNTIMES: &[(&str, &str)] = &[
     ("node", "Node.js 20 LTS with npm (~256MB)"),
     ("go", "Go toolchain (~512MB)"),
     ("rust", "Rust with Cargo (~512MB)"),
+    ("ruby", "Ruby with Bundler (~256MB)"),
+    ("java", "OpenJDK 17 (~512MB)"),
 ];
 
 /// Setup configuration
@@ -38,9 +41,31 @@ impl Default for SetupConfig {
     }
 }
 
-/// Get the default data directory
+/// Environment variable that relocates the data directory (set by `--data-dir`)
+pub const DATA_DIR_ENV: &str = "AGENTKERNEL_DATA_DIR";
+
+/// The data directory from `--data-dir` or `AGENTKERNEL_DATA_DIR`, if set
+pub fn data_dir_override() -> Option<PathBuf> {
+    data_dir_override_from(|key| std::env::var_os(key))
+}
+
+fn data_dir_override_from(env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    env(DATA_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Get the default data directory: the override if set, else
+/// ~/.local/share/agentkernel
 pub fn default_data_dir() -> PathBuf {
-    if let Some(home) = std::env::var_os("HOME") {
+    data_dir_from(|key| std::env::var_os(key))
+}
+
+/// [`default_data_dir`] with the environment read through `env`
+fn data_dir_from(env: impl Fn(&str) -> Option<OsString>) -> PathBuf {
+    if let Some(dir) = data_dir_override_from(&env) {
+        dir
+    } else if let Some(home) = env("HOME") {
         PathBuf::from(home).join(".local/share/agentkernel")
     } else {
         PathBuf::from("/usr/local/share/agentkernel")
@@ -68,6 +93,7 @@ pub fn check_installation() -> SetupStatus {
         docker_available: check_docker(),
         apple_containers_available: check_apple_containers(),
         macos_version_supported: check_macos_version(),
+        container: crate::nested::detect(),
     }
 }
 
@@ -88,6 +114,8 @@ pub struct SetupStatus {
     pub apple_containers_available: bool,
     /// True if macOS version supports Apple containers (26+)
     pub macos_version_supported: bool,
+    /// The container agentkernel runs in, if nested
+    pub container: Option<crate::nested::ContainerEnv>,
 }
 
 impl SetupStatus {
@@ -176,11 +204,27 @@ impl SetupStatus {
                 println!("  Install from: https://github.com/apple/container/releases");
             }
         }
+
+        if let Some(ref env) = self.container {
+            println!("  Container:   {}", env.describe());
+            if !self.kvm_available {
+                println!();
+                println!("  ⚠️  Running nested in a container: Firecracker needs /dev/kvm.");
+                if env.docker_socket.is_some() {
+                    println!("  Sandboxes use the host's Docker through the mounted socket.");
+                } else {
+                    println!(
+                        "  Mount the host's Docker socket (-v /var/run/docker.sock:/var/run/docker.sock)"
+                    );
+                    println!("  or pass --device /dev/kvm to the container.");
+                }
+            }
+        }
     }
 }
 
 /// Find installed kernel
-fn find_kernel(data_dir: &Path) -> Option<PathBuf> {
+pub fn find_kernel(data_dir: &Path) -> Option<PathBuf> {
     let kernel_dir = data_dir.join("images/kernel");
     if kernel_dir.exists()
         && let Ok(entries) = std::fs::read_dir(&kernel_dir)
@@ -197,7 +241,7 @@ fn find_kernel(data_dir: &Path) -> Option<PathBuf> {
 }
 
 /// Find Firecracker binary
-fn find_firecracker() -> Option<PathBuf> {
+pub fn find_firecracker() -> Option<PathBuf> {
     // Check agentkernel's own bin directory first
     let data_dir = default_data_dir();
     let local_fc = data_dir.join("bin/firecracker");
@@ -425,13 +469,29 @@ pub fn prompt_multi_select(
 }
 
 /// Run the interactive setup
-pub async fn run_setup(non_interactive: bool) -> Result<()> {
+pub async fn run_setup(non_interactive: bool, runtimes: &[String]) -> Result<()> {
     println!("=== Agentkernel Setup ===\n");
 
+    // Validate explicitly requested runtimes before doing any work
+    for runtime in runtimes {
+        crate::validation::validate_runtime(runtime)?;
+        if !RUNTIMES.iter().any(|(name, _)| name == runtime) {
+            bail!(
+                "No rootfs recipe for runtime '{}'. Available: {}",
+                runtime,
+                RUNTIMES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+
     let status = check_installation();
     status.print();
 
-    if status.is_ready() && non_interactive {
+    if status.is_ready() && non_interactive && runtimes.is_empty() {
         println!("\nAgentkernel is already set up and ready to use!");
         offer_plugin_install(non_interactive)?;
         return Ok(());
@@ -455,7 +515,17 @@ pub async fn run_setup(non_interactive: bool) -> Result<()> {
     let mut install_firecracker = !status.firecracker_installed;
     let mut runtimes_to_install: Vec<String> = Vec::new();
 
-    if non_interactive {
+    if !runtimes.is_empty() {
+        // Explicit runtimes: build exactly what was asked for
+        for runtime in runtimes {
+            let rootfs_path = data_dir.join(format!("images/rootfs/{}.ext4", runtime));
+            if !rootfs_path.exists() {
+                runtimes_to_install.push(runtime.clone());
+            } else {
+                println!("Rootfs for {} already installed", runtime);
+            }
+        }
+    } else if non_interactive {
         // Non-interactive: install everything needed
         if !status.rootfs_base_installed {
             runtimes_to_install.push("base".to_string());
@@ -663,6 +733,8 @@ async fn build_guest_agent(data_dir: &Path) -> Result<()> {
 
     // Embedded guest agent source
     let guest_agent_source = include_str!("../guest-agent/src/main.rs");
+    let guest_agent_frame = include_str!("../guest-agent/src/frame.rs");
+    let guest_agent_pty = include_str!("../guest-agent/src/pty.rs");
     let guest_agent_cargo = include_str!("../guest-agent/Cargo.toml");
 
     // Create temp directory for build
@@ -672,6 +744,8 @@ async fn build_guest_agent(data_dir: &Path) -> Result<()> {
 
     // Write source files
     std::fs::write(temp_dir.join("src/main.rs"), guest_agent_source)?;
+    std::fs::write(temp_dir.join("src/frame.rs"), guest_agent_frame)?;
+    std::fs::write(temp_dir.join("src/pty.rs"), guest_agent_pty)?;
     std::fs::write(temp_dir.join("Cargo.toml"), guest_agent_cargo)?;
 
     // Dockerfile for building with musl
@@ -789,8 +863,8 @@ async fn build_rootfs(data_dir: &Path, runtime: &str) -> Result<()> {
     // Size based on runtime
     let size_mb = match runtime {
         "base" => 64,
-        "python" | "node" => 256,
-        "go" | "rust" => 512,
+        "python" | "node" | "ruby" => 256,
+        "go" | "rust" | "java" => 512,
         _ => 256,
     };
 
@@ -800,6 +874,8 @@ async fn build_rootfs(data_dir: &Path, runtime: &str) -> Result<()> {
         "node" => "nodejs npm",
         "go" => "go",
         "rust" => "rust cargo",
+        "ruby" => "ruby ruby-bundler",
+        "java" => "openjdk17",
         _ => "",
     };
 
@@ -830,7 +906,7 @@ apk -X https://dl-cdn.alpinelinux.org/alpine/v3.20/main \
     -U --allow-untrusted --root "$MOUNT_DIR" --initdb \
     add alpine-base busybox-static $PACKAGES || true
 
-mkdir -p "$MOUNT_DIR"/{{dev,proc,sys,tmp,run,root,app,usr/bin}}
+mkdir -p "$MOUNT_DIR"/{{dev,proc,sys,tmp,run,root,app,workspace,usr/bin}}
 chmod 1777 "$MOUNT_DIR/tmp"
 
 # Copy guest agent if available
@@ -851,11 +927,13 @@ mknod -m 666 "$MOUNT_DIR/dev/urandom" c 1 9 || true
 # Create init script that starts the guest agent
 cat > "$MOUNT_DIR/init" << 'INIT'
 #!/bin/busybox sh
+BB=/bin/busybox
 /bin/busybox mount -t proc proc /proc
 /bin/busybox mount -t sysfs sysfs /sys
 /bin/busybox mount -t devtmpfs devtmpfs /dev 2>/dev/null || true
 /bin/busybox hostname agentkernel
 
+{guest_init}
 # Start guest agent in background if available
 if [ -x /usr/bin/agent ]; then
     /usr/bin/agent &
@@ -888,7 +966,8 @@ ls -lh "$ROOTFS_IMG"
 "#,
         runtime = runtime,
         size_mb = size_mb,
-        packages = packages
+        packages = packages,
+        guest_init = crate::rootfs::GUEST_INIT
     );
 
     // Create temp directory
@@ -1082,11 +1161,13 @@ fn offer_plugin_install(non_interactive: bool) -> Result<()> {
 
 /// Common Docker images to pre-pull for faster container startup
 const DOCKER_IMAGES: &[&str] = &[
-    "alpine:3.20",        // Default pool image
-    "python:3.12-alpine", // Python runtime
-    "node:20-alpine",     // Node.js runtime
-    "golang:1.22-alpine", // Go runtime
-    "rust:1.85-alpine",   // Rust runtime
+    "alpine:3.20",               // Default pool image
+    "python:3.12-alpine",        // Python runtime
+    "node:20-alpine",            // Node.js runtime
+    "golang:1.22-alpine",        // Go runtime
+    "rust:1.85-alpine",          // Rust runtime
+    "ruby:3.3-alpine",           // Ruby runtime
+    "eclipse-temurin:21-alpine", // Java runtime
 ];
 
 /// Pre-pull common Docker images to avoid download delays during sandbox creation
@@ -1186,3 +1267,40 @@ async fn install_firecracker_binary(data_dir: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_dir_env_override() {
+        fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + use<> {
+            let vars: Vec<(String, OsString)> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), OsString::from(v)))
+                .collect();
+            move |key| vars.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+        }
+
+        let home = env(&[("HOME", "/home/dev")]);
+        assert_eq!(
+            data_dir_from(home),
+            PathBuf::from("/home/dev/.local/share/agentkernel")
+        );
+
+        let relocated = env(&[("HOME", "/home/dev"), (DATA_DIR_ENV, "/srv/agentkernel")]);
+        assert_eq!(data_dir_from(relocated), PathBuf::from("/srv/agentkernel"));
+
+        // An empty override is ignored
+        let empty = env(&[("HOME", "/home/dev"), (DATA_DIR_ENV, "")]);
+        assert_eq!(
+            data_dir_from(empty),
+            PathBuf::from("/home/dev/.local/share/agentkernel")
+        );
+
+        assert_eq!(
+            data_dir_from(env(&[])),
+            PathBuf::from("/usr/local/share/agentkernel")
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Host terminal handling for interactive sessions.
+//!
+//! Puts the local terminal into raw mode while attached to a sandbox shell
+//! so keystrokes (including Ctrl-C) are forwarded verbatim, and restores it
+//! afterwards.
+#![cfg(unix)]
+
+use anyhow::Result;
+use std::os::unix::io::RawFd;
+
+/// Apply raw-mode flags to a termios struct (equivalent to `cfmakeraw`).
+pub fn make_raw(termios: &mut libc::termios) {
+    termios.c_iflag &= !(libc::IGNBRK
+        | libc::BRKINT
+        | libc::PARMRK
+        | libc::ISTRIP
+        | libc::INLCR
+        | libc::IGNCR
+        | libc::ICRNL
+        | libc::IXON);
+    termios.c_oflag &= !libc::OPOST;
+    termios.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+    termios.c_cflag &= !(libc::CSIZE | libc::PARENB);
+    termios.c_cflag |= libc::CS8;
+    termios.c_cc[libc::VMIN] = 1;
+    termios.c_cc[libc::VTIME] = 0;
+}
+
+/// Keeps a terminal in raw mode; the original settings are restored on drop.
+pub struct RawModeGuard {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawModeGuard {
+    /// Switch `fd` to raw mode. Returns `None` if it isn't a terminal.
+    pub fn enable(fd: RawFd) -> Result<Option<Self>> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return Ok(None);
+        }
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            anyhow::bail!(
+                "Failed to read terminal settings: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let mut raw = original;
+        make_raw(&mut raw);
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            anyhow::bail!(
+                "Failed to enable raw mode: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(Some(Self { fd, original }))
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Current terminal size as `(rows, cols)`, if `fd` is a terminal.
+pub fn window_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_raw_clears_line_discipline() {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        termios.c_iflag = libc::ICRNL | libc::IXON;
+        termios.c_oflag = libc::OPOST;
+        termios.c_lflag = libc::ECHO | libc::ICANON | libc::ISIG;
+        termios.c_cflag = libc::PARENB;
+
+        make_raw(&mut termios);
+
+        assert_eq!(termios.c_iflag & (libc::ICRNL | libc::IXON), 0);
+        assert_eq!(termios.c_oflag & libc::OPOST, 0);
+        assert_eq!(
+            termios.c_lflag & (libc::ECHO | libc::ICANON | libc::ISIG),
+            0
+        );
+        assert_eq!(termios.c_cflag & libc::PARENB, 0);
+        assert_eq!(termios.c_cflag & libc::CS8, libc::CS8);
+        assert_eq!(termios.c_cc[libc::VMIN], 1);
+    }
+
+    #[test]
+    fn test_raw_mode_restores_settings() {
+        // Use a pseudo-terminal so the test works without a real TTY
+        let (mut master, mut slave) = (0, 0);
+        let rc = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        assert_eq!(rc, 0);
+
+        let read_lflag = |fd| {
+            let mut t: libc::termios = unsafe { std::mem::zeroed() };
+            unsafe { libc::tcgetattr(fd, &mut t) };
+            t.c_lflag
+        };
+        let before = read_lflag(slave);
+        assert_ne!(before & libc::ICANON, 0);
+
+        let guard = RawModeGuard::enable(slave).unwrap().unwrap();
+        assert_eq!(read_lflag(slave) & (libc::ICANON | libc::ECHO), 0);
+        drop(guard);
+        assert_eq!(read_lflag(slave), before);
+
+        unsafe {
+            libc::close(master);
+            libc::close(slave);
+        }
+    }
+
+    #[test]
+    fn test_raw_mode_skips_non_terminal() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&file);
+        assert!(RawModeGuard::enable(fd).unwrap().is_none());
+    }
+}
@@ -12,6 +12,18 @@ const MAX_SANDBOX_NAME_LEN: usize = 63;
 /// Maximum length for runtime names
 const MAX_RUNTIME_NAME_LEN: usize = 32;
 
+/// Most vCPUs a sandbox can request (Firecracker's limit)
+pub const MAX_VCPUS: u32 = 32;
+
+/// Least memory a sandbox can request, in MB
+pub const MIN_MEMORY_MB: u64 = 64;
+
+/// Most memory a sandbox can request, in MB
+pub const MAX_MEMORY_MB: u64 = 65536;
+
+/// Maximum length for hostnames set inside a sandbox
+const MAX_HOSTNAME_LEN: usize = 253;
+
 /// Allowed runtimes (validated against this list to prevent path traversal)
 const ALLOWED_RUNTIMES: &[&str] = &[
     "base", "python", "node", "go", "rust", "ruby", "java", "c", "dotnet",
@@ -142,7 +154,9 @@ pub fn validate_seatbelt_path(path: &str) -> Result<String> {
 ///
 /// # Security
 /// Validates that the image name follows Docker's naming conventions
-/// to prevent injection via malformed image references.
+/// to prevent injection via malformed image references, and that it isn't
+/// a host path: Firecracker boots `rootfs:<path>` and `.ext4` files, which
+/// only the local CLI may choose.
 pub fn validate_docker_image(image: &str) -> Result<()> {
     if image.is_empty() {
         bail!("Docker image name cannot be empty");
@@ -182,6 +196,82 @@ pub fn validate_docker_image(image: &str) -> Result<()> {
         }
     }
 
+    // Image references never start with `/` or `.`; rootfs files are local-only
+    if image.starts_with('/')
+        || image.starts_with('.')
+        || image.contains("..")
+        || image.starts_with("rootfs:")
+        || image.ends_with(".ext4")
+    {
+        bail!("Docker image name cannot be a file path: {}", image);
+    }
+
+    Ok(())
+}
+
+/// Validate requested sandbox resources against [`MAX_VCPUS`],
+/// [`MIN_MEMORY_MB`] and [`MAX_MEMORY_MB`].
+///
+/// Keeps a caller from asking for more than any backend can provide.
+pub fn validate_resources(vcpus: u32, memory_mb: u64) -> Result<()> {
+    if !(1..=MAX_VCPUS).contains(&vcpus) {
+        bail!("vcpus must be between 1 and {}, got {}", MAX_VCPUS, vcpus);
+    }
+    if !(MIN_MEMORY_MB..=MAX_MEMORY_MB).contains(&memory_mb) {
+        bail!(
+            "memory_mb must be between {} and {}, got {}",
+            MIN_MEMORY_MB,
+            MAX_MEMORY_MB,
+            memory_mb
+        );
+    }
+    Ok(())
+}
+
+/// Validate a hostname for inside a sandbox.
+///
+/// Dot-separated labels of letters, numbers, and hyphens (1-63 characters
+/// each, not starting or ending with a hyphen), at most 253 characters in all.
+/// The hostname is passed to `docker run --hostname` and to `hostname` in the guest.
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > MAX_HOSTNAME_LEN {
+        bail!(
+            "Hostname must be between 1 and {} characters",
+            MAX_HOSTNAME_LEN
+        );
+    }
+    for label in hostname.split('.') {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!(
+                "Invalid hostname '{}'. Use letters, numbers, and hyphens, with dots between labels",
+                hostname
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Validate a working directory for commands run inside a sandbox.
+///
+/// Must be an absolute path without `..` components or control characters.
+pub fn validate_workdir(path: &str) -> Result<()> {
+    if !path.starts_with('/') {
+        bail!("Working directory must be an absolute path, got '{}'", path);
+    }
+    if Path::new(path)
+        .components()
+        .any(|c| c == std::path::Component::ParentDir)
+    {
+        bail!("Working directory cannot contain '..': {}", path);
+    }
+    if path.chars().any(|c| c.is_control()) {
+        bail!("Working directory contains control characters");
+    }
     Ok(())
 }
 
@@ -280,5 +370,53 @@ mod tests {
         assert!(validate_docker_image("alpine; rm -rf /").is_err());
         assert!(validate_docker_image("alpine$(whoami)").is_err());
         assert!(validate_docker_image("alpine`id`").is_err());
+
+        // Host paths Firecracker would boot
+        assert!(validate_docker_image("/var/lib/agentkernel/rootfs/base.ext4").is_err());
+        assert!(validate_docker_image("rootfs:/tmp/agentkernel-x-scratch.ext4").is_err());
+        assert!(validate_docker_image("./app.ext4").is_err());
+        assert!(validate_docker_image("images/../secret.ext4").is_err());
+    }
+
+    #[test]
+    fn test_validate_workdir() {
+        assert!(validate_workdir("/").is_ok());
+        assert!(validate_workdir("/workspace/src").is_ok());
+
+        let err = validate_workdir("src").unwrap_err().to_string();
+        assert!(err.contains("absolute"), "{}", err);
+        assert!(validate_workdir("./src").is_err());
+        assert!(validate_workdir("").is_err());
+        assert!(validate_workdir("/workspace/../etc").is_err());
+        assert!(validate_workdir("/tmp\nx").is_err());
+    }
+
+    #[test]
+    fn test_validate_resources() {
+        assert!(validate_resources(1, 512).is_ok());
+        assert!(validate_resources(MAX_VCPUS, MAX_MEMORY_MB).is_ok());
+        assert!(validate_resources(1, MIN_MEMORY_MB).is_ok());
+
+        let err = validate_resources(0, 512).unwrap_err().to_string();
+        assert!(err.contains("vcpus"), "{}", err);
+        assert!(validate_resources(MAX_VCPUS + 1, 512).is_err());
+        let err = validate_resources(1, 16).unwrap_err().to_string();
+        assert!(err.contains("memory_mb"), "{}", err);
+        assert!(validate_resources(1, MAX_MEMORY_MB + 1).is_err());
+    }
+
+    #[test]
+    fn test_validate_hostname() {
+        assert!(validate_hostname("dev").is_ok());
+        assert!(validate_hostname("ci-runner-3").is_ok());
+        assert!(validate_hostname("build.example.com").is_ok());
+
+        assert!(validate_hostname("").is_err());
+        assert!(validate_hostname("-dev").is_err());
+        assert!(validate_hostname("dev-").is_err());
+        assert!(validate_hostname("my_box").is_err());
+        assert!(validate_hostname("a..b").is_err());
+        assert!(validate_hostname("dev; reboot").is_err());
+        assert!(validate_hostname(&"a".repeat(64)).is_err());
     }
 }
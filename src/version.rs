@@ -0,0 +1,215 @@
+//! Build, backend, and guest details for `agentkernel version --verbose`.
+
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use crate::setup::{find_firecracker, find_kernel};
+
+/// Prefix of the version string embedded in the guest agent binary
+const AGENT_MARKER: &[u8] = b"agentkernel-guest-agent/";
+
+/// Crate version, e.g. `0.4.0`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// One-line version, as printed by `agentkernel version`
+pub fn short() -> String {
+    format!("agentkernel {} ({})", VERSION, env!("AGENTKERNEL_GIT_SHA"))
+}
+
+/// Full report: build info, backend versions, and installed guest components
+pub fn verbose(data_dir: &Path) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "agentkernel {}", VERSION);
+    row(&mut out, "commit", env!("AGENTKERNEL_GIT_SHA"));
+    row(&mut out, "built", &build_time());
+    row(&mut out, "rustc", env!("AGENTKERNEL_RUSTC_VERSION"));
+    row(
+        &mut out,
+        "platform",
+        &format!("{}/{}", std::env::consts::OS, std::env::consts::ARCH),
+    );
+
+    let _ = writeln!(out, "\nBackends:");
+    let firecracker = find_firecracker().map(|p| p.to_string_lossy().into_owned());
+    let backends = [
+        ("docker", tool_version("docker")),
+        ("podman", tool_version("podman")),
+        ("firecracker", firecracker.as_deref().and_then(tool_version)),
+    ];
+    for (name, version) in &backends {
+        row(&mut out, name, version.as_deref().unwrap_or("not found"));
+    }
+
+    let _ = writeln!(out, "\nGuest:");
+    let kernel = find_kernel(data_dir).and_then(|p| kernel_version(&p));
+    row(
+        &mut out,
+        "kernel",
+        kernel.as_deref().unwrap_or("not installed"),
+    );
+    let rootfs = data_dir.join("images/rootfs/base.ext4");
+    let agent = std::fs::File::open(&rootfs)
+        .ok()
+        .and_then(|f| agent_version(f).ok().flatten());
+    let agent = match agent {
+        Some(v) => v,
+        None if rootfs.exists() => "unknown (rootfs predates version marker)".to_string(),
+        None => "not installed".to_string(),
+    };
+    row(&mut out, "guest agent", &agent);
+    out
+}
+
+fn row(out: &mut String, label: &str, value: &str) {
+    let _ = writeln!(out, "  {:<14}{}", format!("{}:", label), value);
+}
+
+/// Build time as UTC, from the build script's timestamp
+fn build_time() -> String {
+    env!("AGENTKERNEL_BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// First line of `<program> --version`, if the program runs
+fn tool_version(program: &str) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Kernel version from an installed `vmlinux-<version>-agentkernel` image
+fn kernel_version(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let version = name
+        .strip_prefix("vmlinux-")?
+        .strip_suffix("-agentkernel")?;
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Scan a rootfs image (or the agent binary) for the guest agent's
+/// embedded version marker
+fn agent_version(mut reader: impl Read) -> std::io::Result<Option<String>> {
+    // Longest version string looked for after the marker
+    const MAX_VERSION_LEN: usize = 32;
+    // Keep the tail of the previous chunk so a marker split across reads is found
+    let keep = AGENT_MARKER.len() + MAX_VERSION_LEN;
+    let mut buf = vec![0u8; 1 << 20];
+    let mut carry = 0;
+    loop {
+        let n = reader.read(&mut buf[carry..])?;
+        if n == 0 {
+            return Ok(None);
+        }
+        let filled = carry + n;
+        let window = &buf[..filled];
+        let markers = window
+            .windows(AGENT_MARKER.len())
+            .enumerate()
+            .filter(|(_, w)| *w == AGENT_MARKER)
+            .map(|(pos, _)| pos);
+        let mut restart = None;
+        for pos in markers {
+            let rest = &window[pos + AGENT_MARKER.len()..];
+            let rest = &rest[..rest.len().min(MAX_VERSION_LEN + 1)];
+            match rest.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    let version = String::from_utf8_lossy(&rest[..end]).into_owned();
+                    return Ok(Some(version));
+                }
+                // The terminator may be in the next chunk
+                None if rest.len() <= MAX_VERSION_LEN => {
+                    restart = Some(pos);
+                    break;
+                }
+                // Too long to be a version: not the marker after all
+                None => {}
+            }
+        }
+        carry = match restart {
+            // Restart from the marker, which may already be at the start
+            Some(pos) => filled - pos,
+            None => keep.min(filled),
+        };
+        buf.copy_within(filled - carry..filled, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_kernel_version_from_file_name() {
+        let path = PathBuf::from("/data/images/kernel/vmlinux-6.1.70-agentkernel");
+        assert_eq!(kernel_version(&path).as_deref(), Some("6.1.70"));
+        assert_eq!(kernel_version(Path::new("/data/vmlinux")), None);
+    }
+
+    #[test]
+    fn test_agent_version_found_across_chunks() {
+        let mut image = vec![0xAAu8; (1 << 20) - 10];
+        image.extend_from_slice(b"agentkernel-guest-agent/0.1.0\0");
+        image.extend(vec![0u8; 100]);
+        let version = agent_version(image.as_slice()).unwrap();
+        assert_eq!(version.as_deref(), Some("0.1.0"));
+
+        assert_eq!(agent_version(&[0u8; 4096][..]).unwrap(), None);
+    }
+
+    /// Hands out one chunk per read
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_agent_version_marker_at_start_without_terminator() {
+        let chunks = |parts: &[&[u8]]| Chunks(parts.iter().map(|p| p.to_vec()).collect());
+
+        // The version trickles in after a marker at the start of the buffer
+        let reader = chunks(&[b"agentkernel-guest-agent/", b"0.", b"1.0", b"\0rest"]);
+        assert_eq!(agent_version(reader).unwrap().as_deref(), Some("0.1.0"));
+
+        // No terminator before the end of the image
+        let reader = chunks(&[b"agentkernel-guest-agent/", b"0.1"]);
+        assert_eq!(agent_version(reader).unwrap(), None);
+
+        // Too long to be a version, but a real marker follows
+        let mut junk = b"agentkernel-guest-agent/".to_vec();
+        junk.extend(vec![b'x'; 100]);
+        let reader = chunks(&[&junk, b"agentkernel-guest-agent/0.2.0\0"]);
+        assert_eq!(agent_version(reader).unwrap().as_deref(), Some("0.2.0"));
+        junk.extend_from_slice(b"\0agentkernel-guest-agent/0.3.0\0");
+        let reader = chunks(&[&junk]);
+        assert_eq!(agent_version(reader).unwrap().as_deref(), Some("0.3.0"));
+    }
+
+    #[test]
+    fn test_verbose_report_has_version_and_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = verbose(dir.path());
+        assert!(report.starts_with(&format!("agentkernel {}", VERSION)));
+        assert!(report.contains("docker:"));
+        assert!(report.contains("firecracker:"));
+        assert!(report.contains("guest agent:  not installed"));
+    }
+}
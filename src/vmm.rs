@@ -4,22 +4,35 @@
 //! or containers (Docker/Podman) as fallback when KVM is not available.
 
 use crate::audit::{AuditEvent, log_event};
+use crate::backend::firecracker::local_rootfs;
 use crate::backend::{
-    BackendType, FileInjection, Sandbox, SandboxConfig, create_sandbox, detect_best_backend,
+    BACKGROUND_EXEC_DIR, BackendType, BackendUnavailable, BackgroundExec, BackgroundExecPaths,
+    DEFAULT_MAX_OUTPUT_BYTES, DetachedPaths, EphemeralUnsupported, ExecBytes, ExecOptions,
+    ExecResult, ExitReason, FileInjection, LogLine, PortMapping, RestartPolicy, SHELL_FALLBACKS,
+    Sandbox, SandboxConfig, SandboxGone, WriteOptions, background_kill_command,
+    background_list_command, create_sandbox, detached_log_command, detect_best_backend,
+    detect_shell, display_command, parse_background_list, read_capped, validate_background_id,
+};
+use crate::docker_backend::{
+    ContainerRuntime, current_owner, detect_container_runtime, image_digest, load_image_archive,
+    owner_alive, reap_ephemeral_containers,
 };
-use crate::config::Config;
-use crate::docker_backend::detect_container_runtime;
 use crate::languages::docker_image_to_firecracker_runtime;
-use crate::permissions::Permissions;
+use crate::permissions::{FileMount, Permissions, resolve_user};
+use crate::policy::PolicyScope;
 use crate::pool::ContainerPool;
 use crate::validation;
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::OnceCell;
 
+/// Image the pooled containers behind `run --fast` run
+pub const POOL_IMAGE: &str = "alpine:3.20";
+
 /// Global container pool for fast ephemeral runs
 static CONTAINER_POOL: OnceCell<Arc<ContainerPool>> = OnceCell::const_new();
 
@@ -27,7 +40,7 @@ static CONTAINER_POOL: OnceCell<Arc<ContainerPool>> = OnceCell::const_new();
 async fn get_pool() -> Result<Arc<ContainerPool>> {
     CONTAINER_POOL
         .get_or_try_init(|| async {
-            let pool = ContainerPool::with_config(5, 20, "alpine:3.20")?;
+            let pool = ContainerPool::with_config(5, 20, POOL_IMAGE)?;
             pool.start().await?;
             Ok(Arc::new(pool))
         })
@@ -48,6 +61,553 @@ pub struct SandboxState {
     /// Backend type used to create this sandbox
     #[serde(default)]
     pub backend: Option<BackendType>,
+    /// Created for a single command; removed by the reaper if left behind
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Why the sandbox last stopped on its own (cleared on start)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit: Option<ExitReason>,
+    /// User-defined labels (e.g. project, owner)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+    /// Image digest resolved at the last start (container backends)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_digest: Option<String>,
+    /// When the sandbox is removed automatically (RFC 3339), from `--ttl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// What to do when the sandbox exits on its own
+    #[serde(default, skip_serializing_if = "is_never")]
+    pub restart: RestartPolicy,
+    /// Times the sandbox was restarted by its restart policy since it was last started
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub restart_count: u32,
+    /// Script run once on the first start, from `[sandbox] setup_script`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup_script: Option<String>,
+    /// Whether the setup script has completed successfully
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub setup_done: bool,
+    /// How long the backend took to start at the last start (ms)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_boot_ms: Option<u64>,
+    /// Checkpoint tarball restored on the first start, from `create --from-checkpoint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<PathBuf>,
+    /// Sandbox ports published on the host, from `--publish`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortMapping>,
+    /// Backend command that last started the sandbox, secrets redacted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_command: Option<String>,
+    /// Process that created an ephemeral sandbox (`host:pid`); the reaper
+    /// leaves the sandbox alone while it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Network access set with `network on`/`off`; wins over the profile
+    /// at the next start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<bool>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn is_never(policy: &RestartPolicy) -> bool {
+    *policy == RestartPolicy::Never
+}
+
+/// Parse a `key=value` label
+pub fn parse_label(label: &str) -> Result<(String, String)> {
+    let Some((key, value)) = label.split_once('=') else {
+        bail!("Invalid label '{}': expected key=value", label);
+    };
+    validate_label(key, value)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Label key prefix agentkernel keeps for its own container labels
+const RESERVED_LABEL_PREFIX: &str = "agentkernel.";
+
+/// Check a label key and value are safe to store and pass to a backend
+pub fn validate_label(key: &str, value: &str) -> Result<()> {
+    check_label(key, value)?;
+    if key.starts_with(RESERVED_LABEL_PREFIX) {
+        bail!(
+            "Label key '{}' is reserved: '{}*' keys are set by agentkernel",
+            key,
+            RESERVED_LABEL_PREFIX
+        );
+    }
+    Ok(())
+}
+
+/// Label syntax shared by labels and filters (filters may name reserved keys)
+fn check_label(key: &str, value: &str) -> Result<()> {
+    let key_ok = !key.is_empty()
+        && key.len() <= 128
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '/'));
+    if !key_ok {
+        bail!(
+            "Invalid label key '{}': use letters, digits, '.', '-', '_' or '/'",
+            key
+        );
+    }
+    if value.len() > 256 || value.chars().any(char::is_control) {
+        bail!("Invalid value for label '{}'", key);
+    }
+    Ok(())
+}
+
+/// Selects sandboxes by label: `key` (label present) or `key=value`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl LabelFilter {
+    /// Parse a label selector (`key` or `key=value`)
+    pub fn parse(selector: &str) -> Result<Self> {
+        let (key, value) = match selector.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (selector, None),
+        };
+        check_label(key, value.as_deref().unwrap_or_default())?;
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+
+    /// Parse a `list --filter` expression (only `label=...` is supported)
+    pub fn from_filter(filter: &str) -> Result<Self> {
+        match filter.strip_prefix("label=") {
+            Some(selector) => Self::parse(selector),
+            None => bail!(
+                "Unsupported filter '{}'. Use label=<key> or label=<key>=<value>",
+                filter
+            ),
+        }
+    }
+
+    /// Whether a sandbox's labels satisfy this filter
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match (labels.get(&self.key), &self.value) {
+            (Some(actual), Some(expected)) => actual == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when a named sandbox can't be used,
+/// so callers like the HTTP API can tell it from other failures
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxLookupError {
+    /// No sandbox has this name
+    NotFound { name: String },
+    /// The sandbox exists but isn't running, with why it last exited if known
+    NotRunning {
+        name: String,
+        reason: Option<String>,
+    },
+}
+
+impl std::fmt::Display for SandboxLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxLookupError::NotFound { name } => write!(f, "Sandbox '{}' not found", name),
+            SandboxLookupError::NotRunning {
+                name,
+                reason: Some(reason),
+            } => write!(
+                f,
+                "Sandbox '{}' is not running: {}. Start it with: agentkernel start {}",
+                name, reason, name
+            ),
+            SandboxLookupError::NotRunning { name, reason: None } => write!(
+                f,
+                "Sandbox '{}' is not running. Start it with: agentkernel start {}",
+                name, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SandboxLookupError {}
+
+/// Error for a sandbox name that isn't known
+fn not_found_error(name: &str) -> anyhow::Error {
+    SandboxLookupError::NotFound {
+        name: name.to_string(),
+    }
+    .into()
+}
+
+/// Error for operations on a sandbox that isn't running, with its last exit
+/// reason if known. Without any state the sandbox doesn't exist at all.
+fn not_running_error(name: &str, state: Option<&SandboxState>) -> anyhow::Error {
+    let Some(state) = state else {
+        return not_found_error(name);
+    };
+    SandboxLookupError::NotRunning {
+        name: name.to_string(),
+        reason: state.last_exit.as_ref().map(|reason| reason.to_string()),
+    }
+    .into()
+}
+
+/// Hostname for a sandbox that doesn't set one: its name, with underscores
+/// (not allowed in hostnames) turned into hyphens
+fn default_hostname(name: &str) -> String {
+    let hostname = name.replace('_', "-");
+    if validation::validate_hostname(&hostname).is_ok() {
+        hostname
+    } else {
+        crate::backend::DEFAULT_HOSTNAME.to_string()
+    }
+}
+
+/// Ephemeral sandboxes older than this are treated as leaked by a crashed run
+pub const DEFAULT_EPHEMERAL_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// Ensures the automatic reaper runs at most once per process
+static EPHEMERAL_REAPER: Once = Once::new();
+
+/// Whether a sandbox created at `created_at` (RFC 3339) is at least `max_age` old.
+///
+/// Unparseable timestamps are never considered stale.
+fn is_stale(created_at: &str, now: chrono::DateTime<chrono::Utc>, max_age: Duration) -> bool {
+    chrono::DateTime::parse_from_rfc3339(created_at)
+        .ok()
+        .and_then(|created| (now - created.with_timezone(&chrono::Utc)).to_std().ok())
+        .is_some_and(|age| age >= max_age)
+}
+
+/// How often a long-running server sweeps for expired sandboxes
+pub const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often a long-running server checks for sandboxes to restart
+pub const RESTART_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a long-running server looks for containers started or stopped
+/// outside it
+pub const DETECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// State files modified more recently than this are read on every refresh:
+/// a second write within the filesystem's timestamp granularity can leave
+/// the modification time and size unchanged
+const STATE_STAMP_SETTLE: Duration = Duration::from_secs(2);
+
+/// Archives the top-level entries of the directory `$1` into `$2` (`-` for
+/// stdout), leaving out virtual filesystems and scratch space
+const CHECKPOINT_SCRIPT: &str = concat!(
+    r#"cd "$1" && find . -mindepth 1 -maxdepth 1"#,
+    " ! -name proc ! -name sys ! -name dev ! -name run ! -name tmp",
+    r#" -print0 | xargs -0 tar -cf "$2""#,
+);
+
+/// Extracts the archive `$2` into the directory `$1`, then removes it
+const RESTORE_SCRIPT: &str = r#"tar -xf "$2" -C "$1"; status=$?; rm -f "$2"; exit $status"#;
+
+/// Setup script output kept for the audit log and error message
+const SETUP_OUTPUT_TAIL: usize = 16 * 1024;
+
+/// The last [`SETUP_OUTPUT_TAIL`] bytes of a setup script's output, where
+/// its errors are, noting how much was left out
+fn output_tail(output: &str) -> String {
+    if output.len() <= SETUP_OUTPUT_TAIL {
+        return output.to_string();
+    }
+    let mut start = output.len() - SETUP_OUTPUT_TAIL;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[{} bytes omitted]\n{}", start, &output[start..])
+}
+
+/// A unique path for a checkpoint tarball inside a sandbox
+fn checkpoint_staging_path() -> String {
+    format!(
+        "/tmp/agentkernel-checkpoint-{}.tar",
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Error output kept from a streamed checkpoint
+const CHECKPOINT_ERRORS_LIMIT: usize = 16 * 1024;
+
+/// Save a streamed checkpoint tarball to `dest`, returning its size
+async fn save_checkpoint(mut child: tokio::process::Child, dest: &Path) -> Result<u64> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut stdout = child.stdout.take().context("No output to save")?;
+    let stderr = child.stderr.take();
+    let mut file = tokio::fs::File::create(dest)
+        .await
+        .with_context(|| format!("Failed to write {}", dest.display()))?;
+    // Drain stderr alongside, so a chatty tar can't block on a full pipe.
+    // The copy owns stdout and closes it when it ends, so a failed write
+    // stops tar too.
+    let save = async move {
+        let size = tokio::io::copy(&mut stdout, &mut file).await?;
+        file.flush().await?;
+        Ok::<_, std::io::Error>(size)
+    };
+    let (saved, errors) = tokio::join!(save, async {
+        match stderr {
+            Some(stderr) => read_capped(stderr, CHECKPOINT_ERRORS_LIMIT).await.0,
+            None => Vec::new(),
+        }
+    });
+    let status = child.wait().await?;
+    let result = match saved {
+        Ok(size) if status.success() => Ok(size),
+        Ok(_) => Err(anyhow::anyhow!(
+            "{}",
+            String::from_utf8_lossy(&errors).trim()
+        )),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to write {}", dest.display()))),
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(dest);
+    }
+    result
+}
+
+/// Copy a checkpoint tarball into a sandbox and extract it under `root`
+async fn extract_checkpoint(sandbox: &mut dyn Sandbox, path: &Path, root: &str) -> Result<()> {
+    let content = std::fs::read(path).context("Failed to read the checkpoint")?;
+    let staging = checkpoint_staging_path();
+    sandbox.write_file(&staging, &content).await?;
+    let result = sandbox
+        .exec(&["sh", "-c", RESTORE_SCRIPT, "sh", root, &staging])
+        .await?;
+    if !result.is_success() {
+        bail!("{}", result.output().trim());
+    }
+    Ok(())
+}
+
+/// Parse a TTL such as `90s`, `30m`, `2h`, `1d`, or plain seconds.
+///
+/// `0` means no expiry and returns `None`.
+pub fn parse_ttl(ttl: &str) -> Result<Option<Duration>> {
+    let ttl = ttl.trim();
+    let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (digits, unit) = ttl.split_at(split);
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!(
+            "Invalid TTL '{}': expected a number with an optional unit (s, m, h, d), e.g. 30m",
+            ttl
+        ),
+    };
+    let Ok(value) = digits.parse::<u64>() else {
+        bail!("Invalid TTL '{}': expected e.g. 30m, 2h or 1d", ttl);
+    };
+    let secs = value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("TTL '{}' is too large", ttl))?;
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
+/// Expiry time (RFC 3339) for a sandbox given a TTL starting at `now`
+fn expiry_time(now: chrono::DateTime<chrono::Utc>, ttl: Duration) -> Result<String> {
+    let ttl = chrono::Duration::from_std(ttl).map_err(|_| anyhow::anyhow!("TTL is too large"))?;
+    let expires = now
+        .checked_add_signed(ttl)
+        .ok_or_else(|| anyhow::anyhow!("TTL is too large"))?;
+    Ok(expires.to_rfc3339())
+}
+
+/// Whether an expiry time (RFC 3339) has passed.
+///
+/// Unparseable timestamps never expire.
+fn is_expired(expires_at: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+    chrono::DateTime::parse_from_rfc3339(expires_at).is_ok_and(|expires| expires <= now)
+}
+
+/// Exclusive lock on one sandbox, serializing create/start/remove across
+/// threads and processes. Released when dropped.
+struct SandboxLock {
+    _file: std::fs::File,
+}
+
+impl SandboxLock {
+    /// Take the lock for `name`, failing fast if another operation holds it
+    fn acquire(data_dir: &Path, name: &str) -> Result<Self> {
+        let dir = data_dir.join("locks");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.lock", name));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(std::fs::TryLockError::WouldBlock) => bail!(
+                "Another operation on sandbox '{}' is in progress. Try again shortly.",
+                name
+            ),
+            Err(std::fs::TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("Failed to lock sandbox '{}'", name))
+            }
+        }
+    }
+}
+
+/// Rename a sandbox's container, if it has one
+fn rename_container(runtime: ContainerRuntime, old: &str, new: &str) -> Result<()> {
+    let old_container = format!("agentkernel-{}", old);
+    let exists = std::process::Command::new(runtime.cmd())
+        .args(["container", "inspect", &old_container])
+        .output()
+        .is_ok_and(|o| o.status.success());
+    if !exists {
+        return Ok(());
+    }
+    let output = std::process::Command::new(runtime.cmd())
+        .args(["rename", &old_container, &format!("agentkernel-{}", new)])
+        .output()
+        .with_context(|| format!("Failed to run {} rename", runtime.cmd()))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to rename container: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Names of the running agentkernel containers of a runtime (none if the
+/// runtime can't be reached)
+fn running_containers(runtime: ContainerRuntime) -> BTreeSet<String> {
+    std::process::Command::new(runtime.cmd())
+        .args(["ps", "--format", "{{.Names}}", "-f", "name=agentkernel-"])
+        .output()
+        .map(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A saved sandbox its manager holds no running handle for
+pub struct Unwatched {
+    pub name: String,
+    backend: BackendType,
+    exit_recorded: bool,
+}
+
+/// What [`probe_sandboxes`] found for a sandbox
+pub enum Detected {
+    /// Its container is running
+    Running(Box<dyn Sandbox>),
+    /// Its container died while nobody was watching
+    Exited(ExitReason),
+}
+
+/// Look for sandboxes among the live containers.
+///
+/// Blocks on the container runtimes: one `docker ps` per runtime in use,
+/// plus a `docker inspect` per stopped container whose exit isn't recorded.
+/// Other backends need more complex detection and are skipped.
+pub fn probe_sandboxes(sandboxes: Vec<Unwatched>) -> Vec<(String, Detected)> {
+    // One listing per container runtime, however many sandboxes use it
+    let mut containers: Vec<(BackendType, BTreeSet<String>)> = Vec::new();
+    let mut detected = Vec::new();
+    for sandbox in sandboxes {
+        let runtime = match sandbox.backend {
+            BackendType::Docker => ContainerRuntime::Docker,
+            BackendType::Podman => ContainerRuntime::Podman,
+            _ => continue,
+        };
+        let names = match containers.iter().position(|(b, _)| *b == sandbox.backend) {
+            Some(i) => &containers[i].1,
+            None => {
+                containers.push((sandbox.backend, running_containers(runtime)));
+                &containers[containers.len() - 1].1
+            }
+        };
+        let is_running = names.contains(&format!("agentkernel-{}", sandbox.name));
+        if is_running && let Ok(handle) = create_sandbox(sandbox.backend, &sandbox.name) {
+            // Note: DockerSandbox::is_running() checks Docker directly
+            detected.push((sandbox.name, Detected::Running(handle)));
+        } else if !is_running
+            && !sandbox.exit_recorded
+            && let Ok(mut handle) = create_sandbox(sandbox.backend, &sandbox.name)
+            && let Some(reason) = handle.exit_reason()
+        {
+            // Stopped containers are removed, so this is never a clean stop
+            detected.push((sandbox.name, Detected::Exited(reason)));
+        }
+    }
+    detected
+}
+
+/// Build a `sh -c` wrapper that runs `cmd` in the background with stdout
+/// sent to `paths.log` and stderr to `paths.err`, recording its pid and (once
+/// it finishes) exit code.
+///
+/// `setup` runs first, and the command is only launched if it succeeds. The
+/// command gets its own session (via `setsid`, where the sandbox has it), so
+/// its process group holds everything it starts; the recorded pid is the
+/// wrapper shell around it.
+fn detached_command(cmd: &[String], paths: &DetachedPaths, setup: Option<&str>) -> Vec<String> {
+    let quoted: Vec<String> = cmd.iter().map(|arg| shell_quote(arg)).collect();
+    // Write the exit code via a rename so `wait` never reads a partial file
+    let exit_tmp = format!("{}.tmp", paths.exit);
+    let inner = format!(
+        "run=; command -v setsid >/dev/null 2>&1 && run=setsid; \
+         $run {}; echo $? > {} && mv {} {}",
+        quoted.join(" "),
+        shell_quote(&exit_tmp),
+        shell_quote(&exit_tmp),
+        shell_quote(paths.exit)
+    );
+    let launch = format!(
+        "rm -f {exit}; nohup sh -c {} > {} 2> {} < /dev/null & echo $! > {}",
+        shell_quote(&inner),
+        shell_quote(paths.log),
+        shell_quote(paths.err),
+        shell_quote(paths.pid),
+        exit = shell_quote(paths.exit)
+    );
+    let script = match setup {
+        Some(setup) => format!("{} || exit; {}", setup, launch),
+        None => launch,
+    };
+    vec!["sh".to_string(), "-c".to_string(), script]
+}
+
+/// Expand and check `[[mounts]]` sources before the sandbox starts
+fn resolve_mounts(mounts: &[FileMount]) -> Result<Vec<FileMount>> {
+    mounts
+        .iter()
+        .map(|mount| {
+            mount
+                .resolve()
+                .with_context(|| format!("Invalid mount for {}", mount.target))
+        })
+        .collect()
+}
+
+/// Quote a string for safe use as a single POSIX shell word
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
 
 /// VM Manager - manages sandboxes via unified Sandbox trait
@@ -63,12 +623,61 @@ pub struct VmManager {
     running: HashMap<String, Box<dyn Sandbox>>,
     /// Persisted sandbox configurations
     sandboxes: HashMap<String, SandboxState>,
+    /// Modification time and size of each state file when last read, so
+    /// [`VmManager::refresh`] skips unchanged ones
+    state_stamps: HashMap<String, (SystemTime, u64)>,
     /// Data directory for persistence
     data_dir: PathBuf,
     /// Rootfs directory for Firecracker
     rootfs_dir: Option<PathBuf>,
-    /// Next vsock CID
-    next_cid: u32,
+    /// Vsock CIDs held by sandboxes (shared with split-off managers)
+    cids: Arc<Mutex<CidPool>>,
+    /// Start configs of running sandboxes this manager restarts when they
+    /// exit (backends without a native restart policy)
+    restart_configs: HashMap<String, SandboxConfig>,
+    /// Only create sandboxes from digest-pinned images, from ./agentkernel.toml
+    require_image_digest: bool,
+}
+
+/// Lowest CID a guest can use (0-2 are reserved; 2 is the host)
+const MIN_GUEST_CID: u32 = 3;
+
+/// Vsock CIDs in use by sandboxes. Freed CIDs are handed out again, lowest
+/// first, so create/remove cycles don't run the counter up.
+#[derive(Debug, Default)]
+struct CidPool {
+    in_use: BTreeSet<u32>,
+}
+
+impl CidPool {
+    fn new(cids: impl IntoIterator<Item = u32>) -> Self {
+        let mut pool = Self::default();
+        pool.sync(cids);
+        pool
+    }
+
+    /// Take the lowest free CID
+    fn allocate(&mut self) -> u32 {
+        let mut cid = MIN_GUEST_CID;
+        for &used in self.in_use.range(MIN_GUEST_CID..) {
+            if used != cid {
+                break;
+            }
+            cid += 1;
+        }
+        self.in_use.insert(cid);
+        cid
+    }
+
+    /// Hand a CID back to the pool
+    fn release(&mut self, cid: u32) {
+        self.in_use.remove(&cid);
+    }
+
+    /// Replace the in-use set with the CIDs of saved sandboxes
+    fn sync(&mut self, cids: impl IntoIterator<Item = u32>) {
+        self.in_use = cids.into_iter().collect();
+    }
 }
 
 impl VmManager {
@@ -90,15 +699,11 @@ impl VmManager {
         let backend = if let Some(b) = explicit_backend {
             // Verify the requested backend is available
             if !crate::backend::backend_available(b) {
-                bail!("Backend '{}' is not available on this system", b);
+                return Err(BackendUnavailable::Requested(b).into());
             }
             b
         } else {
-            detect_best_backend().ok_or_else(|| {
-                anyhow::anyhow!(
-                    "No sandbox backend available. Need one of: KVM (Linux), Apple containers (macOS 26+), or Docker/Podman."
-                )
-            })?
+            detect_best_backend().ok_or(BackendUnavailable::NoneFound)?
         };
 
         // Find rootfs path (only needed for Firecracker)
@@ -110,81 +715,365 @@ impl VmManager {
 
         // Load existing sandboxes
         let sandboxes = Self::load_sandboxes(&sandboxes_dir)?;
-
-        // Find next available CID
-        let max_cid = sandboxes.values().map(|s| s.vsock_cid).max().unwrap_or(2);
+        let cids = CidPool::new(sandboxes.values().map(|s| s.vsock_cid));
 
         let mut manager = Self {
             backend,
             running: HashMap::new(),
             sandboxes,
+            state_stamps: HashMap::new(),
             data_dir,
             rootfs_dir,
-            next_cid: max_cid + 1,
+            cids: Arc::new(Mutex::new(cids)),
+            restart_configs: HashMap::new(),
+            require_image_digest: crate::config::Config::local_requires_image_digest(),
         };
 
+        // Clean up after runs that crashed before removing their sandbox
+        EPHEMERAL_REAPER.call_once(|| {
+            manager.reap_ephemeral(DEFAULT_EPHEMERAL_MAX_AGE);
+        });
+        // Sandboxes past their --ttl
+        manager.reap_expired();
+
         // Detect already-running sandboxes
         manager.detect_running_sandboxes();
 
         Ok(manager)
     }
 
-    /// Detect sandboxes that are already running (e.g., Docker containers)
-    fn detect_running_sandboxes(&mut self) {
-        // Need to collect names first to avoid borrow checker issues
-        let sandboxes_to_check: Vec<_> = self
-            .sandboxes
-            .iter()
-            .map(|(name, state)| (name.clone(), state.backend.unwrap_or(self.backend)))
-            .collect();
+    /// Create a manager over an isolated data directory, skipping backend detection
+    #[cfg(test)]
+    pub(crate) fn with_data_dir(data_dir: PathBuf, backend: BackendType) -> Result<Self> {
+        let sandboxes_dir = data_dir.join("sandboxes");
+        std::fs::create_dir_all(&sandboxes_dir)?;
+        let sandboxes = Self::load_sandboxes(&sandboxes_dir)?;
+        let cids = CidPool::new(sandboxes.values().map(|s| s.vsock_cid));
+        Ok(Self {
+            backend,
+            running: HashMap::new(),
+            sandboxes,
+            state_stamps: HashMap::new(),
+            data_dir,
+            rootfs_dir: None,
+            cids: Arc::new(Mutex::new(cids)),
+            restart_configs: HashMap::new(),
+            require_image_digest: false,
+        })
+    }
 
-        for (name, sandbox_backend) in sandboxes_to_check {
-            // Check if the sandbox is running
-            let is_running = match sandbox_backend {
-                BackendType::Docker | BackendType::Podman => {
-                    self.detect_docker_sandbox_running(&name, sandbox_backend)
-                }
-                _ => false, // Other backends need more complex detection
-            };
+    /// Remove ephemeral sandboxes and containers older than `max_age`.
+    ///
+    /// Covers both persisted ephemeral sandboxes (`run-*`, `api-run-*`, ...) and
+    /// labelled containers from `docker run --rm` paths that never got a state file.
+    /// Sandboxes whose owning process is still running (a long `run`, or the
+    /// HTTP server) are left alone, however old.
+    /// Returns the names of everything removed.
+    pub fn reap_ephemeral(&mut self, max_age: Duration) -> Vec<String> {
+        let stale = self.stale_ephemeral(max_age);
 
-            if is_running {
-                // Recreate the sandbox object for the running container
-                // Note: DockerSandbox::is_running() checks Docker directly
-                if let Ok(sandbox) = create_sandbox(sandbox_backend, &name) {
-                    self.running.insert(name.clone(), sandbox);
+        let mut removed = Vec::new();
+        for (name, backend) in stale {
+            if self.remove_leftover(&name, backend) {
+                removed.push(name);
+            }
+        }
+
+        // Labelled containers (covers ephemeral runs with no state file)
+        if let Some(runtime) = detect_container_runtime()
+            && let Ok(containers) = reap_ephemeral_containers(runtime, max_age.as_secs())
+        {
+            for container in containers {
+                let name = container
+                    .strip_prefix("agentkernel-")
+                    .unwrap_or(&container)
+                    .to_string();
+                if !removed.contains(&name) {
+                    removed.push(name);
                 }
             }
         }
+
+        removed
     }
 
-    /// Check if a Docker/Podman sandbox is currently running
-    fn detect_docker_sandbox_running(&self, name: &str, backend: BackendType) -> bool {
-        use std::process::Command;
+    /// Ephemeral sandboxes at least `max_age` old whose owner has exited
+    fn stale_ephemeral(&self, max_age: Duration) -> Vec<(String, BackendType)> {
+        let now = chrono::Utc::now();
+        self.sandboxes
+            .values()
+            .filter(|s| {
+                s.ephemeral
+                    && is_stale(&s.created_at, now, max_age)
+                    && !s.owner.as_deref().is_some_and(owner_alive)
+            })
+            .map(|s| (s.name.clone(), s.backend.unwrap_or(self.backend)))
+            .collect()
+    }
 
-        let cmd = match backend {
-            BackendType::Docker => "docker",
-            BackendType::Podman => "podman",
-            _ => return false,
+    /// Set a sandbox's restart policy, used from its next start
+    pub fn set_restart_policy(&mut self, name: &str, restart: RestartPolicy) -> Result<()> {
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
         };
+        state.restart = restart;
+        let state = state.clone();
+        self.save_sandbox(&state)
+    }
 
-        let container_name = format!("agentkernel-{}", name);
+    /// Set a script to run once, on the sandbox's next (first) start
+    pub fn set_setup_script(&mut self, name: &str, script: &str) -> Result<()> {
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
+        };
+        state.setup_script = Some(script.to_string());
+        state.setup_done = false;
+        let state = state.clone();
+        self.save_sandbox(&state)
+    }
 
-        Command::new(cmd)
-            .args(["ps", "-q", "-f", &format!("name={}", container_name)])
-            .output()
-            .map(|o| !String::from_utf8_lossy(&o.stdout).trim().is_empty())
-            .unwrap_or(false)
+    /// Publish sandbox ports on the host, from the next start on.
+    ///
+    /// Only container backends can publish ports.
+    pub fn set_ports(&mut self, name: &str, ports: Vec<PortMapping>) -> Result<()> {
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
+        };
+        let backend = state.backend.unwrap_or(self.backend);
+        if !ports.is_empty() && !matches!(backend, BackendType::Docker | BackendType::Podman) {
+            bail!(
+                "Publishing ports needs the docker or podman backend, but '{}' uses {}",
+                name,
+                backend
+            );
+        }
+        state.ports = ports;
+        let state = state.clone();
+        self.save_sandbox(&state)
     }
 
-    /// Get the data directory
-    fn data_dir() -> PathBuf {
-        if let Some(home) = std::env::var_os("HOME") {
-            PathBuf::from(home).join(".local/share/agentkernel")
+    /// Seed the sandbox's filesystem from a checkpoint tarball on its next (first) start
+    pub fn set_checkpoint(&mut self, name: &str, path: &Path) -> Result<()> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Checkpoint not found: {}", path.display()))?;
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
+        };
+        state.checkpoint = Some(path);
+        let state = state.clone();
+        self.save_sandbox(&state)
+    }
+
+    /// Set or clear (`None`) a sandbox's TTL, counted from now
+    pub fn set_ttl(&mut self, name: &str, ttl: Option<Duration>) -> Result<()> {
+        let expires_at = ttl
+            .map(|ttl| expiry_time(chrono::Utc::now(), ttl))
+            .transpose()?;
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
+        };
+        state.expires_at = expires_at;
+        let state = state.clone();
+        self.save_sandbox(&state)
+    }
+
+    /// Sandboxes whose TTL has run out
+    fn expired_sandboxes(&self, now: chrono::DateTime<chrono::Utc>) -> Vec<(String, BackendType)> {
+        self.sandboxes
+            .values()
+            .filter(|s| s.expires_at.as_deref().is_some_and(|e| is_expired(e, now)))
+            .map(|s| (s.name.clone(), s.backend.unwrap_or(self.backend)))
+            .collect()
+    }
+
+    /// Remove sandboxes whose TTL has run out, returning their names
+    pub fn reap_expired(&mut self) -> Vec<String> {
+        let mut removed = Vec::new();
+        for (name, backend) in self.expired_sandboxes(chrono::Utc::now()) {
+            if self.remove_leftover(&name, backend) {
+                removed.push(name);
+            }
+        }
+        removed
+    }
+
+    /// Force-remove a sandbox without going through its running handle.
+    ///
+    /// Used by the reapers, which may run in a different process from the
+    /// one that started the sandbox. Returns whether its state was deleted.
+    fn remove_leftover(&mut self, name: &str, backend: BackendType) -> bool {
+        // Leave sandboxes that are being created or started for the next sweep
+        let Ok(_lock) = SandboxLock::acquire(&self.data_dir, name) else {
+            return false;
+        };
+        // A handle from this process stops its VM when dropped; the backend
+        // then cleans up after whichever process started the sandbox
+        self.running.remove(name);
+        self.restart_configs.remove(name);
+        let remove_container = |runtime: ContainerRuntime| {
+            let _ = std::process::Command::new(runtime.cmd())
+                .args(["rm", "-f", &format!("agentkernel-{}", name)])
+                .output();
+        };
+        match backend {
+            BackendType::Docker => remove_container(ContainerRuntime::Docker),
+            BackendType::Podman => remove_container(ContainerRuntime::Podman),
+            BackendType::Firecracker => crate::backend::firecracker::kill_vm(name),
+            BackendType::Apple => crate::backend::apple::remove_container(name),
+            BackendType::Hyperlight => {}
+        }
+        if self.delete_sandbox(name).is_err() {
+            return false;
+        }
+        self.forget(name);
+        log_event(AuditEvent::SandboxRemoved {
+            name: name.to_string(),
+        });
+        true
+    }
+
+    /// Pick up sandboxes created, changed or removed by other processes
+    /// since this manager last looked.
+    ///
+    /// Only state files whose modification time or size changed are read
+    /// again. Containers aren't looked at; see [`VmManager::unwatched`].
+    pub fn refresh(&mut self) -> Result<()> {
+        let dir = self.data_dir.join("sandboxes");
+        let entries = if dir.exists() {
+            std::fs::read_dir(&dir)?.collect::<std::io::Result<Vec<_>>>()?
         } else {
-            PathBuf::from("/tmp/agentkernel")
+            Vec::new()
+        };
+
+        let now = SystemTime::now();
+        let mut previous = std::mem::take(&mut self.sandboxes);
+        let mut sandboxes = HashMap::new();
+        let mut stamps = HashMap::new();
+        for entry in entries {
+            let path = entry.path();
+            let Some(stem) = path
+                .file_stem()
+                .filter(|_| path.extension().is_some_and(|e| e == "json"))
+                .map(|s| s.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let stamp = entry
+                .metadata()
+                .ok()
+                .and_then(|m| Some((m.modified().ok()?, m.len())));
+            let unchanged = stamp.is_some() && self.state_stamps.get(&stem) == stamp.as_ref();
+            let state = match previous.remove(&stem) {
+                Some(state) if unchanged => state,
+                _ => match Self::load_state(&path) {
+                    Some(state) => state,
+                    None => continue,
+                },
+            };
+            if let Some(stamp) = stamp
+                && now
+                    .duration_since(stamp.0)
+                    .is_ok_and(|age| age >= STATE_STAMP_SETTLE)
+            {
+                stamps.insert(stem, stamp);
+            }
+            sandboxes.insert(state.name.clone(), state);
+        }
+
+        self.running.retain(|name, _| sandboxes.contains_key(name));
+        self.restart_configs
+            .retain(|name, _| sandboxes.contains_key(name));
+        // Every allocated CID is saved before the pool is unlocked, so the
+        // state files are the full picture
+        self.cid_pool()
+            .sync(sandboxes.values().map(|s| s.vsock_cid));
+        self.sandboxes = sandboxes;
+        self.state_stamps = stamps;
+        Ok(())
+    }
+
+    /// Split off a manager for working on `name` concurrently with others.
+    ///
+    /// The sandbox's running handle moves to the new manager; hand it back
+    /// with [`VmManager::merge`]. Splits share the vsock CID pool.
+    pub fn split_off(&mut self, name: &str) -> Self {
+        Self {
+            backend: self.backend,
+            running: self.running.remove_entry(name).into_iter().collect(),
+            sandboxes: self.sandboxes.clone(),
+            state_stamps: self.state_stamps.clone(),
+            data_dir: self.data_dir.clone(),
+            rootfs_dir: self.rootfs_dir.clone(),
+            cids: Arc::clone(&self.cids),
+            restart_configs: self
+                .restart_configs
+                .remove_entry(name)
+                .into_iter()
+                .collect(),
+            require_image_digest: self.require_image_digest,
+        }
+    }
+
+    /// Take back the running sandboxes of a manager made by [`VmManager::split_off`].
+    ///
+    /// Call [`VmManager::refresh`] afterwards to pick up its state changes.
+    pub fn merge(&mut self, other: Self) {
+        self.running.extend(other.running);
+        self.restart_configs.extend(other.restart_configs);
+    }
+
+    /// Detect sandboxes that are already running (e.g., Docker containers)
+    ///
+    /// Sandboxes this manager already holds a handle for are left alone.
+    fn detect_running_sandboxes(&mut self) {
+        let detected = probe_sandboxes(self.unwatched());
+        self.apply_detected(detected);
+    }
+
+    /// Sandboxes this manager holds no handle for, to look for with
+    /// [`probe_sandboxes`]
+    pub fn unwatched(&self) -> Vec<Unwatched> {
+        self.sandboxes
+            .iter()
+            .filter(|(name, _)| !self.running.contains_key(*name))
+            .map(|(name, state)| Unwatched {
+                name: name.clone(),
+                backend: state.backend.unwrap_or(self.backend),
+                exit_recorded: state.last_exit.is_some(),
+            })
+            .collect()
+    }
+
+    /// Take in what [`probe_sandboxes`] found.
+    ///
+    /// The probe ran without this manager, so sandboxes removed or given a
+    /// handle since, or whose exit was recorded meanwhile, are left alone.
+    pub fn apply_detected(&mut self, detected: Vec<(String, Detected)>) {
+        for (name, found) in detected {
+            if self.running.contains_key(&name) {
+                continue;
+            }
+            let Some(state) = self.sandboxes.get(&name) else {
+                continue;
+            };
+            match found {
+                Detected::Running(sandbox) => {
+                    self.running.insert(name, sandbox);
+                }
+                Detected::Exited(reason) if state.last_exit.is_none() => {
+                    self.record_exit(&name, reason);
+                }
+                Detected::Exited(_) => {}
+            }
         }
     }
 
+    /// Get the data directory
+    fn data_dir() -> PathBuf {
+        crate::setup::default_data_dir()
+    }
+
     /// Load sandboxes from disk
     fn load_sandboxes(sandboxes_dir: &Path) -> Result<HashMap<String, SandboxState>> {
         let mut sandboxes = HashMap::new();
@@ -194,8 +1083,7 @@ impl VmManager {
                 let entry = entry?;
                 let path = entry.path();
                 if path.extension().is_some_and(|e| e == "json")
-                    && let Ok(content) = std::fs::read_to_string(&path)
-                    && let Ok(state) = serde_json::from_str::<SandboxState>(&content)
+                    && let Some(state) = Self::load_state(&path)
                 {
                     sandboxes.insert(state.name.clone(), state);
                 }
@@ -205,24 +1093,42 @@ impl VmManager {
         Ok(sandboxes)
     }
 
+    /// Read one state file, skipping unreadable ones
+    fn load_state(path: &Path) -> Option<SandboxState> {
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Path of a sandbox's state file
+    fn state_path(&self, name: &str) -> PathBuf {
+        self.data_dir
+            .join("sandboxes")
+            .join(format!("{}.json", name))
+    }
+
     /// Save a sandbox state to disk
     fn save_sandbox(&self, state: &SandboxState) -> Result<()> {
-        let path = self
-            .data_dir
-            .join("sandboxes")
-            .join(format!("{}.json", state.name));
+        let path = self.state_path(&state.name);
         let content = serde_json::to_string_pretty(state)?;
         std::fs::write(path, content)?;
         Ok(())
     }
 
     /// Delete a sandbox state from disk
-    fn delete_sandbox(&self, name: &str) -> Result<()> {
-        let path = self
-            .data_dir
-            .join("sandboxes")
-            .join(format!("{}.json", name));
-        if path.exists() {
+    /// Drop a deleted sandbox's state and free its CID
+    fn forget(&mut self, name: &str) {
+        if let Some(state) = self.sandboxes.remove(name) {
+            self.cid_pool().release(state.vsock_cid);
+        }
+    }
+
+    fn cid_pool(&self) -> std::sync::MutexGuard<'_, CidPool> {
+        self.cids.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn delete_sandbox(&self, name: &str) -> Result<()> {
+        let path = self.state_path(name);
+        if path.exists() {
             std::fs::remove_file(path)?;
         }
         Ok(())
@@ -230,11 +1136,9 @@ impl VmManager {
 
     /// Find the images directory
     fn find_images_dir() -> Result<PathBuf> {
-        if let Some(home) = std::env::var_os("HOME") {
-            let home_path = PathBuf::from(home).join(".local/share/agentkernel/images");
-            if home_path.join("kernel").exists() || home_path.join("rootfs").exists() {
-                return Ok(home_path);
-            }
+        let installed = Self::data_dir().join("images");
+        if installed.join("kernel").exists() || installed.join("rootfs").exists() {
+            return Ok(installed);
         }
 
         let paths = [PathBuf::from("images"), PathBuf::from("../images")];
@@ -273,21 +1177,163 @@ impl VmManager {
         vcpus: u32,
         memory_mb: u64,
     ) -> Result<()> {
-        if self.sandboxes.contains_key(name) {
+        self.create_sandbox_state(name, image, vcpus, memory_mb, false)
+    }
+
+    /// Create a sandbox from a local image archive, for hosts that can't pull.
+    ///
+    /// Docker and Podman `load` the archive (`docker save` or OCI layout
+    /// tarball) first; Firecracker boots a rootfs `.ext4` file as is.
+    /// Returns the image the sandbox was created with.
+    pub async fn create_from_archive(
+        &mut self,
+        name: &str,
+        archive: &Path,
+        vcpus: u32,
+        memory_mb: u64,
+    ) -> Result<String> {
+        let runtime = match self.backend {
+            BackendType::Docker => ContainerRuntime::Docker,
+            BackendType::Podman => ContainerRuntime::Podman,
+            BackendType::Firecracker => {
+                if !archive.extension().is_some_and(|ext| ext == "ext4") {
+                    bail!(
+                        "The firecracker backend needs a rootfs .ext4 file, not an image archive"
+                    );
+                }
+                self.create_from_rootfs(name, archive, vcpus, memory_mb, false)
+                    .await?;
+                return Ok(archive.display().to_string());
+            }
+            other => bail!("The {} backend can't load image archives", other),
+        };
+        self.create_from_archive_with(name, archive, vcpus, memory_mb, |archive| {
+            load_image_archive(runtime, archive)
+        })
+        .await
+    }
+
+    /// Create a Firecracker sandbox that boots a rootfs file on this host.
+    ///
+    /// Only for paths the local user chose (`--image-archive`, or a rootfs the
+    /// CLI converted from a Dockerfile): image names from the API or MCP never
+    /// name host files.
+    pub async fn create_from_rootfs(
+        &mut self,
+        name: &str,
+        rootfs: &Path,
+        vcpus: u32,
+        memory_mb: u64,
+        ephemeral: bool,
+    ) -> Result<()> {
+        if self.backend != BackendType::Firecracker {
+            bail!("Only the firecracker backend boots rootfs files");
+        }
+        // Stored absolute so `start` finds it from any directory
+        let path = std::path::absolute(rootfs)?;
+        if !path.is_file() {
+            bail!("Rootfs not found: {}", path.display());
+        }
+        self.insert_sandbox_state(
+            name,
+            format!("rootfs:{}", path.display()),
+            vcpus,
+            memory_mb,
+            ephemeral,
+        )
+    }
+
+    /// Load with `load`, then create the sandbox from the image it returns
+    async fn create_from_archive_with<F>(
+        &mut self,
+        name: &str,
+        archive: &Path,
+        vcpus: u32,
+        memory_mb: u64,
+        load: F,
+    ) -> Result<String>
+    where
+        F: FnOnce(&Path) -> Result<String>,
+    {
+        // Don't load anything for a name that's taken
+        if self.exists(name) {
             bail!("Sandbox '{}' already exists", name);
         }
+        let image = load(archive)?;
+        self.create(name, &image, vcpus, memory_mb).await?;
+        Ok(image)
+    }
+
+    /// Create a sandbox for a single command.
+    ///
+    /// Ephemeral sandboxes are removed by the reaper if the process that created
+    /// them dies before cleaning up.
+    pub async fn create_ephemeral(
+        &mut self,
+        name: &str,
+        image: &str,
+        vcpus: u32,
+        memory_mb: u64,
+    ) -> Result<()> {
+        self.create_sandbox_state(name, image, vcpus, memory_mb, true)
+    }
 
+    fn create_sandbox_state(
+        &mut self,
+        name: &str,
+        image: &str,
+        vcpus: u32,
+        memory_mb: u64,
+        ephemeral: bool,
+    ) -> Result<()> {
+        self.check_image_pinned(image)?;
         // For Firecracker, convert Docker image names to runtime names
         let effective_image = if self.backend == BackendType::Firecracker {
+            if local_rootfs(image).is_some() || image.ends_with(".ext4") {
+                bail!(
+                    "Image '{}' is a file path. Boot a local rootfs with: agentkernel create {} --image-archive <FILE>",
+                    image,
+                    name
+                );
+            }
             let runtime = docker_image_to_firecracker_runtime(image);
             self.rootfs_path(runtime)?;
             runtime.to_string()
         } else {
             image.to_string()
         };
+        self.insert_sandbox_state(name, effective_image, vcpus, memory_mb, ephemeral)
+    }
+
+    /// Reject floating tags when ./agentkernel.toml requires pinned images.
+    ///
+    /// Checked here so sandboxes created through the HTTP API and MCP are
+    /// held to it as well as the CLI's.
+    fn check_image_pinned(&self, image: &str) -> Result<()> {
+        if self.require_image_digest {
+            crate::config::require_pinned_image(image)?;
+        }
+        Ok(())
+    }
+
+    /// Record a new sandbox with an image already resolved for the backend
+    fn insert_sandbox_state(
+        &mut self,
+        name: &str,
+        effective_image: String,
+        vcpus: u32,
+        memory_mb: u64,
+        ephemeral: bool,
+    ) -> Result<()> {
+        let _lock = SandboxLock::acquire(&self.data_dir, name)?;
+        // Another process may have created it since our state was loaded
+        if self.sandboxes.contains_key(name) || self.state_path(name).exists() {
+            bail!("Sandbox '{}' already exists", name);
+        }
 
-        let vsock_cid = self.next_cid;
-        self.next_cid += 1;
+        // Held until the state is saved so no other manager can take the CID
+        let mut cids = self.cid_pool();
+        let vsock_cid = cids.allocate();
 
         let state = SandboxState {
             name: name.to_string(),
@@ -297,9 +1343,28 @@ impl VmManager {
             vsock_cid,
             created_at: chrono::Utc::now().to_rfc3339(),
             backend: Some(self.backend),
+            ephemeral,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: ephemeral.then(current_owner),
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
         };
 
-        self.save_sandbox(&state)?;
+        if let Err(e) = self.save_sandbox(&state) {
+            cids.release(vsock_cid);
+            return Err(e);
+        }
+        drop(cids);
         self.sandboxes.insert(name.to_string(), state);
 
         log_event(AuditEvent::SandboxCreated {
@@ -323,6 +1388,29 @@ impl VmManager {
             .await
     }
 
+    /// Start a sandbox that was just created, removing it if it doesn't start.
+    ///
+    /// For `create --start`, so a failed start doesn't leave a sandbox behind.
+    pub async fn start_created(&mut self, name: &str, perms: &Permissions) -> Result<()> {
+        let result = self.start_with_permissions(name, perms).await;
+        self.remove_if_failed(name, result).await
+    }
+
+    /// Remove `name` (stopping it if it got as far as running) when `result` failed
+    async fn remove_if_failed(&mut self, name: &str, result: Result<()>) -> Result<()> {
+        let Err(e) = result else {
+            return Ok(());
+        };
+        if let Err(remove_err) = self.remove(name).await {
+            eprintln!("Warning: Failed to remove '{}': {}", name, remove_err);
+            return Err(e);
+        }
+        Err(e.context(format!(
+            "Sandbox '{}' failed to start and was removed",
+            name
+        )))
+    }
+
     /// Start a sandbox with specific permissions and files to inject
     pub async fn start_with_permissions_and_files(
         &mut self,
@@ -330,10 +1418,11 @@ impl VmManager {
         perms: &Permissions,
         files: &[FileInjection],
     ) -> Result<()> {
+        let _lock = SandboxLock::acquire(&self.data_dir, name)?;
         let state = self
             .sandboxes
             .get(name)
-            .ok_or_else(|| anyhow::anyhow!("Sandbox '{}' not found", name))?
+            .ok_or_else(|| not_found_error(name))?
             .clone();
 
         if self.running.contains_key(name) {
@@ -344,7 +1433,7 @@ impl VmManager {
         let backend = state.backend.unwrap_or(self.backend);
 
         // Create sandbox using unified factory
-        let mut sandbox = create_sandbox(backend, name)?;
+        let sandbox = create_sandbox(backend, name)?;
 
         // Convert permissions to SandboxConfig
         let work_dir = if perms.mount_cwd {
@@ -356,7 +1445,7 @@ impl VmManager {
         };
 
         // Build environment variables if pass_env is enabled
-        let env = if perms.pass_env {
+        let mut env: Vec<(String, String)> = if perms.pass_env {
             ["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM"]
                 .iter()
                 .filter_map(|&var| std::env::var(var).ok().map(|val| (var.to_string(), val)))
@@ -364,6 +1453,7 @@ impl VmManager {
         } else {
             Vec::new()
         };
+        env.extend(perms.env.iter().cloned());
 
         let config = SandboxConfig {
             image: state.image.clone(),
@@ -372,49 +1462,195 @@ impl VmManager {
             mount_cwd: perms.mount_cwd,
             work_dir,
             env,
-            network: perms.network,
+            network: state.network.unwrap_or(perms.network),
+            network_mode: perms.network_mode.clone(),
             read_only: perms.read_only_root,
             mount_home: perms.mount_home,
+            writable_path: perms.writable_workspace.clone(),
             files: files.to_vec(),
+            ephemeral: state.ephemeral,
+            dns: perms.dns.clone(),
+            extra_hosts: perms.extra_hosts.clone(),
+            labels: state.labels.clone(),
+            user: perms.user.as_deref().map(resolve_user),
+            scratch_mb: perms.scratch_mb,
+            tmp_size_mb: perms.tmp_size_mb,
+            run_size_mb: perms.run_size_mb,
+            ulimits: perms.ulimits.for_image(&state.image),
+            init: perms.init,
+            agent_port: perms.agent_port,
+            vsock_cid: Some(state.vsock_cid),
+            egress: perms.egress.clone(),
+            ports: state.ports.clone(),
+            hostname: Some(
+                perms
+                    .hostname
+                    .clone()
+                    .unwrap_or_else(|| default_hostname(&state.name)),
+            ),
+            gpus: perms.gpus.clone(),
+            mounts: resolve_mounts(&perms.mounts)?,
+            restart: match perms.restart {
+                _ if state.ephemeral => RestartPolicy::Never,
+                RestartPolicy::Never => state.restart,
+                policy => policy,
+            },
         };
 
+        self.launch(name, backend, sandbox, config, format!("{:?}", perms))
+            .await
+    }
+
+    /// Start a backend sandbox and record it as running, timing the boot
+    async fn launch(
+        &mut self,
+        name: &str,
+        backend: BackendType,
+        mut sandbox: Box<dyn Sandbox>,
+        config: SandboxConfig,
+        profile: String,
+    ) -> Result<()> {
+        let boot_started = Instant::now();
         sandbox.start(&config).await?;
+        let boot_ms = boot_started.elapsed().as_millis() as u64;
+        let command = sandbox.launch_command();
 
         // Inject files if any were specified
-        if !files.is_empty() {
-            sandbox.inject_files(files).await?;
+        if !config.files.is_empty() {
+            sandbox.inject_files(&config.files).await?;
         }
 
         self.running.insert(name.to_string(), sandbox);
+        self.restore_checkpoint(name, "/").await?;
+        self.run_setup_script(name).await?;
+
+        // Record what the image tag resolved to for this run
+        let digest = match backend {
+            BackendType::Docker => image_digest(ContainerRuntime::Docker, &config.image),
+            BackendType::Podman => image_digest(ContainerRuntime::Podman, &config.image),
+            _ => None,
+        };
+
+        // Docker and Podman apply the restart policy themselves
+        if config.restart != RestartPolicy::Never
+            && !matches!(backend, BackendType::Docker | BackendType::Podman)
+        {
+            self.restart_configs.insert(name.to_string(), config);
+        } else {
+            self.restart_configs.remove(name);
+        }
+
+        if let Some(state) = self.sandboxes.get_mut(name) {
+            state.last_exit = None;
+            state.restart_count = 0;
+            if digest.is_some() {
+                state.image_digest = digest;
+            }
+            state.last_boot_ms = Some(boot_ms);
+            state.launch_command = command.clone();
+            let state = state.clone();
+            self.save_sandbox(&state)?;
+        }
 
         log_event(AuditEvent::SandboxStarted {
             name: name.to_string(),
-            profile: Some(format!("{:?}", perms)),
+            profile: Some(profile),
+            boot_ms: Some(boot_ms),
+            command,
         });
 
         Ok(())
     }
 
-    /// Check if a command is allowed by the security policy in agentkernel.toml.
-    /// Logs a PolicyViolation audit event and returns an error if blocked.
-    fn enforce_command_policy(cmd: &[String]) -> Result<()> {
-        if let Some(binary) = cmd.first()
-            && let Ok(cfg) = Config::from_file(&PathBuf::from("agentkernel.toml"))
-            && !cfg.security.commands.is_allowed(binary)
-        {
-            log_event(AuditEvent::PolicyViolation {
-                sandbox: "ephemeral".to_string(),
-                policy: "commands".to_string(),
-                details: format!("blocked command: {}", binary),
-            });
+    /// Extract the sandbox's pending checkpoint under `root`, then forget it.
+    ///
+    /// If it can't be restored the sandbox is stopped, and the restore is
+    /// tried again on the next start.
+    async fn restore_checkpoint(&mut self, name: &str, root: &str) -> Result<()> {
+        let Some(path) = self.sandboxes.get(name).and_then(|s| s.checkpoint.clone()) else {
+            return Ok(());
+        };
+        let Some(sandbox) = self.running.get_mut(name) else {
+            return Ok(());
+        };
+
+        eprintln!("Restoring checkpoint {} into '{}'...", path.display(), name);
+        if let Err(e) = extract_checkpoint(sandbox.as_mut(), &path, root).await {
+            if let Some(mut sandbox) = self.running.remove(name) {
+                let _ = sandbox.stop().await;
+            }
+            bail!(
+                "Failed to restore checkpoint {} into '{}': {}",
+                path.display(),
+                name,
+                e
+            );
+        }
+
+        if let Some(state) = self.sandboxes.get_mut(name) {
+            state.checkpoint = None;
+            let state = state.clone();
+            self.save_sandbox(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Run the sandbox's setup script if it hasn't completed yet.
+    ///
+    /// Its output goes to the audit log. If it fails the sandbox is stopped,
+    /// and the script runs again on the next start.
+    async fn run_setup_script(&mut self, name: &str) -> Result<()> {
+        let Some(state) = self.sandboxes.get(name) else {
+            return Ok(());
+        };
+        let Some(script) = state.setup_script.clone().filter(|_| !state.setup_done) else {
+            return Ok(());
+        };
+        let Some(sandbox) = self.running.get_mut(name) else {
+            return Ok(());
+        };
+
+        eprintln!("Running setup script for '{}'...", name);
+        let opts = ExecOptions::default().max_output(Some(DEFAULT_MAX_OUTPUT_BYTES));
+        let result = sandbox
+            .exec_with_options(&["sh", "-c", &script], &opts)
+            .await;
+        let (exit_code, output) = match &result {
+            Ok(result) => (result.exit_code, output_tail(&result.output())),
+            Err(e) => (-1, e.to_string()),
+        };
+        log_event(AuditEvent::SetupScriptRun {
+            sandbox: name.to_string(),
+            exit_code,
+            output: output.clone(),
+        });
+
+        if exit_code != 0 {
+            if let Some(mut sandbox) = self.running.remove(name) {
+                let _ = sandbox.stop().await;
+            }
             bail!(
-                "Command '{}' blocked by security policy. Check [security.commands] in agentkernel.toml",
-                binary
+                "Setup script for '{}' failed with exit code {}: {}",
+                name,
+                exit_code,
+                output.trim()
             );
         }
+
+        if let Some(state) = self.sandboxes.get_mut(name) {
+            state.setup_done = true;
+            let state = state.clone();
+            self.save_sandbox(&state)?;
+        }
         Ok(())
     }
 
+    /// Check if a command is allowed by the security policy in scope.
+    /// Logs a PolicyViolation audit event and returns an error if blocked.
+    fn enforce_command_policy(sandbox: &str, cmd: &[String]) -> Result<()> {
+        PolicyScope::current().enforce_command(cmd, sandbox)
+    }
+
     /// Execute a command in a sandbox
     pub async fn exec_cmd(&mut self, name: &str, cmd: &[String]) -> Result<String> {
         self.exec_cmd_with_env(name, cmd, &[]).await
@@ -427,26 +1663,18 @@ impl VmManager {
         cmd: &[String],
         env: &[String],
     ) -> Result<String> {
-        Self::enforce_command_policy(cmd)?;
-
-        let sandbox = self.running.get_mut(name).ok_or_else(|| {
-            anyhow::anyhow!(
-                "Sandbox '{}' is not running. Start it with: agentkernel start {}",
-                name,
-                name
-            )
-        })?;
-
-        // Convert &[String] to &[&str]
-        let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
-
-        let result = sandbox.exec_with_env(&cmd_refs, env).await?;
+        self.exec_cmd_with_options(name, cmd, &ExecOptions::with_env(env))
+            .await
+    }
 
-        log_event(AuditEvent::CommandExecuted {
-            sandbox: name.to_string(),
-            command: cmd.to_vec(),
-            exit_code: Some(result.exit_code),
-        });
+    /// Execute a command in a sandbox with per-command options
+    pub async fn exec_cmd_with_options(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        opts: &ExecOptions,
+    ) -> Result<String> {
+        let result = self.exec_cmd_full(name, cmd, opts).await?;
 
         if result.exit_code != 0 {
             bail!(
@@ -459,91 +1687,648 @@ impl VmManager {
         Ok(result.output())
     }
 
-    /// Attach to a sandbox's interactive shell with optional environment variables
-    pub async fn attach_with_env(&mut self, name: &str, env: &[String]) -> Result<i32> {
-        let sandbox = self.running.get_mut(name).ok_or_else(|| {
-            anyhow::anyhow!(
-                "Sandbox '{}' is not running. Start it with: agentkernel start {}",
-                name,
-                name
-            )
-        })?;
+    /// Execute a command in a sandbox and return the full result
+    ///
+    /// Unlike `exec_cmd_with_options`, a non-zero exit code is not an error.
+    pub async fn exec_cmd_full(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        opts: &ExecOptions,
+    ) -> Result<ExecResult> {
+        Self::enforce_command_policy(name, cmd)?;
 
-        log_event(AuditEvent::SessionAttached {
-            sandbox: name.to_string(),
-        });
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        // Convert &[String] to &[&str]
+        let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
 
-        sandbox.attach_with_env(None, env).await
+        let result = match sandbox.exec_with_options(&cmd_refs, opts).await {
+            Ok(result) => result,
+            Err(e) => return Err(self.exec_failed(name, e).await),
+        };
+        self.exec_finished(name, cmd, result.exit_code).await?;
+        Ok(result)
     }
 
-    /// Stop a sandbox
-    pub async fn stop(&mut self, name: &str) -> Result<()> {
-        if let Some(mut sandbox) = self.running.remove(name) {
-            sandbox.stop().await?;
-            log_event(AuditEvent::SandboxStopped {
-                name: name.to_string(),
-            });
+    /// Execute a command in a sandbox and keep its output as raw bytes
+    ///
+    /// Like `exec_cmd_full`, but output that isn't valid UTF-8 (e.g. a
+    /// binary written to stdout) comes back unchanged on backends that
+    /// support it.
+    pub async fn exec_bytes(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        opts: &ExecOptions,
+    ) -> Result<ExecBytes> {
+        Self::enforce_command_policy(name, cmd)?;
+
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
+        let result = match sandbox.exec_bytes(&cmd_refs, opts).await {
+            Ok(result) => result,
+            Err(e) => return Err(self.exec_failed(name, e).await),
+        };
+        self.exec_finished(name, cmd, result.exit_code).await?;
+        Ok(result)
+    }
+
+    /// Audit a finished command, and fail if the sandbox died with it
+    async fn exec_finished(&mut self, name: &str, cmd: &[String], exit_code: i32) -> Result<()> {
+        log_event(AuditEvent::CommandExecuted {
+            sandbox: name.to_string(),
+            command: cmd.to_vec(),
+            exit_code: Some(exit_code),
+        });
+
+        // A failure may mean the sandbox itself died (e.g. OOM-killed)
+        if exit_code != 0
+            && let Some(reason) = self.reap_exited(name).await
+        {
+            bail!("Sandbox '{}' exited: {}", name, reason);
         }
         Ok(())
     }
 
-    /// Remove a sandbox
-    pub async fn remove(&mut self, name: &str) -> Result<()> {
+    /// Turn an exec error into the one to report.
+    ///
+    /// If the sandbox's container was removed behind our back, the sandbox
+    /// is dropped from the running set and the exit is recorded, so later
+    /// calls see it as stopped.
+    async fn exec_failed(&mut self, name: &str, err: anyhow::Error) -> anyhow::Error {
+        if err.downcast_ref::<SandboxGone>().is_none() {
+            return err;
+        }
         if let Some(mut sandbox) = self.running.remove(name) {
             let _ = sandbox.stop().await;
         }
-
-        self.delete_sandbox(name)?;
-        self.sandboxes.remove(name);
-
-        log_event(AuditEvent::SandboxRemoved {
+        log_event(AuditEvent::SandboxStopped {
             name: name.to_string(),
         });
-
-        Ok(())
+        self.record_exit(
+            name,
+            ExitReason {
+                error: Some("container removed outside agentkernel".to_string()),
+                finished_at: Some(chrono::Utc::now().to_rfc3339()),
+                ..Default::default()
+            },
+        );
+        err.context(format!(
+            "Sandbox '{}' is gone: its container was removed outside agentkernel. \
+             Recreate it with: agentkernel start {}",
+            name, name
+        ))
     }
 
-    /// List all sandboxes (persisted, with running status and backend)
-    pub fn list(&self) -> Vec<(&str, bool, Option<BackendType>)> {
-        self.sandboxes
-            .iter()
-            .map(|(name, state)| {
-                let running = self
-                    .running
-                    .get(name)
-                    .map(|s| s.is_running())
-                    .unwrap_or(false);
-                (name.as_str(), running, state.backend)
-            })
-            .collect()
+    /// Launch a command in the background inside a running sandbox.
+    ///
+    /// Output is redirected to the `DetachedPaths::DEFAULT` logs inside the
+    /// sandbox, which [`VmManager::logs`] reads.
+    pub async fn exec_detached(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        opts: &ExecOptions,
+    ) -> Result<()> {
+        let wrapper = detached_command(cmd, &DetachedPaths::DEFAULT, None);
+        self.launch_detached(name, cmd, &wrapper, opts).await
     }
 
-    /// Check if a sandbox exists
-    pub fn exists(&self, name: &str) -> bool {
-        self.sandboxes.contains_key(name)
+    /// Start a command in the background in a running sandbox (`exec --detach`).
+    ///
+    /// Unlike `exec_detached`, each command gets its own files under
+    /// `BACKGROUND_EXEC_DIR`, so several can run at once. Returns the id to
+    /// list, read the output of, or kill the command by.
+    pub async fn exec_background(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        opts: &ExecOptions,
+    ) -> Result<String> {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+        let paths = BackgroundExecPaths::new(BACKGROUND_EXEC_DIR, &id);
+        // A newline would split the command's entry in `exec --list`
+        let cmdline = display_command(&cmd[0], &cmd[1..]).replace('\n', " ");
+        let setup = format!(
+            "mkdir -p {} && printf '%s\\n' {} > {}",
+            shell_quote(BACKGROUND_EXEC_DIR),
+            shell_quote(&cmdline),
+            shell_quote(&paths.cmd)
+        );
+        let wrapper = detached_command(cmd, &paths.detached(), Some(&setup));
+        self.launch_detached(name, cmd, &wrapper, opts).await?;
+        Ok(id)
     }
 
-    /// Get the backend type for a sandbox (from stored state or current default)
-    /// Check if a sandbox is currently running
-    pub fn is_running(&self, name: &str) -> bool {
-        self.running
-            .get(name)
-            .map(|s| s.is_running())
-            .unwrap_or(false)
+    /// Run a `detached_command` wrapper, which returns once `cmd` is launched
+    async fn launch_detached(
+        &mut self,
+        name: &str,
+        cmd: &[String],
+        wrapper: &[String],
+        opts: &ExecOptions,
+    ) -> Result<()> {
+        Self::enforce_command_policy(name, cmd)?;
+
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        let wrapper_refs: Vec<&str> = wrapper.iter().map(|s| s.as_str()).collect();
+        let result = match sandbox.exec_with_options(&wrapper_refs, opts).await {
+            Ok(result) => result,
+            Err(e) => return Err(self.exec_failed(name, e).await),
+        };
+        if result.exit_code != 0 {
+            bail!(
+                "Failed to launch background command: {}",
+                result.output().trim()
+            );
+        }
+
+        // The command keeps running, so there is no exit code to record yet
+        log_event(AuditEvent::CommandExecuted {
+            sandbox: name.to_string(),
+            command: cmd.to_vec(),
+            exit_code: None,
+        });
+
+        Ok(())
     }
 
-    /// Get the current backend
-    #[allow(dead_code)]
-    pub fn backend(&self) -> BackendType {
-        self.backend
+    /// Commands started in a sandbox with `exec --detach`
+    pub async fn background_execs(&mut self, name: &str) -> Result<Vec<BackgroundExec>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        let cmd = background_list_command(BACKGROUND_EXEC_DIR);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = sandbox.exec(&cmd).await?;
+        if result.exit_code != 0 {
+            bail!(
+                "Failed to list background commands: {}",
+                result.output().trim()
+            );
+        }
+        parse_background_list(&result.stdout)
+    }
+
+    /// Paths of a background command, failing if the sandbox has none by that id
+    async fn background_paths(&mut self, name: &str, id: &str) -> Result<BackgroundExecPaths> {
+        validate_background_id(id)?;
+        let execs = self.background_execs(name).await?;
+        if !execs.iter().any(|e| e.id == id) {
+            bail!(
+                "No background command '{}' in sandbox '{}'. List them with: agentkernel exec {} --list",
+                id,
+                name,
+                name
+            );
+        }
+        Ok(BackgroundExecPaths::new(BACKGROUND_EXEC_DIR, id))
+    }
+
+    /// Combined output of a command started with `exec --detach`
+    pub async fn background_logs(
+        &mut self,
+        name: &str,
+        id: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<LogLine>> {
+        let paths = self.background_paths(name, id).await?;
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        let cmd = detached_log_command(&paths.detached(), tail);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = sandbox.exec(&cmd).await?;
+        Ok(LogLine::from_detached(&result))
+    }
+
+    /// Stop a command started with `exec --detach`. The sandbox keeps running.
+    pub async fn kill_background(&mut self, name: &str, id: &str) -> Result<()> {
+        let paths = self.background_paths(name, id).await?;
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        let cmd = background_kill_command(&paths);
+        let cmd: Vec<&str> = cmd.iter().map(String::as_str).collect();
+        let result = sandbox.exec(&cmd).await?;
+        if result.exit_code != 0 {
+            bail!(
+                "Failed to kill background command: {}",
+                result.output().trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Wait for a sandbox's background command to exit and return its exit code
+    pub async fn wait(&mut self, name: &str, timeout: Option<Duration>) -> Result<i32> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.wait(timeout).await
+    }
+
+    /// Exit code of a sandbox's background command, or None while it runs
+    pub async fn detached_status(&mut self, name: &str) -> Result<Option<i32>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.detached_status().await
+    }
+
+    /// Captured output of a sandbox's processes, oldest first. With `tail`,
+    /// at least the last `tail` lines.
+    pub async fn logs(&mut self, name: &str, tail: Option<usize>) -> Result<Vec<LogLine>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.logs(tail).await
+    }
+
+    /// Bytes of host disk a running sandbox uses
+    pub async fn disk_usage(&self, name: &str) -> Result<u64> {
+        let sandbox = self
+            .running
+            .get(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.disk_usage().await
+    }
+
+    /// A running sandbox's IP address on its network, if it has one
+    pub async fn ip_address(&self, name: &str) -> Result<Option<std::net::IpAddr>> {
+        let sandbox = self
+            .running
+            .get(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.ip_address().await
+    }
+
+    /// The shell to attach with: `shell` if given, otherwise the first of
+    /// [`SHELL_FALLBACKS`] found in the sandbox
+    pub async fn resolve_shell(&mut self, name: &str, shell: Option<&str>) -> Result<String> {
+        if let Some(shell) = shell {
+            return Ok(shell.to_string());
+        }
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        detect_shell(sandbox.as_mut(), SHELL_FALLBACKS).await
+    }
+
+    /// Attach to a sandbox's interactive shell with optional environment variables
+    pub async fn attach_with_env(
+        &mut self,
+        name: &str,
+        shell: Option<&str>,
+        env: &[String],
+    ) -> Result<i32> {
+        let shell = self.resolve_shell(name, shell).await?;
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        log_event(AuditEvent::SessionAttached {
+            sandbox: name.to_string(),
+        });
+
+        sandbox.attach_with_env(Some(&shell), env).await
+    }
+
+    /// Stop a sandbox.
+    ///
+    /// Returns the exit reason if the sandbox had already exited on its own.
+    pub async fn stop(&mut self, name: &str) -> Result<Option<ExitReason>> {
+        // A stopped sandbox stays stopped whatever its restart policy
+        self.restart_configs.remove(name);
+        if let Some(reason) = self.reap_exited(name).await {
+            return Ok(Some(reason));
+        }
+        if let Some(mut sandbox) = self.running.remove(name) {
+            sandbox.stop().await?;
+            log_event(AuditEvent::SandboxStopped {
+                name: name.to_string(),
+            });
+            return Ok(None);
+        }
+        Ok(self.sandboxes.get(name).and_then(|s| s.last_exit.clone()))
+    }
+
+    /// Stop every running sandbox, e.g. before a host reboot.
+    ///
+    /// Returns each sandbox with the result of stopping it, in name order.
+    /// A failure doesn't keep the rest from being stopped.
+    pub async fn stop_all(&mut self) -> Vec<(String, Result<Option<ExitReason>>)> {
+        let mut names: Vec<String> = self.running.keys().cloned().collect();
+        names.sort();
+        let mut results = Vec::with_capacity(names.len());
+        for name in names {
+            let result = self.stop(&name).await;
+            results.push((name, result));
+        }
+        results
+    }
+
+    /// Shut down the Firecracker VM pool daemon. Returns false if none was running.
+    pub async fn stop_daemon_pool() -> Result<bool> {
+        let client = crate::daemon::DaemonClient::new();
+        if !client.is_available() {
+            return Ok(false);
+        }
+        client.shutdown().await?;
+        Ok(true)
+    }
+
+    /// If a running sandbox has exited on its own, clean it up and record why.
+    ///
+    /// Returns the exit reason, or `None` if the sandbox is still running
+    /// (or the backend can't tell why it stopped).
+    async fn reap_exited(&mut self, name: &str) -> Option<ExitReason> {
+        let sandbox = self.running.get_mut(name)?;
+        if sandbox.is_running() {
+            return None;
+        }
+        let reason = sandbox.exit_reason();
+
+        if let Some(mut sandbox) = self.running.remove(name) {
+            let _ = sandbox.stop().await;
+        }
+        log_event(AuditEvent::SandboxStopped {
+            name: name.to_string(),
+        });
+
+        let reason = reason?;
+        self.record_exit(name, reason.clone());
+        Some(reason)
+    }
+
+    /// Sandboxes this manager started that have a restart policy
+    pub fn restart_candidates(&self) -> Vec<String> {
+        self.restart_configs.keys().cloned().collect()
+    }
+
+    /// Restart sandboxes that exited on their own, following their restart
+    /// policy up to [`crate::backend::MAX_RESTARTS`] times.
+    ///
+    /// Docker and Podman restart containers themselves; this covers the other
+    /// backends for sandboxes started by this manager. Returns the names restarted.
+    pub async fn restart_exited(&mut self) -> Vec<String> {
+        let mut restarted = Vec::new();
+        let names: Vec<String> = self.restart_configs.keys().cloned().collect();
+        for name in names {
+            if self.running.get(&name).is_some_and(|s| s.is_running()) {
+                continue;
+            }
+            // An exec may already have noticed the exit and recorded it
+            let reason = match self.reap_exited(&name).await {
+                Some(reason) => Some(reason),
+                None => self.sandboxes.get(&name).and_then(|s| s.last_exit.clone()),
+            };
+            let Some(state) = self.sandboxes.get(&name) else {
+                self.restart_configs.remove(&name);
+                continue;
+            };
+            let Some(config) = self.restart_configs.get(&name).cloned() else {
+                continue;
+            };
+            let exit_code = reason.and_then(|r| r.exit_code);
+            if !config
+                .restart
+                .should_restart(exit_code, state.restart_count)
+            {
+                self.restart_configs.remove(&name);
+                continue;
+            }
+            // Skip this round if someone else is starting or removing it
+            let Ok(_lock) = SandboxLock::acquire(&self.data_dir, &name) else {
+                continue;
+            };
+            let backend = state.backend.unwrap_or(self.backend);
+
+            // Count failed attempts too, so a sandbox that can't start gives up
+            if let Some(state) = self.sandboxes.get_mut(&name) {
+                state.restart_count += 1;
+                let state = state.clone();
+                if let Err(e) = self.save_sandbox(&state) {
+                    eprintln!(
+                        "Warning: failed to save restart count for '{}': {}",
+                        name, e
+                    );
+                }
+            }
+
+            let boot_started = Instant::now();
+            match Self::relaunch(backend, &name, &config).await {
+                Ok(sandbox) => {
+                    let command = sandbox.launch_command();
+                    self.running.insert(name.clone(), sandbox);
+                    log_event(AuditEvent::SandboxStarted {
+                        name: name.clone(),
+                        profile: Some(format!("restart policy {}", config.restart)),
+                        boot_ms: Some(boot_started.elapsed().as_millis() as u64),
+                        command,
+                    });
+                    restarted.push(name);
+                }
+                Err(e) => eprintln!("Warning: failed to restart sandbox '{}': {}", name, e),
+            }
+        }
+        restarted
+    }
+
+    /// Start a fresh backend sandbox from a saved config
+    async fn relaunch(
+        backend: BackendType,
+        name: &str,
+        config: &SandboxConfig,
+    ) -> Result<Box<dyn Sandbox>> {
+        let mut sandbox = create_sandbox(backend, name)?;
+        sandbox.start(config).await?;
+        if !config.files.is_empty() {
+            sandbox.inject_files(&config.files).await?;
+        }
+        Ok(sandbox)
+    }
+
+    /// Persist the exit reason for a sandbox
+    fn record_exit(&mut self, name: &str, reason: ExitReason) {
+        if let Some(state) = self.sandboxes.get_mut(name) {
+            state.last_exit = Some(reason);
+            let state = state.clone();
+            if let Err(e) = self.save_sandbox(&state) {
+                eprintln!("Warning: failed to save exit reason for '{}': {}", name, e);
+            }
+        }
+    }
+
+    /// Remove a sandbox
+    pub async fn remove(&mut self, name: &str) -> Result<()> {
+        let _lock = SandboxLock::acquire(&self.data_dir, name)?;
+        self.restart_configs.remove(name);
+        if let Some(mut sandbox) = self.running.remove(name) {
+            let _ = sandbox.stop().await;
+        }
+
+        self.delete_sandbox(name)?;
+        self.forget(name);
+
+        log_event(AuditEvent::SandboxRemoved {
+            name: name.to_string(),
+        });
+
+        Ok(())
+    }
+
+    /// List all sandboxes (persisted, with running status and backend)
+    pub fn list(&self) -> Vec<(&str, bool, Option<BackendType>)> {
+        self.list_filtered(&[])
+    }
+
+    /// List sandboxes matching all of the given label filters
+    pub fn list_filtered(&self, filters: &[LabelFilter]) -> Vec<(&str, bool, Option<BackendType>)> {
+        self.sandboxes
+            .iter()
+            .filter(|(_, state)| filters.iter().all(|f| f.matches(&state.labels)))
+            .map(|(name, state)| {
+                let running = self
+                    .running
+                    .get(name)
+                    .map(|s| s.is_running())
+                    .unwrap_or(false);
+                (name.as_str(), running, state.backend)
+            })
+            .collect()
+    }
+
+    /// Rename a sandbox, keeping its state.
+    ///
+    /// Docker/Podman containers are renamed in place. Other backends name
+    /// their sockets and disks after the sandbox, so they must be stopped first.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        validation::validate_sandbox_name(new)?;
+        let _old_lock = SandboxLock::acquire(&self.data_dir, old)?;
+        let _new_lock = SandboxLock::acquire(&self.data_dir, new)?;
+
+        let Some(state) = self.sandboxes.get(old) else {
+            return Err(not_found_error(old));
+        };
+        if self.sandboxes.contains_key(new) || self.state_path(new).exists() {
+            bail!("Sandbox '{}' already exists", new);
+        }
+
+        let backend = state.backend.unwrap_or(self.backend);
+        let runtime = match backend {
+            BackendType::Docker => Some(ContainerRuntime::Docker),
+            BackendType::Podman => Some(ContainerRuntime::Podman),
+            _ => None,
+        };
+        match runtime {
+            Some(runtime) => rename_container(runtime, old, new)?,
+            None => {
+                let vm_running = backend == BackendType::Firecracker
+                    && Path::new(&format!("/tmp/agentkernel-{}.sock", old)).exists();
+                if self.is_running(old) || vm_running {
+                    bail!(
+                        "Sandbox '{}' is running. Stop it before renaming: agentkernel stop {}",
+                        old,
+                        old
+                    );
+                }
+            }
+        }
+
+        std::fs::rename(self.state_path(old), self.state_path(new))?;
+        let mut state = self.sandboxes.remove(old).expect("checked above");
+        state.name = new.to_string();
+        self.save_sandbox(&state)?;
+        self.sandboxes.insert(new.to_string(), state);
+
+        // The handle refers to the container by its old name
+        if self.running.remove(old).is_some()
+            && let Ok(sandbox) = create_sandbox(backend, new)
+        {
+            self.running.insert(new.to_string(), sandbox);
+        }
+        if let Some(config) = self.restart_configs.remove(old) {
+            self.restart_configs.insert(new.to_string(), config);
+        }
+        Ok(())
+    }
+
+    /// Replace a sandbox's labels
+    pub fn set_labels(&mut self, name: &str, labels: HashMap<String, String>) -> Result<()> {
+        let Some(state) = self.sandboxes.get_mut(name) else {
+            return Err(not_found_error(name));
+        };
+        state.labels = labels;
+        let state = state.clone();
+        self.save_sandbox(&state)
+    }
+
+    /// Check if a sandbox exists
+    pub fn exists(&self, name: &str) -> bool {
+        self.sandboxes.contains_key(name)
+    }
+
+    /// Get the backend type for a sandbox (from stored state or current default)
+    /// Check if a sandbox is currently running
+    pub fn is_running(&self, name: &str) -> bool {
+        self.running
+            .get(name)
+            .map(|s| s.is_running())
+            .unwrap_or(false)
+    }
+
+    /// Get the current backend
+    #[allow(dead_code)]
+    pub fn backend(&self) -> BackendType {
+        self.backend
     }
 
     /// Run a command using the container pool (fast path for ephemeral runs)
-    pub async fn run_pooled(cmd: &[String]) -> Result<String> {
-        Self::enforce_command_policy(cmd)?;
+    ///
+    /// Returns the output, cut short at `max_output` bytes (None = all of it),
+    /// and whether it was.
+    pub async fn run_pooled(cmd: &[String], max_output: Option<usize>) -> Result<(String, bool)> {
+        PolicyScope::current()
+            .for_pooled()
+            .enforce_command(cmd, "pooled")?;
+        let pool = get_pool().await?;
+        let container = pool.acquire().await?;
+        let result = container.run_command(cmd, max_output).await;
+        pool.release(container).await;
+        result
+    }
+
+    /// Run a command using the container pool, returning its exit code and both streams
+    ///
+    /// Unlike `run_pooled`, a non-zero exit code is not an error.
+    pub async fn run_pooled_full(cmd: &[String], max_output: Option<usize>) -> Result<ExecResult> {
+        PolicyScope::current()
+            .for_pooled()
+            .enforce_command(cmd, "pooled")?;
         let pool = get_pool().await?;
         let container = pool.acquire().await?;
-        let result = container.run_command(cmd).await;
+        let result = container.exec(cmd, max_output).await;
         pool.release(container).await;
         result
     }
@@ -562,7 +2347,8 @@ impl VmManager {
         cmd: &[String],
         perms: &Permissions,
     ) -> Result<String> {
-        self.run_ephemeral_with_files(image, cmd, perms, &[]).await
+        self.run_ephemeral_with_files(image, cmd, perms, &[], None)
+            .await
     }
 
     /// Run a command in an ephemeral sandbox with file injection
@@ -572,8 +2358,45 @@ impl VmManager {
         cmd: &[String],
         perms: &Permissions,
         files: &[FileInjection],
+        workdir: Option<&str>,
     ) -> Result<String> {
-        Self::enforce_command_policy(cmd)?;
+        let result = self
+            .run_ephemeral_full(image, cmd, perms, files, workdir)
+            .await?;
+        if !result.is_success() {
+            bail!(
+                "Command failed (exit {}): {}",
+                result.exit_code,
+                result.output()
+            );
+        }
+        Ok(result.output())
+    }
+
+    /// Run a command in an ephemeral sandbox and return the full result
+    ///
+    /// Unlike `run_ephemeral_with_files`, a non-zero exit code is not an error.
+    /// Fails with [`EphemeralUnsupported`] on Firecracker: see
+    /// [`Self::run_in_new_sandbox`].
+    pub async fn run_ephemeral_full(
+        &mut self,
+        image: &str,
+        cmd: &[String],
+        perms: &Permissions,
+        files: &[FileInjection],
+        workdir: Option<&str>,
+    ) -> Result<ExecResult> {
+        // VMs need a vsock CID from the manager, and state it can clean up
+        if self.backend == BackendType::Firecracker {
+            bail!(EphemeralUnsupported(self.backend));
+        }
+        Self::enforce_command_policy("ephemeral", cmd)?;
+        self.check_image_pinned(image)?;
+        // Resolve mounts once, for whichever path runs the command
+        let perms = &Permissions {
+            mounts: resolve_mounts(&perms.mounts)?,
+            ..perms.clone()
+        };
         // Build config from permissions
         let work_dir = if perms.mount_cwd {
             std::env::current_dir()
@@ -583,7 +2406,7 @@ impl VmManager {
             None
         };
 
-        let env = if perms.pass_env {
+        let mut env: Vec<(String, String)> = if perms.pass_env {
             ["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM"]
                 .iter()
                 .filter_map(|&var| std::env::var(var).ok().map(|val| (var.to_string(), val)))
@@ -591,23 +2414,45 @@ impl VmManager {
         } else {
             Vec::new()
         };
+        env.extend(perms.env.iter().cloned());
 
         let config = SandboxConfig {
             image: image.to_string(),
-            vcpus: 1,
+            vcpus: perms.max_cpu_percent.map_or(1, |p| p.div_ceil(100).max(1)),
             memory_mb: perms.max_memory_mb.unwrap_or(512),
             mount_cwd: perms.mount_cwd,
             work_dir,
             env,
             network: perms.network,
+            network_mode: perms.network_mode.clone(),
             read_only: perms.read_only_root,
             mount_home: perms.mount_home,
+            writable_path: perms.writable_workspace.clone(),
             files: files.to_vec(),
+            ephemeral: true,
+            dns: perms.dns.clone(),
+            extra_hosts: perms.extra_hosts.clone(),
+            labels: HashMap::new(),
+            user: perms.user.as_deref().map(resolve_user),
+            scratch_mb: perms.scratch_mb,
+            tmp_size_mb: perms.tmp_size_mb,
+            run_size_mb: perms.run_size_mb,
+            ulimits: perms.ulimits.for_image(image),
+            init: perms.init,
+            agent_port: perms.agent_port,
+            vsock_cid: None,
+            egress: perms.egress.clone(),
+            restart: RestartPolicy::Never,
+            ports: Vec::new(),
+            hostname: perms.hostname.clone(),
+            gpus: perms.gpus.clone(),
+            mounts: perms.mounts.clone(),
         };
 
         // Use optimized `docker/podman run --rm` for container backends
-        // Note: File injection not supported in fast path; use generic path if files specified
-        if files.is_empty() {
+        // Note: File injection and egress rules aren't supported in the fast path;
+        // use the generic path if either is needed
+        if files.is_empty() && (perms.egress.is_none() || !perms.network) {
             match self.backend {
                 BackendType::Docker => {
                     use crate::docker_backend::{ContainerRuntime, ContainerSandbox};
@@ -616,11 +2461,15 @@ impl VmManager {
                         image,
                         cmd,
                         perms,
+                        workdir,
                     )?;
-                    if exit_code != 0 {
-                        bail!("Command failed (exit {}): {}{}", exit_code, stdout, stderr);
-                    }
-                    return Ok(format!("{}{}", stdout, stderr));
+                    return Ok(ExecResult {
+                        exit_code,
+                        stdout,
+                        stderr,
+                        rusage: None,
+                        truncated: false,
+                    });
                 }
                 BackendType::Podman => {
                     use crate::docker_backend::{ContainerRuntime, ContainerSandbox};
@@ -629,11 +2478,15 @@ impl VmManager {
                         image,
                         cmd,
                         perms,
+                        workdir,
                     )?;
-                    if exit_code != 0 {
-                        bail!("Command failed (exit {}): {}{}", exit_code, stdout, stderr);
-                    }
-                    return Ok(format!("{}{}", stdout, stderr));
+                    return Ok(ExecResult {
+                        exit_code,
+                        stdout,
+                        stderr,
+                        rusage: None,
+                        truncated: false,
+                    });
                 }
                 _ => {
                     // Fall through to generic start→exec→stop for other backends
@@ -654,39 +2507,77 @@ impl VmManager {
         }
 
         let cmd_refs: Vec<&str> = cmd.iter().map(|s| s.as_str()).collect();
-        let result = sandbox.exec(&cmd_refs).await;
+        let opts = ExecOptions::default().workdir(workdir);
+        let result = sandbox.exec_with_options(&cmd_refs, &opts).await;
 
         // Always stop, even on error
         let _ = sandbox.stop().await;
 
-        let result = result?;
-        if !result.is_success() {
-            bail!("Command failed: {}", result.output());
-        }
+        result
+    }
 
-        Ok(result.output())
+    /// Run a command in a sandbox created for it, then remove the sandbox.
+    ///
+    /// The slower path for backends [`Self::run_ephemeral_full`] doesn't
+    /// support.
+    pub async fn run_in_new_sandbox(
+        &mut self,
+        image: &str,
+        cmd: &[String],
+        perms: &Permissions,
+    ) -> Result<ExecResult> {
+        let name = format!("run-{}", &uuid::Uuid::new_v4().to_string()[..8]);
+        let vcpus = perms.max_cpu_percent.map_or(1, |p| p.div_ceil(100).max(1));
+        let memory_mb = perms.max_memory_mb.unwrap_or(512);
+        self.create_ephemeral(&name, image, vcpus, memory_mb)
+            .await?;
+        if let Err(e) = self
+            .start_with_permissions_and_files(&name, perms, &[])
+            .await
+        {
+            let _ = self.remove(&name).await;
+            return Err(e);
+        }
+        let result = self
+            .exec_cmd_full(&name, cmd, &ExecOptions::default())
+            .await;
+        let _ = self.stop(&name).await;
+        let _ = self.remove(&name).await;
+        result
     }
 
     /// Get pool statistics (for debugging/monitoring)
-    #[allow(dead_code)]
+    ///
+    /// `None` until the pool is started by the first pooled run.
     pub async fn pool_stats() -> Option<crate::pool::PoolStats> {
-        CONTAINER_POOL.get().map(|pool| {
-            // Use blocking because stats() is async
-            tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(pool.stats()))
-        })
+        match CONTAINER_POOL.get() {
+            Some(pool) => Some(pool.stats().await),
+            None => None,
+        }
     }
 
     /// Write a file to a running sandbox
     pub async fn write_file(&mut self, name: &str, path: &str, content: &[u8]) -> Result<()> {
-        let sandbox = self.running.get_mut(name).ok_or_else(|| {
-            anyhow::anyhow!(
-                "Sandbox '{}' is not running. Start it with: agentkernel start {}",
-                name,
-                name
-            )
-        })?;
+        self.write_file_with_options(name, path, content, &WriteOptions::default())
+            .await
+    }
+
+    /// Write a file to a running sandbox, optionally appending or setting its mode
+    pub async fn write_file_with_options(
+        &mut self,
+        name: &str,
+        path: &str,
+        content: &[u8],
+        opts: &WriteOptions,
+    ) -> Result<()> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
 
-        sandbox.write_file(path, content).await?;
+        if let Err(e) = sandbox.write_file_with_options(path, content, opts).await {
+            return Err(self.exec_failed(name, e).await);
+        }
 
         log_event(AuditEvent::FileWritten {
             sandbox: name.to_string(),
@@ -696,44 +2587,407 @@ impl VmManager {
         Ok(())
     }
 
-    /// Get the stored state for a sandbox
-    pub fn get_state(&self, name: &str) -> Option<&SandboxState> {
-        self.sandboxes.get(name)
-    }
+    /// Write several files to a running sandbox in one batch, creating
+    /// parent directories as needed
+    pub async fn write_files(&mut self, name: &str, files: &[FileInjection]) -> Result<()> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        if let Err(e) = sandbox.inject_files(files).await {
+            return Err(self.exec_failed(name, e).await);
+        }
+
+        for file in files {
+            log_event(AuditEvent::FileWritten {
+                sandbox: name.to_string(),
+                path: file.dest.clone(),
+            });
+        }
 
-    /// Delete a file from a running sandbox
-    pub async fn delete_file(&mut self, name: &str, path: &str) -> Result<()> {
-        let cmd = vec!["rm".to_string(), "-f".to_string(), path.to_string()];
-        self.exec_cmd(name, &cmd).await?;
         Ok(())
     }
 
-    /// Read a file from a running sandbox
-    pub async fn read_file(&mut self, name: &str, path: &str) -> Result<Vec<u8>> {
-        let sandbox = self.running.get_mut(name).ok_or_else(|| {
-            anyhow::anyhow!(
-                "Sandbox '{}' is not running. Start it with: agentkernel start {}",
-                name,
-                name
-            )
-        })?;
+    /// Create a link inside a running sandbox (symbolic unless `hard`)
+    pub async fn link(&mut self, name: &str, target: &str, link: &str, hard: bool) -> Result<()> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
 
-        let content = sandbox.read_file(path).await?;
+        let linked = if hard {
+            sandbox.hardlink(target, link).await
+        } else {
+            sandbox.symlink(target, link).await
+        };
+        if let Err(e) = linked {
+            return Err(self.exec_failed(name, e).await);
+        }
 
-        log_event(AuditEvent::FileRead {
+        log_event(AuditEvent::FileWritten {
             sandbox: name.to_string(),
-            path: path.to_string(),
+            path: link.to_string(),
         });
 
-        Ok(content)
+        Ok(())
+    }
+
+    /// Turn network access on or off for a running sandbox
+    pub async fn set_network(&mut self, name: &str, enabled: bool) -> Result<()> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        sandbox.set_network(enabled).await?;
+
+        // Keep the setting across restarts
+        if let Some(state) = self.sandboxes.get_mut(name) {
+            state.network = Some(enabled);
+            let state = state.clone();
+            self.save_sandbox(&state)?;
+        }
+
+        log_event(AuditEvent::NetworkChanged {
+            sandbox: name.to_string(),
+            enabled,
+        });
+
+        Ok(())
+    }
+
+    /// Save a running sandbox's filesystem to a tarball on the host.
+    ///
+    /// /proc, /sys, /dev, /run, and /tmp are left out. Returns the tarball's
+    /// size in bytes. Seed a new sandbox from it with [`Self::set_checkpoint`].
+    pub async fn checkpoint(&mut self, name: &str, dest: &Path) -> Result<u64> {
+        self.checkpoint_root(name, "/", dest).await
+    }
+
+    async fn checkpoint_root(&mut self, name: &str, root: &str, dest: &Path) -> Result<u64> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        let stream = sandbox
+            .stream_exec(&["sh", "-c", CHECKPOINT_SCRIPT, "sh", root, "-"])
+            .await?;
+        let size = match stream {
+            Some(child) => save_checkpoint(child, dest)
+                .await
+                .with_context(|| format!("Failed to checkpoint '{}'", name))?,
+            None => {
+                // Exec output isn't byte for byte here, so the tarball goes
+                // through a file in the sandbox
+                let staging = checkpoint_staging_path();
+                let result = sandbox
+                    .exec(&["sh", "-c", CHECKPOINT_SCRIPT, "sh", root, &staging])
+                    .await?;
+                let content = if result.is_success() {
+                    sandbox.read_file(&staging).await
+                } else {
+                    Err(anyhow::anyhow!("{}", result.output().trim()))
+                };
+                let _ = sandbox.remove_file(&staging).await;
+                let content =
+                    content.with_context(|| format!("Failed to checkpoint '{}'", name))?;
+                std::fs::write(dest, &content)
+                    .with_context(|| format!("Failed to write {}", dest.display()))?;
+                content.len() as u64
+            }
+        };
+
+        log_event(AuditEvent::FileRead {
+            sandbox: name.to_string(),
+            path: root.to_string(),
+        });
+        Ok(size)
+    }
+
+    /// Get the stored state for a sandbox
+    pub fn get_state(&self, name: &str) -> Option<&SandboxState> {
+        self.sandboxes.get(name)
+    }
+
+    /// Delete a file from a running sandbox
+    pub async fn delete_file(&mut self, name: &str, path: &str) -> Result<()> {
+        let cmd = vec!["rm".to_string(), "-f".to_string(), path.to_string()];
+        self.exec_cmd(name, &cmd).await?;
+        Ok(())
+    }
+
+    /// Read a file from a running sandbox
+    pub async fn read_file(&mut self, name: &str, path: &str) -> Result<Vec<u8>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        let content = match sandbox.read_file(path).await {
+            Ok(content) => content,
+            Err(e) => return Err(self.exec_failed(name, e).await),
+        };
+
+        log_event(AuditEvent::FileRead {
+            sandbox: name.to_string(),
+            path: path.to_string(),
+        });
+
+        Ok(content)
+    }
+
+    /// Start a chunked download of a file from a running sandbox.
+    ///
+    /// Returns the file's size; the chunks are read with `read_file_range`.
+    pub async fn begin_download(&mut self, name: &str, path: &str) -> Result<u64> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+
+        let size = match sandbox.file_size(path).await {
+            Ok(size) => size,
+            Err(e) => return Err(self.exec_failed(name, e).await),
+        };
+
+        log_event(AuditEvent::FileRead {
+            sandbox: name.to_string(),
+            path: path.to_string(),
+        });
+
+        Ok(size)
+    }
+
+    /// Read at most `len` bytes of a file in a running sandbox, from `offset`
+    pub async fn read_file_range(
+        &mut self,
+        name: &str,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        match sandbox.read_file_range(path, offset, len).await {
+            Ok(content) => Ok(content),
+            Err(e) => Err(self.exec_failed(name, e).await),
+        }
+    }
+
+    /// Start reading a range of a file in a running sandbox in one go.
+    ///
+    /// None when the backend reads a chunk at a time instead.
+    pub async fn stream_file_range(
+        &mut self,
+        name: &str,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<tokio::process::Child>> {
+        let sandbox = self
+            .running
+            .get_mut(name)
+            .ok_or_else(|| not_running_error(name, self.sandboxes.get(name)))?;
+        sandbox.stream_file_range(path, offset, len).await
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use tempfile::TempDir;
 
+    /// Runs commands and file operations on the host, standing in for a
+    /// started sandbox
+    struct HostSandbox {
+        stopped: Arc<AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl Sandbox for HostSandbox {
+        async fn start(&mut self, _config: &SandboxConfig) -> Result<()> {
+            // Long enough to show up as a boot time
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            Ok(())
+        }
+
+        async fn exec(&mut self, cmd: &[&str]) -> Result<ExecResult> {
+            let output = std::process::Command::new(cmd[0])
+                .args(&cmd[1..])
+                .output()?;
+            Ok(ExecResult {
+                exit_code: output.status.code().unwrap_or(-1),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                rusage: None,
+                truncated: false,
+            })
+        }
+
+        async fn stream_exec(&mut self, cmd: &[&str]) -> Result<Option<tokio::process::Child>> {
+            let child = tokio::process::Command::new(cmd[0])
+                .args(&cmd[1..])
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()?;
+            Ok(Some(child))
+        }
+
+        async fn stop(&mut self) -> Result<()> {
+            self.stopped.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "host"
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Docker
+        }
+
+        fn is_running(&self) -> bool {
+            !self.stopped.load(Ordering::SeqCst)
+        }
+
+        async fn write_file_unchecked(
+            &mut self,
+            path: &str,
+            content: &[u8],
+            _opts: &WriteOptions,
+        ) -> Result<()> {
+            Ok(std::fs::write(path, content)?)
+        }
+
+        async fn read_file_unchecked(&mut self, path: &str) -> Result<Vec<u8>> {
+            Ok(std::fs::read(path)?)
+        }
+
+        async fn remove_file_unchecked(&mut self, path: &str) -> Result<()> {
+            Ok(std::fs::remove_file(path)?)
+        }
+
+        async fn mkdir_unchecked(&mut self, path: &str, _recursive: bool) -> Result<()> {
+            Ok(std::fs::create_dir_all(path)?)
+        }
+    }
+
+    /// A sandbox whose container was removed outside agentkernel
+    struct RemovedSandbox;
+
+    #[async_trait::async_trait]
+    impl Sandbox for RemovedSandbox {
+        async fn start(&mut self, _config: &SandboxConfig) -> Result<()> {
+            Ok(())
+        }
+
+        async fn exec(&mut self, _cmd: &[&str]) -> Result<ExecResult> {
+            Err(SandboxGone {
+                detail: "Error response from daemon: No such container: agentkernel-gone"
+                    .to_string(),
+            }
+            .into())
+        }
+
+        async fn stop(&mut self) -> Result<()> {
+            bail!("No such container: agentkernel-gone")
+        }
+
+        fn name(&self) -> &str {
+            "gone"
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Docker
+        }
+
+        fn is_running(&self) -> bool {
+            true
+        }
+
+        async fn write_file_unchecked(
+            &mut self,
+            _path: &str,
+            _content: &[u8],
+            _opts: &WriteOptions,
+        ) -> Result<()> {
+            bail!(SandboxGone {
+                detail: "Error response from daemon: No such container: agentkernel-gone"
+                    .to_string(),
+            })
+        }
+
+        async fn read_file_unchecked(&mut self, _path: &str) -> Result<Vec<u8>> {
+            bail!(SandboxGone {
+                detail: "Error response from daemon: No such container: agentkernel-gone"
+                    .to_string(),
+            })
+        }
+
+        async fn remove_file_unchecked(&mut self, _path: &str) -> Result<()> {
+            bail!(SandboxGone {
+                detail: "Error response from daemon: No such container: agentkernel-gone"
+                    .to_string(),
+            })
+        }
+
+        async fn mkdir_unchecked(&mut self, _path: &str, _recursive: bool) -> Result<()> {
+            bail!(SandboxGone {
+                detail: "Error response from daemon: No such container: agentkernel-gone"
+                    .to_string(),
+            })
+        }
+    }
+
+    /// A manager with one created sandbox, `name`, running on the host
+    pub(crate) fn manager_with_host_sandbox(
+        dir: &TempDir,
+        name: &str,
+    ) -> (VmManager, Arc<AtomicBool>) {
+        let mut manager =
+            VmManager::with_data_dir(dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        let state = SandboxState {
+            name: name.to_string(),
+            image: "alpine:3.20".to_string(),
+            vcpus: 1,
+            memory_mb: 256,
+            vsock_cid: 3,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            backend: Some(BackendType::Docker),
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
+        };
+        manager.save_sandbox(&state).unwrap();
+        manager.sandboxes.insert(name.to_string(), state);
+        let stopped = Arc::new(AtomicBool::new(false));
+        manager.running.insert(
+            name.to_string(),
+            Box::new(HostSandbox {
+                stopped: stopped.clone(),
+            }),
+        );
+        (manager, stopped)
+    }
+
     #[test]
     fn test_sandbox_state_serialize() {
         let state = SandboxState {
@@ -744,6 +2998,21 @@ mod tests {
             vsock_cid: 5,
             created_at: "2024-01-01T00:00:00Z".to_string(),
             backend: None,
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
         };
 
         let json = serde_json::to_string(&state).unwrap();
@@ -781,6 +3050,21 @@ mod tests {
             vsock_cid: 3,
             created_at: "2024-06-15T12:30:00Z".to_string(),
             backend: None,
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
         };
 
         let json = serde_json::to_string(&original).unwrap();
@@ -827,6 +3111,21 @@ mod tests {
             vsock_cid: 4,
             created_at: "2024-01-01T00:00:00Z".to_string(),
             backend: None,
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
         };
         let json = serde_json::to_string(&state).unwrap();
         std::fs::write(temp_dir.path().join("loaded-sandbox.json"), &json).unwrap();
@@ -866,6 +3165,21 @@ mod tests {
                 vsock_cid: cid,
                 created_at: "2024-01-01T00:00:00Z".to_string(),
                 backend: None,
+                ephemeral: false,
+                last_exit: None,
+                labels: HashMap::new(),
+                image_digest: None,
+                expires_at: None,
+                restart: RestartPolicy::Never,
+                restart_count: 0,
+                setup_script: None,
+                setup_done: false,
+                last_boot_ms: None,
+                launch_command: None,
+                owner: None,
+                network: None,
+                checkpoint: None,
+                ports: Vec::new(),
             };
             let json = serde_json::to_string(&state).unwrap();
             std::fs::write(temp_dir.path().join(format!("{}.json", name)), &json).unwrap();
@@ -878,6 +3192,222 @@ mod tests {
         assert_eq!(max_cid, 10);
     }
 
+    #[test]
+    fn test_cid_pool_reuses_freed_cids() {
+        // Gaps left by removed sandboxes are filled first
+        let mut pool = CidPool::new([5, 10, 3]);
+        assert_eq!(pool.allocate(), 4);
+        assert_eq!(pool.allocate(), 6);
+
+        pool.release(5);
+        assert_eq!(pool.allocate(), 5);
+        assert_eq!(pool.allocate(), 7);
+
+        // Reserved CIDs are never handed out
+        let mut pool = CidPool::new([0, 1, 2]);
+        assert_eq!(pool.allocate(), MIN_GUEST_CID);
+    }
+
+    #[tokio::test]
+    async fn test_data_dir_override_moves_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("state");
+
+        let mut manager = VmManager::with_data_dir(data_dir.clone(), BackendType::Docker).unwrap();
+        manager
+            .create("relocated", "alpine:3.20", 1, 256)
+            .await
+            .unwrap();
+        assert!(data_dir.join("sandboxes/relocated.json").exists());
+
+        // A manager over the same directory loads it; one elsewhere doesn't
+        let reloaded = VmManager::with_data_dir(data_dir, BackendType::Docker).unwrap();
+        assert!(reloaded.exists("relocated"));
+        let other =
+            VmManager::with_data_dir(temp_dir.path().join("other"), BackendType::Docker).unwrap();
+        assert!(!other.exists("relocated"));
+    }
+
+    #[tokio::test]
+    async fn test_create_from_archive_loads_then_creates() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        let archive = temp_dir.path().join("app.tar");
+
+        // The runtime's load reports the tag, which becomes the sandbox's image
+        let mut loaded = Vec::new();
+        let image = manager
+            .create_from_archive_with("offline", &archive, 1, 256, |path| {
+                loaded.push(path.to_path_buf());
+                Ok("offline/app:1.0".to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(image, "offline/app:1.0");
+        assert_eq!(loaded, [archive.clone()]);
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        assert_eq!(reloaded["offline"].image, "offline/app:1.0");
+
+        // Nothing is loaded for a taken name
+        let err = manager
+            .create_from_archive_with("offline", &archive, 1, 256, |_| {
+                panic!("loaded an archive for an existing sandbox")
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+
+        // A failed load creates nothing
+        let err = manager
+            .create_from_archive_with("broken", &archive, 1, 256, |_| {
+                bail!("Failed to load image archive: unexpected EOF")
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unexpected EOF"));
+        assert!(!manager.exists("broken"));
+        assert!(!manager.state_path("broken").exists());
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_run_unsupported_on_firecracker() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Firecracker)
+                .unwrap();
+        let cmd = vec!["true".to_string()];
+        let err = manager
+            .run_ephemeral_full("alpine:3.20", &cmd, &Permissions::default(), &[], None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<EphemeralUnsupported>(),
+            Some(&EphemeralUnsupported(BackendType::Firecracker))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_firecracker_from_local_rootfs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Firecracker)
+                .unwrap();
+        let rootfs = temp_dir.path().join("offline.ext4");
+        std::fs::write(&rootfs, b"").unwrap();
+
+        // No installed rootfs directory is needed for a file given directly
+        let image = manager
+            .create_from_archive("vm", &rootfs, 1, 256)
+            .await
+            .unwrap();
+        assert_eq!(image, rootfs.display().to_string());
+        assert_eq!(
+            manager.sandboxes["vm"].image,
+            format!("rootfs:{}", rootfs.display())
+        );
+
+        let missing = temp_dir.path().join("missing.ext4");
+        let err = manager
+            .create_from_archive("gone", &missing, 1, 256)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Rootfs not found"), "{}", err);
+
+        // Image names never reach host files, even the marker `start` reads
+        for image in [
+            rootfs.to_str().unwrap().to_string(),
+            format!("rootfs:{}", rootfs.display()),
+        ] {
+            let err = manager.create("path", &image, 1, 256).await.unwrap_err();
+            assert!(err.to_string().contains("--image-archive"), "{}", err);
+        }
+        assert!(!manager.exists("path"));
+        let err = manager
+            .create_from_archive("tar", &temp_dir.path().join("app.tar"), 1, 256)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(".ext4"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_create_requires_pinned_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        manager.require_image_digest = true;
+
+        // Every caller of the manager (CLI, HTTP API, MCP) goes through this
+        let err = manager
+            .create("floating", "alpine:3.20", 1, 256)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not pinned"), "{}", err);
+        assert!(manager.get_state("floating").is_none());
+
+        let pinned = format!("alpine@sha256:{}", "a".repeat(64));
+        manager.create("pinned", &pinned, 1, 256).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_remove_cycles_reuse_cids() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+
+        // A long-lived sandbox holds its CID through every cycle
+        manager
+            .create("keeper", "alpine:3.20", 1, 256)
+            .await
+            .unwrap();
+        let keeper_cid = manager.get_state("keeper").unwrap().vsock_cid;
+
+        for round in 0..50 {
+            for i in 0..4 {
+                let name = format!("cycle-{}-{}", round, i);
+                manager.create(&name, "alpine:3.20", 1, 256).await.unwrap();
+            }
+            let live: Vec<u32> = manager.sandboxes.values().map(|s| s.vsock_cid).collect();
+            let unique: BTreeSet<u32> = live.iter().copied().collect();
+            assert_eq!(unique.len(), live.len(), "CID collision: {:?}", live);
+            assert!(live.iter().all(|&cid| cid >= MIN_GUEST_CID));
+
+            for i in 0..4 {
+                manager
+                    .remove(&format!("cycle-{}-{}", round, i))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // 200 creates only ever needed five CIDs
+        let max = manager.cid_pool().in_use.iter().max().copied();
+        assert_eq!(max, Some(keeper_cid));
+        manager.create("last", "alpine:3.20", 1, 256).await.unwrap();
+        assert!(manager.get_state("last").unwrap().vsock_cid <= MIN_GUEST_CID + 4);
+
+        // Managers split off for concurrent work draw from the same pool
+        let mut a = manager.split_off("keeper");
+        let mut b = manager.split_off("last");
+        a.create("split-a", "alpine:3.20", 1, 256).await.unwrap();
+        b.create("split-b", "alpine:3.20", 1, 256).await.unwrap();
+        assert_ne!(
+            a.get_state("split-a").unwrap().vsock_cid,
+            b.get_state("split-b").unwrap().vsock_cid
+        );
+
+        // A fresh manager picks up the same CIDs from disk
+        manager.merge(a);
+        manager.merge(b);
+        manager.refresh().unwrap();
+        let reloaded =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        assert_eq!(
+            reloaded.cid_pool().in_use,
+            manager.cid_pool().in_use.clone()
+        );
+    }
+
     #[test]
     fn test_sandbox_state_default_values() {
         // Test that missing fields in JSON cause parse failures (strict)
@@ -885,4 +3415,1002 @@ mod tests {
         let result: Result<SandboxState, _> = serde_json::from_str(incomplete_json);
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_stale_ephemeral_skips_live_owner() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        for name in ["mine", "crashed", "legacy"] {
+            manager
+                .create_ephemeral(name, "alpine:3.20", 1, 256)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            manager.sandboxes["mine"].owner.as_deref(),
+            Some(current_owner().as_str())
+        );
+        let owner = current_owner();
+        let (host, _) = owner.rsplit_once(':').unwrap();
+        manager.sandboxes.get_mut("crashed").unwrap().owner = Some(format!("{}:0", host));
+        manager.sandboxes.get_mut("legacy").unwrap().owner = None;
+
+        // Everything is old enough; only the live owner keeps its sandbox
+        let mut stale: Vec<String> = manager
+            .stale_ephemeral(Duration::ZERO)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        stale.sort();
+        assert_eq!(stale, ["crashed", "legacy"]);
+    }
+
+    #[test]
+    fn test_is_stale_age_threshold() {
+        let now = chrono::Utc::now();
+        let hour = Duration::from_secs(3600);
+
+        let old = (now - chrono::Duration::hours(2)).to_rfc3339();
+        let recent = (now - chrono::Duration::minutes(5)).to_rfc3339();
+        let future = (now + chrono::Duration::minutes(5)).to_rfc3339();
+
+        assert!(is_stale(&old, now, hour));
+        assert!(!is_stale(&recent, now, hour));
+        assert!(is_stale(&recent, now, Duration::ZERO));
+        // Clock skew or garbage never marks a sandbox as stale
+        assert!(!is_stale(&future, now, Duration::ZERO));
+        assert!(!is_stale("not-a-date", now, Duration::ZERO));
+    }
+
+    // Hyperlight can't start without the feature, so every relaunch fails
+    #[cfg(not(feature = "hyperlight"))]
+    #[tokio::test]
+    async fn test_restart_exited_stops_at_retry_cap() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        for (name, exit_code) in [("crashy", 1), ("clean", 0)] {
+            let state = SandboxState {
+                name: name.to_string(),
+                image: "alpine:3.20".to_string(),
+                vcpus: 1,
+                memory_mb: 256,
+                vsock_cid: 3,
+                created_at: "2024-01-01T00:00:00Z".to_string(),
+                backend: Some(BackendType::Hyperlight),
+                ephemeral: false,
+                last_exit: Some(ExitReason {
+                    exit_code: Some(exit_code),
+                    ..Default::default()
+                }),
+                labels: HashMap::new(),
+                image_digest: None,
+                expires_at: None,
+                restart: RestartPolicy::OnFailure,
+                restart_count: 0,
+                setup_script: None,
+                setup_done: false,
+                last_boot_ms: None,
+                launch_command: None,
+                owner: None,
+                network: None,
+                checkpoint: None,
+                ports: Vec::new(),
+            };
+            manager.save_sandbox(&state).unwrap();
+            manager.sandboxes.insert(name.to_string(), state);
+            manager.restart_configs.insert(
+                name.to_string(),
+                SandboxConfig::default().with_restart(RestartPolicy::OnFailure),
+            );
+        }
+
+        // A clean exit isn't restarted under on-failure
+        assert!(manager.restart_exited().await.is_empty());
+        assert!(!manager.restart_configs.contains_key("clean"));
+        assert_eq!(manager.get_state("clean").unwrap().restart_count, 0);
+
+        // Failed relaunches still count, until the cap is reached
+        for _ in 0..crate::backend::MAX_RESTARTS + 2 {
+            assert!(manager.restart_exited().await.is_empty());
+        }
+        assert_eq!(
+            manager.get_state("crashy").unwrap().restart_count,
+            crate::backend::MAX_RESTARTS
+        );
+        assert!(!manager.restart_configs.contains_key("crashy"));
+
+        // The count is persisted
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        assert_eq!(
+            reloaded["crashy"].restart_count,
+            crate::backend::MAX_RESTARTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start_records_boot_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "boot-timed");
+        let sandbox = manager.running.remove("boot-timed").unwrap();
+        let mut events = crate::audit::subscribe();
+
+        manager
+            .launch(
+                "boot-timed",
+                BackendType::Hyperlight,
+                sandbox,
+                SandboxConfig::default(),
+                "default".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let entry = std::iter::from_fn(|| events.try_recv().ok())
+            .find(|e| e.event.sandbox() == "boot-timed")
+            .unwrap();
+        let AuditEvent::SandboxStarted { boot_ms, .. } = entry.event else {
+            panic!("expected sandbox_started, got {}", entry.event.event_type());
+        };
+        assert!(boot_ms.unwrap() > 0);
+
+        // Kept for `agentkernel info`, across reloads
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        assert_eq!(reloaded["boot-timed"].last_boot_ms, boot_ms);
+    }
+
+    // Hyperlight can't start without the feature
+    #[cfg(not(feature = "hyperlight"))]
+    #[tokio::test]
+    async fn test_start_created_removes_sandbox_on_failure() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Hyperlight)
+                .unwrap();
+        manager.create("doomed", "app.wasm", 1, 256).await.unwrap();
+
+        let err = manager
+            .start_created("doomed", &Permissions::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("was removed"), "{}", err);
+        assert!(!manager.exists("doomed"));
+        assert!(!manager.state_path("doomed").exists());
+    }
+
+    #[tokio::test]
+    async fn test_create_then_start_keeps_or_removes_sandbox() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, stopped) = manager_with_host_sandbox(&temp_dir, "fresh");
+        let sandbox = manager.running.remove("fresh").unwrap();
+
+        // Started: left running
+        let result = manager
+            .launch(
+                "fresh",
+                BackendType::Hyperlight,
+                sandbox,
+                SandboxConfig::default(),
+                "default".to_string(),
+            )
+            .await;
+        manager.remove_if_failed("fresh", result).await.unwrap();
+        assert!(manager.is_running("fresh"));
+        assert!(manager.state_path("fresh").exists());
+
+        // A setup script failure gets as far as running the sandbox
+        let sandbox = manager.running.remove("fresh").unwrap();
+        manager.set_setup_script("fresh", "exit 3").unwrap();
+        let result = manager
+            .launch(
+                "fresh",
+                BackendType::Hyperlight,
+                sandbox,
+                SandboxConfig::default(),
+                "default".to_string(),
+            )
+            .await;
+        let err = manager.remove_if_failed("fresh", result).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("exit code 3"), "{:#}", err);
+        assert!(stopped.load(Ordering::SeqCst));
+        assert!(!manager.exists("fresh"));
+        assert!(!manager.state_path("fresh").exists());
+    }
+
+    #[tokio::test]
+    async fn test_detect_shell_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "box");
+        let sandbox = manager.running.get_mut("box").unwrap().as_mut();
+
+        // Missing shells are skipped in order
+        let shell = detect_shell(
+            sandbox,
+            &["/nonexistent/bash", "/bin/sh", "/nonexistent/ash"],
+        )
+        .await
+        .unwrap();
+        assert_eq!(shell, "/bin/sh");
+
+        let err = detect_shell(sandbox, &["/nonexistent/bash", "/nonexistent/ash"])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No shell found"), "{}", err);
+        assert!(err.to_string().contains("/nonexistent/ash"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_shell() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "box");
+
+        // An explicit shell is used as is, without probing
+        let shell = manager
+            .resolve_shell("box", Some("/opt/custom/zsh"))
+            .await
+            .unwrap();
+        assert_eq!(shell, "/opt/custom/zsh");
+
+        let expected = SHELL_FALLBACKS
+            .iter()
+            .find(|s| Path::new(s).exists())
+            .unwrap();
+        assert_eq!(manager.resolve_shell("box", None).await.unwrap(), *expected);
+    }
+
+    #[test]
+    fn test_published_ports_are_recorded() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "web");
+        let ports = vec!["3000:3000".parse().unwrap(), "8080:80".parse().unwrap()];
+        manager.set_ports("web", ports.clone()).unwrap();
+
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        assert_eq!(reloaded["web"].ports, ports);
+
+        // VM backends can't publish ports
+        manager.sandboxes.get_mut("web").unwrap().backend = Some(BackendType::Firecracker);
+        let err = manager.set_ports("web", ports).unwrap_err();
+        assert!(err.to_string().contains("docker or podman"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let source_root = temp_dir.path().join("root");
+        std::fs::create_dir_all(source_root.join("data")).unwrap();
+        std::fs::write(source_root.join("data/bug.txt"), "repro state").unwrap();
+        std::fs::create_dir_all(source_root.join("my notes")).unwrap();
+        std::fs::write(source_root.join("my notes/todo"), "spaced").unwrap();
+        std::fs::create_dir_all(source_root.join("tmp")).unwrap();
+        std::fs::write(source_root.join("tmp/scratch"), "left out").unwrap();
+        let tarball = temp_dir.path().join("state.tar");
+
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "buggy");
+        let size = manager
+            .checkpoint_root("buggy", source_root.to_str().unwrap(), &tarball)
+            .await
+            .unwrap();
+        assert_eq!(size, std::fs::metadata(&tarball).unwrap().len());
+
+        let restore_dir = TempDir::new().unwrap();
+        let target_root = restore_dir.path().join("root");
+        std::fs::create_dir_all(&target_root).unwrap();
+        let (mut restored, _) = manager_with_host_sandbox(&restore_dir, "repro");
+        restored.set_checkpoint("repro", &tarball).unwrap();
+        restored
+            .restore_checkpoint("repro", target_root.to_str().unwrap())
+            .await
+            .unwrap();
+
+        let path = target_root.join("data/bug.txt");
+        let content = restored
+            .read_file("repro", path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(content, b"repro state");
+        let notes = std::fs::read(target_root.join("my notes/todo")).unwrap();
+        assert_eq!(notes, b"spaced");
+        assert!(!target_root.join("tmp").exists());
+
+        // Only the first start restores it
+        assert!(restored.get_state("repro").unwrap().checkpoint.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_setup_script_runs_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let marker = temp_dir.path().join("runs");
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "prov");
+        manager
+            .set_setup_script("prov", &format!("echo ran >> '{}'", marker.display()))
+            .unwrap();
+
+        manager.run_setup_script("prov").await.unwrap();
+        assert!(manager.get_state("prov").unwrap().setup_done);
+
+        // A second start skips it, and completion survives a reload
+        manager.run_setup_script("prov").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "ran\n");
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        assert!(reloaded["prov"].setup_done);
+    }
+
+    #[tokio::test]
+    async fn test_setup_script_failure_stops_sandbox() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, stopped) = manager_with_host_sandbox(&temp_dir, "prov");
+        manager
+            .set_setup_script("prov", "echo missing deps; exit 3")
+            .unwrap();
+
+        let err = manager.run_setup_script("prov").await.unwrap_err();
+        assert!(err.to_string().contains("exit code 3"));
+        assert!(err.to_string().contains("missing deps"));
+
+        // Stopped, and left to retry on the next start
+        assert!(stopped.load(Ordering::SeqCst));
+        assert!(!manager.running.contains_key("prov"));
+        assert!(!manager.get_state("prov").unwrap().setup_done);
+    }
+
+    #[test]
+    fn test_setup_output_tail() {
+        assert_eq!(output_tail("short"), "short");
+
+        let long = format!("{}é{}", "a".repeat(SETUP_OUTPUT_TAIL), "error: no deps");
+        let tail = output_tail(&long);
+        assert!(tail.starts_with("["));
+        assert!(tail.ends_with("error: no deps"));
+        assert!(tail.len() < SETUP_OUTPUT_TAIL + 32);
+    }
+
+    #[test]
+    fn test_refresh_rereads_only_changed_state_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "web");
+        let path = manager.state_path("web");
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        manager.refresh().unwrap();
+
+        // Unchanged on disk, so the copy in memory stands
+        manager.sandboxes.get_mut("web").unwrap().restart_count = 7;
+        manager.refresh().unwrap();
+        assert_eq!(manager.get_state("web").unwrap().restart_count, 7);
+
+        // Written by another process
+        let mut other =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Docker).unwrap();
+        other
+            .set_restart_policy("web", RestartPolicy::Always)
+            .unwrap();
+        manager.refresh().unwrap();
+        let state = manager.get_state("web").unwrap();
+        assert_eq!(state.restart, RestartPolicy::Always);
+        assert_eq!(state.restart_count, 0);
+
+        // Removed by another process
+        std::fs::remove_file(&path).unwrap();
+        manager.refresh().unwrap();
+        assert!(!manager.exists("web"));
+        assert!(!manager.running.contains_key("web"));
+    }
+
+    #[test]
+    fn test_apply_detected_leaves_watched_sandboxes_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "web");
+        // Saved by another manager, which keeps the handle
+        let (_idle, _) = manager_with_host_sandbox(&temp_dir, "idle");
+        manager.refresh().unwrap();
+        let names: Vec<String> = manager.unwatched().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["idle"]);
+
+        let died = ExitReason {
+            exit_code: Some(137),
+            ..Default::default()
+        };
+        manager.apply_detected(vec![
+            ("web".to_string(), Detected::Exited(died.clone())),
+            ("idle".to_string(), Detected::Exited(died.clone())),
+            ("gone".to_string(), Detected::Exited(died.clone())),
+        ]);
+        assert!(manager.running.contains_key("web"));
+        assert_eq!(manager.get_state("web").unwrap().last_exit, None);
+        assert_eq!(
+            manager.get_state("idle").unwrap().last_exit,
+            Some(died.clone())
+        );
+        assert!(!manager.exists("gone"));
+
+        // An exit already recorded isn't overwritten
+        let later = ExitReason {
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        manager.apply_detected(vec![("idle".to_string(), Detected::Exited(later))]);
+        assert_eq!(manager.get_state("idle").unwrap().last_exit, Some(died));
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        manager.apply_detected(vec![(
+            "idle".to_string(),
+            Detected::Running(Box::new(HostSandbox { stopped })),
+        )]);
+        assert!(manager.running.contains_key("idle"));
+        assert!(manager.unwatched().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stop_all_stops_every_running_sandbox() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, stopped_web) = manager_with_host_sandbox(&temp_dir, "web");
+        let (worker, stopped_worker) = manager_with_host_sandbox(&temp_dir, "worker");
+        let (db, stopped_db) = manager_with_host_sandbox(&temp_dir, "db");
+        manager.merge(worker);
+        manager.merge(db);
+        manager.refresh().unwrap();
+        assert_eq!(manager.running.len(), 3);
+
+        let results = manager.stop_all().await;
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["db", "web", "worker"]);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        for stopped in [&stopped_web, &stopped_worker, &stopped_db] {
+            assert!(stopped.load(Ordering::SeqCst));
+        }
+        assert!(manager.running.is_empty());
+        // Stopped, not removed
+        assert!(manager.exists("web") && manager.exists("worker") && manager.exists("db"));
+
+        // Nothing left to stop
+        assert!(manager.stop_all().await.is_empty());
+    }
+
+    #[test]
+    fn test_rename_moves_state_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Firecracker)
+                .unwrap();
+        let state = SandboxState {
+            name: "before".to_string(),
+            image: "python".to_string(),
+            vcpus: 2,
+            memory_mb: 256,
+            vsock_cid: 7,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            backend: None,
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::from([("team".to_string(), "infra".to_string())]),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
+        };
+        manager.save_sandbox(&state).unwrap();
+        manager.sandboxes.insert("before".to_string(), state);
+
+        manager.rename("before", "after").unwrap();
+        assert!(!manager.exists("before"));
+        assert!(!manager.state_path("before").exists());
+
+        let reloaded = VmManager::load_sandboxes(&temp_dir.path().join("sandboxes")).unwrap();
+        let renamed = &reloaded["after"];
+        assert_eq!(renamed.name, "after");
+        assert_eq!(renamed.vcpus, 2);
+        assert_eq!(renamed.vsock_cid, 7);
+        assert_eq!(renamed.labels["team"], "infra");
+        assert!(!reloaded.contains_key("before"));
+    }
+
+    #[tokio::test]
+    async fn test_rename_rejects_collisions_and_bad_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Hyperlight)
+                .unwrap();
+        manager.create("first", "app.wasm", 1, 256).await.unwrap();
+        manager.create("second", "app.wasm", 1, 256).await.unwrap();
+
+        let err = manager.rename("first", "second").unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+        assert!(manager.rename("first", "bad name!").is_err());
+        let err = manager.rename("missing", "third").unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+
+        // A sandbox created by another process since this manager loaded
+        let mut other =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Hyperlight)
+                .unwrap();
+        other.create("third", "app.wasm", 1, 256).await.unwrap();
+        let err = manager.rename("first", "third").unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+
+        // Failed renames leave everything in place
+        assert!(manager.exists("first"));
+        assert!(manager.state_path("first").exists());
+    }
+
+    #[test]
+    fn test_concurrent_create_same_name_one_succeeds() {
+        let temp_dir = TempDir::new().unwrap();
+        let threads = 8;
+        let barrier = Arc::new(std::sync::Barrier::new(threads));
+
+        // Separate managers over one data dir, as separate processes would have
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let data_dir = temp_dir.path().to_path_buf();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let mut manager =
+                        VmManager::with_data_dir(data_dir, BackendType::Docker).unwrap();
+                    barrier.wait();
+                    manager.create_sandbox_state("racy", "alpine:3.20", 1, 256, false)
+                })
+            })
+            .collect();
+        let results: Vec<Result<()>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        for err in results.iter().filter_map(|r| r.as_ref().err()) {
+            let msg = err.to_string();
+            assert!(
+                msg.contains("already exists") || msg.contains("in progress"),
+                "{}",
+                msg
+            );
+        }
+
+        // A held lock is reported as contention rather than blocking
+        let _lock = SandboxLock::acquire(temp_dir.path(), "busy").unwrap();
+        let err = SandboxLock::acquire(temp_dir.path(), "busy").err().unwrap();
+        assert!(err.to_string().contains("in progress"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("30m").unwrap(), Some(Duration::from_secs(1800)));
+        assert_eq!(parse_ttl("2h").unwrap(), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_ttl("1d").unwrap(), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_ttl("90s").unwrap(), Some(Duration::from_secs(90)));
+        assert_eq!(parse_ttl("45").unwrap(), Some(Duration::from_secs(45)));
+        assert_eq!(parse_ttl("0").unwrap(), None);
+        assert_eq!(parse_ttl("0m").unwrap(), None);
+
+        for bad in [
+            "",
+            "m",
+            "abc",
+            "10x",
+            "-5m",
+            "1.5h",
+            "99999999999999999999d",
+        ] {
+            assert!(parse_ttl(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_expiry_time_and_is_expired() {
+        let now = chrono::Utc::now();
+        let expires = expiry_time(now, Duration::from_secs(1800)).unwrap();
+        let parsed = chrono::DateTime::parse_from_rfc3339(&expires).unwrap();
+        assert_eq!(parsed, now + chrono::Duration::minutes(30));
+
+        assert!(!is_expired(&expires, now));
+        assert!(is_expired(&expires, now + chrono::Duration::minutes(30)));
+        assert!(is_expired(&expires, now + chrono::Duration::minutes(31)));
+        assert!(!is_expired("not-a-date", now));
+    }
+
+    #[test]
+    fn test_expired_sandboxes_selects_only_expired() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manager =
+            VmManager::with_data_dir(temp_dir.path().to_path_buf(), BackendType::Firecracker)
+                .unwrap();
+        let now = chrono::Utc::now();
+        let past = (now - chrono::Duration::minutes(1)).to_rfc3339();
+        let future = (now + chrono::Duration::hours(1)).to_rfc3339();
+
+        for (name, expires_at) in [
+            ("expired", Some(past)),
+            ("alive", Some(future)),
+            ("forever", None),
+        ] {
+            let state = SandboxState {
+                name: name.to_string(),
+                image: "alpine:3.20".to_string(),
+                vcpus: 1,
+                memory_mb: 256,
+                vsock_cid: 3,
+                created_at: "2024-01-01T00:00:00Z".to_string(),
+                backend: None,
+                ephemeral: false,
+                last_exit: None,
+                labels: HashMap::new(),
+                image_digest: None,
+                expires_at,
+                restart: RestartPolicy::Never,
+                restart_count: 0,
+                setup_script: None,
+                setup_done: false,
+                last_boot_ms: None,
+                launch_command: None,
+                owner: None,
+                network: None,
+                checkpoint: None,
+                ports: Vec::new(),
+            };
+            manager.save_sandbox(&state).unwrap();
+            manager.sandboxes.insert(name.to_string(), state);
+        }
+
+        let expired = manager.expired_sandboxes(now);
+        assert_eq!(
+            expired,
+            vec![("expired".to_string(), BackendType::Firecracker)]
+        );
+
+        assert_eq!(manager.reap_expired(), vec!["expired".to_string()]);
+        assert!(!manager.exists("expired"));
+        assert!(manager.exists("alive"));
+        assert!(manager.exists("forever"));
+
+        // Clearing the TTL keeps a sandbox around indefinitely
+        manager.set_ttl("alive", None).unwrap();
+        assert!(
+            manager
+                .expired_sandboxes(now + chrono::Duration::days(1))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_sandbox_state_ephemeral_defaults_false() {
+        // State files written before the ephemeral flag existed
+        let json = r#"{
+            "name": "legacy",
+            "image": "alpine:3.20",
+            "vcpus": 1,
+            "memory_mb": 512,
+            "vsock_cid": 3,
+            "created_at": "2024-01-01T00:00:00Z"
+        }"#;
+        let state: SandboxState = serde_json::from_str(json).unwrap();
+        assert!(!state.ephemeral);
+        assert!(state.last_exit.is_none());
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(
+            parse_label("project=foo").unwrap(),
+            ("project".to_string(), "foo".to_string())
+        );
+        // Only the first '=' separates key and value
+        assert_eq!(
+            parse_label("cmd=a=b").unwrap(),
+            ("cmd".to_string(), "a=b".to_string())
+        );
+        assert_eq!(parse_label("empty=").unwrap().1, "");
+        assert!(parse_label("novalue").is_err());
+        assert!(parse_label("=foo").is_err());
+        assert!(parse_label("bad key=foo").is_err());
+        assert!(parse_label("key=line\nbreak").is_err());
+        // Keys agentkernel sets on containers can't be spoofed
+        assert!(parse_label("agentkernel.ephemeral=false").is_err());
+        assert!(validate_label("agentkernel.owner", "host:1").is_err());
+        assert!(parse_label("agentkernel-team=infra").is_ok());
+        assert!(LabelFilter::parse("agentkernel.ephemeral=true").is_ok());
+    }
+
+    #[test]
+    fn test_label_filter_matching() {
+        let labels: HashMap<String, String> = [("project", "foo"), ("owner", "alice")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let filter = LabelFilter::from_filter("label=project=foo").unwrap();
+        assert_eq!(filter.key, "project");
+        assert!(filter.matches(&labels));
+        assert!(!LabelFilter::parse("project=bar").unwrap().matches(&labels));
+        assert!(LabelFilter::parse("owner").unwrap().matches(&labels));
+        assert!(!LabelFilter::parse("team").unwrap().matches(&labels));
+        assert!(
+            !LabelFilter::parse("project")
+                .unwrap()
+                .matches(&HashMap::new())
+        );
+        assert!(LabelFilter::from_filter("name=foo").is_err());
+
+        // Labels survive a round trip through the state file
+        let state = SandboxState {
+            name: "labeled".to_string(),
+            image: "alpine:3.20".to_string(),
+            vcpus: 1,
+            memory_mb: 512,
+            vsock_cid: 3,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            backend: Some(BackendType::Docker),
+            ephemeral: false,
+            last_exit: None,
+            labels: labels.clone(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: SandboxState = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.labels, labels);
+    }
+
+    #[tokio::test]
+    async fn test_exec_in_externally_removed_sandbox() {
+        let dir = TempDir::new().unwrap();
+        let (mut manager, _stopped) = manager_with_host_sandbox(&dir, "gone");
+        manager
+            .running
+            .insert("gone".to_string(), Box::new(RemovedSandbox));
+
+        let cmd = vec!["true".to_string()];
+        let err = manager.exec_cmd("gone", &cmd).await.unwrap_err();
+        assert!(err.downcast_ref::<SandboxGone>().is_some());
+        assert!(
+            err.to_string()
+                .contains("container was removed outside agentkernel")
+        );
+        assert!(err.to_string().contains("agentkernel start gone"));
+
+        // The manager no longer counts it as running, but keeps its state
+        assert!(!manager.is_running("gone"));
+        assert!(manager.exists("gone"));
+        let reason = manager.sandboxes["gone"].last_exit.clone().unwrap();
+        assert!(reason.to_string().contains("removed outside agentkernel"));
+
+        let err = manager.exec_cmd("gone", &cmd).await.unwrap_err();
+        assert!(err.to_string().contains("is not running"));
+
+        // File ops and detached commands notice it too
+        manager
+            .running
+            .insert("gone".to_string(), Box::new(RemovedSandbox));
+        let err = manager
+            .read_file("gone", "/etc/hostname")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<SandboxGone>().is_some());
+        assert!(!manager.is_running("gone"));
+
+        manager
+            .running
+            .insert("gone".to_string(), Box::new(RemovedSandbox));
+        let err = manager
+            .exec_detached("gone", &cmd, &ExecOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<SandboxGone>().is_some());
+        assert!(!manager.is_running("gone"));
+    }
+
+    #[test]
+    fn test_default_hostname() {
+        assert_eq!(default_hostname("dev"), "dev");
+        assert_eq!(default_hostname("my_box-2"), "my-box-2");
+        assert_eq!(default_hostname(""), "agentkernel");
+    }
+
+    #[test]
+    fn test_not_running_error_includes_last_exit() {
+        let mut state = SandboxState {
+            name: "oom".to_string(),
+            image: "alpine:3.20".to_string(),
+            vcpus: 1,
+            memory_mb: 64,
+            vsock_cid: 3,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            backend: Some(BackendType::Docker),
+            ephemeral: false,
+            last_exit: None,
+            labels: HashMap::new(),
+            image_digest: None,
+            expires_at: None,
+            restart: RestartPolicy::Never,
+            restart_count: 0,
+            setup_script: None,
+            setup_done: false,
+            last_boot_ms: None,
+            launch_command: None,
+            owner: None,
+            network: None,
+            checkpoint: None,
+            ports: Vec::new(),
+        };
+        let err = not_running_error("oom", Some(&state)).to_string();
+        assert!(err.starts_with("Sandbox 'oom' is not running. Start it with"));
+
+        state.last_exit = Some(ExitReason {
+            exit_code: Some(137),
+            oom_killed: true,
+            ..Default::default()
+        });
+        let err = not_running_error("oom", Some(&state)).to_string();
+        assert!(
+            err.contains("sandbox killed: out of memory, increase memory_mb"),
+            "{}",
+            err
+        );
+
+        // The exit reason survives a round trip through the state file
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: SandboxState = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.last_exit, state.last_exit);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME; rm -rf /"), "'$HOME; rm -rf /'");
+    }
+
+    #[test]
+    fn test_detached_command_captures_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("run.log");
+        let cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo \"it's detached\"; echo err >&2".to_string(),
+        ];
+
+        let err_path = temp_dir.path().join("run.err");
+        let exit_path = temp_dir.path().join("run.exit");
+        let pid_path = temp_dir.path().join("run.pid");
+        let paths = DetachedPaths {
+            log: log_path.to_str().unwrap(),
+            err: err_path.to_str().unwrap(),
+            exit: exit_path.to_str().unwrap(),
+            pid: pid_path.to_str().unwrap(),
+        };
+        let wrapper = detached_command(&cmd, &paths, None);
+        assert_eq!(wrapper[0], "sh");
+        assert_eq!(wrapper[1], "-c");
+
+        // The wrapper returns immediately; the command finishes in the background
+        let status = std::process::Command::new(&wrapper[0])
+            .args(&wrapper[1..])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut err = String::new();
+        for _ in 0..50 {
+            err = std::fs::read_to_string(&err_path).unwrap_or_default();
+            if err.contains("err") {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log, "it's detached\n");
+        assert_eq!(err, "err\n");
+    }
+
+    #[test]
+    fn test_detached_command_records_exit_code() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = |name: &str| temp_dir.path().join(name).to_string_lossy().into_owned();
+        let (log, err) = (path("run.log"), path("run.err"));
+        let (exit, pid) = (path("run.exit"), path("run.pid"));
+        let paths = DetachedPaths {
+            log: &log,
+            err: &err,
+            exit: &exit,
+            pid: &pid,
+        };
+        let status = |paths: &DetachedPaths| {
+            let cmd = crate::backend::wait_status_command(paths);
+            let output = std::process::Command::new(&cmd[0])
+                .args(&cmd[1..])
+                .output()
+                .unwrap();
+            crate::backend::parse_wait_status(&String::from_utf8_lossy(&output.stdout))
+        };
+
+        // Nothing started yet
+        assert!(status(&paths).is_err());
+
+        let cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "sleep 0.3; exit 7".to_string(),
+        ];
+        let wrapper = detached_command(&cmd, &paths, None);
+        std::process::Command::new(&wrapper[0])
+            .args(&wrapper[1..])
+            .status()
+            .unwrap();
+        assert_eq!(status(&paths).unwrap(), None);
+
+        let mut code = None;
+        for _ in 0..50 {
+            code = status(&paths).unwrap();
+            if code.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert_eq!(code, Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_exec_background_returns_immediately() {
+        use crate::backend::BackgroundState;
+
+        let temp_dir = TempDir::new().unwrap();
+        let (mut manager, _) = manager_with_host_sandbox(&temp_dir, "bg");
+        let cmd = vec!["sleep".to_string(), "30".to_string()];
+
+        let started = Instant::now();
+        let id = manager
+            .exec_background("bg", &cmd, &ExecOptions::default())
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(manager.is_running("bg"));
+
+        let execs = manager.background_execs("bg").await.unwrap();
+        let exec = execs.iter().find(|e| e.id == id).unwrap();
+        assert_eq!(exec.state, BackgroundState::Running);
+        assert_eq!(exec.command, "sleep 30");
+
+        // Killing the command leaves the sandbox running
+        manager.kill_background("bg", &id).await.unwrap();
+        let mut state = BackgroundState::Running;
+        for _ in 0..50 {
+            let execs = manager.background_execs("bg").await.unwrap();
+            state = execs.iter().find(|e| e.id == id).unwrap().state;
+            if state != BackgroundState::Running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(state, BackgroundState::Exited(143));
+        assert!(manager.is_running("bg"));
+
+        assert!(manager.kill_background("bg", "missing1").await.is_err());
+        assert!(manager.background_logs("bg", "../etc", None).await.is_err());
+
+        let paths = BackgroundExecPaths::new(BACKGROUND_EXEC_DIR, &id);
+        for path in [&paths.log, &paths.err, &paths.exit, &paths.pid, &paths.cmd] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
 }
@@ -1,11 +1,25 @@
 //! Vsock Communication Layer
 //!
 //! Host-to-guest communication via virtio-vsock for Firecracker microVMs.
-//! Uses a simple JSON-RPC protocol over length-prefixed messages.
+//! Uses a simple JSON-RPC protocol over framed messages.
+//!
+//! Each frame is a 14-byte header followed by a JSON payload:
+//!
+//! | Bytes | Field                          |
+//! |-------|--------------------------------|
+//! | 0-3   | Magic `AKVS`                   |
+//! | 4-5   | Protocol version (u16 LE)      |
+//! | 6-9   | Payload length (u32 LE)        |
+//! | 10-13 | CRC32 of the payload (u32 LE)  |
+//!
+//! On accept the guest agent sends an empty frame announcing its protocol
+//! version, so the host can reject an incompatible agent up front.
 
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::timeout;
@@ -17,15 +31,353 @@ use tokio_vsock::{VsockAddr, VsockStream};
 #[allow(dead_code)]
 pub const AGENT_PORT: u32 = 52000;
 
+/// Environment variable overriding the guest agent port
+pub const AGENT_PORT_ENV: &str = "AGENTKERNEL_AGENT_PORT";
+
+/// Port the guest agent listens on: `AGENTKERNEL_AGENT_PORT`, else [`AGENT_PORT`]
+pub fn agent_port() -> u32 {
+    parse_agent_port(std::env::var(AGENT_PORT_ENV).ok().as_deref())
+}
+
+/// Parse an agent port override, falling back to [`AGENT_PORT`] when unset or invalid
+fn parse_agent_port(value: Option<&str>) -> u32 {
+    value
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .filter(|&port| port > 0)
+        .unwrap_or(AGENT_PORT)
+}
+
+/// Guest agent version this build expects, from guest-agent/Cargo.toml
+/// ("unknown" when built without it, which skips the check)
+pub const EXPECTED_AGENT_VERSION: &str = env!("AGENTKERNEL_GUEST_AGENT_VERSION");
+
+/// Environment variable that turns a guest agent version mismatch into an error
+pub const STRICT_AGENT_VERSION_ENV: &str = "AGENTKERNEL_STRICT_AGENT_VERSION";
+
+/// `strict_agent_version` from `[firecracker]` (see [`set_strict_agent_version`])
+static STRICT_AGENT_VERSION: AtomicBool = AtomicBool::new(false);
+
+/// Turn a guest agent version mismatch into an error for this process, as
+/// `[firecracker] strict_agent_version` asks
+pub fn set_strict_agent_version(strict: bool) {
+    STRICT_AGENT_VERSION.store(strict, Ordering::Relaxed);
+}
+
+/// Whether a version mismatch fails the start: set in the config or by
+/// [`STRICT_AGENT_VERSION_ENV`]
+fn strict_agent_version() -> bool {
+    STRICT_AGENT_VERSION.load(Ordering::Relaxed)
+        || std::env::var(STRICT_AGENT_VERSION_ENV).is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Returned (inside `anyhow::Error`) when the guest agent in the rootfs can't
+/// work with this build: it speaks another protocol version, or reports a
+/// different agent version while strict checking is on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentIncompatible {
+    /// What is wrong and how to fix it
+    pub detail: String,
+}
+
+impl std::fmt::Display for AgentIncompatible {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl std::error::Error for AgentIncompatible {}
+
+impl AgentIncompatible {
+    fn error(detail: impl Into<String>) -> anyhow::Error {
+        AgentIncompatible {
+            detail: detail.into(),
+        }
+        .into()
+    }
+}
+
+/// Why a guest agent reporting `reported` on Ping needs a rootfs rebuild, if it does
+pub fn agent_version_mismatch(reported: Option<&str>) -> Option<String> {
+    let found = match reported {
+        _ if EXPECTED_AGENT_VERSION == "unknown" => return None,
+        Some(version) if version == EXPECTED_AGENT_VERSION => return None,
+        Some(version) => format!("v{}", version),
+        None => "a version that doesn't report itself".to_string(),
+    };
+    Some(format!(
+        "Guest agent is {}, but agentkernel expects v{}; the rootfs is stale. \
+         Rebuild it with: agentkernel setup",
+        found, EXPECTED_AGENT_VERSION
+    ))
+}
+
+/// Warn about a stale guest agent, or fail when `strict`
+fn check_agent_version(pong: &AgentResponse, strict: bool) -> Result<()> {
+    let Some(message) = agent_version_mismatch(pong.version.as_deref()) else {
+        return Ok(());
+    };
+    if strict {
+        return Err(AgentIncompatible::error(message));
+    }
+    eprintln!("Warning: {}", message);
+    Ok(())
+}
+
+/// First guest agent version that honours `offset` and `length` on ReadFile
+pub const RANGED_READ_AGENT_VERSION: &str = "0.2.0";
+
+/// First guest agent version that honours `append` on WriteFile; older
+/// ones silently truncate the file instead
+pub const APPEND_WRITE_AGENT_VERSION: &str = "0.2.0";
+
+/// Whether an agent reporting `reported` is at least version `min`.
+///
+/// An agent that doesn't report a version predates every feature gated on one.
+pub fn agent_supports(reported: Option<&str>, min: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .split(['.', '-', '+'])
+            .take(3)
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    }
+    reported.is_some_and(|version| parts(version) >= parts(min))
+}
+
+/// Versions reported by the guest agents seen so far, by endpoint
+static AGENT_VERSIONS: Mutex<Vec<(String, Option<String>)>> = Mutex::new(Vec::new());
+
+/// How long a freshly booted VM gets for its guest agent to come up
+pub const AGENT_BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay between connection attempts while the guest agent starts
+pub const AGENT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Host CID (always 2 for the host)
 #[allow(dead_code)]
 pub const HOST_CID: u32 = 2;
 
+/// Magic bytes at the start of every frame
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"AKVS";
+
+/// Protocol version spoken by this build (must match the guest agent)
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Largest payload accepted in a single frame
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Size of the frame header in bytes
+const FRAME_HEADER_LEN: usize = 14;
+
+/// How long to wait for the guest agent's version greeting
+const GREETING_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32 (IEEE) checksum
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Encode a payload as a frame (header + payload)
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&PROTOCOL_MAGIC);
+    frame.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Error for a peer speaking a different protocol version
+fn version_mismatch(peer_version: u16) -> anyhow::Error {
+    if peer_version < PROTOCOL_VERSION {
+        AgentIncompatible::error(format!(
+            "Guest agent too old (protocol v{}, host expects v{}). Rebuild the rootfs with: agentkernel setup",
+            peer_version, PROTOCOL_VERSION
+        ))
+    } else {
+        AgentIncompatible::error(format!(
+            "Guest agent is newer than this agentkernel (protocol v{}, host supports v{}). Upgrade agentkernel",
+            peer_version, PROTOCOL_VERSION
+        ))
+    }
+}
+
+/// Write one frame
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    if payload.len() > MAX_MESSAGE_SIZE {
+        bail!("Message too large: {} bytes", payload.len());
+    }
+    writer.write_all(&encode_frame(payload)).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read one frame, validating magic, version, size, and checksum
+pub async fn read_frame<R>(reader: &mut R) -> Result<Vec<u8>>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .await
+        .context("Failed to read frame header")?;
+
+    if header[0..4] != PROTOCOL_MAGIC {
+        return Err(AgentIncompatible::error(
+            "Invalid frame from guest agent (bad magic). The agent is likely too old; rebuild the rootfs with: agentkernel setup",
+        ));
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != PROTOCOL_VERSION {
+        return Err(version_mismatch(version));
+    }
+
+    let len = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        bail!("Response too large: {} bytes", len);
+    }
+    let expected_crc = u32::from_le_bytes([header[10], header[11], header[12], header[13]]);
+
+    let mut payload = vec![0u8; len];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .context("Failed to read frame payload")?;
+
+    let actual_crc = crc32(&payload);
+    if actual_crc != expected_crc {
+        bail!(
+            "Frame checksum mismatch (expected {:08x}, got {:08x}): message was corrupted or truncated",
+            expected_crc,
+            actual_crc
+        );
+    }
+
+    Ok(payload)
+}
+
+/// Read the guest agent's version greeting after connecting
+pub async fn read_greeting<R>(reader: &mut R) -> Result<()>
+where
+    R: AsyncReadExt + Unpin,
+{
+    match timeout(GREETING_TIMEOUT, read_frame(reader)).await {
+        Ok(result) => result.map(|_| ()),
+        Err(_) => Err(AgentIncompatible::error(
+            "Guest agent did not announce a protocol version; it is likely too old. Rebuild the rootfs with: agentkernel setup",
+        )),
+    }
+}
+
+/// Send a request and read the response on a connected stream
+#[cfg(unix)]
+async fn exchange<S>(
+    stream: &mut S,
+    request: &AgentRequest,
+    timeout_secs: u64,
+) -> Result<AgentResponse>
+where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let request_bytes = serde_json::to_vec(request)?;
+    write_frame(stream, &request_bytes).await?;
+
+    let response_bytes = timeout(Duration::from_secs(timeout_secs), read_frame(stream))
+        .await
+        .context("Read timeout")??;
+
+    let response: AgentResponse =
+        serde_json::from_slice(&response_bytes).context("Failed to parse response")?;
+
+    // Frame errors are reported without an id, since the request couldn't be read
+    if !response.id.is_empty() && response.id != request.id {
+        bail!(
+            "Guest agent answered request '{}' with a response for '{}'",
+            request.id,
+            response.id
+        );
+    }
+
+    Ok(response)
+}
+
+/// Read Firecracker's `OK <port>` reply to a CONNECT.
+///
+/// Reads byte by byte so the guest's greeting isn't consumed along with it.
+#[cfg(unix)]
+async fn read_connect_ack<R>(reader: &mut R) -> Result<()>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut line = Vec::with_capacity(32);
+    loop {
+        let mut byte = [0u8; 1];
+        let n = timeout(Duration::from_secs(5), reader.read(&mut byte))
+            .await
+            .context("Timeout waiting for CONNECT response")?
+            .context("Failed to read CONNECT response")?;
+        if n == 0 || byte[0] == b'\n' || line.len() >= 64 {
+            break;
+        }
+        line.push(byte[0]);
+    }
+
+    let response_str = std::str::from_utf8(&line)
+        .context("Invalid CONNECT response")?
+        .trim();
+
+    if !response_str.starts_with("OK ") {
+        bail!("Firecracker vsock CONNECT failed: {}", response_str);
+    }
+    Ok(())
+}
+
+/// A liveness check for the guest agent
+#[cfg(unix)]
+fn ping_request() -> AgentRequest {
+    AgentRequest {
+        id: uuid::Uuid::new_v4().to_string(),
+        request_type: RequestType::Ping,
+        ..Default::default()
+    }
+}
+
 /// Request types supported by the guest agent
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RequestType {
     /// Run a command and return output
+    #[default]
     Run,
     /// Start an interactive shell (PTY)
     Shell,
@@ -47,10 +399,16 @@ pub enum RequestType {
     RemoveFile,
     /// Create a directory in the guest filesystem
     Mkdir,
+    /// Create a symbolic link in the guest filesystem
+    Symlink,
+    /// Create a hard link in the guest filesystem
+    Hardlink,
+    /// Kill a running command (the Run request with the same id)
+    Cancel,
 }
 
 /// Request sent from host to guest
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AgentRequest {
     /// Unique request ID
     pub id: String,
@@ -88,6 +446,28 @@ pub struct AgentRequest {
     /// Input data as base64 (for ShellInput)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub input_base64: Option<String>,
+    /// Link target (for Symlink, Hardlink; `path` is the link itself)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// User to act as, `uid[:gid]` or `name[:group]` (for Run, Shell, and
+    /// file operations)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Append instead of truncating (for WriteFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append: Option<bool>,
+    /// Permission bits for the file (for WriteFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Capture stdout and stderr as one stream, in the order written (for Run)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined: Option<bool>,
+    /// Byte to start reading at (for ReadFile)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    /// Most bytes to read (for ReadFile; None = to the end)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
 }
 
 /// Response from guest to host
@@ -104,6 +484,10 @@ pub struct AgentResponse {
     /// Standard error
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stderr: Option<String>,
+    /// Both streams interleaved in the order written (Run with `combined`,
+    /// in place of `stdout` and `stderr`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
     /// Error message if request failed
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
@@ -120,6 +504,36 @@ pub struct AgentResponse {
     /// Response type for shell events (started, output, exited)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shell_event: Option<ShellEvent>,
+    /// Resource usage of the command (for Run type; absent from older agents)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rusage: Option<ResourceUsage>,
+    /// Guest agent version (for Ping; absent from older agents)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Framing protocol version (for Ping; absent from older agents)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u16>,
+}
+
+/// Resource usage of a completed command, as reported by the guest agent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// User CPU time in milliseconds
+    pub user_time_ms: u64,
+    /// System CPU time in milliseconds
+    pub system_time_ms: u64,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
+impl std::fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "user {}ms, sys {}ms, max RSS {} KB",
+            self.user_time_ms, self.system_time_ms, self.max_rss_kb
+        )
+    }
 }
 
 /// Shell event types for async shell communication
@@ -141,6 +555,7 @@ pub struct RunResult {
     pub exit_code: i32,
     pub stdout: String,
     pub stderr: String,
+    pub rusage: Option<ResourceUsage>,
 }
 
 /// A persistent vsock connection that can be reused for multiple commands.
@@ -175,19 +590,8 @@ impl VsockConnection {
         stream.flush().await?;
 
         // Read response: OK <host_port>\n
-        let mut response_buf = [0u8; 32];
-        let n = timeout(Duration::from_secs(5), stream.read(&mut response_buf))
-            .await
-            .context("Timeout waiting for CONNECT response")?
-            .context("Failed to read CONNECT response")?;
-
-        let response_str = std::str::from_utf8(&response_buf[..n])
-            .context("Invalid CONNECT response")?
-            .trim();
-
-        if !response_str.starts_with("OK ") {
-            bail!("Firecracker vsock CONNECT failed: {}", response_str);
-        }
+        read_connect_ack(&mut stream).await?;
+        read_greeting(&mut stream).await?;
 
         Ok(Self {
             stream,
@@ -201,15 +605,7 @@ impl VsockConnection {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::Run,
             command: Some(command.to_vec()),
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -220,50 +616,63 @@ impl VsockConnection {
 
         Ok(RunResult {
             exit_code: response.exit_code.unwrap_or(-1),
-            stdout: response.stdout.unwrap_or_default(),
+            stdout: response.output.or(response.stdout).unwrap_or_default(),
             stderr: response.stderr.unwrap_or_default(),
+            rusage: response.rusage,
         })
     }
 
     /// Send a request and receive response over the established connection.
     async fn send_request(&mut self, request: &AgentRequest) -> Result<AgentResponse> {
-        // Serialize request
-        let request_bytes = serde_json::to_vec(request)?;
-
-        // Send length-prefixed request
-        let len = request_bytes.len() as u32;
-        self.stream.write_all(&len.to_le_bytes()).await?;
-        self.stream.write_all(&request_bytes).await?;
-        self.stream.flush().await?;
-
-        // Read length-prefixed response
-        let mut len_bytes = [0u8; 4];
-        timeout(
-            Duration::from_secs(self.timeout_secs),
-            self.stream.read_exact(&mut len_bytes),
-        )
-        .await
-        .context("Read timeout")?
-        .context("Failed to read response length")?;
+        exchange(&mut self.stream, request, self.timeout_secs).await
+    }
 
-        let len = u32::from_le_bytes(len_bytes) as usize;
-        if len > 10 * 1024 * 1024 {
-            bail!("Response too large: {} bytes", len);
+    /// Start an interactive shell, handing this connection over to it.
+    pub async fn start_shell(
+        mut self,
+        command: Option<Vec<String>>,
+        rows: u16,
+        cols: u16,
+        env: Option<HashMap<String, String>>,
+        user: Option<String>,
+    ) -> Result<ShellSession> {
+        let request = AgentRequest {
+            command,
+            env,
+            user,
+            rows: Some(rows),
+            cols: Some(cols),
+            ..shell_request(RequestType::Shell, None)
+        };
+        let response = self.send_request(&request).await?;
+        if let Some(error) = response.error {
+            bail!("Failed to start shell: {}", error);
         }
-
-        let mut response_bytes = vec![0u8; len];
-        timeout(
-            Duration::from_secs(self.timeout_secs),
-            self.stream.read_exact(&mut response_bytes),
-        )
-        .await
-        .context("Read timeout")?
-        .context("Failed to read response body")?;
-
-        let response: AgentResponse =
-            serde_json::from_slice(&response_bytes).context("Failed to parse response")?;
-
-        Ok(response)
+        let session_id = response
+            .session_id
+            .ok_or_else(|| anyhow::anyhow!("No session ID in shell response"))?;
+
+        // Read frames on a separate task so reads are never cut off mid-frame
+        let (mut reader, writer) = self.stream.into_split();
+        let (tx, responses) = tokio::sync::mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                let response = read_frame(&mut reader).await.and_then(|bytes| {
+                    serde_json::from_slice::<AgentResponse>(&bytes)
+                        .context("Failed to parse response")
+                });
+                let failed = response.is_err();
+                if tx.send(response).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(ShellSession {
+            session_id,
+            writer,
+            responses,
+        })
     }
 
     /// Check if the connection is still alive by sending a ping.
@@ -272,22 +681,119 @@ impl VsockConnection {
         let request = AgentRequest {
             id: "ping".to_string(),
             request_type: RequestType::Ping,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            ..Default::default()
         };
 
         self.send_request(&request).await.is_ok()
     }
 }
 
+/// Build a request for a shell session operation
+fn shell_request(request_type: RequestType, session_id: Option<&str>) -> AgentRequest {
+    AgentRequest {
+        id: uuid::Uuid::new_v4().to_string(),
+        request_type,
+        session_id: session_id.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+/// Build a request that resizes a shell session's terminal
+pub fn shell_resize_request(session_id: &str, rows: u16, cols: u16) -> AgentRequest {
+    AgentRequest {
+        rows: Some(rows),
+        cols: Some(cols),
+        ..shell_request(RequestType::ShellResize, Some(session_id))
+    }
+}
+
+/// Something the guest sent back for an interactive shell
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShellMessage {
+    /// Terminal output from the shell
+    Output(Vec<u8>),
+    /// The shell exited with this code
+    Exited(i32),
+}
+
+/// An interactive shell running in the guest.
+///
+/// The guest streams output over the connection that started the shell, and
+/// kills the shell when that connection closes.
+#[cfg(unix)]
+pub struct ShellSession {
+    session_id: String,
+    writer: tokio::net::unix::OwnedWriteHalf,
+    responses: tokio::sync::mpsc::Receiver<Result<AgentResponse>>,
+}
+
+#[cfg(unix)]
+impl ShellSession {
+    /// Guest-side session ID
+    #[allow(dead_code)]
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Send keyboard input to the shell
+    pub async fn send_input(&mut self, data: &[u8]) -> Result<()> {
+        use base64::{Engine, engine::general_purpose::STANDARD};
+
+        let request = AgentRequest {
+            input_base64: Some(STANDARD.encode(data)),
+            ..shell_request(RequestType::ShellInput, Some(&self.session_id))
+        };
+        self.send(&request).await
+    }
+
+    /// Resize the shell's terminal
+    pub async fn resize(&mut self, rows: u16, cols: u16) -> Result<()> {
+        let request = shell_resize_request(&self.session_id, rows, cols);
+        self.send(&request).await
+    }
+
+    /// Ask the guest to close the session
+    pub async fn close(&mut self) -> Result<()> {
+        let request = shell_request(RequestType::ShellClose, Some(&self.session_id));
+        self.send(&request).await
+    }
+
+    /// Wait for the next output chunk or the shell's exit.
+    ///
+    /// Cancel-safe, so it can be used in `tokio::select!`.
+    pub async fn next_message(&mut self) -> Result<ShellMessage> {
+        use base64::{Engine, engine::general_purpose::STANDARD};
+
+        loop {
+            let Some(response) = self.responses.recv().await else {
+                bail!("Shell connection closed by the guest");
+            };
+            let response = response?;
+            if let Some(error) = response.error {
+                bail!("Shell error: {}", error);
+            }
+            match response.shell_event {
+                Some(ShellEvent::Output) => {
+                    let data = STANDARD
+                        .decode(response.output_base64.unwrap_or_default())
+                        .context("Invalid shell output")?;
+                    return Ok(ShellMessage::Output(data));
+                }
+                Some(ShellEvent::Exited) => {
+                    return Ok(ShellMessage::Exited(response.exit_code.unwrap_or(-1)));
+                }
+                // Acknowledgements for input and resize requests
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send(&mut self, request: &AgentRequest) -> Result<()> {
+        let request_bytes = serde_json::to_vec(request)?;
+        write_frame(&mut self.writer, &request_bytes).await
+    }
+}
+
 /// Vsock client for communicating with guest agent
 ///
 /// Supports two modes:
@@ -300,6 +806,10 @@ pub struct VsockClient {
     timeout_secs: u64,
     /// Path to Firecracker vsock UDS (if using Firecracker mode)
     uds_path: Option<std::path::PathBuf>,
+    /// User that commands and file operations run as (None = the agent's user)
+    user: Option<String>,
+    /// Capture stdout and stderr as one ordered stream
+    combined: bool,
 }
 
 #[allow(dead_code)]
@@ -308,9 +818,11 @@ impl VsockClient {
     pub fn new(cid: u32) -> Self {
         Self {
             cid,
-            port: AGENT_PORT,
+            port: agent_port(),
             timeout_secs: 30,
             uds_path: None,
+            user: None,
+            combined: false,
         }
     }
 
@@ -318,9 +830,11 @@ impl VsockClient {
     pub fn for_firecracker(uds_path: impl Into<std::path::PathBuf>) -> Self {
         Self {
             cid: 0, // Not used in Firecracker mode
-            port: AGENT_PORT,
+            port: agent_port(),
             timeout_secs: 30,
             uds_path: Some(uds_path.into()),
+            user: None,
+            combined: false,
         }
     }
 
@@ -338,6 +852,19 @@ impl VsockClient {
         self
     }
 
+    /// Run commands and file operations as this user instead of the agent's user
+    pub fn with_user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+    /// Capture stdout and stderr as one stream in the order they were
+    /// written, returned as stdout
+    pub fn with_combined(mut self, combined: bool) -> Self {
+        self.combined = combined;
+        self
+    }
+
     /// Run a command in the guest
     #[cfg(unix)]
     pub async fn run_command(&self, command: &[String]) -> Result<RunResult> {
@@ -345,15 +872,9 @@ impl VsockClient {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::Run,
             command: Some(command.to_vec()),
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            user: self.user.clone(),
+            combined: self.combined.then_some(true),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -364,14 +885,14 @@ impl VsockClient {
 
         Ok(RunResult {
             exit_code: response.exit_code.unwrap_or(-1),
-            stdout: response.stdout.unwrap_or_default(),
+            stdout: response.output.or(response.stdout).unwrap_or_default(),
             stderr: response.stderr.unwrap_or_default(),
+            rusage: response.rusage,
         })
     }
 
     /// Run a command with custom working directory and environment
     #[cfg(unix)]
-    #[allow(dead_code)]
     pub async fn run_command_with_env(
         &self,
         command: &[String],
@@ -384,13 +905,9 @@ impl VsockClient {
             command: Some(command.to_vec()),
             cwd: cwd.map(|s| s.to_string()),
             env,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            user: self.user.clone(),
+            combined: self.combined.then_some(true),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -401,36 +918,137 @@ impl VsockClient {
 
         Ok(RunResult {
             exit_code: response.exit_code.unwrap_or(-1),
-            stdout: response.stdout.unwrap_or_default(),
+            stdout: response.output.or(response.stdout).unwrap_or_default(),
             stderr: response.stderr.unwrap_or_default(),
+            rusage: response.rusage,
         })
     }
 
-    /// Ping the guest agent to check if it's alive
+    /// Run a command, killing it in the guest if it outlives `limit`.
+    ///
+    /// A command that times out exits with [`crate::backend::TIMEOUT_EXIT_CODE`].
     #[cfg(unix)]
-    #[allow(dead_code)]
-    pub async fn ping(&self) -> Result<bool> {
+    pub async fn run_command_with_timeout(
+        &self,
+        command: &[String],
+        cwd: Option<&str>,
+        env: Option<HashMap<String, String>>,
+        limit: Option<Duration>,
+    ) -> Result<RunResult> {
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
-            request_type: RequestType::Ping,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            request_type: RequestType::Run,
+            command: Some(command.to_vec()),
+            cwd: cwd.map(|s| s.to_string()),
+            env,
+            user: self.user.clone(),
+            combined: self.combined.then_some(true),
+            ..Default::default()
         };
 
-        match self.send_request(&request).await {
+        // `limit` decides when to give up, so the read must not time out first;
+        // without one, wait for as long as the command runs
+        let timeout_secs = match limit {
+            Some(limit) => self.timeout_secs.max(limit.as_secs() + 1),
+            None => u64::MAX,
+        };
+        let client = VsockClient {
+            cid: self.cid,
+            port: self.port,
+            timeout_secs,
+            uds_path: self.uds_path.clone(),
+            user: self.user.clone(),
+            combined: self.combined,
+        };
+        let response = match limit {
+            None => client.send_request(&request).await?,
+            Some(limit) => match timeout(limit, client.send_request(&request)).await {
+                Ok(response) => response?,
+                Err(_) => {
+                    // Dropping the request would leave the command running in the guest
+                    if let Err(e) = self.cancel(&request.id).await {
+                        eprintln!("Warning: failed to kill timed out command: {}", e);
+                    }
+                    return Ok(RunResult {
+                        exit_code: crate::backend::TIMEOUT_EXIT_CODE,
+                        stdout: String::new(),
+                        stderr: crate::backend::timeout_message(limit),
+                        rusage: None,
+                    });
+                }
+            },
+        };
+
+        if let Some(error) = response.error {
+            bail!("Guest agent error: {}", error);
+        }
+
+        Ok(RunResult {
+            exit_code: response.exit_code.unwrap_or(-1),
+            stdout: response.output.or(response.stdout).unwrap_or_default(),
+            stderr: response.stderr.unwrap_or_default(),
+            rusage: response.rusage,
+        })
+    }
+
+    /// Kill the command started by the Run request `id`
+    #[cfg(unix)]
+    pub async fn cancel(&self, id: &str) -> Result<()> {
+        let request = AgentRequest {
+            id: id.to_string(),
+            request_type: RequestType::Cancel,
+            ..Default::default()
+        };
+
+        let response = self.send_request(&request).await?;
+        if let Some(error) = response.error {
+            bail!("Guest agent error: {}", error);
+        }
+        Ok(())
+    }
+
+    /// Ping the guest agent to check if it's alive
+    #[cfg(unix)]
+    #[allow(dead_code)]
+    pub async fn ping(&self) -> Result<bool> {
+        match self.send_request(&ping_request()).await {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     }
 
+    /// Wait for a booting guest's agent to answer, retrying every
+    /// [`AGENT_RETRY_INTERVAL`] until `within` has passed.
+    ///
+    /// Right after boot the agent may not be listening yet, so refused
+    /// connections are expected for a while.
+    #[cfg(unix)]
+    pub async fn wait_until_ready(&self, within: Duration) -> Result<()> {
+        let started = std::time::Instant::now();
+        let mut next_notice = Duration::from_secs(2);
+        loop {
+            let err = match self.send_request(&ping_request()).await {
+                Ok(pong) => {
+                    self.remember_version(pong.version.clone());
+                    return check_agent_version(&pong, strict_agent_version());
+                }
+                Err(e) => e,
+            };
+            let waited = started.elapsed();
+            if waited + AGENT_RETRY_INTERVAL > within {
+                return Err(err.context(format!(
+                    "Guest agent not available after {} seconds",
+                    within.as_secs_f32()
+                )));
+            }
+            if waited >= next_notice {
+                eprintln!("Waiting for guest agent... ({}s)", waited.as_secs());
+                next_notice += Duration::from_secs(2);
+            }
+            tokio::time::sleep(AGENT_RETRY_INTERVAL).await;
+        }
+    }
+
     /// Request graceful shutdown of the guest
     #[cfg(unix)]
     #[allow(dead_code)]
@@ -438,16 +1056,7 @@ impl VsockClient {
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::Shutdown,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            ..Default::default()
         };
 
         // Shutdown may not get a response if the guest shuts down quickly
@@ -457,22 +1066,34 @@ impl VsockClient {
 
     /// Write a file to the guest filesystem
     #[cfg(unix)]
-    pub async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+    pub async fn write_file(
+        &self,
+        path: &str,
+        content: &[u8],
+        opts: &crate::backend::WriteOptions,
+    ) -> Result<()> {
         use base64::{Engine, engine::general_purpose::STANDARD};
 
+        if opts.append {
+            let version = self.agent_version().await?;
+            if !agent_supports(version.as_deref(), APPEND_WRITE_AGENT_VERSION) {
+                bail!(
+                    "Guest agent {} can't append to files (needs {}). Rebuild the rootfs with: agentkernel setup",
+                    version.as_deref().unwrap_or("(unknown version)"),
+                    APPEND_WRITE_AGENT_VERSION
+                );
+            }
+        }
+
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::WriteFile,
-            command: None,
-            cwd: None,
-            env: None,
             path: Some(path.to_string()),
             content_base64: Some(STANDARD.encode(content)),
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            append: opts.append.then_some(true),
+            mode: opts.mode,
+            user: self.user.clone(),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -487,21 +1108,77 @@ impl VsockClient {
     /// Read a file from the guest filesystem
     #[cfg(unix)]
     pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.read_file_part(path, None, None).await
+    }
+
+    /// Read at most `len` bytes of a guest file, starting at `offset`.
+    ///
+    /// Agents older than [`RANGED_READ_AGENT_VERSION`] ignore the range, so
+    /// for those the whole file is read and cut down here.
+    #[cfg(unix)]
+    pub async fn read_file_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let version = self.agent_version().await?;
+        if agent_supports(version.as_deref(), RANGED_READ_AGENT_VERSION) {
+            let mut content = self.read_file_part(path, Some(offset), Some(len)).await?;
+            content.truncate(len as usize);
+            return Ok(content);
+        }
+        let content = self.read_file_part(path, None, None).await?;
+        let start = (offset as usize).min(content.len());
+        let end = start.saturating_add(len as usize).min(content.len());
+        Ok(content[start..end].to_vec())
+    }
+
+    /// Key identifying the agent this client talks to
+    fn endpoint(&self) -> String {
+        match &self.uds_path {
+            Some(path) => format!("{}:{}", path.display(), self.port),
+            None => format!("cid {}:{}", self.cid, self.port),
+        }
+    }
+
+    fn remember_version(&self, version: Option<String>) {
+        let endpoint = self.endpoint();
+        let mut versions = AGENT_VERSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        versions.retain(|(known, _)| *known != endpoint);
+        versions.push((endpoint, version));
+    }
+
+    /// Version the guest agent reported, pinging it if it hasn't been asked yet
+    #[cfg(unix)]
+    pub async fn agent_version(&self) -> Result<Option<String>> {
+        let endpoint = self.endpoint();
+        let cached = AGENT_VERSIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|(known, _)| *known == endpoint)
+            .map(|(_, version)| version.clone());
+        if let Some(version) = cached {
+            return Ok(version);
+        }
+        let pong = self.send_request(&ping_request()).await?;
+        self.remember_version(pong.version.clone());
+        Ok(pong.version)
+    }
+
+    #[cfg(unix)]
+    async fn read_file_part(
+        &self,
+        path: &str,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> Result<Vec<u8>> {
         use base64::{Engine, engine::general_purpose::STANDARD};
 
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::ReadFile,
-            command: None,
-            cwd: None,
-            env: None,
             path: Some(path.to_string()),
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            offset,
+            length,
+            user: self.user.clone(),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -527,16 +1204,9 @@ impl VsockClient {
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::RemoveFile,
-            command: None,
-            cwd: None,
-            env: None,
             path: Some(path.to_string()),
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            user: self.user.clone(),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -554,16 +1224,10 @@ impl VsockClient {
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
             request_type: RequestType::Mkdir,
-            command: None,
-            cwd: None,
-            env: None,
             path: Some(path.to_string()),
-            content_base64: None,
             recursive: Some(recursive),
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            user: self.user.clone(),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
@@ -575,130 +1239,31 @@ impl VsockClient {
         Ok(())
     }
 
-    // --- Shell/PTY Operations ---
-
-    /// Start a new shell session in the guest
-    #[cfg(unix)]
-    #[allow(dead_code)]
-    pub async fn start_shell(
-        &self,
-        command: Option<Vec<String>>,
-        rows: u16,
-        cols: u16,
-        env: Option<HashMap<String, String>>,
-    ) -> Result<String> {
-        let request = AgentRequest {
-            id: uuid::Uuid::new_v4().to_string(),
-            request_type: RequestType::Shell,
-            command,
-            cwd: None,
-            env,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: Some(rows),
-            cols: Some(cols),
-            input_base64: None,
-        };
-
-        let response = self.send_request(&request).await?;
-
-        if let Some(error) = response.error {
-            bail!("Failed to start shell: {}", error);
-        }
-
-        response
-            .session_id
-            .ok_or_else(|| anyhow::anyhow!("No session ID in shell response"))
-    }
-
-    /// Send input to a shell session
-    #[cfg(unix)]
-    #[allow(dead_code)]
-    pub async fn shell_input(&self, session_id: &str, data: &[u8]) -> Result<()> {
-        use base64::{Engine, engine::general_purpose::STANDARD};
-
-        let request = AgentRequest {
-            id: uuid::Uuid::new_v4().to_string(),
-            request_type: RequestType::ShellInput,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: Some(session_id.to_string()),
-            rows: None,
-            cols: None,
-            input_base64: Some(STANDARD.encode(data)),
-        };
-
-        let response = self.send_request(&request).await?;
-
-        if let Some(error) = response.error {
-            bail!("Failed to send shell input: {}", error);
-        }
-
-        Ok(())
-    }
-
-    /// Resize a shell session's terminal
+    /// Create a link at `link` pointing to `target` (symbolic unless `hard`)
     #[cfg(unix)]
-    #[allow(dead_code)]
-    pub async fn shell_resize(&self, session_id: &str, rows: u16, cols: u16) -> Result<()> {
+    pub async fn link(&self, target: &str, link: &str, hard: bool) -> Result<()> {
         let request = AgentRequest {
             id: uuid::Uuid::new_v4().to_string(),
-            request_type: RequestType::ShellResize,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: Some(session_id.to_string()),
-            rows: Some(rows),
-            cols: Some(cols),
-            input_base64: None,
+            request_type: if hard {
+                RequestType::Hardlink
+            } else {
+                RequestType::Symlink
+            },
+            path: Some(link.to_string()),
+            target: Some(target.to_string()),
+            user: self.user.clone(),
+            ..Default::default()
         };
 
         let response = self.send_request(&request).await?;
 
         if let Some(error) = response.error {
-            bail!("Failed to resize shell: {}", error);
+            bail!("Failed to create link: {}", error);
         }
 
         Ok(())
     }
 
-    /// Close a shell session
-    #[cfg(unix)]
-    #[allow(dead_code)]
-    pub async fn shell_close(&self, session_id: &str) -> Result<i32> {
-        let request = AgentRequest {
-            id: uuid::Uuid::new_v4().to_string(),
-            request_type: RequestType::ShellClose,
-            command: None,
-            cwd: None,
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: Some(session_id.to_string()),
-            rows: None,
-            cols: None,
-            input_base64: None,
-        };
-
-        let response = self.send_request(&request).await?;
-
-        if let Some(error) = response.error {
-            bail!("Failed to close shell: {}", error);
-        }
-
-        Ok(response.exit_code.unwrap_or(-1))
-    }
-
     /// Send a request to the guest agent and receive response
     #[cfg(unix)]
     async fn send_request(&self, request: &AgentRequest) -> Result<AgentResponse> {
@@ -753,19 +1318,7 @@ impl VsockClient {
         stream.flush().await?;
 
         // Read response: OK <host_port>\n
-        let mut response_buf = [0u8; 32];
-        let n = timeout(Duration::from_secs(5), stream.read(&mut response_buf))
-            .await
-            .context("Timeout waiting for CONNECT response")?
-            .context("Failed to read CONNECT response")?;
-
-        let response_str = std::str::from_utf8(&response_buf[..n])
-            .context("Invalid CONNECT response")?
-            .trim();
-
-        if !response_str.starts_with("OK ") {
-            bail!("Firecracker vsock CONNECT failed: {}", response_str);
-        }
+        read_connect_ack(&mut stream).await?;
 
         // Now we can communicate with the guest agent
         self.send_and_receive(&mut stream, request).await
@@ -781,43 +1334,9 @@ impl VsockClient {
     where
         S: AsyncReadExt + AsyncWriteExt + Unpin,
     {
-        // Serialize request
-        let request_bytes = serde_json::to_vec(request)?;
-
-        // Send length-prefixed request
-        let len = request_bytes.len() as u32;
-        stream.write_all(&len.to_le_bytes()).await?;
-        stream.write_all(&request_bytes).await?;
-        stream.flush().await?;
-
-        // Read length-prefixed response
-        let mut len_bytes = [0u8; 4];
-        timeout(
-            Duration::from_secs(self.timeout_secs),
-            stream.read_exact(&mut len_bytes),
-        )
-        .await
-        .context("Read timeout")?
-        .context("Failed to read response length")?;
-
-        let len = u32::from_le_bytes(len_bytes) as usize;
-        if len > 10 * 1024 * 1024 {
-            bail!("Response too large: {} bytes", len);
-        }
-
-        let mut response_bytes = vec![0u8; len];
-        timeout(
-            Duration::from_secs(self.timeout_secs),
-            stream.read_exact(&mut response_bytes),
-        )
-        .await
-        .context("Read timeout")?
-        .context("Failed to read response body")?;
-
-        let response: AgentResponse =
-            serde_json::from_slice(&response_bytes).context("Failed to parse response")?;
-
-        Ok(response)
+        // Every new connection starts with the agent's version greeting
+        read_greeting(stream).await?;
+        exchange(stream, request, self.timeout_secs).await
     }
 
     /// Stub for non-unix platforms
@@ -838,6 +1357,19 @@ impl VsockClient {
         bail!("Vsock is only supported on Unix platforms");
     }
 
+    /// Stub for non-unix platforms
+    #[cfg(not(unix))]
+    #[allow(dead_code)]
+    pub async fn run_command_with_timeout(
+        &self,
+        _command: &[String],
+        _cwd: Option<&str>,
+        _env: Option<HashMap<String, String>>,
+        _limit: Option<Duration>,
+    ) -> Result<RunResult> {
+        bail!("Vsock is only supported on Unix platforms");
+    }
+
     /// Stub for non-unix platforms
     #[cfg(not(unix))]
     #[allow(dead_code)]
@@ -857,17 +1389,10 @@ impl VsockClient {
 #[cfg(unix)]
 #[allow(dead_code)]
 pub async fn wait_for_agent(cid: u32, timeout_secs: u64) -> Result<()> {
-    let client = VsockClient::new(cid).with_timeout(5);
-    let deadline = std::time::Instant::now() + Duration::from_secs(timeout_secs);
-
-    while std::time::Instant::now() < deadline {
-        if client.ping().await.unwrap_or(false) {
-            return Ok(());
-        }
-        tokio::time::sleep(Duration::from_millis(100)).await;
-    }
-
-    bail!("Guest agent not available after {} seconds", timeout_secs);
+    VsockClient::new(cid)
+        .with_timeout(5)
+        .wait_until_ready(Duration::from_secs(timeout_secs))
+        .await
 }
 
 /// Wait for agent stub for non-unix
@@ -881,6 +1406,15 @@ pub async fn wait_for_agent(_cid: u32, _timeout_secs: u64) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_agent_port() {
+        assert_eq!(parse_agent_port(None), AGENT_PORT);
+        assert_eq!(parse_agent_port(Some("6000")), 6000);
+        assert_eq!(parse_agent_port(Some(" 6000\n")), 6000);
+        assert_eq!(parse_agent_port(Some("0")), AGENT_PORT);
+        assert_eq!(parse_agent_port(Some("nope")), AGENT_PORT);
+    }
+
     #[test]
     fn test_request_serialize() {
         let request = AgentRequest {
@@ -888,14 +1422,7 @@ mod tests {
             request_type: RequestType::Run,
             command: Some(vec!["ls".to_string(), "-la".to_string()]),
             cwd: Some("/app".to_string()),
-            env: None,
-            path: None,
-            content_base64: None,
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -904,27 +1431,35 @@ mod tests {
         assert!(json.contains("\"cwd\":\"/app\""));
     }
 
+    #[test]
+    fn test_shell_resize_request_serialize() {
+        let request = shell_resize_request("sess-1", 40, 120);
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("\"type\":\"shell_resize\""));
+        assert!(json.contains("\"session_id\":\"sess-1\""));
+        assert!(json.contains("\"rows\":40"));
+        assert!(json.contains("\"cols\":120"));
+        assert!(!json.contains("input_base64"));
+    }
+
     #[test]
     fn test_write_file_request_serialize() {
         let request = AgentRequest {
             id: "test-456".to_string(),
             request_type: RequestType::WriteFile,
-            command: None,
-            cwd: None,
-            env: None,
             path: Some("/tmp/test.txt".to_string()),
             content_base64: Some("SGVsbG8gV29ybGQ=".to_string()), // "Hello World"
-            recursive: None,
-            session_id: None,
-            rows: None,
-            cols: None,
-            input_base64: None,
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&request).unwrap();
         assert!(json.contains("\"type\":\"write_file\""));
         assert!(json.contains("\"path\":\"/tmp/test.txt\""));
         assert!(json.contains("\"content_base64\":\"SGVsbG8gV29ybGQ=\""));
+        // Plain writes leave the new options out, so older agents still accept them
+        assert!(!json.contains("append"));
+        assert!(!json.contains("mode"));
     }
 
     #[test]
@@ -940,5 +1475,229 @@ mod tests {
         assert_eq!(response.id, "test-123");
         assert_eq!(response.exit_code, Some(0));
         assert_eq!(response.stdout, Some("hello world\n".to_string()));
+        assert!(response.rusage.is_none());
+    }
+
+    #[test]
+    fn test_combined_run_request_and_response() {
+        let request = AgentRequest {
+            combined: Some(true),
+            ..shell_request(RequestType::Run, None)
+        };
+        assert!(
+            serde_json::to_string(&request)
+                .unwrap()
+                .contains(r#""combined":true"#)
+        );
+
+        // Separate streams by default, so older agents still accept the request
+        let request = shell_request(RequestType::Run, None);
+        assert!(
+            !serde_json::to_string(&request)
+                .unwrap()
+                .contains("combined")
+        );
+
+        let json = r#"{"id": "test-123", "exit_code": 0, "output": "out\nerr\n"}"#;
+        let response: AgentResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(response.output.as_deref(), Some("out\nerr\n"));
+        assert!(response.stdout.is_none() && response.stderr.is_none());
+    }
+
+    #[test]
+    fn test_response_deserialize_rusage() {
+        let json = r#"{
+            "id": "test-123",
+            "exit_code": 0,
+            "stdout": "",
+            "stderr": "",
+            "rusage": {"user_time_ms": 120, "system_time_ms": 15, "max_rss_kb": 20480}
+        }"#;
+
+        let response: AgentResponse = serde_json::from_str(json).unwrap();
+        let rusage = response.rusage.unwrap();
+        assert_eq!(rusage.user_time_ms, 120);
+        assert_eq!(rusage.system_time_ms, 15);
+        assert_eq!(rusage.max_rss_kb, 20480);
+        assert_eq!(rusage.to_string(), "user 120ms, sys 15ms, max RSS 20480 KB");
+    }
+
+    #[test]
+    fn test_agent_version_mismatch() {
+        let pong: AgentResponse = serde_json::from_str(&format!(
+            r#"{{"id":"ping","exit_code":0,"version":"{}","protocol_version":{}}}"#,
+            EXPECTED_AGENT_VERSION, PROTOCOL_VERSION
+        ))
+        .unwrap();
+        assert!(!EXPECTED_AGENT_VERSION.is_empty());
+        assert_eq!(agent_version_mismatch(pong.version.as_deref()), None);
+        assert!(check_agent_version(&pong, true).is_ok());
+
+        let stale = agent_version_mismatch(Some("0.0.1")).unwrap();
+        assert!(stale.contains("v0.0.1"), "{}", stale);
+        assert!(
+            stale.contains("Rebuild it with: agentkernel setup"),
+            "{}",
+            stale
+        );
+
+        // Agents from before the version field don't report one
+        let legacy: AgentResponse = serde_json::from_str(r#"{"id":"ping","exit_code":0}"#).unwrap();
+        let message = agent_version_mismatch(legacy.version.as_deref()).unwrap();
+        assert!(message.contains("rootfs is stale"), "{}", message);
+
+        // A mismatch only warns unless strict
+        assert!(check_agent_version(&legacy, false).is_ok());
+        let err = check_agent_version(&legacy, true).unwrap_err();
+        assert!(err.downcast_ref::<AgentIncompatible>().is_some());
+    }
+
+    #[test]
+    fn test_agent_supports() {
+        assert!(agent_supports(Some("0.2.0"), RANGED_READ_AGENT_VERSION));
+        assert!(agent_supports(Some("0.10.1"), RANGED_READ_AGENT_VERSION));
+        assert!(agent_supports(Some("1.0.0-rc1"), RANGED_READ_AGENT_VERSION));
+        assert!(!agent_supports(Some("0.1.0"), RANGED_READ_AGENT_VERSION));
+        assert!(!agent_supports(None, RANGED_READ_AGENT_VERSION));
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let payload = br#"{"id":"1","type":"ping"}"#;
+
+        write_frame(&mut client, payload).await.unwrap();
+        write_frame(&mut client, b"").await.unwrap();
+
+        assert_eq!(read_frame(&mut server).await.unwrap(), payload);
+        assert!(read_frame(&mut server).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frame_rejects_version_mismatch() {
+        let mut frame = encode_frame(b"{}");
+        frame[4..6].copy_from_slice(&0u16.to_le_bytes());
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("Guest agent too old"), "{}", err);
+        assert!(err.to_string().contains("agentkernel setup"));
+        assert!(err.downcast_ref::<AgentIncompatible>().is_some());
+
+        let mut frame = encode_frame(b"{}");
+        frame[4..6].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("Upgrade agentkernel"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_frame_rejects_legacy_and_corrupt_messages() {
+        // Old agents send a bare length prefix
+        let mut legacy = 2u32.to_le_bytes().to_vec();
+        legacy.extend_from_slice(b"{}");
+        legacy.extend_from_slice(&[0u8; 12]);
+        let err = read_frame(&mut legacy.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("bad magic"), "{}", err);
+
+        let mut frame = encode_frame(b"{\"id\":\"1\"}");
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+
+        // Truncated payload
+        let frame = encode_frame(b"{\"id\":\"1\"}");
+        let err = read_frame(&mut &frame[..frame.len() - 3])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("payload"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_with_greeting() {
+        let (mut host, mut guest) = tokio::io::duplex(64 * 1024);
+
+        let agent = tokio::spawn(async move {
+            write_frame(&mut guest, b"").await.unwrap();
+            let request: AgentRequest =
+                serde_json::from_slice(&read_frame(&mut guest).await.unwrap()).unwrap();
+            let response = format!(r#"{{"id":"{}","exit_code":0,"stdout":"pong"}}"#, request.id);
+            write_frame(&mut guest, response.as_bytes()).await.unwrap();
+        });
+
+        read_greeting(&mut host).await.unwrap();
+        let request = AgentRequest {
+            id: "req-1".to_string(),
+            request_type: RequestType::Ping,
+            ..Default::default()
+        };
+        let response = exchange(&mut host, &request, 5).await.unwrap();
+        assert_eq!(response.id, "req-1");
+        assert_eq!(response.stdout.as_deref(), Some("pong"));
+        agent.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_exchange_rejects_response_for_another_request() {
+        let (mut host, mut guest) = tokio::io::duplex(64 * 1024);
+
+        let agent = tokio::spawn(async move {
+            read_frame(&mut guest).await.unwrap();
+            write_frame(&mut guest, br#"{"id":"other","exit_code":0,"stdout":"b"}"#)
+                .await
+                .unwrap();
+        });
+
+        let request = ping_request();
+        let err = exchange(&mut host, &request, 5).await.unwrap_err();
+        assert!(err.to_string().contains("'other'"), "{}", err);
+        agent.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_wait_until_ready_retries_until_agent_binds() {
+        use tokio::io::AsyncBufReadExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let uds_path = dir.path().join("vsock.sock");
+        let client = VsockClient::for_firecracker(&uds_path).with_timeout(5);
+
+        // Nothing ever listening: gives up once the window has passed
+        let err = client
+            .wait_until_ready(Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not available"), "{}", err);
+
+        // An agent that binds a few retries after the host starts connecting
+        let listen_path = uds_path.clone();
+        let agent = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let listener = tokio::net::UnixListener::bind(&listen_path).unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tokio::io::BufReader::new(stream);
+            let mut connect = String::new();
+            stream.read_line(&mut connect).await.unwrap();
+            assert_eq!(connect, format!("CONNECT {}\n", agent_port()));
+            stream.write_all(b"OK 1024\n").await.unwrap();
+            write_frame(&mut stream, b"").await.unwrap();
+            let request: AgentRequest =
+                serde_json::from_slice(&read_frame(&mut stream).await.unwrap()).unwrap();
+            let response = format!(r#"{{"id":"{}","exit_code":0}}"#, request.id);
+            write_frame(&mut stream, response.as_bytes()).await.unwrap();
+        });
+
+        let started = std::time::Instant::now();
+        client
+            .wait_until_ready(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        agent.await.unwrap();
     }
 }
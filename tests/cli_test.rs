@@ -47,6 +47,15 @@ fn test_version() {
     assert!(stdout.contains("agentkernel"));
 }
 
+#[test]
+fn test_version_verbose() {
+    let (exit_code, stdout, _stderr) = run_cmd(&["version", "--verbose"]);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains(env!("CARGO_PKG_VERSION")));
+    assert!(stdout.contains("docker:"));
+    assert!(stdout.contains("rustc:"));
+}
+
 #[test]
 fn test_help_subcommand() {
     let (exit_code, stdout, _stderr) = run_cmd(&["help"]);
@@ -85,6 +94,31 @@ fn test_exec_help() {
     let (exit_code, stdout, _stderr) = run_cmd(&["exec", "--help"]);
     assert_eq!(exit_code, 0);
     assert!(stdout.contains("Execute a command"));
+    assert!(stdout.contains("--user"));
+    assert!(stdout.contains("--workdir"));
+}
+
+#[test]
+fn test_exec_rejects_invalid_user_and_workdir() {
+    let (exit_code, _stdout, stderr) =
+        run_cmd(&["exec", "some-box", "--workdir", "src", "--", "pwd"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("absolute"), "stderr was: {}", stderr);
+
+    let (exit_code, _stdout, stderr) = run_cmd(&["exec", "some-box", "--user", "a/b", "--", "id"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Invalid user"), "stderr was: {}", stderr);
+}
+
+#[test]
+fn test_rename_rejects_invalid_name() {
+    let (exit_code, _stdout, stderr) = run_cmd(&["rename", "some-box", "bad name"]);
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("invalid character"),
+        "stderr was: {}",
+        stderr
+    );
 }
 
 #[test]
@@ -94,6 +128,51 @@ fn test_run_help() {
     assert!(stdout.contains("Run a command in a temporary sandbox"));
 }
 
+#[test]
+fn test_run_ttl_requires_keep_and_valid_duration() {
+    let (exit_code, _stdout, stderr) = run_cmd(&["run", "--ttl", "5m", "--", "true"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--keep"), "stderr was: {}", stderr);
+
+    let (exit_code, _stdout, stderr) = run_cmd(&["run", "--keep", "--ttl", "soon", "--", "true"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Invalid TTL"), "stderr was: {}", stderr);
+}
+
+#[test]
+fn test_run_rejects_relative_workdir() {
+    let (exit_code, _stdout, stderr) = run_cmd(&["run", "--workdir", "src", "--", "pwd"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("absolute"), "stderr was: {}", stderr);
+}
+
+#[test]
+fn test_run_stdin_requires_a_script() {
+    // Nothing piped in: stdin is empty
+    let (exit_code, _stdout, stderr) = run_cmd(&["run", "--stdin"]);
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("No script on stdin"),
+        "stderr was: {}",
+        stderr
+    );
+
+    let (exit_code, _stdout, stderr) = run_cmd(&["exec", "some-box", "-"]);
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("No script on stdin"),
+        "stderr was: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_prune_requires_target() {
+    let (exit_code, _stdout, stderr) = run_cmd(&["prune"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--ephemeral"));
+}
+
 #[test]
 fn test_list_help() {
     let (exit_code, stdout, _stderr) = run_cmd(&["list", "--help"]);
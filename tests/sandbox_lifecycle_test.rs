@@ -339,3 +339,265 @@ fn test_run_exit_code_propagation() {
     let (exit_code, _, _) = run_cmd(&["run", "--backend", "docker", "--", "sh", "-c", "exit 42"]);
     assert_ne!(exit_code, 0);
 }
+
+#[test]
+#[ignore] // Requires Docker
+fn test_exec_exit_code_propagation() {
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let name = unique_sandbox_name();
+    cleanup_sandbox(&name);
+    run_cmd(&["create", &name, "--backend", "docker"]);
+    run_cmd(&["start", &name]);
+
+    // The remote exit code is passed through, and stderr stays on stderr
+    let (exit_code, stdout, stderr) = run_cmd(&[
+        "exec",
+        &name,
+        "--",
+        "sh",
+        "-c",
+        "echo out; echo err >&2; exit 3",
+    ]);
+    assert_eq!(exit_code, 3, "stderr: {}", stderr);
+    assert_eq!(stdout.trim(), "out");
+    assert!(stderr.contains("err"), "stderr: {}", stderr);
+
+    let (exit_code, _, _) = run_cmd(&["exec", &name, "--", "test", "-f", "/nonexistent"]);
+    assert_eq!(exit_code, 1);
+
+    cleanup_sandbox(&name);
+}
+
+#[test]
+#[ignore] // Requires Docker
+fn test_exec_preserves_binary_output() {
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let name = unique_sandbox_name();
+    cleanup_sandbox(&name);
+    run_cmd(&["create", &name, "--backend", "docker"]);
+    run_cmd(&["start", &name]);
+
+    // Invalid UTF-8 comes through byte for byte, not as replacement characters
+    let output = Command::new(agentkernel_bin())
+        .args(["exec", &name, "--", "printf", "\\377\\376ok\\000\\200"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\xff\xfeok\x00\x80");
+
+    cleanup_sandbox(&name);
+}
+
+// === Detached Run Tests ===
+
+#[test]
+#[ignore] // Requires Docker
+fn test_run_detach_keeps_sandbox() {
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let (exit_code, stdout, stderr) = run_cmd(&[
+        "run",
+        "--detach",
+        "--backend",
+        "docker",
+        "--",
+        "sh",
+        "-c",
+        "echo detached-output; sleep 60",
+    ]);
+    assert_eq!(exit_code, 0, "Detached run failed: {}", stderr);
+
+    // stdout is just the generated sandbox name
+    let name = stdout.trim().to_string();
+    assert!(name.starts_with("run-"), "Unexpected output: {}", stdout);
+    assert!(stderr.contains("/tmp/agentkernel-run.log"));
+
+    // The sandbox outlives the CLI invocation
+    let (_, stdout, _) = run_cmd(&["list"]);
+    assert!(stdout.contains(&name), "Detached sandbox not in list");
+
+    // Output lands in the log file while the command keeps running
+    let mut log = String::new();
+    for _ in 0..20 {
+        let (_, out, _) = run_cmd(&["exec", &name, "--", "cat", "/tmp/agentkernel-run.log"]);
+        log = out;
+        if log.contains("detached-output") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+    assert!(
+        log.contains("detached-output"),
+        "Log missing output: {}",
+        log
+    );
+
+    cleanup_sandbox(&name);
+}
+
+#[test]
+#[ignore] // Requires Docker
+fn test_wait_returns_detached_exit_code() {
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let (exit_code, stdout, stderr) = run_cmd(&[
+        "run",
+        "--detach",
+        "--backend",
+        "docker",
+        "--",
+        "sh",
+        "-c",
+        "sleep 1; exit 3",
+    ]);
+    assert_eq!(exit_code, 0, "Detached run failed: {}", stderr);
+    let name = stdout.trim().to_string();
+
+    let (exit_code, stdout, stderr) = run_cmd(&["wait", &name, "--timeout", "30"]);
+    assert_eq!(exit_code, 3, "Unexpected wait result: {}", stderr);
+    assert_eq!(stdout.trim(), "3");
+
+    cleanup_sandbox(&name);
+}
+
+#[test]
+#[ignore] // Requires KVM and Firecracker images
+fn test_run_firecracker_scratch_disk() {
+    if !std::path::Path::new("/dev/kvm").exists() {
+        eprintln!("Skipping test: KVM not available");
+        return;
+    }
+
+    // 256MB scratch disk, then write a file bigger than the base rootfs
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("agentkernel.toml");
+    std::fs::write(
+        &config_path,
+        "[sandbox]\nname = \"scratch-test\"\n\n[firecracker]\nscratch_mb = 256\n",
+    )
+    .unwrap();
+
+    let (exit_code, stdout, stderr) = run_cmd(&[
+        "run",
+        "--backend",
+        "firecracker",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--",
+        "sh",
+        "-c",
+        "dd if=/dev/zero of=/workspace/big bs=1M count=160 && du -m /workspace/big",
+    ]);
+    assert_eq!(exit_code, 0, "Run failed: {}", stderr);
+    assert!(stdout.contains("160"), "stdout: {}", stdout);
+}
+
+#[test]
+#[ignore] // Requires Docker
+fn test_exec_script_from_stdin() {
+    use std::io::Write;
+
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let name = unique_sandbox_name();
+    cleanup_sandbox(&name);
+
+    let (exit_code, _stdout, stderr) = run_cmd(&["create", &name, "--backend", "docker"]);
+    assert_eq!(exit_code, 0, "Create failed: {}", stderr);
+    let (exit_code, _stdout, stderr) = run_cmd(&["start", &name]);
+    assert_eq!(exit_code, 0, "Start failed: {}", stderr);
+
+    let script = "for word in \"$@\"; do\n  echo \"got $word\"\ndone\necho 'it'\"'\"'s quoted'\n";
+    let mut child = Command::new(agentkernel_bin())
+        .args(["exec", &name, "--stdin", "--", "one", "two"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "Exec failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "got one\ngot two\nit's quoted\n"
+    );
+
+    cleanup_sandbox(&name);
+}
+
+#[test]
+#[ignore] // Requires Docker
+fn test_exec_timeout_kills_process() {
+    if !docker_available() {
+        eprintln!("Skipping test: Docker not available");
+        return;
+    }
+
+    let name = unique_sandbox_name();
+    cleanup_sandbox(&name);
+
+    let (exit_code, _stdout, stderr) = run_cmd(&["create", &name, "--backend", "docker"]);
+    assert_eq!(exit_code, 0, "Create failed: {}", stderr);
+    let (exit_code, _stdout, stderr) = run_cmd(&["start", &name]);
+    assert_eq!(exit_code, 0, "Start failed: {}", stderr);
+
+    let started = std::time::Instant::now();
+    let (exit_code, _stdout, stderr) = run_cmd(&[
+        "exec",
+        &name,
+        "--timeout",
+        "2",
+        "--",
+        "sh",
+        "-c",
+        "sleep 300",
+    ]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("timed out"), "Unexpected error: {}", stderr);
+    assert!(started.elapsed() < std::time::Duration::from_secs(60));
+
+    // The sleep must be gone, not just abandoned
+    let (exit_code, stdout, stderr) = run_cmd(&[
+        "exec",
+        &name,
+        "--",
+        "sh",
+        "-c",
+        "cat /proc/[0-9]*/comm 2>/dev/null",
+    ]);
+    assert_eq!(exit_code, 0, "Exec failed: {}", stderr);
+    assert!(
+        !stdout.lines().any(|l| l.trim() == "sleep"),
+        "sleep still running: {}",
+        stdout
+    );
+
+    cleanup_sandbox(&name);
+}